@@ -0,0 +1,141 @@
+//! The ADC of the Apollo3 for the auxiliary analog channels (see `sfy::adc`), with the `adc`
+//! feature. The conversions are single scans of one slot, triggered by software, with the
+//! internal 2.0 V reference and 14 bit precision averaged over 128 measurements. The ADC is
+//! powered up for the readings and down again after.
+
+use ambiq_hal::prelude::*;
+use core::ffi::c_void;
+use embedded_hal::blocking::delay::DelayMs;
+
+use sfy::adc::{Channel, Sampler, REFERENCE};
+
+/// Full scale of a sample at 14 bit precision.
+const FULL_SCALE: f32 = ((1 << 14) - 1) as f32;
+
+/// Time to wait for a conversion [ms].
+const CONVERSION_TIMEOUT: u16 = 50;
+
+/// The ADC, failed requests are the status code of the HAL.
+#[derive(Debug, defmt::Format)]
+pub enum AdcError {
+    Hal(u32),
+
+    /// The conversion did not complete in time.
+    Timeout,
+}
+
+fn check(status: u32) -> Result<(), AdcError> {
+    match status {
+        0 => Ok(()),
+        e => Err(AdcError::Hal(e)),
+    }
+}
+
+pub struct Adc {
+    handle: *mut c_void,
+}
+
+impl Adc {
+    /// Initialize the ADC and set the pads of the `channels` to the ADC function. The ADC stays
+    /// powered down until read.
+    pub fn new(channels: &[Channel]) -> Result<Adc, AdcError> {
+        let mut handle: *mut c_void = core::ptr::null_mut();
+
+        unsafe {
+            check(halc::am_hal_adc_initialize(0, &mut handle))?;
+
+            // The ADC is function 0 of all the pads with an input.
+            for c in channels {
+                let cfg: halc::am_hal_gpio_pincfg_t = core::mem::zeroed();
+                check(halc::am_hal_gpio_pinconfig(c.pad as u32, cfg))?;
+            }
+        }
+
+        Ok(Adc { handle })
+    }
+
+    fn power(&mut self, on: bool) -> Result<(), AdcError> {
+        let state = if on {
+            halc::am_hal_sysctrl_power_state_e_AM_HAL_SYSCTRL_WAKE
+        } else {
+            halc::am_hal_sysctrl_power_state_e_AM_HAL_SYSCTRL_DEEPSLEEP
+        };
+
+        unsafe { check(halc::am_hal_adc_power_control(self.handle, state, false)) }
+    }
+
+    /// Configure and enable the ADC to convert `channel` in slot 0.
+    fn configure(&mut self, channel: u8) -> Result<(), AdcError> {
+        let mut config = halc::am_hal_adc_config_t {
+            eClock: halc::am_hal_adc_clksel_e_AM_HAL_ADC_CLKSEL_HFRC,
+            ePolarity: halc::am_hal_adc_trigpol_e_AM_HAL_ADC_TRIGPOL_RISING,
+            eTrigger: halc::am_hal_adc_trigsel_e_AM_HAL_ADC_TRIGSEL_SOFTWARE,
+            eReference: halc::am_hal_adc_refsel_e_AM_HAL_ADC_REFSEL_INT_2P0,
+            eClockMode: halc::am_hal_adc_clkmode_e_AM_HAL_ADC_CLKMODE_LOW_POWER,
+            ePowerMode: halc::am_hal_adc_lpmode_e_AM_HAL_ADC_LPMODE0,
+            eRepeat: halc::am_hal_adc_repeat_e_AM_HAL_ADC_SINGLE_SCAN,
+        };
+
+        // The single-ended channels are the first of the channel select.
+        let mut slot = halc::am_hal_adc_slot_config_t {
+            eMeasToAvg: halc::am_hal_adc_meas_avg_e_AM_HAL_ADC_SLOT_AVG_128,
+            ePrecisionMode: halc::am_hal_adc_slot_prec_e_AM_HAL_ADC_SLOT_14BIT,
+            eChannel: channel as halc::am_hal_adc_slot_chan_e,
+            bWindowCompare: false,
+            bEnabled: true,
+        };
+
+        unsafe {
+            check(halc::am_hal_adc_disable(self.handle))?;
+            check(halc::am_hal_adc_configure(self.handle, &mut config))?;
+            check(halc::am_hal_adc_configure_slot(self.handle, 0, &mut slot))?;
+            check(halc::am_hal_adc_enable(self.handle))
+        }
+    }
+
+    fn convert(&mut self, delay: &mut impl DelayMs<u16>) -> Result<u32, AdcError> {
+        unsafe {
+            check(halc::am_hal_adc_sw_trigger(self.handle))?;
+        }
+
+        for _ in 0..CONVERSION_TIMEOUT {
+            let mut sample: halc::am_hal_adc_sample_t = unsafe { core::mem::zeroed() };
+            let mut n = 1;
+
+            unsafe {
+                check(halc::am_hal_adc_samples_read(
+                    self.handle,
+                    false,
+                    core::ptr::null_mut(),
+                    &mut n,
+                    &mut sample,
+                ))?;
+            }
+
+            if n == 1 {
+                return Ok(sample.ui32Sample);
+            }
+
+            delay.delay_ms(1);
+        }
+
+        Err(AdcError::Timeout)
+    }
+}
+
+impl Sampler for Adc {
+    type Error = AdcError;
+
+    fn sample(&mut self, channel: u8, delay: &mut impl DelayMs<u16>) -> Result<f32, AdcError> {
+        self.power(true)?;
+
+        let r = self.configure(channel).and_then(|_| self.convert(delay));
+
+        unsafe {
+            check(halc::am_hal_adc_disable(self.handle)).ok();
+        }
+        self.power(false)?;
+
+        Ok(r? as f32 / FULL_SCALE * REFERENCE)
+    }
+}
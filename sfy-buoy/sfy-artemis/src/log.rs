@@ -1,7 +1,17 @@
-use ambiq_hal::i2c;
 use sfy::note::Notecarrier;
 
+use crate::{Note, NoteBus};
+
 /// A reference to the Notecarrier once it is initialized. The idea is that
 /// it can be used from reset routines to transfer log messages. In that case the main thread will
 /// not be running anyway.
-pub static mut NOTE: Option<*mut Notecarrier<i2c::Iom4>> = None;
+pub static mut NOTE: Option<*mut Note> = None;
+
+/// The (active) Notecarrier of `NOTE`.
+pub unsafe fn carrier() -> Option<*mut Notecarrier<NoteBus>> {
+    #[cfg(not(feature = "redundant-notecard"))]
+    return NOTE;
+
+    #[cfg(feature = "redundant-notecard")]
+    return NOTE.map(|n| &mut **n as *mut _);
+}
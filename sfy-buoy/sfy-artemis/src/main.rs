@@ -2,7 +2,7 @@
 #![no_std]
 #![no_main]
 
-#[cfg(not(feature = "deploy"))]
+#[cfg(not(any(feature = "deploy", all(feature = "assert-note", debug_assertions))))]
 use panic_probe as _;
 
 #[allow(unused_imports)]
@@ -22,17 +22,15 @@ use chrono::NaiveDate;
 use core::cell::RefCell;
 use core::fmt::Write as _;
 use core::panic::PanicInfo;
-use core::sync::atomic::{AtomicI32, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 #[allow(unused_imports)]
 use cortex_m::{
     asm,
     interrupt::{free, Mutex},
+    peripheral::DWT,
 };
 use cortex_m_rt::{entry, exception, ExceptionFrame};
-use embedded_hal::blocking::{
-    delay::DelayMs,
-    i2c::{Read, Write},
-};
+use embedded_hal::blocking::delay::DelayMs;
 
 #[cfg(feature = "storage")]
 use embedded_hal::spi::MODE_0; 
@@ -42,26 +40,119 @@ use hal::spi::{Freq, Spi};
 use git_version::git_version;
 use hal::{i2c, pac::interrupt};
 
+#[cfg(feature = "redundant-notecard")]
+use sfy::bus::Either;
+use sfy::bus::Guarded;
+use sfy::cmd::Command;
+use sfy::config::{Config, CONFIG_SZ};
+use sfy::deployment::Deployment;
+#[cfg(feature = "redundant-notecard")]
+use sfy::failover::RedundantNotecarrier;
 use sfy::log::log;
-use sfy::note::Notecarrier;
-use sfy::waves::Waves;
+use sfy::note::{NoteError, Notecarrier, NotecarrierBuilder};
+#[cfg(feature = "profiling")]
+use sfy::profile::{Phase, Profiler};
+use sfy::reboots::{Reboots, ResetCause};
 #[cfg(feature = "storage")]
-use sfy::{
-    storage::{SdSpiSpeed, Storage},
-    STORAGEQ,
-};
-use sfy::{Imu, Location, SharedState, State, NOTEQ};
+use sfy::safe_mode::SafeMode;
+#[cfg(feature = "storage")]
+use sfy::storage::{SdSpiSpeed, Storage, StorageBackend};
+use sfy::waves::{Exhausted, ImuError, Waves};
+use sfy::{Imu, Location, SharedState, State, WithState};
 
+#[cfg(feature = "adc")]
+mod adc;
 mod log;
 
 /// This static is used to transfer ownership of the IMU subsystem to the interrupt handler.
-type I = hal::i2c::Iom3;
+#[cfg(not(feature = "redundant-imu"))]
+type I = Guarded<hal::i2c::Iom3>;
+
+/// Both IMUs share the bus with the `redundant-imu` feature, locked in a critical section for
+/// every transaction (see `sfy::bus`).
+#[cfg(feature = "redundant-imu")]
+type I = shared_bus::I2cProxy<'static, shared_bus::CortexMMutex<Guarded<hal::i2c::Iom3>>>;
+
+/// The bus of the (primary) Notecard, used from the main loop.
+#[cfg(not(feature = "ct"))]
+type PrimaryNoteBus = Guarded<hal::i2c::Iom4>;
+
+/// The CT sensor shares the bus of the Notecard with the `ct` feature, both from the main loop.
+#[cfg(feature = "ct")]
+type PrimaryNoteBus = shared_bus::I2cProxy<'static, NoteMutex>;
+#[cfg(feature = "ct")]
+type NoteMutex = shared_bus::CortexMMutex<Guarded<hal::i2c::Iom4>>;
+
+#[cfg(not(feature = "redundant-notecard"))]
+pub type NoteBus = PrimaryNoteBus;
+
+/// The second Notecard answers at the same address, and is on a bus of its own with the
+/// `redundant-notecard` feature, also used from the main loop only (see `sfy::bus`).
+#[cfg(feature = "redundant-notecard")]
+pub type NoteBus = Either<PrimaryNoteBus, Guarded<hal::i2c::Iom2>>;
+
+/// Self-test of every IMU found at boot (see `sfy::bist`).
+type ImuChecks = heapless::Vec<sfy::bist::ImuCheck, { sfy::bist::MAX_IMUS }>;
+
+#[cfg(not(feature = "redundant-notecard"))]
+pub type Note = Notecarrier<NoteBus>;
+
+/// Requests go to the active Notecard, see `sfy::failover`.
+#[cfg(feature = "redundant-notecard")]
+pub type Note = RedundantNotecarrier<Notecarrier<NoteBus>>;
+
+/// Set during a transaction on the bus of the Notecard and the bus of the IMU (see `sfy::bus`).
+pub static NOTE_BUS: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "redundant-notecard")]
+pub static NOTE_BUS_SECONDARY: AtomicBool = AtomicBool::new(false);
+static IMU_BUS: AtomicBool = AtomicBool::new(false);
 type E = <I as embedded_hal::blocking::i2c::Write>::Error;
 static mut IMU: Option<sfy::Imu<E, I>> = None;
 
 pub static COUNT: AtomicI32 = AtomicI32::new(0);
+
+/// Core clock (`SYSCLK_MAX`), used as reference when checking the RTC.
+const SYSCLK_HZ: u32 = 48_000_000;
+
+/// Set to request a reset of the IMU from the `RTC` interrupt.
+pub static IMU_REINIT: AtomicBool = AtomicBool::new(false);
+
+/// Set to start the calibration capture from the `RTC` interrupt.
+pub static IMU_CALIBRATE: AtomicBool = AtomicBool::new(false);
+
+/// Set to flush the partially filled buffer from the `RTC` interrupt, cleared when done.
+pub static IMU_FLUSH: AtomicBool = AtomicBool::new(false);
+
+/// Set to stop sampling for good at the end of the deployment: the partially filled buffer is
+/// flushed and the IMU powered down from the `RTC` interrupt, which is then masked. Cleared when
+/// done.
+pub static IMU_STOP: AtomicBool = AtomicBool::new(false);
 defmt::timestamp!("{=i32}", COUNT.load(Ordering::Relaxed));
 
+/// Time to wait before resetting to retry after a failed setup [ms].
+const SETUP_RETRY_DELAY: u32 = 10 * 60_000;
+
+/// Stage of the setup at boot that failed, see `setup_failed`.
+#[derive(Debug, defmt::Format)]
+enum SetupError {
+    /// The Notecard did not answer, or could not be set up.
+    Notecard(NoteError),
+
+    /// The IMU did not answer, or could not be configured, after the retries.
+    #[cfg(not(feature = "redundant-imu"))]
+    Imu(Exhausted<E>),
+
+    /// Neither of the IMUs came up.
+    #[cfg(feature = "redundant-imu")]
+    NoImu,
+
+    /// Taking the first (empty) buffer, which sets the timestamp of the IMU.
+    ImuTimestamp(ImuError<E>),
+
+    /// Enabling the FIFO of the IMU, after the retries.
+    ImuFifo(Exhausted<E>),
+}
+
 /// The STATE contains the Real-Time-Clock which needs to be shared, as well as up-to-date
 /// longitude and latitude.
 pub static STATE: Mutex<RefCell<Option<SharedState<hal::rtc::Rtc>>>> =
@@ -107,8 +198,30 @@ fn main() -> ! {
     info!("Setting up IOM and RTC.");
     delay.delay_ms(1_000u32);
 
-    let i2c4 = i2c::I2c::new(dp.IOM4, pins.d10, pins.d9, i2c::Freq::F100kHz);
-    let i2c3 = i2c::I2c::new(dp.IOM3, pins.d6, pins.d7, i2c::Freq::F1mHz);
+    // The Notecard is only used from the main loop and the IMU only from the `RTC` interrupt, so
+    // that the buses are never contended (see `sfy::bus`).
+    let i2c4 = Guarded::new(
+        i2c::I2c::new(dp.IOM4, pins.d10, pins.d9, i2c::Freq::F100kHz),
+        &NOTE_BUS,
+    );
+    let i2c3 = Guarded::new(
+        i2c::I2c::new(dp.IOM3, pins.d6, pins.d7, i2c::Freq::F1mHz),
+        &IMU_BUS,
+    );
+
+    #[cfg(feature = "ct")]
+    let (i2c4, ct_bus) = {
+        // Only fails if the bus manager is created twice.
+        let bus = shared_bus::new_cortexm!(Guarded<hal::i2c::Iom4> = i2c4).unwrap();
+        (bus.acquire_i2c(), bus.acquire_i2c())
+    };
+
+    // The second Notecard on IOM2 (pads 25 and 27).
+    #[cfg(feature = "redundant-notecard")]
+    let i2c2 = Guarded::new(
+        i2c::I2c::new(dp.IOM2, pins.d25, pins.d27, i2c::Freq::F100kHz),
+        &NOTE_BUS_SECONDARY,
+    );
 
     // Set up RTC
     let mut rtc = hal::rtc::Rtc::new(dp.RTC, &mut dp.CLKGEN);
@@ -122,10 +235,48 @@ fn main() -> ! {
     rtc.set_alarm_repeat(hal::rtc::AlarmRepeat::DeciSecond);
     rtc.enable_alarm();
 
-    let mut location = Location::new();
+    // Check that the RTC (external crystal) is running against the cycle counter (internal
+    // oscillator).
+    {
+        let mut dcb = core.DCB;
+        let mut dwt = core.DWT;
+        dcb.enable_trace();
+        dwt.enable_cycle_counter();
+
+        let r0 = rtc.now().timestamp_millis();
+        let c0 = DWT::cycle_count();
+        delay.delay_ms(1_000u32);
+        let r1 = rtc.now().timestamp_millis();
+        let c1 = DWT::cycle_count();
+
+        let ref_ms = (c1.wrapping_sub(c0) / (SYSCLK_HZ / 1000)) as i64;
+        info!(
+            "RTC check: RTC: {} ms, cycle counter: {} ms",
+            r1 - r0,
+            ref_ms
+        );
+
+        if !sfy::clock::rate_ok(r1 - r0, ref_ms) {
+            rtc_fallback();
+        }
+    }
+
+    // Time stamps of the event log on the SD-card.
+    sfy::log::set_clock(&COUNT);
+
+    #[allow(unused_mut)]
+    let mut config = Config::default();
+    config
+        .validate()
+        .inspect_err(|e| error!("Compiled default config is not valid: {:?}", e))
+        .ok();
 
     let mut led = pins.d19.into_push_pull_output();
 
+    // Beeper or bright LED for recovery (see `sfy::beacon`).
+    #[cfg(feature = "beacon")]
+    let mut beacon_pin = pins.d8.into_push_pull_output();
+
     info!("Blinking to indicate start-up.");
     led.set_high().unwrap();
 
@@ -134,8 +285,13 @@ fn main() -> ! {
 
     led.set_low().unwrap();
 
+    // Cause of the last reset, reported in the health records and counted in the reboots.
+    let reset_cause = dp.RSTGEN.stat.read().bits();
+    let cause = ResetCause::from_stat(reset_cause);
+    info!("Reset cause: {:#x} ({:?})", reset_cause, cause);
+
     #[cfg(feature = "storage")]
-    let storage = {
+    let (mut storage, snapshot, mut reboots, mut deployment, mut safe_mode) = {
         info!("Setting up storage..");
 
         debug!("Setting up SPI for SD card..");
@@ -167,32 +323,269 @@ fn main() -> ! {
             })
             .ok();
 
-        storage
+        let mut buf = [0u8; CONFIG_SZ];
+        match storage.read_config(&mut buf) {
+            Ok(Some(c)) => {
+                info!("Applying config from SD-card..");
+                config
+                    .apply_json(c)
+                    .inspect_err(|e| error!("Invalid config file: {:?}", e))
+                    .ok();
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to read config file: {:?}", e),
+        }
+
+        let snapshot = storage
+            .read_snapshot()
+            .inspect_err(|e| error!("Failed to read snapshot: {:?}", e))
+            .ok()
+            .flatten();
+        info!("Snapshot from before reboot: {:?}", snapshot);
+
+        let read = storage
+            .read_reboots()
+            .inspect_err(|e| error!("Failed to read reboots: {:?}", e))
+            .ok();
+        let mut reboots = read.clone().flatten().unwrap_or_default();
+        reboots.count(cause);
+        let written = storage
+            .write_reboots(&reboots)
+            .inspect_err(|e| error!("Failed to write reboots: {:?}", e))
+            .is_ok();
+
+        // The nonces of this boot must not repeat those of the last boot, so the count is only
+        // used once it has been read and written (see `crypt`).
+        if read.is_some() && written {
+            #[cfg(feature = "encryption")]
+            sfy::crypt::set_boot(reboots.total);
+        } else {
+            warn!("Reboot count not persisted.");
+
+            #[cfg(feature = "encryption")]
+            log("Reboot count not persisted, packages are not sealed or sent.");
+        }
+
+        let deployment = storage
+            .read_deployment()
+            .inspect_err(|e| error!("Failed to read deployment: {:?}", e))
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+
+        // Counted once the effective config is known.
+        let safe_mode = storage
+            .read_safe_mode()
+            .inspect_err(|e| error!("Failed to read safe mode: {:?}", e))
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+
+        (storage, snapshot, reboots, deployment, safe_mode)
+    };
+
+    #[cfg(not(feature = "storage"))]
+    let mut reboots = {
+        let mut reboots = Reboots::default();
+        reboots.count(cause);
+        reboots
     };
+    info!("Reboots: {:?}", reboots);
+
+    #[cfg(not(feature = "storage"))]
+    let mut deployment = Deployment::default();
+    info!("Deployment: {:?}", deployment);
+
+    // Samples leading up to a panic or hard fault, kept in RAM across the reset.
+    let postmortem = unsafe { sfy::postmortem::ring() };
+    if let Some(d) = postmortem.frozen() {
+        let mut msg = heapless::String::<256>::new();
+        write!(&mut msg, "{}", d)
+            .inspect_err(|e| {
+                defmt::error!("failed to format post-mortem: {:?}", defmt::Debug2Format(e))
+            })
+            .ok();
+        warn!("{}", msg.as_str());
+        log(&msg);
+
+        #[cfg(feature = "storage")]
+        storage
+            .append_postmortem(&d)
+            .inspect_err(|e| error!("Failed to write post-mortem: {:?}", e))
+            .ok();
+    }
+    postmortem.start(0, 0.);
+
+    #[cfg(all(feature = "encryption", not(feature = "storage")))]
+    log("Reboot count not persisted without storage, packages are not sealed or sent.");
+
+    // Only fails if the queues are split twice. The endpoints are kept through resets of the IMU.
+    let queues = sfy::split_queues().unwrap();
+    let imu_p = queues.imu;
+    let mut imu_queue = queues.note;
 
     #[cfg(feature = "storage")]
-    let (imu_p, storage_consumer) = unsafe { STORAGEQ.split() };
+    let (storage_consumer, note_p) = (queues.storage, queues.note_producer);
+
+    // Unique ID of the chip, seeds the sync jitter of the buoy.
+    let device_id =
+        (dp.MCUCTRL.chipid0.read().bits() as u64) << 32 | dp.MCUCTRL.chipid1.read().bits() as u64;
+    info!("Device ID: {:#x}", device_id);
+
+    info!("Setting up Notecarrier..");
+    #[cfg(not(feature = "redundant-notecard"))]
+    let note = NotecarrierBuilder::new()
+        .config(config)
+        .device_id(device_id)
+        .clock(|| STATE.try_now_millis())
+        .build(i2c4, &mut delay);
+
+    #[cfg(feature = "redundant-notecard")]
+    let note = {
+        let (failover_syncs, failover_retry) = (config.failover_syncs, config.failover_retry);
+        let mut build = |i2c: NoteBus| {
+            NotecarrierBuilder::new()
+                .config(config.clone())
+                .device_id(device_id)
+                .clock(|| STATE.try_now_millis())
+                .build(i2c, &mut delay)
+                .inspect_err(|e| error!("Failed to set up Notecard: {:?}", e))
+        };
+
+        match (build(Either::A(i2c4)), build(Either::B(i2c2))) {
+            (Err(e), Err(_)) => Err(e),
+            (p, s) => Ok(
+                RedundantNotecarrier::new(p.ok(), s.ok(), failover_syncs, failover_retry).unwrap(),
+            ),
+        }
+    };
+
+    let mut note = match note {
+        Ok(note) => note,
+        Err(e) => {
+            let msg = setup_msg(SetupError::Notecard(e), &reboots);
+
+            #[cfg(feature = "storage")]
+            storage
+                .append_setup_log(&msg)
+                .inspect_err(|e| error!("Failed to write setup log: {:?}", e))
+                .ok();
 
+            setup_failed(&msg, None, None, &mut delay)
+        }
+    };
+
+    let config = note.config().clone();
+    info!("Effective config: {:?}", config);
+    sfy::log::set_log_time(config.log_time);
+
+    // The last panic, kept in RAM across the reset, with the time format of the event log.
     #[cfg(feature = "storage")]
-    let (note_p, mut imu_queue) = unsafe { NOTEQ.split() };
+    if let Some(p) = unsafe { sfy::panic_log::record() }.take() {
+        let line = p.line(&sfy::log::log_time());
+        warn!("Panic before the reset: {}", line.as_str());
+
+        storage
+            .append_panic_log(&line)
+            .inspect_err(|e| error!("Failed to write panic log: {:?}", e))
+            .ok();
+    }
+
+    // Reset the board if the main loop, or the interrupt reading the IMU, stalls (see
+    // `sfy::watchdog`). Armed before safe mode, so that a board that hangs in safe mode or while
+    // it is set up is reset as well. Restarted every turn of the main loop or the safe mode loop.
+    let wdt = dp.WDT;
+    if config.watchdog > 0 {
+        info!(
+            "Arming watchdog: {} s",
+            sfy::watchdog::timeout(config.watchdog)
+        );
+        dp.RSTGEN
+            .cfg
+            .modify(|r, w| unsafe { w.bits(r.bits() | sfy::watchdog::WDREN) });
+        wdt.cfg
+            .write(|w| unsafe { w.bits(sfy::watchdog::cfg(config.watchdog)) });
+        wdt.rstrt
+            .write(|w| unsafe { w.bits(sfy::watchdog::RSTRT_KEY) });
+    }
 
+    // Before anything that may have caused the resets is set up.
     #[cfg(feature = "storage")]
-    let mut storage_manager = sfy::StorageManager::new(storage, storage_consumer, note_p);
+    {
+        let active = safe_mode.boot(cause, &config.safe_mode);
+        info!("Safe mode: {:?} (active: {})", safe_mode, active);
 
-    #[cfg(not(feature = "storage"))]
-    let (imu_p, mut imu_queue) = unsafe { NOTEQ.split() };
+        storage
+            .write_safe_mode(&safe_mode)
+            .inspect_err(|e| error!("Failed to write safe mode: {:?}", e))
+            .ok();
 
-    info!("Setting up Notecarrier..");
-    let mut note = Notecarrier::new(i2c4, &mut delay).unwrap();
+        if active {
+            safe_mode_loop(
+                &mut note,
+                &mut storage,
+                &mut safe_mode,
+                &config,
+                &reboots,
+                cause,
+                &wdt,
+                &mut delay,
+            );
+        }
+    }
+
+    // Before the interrupts are enabled.
+    postmortem.start(config.postmortem, sfy::waves::FREQ.value());
+    unsafe { sfy::burst::detector() }.start(&config.burst, sfy::waves::FREQ.value());
+
+    let mut location = Location::new(&config);
+
+    #[cfg(feature = "ct")]
+    let mut ct = {
+        info!("Probing for CT sensor..");
+        sfy::ct::CtSensor::probe(ct_bus, &mut delay)
+            .inspect_err(|e| {
+                warn!("CT sensor not found: {:?}", e);
+                log("CT sensor not found.");
+            })
+            .ok()
+    };
+
+    #[cfg(feature = "adc")]
+    let mut adc = if config.adc.is_empty() {
+        None
+    } else {
+        info!("Setting up {} ADC channels..", config.adc.len());
+        adc::Adc::new(&config.adc)
+            .inspect_err(|e| {
+                warn!("ADC setup failed: {:?}", e);
+                log("ADC setup failed.");
+            })
+            .ok()
+    };
+
+    #[cfg(feature = "storage")]
+    let mut storage_manager = sfy::StorageManager::new(storage, storage_consumer, note_p, &config);
+
+    #[cfg(feature = "storage")]
+    if let Some(s) = &snapshot {
+        storage_manager.restore(s);
+    }
 
     info!("Send startup-message over cellular.");
 
-    let mut w = heapless::String::<100>::new();
+    let mut w = heapless::String::<160>::new();
     w.push_str("SFY (v").unwrap();
     w.push_str(git_version!()).unwrap();
     w.push_str(") (sn: ").unwrap();
     w.push_str(sfy::note::BUOYSN).unwrap();
-    w.push_str(") started up.").unwrap();
+    w.push_str(") started up").unwrap();
+    write!(
+        &mut w,
+        " (reboots: {}, in deployment: {}, cause: {:?}).",
+        reboots.total, reboots.deployment, cause
+    )
+    .ok();
     info!("{}", w);
 
     note.hub()
@@ -204,17 +597,32 @@ fn main() -> ! {
     // logging on panic and hard resets.
     //
     // TODO: Should maybe `pin_mut!` NOTE to prevent it being moved on the stack.
+    // Start out with the last known position (if any), until a new one is retrieved.
+    #[cfg(feature = "storage")]
+    let (position_time, lon, lat) = snapshot
+        .as_ref()
+        .map(|s| (s.position_time, s.lon, s.lat))
+        .unwrap_or((0, 0.0, 0.0));
+
+    #[cfg(not(feature = "storage"))]
+    let (position_time, lon, lat) = (0, 0.0, 0.0);
+
     free(|cs| unsafe {
         log::NOTE = Some(&mut note as *mut _);
 
         STATE.borrow(cs).replace(Some(SharedState {
             rtc,
-            position_time: 0,
-            lon: 0.0,
-            lat: 0.0,
+            position_time,
+            lon,
+            lat,
+            gps: Default::default(),
+            time: Default::default(),
         }));
     });
 
+    // Fixes right after power-on are discarded, starting now.
+    location.start_warmup(STATE.now_millis());
+
     info!("Try to fetch location and time before starting main loop..");
     location
         .check_retrieve(&STATE, &mut delay, &mut note)
@@ -222,28 +630,57 @@ fn main() -> ! {
         .ok();
 
     let (now, position_time, lat, lon) = STATE.get();
-    COUNT.store(
-        (now.timestamp_millis() / 1000).try_into().unwrap_or(0),
-        Ordering::Relaxed,
-    );
+    let now = now.timestamp_millis();
+    COUNT.store((now / 1000).try_into().unwrap_or(0), Ordering::Relaxed);
     info!(
         "Now: {} ms, position_time: {}, lat: {}, lon: {}",
-        now.timestamp_millis(),
-        position_time,
-        lat,
-        lon
+        now, position_time, lat, lon
     );
 
     info!("Setting up IMU..");
-    let mut waves = Waves::new(i2c3).unwrap();
-    waves
-        .take_buf(now.timestamp_millis(), position_time, lon, lat)
-        .unwrap(); // set timestamp.
+    let (waves, imu_checks) =
+        match setup_imu(i2c3, &config, now, position_time, lon, lat, &mut delay) {
+            Ok(r) => r,
+            Err(e) => {
+                let msg = setup_msg(e, &reboots);
 
-    info!("Enable IMU.");
-    waves.enable_fifo(&mut delay).unwrap();
+                #[cfg(feature = "storage")]
+                storage_manager
+                    .append_setup_log(&msg)
+                    .inspect_err(|e| error!("Failed to write setup log: {:?}", e))
+                    .ok();
+
+                setup_failed(&msg, Some(&mut note), Some(&wdt), &mut delay)
+            }
+        };
+
+    // The buoy continues when the self-test fails, the report flags what is degraded.
+    if config.bist.enabled {
+        info!("Running self-test..");
+
+        #[cfg(feature = "storage")]
+        let sd = Some(storage_manager.check_sd(now as u32));
+
+        #[cfg(not(feature = "storage"))]
+        let sd = None;
+
+        let report = sfy::bist::run_bist(&mut note, imu_checks, sd, git_version!(), &mut delay);
+
+        #[cfg(feature = "storage")]
+        storage_manager
+            .append_setup_log(&report.summary())
+            .inspect_err(|e| error!("Failed to write setup log: {:?}", e))
+            .ok();
+    }
 
-    let imu = sfy::Imu::new(waves, imu_p);
+    let imu = sfy::Imu::new(
+        waves,
+        imu_p,
+        sfy::clock::DriftCorrection::new(config.rtc_temp_coeff),
+        config.warmup,
+        config.queue_full,
+        config.monotonic,
+    );
 
     // Move IMU into temporary variable for moving it into the `RTC` interrupt
     // routine, _before_ we enable interrupts.
@@ -263,13 +700,35 @@ fn main() -> ! {
     let mut good_tries: u32 = GOOD_TRIES;
     #[cfg(feature = "storage")]
     let mut sd_good: bool = true; // Do not spam with log messags.
+    #[cfg(feature = "storage")]
+    let mut last_snapshot: i64 = 0;
+    #[cfg(feature = "storage")]
+    let mut uptime: i64 = 0;
+    let mut last_health: i64 = 0;
+    #[cfg(feature = "profiling")]
+    let mut profiler = Profiler::new(STATE.now_millis());
+
+    #[cfg(feature = "beacon")]
+    let mut beacon = sfy::beacon::Controller::new(config.beacon);
+    #[cfg(feature = "beacon")]
+    beacon.start_startup(STATE.now_millis());
 
     loop {
-        let now = STATE.now().timestamp_millis();
+        let now = STATE.now_millis();
+
+        #[cfg(feature = "beacon")]
+        if beacon.output(now) {
+            beacon_pin.set_high().unwrap();
+        } else {
+            beacon_pin.set_low().unwrap();
+        }
+
+        #[cfg(feature = "profiling")]
+        profiler.start(DWT::cycle_count());
 
         // Move data to SD card and enqueue for Notecard.
         #[cfg(feature = "storage")]
-        match storage_manager.drain_queue(&mut note, &mut delay) {
+        match storage_manager.drain_queue() {
             Err(e) => {
                 error!("Failed to write to SD card: {:?}", e);
 
@@ -294,6 +753,27 @@ fn main() -> ! {
             _ => {}
         };
 
+        // A burst around an extreme event, captured by the IMU interrupt.
+        sfy::burst::drain(|b| {
+            #[cfg(feature = "storage")]
+            storage_manager
+                .append_burst(b)
+                .inspect_err(|e| error!("Failed to write burst: {:?}", e))
+                .ok();
+
+            let mut msg = heapless::String::<256>::new();
+            write!(&mut msg, "{}", b).ok();
+
+            if config.burst.notice {
+                sfy::log::log_at(sfy::log::Category::Imu, sfy::log::Level::Warn, &msg);
+            } else {
+                info!("{}", msg.as_str());
+            }
+        });
+
+        #[cfg(feature = "profiling")]
+        profiler.lap(Phase::Storage, DWT::cycle_count());
+
         // XXX: This needs to be adapted to frequency, and queue length. Maybe just remove when we
         // have the remaining space check? Check after Hjeltefjorden deployment.
         const LOOP_DELAY: u32 = 14 * 20_000;
@@ -304,43 +784,416 @@ fn main() -> ! {
             || ((imu_queue.capacity() - imu_queue.len()) < 3
                 && (now - last) > SHORT_LOOP_DELAY as i64)
         {
+            let output_freq = sfy::waves::FREQ.value() / f32::from(config.decimation());
             let queue_time: f64 = f64::from(sfy::axl::SAMPLE_NO as u32)
                 * f64::from(sfy::NOTEQ_SZ as u32)
-                / f64::from(sfy::waves::OUTPUT_FREQ);
+                / f64::from(output_freq);
             debug_assert!(
                 (f64::from(LOOP_DELAY) / 1000.)
                     < queue_time,
-                "loop is too slow, NOTEQ will overflow: loop: {} ms vs queue: {} ms (length: {}, sample_no: {}, freq: {})", LOOP_DELAY, queue_time * 1000., sfy::NOTEQ_SZ, sfy::axl::SAMPLE_NO, sfy::waves::OUTPUT_FREQ
+                "loop is too slow, NOTEQ will overflow: loop: {} ms vs queue: {} ms (length: {}, sample_no: {}, freq: {})", LOOP_DELAY, queue_time * 1000., sfy::NOTEQ_SZ, sfy::axl::SAMPLE_NO, output_freq
             );
 
             // This updates the RTC. It should happen in the same block as `last`, otherwise we
             // could theoretically get a negative time jump. In practice that should not be possible.
             let l = location.check_retrieve(&STATE, &mut delay, &mut note);
 
+            if location.clock.take_fault() && !sfy::clock::time_degraded() {
+                rtc_fallback();
+            }
+
+            #[cfg(feature = "beacon")]
+            beacon.check_position(now, location.lat, location.lon, location.position_time);
+
+            #[cfg(feature = "storage")]
+            storage_manager
+                .append_location_events(&mut location.events)
+                .inspect_err(|e| error!("Failed to append location events: {:?}", e))
+                .ok();
+
+            #[cfg(feature = "profiling")]
+            profiler.lap(Phase::Location, DWT::cycle_count());
+
             #[cfg(feature = "storage")]
             defmt::debug!(
-                "notecard iteration, now: {}, note queue: {}, storage queue: {}",
+                "notecard iteration, now: {}, note queue: {}, storage queue: {}, dropped: {:?}",
                 now,
                 imu_queue.len(),
-                storage_manager.storage_queue.len()
+                storage_manager.storage_queue.len(),
+                sfy::queue::DROPPED.get()
             );
 
             #[cfg(not(feature = "storage"))]
             defmt::debug!(
-                "notecard iteration, now: {}, note queue: {}",
+                "notecard iteration, now: {}, note queue: {}, dropped: {:?}",
                 now,
                 imu_queue.len(),
+                sfy::queue::DROPPED.get()
             );
 
             #[cfg(not(feature = "deploy"))]
             led.toggle().unwrap();
 
+            note.check_quiet(now, &mut delay)
+                .inspect_err(|e| error!("Failed to check quiet hours: {:?}", e))
+                .ok();
+
             sfy::log::drain_log(&mut note, &mut delay)
                 .inspect_err(|e| defmt::error!("drain log: {:?}", e))
                 .ok();
 
+            #[cfg(feature = "storage")]
+            storage_manager
+                .drain_event_log()
+                .inspect_err(|e| error!("Failed to write event log: {:?}", e))
+                .ok();
+
+            note.check_motion(now, &mut delay)
+                .inspect_err(|e| error!("Failed to check motion: {:?}", e))
+                .ok();
+
+            note.read_card(&mut delay);
+
+            #[cfg(feature = "storage")]
+            storage_manager
+                .queue_requested_packages(now, &mut note, &mut delay)
+                .inspect_err(|e| error!("Failed to send requested packages: {:?}", e))
+                .ok();
+
             let nd = note.drain_queue(&mut imu_queue, &mut delay);
-            let ns = note.check_and_sync(&mut delay);
+            let ns = note.check_and_sync(now, &mut delay);
+
+            #[cfg(feature = "redundant-notecard")]
+            note.check_failover(now);
+
+            #[cfg(feature = "profiling")]
+            profiler.lap(Phase::Notecard, DWT::cycle_count());
+
+            #[cfg(feature = "storage")]
+            if note.sync_history.take_changed() {
+                storage_manager
+                    .write_sync_history(&note.sync_history)
+                    .inspect_err(|e| error!("Failed to write sync history: {:?}", e))
+                    .ok();
+            }
+
+            #[cfg(feature = "profiling")]
+            profiler.lap(Phase::Storage, DWT::cycle_count());
+
+            if (now - last_health) > sfy::note::HEALTH_INTERVAL {
+                let mut health = note.health(now);
+                health.note_queue = imu_queue.len() as u32;
+                health.reset_cause = reset_cause;
+                health.reboots = reboots.total;
+                health.reboots_deployment = reboots.deployment;
+                health.gps = STATE.with_state(|s| s.gps).unwrap_or_default();
+                #[cfg(feature = "redundant-notecard")]
+                {
+                    health.notecard = Some(note.active() as u8);
+                }
+                health.bearer = note
+                    .bearer(&mut delay)
+                    .inspect_err(|e| error!("Failed to read transport: {:?}", e))
+                    .ok()
+                    .flatten();
+
+                #[cfg(feature = "ct")]
+                {
+                    health.ct = ct.as_mut().and_then(|ct| {
+                        ct.read(&mut delay)
+                            .inspect_err(|e| error!("Failed to read CT sensor: {:?}", e))
+                            .ok()
+                    });
+                }
+
+                #[cfg(feature = "adc")]
+                {
+                    health.adc = adc.as_mut().and_then(|adc| {
+                        sfy::adc::read(adc, &config.adc, &mut delay)
+                            .inspect_err(|e| error!("Failed to read ADC channels: {:?}", e))
+                            .ok()
+                    });
+                }
+
+                #[cfg(feature = "profiling")]
+                {
+                    let b = profiler.take(now, SYSCLK_HZ);
+                    info!("{}", b);
+
+                    let mut msg = heapless::String::<160>::new();
+                    write!(&mut msg, "{}", b).ok();
+                    log(&msg);
+
+                    health.profile = Some(b);
+                }
+
+                #[cfg(feature = "storage")]
+                {
+                    health.storage_queue = storage_manager.storage_queue.len() as u32;
+                    health.free_space = storage_manager.free_space();
+
+                    storage_manager
+                        .append_health(&health)
+                        .inspect_err(|e| error!("Failed to append health: {:?}", e))
+                        .ok();
+
+                    #[cfg(feature = "profiling")]
+                    profiler.lap(Phase::Storage, DWT::cycle_count());
+                }
+
+                note.send_health(&health, &mut delay)
+                    .inspect_err(|e| error!("Failed to send health note: {:?}", e))
+                    .ok();
+                last_health = now;
+
+                #[cfg(feature = "profiling")]
+                profiler.lap(Phase::Notecard, DWT::cycle_count());
+            }
+
+            #[cfg(feature = "storage")]
+            if (now - last_snapshot) > sfy::storage::snapshot::SNAPSHOT_INTERVAL {
+                storage_manager
+                    .write_snapshot(&STATE)
+                    .inspect_err(|e| error!("Failed to write snapshot: {:?}", e))
+                    .ok();
+                last_snapshot = now;
+
+                #[cfg(feature = "profiling")]
+                profiler.lap(Phase::Storage, DWT::cycle_count());
+            }
+
+            // Time since boot for the safe mode, not counting a jump when the RTC is set.
+            #[cfg(feature = "storage")]
+            if last > 0 {
+                uptime += (now - last).clamp(0, 2 * LOOP_DELAY as i64);
+
+                if safe_mode.check_settled(uptime, &config.safe_mode) {
+                    info!(
+                        "No fault reset for {} ms, clearing safe mode count.",
+                        uptime
+                    );
+                    storage_manager
+                        .write_safe_mode(&safe_mode)
+                        .inspect_err(|e| error!("Failed to write safe mode: {:?}", e))
+                        .ok();
+                }
+            }
+
+            // The RTC may have been set in this iteration.
+            let t = STATE.now_millis();
+
+            if deployment.check_start(t, sfy::clock::time_synced()) {
+                info!("Deployment started at: {}", t);
+
+                #[cfg(feature = "storage")]
+                storage_manager
+                    .write_deployment(&deployment)
+                    .inspect_err(|e| error!("Failed to write deployment: {:?}", e))
+                    .ok();
+            }
+
+            if deployment.expired(note.config().deployment_duration, t) {
+                // The duration may have been extended on notehub.
+                let duration = note
+                    .refresh_deployment_duration(&mut delay)
+                    .inspect_err(|e| error!("Failed to read deployment duration: {:?}", e))
+                    .unwrap_or(note.config().deployment_duration);
+
+                if deployment.expired(duration, t) {
+                    let mut msg = heapless::String::<128>::new();
+                    write!(
+                        &mut msg,
+                        "Deployment duration of {} h reached (started at {} ms), stopping.",
+                        duration, deployment.start
+                    )
+                    .ok();
+                    warn!("{}", msg.as_str());
+                    sfy::log::log_at(sfy::log::Category::Imu, sfy::log::Level::Warn, &msg);
+
+                    IMU_STOP.store(true, Ordering::Release);
+                    for _ in 0..50 {
+                        if !IMU_STOP.load(Ordering::Acquire) {
+                            break;
+                        }
+                        delay.delay_ms(100u16);
+                    }
+
+                    #[cfg(feature = "storage")]
+                    {
+                        storage_manager.flush(
+                            &mut note,
+                            &mut imu_queue,
+                            &mut delay,
+                            sfy::note::FLUSH_TIMEOUT,
+                        );
+                        storage_manager
+                            .shutdown(&STATE)
+                            .inspect_err(|e| error!("Failed to shut down storage: {:?}", e))
+                            .ok();
+                    }
+
+                    #[cfg(not(feature = "storage"))]
+                    note.flush_queue(
+                        &mut imu_queue,
+                        &mut delay,
+                        t + sfy::note::FLUSH_TIMEOUT as i64,
+                    )
+                    .inspect_err(|e| error!("Failed to flush notecard queue: {:?}", e))
+                    .ok();
+                    note.shutdown(&mut delay)
+                        .inspect_err(|e| error!("Failed to shut down notecard: {:?}", e))
+                        .ok();
+
+                    standby(&wdt);
+                }
+            }
+
+            match note.read_log_levels(&mut delay) {
+                Ok(Some(l)) => l.apply(),
+                Ok(None) => {}
+                Err(e) => error!("Failed to read log levels: {:?}", e),
+            }
+
+            match note.read_command(&mut delay) {
+                Ok(Some((cmd, value))) => {
+                    info!("Executing command: {:?} (value: {:?})", cmd, value);
+
+                    // The setting in effect after the command, for commands that change one.
+                    let mut setting = None;
+
+                    // The fix retrieved by the `locate` command.
+                    let mut fix = None;
+
+                    let ok = match cmd {
+                        Command::Reset => {
+                            // Let the IMU interrupt push the last samples before draining the
+                            // queues.
+                            IMU_FLUSH.store(true, Ordering::Release);
+                            for _ in 0..50 {
+                                if !IMU_FLUSH.load(Ordering::Acquire) {
+                                    break;
+                                }
+                                delay.delay_ms(100u16);
+                            }
+
+                            // Storage first: the snapshot is written before the card is
+                            // released, and the drained packages are queued for the notecard.
+                            #[cfg(feature = "storage")]
+                            {
+                                storage_manager.flush(
+                                    &mut note,
+                                    &mut imu_queue,
+                                    &mut delay,
+                                    sfy::note::FLUSH_TIMEOUT,
+                                );
+                                storage_manager
+                                    .shutdown(&STATE)
+                                    .inspect_err(|e| error!("Failed to shut down storage: {:?}", e))
+                                    .ok();
+                            }
+
+                            #[cfg(not(feature = "storage"))]
+                            note.flush_queue(
+                                &mut imu_queue,
+                                &mut delay,
+                                t + sfy::note::FLUSH_TIMEOUT as i64,
+                            )
+                            .inspect_err(|e| error!("Failed to flush notecard queue: {:?}", e))
+                            .ok();
+                            note.ack_command(&mut delay, cmd, true, None).ok();
+                            note.shutdown(&mut delay)
+                                .inspect_err(|e| error!("Failed to shut down notecard: {:?}", e))
+                                .ok();
+
+                            reset(&mut note, &mut delay);
+                        }
+                        Command::ReinitNotecard => note
+                            .reinit(&mut delay)
+                            .inspect_err(|e| error!("Failed to re-initialize notecard: {:?}", e))
+                            .is_ok(),
+                        Command::Provision => note
+                            .provision(&mut delay)
+                            .inspect_err(|e| error!("Failed to provision notecard: {:?}", e))
+                            .is_ok(),
+                        Command::ReinitImu => {
+                            IMU_REINIT.store(true, Ordering::Release);
+                            true
+                        }
+                        Command::Calibrate => {
+                            IMU_CALIBRATE.store(true, Ordering::Release);
+                            true
+                        }
+                        Command::DumpLogs => sfy::log::drain_log(&mut note, &mut delay)
+                            .and_then(|_| note.hub().sync(&mut delay, false)?.wait(&mut delay))
+                            .is_ok(),
+                        Command::SyncPeriod => {
+                            let ok = match value {
+                                Some(minutes) => note
+                                    .set_sync_period(&mut delay, minutes)
+                                    .inspect_err(|e| error!("Failed to set sync period: {:?}", e))
+                                    .unwrap_or(false),
+                                None => {
+                                    warn!("No sync period given.");
+                                    false
+                                }
+                            };
+                            setting = Some(note.config().sync_period);
+                            ok
+                        }
+                        Command::Locate => {
+                            fix = location
+                                .force_retrieve(&STATE, &mut delay, &mut note)
+                                .inspect_err(|e| error!("Failed to retrieve location: {:?}", e))
+                                .ok()
+                                .flatten();
+                            info!("Requested fix: {:?}", fix);
+                            fix.is_some()
+                        }
+                        Command::StartDeployment => {
+                            reboots.start_deployment();
+                            deployment.restart();
+
+                            #[cfg(feature = "storage")]
+                            let ok = storage_manager
+                                .write_reboots(&reboots)
+                                .and_then(|_| storage_manager.write_deployment(&deployment))
+                                .inspect_err(|e| error!("Failed to write reboots: {:?}", e))
+                                .is_ok();
+
+                            #[cfg(not(feature = "storage"))]
+                            let ok = true;
+
+                            ok
+                        }
+                        #[cfg(feature = "beacon")]
+                        Command::Beacon => {
+                            let duration = value.map(|m| m as i64 * 60 * 1000).unwrap_or(i64::MAX);
+                            let duration =
+                                beacon.start(now, duration, sfy::beacon::Reason::Command);
+                            setting = Some((duration / (60 * 1000)) as u32);
+                            true
+                        }
+                        #[cfg(not(feature = "beacon"))]
+                        Command::Beacon => {
+                            warn!("No beacon, build with the `beacon` feature.");
+                            false
+                        }
+                        Command::ExitSafeMode => {
+                            warn!("Not in safe mode.");
+                            false
+                        }
+                    };
+
+                    let ack = match cmd {
+                        Command::Locate => note.ack_locate(&mut delay, fix),
+                        _ => note.ack_command(&mut delay, cmd, ok, setting),
+                    };
+                    ack.inspect_err(|e| error!("Failed to acknowledge command: {:?}", e))
+                        .ok();
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to read command: {:?}", e),
+            }
 
             match (l, nd, ns) {
                 (Ok(_), Ok(_), Ok(_)) => good_tries = GOOD_TRIES,
@@ -377,6 +1230,10 @@ fn main() -> ! {
                     }
                 }
             };
+
+            #[cfg(feature = "profiling")]
+            profiler.lap(Phase::Notecard, DWT::cycle_count());
+
             last = now;
         }
 
@@ -388,12 +1245,206 @@ fn main() -> ! {
 
         // defmt::flush();
 
-        // TODO:
-        // * Set up and feed watchdog.
+        // The loop came around, restart the watchdog.
+        wdt.rstrt
+            .write(|w| unsafe { w.bits(sfy::watchdog::RSTRT_KEY) });
+    }
+}
+
+/// Set up the IMU(s) and start sampling at `now`.
+fn setup_imu(
+    i2c: Guarded<hal::i2c::Iom3>,
+    config: &Config,
+    now: i64,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    delay: &mut impl DelayMs<u16>,
+) -> Result<(sfy::ImuWaves<I>, ImuChecks), SetupError> {
+    let mut checks = ImuChecks::new();
+
+    #[cfg(not(feature = "redundant-imu"))]
+    let mut waves = {
+        let mut waves = Waves::new(i2c, config, delay).map_err(SetupError::Imu)?;
+
+        if config.bist.enabled {
+            checks.push(sfy::bist::check_imu(&mut waves, delay)).ok();
+        }
+
+        waves
+    };
+
+    #[cfg(feature = "redundant-imu")]
+    let mut waves = {
+        // Only fails if the bus manager is created twice.
+        let bus = shared_bus::new_cortexm!(Guarded<hal::i2c::Iom3> = i2c).unwrap();
+        let mut primary = Waves::new(bus.acquire_i2c(), config, delay).ok();
+        let mut secondary = Waves::new_with_address(
+            bus.acquire_i2c(),
+            config,
+            config.imu_address_secondary,
+            delay,
+        )
+        .ok();
+
+        // Before the redundant IMU takes over the FIFOs.
+        if config.bist.enabled {
+            for w in [primary.as_mut(), secondary.as_mut()].into_iter().flatten() {
+                checks.push(sfy::bist::check_imu(w, delay)).ok();
+            }
+        }
+
+        sfy::waves::redundant::RedundantImu::new(primary, secondary).ok_or(SetupError::NoImu)?
+    };
+    waves
+        .take_buf(now, position_time, lon, lat)
+        .map_err(SetupError::ImuTimestamp)?; // set timestamp.
+
+    info!("Enable IMU.");
+    waves.start_fifo(delay).map_err(SetupError::ImuFifo)?;
+
+    Ok((waves, checks))
+}
+
+/// Message logged when the setup fails at `e`.
+fn setup_msg(e: SetupError, reboots: &Reboots) -> heapless::String<256> {
+    let mut msg = heapless::String::<256>::new();
+    write!(
+        &mut msg,
+        "SFY (v{}) setup failed: {:?} (reboots: {}).",
+        git_version!(),
+        e,
+        reboots.total
+    )
+    .inspect_err(|e| defmt::error!("failed to format setup-err: {:?}", defmt::Debug2Format(e)))
+    .ok();
+
+    msg
+}
+
+/// The setup at boot failed: send `msg` to notehub if the Notecard is up, and reset after
+/// `SETUP_RETRY_DELAY` to try again. The buoy keeps retrying (and reporting) on every boot rather
+/// than halting. The watchdog, when it is armed, is restarted while waiting.
+fn setup_failed(
+    msg: &str,
+    note: Option<&mut Note>,
+    wdt: Option<&hal::pac::WDT>,
+    delay: &mut impl DelayMs<u16>,
+) -> ! {
+    error!("{}", msg);
+
+    if let Some(note) = note {
+        log(msg);
+        sfy::log::drain_log(note, delay)
+            .inspect_err(|e| error!("Failed to send setup error: {:?}", e))
+            .ok();
+        note.sync_and_wait(delay, sfy::note::SHUTDOWN_SYNC_TIMEOUT)
+            .inspect_err(|e| error!("Failed to sync setup error: {:?}", e))
+            .ok();
+    }
+
+    warn!(
+        "Resetting in {} s to retry setup..",
+        SETUP_RETRY_DELAY / 1000
+    );
+    for _ in 0..(SETUP_RETRY_DELAY / 1000) {
+        delay.delay_ms(1_000u16);
+
+        // The retry is not a stall, the watchdog must not reset the board first.
+        if let Some(wdt) = wdt {
+            wdt.rstrt
+                .write(|w| unsafe { w.bits(sfy::watchdog::RSTRT_KEY) });
+        }
+    }
+
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Run in safe mode (see `sfy::safe_mode`) until the `exit-safe-mode` command: the IMU, GPS and
+/// storage of packages are never set up, only a heartbeat is logged every `heartbeat` minutes
+/// and commands are checked every minute. The watchdog is restarted every minute.
+#[cfg(feature = "storage")]
+fn safe_mode_loop(
+    note: &mut Note,
+    storage: &mut impl StorageBackend,
+    safe_mode: &mut SafeMode,
+    config: &Config,
+    reboots: &Reboots,
+    cause: ResetCause,
+    wdt: &hal::pac::WDT,
+    delay: &mut impl DelayMs<u16>,
+) -> ! {
+    warn!("Entering safe mode: {:?}", safe_mode);
+
+    let heartbeat = (config.safe_mode.heartbeat as u32).max(1);
+    let mut minutes: u32 = 0;
+
+    loop {
+        if minutes % heartbeat == 0 {
+            let mut msg = heapless::String::<256>::new();
+            write!(
+                &mut msg,
+                "SFY (v{}) in safe mode after {} fault resets (reboots: {}, cause: {:?}, \
+                 minutes: {}), send exit-safe-mode to leave.",
+                git_version!(),
+                safe_mode.recent,
+                reboots.total,
+                cause,
+                minutes
+            )
+            .ok();
+            warn!("{}", msg.as_str());
+
+            note.hub()
+                .log(delay, &msg, false, false)
+                .and_then(|r| r.wait(delay))
+                .inspect_err(|e| error!("Failed to send heartbeat: {:?}", e))
+                .ok();
+            note.sync_and_wait(delay, sfy::note::SHUTDOWN_SYNC_TIMEOUT)
+                .inspect_err(|e| error!("Failed to sync heartbeat: {:?}", e))
+                .ok();
+        }
+
+        match note.read_command(delay) {
+            Ok(Some((Command::ExitSafeMode, _))) => {
+                safe_mode.exit();
+
+                let ok = storage
+                    .write_safe_mode(safe_mode)
+                    .inspect_err(|e| error!("Failed to write safe mode: {:?}", e))
+                    .is_ok();
+                note.ack_command(delay, Command::ExitSafeMode, ok, None)
+                    .inspect_err(|e| error!("Failed to acknowledge command: {:?}", e))
+                    .ok();
+
+                if ok {
+                    warn!("Leaving safe mode.");
+                    log("Leaving safe mode, resetting.");
+                    reset(note, delay);
+                }
+            }
+            Ok(Some((cmd, _))) => {
+                warn!("Command {:?} ignored in safe mode.", cmd);
+                note.ack_command(delay, cmd, false, None)
+                    .inspect_err(|e| error!("Failed to acknowledge command: {:?}", e))
+                    .ok();
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to read command: {:?}", e),
+        }
+
+        for _ in 0..60 {
+            delay.delay_ms(1_000u16);
+        }
+        minutes = minutes.wrapping_add(1);
+
+        // The loop came around, restart the watchdog.
+        wdt.rstrt
+            .write(|w| unsafe { w.bits(sfy::watchdog::RSTRT_KEY) });
     }
 }
 
-fn reset<I: Read + Write>(note: &mut Notecarrier<I>, delay: &mut impl DelayMs<u16>) -> ! {
+fn reset(note: &mut Note, delay: &mut impl DelayMs<u16>) -> ! {
     cortex_m::interrupt::disable();
 
     warn!("Resetting device!");
@@ -410,6 +1461,38 @@ fn reset<I: Read + Write>(note: &mut Notecarrier<I>, delay: &mut impl DelayMs<u1
     cortex_m::peripheral::SCB::sys_reset()
 }
 
+/// Stay in standby at the end of the deployment (see `sfy::deployment`) until the device is
+/// reset. The IMU is powered down and the `RTC` interrupt masked, so the MCU sleeps. The watchdog
+/// is stopped, it would otherwise reset the board out of standby.
+fn standby(wdt: &hal::pac::WDT) -> ! {
+    warn!("Deployment ended, entering standby until reset.");
+
+    cortex_m::peripheral::NVIC::mask(hal::pac::Interrupt::RTC);
+    wdt.cfg.write(|w| unsafe { w.bits(0) });
+
+    loop {
+        asm::wfi();
+    }
+}
+
+/// Switch the RTC to the internal LFRC oscillator after a fault of the external crystal.
+fn rtc_fallback() {
+    error!("RTC crystal fault detected, switching RTC to the internal LFRC oscillator.");
+
+    unsafe {
+        (*hal::pac::CLKGEN::ptr())
+            .octrl
+            .modify(|_, w| w.osel().set_bit());
+    }
+
+    sfy::clock::set_time_degraded();
+    sfy::log::log_at(
+        sfy::log::Category::Location,
+        sfy::log::Level::Error,
+        "RTC crystal fault: switched RTC to internal LFRC oscillator, reduced time accuracy.",
+    );
+}
+
 #[cfg(not(feature = "host-tests"))]
 #[allow(non_snake_case)]
 #[interrupt]
@@ -429,31 +1512,98 @@ fn RTC() {
     }
 
     if let Some(imu) = imu {
-        let (now, position_time, lon, lat) = free(|cs| {
-            let state = STATE.borrow(cs).borrow();
-            let state = state.as_ref().unwrap();
+        // The state may be borrowed by the main loop if the interrupt fired while it held the
+        // borrow, skip this round rather than panic. The IMU FIFO is emptied on the next one.
+        let Some((now, position_time, lon, lat)) = STATE.with_state(|state| {
+            let now = state.now_millis();
+
+            (now, state.position_time, state.lon, state.lat)
+        }) else {
+            error!("Shared state not available, skipping IMU read-out.");
+            return;
+        };
+        let now = imu.correct_time(now);
 
-            let now = state.rtc.now().timestamp_millis();
-            let position_time = state.position_time;
-            let lon = state.lon;
-            let lat = state.lat;
+        COUNT.store((now / 1000).try_into().unwrap_or(0), Ordering::Relaxed);
 
-            (now, position_time, lon, lat)
-        });
+        if IMU_REINIT.swap(false, Ordering::Acquire) {
+            warn!("IMU re-initialization requested, resetting IMU..");
+            let mut delay = hal::delay::FlashDelay;
+            let r = imu.reset(now, position_time, lon, lat, &mut delay);
+            log("IMU re-initialized by command.");
+            warn!("IMU reset: {:?}", r);
+        }
 
-        COUNT.store((now / 1000).try_into().unwrap_or(0), Ordering::Relaxed);
+        if IMU_CALIBRATE.swap(false, Ordering::Acquire) {
+            warn!("Calibration capture requested..");
+            if let Err(e) = imu.calibrate(now, position_time, lon, lat) {
+                error!("Failed to start calibration capture: {:?}", e);
+            }
+        }
+
+        if IMU_STOP.load(Ordering::Acquire) {
+            warn!("Stopping IMU at the end of the deployment..");
+            if let Err(e) = imu.sleep(now, position_time, lon, lat) {
+                error!("Failed to stop IMU: {:?}", e);
+            }
+
+            cortex_m::peripheral::NVIC::mask(hal::pac::Interrupt::RTC);
+            IMU_STOP.store(false, Ordering::Release);
+            return;
+        }
+
+        if IMU_FLUSH.load(Ordering::Acquire) {
+            debug!("Flushing partial IMU buffer..");
+            if let Err(e) = imu.flush_partial(now, position_time, lon, lat) {
+                error!("Failed to flush partial buffer: {:?}", e);
+            }
+            IMU_FLUSH.store(false, Ordering::Release);
+        }
+
+        if imu.warming_up() {
+            let mut delay = hal::delay::FlashDelay;
+            if let Err(e) = imu.check_warmup(now, position_time, lon, lat, &mut delay) {
+                error!("Failed to end IMU warm-up: {:?}", e);
+            }
+        }
 
         // XXX: This is the most time-critical part of the program.
         //
         // It seems that the IMU I2C communication sometimes fails with a NAK, causing a module
         // reset, which again might cause a HardFault.
-        match imu.check_retrieve(now, position_time, lon, lat) {
+        #[cfg(feature = "profiling")]
+        let c0 = DWT::cycle_count();
+
+        let r = imu.check_retrieve(now, position_time, lon, lat);
+
+        #[cfg(feature = "profiling")]
+        sfy::profile::add_imu(DWT::cycle_count().wrapping_sub(c0));
+
+        match r {
             Ok(_) => {
                 *GOOD_TRIES = 5;
             }
             Err(e) => {
                 error!("IMU ISR failed: {:?}, resetting IMU..", e);
 
+                // Repeated failure: keep the registers as found, before the reset overwrites them.
+                if *GOOD_TRIES < 5 {
+                    let mut msg = heapless::String::<256>::new();
+                    let w = match imu.dump_registers() {
+                        Ok(r) => write!(&mut msg, "IMU registers: {}", r),
+                        Err(e) => write!(&mut msg, "IMU registers not readable: {:?}", e),
+                    };
+                    w.inspect_err(|e| {
+                        defmt::error!(
+                            "failed to format IMU registers: {:?}",
+                            defmt::Debug2Format(e)
+                        )
+                    })
+                    .ok();
+                    error!("{}", msg.as_str());
+                    log(&msg);
+                }
+
                 let mut delay = hal::delay::FlashDelay;
 
                 let r = imu.reset(now, position_time, lon, lat, &mut delay);
@@ -484,6 +1634,8 @@ fn RTC() {
 #[allow(non_snake_case)]
 #[exception]
 unsafe fn HardFault(ef: &ExceptionFrame) -> ! {
+    sfy::postmortem::freeze(sfy::postmortem::Fault::HardFault);
+
     error!(
         "hard fault exception: {:#?}. resetting system.",
         defmt::Debug2Format(ef)
@@ -491,21 +1643,38 @@ unsafe fn HardFault(ef: &ExceptionFrame) -> ! {
     cortex_m::peripheral::SCB::sys_reset()
 }
 
-#[cfg(feature = "deploy")]
+/// With `deploy`, and with `assert-note` in debug builds (failed `debug_assert!`s during bench
+/// bring-up and field trials of pre-release firmware), the panic is sent to the Notecard before
+/// the reset. Otherwise `panic_probe` prints it over RTT.
+#[cfg(any(feature = "deploy", all(feature = "assert-note", debug_assertions)))]
 #[inline(never)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    unsafe { sfy::postmortem::freeze(sfy::postmortem::Fault::Panic) };
+
+    // Written to the SD-card at the next boot, the panic may have interrupted the storage.
+    #[cfg(feature = "storage")]
+    unsafe {
+        sfy::panic_log::set(
+            &sfy::log::panic_message(info),
+            COUNT.load(Ordering::Relaxed),
+            STATE.try_get(),
+        )
+    };
+
     defmt::error!("panic: {}", defmt::Debug2Format(info));
     log("panic reset.");
-    let mut msg = heapless::String::<256>::new();
-    write!(&mut msg, "panic: {}", info)
-        .inspect_err(|e| defmt::error!("failed to format panic: {:?}", defmt::Debug2Format(e)))
-        .ok();
-    log(&msg);
+    sfy::log::log_panic(info);
 
     let mut delay = hal::delay::FlashDelay;
 
-    free(|_| unsafe { sfy::log::panic_drain_log(log::NOTE, &mut delay) });
+    // The panic may have interrupted a transaction with the Notecard, which is reset before the
+    // log is sent.
+    NOTE_BUS.store(false, Ordering::Release);
+    #[cfg(feature = "redundant-notecard")]
+    NOTE_BUS_SECONDARY.store(false, Ordering::Release);
+
+    free(|_| unsafe { sfy::log::panic_drain_log(log::carrier(), &mut delay) });
 
     defmt::error!("panic logged, resetting..");
     cortex_m::peripheral::SCB::sys_reset();
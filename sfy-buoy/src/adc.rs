@@ -0,0 +1,152 @@
+//! Auxiliary analog channels (e.g. battery current, solar voltage or a water-ingress sensor).
+//!
+//! With the `adc` feature of the firmware the channels in `adc` of the config are read on the ADC
+//! of the Apollo3 at every health report, and the readings are attached to the report
+//! (`Health::adc`, in the note and in `HEALTH.LOG`) in the order of the config. A channel is a pad
+//! with a single-ended input of the ADC (see [`PADS`]). The voltage at the pad is scaled and
+//! offset by the config of the channel to the reading, e.g. the scale undoes a voltage divider in
+//! front of the pad. The ADC is only powered while the channels are read.
+
+use embedded_hal::blocking::delay::DelayMs;
+use heapless::Vec;
+
+/// Maximum number of channels read.
+pub const MAX_CHANNELS: usize = 4;
+
+/// Pads of the single-ended inputs of the ADC, the index is the channel (`SE0` to `SE9`).
+pub const PADS: [u8; 10] = [16, 29, 11, 31, 32, 33, 34, 35, 13, 12];
+
+/// Reference voltage of the ADC, the voltage at a pad is at most this [V].
+pub const REFERENCE: f32 = 2.0;
+
+/// An auxiliary analog channel.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Channel {
+    /// Pad of the input (see [`PADS`]).
+    pub pad: u8,
+
+    /// Factor from the voltage at the pad to the reading, e.g. `11` for a 100k/10k divider.
+    #[serde(default = "one")]
+    pub scale: f32,
+
+    /// Added to the reading after the scaling, e.g. the zero of a current sense amplifier.
+    #[serde(default)]
+    pub offset: f32,
+}
+
+fn one() -> f32 {
+    1.
+}
+
+impl Channel {
+    /// Channel of the ADC at the pad, `None` if the pad is not an input of the ADC.
+    pub fn channel(&self) -> Option<u8> {
+        PADS.iter().position(|p| *p == self.pad).map(|c| c as u8)
+    }
+
+    /// Reading of the voltage `v` [V] at the pad.
+    pub fn reading(&self, v: f32) -> f32 {
+        v * self.scale + self.offset
+    }
+}
+
+/// Channels are on inputs of the ADC, each pad at most once, and the scaling is finite.
+pub fn is_valid(channels: &[Channel]) -> bool {
+    channels.iter().enumerate().all(|(i, c)| {
+        c.channel().is_some()
+            && c.scale.is_finite()
+            && c.offset.is_finite()
+            && !channels[..i].iter().any(|o| o.pad == c.pad)
+    })
+}
+
+/// Single conversions on the ADC.
+pub trait Sampler {
+    type Error: core::fmt::Debug;
+
+    /// Voltage at the pad of the single-ended `channel` [V].
+    fn sample(&mut self, channel: u8, delay: &mut impl DelayMs<u16>) -> Result<f32, Self::Error>;
+}
+
+/// Read the `channels`, in order.
+pub fn read<S: Sampler>(
+    adc: &mut S,
+    channels: &[Channel],
+    delay: &mut impl DelayMs<u16>,
+) -> Result<Vec<f32, MAX_CHANNELS>, S::Error> {
+    let mut readings = Vec::new();
+
+    // The channels are checked by the config.
+    for c in channels.iter().take(MAX_CHANNELS) {
+        if let Some(channel) = c.channel() {
+            let v = adc.sample(channel, delay)?;
+            defmt::debug!("adc: pad {} (channel {}): {} V", c.pad, channel, v);
+            readings.push(c.reading(v)).ok();
+        }
+    }
+
+    Ok(readings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Mock;
+
+    impl Sampler for Mock {
+        type Error = ();
+
+        fn sample(&mut self, channel: u8, _delay: &mut impl DelayMs<u16>) -> Result<f32, ()> {
+            match channel {
+                9 => Err(()),
+                c => Ok(c as f32 / 10.),
+            }
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayMs<u16> for NoDelay {
+        fn delay_ms(&mut self, _ms: u16) {}
+    }
+
+    fn channel(pad: u8, scale: f32, offset: f32) -> Channel {
+        Channel { pad, scale, offset }
+    }
+
+    #[test]
+    fn channels() {
+        assert_eq!(channel(16, 1., 0.).channel(), Some(0));
+        assert_eq!(channel(12, 1., 0.).channel(), Some(9));
+        assert_eq!(channel(6, 1., 0.).channel(), None);
+
+        assert!(is_valid(&[]));
+        assert!(is_valid(&[channel(16, 11., 0.), channel(29, 1., -0.5)]));
+        assert!(!is_valid(&[channel(16, 11., 0.), channel(16, 1., 0.)]));
+        assert!(!is_valid(&[channel(6, 1., 0.)]));
+        assert!(!is_valid(&[channel(16, f32::NAN, 0.)]));
+    }
+
+    #[test]
+    fn readings() {
+        let channels = [channel(11, 11., 0.), channel(32, 2., -0.5)];
+        let r = read(&mut Mock, &channels, &mut NoDelay).unwrap();
+        assert_eq!(r.len(), 2);
+        assert!((r[0] - 2.2).abs() < 1e-6);
+        assert!((r[1] - 0.3).abs() < 1e-6);
+
+        assert!(read(&mut Mock, &[channel(12, 1., 0.)], &mut NoDelay).is_err());
+    }
+
+    #[test]
+    fn config() {
+        let c: Channel = serde_json_core::from_str(r#"{ "pad": 29, "scale": 3.0 }"#)
+            .unwrap()
+            .0;
+        assert_eq!(c, channel(29, 3., 0.));
+
+        let c: Channel = serde_json_core::from_str(r#"{ "pad": 29 }"#).unwrap().0;
+        assert_eq!(c.scale, 1.);
+    }
+}
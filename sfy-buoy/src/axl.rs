@@ -1,5 +1,10 @@
 use defmt::{write, Format, Formatter};
 use heapless::Vec;
+use static_assertions as sa;
+
+use crate::waves::wire::{self, ACCEL_MAX};
+use crate::waves::Frame;
+use crate::weighting::Weighting;
 
 #[cfg(feature = "raw")]
 pub const SAMPLE_NO: usize = 1024;
@@ -9,20 +14,219 @@ pub const SAMPLE_NO: usize = 1024;
 
 pub const SAMPLE_SZ: usize = 3;
 pub const AXL_SZ: usize = SAMPLE_SZ * SAMPLE_NO;
-pub const VERSION: u32 = 5;
+pub const VERSION: u32 = 22;
+
+/// Capacity of the gyroscope samples of a package (see [`AxlPacket::gyro`]): a package worth of
+/// samples with the `gyro` feature, and none without. `sfypack` (`std`) reads both.
+#[cfg(any(feature = "gyro", feature = "std"))]
+pub const GYRO_SZ: usize = AXL_SZ;
+
+#[cfg(not(any(feature = "gyro", feature = "std")))]
+pub const GYRO_SZ: usize = 0;
+
+/// Maximum number of interpolated samples listed in a package (see [`AxlPacket::filled`]).
+pub const MAX_FILLED: usize = 16;
+
+/// Maximum length of the comma-separated list of interpolated samples in the body of a note: the
+/// sample indices have at most 4 digits.
+pub const FILLED_STR_SZ: usize = MAX_FILLED * 5;
+
+sa::const_assert!(SAMPLE_NO <= 10_000);
+
+/// Mask of the axes in a package (bit 0: x, bit 1: y, bit 2: z), see [`AxlPacket::axes`].
+pub const AXES_ALL: u8 = 0b111;
+
+/// Number of values per sample with the axes in `axes`.
+pub const fn axes_width(axes: u8) -> usize {
+    (axes & AXES_ALL).count_ones() as usize
+}
+
+/// Format version tag, the first byte of every (COBS-decoded) serialized package. Packages of
+/// version 5 and older are not tagged, their version is given by the extension of the collection
+/// file.
+pub const FORMAT_VERSION: u8 = VERSION as u8;
+
+/// Last version without the format version tag.
+pub const LAST_UNTAGGED_VERSION: u32 = 5;
+
+/// Tag of an encrypted package, in place of the format version tag (see `crypt`).
+pub const SEALED: u8 = 0x80;
+
+/// Flag of the format version tag of a package with the samples delta encoded (see
+/// [`SampleEncoding::Delta`]).
+pub const DELTA: u8 = 0x40;
+
+/// Encoding of the samples of a package on the SD-card and in the payload of the data notes,
+/// selected by `sample_encoding` in the config.
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleEncoding {
+    /// The samples as they are: varints on the SD-card (up to three bytes), two bytes each in the
+    /// payload of the notes.
+    #[default]
+    Raw,
+
+    /// The difference from the previous sample of the same axis (wrapping) as zigzag varints,
+    /// mostly one or two bytes for the slowly varying acceleration. A package is kept raw when
+    /// the deltas are not smaller (e.g. noise), so it never grows.
+    Delta,
+}
+
+impl SampleEncoding {
+    /// Code of the encoding in the body of the data notes.
+    pub fn code(&self) -> u8 {
+        match self {
+            SampleEncoding::Raw => 0,
+            SampleEncoding::Delta => 1,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<SampleEncoding> {
+        match code {
+            0 => Some(SampleEncoding::Raw),
+            1 => Some(SampleEncoding::Delta),
+            _ => None,
+        }
+    }
+}
+
+/// Replace the samples with the difference from the previous sample of the same axis.
+pub fn delta_encode(data: &mut [u16], width: usize) {
+    let mut prev = [0u16; SAMPLE_SZ];
+
+    for (i, v) in data.iter_mut().enumerate() {
+        let x = *v;
+        *v = x.wrapping_sub(prev[i % width]);
+        prev[i % width] = x;
+    }
+}
+
+/// The inverse of [`delta_encode`].
+pub fn delta_decode(data: &mut [u16], width: usize) {
+    let mut prev = [0u16; SAMPLE_SZ];
+
+    for (i, v) in data.iter_mut().enumerate() {
+        *v = v.wrapping_add(prev[i % width]);
+        prev[i % width] = *v;
+    }
+}
+
+/// Zigzag encoding of a delta, as postcard writes an `i16`.
+fn zigzag(d: u16) -> u16 {
+    let d = d as i16;
+    ((d << 1) ^ (d >> 15)) as u16
+}
+
+/// The inverse of [`zigzag`].
+fn unzigzag(z: u16) -> u16 {
+    (z >> 1) ^ (z & 1).wrapping_neg()
+}
+
+/// Size of `v` as a varint.
+fn varint_sz(v: u16) -> usize {
+    match v {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        _ => 3,
+    }
+}
+
+/// Write the deltas of `data` as zigzag varints into `buf`. `None` when they do not fit, the
+/// length of the varints otherwise.
+fn write_varint_deltas(data: &[u16], width: usize, buf: &mut [u8]) -> Option<usize> {
+    let mut prev = [0u16; SAMPLE_SZ];
+    let mut n = 0;
+
+    for (i, x) in data.iter().enumerate() {
+        let mut z = zigzag(x.wrapping_sub(prev[i % width]));
+        prev[i % width] = *x;
+
+        while z >= 0x80 {
+            *buf.get_mut(n)? = (z as u8) | 0x80;
+            z >>= 7;
+            n += 1;
+        }
+        *buf.get_mut(n)? = z as u8;
+        n += 1;
+    }
+
+    Some(n)
+}
+
+/// The inverse of [`write_varint_deltas`]. `None` when a varint is cut short or too long, or
+/// there are more than `AXL_SZ` samples.
+fn read_varint_deltas(buf: &[u8], width: usize) -> Option<Vec<u16, AXL_SZ>> {
+    let mut data = Vec::new();
+    let mut z = 0u32;
+    let mut shift = 0;
+
+    for b in buf {
+        z |= ((b & 0x7f) as u32) << shift;
+
+        if b & 0x80 == 0 {
+            data.push(unzigzag(u16::try_from(z).ok()?)).ok()?;
+            z = 0;
+            shift = 0;
+        } else {
+            shift += 7;
+            if shift > 14 {
+                return None;
+            }
+        }
+    }
+
+    if shift != 0 {
+        return None;
+    }
+
+    delta_decode(&mut data, width);
+    Some(data)
+}
 
 /// Maximum length of base64 string from [f16; AXL_SZ]
 pub const AXL_OUTN: usize = { AXL_SZ * 2 } * 4 / 3 + 4;
 
-/// Max size of `AxlPacket` serialized using postcard with COBS. Set with some margin since
-/// postcard messages are not fixed size.
-#[cfg(feature = "raw")]
-pub const AXL_POSTCARD_SZ: usize = 1024 * 10;
+/// Size of the slot of a package on the SD-card without gyroscope samples. The collections on the
+/// card are split into packages by the slot, so it is fixed rather than following the size of the
+/// package: the upper bound of the serialized package is checked against it below.
+pub const ACCEL_POSTCARD_SZ: usize = 1024 * 10;
 
-#[cfg(not(feature = "raw"))]
-pub const AXL_POSTCARD_SZ: usize = 1024 * 10;
+/// Size of the slot of a package on the SD-card with gyroscope samples (the `gyro` feature).
+pub const GYRO_POSTCARD_SZ: usize = 1024 * 20;
+
+/// Max size of `AxlPacket` serialized using postcard with COBS, the slot of a package on the
+/// SD-card. Set with some margin since postcard messages are not fixed size.
+#[cfg(feature = "gyro")]
+pub const AXL_POSTCARD_SZ: usize = GYRO_POSTCARD_SZ;
+
+#[cfg(not(feature = "gyro"))]
+pub const AXL_POSTCARD_SZ: usize = ACCEL_POSTCARD_SZ;
+
+/// Upper bound of the serialized fields of `AxlPacket` other than the samples, including the
+/// format version tag and the lengths of `data` and `gyro`. The fields add up to 188 bytes with
+/// every varint at its longest and `filled` full.
+pub const HEADER_MAX_SZ: usize = 192;
+
+/// Upper bound of an `AxlPacket` without gyroscope samples serialized with postcard, before the
+/// COBS framing: every sample takes at most 3 bytes as a varint.
+pub const POSTCARD_MAX_SZ: usize = HEADER_MAX_SZ + AXL_SZ * 3;
 
-#[derive(serde::Serialize, serde::Deserialize, PartialEq)]
+/// Upper bound of an `AxlPacket` with gyroscope samples serialized with postcard.
+pub const GYRO_POSTCARD_MAX_SZ: usize = POSTCARD_MAX_SZ + AXL_SZ * 3;
+
+/// Upper bound of `n` bytes with COBS framing, including the terminating zero.
+pub const fn cobs_max_sz(n: usize) -> usize {
+    n + n / 254 + 2
+}
+
+// A full package, also when sealed, always fits in the serialization buffer and in its slot on
+// the SD-card.
+sa::const_assert!(cobs_max_sz(POSTCARD_MAX_SZ + crate::crypt::OVERHEAD) <= ACCEL_POSTCARD_SZ);
+sa::const_assert!(cobs_max_sz(GYRO_POSTCARD_MAX_SZ + crate::crypt::OVERHEAD) <= GYRO_POSTCARD_SZ);
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq)]
 pub struct AxlPacket {
     /// Timestamp of sample at `offset` in ms.
     pub timestamp: i64,
@@ -44,154 +248,3495 @@ pub struct AxlPacket {
     /// Frequency of data.
     pub freq: f32,
 
+    /// Step of the calibration procedure (`1..=calibration::STEPS`) the package was captured in,
+    /// `0` for normal packages. Calibration packages hold unfiltered acceleration in the body
+    /// frame at the IMU rate, see `calibration`.
+    pub calibration: u8,
+
+    /// Quality flags, `0` when no problems were detected (see `quality`).
+    pub quality: u8,
+
+    /// Offset removal applied to the samples (`waves::BiasRemoval::code`), `0` for none.
+    pub bias_mode: u8,
+
+    /// Offset (x, y, z) subtracted from the samples in m/s^2, see `waves::bias`.
+    pub bias: [f32; 3],
+
+    /// Scale factor (x, y, z) the samples were divided by after the offset was subtracted, `1`
+    /// unless calibrated by the temperature table (see `waves::thermal`).
+    pub scale: [f32; 3],
+
+    /// Dilution of precision of the fix at `position_time`, `0` if unknown.
+    pub dop: f32,
+
+    /// Full scale of the encoding of the acceleration in `data` [m/s^2]: the samples are 16 bit
+    /// counts from `-accel_max` to `accel_max` (see `waves::wire` and [`AxlPacket::accel`]).
+    pub accel_max: f32,
+
+    /// Frame of reference of the samples (`waves::Frame::code`), see `waves::frame`.
+    pub frame: u8,
+
+    /// Axes in `data` ([`AXES_ALL`] for all), see `Config::axes`. Only the enabled axes are
+    /// stored, interleaved in the order x, y, z.
+    pub axes: u8,
+
+    /// Warm-up after power-on before the samples were kept [s], `0` without (see
+    /// `Config::warmup`).
+    pub warmup: u16,
+
+    /// Indices of the samples in `data` that were filled in by interpolation over a short gap in
+    /// the IMU FIFO (see `waves::gap`), at most [`MAX_FILLED`]. The gap is interpolated at the IMU
+    /// rate, the FIR filter spreads it over the neighbouring samples as well.
+    pub filled: Vec<u16, MAX_FILLED>,
+
+    /// Time of the sample at `offset` as read from the RTC [ms]. The same as `timestamp`, unless
+    /// the timestamp was moved forward to keep the timestamps monotonic (see `clock::Monotonic`).
+    pub rtc_timestamp: i64,
+
+    /// Samples from the IMU (at the IMU rate, including the samples interpolated over gaps) that
+    /// went into the package, and the samples that came out of the decimation (including the
+    /// samples discarded in the start-up transient of the filters). Their ratio is the decimation
+    /// of the package, see [`AxlPacket::check_decimation`]. `0` when not recorded (see
+    /// `Config::sample_counts`).
+    pub raw_samples: u32,
+    pub output_samples: u32,
+
+    /// CRC-32 of the samples in `data` when the package was taken from the IMU buffer, see
+    /// [`AxlPacket::verify`]. `0` for packages from before it was recorded, which cannot be
+    /// verified.
+    pub crc: u32,
+
+    /// Battery voltage [V] and temperature of the board [°C] measured by the Notecard, the last
+    /// readings when the package was taken (see `note::Notecarrier::read_card`). `None` when not
+    /// read, e.g. the Notecard gave no voltage before the battery is charged.
+    pub battery_v: Option<f32>,
+    pub temperature_c: Option<f32>,
+
+    /// IMU samples that went into the package with the acceleration at the full scale of the
+    /// accelerometer on any axis, counted on the samples as read from the FIFO before they are
+    /// filtered (see `waves::raw::saturated`). The filter smears a clipped sample over its
+    /// neighbours, so the clipping is not seen in `data`. `0` for packages from before it was
+    /// recorded.
+    pub clipped_samples: u16,
+
     /// IMU data. This is moved to the payload when transmitting.
     pub data: Vec<u16, { AXL_SZ }>,
+
+    /// Angular rate in the body frame (x, y, z for every sample in `data`) with the `gyro`
+    /// feature, filtered and decimated in step with the acceleration: 16 bit counts from
+    /// `-wire::GYRO_MAX` to `wire::GYRO_MAX` [rad/s] (see [`AxlPacket::gyro_rate`]). Empty without
+    /// the feature. Only stored on the SD-card, the gyroscope is not sent.
+    pub gyro: Vec<u16, { GYRO_SZ }>,
 }
 
-fn f32_not_normal(f: &f32) -> bool {
-    !f32::is_subnormal(*f)
+/// Layout of versions 1 to 6, before the calibration step was added.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV6 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    data: Vec<u16, { AXL_SZ }>,
 }
 
-#[derive(serde::Serialize, Default)]
-pub struct AxlPacketMeta {
-    pub timestamp: i64,
-    pub offset: u32,
+impl From<AxlPacketV6> for AxlPacketV7 {
+    fn from(p: AxlPacketV6) -> AxlPacketV7 {
+        AxlPacketV7 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: 0,
+            data: p.data,
+        }
+    }
+}
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub storage_id: Option<u32>,
-    pub storage_version: u32,
+/// Layout of version 7, before the quality flags were added.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV7 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    data: Vec<u16, { AXL_SZ }>,
+}
 
-    pub position_time: u32,
-    pub lon: f64,
-    pub lat: f64,
+impl From<AxlPacketV7> for AxlPacketV8 {
+    fn from(p: AxlPacketV7) -> AxlPacketV8 {
+        AxlPacketV8 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: 0,
+            data: p.data,
+        }
+    }
+}
 
-    #[serde(skip_serializing_if = "f32_not_normal")]
-    pub temperature: f32,
+/// Layout of version 8, before the offset removal was recorded.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV8 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    data: Vec<u16, { AXL_SZ }>,
+}
 
-    pub freq: f32,
-    pub length: u32,
+impl From<AxlPacketV8> for AxlPacketV9 {
+    fn from(p: AxlPacketV8) -> AxlPacketV9 {
+        AxlPacketV9 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: 0,
+            bias: [0.; 3],
+            data: p.data,
+        }
+    }
 }
 
-impl core::fmt::Debug for AxlPacket {
-    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        core::write!(fmt, "AxlPacket(timestamp: {}, offset: {}, storage_id: {:?} (v: {:?}), position_time: {}, lon: {}, lat: {}, temp: {}, freq: {}, data (length): {}))",
-            self.timestamp,
-            self.offset,
-            self.storage_id,
-            self.storage_version,
-            self.position_time,
-            self.lon,
-            self.lat,
-            self.temperature,
-            self.freq,
-            self.data.len()
-            )
+/// Layout of version 9, before the dilution of precision of the fix was recorded.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV9 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    data: Vec<u16, { AXL_SZ }>,
+}
+
+impl From<AxlPacketV9> for AxlPacketV10 {
+    fn from(p: AxlPacketV9) -> AxlPacketV10 {
+        AxlPacketV10 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            dop: 0.,
+            data: p.data,
+        }
     }
 }
 
-impl Format for AxlPacket {
-    fn format(&self, fmt: Formatter) {
-        write!(fmt, "AxlPacket(timestamp: {}, offset: {}, storage_id: {:?}, position_time: {}, lon: {}, lat: {}, temp: {}, freq: {}, data (length): {}))",
-            self.timestamp,
-            self.offset,
-            self.storage_id,
-            self.position_time,
-            self.lon,
-            self.lat,
-            self.temperature,
-            self.freq,
-            self.data.len()
-            );
+/// Layout of version 10, before the scale of the acceleration was recorded (always
+/// `wire::ACCEL_MAX`).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV10 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    dop: f32,
+    data: Vec<u16, { AXL_SZ }>,
+}
+
+impl From<AxlPacketV10> for AxlPacketV11 {
+    fn from(p: AxlPacketV10) -> AxlPacketV11 {
+        AxlPacketV11 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            dop: p.dop,
+            accel_max: ACCEL_MAX,
+            data: p.data,
+        }
     }
 }
 
-impl AxlPacket {
-    pub fn base64(&self) -> Vec<u8, AXL_OUTN> {
-        let mut b64: Vec<_, AXL_OUTN> = Vec::new();
-        b64.resize_default(AXL_OUTN).unwrap();
+/// Layout of version 11, before the frame of reference was recorded (see `waves::frame`).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV11 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    data: Vec<u16, { AXL_SZ }>,
+}
 
-        // Check endianness (TODO:  swap order if compiled for big endian machine).
-        #[cfg(target_endian = "big")]
-        compile_error!("serializied samples are assumed to be in little endian, target platform is big endian and no conversion is implemented.");
+impl From<AxlPacketV11> for AxlPacketV12 {
+    fn from(p: AxlPacketV11) -> AxlPacketV12 {
+        AxlPacketV12 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: Frame::of(p.calibration, false).code(),
+            data: p.data,
+        }
+    }
+}
 
-        let data = bytemuck::cast_slice(&self.data);
-        let written = base64::encode_config_slice(data, base64::STANDARD, &mut b64);
-        b64.truncate(written);
+/// Layout of version 12, before axes could be left out (see `Config::axes`).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV12 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    data: Vec<u16, { AXL_SZ }>,
+}
 
-        b64
+impl From<AxlPacketV12> for AxlPacketV13 {
+    fn from(p: AxlPacketV12) -> AxlPacketV13 {
+        AxlPacketV13 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: AXES_ALL,
+            data: p.data,
+        }
     }
+}
 
-    /// Split package into metadata and payload.
-    pub fn split(&self) -> (AxlPacketMeta, Vec<u8, AXL_OUTN>) {
-        let b64 = self.base64();
+/// Layout of version 13, before the warm-up was recorded (see `Config::warmup`).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV13 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    data: Vec<u16, { AXL_SZ }>,
+}
 
-        let meta = AxlPacketMeta {
-            timestamp: self.timestamp,
-            offset: self.offset as u32,
-            length: b64.len() as u32,
-            freq: self.freq,
-            storage_id: self.storage_id,
-            storage_version: self.storage_version,
-            position_time: self.position_time,
-            lon: self.lon,
-            lat: self.lat,
-            temperature: self.temperature,
-        };
+impl From<AxlPacketV13> for AxlPacketV14 {
+    fn from(p: AxlPacketV13) -> AxlPacketV14 {
+        AxlPacketV14 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: 0,
+            data: p.data,
+        }
+    }
+}
 
-        (meta, b64)
+/// Layout of version 14, before the interpolated samples were recorded (see `waves::gap`).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV14 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    warmup: u16,
+    data: Vec<u16, { AXL_SZ }>,
+}
+
+impl From<AxlPacketV14> for AxlPacketV15 {
+    fn from(p: AxlPacketV14) -> AxlPacketV15 {
+        AxlPacketV15 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: Vec::new(),
+            data: p.data,
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Layout of version 15, before the scale was recorded (see `waves::thermal`).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV15 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    warmup: u16,
+    filled: Vec<u16, MAX_FILLED>,
+    data: Vec<u16, { AXL_SZ }>,
+}
 
-    #[test]
-    fn base64_data_package() {
-        let p = AxlPacket {
-            timestamp: 0,
-            position_time: 0,
-            lat: 0.0,
-            lon: 0.0,
-            freq: 100.0,
-            offset: 0,
-            storage_id: Some(0),
-            storage_version: VERSION,
-            temperature: 0.0,
-            data: (0..AXL_SZ)
-                .map(|v| v as u16)
-                .collect::<Vec<_, { AXL_SZ }>>(),
-        };
+impl From<AxlPacketV15> for AxlPacketV16 {
+    fn from(p: AxlPacketV15) -> AxlPacketV16 {
+        AxlPacketV16 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: [1.; 3],
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled,
+            data: p.data,
+        }
+    }
+}
 
-        let b64 = p.base64();
-        println!("{}", core::str::from_utf8(&b64).unwrap());
+/// Layout of version 16, before the time of the RTC was recorded (see `clock::Monotonic`).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV16 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    scale: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    warmup: u16,
+    filled: Vec<u16, MAX_FILLED>,
+    data: Vec<u16, { AXL_SZ }>,
+}
+
+impl From<AxlPacketV16> for AxlPacketV17 {
+    fn from(p: AxlPacketV16) -> AxlPacketV17 {
+        AxlPacketV17 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled,
+            rtc_timestamp: p.timestamp,
+            data: p.data,
+        }
     }
+}
 
-    #[test]
-    fn postcard_size() {
-        let p = AxlPacket {
-            timestamp: 100212312312330,
-            position_time: 123123,
-            lat: 34.52341,
-            lon: 54.012,
-            freq: 53.0,
-            offset: 0,
-            storage_id: Some(1489),
-            storage_version: VERSION,
-            temperature: 0.0,
-            data: (0..AXL_SZ)
-                .map(|v| v as u16)
-                .collect::<Vec<_, { AXL_SZ }>>(),
-        };
+/// Layout of version 17, before the sample counts were recorded.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV17 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    scale: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    warmup: u16,
+    filled: Vec<u16, MAX_FILLED>,
+    rtc_timestamp: i64,
+    data: Vec<u16, { AXL_SZ }>,
+}
 
-        assert!(p.data.is_full());
+impl From<AxlPacketV17> for AxlPacketV18 {
+    fn from(p: AxlPacketV17) -> AxlPacketV18 {
+        AxlPacketV18 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled,
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: 0,
+            output_samples: 0,
+            data: p.data,
+        }
+    }
+}
 
-        let v: Vec<_, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&p).unwrap();
-        println!("{}", v.len());
+/// Layout of version 18, before the CRC of the samples was recorded.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV18 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    scale: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    warmup: u16,
+    filled: Vec<u16, MAX_FILLED>,
+    rtc_timestamp: i64,
+    raw_samples: u32,
+    output_samples: u32,
+    data: Vec<u16, { AXL_SZ }>,
+}
 
-        assert!(v.len() < AXL_POSTCARD_SZ);
+impl From<AxlPacketV18> for AxlPacketV19 {
+    fn from(p: AxlPacketV18) -> AxlPacketV19 {
+        AxlPacketV19 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled,
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: p.raw_samples,
+            output_samples: p.output_samples,
+            crc: 0,
+            data: p.data,
+        }
+    }
+}
+
+/// Layout of version 19, before the gyroscope samples were added.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV19 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    scale: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    warmup: u16,
+    filled: Vec<u16, MAX_FILLED>,
+    rtc_timestamp: i64,
+    raw_samples: u32,
+    output_samples: u32,
+    crc: u32,
+    data: Vec<u16, { AXL_SZ }>,
+}
+
+impl From<AxlPacketV19> for AxlPacketV20 {
+    fn from(p: AxlPacketV19) -> AxlPacketV20 {
+        AxlPacketV20 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled,
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: p.raw_samples,
+            output_samples: p.output_samples,
+            crc: p.crc,
+            data: p.data,
+            gyro: Vec::new(),
+        }
+    }
+}
+
+/// Layout of version 20, before the battery voltage and the temperature of the board were
+/// recorded.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV20 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    scale: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    warmup: u16,
+    filled: Vec<u16, MAX_FILLED>,
+    rtc_timestamp: i64,
+    raw_samples: u32,
+    output_samples: u32,
+    crc: u32,
+    data: Vec<u16, { AXL_SZ }>,
+    gyro: Vec<u16, { GYRO_SZ }>,
+}
+
+impl From<AxlPacketV20> for AxlPacketV21 {
+    fn from(p: AxlPacketV20) -> AxlPacketV21 {
+        AxlPacketV21 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled,
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: p.raw_samples,
+            output_samples: p.output_samples,
+            crc: p.crc,
+            battery_v: None,
+            temperature_c: None,
+            data: p.data,
+            gyro: p.gyro,
+        }
+    }
+}
+
+/// Layout of version 21, before the clipped samples were counted.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AxlPacketV21 {
+    timestamp: i64,
+    offset: u16,
+    storage_id: Option<u32>,
+    storage_version: u32,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    temperature: f32,
+    freq: f32,
+    calibration: u8,
+    quality: u8,
+    bias_mode: u8,
+    bias: [f32; 3],
+    scale: [f32; 3],
+    dop: f32,
+    accel_max: f32,
+    frame: u8,
+    axes: u8,
+    warmup: u16,
+    filled: Vec<u16, MAX_FILLED>,
+    rtc_timestamp: i64,
+    raw_samples: u32,
+    output_samples: u32,
+    crc: u32,
+    battery_v: Option<f32>,
+    temperature_c: Option<f32>,
+    data: Vec<u16, { AXL_SZ }>,
+    gyro: Vec<u16, { GYRO_SZ }>,
+}
+
+impl From<AxlPacketV21> for AxlPacket {
+    fn from(p: AxlPacketV21) -> AxlPacket {
+        AxlPacket {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled,
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: p.raw_samples,
+            output_samples: p.output_samples,
+            crc: p.crc,
+            battery_v: p.battery_v,
+            temperature_c: p.temperature_c,
+            clipped_samples: 0,
+            data: p.data,
+            gyro: p.gyro,
+        }
+    }
+}
+
+/// A package deserialized with the layout of its format version, upgraded one version at a time
+/// to the current layout (see [`Versioned::upgrade`]).
+enum Versioned {
+    V6(AxlPacketV6),
+    V7(AxlPacketV7),
+    V8(AxlPacketV8),
+    V9(AxlPacketV9),
+    V10(AxlPacketV10),
+    V11(AxlPacketV11),
+    V12(AxlPacketV12),
+    V13(AxlPacketV13),
+    V14(AxlPacketV14),
+    V15(AxlPacketV15),
+    V16(AxlPacketV16),
+    V17(AxlPacketV17),
+    V18(AxlPacketV18),
+    V19(AxlPacketV19),
+    V20(AxlPacketV20),
+    V21(AxlPacketV21),
+    Current(AxlPacket),
+}
+
+impl Versioned {
+    /// Deserialize a package with the layout of `version` from the start of `buf`, returning the
+    /// bytes after the package. Versions 1 to 5 have the layout of version 6.
+    fn take(version: u32, buf: &[u8]) -> Result<(Versioned, &[u8]), DecodeError> {
+        use postcard::take_from_bytes as take;
+        use Versioned::*;
+
+        match version {
+            0..=6 => take(buf).map(|(p, b)| (V6(p), b)),
+            7 => take(buf).map(|(p, b)| (V7(p), b)),
+            8 => take(buf).map(|(p, b)| (V8(p), b)),
+            9 => take(buf).map(|(p, b)| (V9(p), b)),
+            10 => take(buf).map(|(p, b)| (V10(p), b)),
+            11 => take(buf).map(|(p, b)| (V11(p), b)),
+            12 => take(buf).map(|(p, b)| (V12(p), b)),
+            13 => take(buf).map(|(p, b)| (V13(p), b)),
+            14 => take(buf).map(|(p, b)| (V14(p), b)),
+            15 => take(buf).map(|(p, b)| (V15(p), b)),
+            16 => take(buf).map(|(p, b)| (V16(p), b)),
+            17 => take(buf).map(|(p, b)| (V17(p), b)),
+            18 => take(buf).map(|(p, b)| (V18(p), b)),
+            19 => take(buf).map(|(p, b)| (V19(p), b)),
+            20 => take(buf).map(|(p, b)| (V20(p), b)),
+            21 => take(buf).map(|(p, b)| (V21(p), b)),
+            VERSION => take(buf).map(|(p, b)| (Current(p), b)),
+            v => return Err(DecodeError::UnsupportedVersion(v)),
+        }
+        .map_err(DecodeError::Postcard)
+    }
+
+    /// Upgrade the package to the layout of the next version.
+    fn upgrade(self) -> Versioned {
+        use Versioned::*;
+
+        match self {
+            V6(p) => V7(p.into()),
+            V7(p) => V8(p.into()),
+            V8(p) => V9(p.into()),
+            V9(p) => V10(p.into()),
+            V10(p) => V11(p.into()),
+            V11(p) => V12(p.into()),
+            V12(p) => V13(p.into()),
+            V13(p) => V14(p.into()),
+            V14(p) => V15(p.into()),
+            V15(p) => V16(p.into()),
+            V16(p) => V17(p.into()),
+            V17(p) => V18(p.into()),
+            V18(p) => V19(p.into()),
+            V19(p) => V20(p.into()),
+            V20(p) => V21(p.into()),
+            V21(p) => Current(p.into()),
+            Current(p) => Current(p),
+        }
+    }
+
+    /// Upgrade the package to the current layout.
+    fn current(mut self) -> AxlPacket {
+        loop {
+            match self {
+                Versioned::Current(p) => return p,
+                v => self = v.upgrade(),
+            }
+        }
+    }
+}
+
+#[derive(Debug, defmt::Format, PartialEq)]
+pub enum DecodeError {
+    /// Package is of a format version this build does not know, upgrade `sfypack`.
+    UnsupportedVersion(u32),
+    Cobs,
+    Empty,
+    Postcard(postcard::Error),
+
+    /// Payload of note is not valid base64 or does not match the body.
+    Payload,
+
+    /// Package is encrypted, and no key was given.
+    Sealed,
+
+    /// Package could not be decrypted: wrong key or corrupted package.
+    Unseal,
+
+    /// Batch is compressed with an algorithm this build does not know, upgrade `sfypack` (see
+    /// `backfill::Compression`).
+    Compression(u8),
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(v) => core::write!(
+                fmt,
+                "unsupported package format version: {} (supports up to: {}), upgrade sfypack",
+                v,
+                VERSION
+            ),
+            DecodeError::Cobs => core::write!(fmt, "invalid COBS framing"),
+            DecodeError::Empty => core::write!(fmt, "empty package"),
+            DecodeError::Postcard(e) => core::write!(fmt, "failed to deserialize package: {}", e),
+            DecodeError::Payload => core::write!(fmt, "invalid note payload"),
+            DecodeError::Sealed => core::write!(fmt, "package is encrypted, no key given"),
+            DecodeError::Unseal => core::write!(fmt, "failed to decrypt package, wrong key?"),
+            DecodeError::Compression(c) => {
+                core::write!(fmt, "unknown compression of batch: {}, upgrade sfypack", c)
+            }
+        }
+    }
+}
+
+/// A package that [`AxlPacketBuilder::build`] refused to build.
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub enum PacketError {
+    /// Samples without a timestamp.
+    Timestamp,
+
+    /// Frequency of the samples is not positive.
+    Frequency,
+
+    /// Full scale of the encoding of the samples is not positive.
+    AccelMax,
+
+    /// No axes, or axes that do not exist (see [`AXES_ALL`]).
+    Axes(u8),
+
+    /// Length of the data is not a whole number of samples of the axes.
+    Length(usize),
+
+    /// Interpolated sample beyond the samples in the data.
+    Filled(u16),
+
+    /// Length of the gyroscope samples does not match the samples in the data.
+    Gyro(usize),
+}
+
+impl core::fmt::Display for PacketError {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PacketError::Timestamp => core::write!(fmt, "samples without timestamp"),
+            PacketError::Frequency => core::write!(fmt, "invalid frequency"),
+            PacketError::AccelMax => core::write!(fmt, "invalid full scale of samples"),
+            PacketError::Axes(a) => core::write!(fmt, "invalid axes: {:#b}", a),
+            PacketError::Length(n) => {
+                core::write!(fmt, "data length {} is not a whole number of samples", n)
+            }
+            PacketError::Filled(i) => core::write!(fmt, "interpolated sample {} out of range", i),
+            PacketError::Gyro(n) => {
+                core::write!(fmt, "gyroscope length {} does not match the samples", n)
+            }
+        }
+    }
+}
+
+#[derive(Debug, defmt::Format, PartialEq)]
+pub enum SerializationError {
+    /// Package does not fit in the buffer.
+    BufferFull,
+    Postcard,
+}
+
+impl From<postcard::Error> for SerializationError {
+    fn from(e: postcard::Error) -> SerializationError {
+        match e {
+            postcard::Error::SerializeBufferFull => SerializationError::BufferFull,
+            _ => SerializationError::Postcard,
+        }
+    }
+}
+
+impl core::fmt::Display for SerializationError {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SerializationError::BufferFull => core::write!(fmt, "package too large for buffer"),
+            SerializationError::Postcard => core::write!(fmt, "failed to serialize package"),
+        }
+    }
+}
+
+/// Buffer for reading and decoding packages. Implemented for `heapless::Vec` (firmware) and, with
+/// the `std` feature, for `std::vec::Vec` (`sfypack`), so that the same decoding is used by both.
+pub trait PackageBuf: AsRef<[u8]> + AsMut<[u8]> {
+    /// Buffer of `len` zeros. Panics if `len` exceeds the capacity of a fixed size buffer.
+    fn zeroed(len: usize) -> Self;
+}
+
+impl<const N: usize> PackageBuf for Vec<u8, N> {
+    fn zeroed(len: usize) -> Self {
+        let mut v = Vec::new();
+        v.resize(len, 0).expect("package buffer too small");
+        v
+    }
+}
+
+#[cfg(feature = "std")]
+impl PackageBuf for std::vec::Vec<u8> {
+    fn zeroed(len: usize) -> Self {
+        std::vec![0; len]
+    }
+}
+
+fn f32_not_normal(f: &f32) -> bool {
+    !f32::is_subnormal(*f)
+}
+
+fn is_zero(v: &u8) -> bool {
+    *v == 0
+}
+
+fn is_zero_f32(v: &f32) -> bool {
+    *v == 0.
+}
+
+fn is_one_f32(v: &f32) -> bool {
+    *v == 1.
+}
+
+fn is_zero_i64(v: &i64) -> bool {
+    *v == 0
+}
+
+fn is_zero_u16(v: &u16) -> bool {
+    *v == 0
+}
+
+fn is_zero_u32(v: &u32) -> bool {
+    *v == 0
+}
+
+/// CRC-32 (IEEE 802.3, as zlib) of the samples, little-endian. Computed with a nibble table to keep
+/// the table small.
+pub fn crc32(data: &[u16]) -> u32 {
+    const TABLE: [u32; 16] = [
+        0x00000000, 0x1db71064, 0x3b6e20c8, 0x26d930ac, 0x76dc4190, 0x6b6b51f4, 0x4db26158,
+        0x5005713c, 0xedb88320, 0xf00f9344, 0xd6d6a3e8, 0xcb61b38c, 0x9b64c2b0, 0x86d3d2d4,
+        0xa00ae278, 0xbdbdf21c,
+    ];
+
+    let mut crc = !0u32;
+    for b in data.iter().flat_map(|d| d.to_le_bytes()) {
+        crc = TABLE[((crc ^ b as u32) & 0xf) as usize] ^ (crc >> 4);
+        crc = TABLE[((crc ^ (b as u32 >> 4)) & 0xf) as usize] ^ (crc >> 4);
+    }
+    !crc
+}
+
+fn one_f32() -> f32 {
+    1.
+}
+
+/// Comma-separated list of the interpolated samples, for the body of a note.
+fn filled_to_str(filled: &[u16]) -> heapless::String<FILLED_STR_SZ> {
+    use core::fmt::Write;
+
+    let mut s = heapless::String::new();
+    for (i, f) in filled.iter().enumerate() {
+        let sep = if i == 0 { "" } else { "," };
+        // The indices are below `SAMPLE_NO`, so the list always fits.
+        core::write!(s, "{}{}", sep, f).ok();
+    }
+    s
+}
+
+/// Parse the list of interpolated samples from the body of a note, `None` if not valid.
+fn filled_from_str(s: &str) -> Option<Vec<u16, MAX_FILLED>> {
+    if s.is_empty() {
+        return Some(Vec::new());
+    }
+
+    let mut filled = Vec::new();
+    for f in s.split(',') {
+        filled.push(f.trim().parse().ok()?).ok()?;
+    }
+    Some(filled)
+}
+
+/// Notes from before the scale was recorded.
+fn default_accel_max() -> f32 {
+    ACCEL_MAX
+}
+
+fn default_axes() -> u8 {
+    AXES_ALL
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug)]
+pub struct AxlPacketMeta {
+    pub timestamp: i64,
+    pub offset: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_id: Option<u32>,
+    pub storage_version: u32,
+
+    pub position_time: u32,
+    pub lon: f64,
+    pub lat: f64,
+
+    #[serde(skip_serializing_if = "f32_not_normal", default)]
+    pub temperature: f32,
+
+    pub freq: f32,
+    pub length: u32,
+
+    /// Age of position at `timestamp` in seconds. Only for display on notehub, `None` unless
+    /// `note_summary` is set in the config.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub fix_age: Option<u32>,
+
+    /// Rough significant wave height of the package in m (see [`AxlPacket::hs`]). Only for
+    /// display on notehub, `None` unless `note_summary` is set in the config.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub hs: Option<f32>,
+
+    /// The RTC runs on the fallback oscillator, timestamps are less accurate (see `clock`).
+    #[serde(skip_serializing_if = "core::ops::Not::not", default)]
+    pub time_degraded: bool,
+
+    /// Step of the calibration procedure, `0` for normal packages.
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub calibration: u8,
+
+    /// Quality flags, `0` when no problems were detected (see `quality`).
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub quality: u8,
+
+    /// On-chip low-pass filter of the accelerometer: the bandwidth is the IMU rate divided by
+    /// this (see `waves::dlpf`). `0` if unknown.
+    #[serde(default)]
+    pub accel_lpf: u16,
+
+    /// On-chip low-pass filter (LPF1) of the gyroscope: `FTYPE`, or `-1` when off.
+    #[serde(default)]
+    pub gyro_lpf: i8,
+
+    /// Offset removal applied to the samples, `0` for none (see `waves::bias`).
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub bias_mode: u8,
+
+    /// Offset subtracted from the samples in m/s^2.
+    #[serde(skip_serializing_if = "is_zero_f32", default)]
+    pub bias_x: f32,
+
+    #[serde(skip_serializing_if = "is_zero_f32", default)]
+    pub bias_y: f32,
+
+    #[serde(skip_serializing_if = "is_zero_f32", default)]
+    pub bias_z: f32,
+
+    /// Scale factor the samples were divided by, `1` for notes from before it was recorded.
+    #[serde(skip_serializing_if = "is_one_f32", default = "one_f32")]
+    pub scale_x: f32,
+
+    #[serde(skip_serializing_if = "is_one_f32", default = "one_f32")]
+    pub scale_y: f32,
+
+    #[serde(skip_serializing_if = "is_one_f32", default = "one_f32")]
+    pub scale_z: f32,
+
+    /// Dilution of precision of the fix, `0` if unknown.
+    #[serde(skip_serializing_if = "is_zero_f32", default)]
+    pub dop: f32,
+
+    /// Full scale of the encoding of the acceleration in the payload [m/s^2].
+    #[serde(default = "default_accel_max")]
+    pub accel_max: f32,
+
+    /// Frame of reference of the samples (`waves::Frame::code`), `0` for notes from before it was
+    /// recorded.
+    #[serde(default)]
+    pub frame: u8,
+
+    /// Axes in the payload (see `AxlPacket::axes`), all for notes from before axes could be left
+    /// out.
+    #[serde(default = "default_axes")]
+    pub axes: u8,
+
+    /// Warm-up after power-on (see `AxlPacket::warmup`), `0` for notes from before it was
+    /// recorded.
+    #[serde(default)]
+    pub warmup: u16,
+
+    /// Interpolated samples (see `AxlPacket::filled`) as a comma-separated list, since the
+    /// templates of the Notecard do not take arrays. Empty for notes from before they were
+    /// recorded.
+    #[serde(skip_serializing_if = "str::is_empty", default)]
+    pub filled: heapless::String<FILLED_STR_SZ>,
+
+    /// How far the timestamp was moved forward from the time of the RTC [ms] (see
+    /// `AxlPacket::rtc_timestamp`), `0` when it was not and for notes from before it was recorded.
+    #[serde(skip_serializing_if = "is_zero_i64", default)]
+    pub rtc_shift: i64,
+
+    /// Sample counts of the package (see `AxlPacket::raw_samples`), `0` when not recorded and
+    /// for notes from before they were recorded.
+    #[serde(skip_serializing_if = "is_zero_u32", default)]
+    pub raw_samples: u32,
+
+    #[serde(skip_serializing_if = "is_zero_u32", default)]
+    pub output_samples: u32,
+
+    /// CRC-32 of the samples (see `AxlPacket::crc`), `0` for notes from before it was recorded.
+    #[serde(skip_serializing_if = "is_zero_u32", default)]
+    pub crc: u32,
+
+    /// Battery voltage [V] and temperature of the board [°C] (see `AxlPacket::battery_v`), left
+    /// out when not read and for notes from before they were recorded.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub battery_v: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub temperature_c: Option<f32>,
+
+    /// IMU samples at the full scale of the accelerometer (see `AxlPacket::clipped_samples`),
+    /// left out when none and for notes from before they were counted.
+    #[serde(skip_serializing_if = "is_zero_u16", default)]
+    pub clipped_samples: u16,
+
+    /// Sample rate of the IMU [Hz] and the decimation to the output rate (`freq`), see
+    /// `AxlPacket::decimation`. `0` if unknown.
+    #[serde(default)]
+    pub imu_freq: f32,
+
+    #[serde(default)]
+    pub decimation: u8,
+
+    /// Encoding of the samples in the payload ([`SampleEncoding::code`]), `0` (raw) for notes
+    /// from before it was recorded.
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub encoding: u8,
+
+    /// The payload is an encrypted package, and the rest of the body is left out (see `crypt`).
+    #[serde(skip_serializing_if = "core::ops::Not::not", default)]
+    pub sealed: bool,
+}
+
+/// Statistics of a package, sent instead of (or in addition to) the full time series. Can be
+/// correlated with the time series through `timestamp` and `storage_id`. The acceleration is in
+/// the earth frame, with gravity removed from the vertical (see `waves::buf`).
+#[derive(serde::Serialize, Default, Debug, PartialEq)]
+pub struct AxlStats {
+    pub timestamp: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_id: Option<u32>,
+
+    pub position_time: u32,
+    pub lon: f64,
+    pub lat: f64,
+
+    pub freq: f32,
+
+    /// Number of samples.
+    pub samples: u32,
+
+    /// Standard deviation of acceleration in m/s^2, `0` for axes left out of the package. Flagged
+    /// samples are weighted (see `weighting`).
+    pub x_std: f32,
+    pub y_std: f32,
+    pub z_std: f32,
+
+    /// Maximum absolute vertical acceleration in m/s^2, of the samples with weight.
+    pub z_max: f32,
+
+    /// Number of clipped values.
+    pub clipped: u32,
+
+    /// Fraction of samples that are not flagged (clipped or interpolated).
+    pub good: f32,
+
+    /// Too few good samples, the statistics are `0` (see `weighting`).
+    pub rejected: bool,
+
+    /// Quality flags of the package (see `quality`).
+    pub quality: u8,
+
+    /// Decision of the motion gate for the package (see `gate::Decision::code`), `0` when the
+    /// gate is disabled.
+    pub gate: u8,
+}
+
+impl core::fmt::Debug for AxlPacket {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(fmt, "AxlPacket(timestamp: {}, offset: {}, storage_id: {:?} (v: {:?}), position_time: {}, lon: {}, lat: {}, temp: {}, freq: {}, calibration: {}, quality: {:#x}, bias: {} {:?}, scale: {:?}, dop: {}, accel_max: {}, frame: {}, axes: {:#b}, warmup: {}, filled: {:?}, rtc_timestamp: {}, samples: {}/{}, crc: {:#x}, battery: {:?} V, board temp: {:?}, clipped: {}, data (length): {}, gyro (length): {}))",
+            self.timestamp,
+            self.offset,
+            self.storage_id,
+            self.storage_version,
+            self.position_time,
+            self.lon,
+            self.lat,
+            self.temperature,
+            self.freq,
+            self.calibration,
+            self.quality,
+            self.bias_mode,
+            self.bias,
+            self.scale,
+            self.dop,
+            self.accel_max,
+            self.frame,
+            self.axes,
+            self.warmup,
+            self.filled,
+            self.rtc_timestamp,
+            self.raw_samples,
+            self.output_samples,
+            self.crc,
+            self.battery_v,
+            self.temperature_c,
+            self.clipped_samples,
+            self.data.len(),
+            self.gyro.len()
+            )
+    }
+}
+
+impl Format for AxlPacket {
+    fn format(&self, fmt: Formatter) {
+        write!(fmt, "AxlPacket(timestamp: {}, offset: {}, storage_id: {:?}, position_time: {}, lon: {}, lat: {}, temp: {}, freq: {}, calibration: {}, quality: {:#x}, bias: {} {:?}, scale: {:?}, dop: {}, accel_max: {}, frame: {}, axes: {:#b}, warmup: {}, filled: {:?}, rtc_timestamp: {}, samples: {}/{}, crc: {:#x}, battery: {:?} V, board temp: {:?}, clipped: {}, data (length): {}, gyro (length): {}))",
+            self.timestamp,
+            self.offset,
+            self.storage_id,
+            self.position_time,
+            self.lon,
+            self.lat,
+            self.temperature,
+            self.freq,
+            self.calibration,
+            self.quality,
+            self.bias_mode,
+            self.bias,
+            self.scale,
+            self.dop,
+            self.accel_max,
+            self.frame,
+            self.axes,
+            self.warmup,
+            self.filled,
+            self.rtc_timestamp,
+            self.raw_samples,
+            self.output_samples,
+            self.crc,
+            self.battery_v,
+            self.temperature_c,
+            self.clipped_samples,
+            self.data.len(),
+            self.gyro.len()
+            );
+    }
+}
+
+/// Builder of an [`AxlPacket`], the one way packages are made from samples (`Waves::take_buf`)
+/// or from a note. The fields every package needs are the arguments of [`AxlPacketBuilder::new`],
+/// the rest have defaults, and [`AxlPacketBuilder::build`] checks the package before it is made.
+/// Packages decoded from the SD-card are taken as they were stored.
+pub struct AxlPacketBuilder {
+    pck: AxlPacket,
+}
+
+impl AxlPacketBuilder {
+    /// Package of `data` with the time of the sample at `offset` (see [`AxlPacket::timestamp`]),
+    /// the frequency and the full scale of the encoding of the samples.
+    pub fn new(
+        timestamp: i64,
+        freq: f32,
+        accel_max: f32,
+        data: Vec<u16, { AXL_SZ }>,
+    ) -> AxlPacketBuilder {
+        AxlPacketBuilder {
+            pck: AxlPacket {
+                timestamp,
+                offset: 0,
+                storage_id: None,
+                storage_version: VERSION,
+                position_time: 0,
+                lon: 0.,
+                lat: 0.,
+                temperature: 0.,
+                freq,
+                calibration: 0,
+                quality: 0,
+                bias_mode: 0,
+                bias: [0.; 3],
+                scale: [1.; 3],
+                dop: 0.,
+                accel_max,
+                frame: 0,
+                axes: AXES_ALL,
+                warmup: 0,
+                filled: Vec::new(),
+                rtc_timestamp: timestamp,
+                raw_samples: 0,
+                output_samples: 0,
+                crc: 0,
+                battery_v: None,
+                temperature_c: None,
+                clipped_samples: 0,
+                data,
+                gyro: Vec::new(),
+            },
+        }
+    }
+
+    /// Offset in the IMU FIFO at the time of the timestamp.
+    pub fn offset(mut self, offset: u16) -> Self {
+        self.pck.offset = offset;
+        self
+    }
+
+    /// ID on the SD-card and the format version the package was stored with.
+    pub fn storage(mut self, id: Option<u32>, version: u32) -> Self {
+        self.pck.storage_id = id;
+        self.pck.storage_version = version;
+        self
+    }
+
+    /// Time [s], longitude and latitude of the position, and the dilution of precision of the
+    /// fix.
+    pub fn position(mut self, time: u32, lon: f64, lat: f64, dop: f32) -> Self {
+        self.pck.position_time = time;
+        self.pck.lon = lon;
+        self.pck.lat = lat;
+        self.pck.dop = dop;
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.pck.temperature = temperature;
+        self
+    }
+
+    /// Step of the calibration procedure, `0` for normal packages.
+    pub fn calibration(mut self, step: u8) -> Self {
+        self.pck.calibration = step;
+        self
+    }
+
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.pck.quality = quality;
+        self
+    }
+
+    /// Offset removal applied to the samples, with the offset and the scale factor.
+    pub fn bias(mut self, mode: u8, bias: [f32; 3], scale: [f32; 3]) -> Self {
+        self.pck.bias_mode = mode;
+        self.pck.bias = bias;
+        self.pck.scale = scale;
+        self
+    }
+
+    /// Frame of reference (`waves::Frame::code`), by default the frame of the calibration step
+    /// without lever arm correction.
+    pub fn frame(mut self, frame: u8) -> Self {
+        self.pck.frame = frame;
+        self
+    }
+
+    pub fn axes(mut self, axes: u8) -> Self {
+        self.pck.axes = axes;
+        self
+    }
+
+    pub fn warmup(mut self, warmup: u16) -> Self {
+        self.pck.warmup = warmup;
+        self
+    }
+
+    pub fn filled(mut self, filled: Vec<u16, MAX_FILLED>) -> Self {
+        self.pck.filled = filled;
+        self
+    }
+
+    /// Time of the RTC, when the timestamp has been moved (see [`AxlPacket::rtc_timestamp`]).
+    pub fn rtc_timestamp(mut self, rtc_timestamp: i64) -> Self {
+        self.pck.rtc_timestamp = rtc_timestamp;
+        self
+    }
+
+    /// Samples from the IMU and out of the decimation (see [`AxlPacket::raw_samples`]).
+    pub fn sample_counts(mut self, raw_samples: u32, output_samples: u32) -> Self {
+        self.pck.raw_samples = raw_samples;
+        self.pck.output_samples = output_samples;
+        self
+    }
+
+    /// CRC-32 of the samples as recorded (see [`AxlPacket::crc`]), e.g. from the body of a note.
+    pub fn crc(mut self, crc: u32) -> Self {
+        self.pck.crc = crc;
+        self
+    }
+
+    /// Battery voltage [V] and temperature of the board [°C] measured by the Notecard (see
+    /// [`AxlPacket::battery_v`]).
+    pub fn card(mut self, battery_v: Option<f32>, temperature_c: Option<f32>) -> Self {
+        self.pck.battery_v = battery_v;
+        self.pck.temperature_c = temperature_c;
+        self
+    }
+
+    /// IMU samples at the full scale of the accelerometer (see [`AxlPacket::clipped_samples`]).
+    pub fn clipped_samples(mut self, clipped_samples: u16) -> Self {
+        self.pck.clipped_samples = clipped_samples;
+        self
+    }
+
+    /// Angular rate with the samples (see [`AxlPacket::gyro`]).
+    pub fn gyro(mut self, gyro: Vec<u16, { GYRO_SZ }>) -> Self {
+        self.pck.gyro = gyro;
+        self
+    }
+
+    /// Check the package and make it. An empty package (the buffer taken at a reset) does not
+    /// need a timestamp.
+    pub fn build(self) -> Result<AxlPacket, PacketError> {
+        let mut p = self.pck;
+
+        if !p.data.is_empty() && p.timestamp <= 0 {
+            return Err(PacketError::Timestamp);
+        }
+
+        if !(p.freq.is_finite() && p.freq > 0.) {
+            return Err(PacketError::Frequency);
+        }
+
+        if !(p.accel_max.is_finite() && p.accel_max > 0.) {
+            return Err(PacketError::AccelMax);
+        }
+
+        let width = axes_width(p.axes);
+        if width == 0 || p.axes & !AXES_ALL != 0 {
+            return Err(PacketError::Axes(p.axes));
+        }
+
+        if p.data.len() % width != 0 {
+            return Err(PacketError::Length(p.data.len()));
+        }
+
+        let samples = p.data.len() / width;
+        if !p.gyro.is_empty() && p.gyro.len() != samples * SAMPLE_SZ {
+            return Err(PacketError::Gyro(p.gyro.len()));
+        }
+
+        if let Some(f) = p.filled.iter().find(|f| **f as usize >= samples) {
+            return Err(PacketError::Filled(*f));
+        }
+
+        if p.frame == 0 {
+            p.frame = Frame::of(p.calibration, false).code();
+        }
+
+        Ok(p)
+    }
+}
+
+impl AxlPacket {
+    pub fn base64(&self) -> Vec<u8, AXL_OUTN> {
+        let mut b64: Vec<_, AXL_OUTN> = Vec::new();
+        b64.resize_default(AXL_OUTN).unwrap();
+
+        // Check endianness (TODO:  swap order if compiled for big endian machine).
+        #[cfg(target_endian = "big")]
+        compile_error!("serializied samples are assumed to be in little endian, target platform is big endian and no conversion is implemented.");
+
+        let data = bytemuck::cast_slice(&self.data);
+        let written = base64::encode_config_slice(data, base64::STANDARD, &mut b64);
+        b64.truncate(written);
+
+        b64
+    }
+
+    /// The deltas of the samples as zigzag varints in base64 (see [`SampleEncoding::Delta`]),
+    /// `None` when they are not smaller than the samples (two bytes each).
+    pub fn base64_delta(&self) -> Option<Vec<u8, AXL_OUTN>> {
+        let mut buf = [0u8; AXL_SZ * 2];
+        let n = write_varint_deltas(&self.data, self.width().max(1), &mut buf)
+            .filter(|n| *n < self.data.len() * 2)?;
+
+        let mut b64: Vec<_, AXL_OUTN> = Vec::new();
+        b64.resize_default(AXL_OUTN).unwrap();
+        let written = base64::encode_config_slice(&buf[..n], base64::STANDARD, &mut b64);
+        b64.truncate(written);
+
+        Some(b64)
+    }
+
+    /// Age of position at the time of the package in seconds.
+    pub fn fix_age(&self) -> u32 {
+        u32::try_from(self.timestamp / 1000)
+            .unwrap_or(0)
+            .saturating_sub(self.position_time)
+    }
+
+    /// Acceleration in m/s^2 of the sample `u` (16 bit count) of this package.
+    pub fn accel(&self, u: u16) -> f32 {
+        wire::scale_u16_to_f32(self.accel_max, u)
+    }
+
+    /// Angular rate in rad/s of the gyroscope sample `u` (16 bit count) of this package.
+    pub fn gyro_rate(&self, u: u16) -> f32 {
+        wire::scale_u16_to_f32(wire::GYRO_MAX, u)
+    }
+
+    /// Number of values per sample: the number of axes in the package.
+    pub fn width(&self) -> usize {
+        axes_width(self.axes)
+    }
+
+    /// Number of samples.
+    pub fn samples(&self) -> usize {
+        match self.width() {
+            0 => 0,
+            w => self.data.len() / w,
+        }
+    }
+
+    /// Decimation from the IMU rate (`waves::FREQ`) to the sample rate of the package (`freq`),
+    /// `1` for calibration packages.
+    pub fn decimation(&self) -> u8 {
+        (crate::waves::FREQ.value() / self.freq).round() as u8
+    }
+
+    /// Samples from the IMU per output sample, from the sample counts (see
+    /// [`AxlPacket::raw_samples`]). `None` when the counts were not recorded.
+    pub fn decimation_ratio(&self) -> Option<f32> {
+        (self.output_samples > 0).then(|| self.raw_samples as f32 / self.output_samples as f32)
+    }
+
+    /// The sample counts agree with a decimation of `decimation` (`1` for calibration packages):
+    /// the samples from the IMU are less than one decimation period from `decimation` times the
+    /// output samples, since a package may start and end part way into a period. Samples lost
+    /// between the FIFO and the filters, or output samples that were not made from samples of the
+    /// IMU, show up as a deviation. Always `true` when the counts were not recorded.
+    pub fn check_decimation(&self, decimation: u32) -> bool {
+        if self.raw_samples == 0 && self.output_samples == 0 {
+            return true;
+        }
+
+        let expected = self.output_samples as i64 * decimation as i64;
+        (self.raw_samples as i64 - expected).abs() < decimation.max(1) as i64
+    }
+
+    /// The samples match the CRC-32 recorded when the package was taken from the IMU buffer
+    /// (see [`AxlPacket::crc`]). Packages from before the CRC was recorded cannot be verified, and
+    /// always pass.
+    pub fn verify(&self) -> bool {
+        self.crc == 0 || self.crc == crc32(&self.data)
+    }
+
+    /// Position of `axis` (0: x, 1: y, 2: z) in a sample, `None` if the axis is left out.
+    pub fn column(&self, axis: usize) -> Option<usize> {
+        if axis < SAMPLE_SZ && self.axes & (1 << axis) != 0 {
+            Some(axes_width(self.axes & ((1 << axis) - 1)))
+        } else {
+            None
+        }
+    }
+
+    /// Acceleration in m/s^2 along `axis` (0: x, 1: y, 2: z), empty if the axis is left out.
+    fn axis(&self, axis: usize) -> impl Iterator<Item = f32> + Clone + '_ {
+        let (skip, n) = match self.column(axis) {
+            Some(c) => (c, self.data.len()),
+            None => (0, 0),
+        };
+
+        self.data[..n]
+            .iter()
+            .skip(skip)
+            .step_by(self.width().max(1))
+            .map(|u| self.accel(*u))
+    }
+
+    /// Standard deviation of acceleration along `axis` in m/s^2, `0` if the axis is left out.
+    fn axis_std(&self, axis: usize) -> f32 {
+        self.axis_std_by(axis, |_| 1.)
+    }
+
+    /// Standard deviation of acceleration along `axis` in m/s^2, with the weight of every sample
+    /// from `weight`. `0` if the axis is left out, or the weights sum to `0`.
+    fn axis_std_by(&self, axis: usize, weight: impl Fn(usize) -> f32) -> f32 {
+        if self.column(axis).is_none() {
+            return 0.0;
+        }
+
+        let weight = &weight;
+        let v = self
+            .axis(axis)
+            .enumerate()
+            .map(move |(i, a)| (weight(i), a));
+
+        let sw = v.clone().map(|(w, _)| w).sum::<f32>();
+        if sw <= 0. {
+            return 0.0;
+        }
+
+        let mean = v.clone().map(|(w, a)| w * a).sum::<f32>() / sw;
+        let var = v.map(|(w, a)| w * (a - mean) * (a - mean)).sum::<f32>() / sw;
+
+        libm::sqrtf(var)
+    }
+
+    /// Sample `i` is flagged: one of its values is clipped, or it was interpolated over a gap in
+    /// the IMU FIFO (see `filled`).
+    pub fn flagged(&self, i: usize) -> bool {
+        let w = self.width();
+
+        self.filled.contains(&(i as u16))
+            || self.data[i * w..(i + 1) * w]
+                .iter()
+                .any(|u| *u == 0 || *u == u16::MAX)
+    }
+
+    /// Fraction of samples that are not flagged, `1` for an empty package.
+    pub fn good(&self) -> f32 {
+        match self.samples() {
+            0 => 1.,
+            n => (0..n).filter(|i| !self.flagged(*i)).count() as f32 / n as f32,
+        }
+    }
+
+    /// Standard deviation of the vertical acceleration in m/s^2.
+    pub fn z_std(&self) -> f32 {
+        self.axis_std(2)
+    }
+
+    /// Rough significant wave height in m from the vertical acceleration, as for a regular wave:
+    /// `4 std(a) / w^2`, with `w` from the mean zero up-crossing period of the acceleration. For
+    /// the summary in the data notes, `sfypack stats` gives `Hm0` from the spectrum. `0` if the
+    /// vertical axis is left out, or with less than two up-crossings.
+    pub fn hs(&self) -> f32 {
+        let n = self.axis(2).count();
+        if n == 0 || self.freq <= 0. {
+            return 0.0;
+        }
+
+        // Index of the last up-crossing, the number of them and the index of the first.
+        let mean = self.axis(2).sum::<f32>() / n as f32;
+        let (last, count, first) = self
+            .axis(2)
+            .zip(self.axis(2).skip(1))
+            .enumerate()
+            .filter(|(_, (a, b))| *a < mean && *b >= mean)
+            .fold((0, 0, None), |(_, c, f), (i, _)| (i, c + 1, f.or(Some(i))));
+
+        let Some(first) = first.filter(|_| count >= 2) else {
+            return 0.0;
+        };
+
+        let period = (last - first) as f32 / (count - 1) as f32 / self.freq;
+        let w = 2. * core::f32::consts::PI / period;
+
+        4. * self.z_std() / (w * w)
+    }
+
+    /// Statistics with every sample counted, see [`AxlPacket::stats_weighted`].
+    pub fn stats(&self) -> AxlStats {
+        self.stats_weighted(&Weighting::default())
+    }
+
+    /// Statistics with the flagged samples weighted by `weighting`.
+    pub fn stats_weighted(&self, weighting: &Weighting) -> AxlStats {
+        let good = self.good();
+        let rejected = weighting.rejects(good);
+        let weight = |i| weighting.weight(self.flagged(i));
+
+        let mut s = AxlStats {
+            timestamp: self.timestamp,
+            storage_id: self.storage_id,
+            position_time: self.position_time,
+            lon: self.lon,
+            lat: self.lat,
+            freq: self.freq,
+            samples: self.samples() as u32,
+            clipped: self.clipped() as u32,
+            quality: self.quality,
+            good,
+            rejected,
+            ..Default::default()
+        };
+
+        if !rejected {
+            s.x_std = self.axis_std_by(0, weight);
+            s.y_std = self.axis_std_by(1, weight);
+            s.z_std = self.axis_std_by(2, weight);
+            s.z_max = self
+                .axis(2)
+                .enumerate()
+                .filter(|(i, _)| weight(*i) > 0.)
+                .map(|(_, a)| libm::fabsf(a))
+                .fold(0.0, f32::max);
+        }
+
+        s
+    }
+
+    /// Largest standard deviation of the acceleration of the axes in m/s^2, the motion of the
+    /// package for the motion gate (see `gate`).
+    pub fn motion(&self) -> f32 {
+        (0..SAMPLE_SZ).map(|a| self.axis_std(a)).fold(0.0, f32::max)
+    }
+
+    /// Number of values at the limits of the scaled range (clipped).
+    pub fn clipped(&self) -> usize {
+        self.data
+            .iter()
+            .filter(|u| **u == 0 || **u == u16::MAX)
+            .count()
+    }
+
+    /// Serialize package with the format version tag and COBS framing. A full package fits in
+    /// `AXL_POSTCARD_SZ`, a smaller `N` fails with [`SerializationError::BufferFull`].
+    pub fn to_cobs<const N: usize>(&self) -> Result<Vec<u8, N>, SerializationError> {
+        postcard::to_vec_cobs(&(FORMAT_VERSION, self)).map_err(SerializationError::from)
+    }
+
+    /// Serialize package like [`AxlPacket::to_cobs`] with the samples delta encoded (see
+    /// [`SampleEncoding::Delta`]): tagged with `FORMAT_VERSION | DELTA`, followed by the package
+    /// without the samples, and the deltas as zigzag varints. The package is serialized with
+    /// `to_cobs` when the deltas are not smaller, so it is never larger. The samples are encoded in
+    /// place, and restored before returning. Packages stored with the deltas at an older version
+    /// (`version | DELTA`) are decoded with the layout of that version.
+    pub fn to_cobs_delta<const N: usize>(&mut self) -> Result<Vec<u8, N>, SerializationError> {
+        let width = self.width().max(1);
+        let mut data = core::mem::take(&mut self.data);
+
+        let raw: usize = data.iter().map(|v| varint_sz(*v)).sum();
+        delta_encode(&mut data, width);
+        let deltas: usize = data.iter().map(|d| varint_sz(zigzag(*d))).sum();
+
+        // The empty `data` takes one byte more than the raw package, the deltas are smaller.
+        let b = (deltas < raw).then(|| {
+            let deltas: &[i16] = bytemuck::cast_slice(&data);
+            postcard::to_vec_cobs(&(FORMAT_VERSION | DELTA, &*self, deltas))
+                .map_err(SerializationError::from)
+        });
+
+        delta_decode(&mut data, width);
+        self.data = data;
+
+        b.unwrap_or_else(|| self.to_cobs())
+    }
+
+    /// Serialize package with the samples in `encoding`, see [`AxlPacket::to_cobs`] and
+    /// [`AxlPacket::to_cobs_delta`].
+    pub fn to_cobs_encoded<const N: usize>(
+        &mut self,
+        encoding: SampleEncoding,
+    ) -> Result<Vec<u8, N>, SerializationError> {
+        match encoding {
+            SampleEncoding::Raw => self.to_cobs(),
+            SampleEncoding::Delta => self.to_cobs_delta(),
+        }
+    }
+
+    /// Deserialize a COBS framed package stored with format `version`, this is the version of the
+    /// collection file the package is read from. The buffer is decoded in place.
+    ///
+    /// Packages of version 5 and older are untagged, later packages must carry a tag matching
+    /// `version`.
+    pub fn decode(version: u32, buf: &mut [u8]) -> Result<AxlPacket, DecodeError> {
+        if version > VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let n = cobs::decode_in_place(buf).map_err(|_| DecodeError::Cobs)?;
+
+        Self::decode_bytes(version, &buf[..n])
+    }
+
+    /// Deserialize a package stored with format `version`, after the COBS framing has been
+    /// removed (see [`AxlPacket::decode`]).
+    pub fn decode_bytes(version: u32, buf: &[u8]) -> Result<AxlPacket, DecodeError> {
+        if version > VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        if version <= LAST_UNTAGGED_VERSION {
+            // Versions 1 to 5 have the same layout as version 6, but no tag.
+            return Self::take_bytes(version, buf).map(|(p, _)| p);
+        }
+
+        let (tag, buf) = buf.split_first().ok_or(DecodeError::Empty)?;
+
+        if *tag == SEALED {
+            return Err(DecodeError::Sealed);
+        }
+
+        // The samples are delta encoded when the version is flagged with `DELTA`, the package has
+        // the layout of the version either way.
+        let version = (*tag & !DELTA) as u32;
+        if version <= LAST_UNTAGGED_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let (p, deltas) = Self::take_bytes(version, buf)?;
+
+        if *tag & DELTA != 0 {
+            Self::decode_delta(p, deltas)
+        } else {
+            Ok(p)
+        }
+    }
+
+    /// Deserialize a package with the layout of format `version` from the start of `buf`, without
+    /// the tag, and return it with the bytes after it. The entries of the backfill batches are
+    /// read this way (see `backfill`).
+    pub fn take_bytes(version: u32, buf: &[u8]) -> Result<(AxlPacket, &[u8]), DecodeError> {
+        Versioned::take(version, buf).map(|(p, rest)| (p.current(), rest))
+    }
+
+    /// Restore the samples of a package stored with the samples delta encoded from the deltas
+    /// after the package (see [`AxlPacket::to_cobs_delta`]).
+    fn decode_delta(mut pck: AxlPacket, buf: &[u8]) -> Result<AxlPacket, DecodeError> {
+        if !pck.data.is_empty() {
+            return Err(DecodeError::Postcard(
+                postcard::Error::DeserializeBadEncoding,
+            ));
+        }
+
+        let (deltas, _) =
+            postcard::take_from_bytes::<Vec<i16, AXL_SZ>>(buf).map_err(DecodeError::Postcard)?;
+
+        pck.data = deltas.iter().map(|d| *d as u16).collect();
+        delta_decode(&mut pck.data, pck.width().max(1));
+
+        Ok(pck)
+    }
+
+    /// Deserialize a COBS framed package of the current format version.
+    pub fn from_cobs(buf: &mut [u8]) -> Result<AxlPacket, DecodeError> {
+        Self::decode(VERSION, buf)
+    }
+
+    /// Reassemble package from the body and the base64 payload of a data note, the inverse of
+    /// [`AxlPacket::split`]. This is how the notes are decoded after they have been sent.
+    pub fn from_note(meta: &AxlPacketMeta, payload: &[u8]) -> Result<AxlPacket, DecodeError> {
+        if meta.sealed {
+            return Err(DecodeError::Sealed);
+        }
+
+        if payload.len() != meta.length as usize {
+            return Err(DecodeError::Payload);
+        }
+
+        let mut buf = [0u8; AXL_SZ * 2 + 3];
+        if (payload.len() + 3) / 4 * 3 > buf.len() {
+            return Err(DecodeError::Payload);
+        }
+
+        let n = base64::decode_config_slice(payload, base64::STANDARD, &mut buf)
+            .map_err(|_| DecodeError::Payload)?;
+
+        let width = axes_width(meta.axes);
+        if width == 0 {
+            return Err(DecodeError::Payload);
+        }
+
+        let data: Vec<u16, AXL_SZ> = match SampleEncoding::from_code(meta.encoding) {
+            Some(SampleEncoding::Raw) if n % (2 * width) == 0 => buf[..n]
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+            Some(SampleEncoding::Delta) => read_varint_deltas(&buf[..n], width)
+                .filter(|d| d.len() % width == 0)
+                .ok_or(DecodeError::Payload)?,
+            Some(SampleEncoding::Raw) => return Err(DecodeError::Payload),
+            None => return Err(DecodeError::Compression(meta.encoding)),
+        };
+
+        // Notes from before the frame was recorded get the frame of the calibration step.
+        AxlPacketBuilder::new(meta.timestamp, meta.freq, meta.accel_max, data)
+            .offset(u16::try_from(meta.offset).map_err(|_| DecodeError::Payload)?)
+            .storage(meta.storage_id, meta.storage_version)
+            .position(meta.position_time, meta.lon, meta.lat, meta.dop)
+            .temperature(meta.temperature)
+            .calibration(meta.calibration)
+            .quality(meta.quality)
+            .bias(
+                meta.bias_mode,
+                [meta.bias_x, meta.bias_y, meta.bias_z],
+                [meta.scale_x, meta.scale_y, meta.scale_z],
+            )
+            .frame(meta.frame)
+            .axes(meta.axes)
+            .warmup(meta.warmup)
+            .filled(filled_from_str(&meta.filled).ok_or(DecodeError::Payload)?)
+            .rtc_timestamp(meta.timestamp - meta.rtc_shift)
+            .sample_counts(meta.raw_samples, meta.output_samples)
+            .crc(meta.crc)
+            .card(meta.battery_v, meta.temperature_c)
+            .clipped_samples(meta.clipped_samples)
+            .build()
+            .map_err(|_| DecodeError::Payload)
+    }
+
+    /// Split package into metadata and payload.
+    pub fn split(&self) -> (AxlPacketMeta, Vec<u8, AXL_OUTN>) {
+        self.split_encoded(SampleEncoding::Raw)
+    }
+
+    /// Split package into metadata and payload, with the samples of the payload in `encoding`.
+    /// The payload is raw when the deltas are not smaller than two bytes per sample, `encoding`
+    /// in the metadata gives the encoding that was used.
+    pub fn split_encoded(&self, encoding: SampleEncoding) -> (AxlPacketMeta, Vec<u8, AXL_OUTN>) {
+        let (encoding, b64) = match encoding {
+            SampleEncoding::Delta => match self.base64_delta() {
+                Some(b64) => (SampleEncoding::Delta, b64),
+                None => (SampleEncoding::Raw, self.base64()),
+            },
+            SampleEncoding::Raw => (SampleEncoding::Raw, self.base64()),
+        };
+
+        let meta = AxlPacketMeta {
+            timestamp: self.timestamp,
+            offset: self.offset as u32,
+            length: b64.len() as u32,
+            freq: self.freq,
+            storage_id: self.storage_id,
+            storage_version: self.storage_version,
+            position_time: self.position_time,
+            lon: self.lon,
+            lat: self.lat,
+            temperature: self.temperature,
+
+            fix_age: None,
+            hs: None,
+            time_degraded: crate::clock::time_degraded(),
+            calibration: self.calibration,
+            quality: self.quality,
+            accel_lpf: crate::waves::dlpf::accel_lpf(),
+            gyro_lpf: crate::waves::dlpf::gyro_lpf(),
+            bias_mode: self.bias_mode,
+            bias_x: self.bias[0],
+            bias_y: self.bias[1],
+            bias_z: self.bias[2],
+            scale_x: self.scale[0],
+            scale_y: self.scale[1],
+            scale_z: self.scale[2],
+            dop: self.dop,
+            accel_max: self.accel_max,
+            frame: self.frame,
+            axes: self.axes,
+            warmup: self.warmup,
+            filled: filled_to_str(&self.filled),
+            rtc_shift: self.timestamp - self.rtc_timestamp,
+            raw_samples: self.raw_samples,
+            output_samples: self.output_samples,
+            crc: self.crc,
+            battery_v: self.battery_v,
+            temperature_c: self.temperature_c,
+            clipped_samples: self.clipped_samples,
+            imu_freq: crate::waves::FREQ.value(),
+            decimation: self.decimation(),
+            encoding: encoding.code(),
+            sealed: false,
+        };
+
+        (meta, b64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quality;
+    use crate::waves::wire::{ScaledF32, A16};
+
+    #[test]
+    fn base64_data_package() {
+        let p = package();
+
+        let b64 = p.base64();
+        println!("{}", core::str::from_utf8(&b64).unwrap());
+    }
+
+    #[test]
+    fn builder() {
+        let data = || Vec::from_slice(&[0u16; 6 * SAMPLE_SZ]).unwrap();
+
+        let p = AxlPacketBuilder::new(1_700_000_000_000, 52., ACCEL_MAX, data())
+            .offset(3)
+            .position(1_700_000_000, 5.3, 60.4, 1.2)
+            .filled(Vec::from_slice(&[5]).unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(p.offset, 3);
+        assert_eq!(p.storage_version, VERSION);
+        assert_eq!(p.frame, Frame::Earth.code());
+        assert_eq!(p.scale, [1.; 3]);
+        assert_eq!(p.stats().samples, 6);
+
+        // The empty buffer taken at a reset has no timestamp yet.
+        assert!(AxlPacketBuilder::new(0, 52., ACCEL_MAX, Vec::new())
+            .build()
+            .is_ok());
+        assert_eq!(
+            AxlPacketBuilder::new(0, 52., ACCEL_MAX, data()).build(),
+            Err(PacketError::Timestamp)
+        );
+
+        let b = || AxlPacketBuilder::new(1_700_000_000_000, 52., ACCEL_MAX, data());
+        assert_eq!(
+            AxlPacketBuilder::new(1, f32::NAN, ACCEL_MAX, data()).build(),
+            Err(PacketError::Frequency)
+        );
+        assert_eq!(
+            AxlPacketBuilder::new(1, 52., 0., data()).build(),
+            Err(PacketError::AccelMax)
+        );
+        assert_eq!(b().axes(0).build(), Err(PacketError::Axes(0)));
+        assert_eq!(b().axes(0b1001).build(), Err(PacketError::Axes(0b1001)));
+        assert!(b().axes(0b101).build().is_ok());
+        assert_eq!(
+            AxlPacketBuilder::new(1, 52., ACCEL_MAX, Vec::from_slice(&[0u16; 17]).unwrap()).build(),
+            Err(PacketError::Length(17))
+        );
+        assert_eq!(
+            b().filled(Vec::from_slice(&[6]).unwrap()).build(),
+            Err(PacketError::Filled(6))
+        );
+    }
+
+    #[test]
+    fn package_buf() {
+        let v = <Vec<u8, 16>>::zeroed(10);
+        assert_eq!(v.as_ref(), &[0u8; 10]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn package_buf_too_small() {
+        <Vec<u8, 4>>::zeroed(10);
+    }
+
+    #[test]
+    fn note_round_trip() {
+        let p = package();
+
+        let (meta, b64) = p.split();
+        let d = AxlPacket::from_note(&meta, &b64).unwrap();
+        assert_eq!(d, p);
+
+        assert_eq!(
+            AxlPacket::from_note(&meta, &b64[..b64.len() - 4]),
+            Err(DecodeError::Payload)
+        );
+    }
+
+    #[test]
+    fn short_package() {
+        // Flushed early at a FIFO overrun, see `Imu::reset`.
+        let mut p = package();
+        p.data.truncate(317 * SAMPLE_SZ);
+        p.quality = crate::quality::FIFO_OVERRUN;
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        let d = AxlPacket::decode(VERSION, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.stats().samples, 317);
+
+        let (meta, b64) = p.split();
+        let d = AxlPacket::from_note(&meta, &b64).unwrap();
+        assert_eq!(d, p);
+
+        // A payload missing whole samples does not match the length.
+        assert_eq!(
+            AxlPacket::from_note(&meta, &b64[..b64.len() - 8]),
+            Err(DecodeError::Payload)
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let data = (0..AXL_SZ).map(|_| A16::from_f32(1.0).to_u16()).collect();
+        let mut p = AxlPacketBuilder::new(100_000, 52.0, ACCEL_MAX, data)
+            .position(40, 0.0, 0.0, 0.)
+            .build()
+            .unwrap();
+
+        assert_eq!(p.fix_age(), 60);
+        assert!(p.z_std() < 1.0e-6);
+
+        for (i, z) in p.data.iter_mut().skip(2).step_by(SAMPLE_SZ).enumerate() {
+            *z = A16::from_f32(if i % 2 == 0 { 1.0 } else { -1.0 }).to_u16();
+        }
+
+        assert!((p.z_std() - 1.0).abs() < 1.0e-3);
+
+        p.position_time = 200;
+        assert_eq!(p.fix_age(), 0);
+
+        let s = p.stats();
+        assert_eq!(s.timestamp, p.timestamp);
+        assert_eq!(s.samples, SAMPLE_NO as u32);
+        assert!(s.x_std < 1.0e-6);
+        assert!((s.z_std - 1.0).abs() < 1.0e-3);
+        assert!((s.z_max - 1.0).abs() < 1.0e-3);
+        assert_eq!(s.clipped, 0);
+        assert_eq!((s.good, s.rejected), (1., false));
+    }
+
+    #[test]
+    fn significant_wave_height() {
+        let data = (0..AXL_SZ).map(|_| A16::from_f32(0.0).to_u16()).collect();
+        let mut p = AxlPacketBuilder::new(100_000, 52.0, ACCEL_MAX, data)
+            .build()
+            .unwrap();
+        assert_eq!(p.hs(), 0.);
+
+        // A regular wave of 0.5 m amplitude: Hs = 4 sqrt(0.5^2 / 2).
+        let w = 2. * core::f32::consts::PI * 0.5;
+        for (i, z) in p.data.iter_mut().skip(2).step_by(SAMPLE_SZ).enumerate() {
+            let t = i as f32 / p.freq;
+            *z = A16::from_f32(-0.5 * w * w * libm::sinf(w * t + 0.3)).to_u16();
+        }
+        let hs = 4. * libm::sqrtf(0.5 * 0.5 / 2.);
+        assert!((p.hs() - hs).abs() < 0.05 * hs, "{}", p.hs());
+    }
+
+    #[test]
+    fn weighted_stats() {
+        let mut p = package();
+        p.data = (0..SAMPLE_NO)
+            .flat_map(|i| {
+                let z = if i % 2 == 0 { 1.0 } else { -1.0 };
+                [0., 0., z].map(|v| A16::from_f32(v).to_u16())
+            })
+            .collect();
+
+        // A tenth of the samples clipped in z, and one interpolated far off.
+        let n = SAMPLE_NO / 10;
+        for i in 0..n {
+            p.data[i * 10 * SAMPLE_SZ + 2] = u16::MAX;
+        }
+        p.data[5 * SAMPLE_SZ + 2] = A16::from_f32(5.0).to_u16();
+        p.filled.push(5).unwrap();
+
+        assert!(p.flagged(0) && p.flagged(5) && !p.flagged(1));
+        let good = 1. - (n + 1) as f32 / SAMPLE_NO as f32;
+        assert!((p.good() - good).abs() < 1.0e-6);
+
+        // Counted like the other samples.
+        let s = p.stats();
+        assert_eq!(s.good, p.good());
+        assert!(!s.rejected);
+        assert!(s.z_std > 1.1);
+        assert!(s.z_max > 5.0);
+
+        // Left out.
+        let w = Weighting {
+            weight: 0.,
+            min_good: 0.,
+        };
+        let s = p.stats_weighted(&w);
+        assert!((s.z_std - 1.0).abs() < 1.0e-2);
+        assert!((s.z_max - 1.0).abs() < 1.0e-3);
+        assert!(s.x_std < 1.0e-6);
+
+        // Down-weighted, between the two.
+        let s2 = p.stats_weighted(&Weighting { weight: 0.5, ..w });
+        assert!(s2.z_std > s.z_std && s2.z_std < p.stats().z_std);
+
+        // Too few good samples.
+        let s = p.stats_weighted(&Weighting {
+            min_good: 0.95,
+            ..w
+        });
+        assert!(s.rejected);
+        assert_eq!((s.x_std, s.z_std, s.z_max), (0., 0., 0.));
+        assert_eq!(s.samples, SAMPLE_NO as u32);
+    }
+
+    #[test]
+    fn postcard_size() {
+        let p = package();
+
+        assert!(p.data.is_full());
+
+        let v: Vec<_, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        println!("{}", v.len());
+
+        assert!(v.len() < AXL_POSTCARD_SZ);
+    }
+
+    #[test]
+    fn postcard_max_size() {
+        // Every field at its longest encoding, spelled out so that a new field is added here too.
+        let p = AxlPacket {
+            timestamp: i64::MIN,
+            position_time: u32::MAX,
+            lat: f64::MAX,
+            lon: f64::MIN,
+            freq: f32::MAX,
+            offset: u16::MAX,
+            storage_id: Some(u32::MAX),
+            storage_version: u32::MAX,
+            calibration: u8::MAX,
+            quality: u8::MAX,
+            bias_mode: u8::MAX,
+            bias: [f32::MAX; 3],
+            scale: [f32::MAX; 3],
+            dop: f32::MAX,
+            accel_max: f32::MAX,
+            frame: u8::MAX,
+            axes: u8::MAX,
+            warmup: u16::MAX,
+            filled: (0..MAX_FILLED).map(|_| u16::MAX).collect(),
+            rtc_timestamp: i64::MIN,
+            raw_samples: u32::MAX,
+            output_samples: u32::MAX,
+            crc: u32::MAX,
+            battery_v: None,
+            temperature_c: None,
+            clipped_samples: u16::MAX,
+            temperature: f32::MAX,
+            data: (0..AXL_SZ)
+                .map(|_| u16::MAX)
+                .collect::<Vec<_, { AXL_SZ }>>(),
+            gyro: Vec::new(),
+        };
+
+        let v: Vec<u8, { POSTCARD_MAX_SZ }> = postcard::to_vec(&(FORMAT_VERSION, &p)).unwrap();
+        assert!(v.len() - AXL_SZ * 3 <= HEADER_MAX_SZ);
+
+        let v: Vec<_, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert!(v.len() <= cobs_max_sz(POSTCARD_MAX_SZ));
+
+        let mut v = v;
+        assert!(AxlPacket::decode(VERSION, &mut v) == Ok(p));
+
+        // With the gyroscope samples, in the slot of the `gyro` feature.
+        let mut p = p;
+        p.gyro = (0..GYRO_SZ).map(|_| u16::MAX).collect();
+
+        let v: Vec<u8, { GYRO_POSTCARD_MAX_SZ }> = postcard::to_vec(&(FORMAT_VERSION, &p)).unwrap();
+        assert!(v.len() - (AXL_SZ + GYRO_SZ) * 3 <= HEADER_MAX_SZ);
+
+        let mut v: Vec<_, { GYRO_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert!(v.len() <= cobs_max_sz(GYRO_POSTCARD_MAX_SZ));
+        assert!(AxlPacket::decode(VERSION, &mut v) == Ok(p));
+    }
+
+    /// Packages are written to a collection padded to their slot, and read back by splitting the
+    /// collection into slots. A change of the slot size makes every stored collection unreadable.
+    #[test]
+    fn package_slots() {
+        assert_eq!(ACCEL_POSTCARD_SZ, 10_240);
+        assert_eq!(GYRO_POSTCARD_SZ, 20_480);
+
+        let mut pcks = [package(), package()];
+        pcks[1].storage_id = Some(1490);
+        pcks[1].timestamp += 20_000;
+        pcks[1].crc = crc32(&pcks[1].data);
+
+        let mut collection = std::vec::Vec::new();
+        for p in &pcks {
+            let mut slot: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+            assert!(slot.len() < AXL_POSTCARD_SZ);
+
+            slot.resize_default(slot.capacity()).unwrap();
+            assert_eq!(slot.len(), AXL_POSTCARD_SZ);
+            collection.extend_from_slice(&slot);
+        }
+
+        assert_eq!(collection.len(), 2 * AXL_POSTCARD_SZ);
+
+        for (slot, p) in collection.chunks_exact_mut(AXL_POSTCARD_SZ).zip(&pcks) {
+            assert!(AxlPacket::decode(VERSION, slot) == Ok(p.clone()));
+        }
+    }
+
+    #[test]
+    fn postcard_too_large() {
+        let p = package();
+
+        assert_eq!(
+            p.to_cobs::<1024>().err(),
+            Some(SerializationError::BufferFull)
+        );
+    }
+
+    fn package() -> AxlPacket {
+        let data = (0..AXL_SZ).map(|v| v as u16).collect();
+
+        AxlPacketBuilder::new(100212312312330, 53.0, ACCEL_MAX, data)
+            .storage(Some(1489), VERSION)
+            .position(123123, 54.012, 34.52341, 0.)
+            .build()
+            .unwrap()
+    }
+
+    #[bench]
+    fn serialize_package(b: &mut test::Bencher) {
+        let p = package();
+
+        b.iter(|| {
+            let v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+            test::black_box(v)
+        });
+    }
+
+    #[bench]
+    fn deserialize_package(b: &mut test::Bencher) {
+        let p = package();
+        let v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+
+        b.iter(|| {
+            let mut v = v.clone();
+            test::black_box(AxlPacket::from_cobs(&mut v).unwrap())
+        });
+    }
+
+    #[bench]
+    fn split_package(b: &mut test::Bencher) {
+        let p = package();
+
+        b.iter(|| test::black_box(p.split()));
+    }
+
+    #[bench]
+    fn serialize_package_delta(b: &mut test::Bencher) {
+        let mut p = package();
+
+        b.iter(|| {
+            let v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs_delta().unwrap();
+            test::black_box(v)
+        });
+    }
+
+    #[bench]
+    fn split_package_delta(b: &mut test::Bencher) {
+        let p = package();
+
+        b.iter(|| test::black_box(p.split_encoded(SampleEncoding::Delta)));
+    }
+
+    /// Package of noise that does not delta encode: the samples take at most two bytes as
+    /// varints, most of the deltas three.
+    fn noise() -> AxlPacket {
+        let mut p = package();
+        let mut x = 0x1234_5678u32;
+
+        for v in p.data.iter_mut() {
+            x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            *v = (x >> 18) as u16;
+        }
+
+        p
+    }
+
+    #[test]
+    fn deltas() {
+        let orig = [0, u16::MAX, 1, 3, 0, 5, u16::MAX, 10, 2];
+
+        for width in 1..=SAMPLE_SZ {
+            let mut d = orig;
+            delta_encode(&mut d, width);
+            delta_decode(&mut d, width);
+            assert_eq!(d, orig);
+        }
+    }
+
+    #[test]
+    fn varint_deltas() {
+        let orig = [0, u16::MAX, 1, 3, 0x8000, 5, u16::MAX, 10, 0x7fff];
+
+        for d in [0, 1, 0x7fff, 0x8000, u16::MAX] {
+            assert_eq!(unzigzag(zigzag(d)), d);
+        }
+        assert_eq!(zigzag(u16::MAX), 1);
+        assert_eq!(zigzag(1), 2);
+
+        for width in 1..=SAMPLE_SZ {
+            let mut buf = [0u8; 32];
+            let n = write_varint_deltas(&orig, width, &mut buf).unwrap();
+            assert_eq!(read_varint_deltas(&buf[..n], width).unwrap(), orig);
+
+            // Cut short.
+            assert_eq!(read_varint_deltas(&buf[..n - 1], width), None);
+        }
+
+        assert_eq!(write_varint_deltas(&orig, 1, &mut [0u8; 4]), None);
+        assert_eq!(read_varint_deltas(&[0xff, 0xff, 0x7f], 1), None);
+        assert_eq!(read_varint_deltas(&[0xff, 0xff, 0xff, 0x01], 1), None);
+    }
+
+    #[test]
+    fn delta_package() {
+        let mut p = package();
+        let orig = p.clone();
+
+        let raw: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs_delta().unwrap();
+        assert_eq!(p, orig);
+        assert!(v.len() < raw.len());
+
+        let d = AxlPacket::decode(VERSION, &mut v.clone()).unwrap();
+        assert_eq!(d, p);
+
+        let n = cobs::decode_in_place(&mut v).unwrap();
+        assert_eq!(v[0], FORMAT_VERSION | DELTA);
+
+        // The samples are required.
+        assert!(AxlPacket::decode_bytes(VERSION, &v[..n - 1]).is_err());
+
+        let mut short = package();
+        short.axes = 0b101;
+        short.data.truncate(317 * 2);
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = short.to_cobs_delta().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), short);
+    }
+
+    #[test]
+    fn delta_older_version() {
+        let p = package();
+
+        let mut stripped = p.clone();
+        let mut deltas = core::mem::take(&mut stripped.data);
+        delta_encode(&mut deltas, p.width());
+        let deltas: &[i16] = bytemuck::cast_slice(&deltas);
+
+        // The package is read with the layout of the version in the tag.
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> =
+            postcard::to_vec_cobs(&(6u8 | DELTA, &package_v6(&stripped), deltas)).unwrap();
+        assert_eq!(AxlPacket::decode(6, &mut v.clone()).unwrap(), p);
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> =
+            postcard::to_vec_cobs(&(5u8 | DELTA, &package_v6(&stripped), deltas)).unwrap();
+        assert_eq!(
+            AxlPacket::from_cobs(&mut v),
+            Err(DecodeError::UnsupportedVersion(5))
+        );
+    }
+
+    #[test]
+    fn delta_noise_kept_raw() {
+        let mut p = noise();
+
+        let raw: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        let v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs_encoded(SampleEncoding::Delta).unwrap();
+        assert_eq!(v, raw);
+
+        let (meta, b64) = p.split_encoded(SampleEncoding::Delta);
+        assert_eq!(meta.encoding, SampleEncoding::Raw.code());
+        assert_eq!(b64, p.base64());
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+    }
+
+    #[test]
+    fn delta_note() {
+        let p = package();
+        let (raw, _) = p.split();
+        assert_eq!(raw.encoding, 0);
+
+        let (meta, b64) = p.split_encoded(SampleEncoding::Delta);
+        assert_eq!(meta.encoding, SampleEncoding::Delta.code());
+        assert!(meta.length < raw.length);
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // The body without `encoding` is raw.
+        let body = serde_json::to_string(&raw).unwrap();
+        assert!(!body.contains("encoding"));
+
+        let mut meta = meta;
+        meta.encoding = 2;
+        assert_eq!(
+            AxlPacket::from_note(&meta, &b64),
+            Err(DecodeError::Compression(2))
+        );
+
+        // Deltas of two axes do not make whole samples of three.
+        let mut short = package();
+        short.data.truncate(3 * 100 + 2);
+        let (meta, b64) = short.split_encoded(SampleEncoding::Delta);
+        assert_eq!(AxlPacket::from_note(&meta, &b64), Err(DecodeError::Payload));
+    }
+
+    #[bench]
+    fn stats_package(b: &mut test::Bencher) {
+        let p = package();
+
+        b.iter(|| test::black_box(p.stats()));
+    }
+
+    #[test]
+    fn tagged_round_trip() {
+        let p = package();
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        let d = AxlPacket::from_cobs(&mut v).unwrap();
+        assert_eq!(d, p);
+    }
+
+    fn package_v6(p: &AxlPacket) -> AxlPacketV6 {
+        AxlPacketV6 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            data: p.data.clone(),
+        }
+    }
+
+    #[test]
+    fn untagged_v5() {
+        let p = package();
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&package_v6(&p)).unwrap();
+        let d = AxlPacket::decode(5, &mut v).unwrap();
+        assert_eq!(d, p);
+    }
+
+    #[test]
+    fn tagged_v6() {
+        let p = package();
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> =
+            postcard::to_vec_cobs(&(6u8, &package_v6(&p))).unwrap();
+        let d = AxlPacket::decode(6, &mut v).unwrap();
+        assert_eq!(d, p);
+    }
+
+    #[test]
+    fn tagged_v7() {
+        let mut p = package();
+        p.calibration = 3;
+
+        let mut v7 = AxlPacketV7::from(package_v6(&p));
+        v7.calibration = p.calibration;
+
+        // Calibration packages are read back with their step.
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(7u8, &v7)).unwrap();
+        let d = AxlPacket::decode(7, &mut v).unwrap();
+        assert_eq!(d, p);
+
+        p.quality = quality::CLIPPED | quality::GPS_STALE;
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+    }
+
+    #[test]
+    fn tagged_v8() {
+        let mut p = package();
+        p.quality = quality::CLIPPED;
+
+        let mut v8 = AxlPacketV8::from(AxlPacketV7::from(package_v6(&p)));
+        v8.quality = p.quality;
+
+        // No offset was removed before version 9.
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(8u8, &v8)).unwrap();
+        let d = AxlPacket::decode(8, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.bias_mode, 0);
+
+        p.bias_mode = 1;
+        p.bias = [0.1, -0.2, 0.3];
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (meta, _) = p.split();
+        assert_eq!(meta.bias_mode, 1);
+        assert_eq!([meta.bias_x, meta.bias_y, meta.bias_z], p.bias);
+    }
+
+    #[test]
+    fn tagged_v9() {
+        let mut p = package();
+        p.bias_mode = 1;
+        p.bias = [0.1, -0.2, 0.3];
+
+        let mut v9 = AxlPacketV9::from(AxlPacketV8::from(AxlPacketV7::from(package_v6(&p))));
+        v9.bias_mode = p.bias_mode;
+        v9.bias = p.bias;
+
+        // The dilution of precision is unknown before version 10.
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(9u8, &v9)).unwrap();
+        let d = AxlPacket::decode(9, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.dop, 0.);
+
+        p.dop = 1.4;
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (meta, b64) = p.split();
+        assert_eq!(meta.dop, 1.4);
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+    }
+
+    #[test]
+    fn tagged_v10() {
+        let mut p = package();
+        p.dop = 1.4;
+
+        let mut v10 = AxlPacketV10::from(AxlPacketV9::from(AxlPacketV8::from(AxlPacketV7::from(
+            package_v6(&p),
+        ))));
+        v10.dop = p.dop;
+
+        // Samples were always scaled to `ACCEL_MAX` before version 11.
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(10u8, &v10)).unwrap();
+        let d = AxlPacket::decode(10, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.accel_max, ACCEL_MAX);
+
+        // Scale following a range of 8 g.
+        p.accel_max = 4. * ACCEL_MAX;
+        p.data[0] = wire::scale_f32_to_u16(p.accel_max, 30.);
+        assert!((p.accel(p.data[0]) - 30.).abs() < 1.0e-2);
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (meta, b64) = p.split();
+        assert_eq!(meta.accel_max, p.accel_max);
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+    }
+
+    #[test]
+    fn tagged_v11() {
+        let mut p = package();
+
+        // The frame follows from the calibration step before version 12.
+        for calibration in [0, 1] {
+            p.calibration = calibration;
+            p.frame = Frame::of(calibration, false).code();
+
+            let mut v11 = AxlPacketV11::from(AxlPacketV10::from(AxlPacketV9::from(
+                AxlPacketV8::from(AxlPacketV7::from(package_v6(&p))),
+            )));
+            v11.calibration = calibration;
+
+            let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(11u8, &v11)).unwrap();
+            let d = AxlPacket::decode(11, &mut v).unwrap();
+            assert_eq!(d, p);
+        }
+
+        p.calibration = 0;
+        p.frame = Frame::Corrected.code();
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (mut meta, b64) = p.split();
+        assert_eq!(meta.frame, Frame::Corrected.code());
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // Notes from before the frame was recorded.
+        meta.frame = 0;
+        assert_eq!(
+            AxlPacket::from_note(&meta, &b64).unwrap().frame,
+            Frame::Earth.code()
+        );
+    }
+
+    #[test]
+    fn tagged_v12() {
+        let p = package();
+
+        // All axes were stored before version 13.
+        let v12 = AxlPacketV12::from(AxlPacketV11::from(AxlPacketV10::from(AxlPacketV9::from(
+            AxlPacketV8::from(AxlPacketV7::from(package_v6(&p))),
+        ))));
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(12u8, &v12)).unwrap();
+        let d = AxlPacket::decode(12, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.axes, AXES_ALL);
+    }
+
+    #[test]
+    fn tagged_v13() {
+        let mut p = package();
+
+        // The warm-up was not recorded before version 14.
+        let v13 = AxlPacketV13::from(AxlPacketV12::from(AxlPacketV11::from(AxlPacketV10::from(
+            AxlPacketV9::from(AxlPacketV8::from(AxlPacketV7::from(package_v6(&p)))),
+        ))));
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(13u8, &v13)).unwrap();
+        let d = AxlPacket::decode(13, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.warmup, 0);
+
+        p.warmup = 30;
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (mut meta, b64) = p.split();
+        assert_eq!(meta.warmup, 30);
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // Notes from before the warm-up was recorded.
+        meta.warmup = 0;
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap().warmup, 0);
+    }
+
+    #[test]
+    fn tagged_v14() {
+        let mut p = package();
+        p.warmup = 30;
+
+        // The interpolated samples were not recorded before version 15.
+        let mut v14 = AxlPacketV14::from(AxlPacketV13::from(AxlPacketV12::from(
+            AxlPacketV11::from(AxlPacketV10::from(AxlPacketV9::from(AxlPacketV8::from(
+                AxlPacketV7::from(package_v6(&p)),
+            )))),
+        )));
+        v14.warmup = 30;
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(14u8, &v14)).unwrap();
+        let d = AxlPacket::decode(14, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert!(d.filled.is_empty());
+
+        p.filled = Vec::from_slice(&[3, 17, 1023]).unwrap();
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (mut meta, b64) = p.split();
+        assert_eq!(meta.filled, "3,17,1023");
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // Notes from before the interpolated samples were recorded.
+        meta.filled.clear();
+        assert!(AxlPacket::from_note(&meta, &b64).unwrap().filled.is_empty());
+
+        meta.filled = heapless::String::from("3,x");
+        assert_eq!(AxlPacket::from_note(&meta, &b64), Err(DecodeError::Payload));
+    }
+
+    #[test]
+    fn tagged_v15() {
+        let mut p = package();
+        p.warmup = 30;
+        p.filled = Vec::from_slice(&[3, 17]).unwrap();
+
+        // The scale was not recorded before version 16.
+        let mut v15 = AxlPacketV15::from(AxlPacketV14::from(AxlPacketV13::from(
+            AxlPacketV12::from(AxlPacketV11::from(AxlPacketV10::from(AxlPacketV9::from(
+                AxlPacketV8::from(AxlPacketV7::from(package_v6(&p))),
+            )))),
+        )));
+        v15.warmup = 30;
+        v15.filled = p.filled.clone();
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(15u8, &v15)).unwrap();
+        let d = AxlPacket::decode(15, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.scale, [1.; 3]);
+
+        p.bias_mode = crate::waves::BiasRemoval::Calibration.code();
+        p.bias = [0.05, -0.1, 0.2];
+        p.scale = [1.01, 0.99, 1.];
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (meta, b64) = p.split();
+        assert_eq!((meta.scale_x, meta.scale_z), (1.01, 1.));
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // The scale is left out of the note body when not calibrated, and is `1` in notes from
+        // before it was recorded.
+        let json = serde_json::to_string(&meta).unwrap();
+        assert!(json.contains("scale_x") && !json.contains("scale_z"));
+
+        let meta: AxlPacketMeta = serde_json::from_str(&json.replace("scale_x", "_x")).unwrap();
+        assert_eq!(
+            AxlPacket::from_note(&meta, &b64).unwrap().scale,
+            [1., 0.99, 1.]
+        );
+    }
+
+    #[test]
+    fn tagged_v16() {
+        let mut p = package();
+        p.scale = [1.01, 0.99, 1.];
+
+        // The time of the RTC was not recorded before version 17.
+        let mut v16 = AxlPacketV16::from(AxlPacketV15::from(AxlPacketV14::from(
+            AxlPacketV13::from(AxlPacketV12::from(AxlPacketV11::from(AxlPacketV10::from(
+                AxlPacketV9::from(AxlPacketV8::from(AxlPacketV7::from(package_v6(&p)))),
+            )))),
+        )));
+        v16.scale = p.scale;
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(16u8, &v16)).unwrap();
+        let d = AxlPacket::decode(16, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.rtc_timestamp, d.timestamp);
+
+        // Moved forward to keep the timestamps monotonic.
+        p.rtc_timestamp = p.timestamp - 5000;
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (mut meta, b64) = p.split();
+        assert_eq!(meta.rtc_shift, 5000);
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // Left out of the note body when not moved, and in notes from before it was recorded.
+        meta.rtc_shift = 0;
+        let json = serde_json::to_string(&meta).unwrap();
+        assert!(!json.contains("rtc_shift"));
+
+        let meta: AxlPacketMeta = serde_json::from_str(&json).unwrap();
+        let d = AxlPacket::from_note(&meta, &b64).unwrap();
+        assert_eq!(d.rtc_timestamp, d.timestamp);
+    }
+
+    #[test]
+    fn tagged_v17() {
+        let mut p = package();
+        p.rtc_timestamp = p.timestamp - 5000;
+
+        // The sample counts were not recorded before version 18.
+        let v17 = AxlPacketV17 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled.clone(),
+            rtc_timestamp: p.rtc_timestamp,
+            data: p.data.clone(),
+        };
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(17u8, &v17)).unwrap();
+        let d = AxlPacket::decode(17, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!((d.raw_samples, d.output_samples), (0, 0));
+        assert_eq!(d.decimation_ratio(), None);
+        assert!(d.check_decimation(5));
+
+        p.raw_samples = 5120;
+        p.output_samples = 1024;
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (mut meta, b64) = p.split();
+        assert_eq!((meta.raw_samples, meta.output_samples), (5120, 1024));
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // Left out of the note body when not recorded.
+        meta.raw_samples = 0;
+        meta.output_samples = 0;
+        let json = serde_json::to_string(&meta).unwrap();
+        assert!(!json.contains("raw_samples"));
+        assert!(!json.contains("output_samples"));
+    }
+
+    #[test]
+    fn tagged_v18() {
+        let mut p = package();
+        p.raw_samples = 5120;
+        p.output_samples = 1024;
+
+        // The CRC was not recorded before version 19.
+        let v18 = AxlPacketV18 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled.clone(),
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: p.raw_samples,
+            output_samples: p.output_samples,
+            data: p.data.clone(),
+        };
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(18u8, &v18)).unwrap();
+        let d = AxlPacket::decode(18, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.crc, 0);
+        assert!(d.verify());
+
+        let (meta, _) = d.split();
+        assert!(!serde_json::to_string(&meta).unwrap().contains("crc"));
+    }
+
+    #[test]
+    fn tagged_v19() {
+        let mut p = package();
+        p.crc = crc32(&p.data);
+
+        // No gyroscope samples before version 20.
+        let v19 = AxlPacketV19 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled.clone(),
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: p.raw_samples,
+            output_samples: p.output_samples,
+            crc: p.crc,
+            data: p.data.clone(),
+        };
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(19u8, &v19)).unwrap();
+        let d = AxlPacket::decode(19, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert!(d.gyro.is_empty());
+        assert!(d.verify());
+    }
+
+    #[test]
+    fn tagged_v20() {
+        let mut p = package();
+        p.crc = crc32(&p.data);
+
+        // The Notecard was not read before version 21.
+        let v20 = AxlPacketV20 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled.clone(),
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: p.raw_samples,
+            output_samples: p.output_samples,
+            crc: p.crc,
+            data: p.data.clone(),
+            gyro: p.gyro.clone(),
+        };
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(&(20u8, &v20)).unwrap();
+        let d = AxlPacket::decode(20, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.battery_v, None);
+
+        let (meta, _) = d.split();
+        let s = serde_json::to_string(&meta).unwrap();
+        assert!(!s.contains("battery_v"));
+        assert!(!s.contains("temperature_c"));
+    }
+
+    #[test]
+    fn card() {
+        let p = AxlPacketBuilder::new(1_700_000_000_000, 52., ACCEL_MAX, package().data)
+            .card(Some(3.9), Some(-1.5))
+            .build()
+            .unwrap();
+        assert_eq!(p.battery_v, Some(3.9));
+        assert_eq!(p.temperature_c, Some(-1.5));
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::decode(VERSION, &mut v).unwrap(), p);
+
+        let (meta, b64) = p.split();
+        assert!(serde_json::to_string(&meta)
+            .unwrap()
+            .contains(r#""battery_v":3.9,"temperature_c":-1.5"#));
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // No voltage before the battery is charged.
+        let p = AxlPacketBuilder::new(1_700_000_000_000, 52., ACCEL_MAX, package().data)
+            .card(None, Some(20.))
+            .build()
+            .unwrap();
+        let (meta, b64) = p.split();
+        assert_eq!(meta.battery_v, None);
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap().battery_v, None);
+    }
+
+    /// `p` with the layout of version 21, before the clipped samples were counted.
+    fn package_v21(p: &AxlPacket) -> AxlPacketV21 {
+        AxlPacketV21 {
+            timestamp: p.timestamp,
+            offset: p.offset,
+            storage_id: p.storage_id,
+            storage_version: p.storage_version,
+            position_time: p.position_time,
+            lon: p.lon,
+            lat: p.lat,
+            temperature: p.temperature,
+            freq: p.freq,
+            calibration: p.calibration,
+            quality: p.quality,
+            bias_mode: p.bias_mode,
+            bias: p.bias,
+            scale: p.scale,
+            dop: p.dop,
+            accel_max: p.accel_max,
+            frame: p.frame,
+            axes: p.axes,
+            warmup: p.warmup,
+            filled: p.filled.clone(),
+            rtc_timestamp: p.rtc_timestamp,
+            raw_samples: p.raw_samples,
+            output_samples: p.output_samples,
+            crc: p.crc,
+            battery_v: p.battery_v,
+            temperature_c: p.temperature_c,
+            data: p.data.clone(),
+            gyro: p.gyro.clone(),
+        }
+    }
+
+    #[test]
+    fn tagged_v21() {
+        let mut p = package();
+        p.crc = crc32(&p.data);
+        p.battery_v = Some(3.9);
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> =
+            postcard::to_vec_cobs(&(21u8, &package_v21(&p))).unwrap();
+        let d = AxlPacket::decode(21, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert_eq!(d.clipped_samples, 0);
+
+        let (meta, _) = d.split();
+        assert!(!serde_json::to_string(&meta)
+            .unwrap()
+            .contains("clipped_samples"));
+    }
+
+    #[test]
+    fn delta_v21() {
+        let mut p = package();
+        p.crc = crc32(&p.data);
+
+        // A delta package written before version 22 is tagged `21 | DELTA`.
+        let mut stripped = package_v21(&p);
+        let mut deltas = core::mem::take(&mut stripped.data);
+        delta_encode(&mut deltas, p.width());
+        let deltas: &[i16] = bytemuck::cast_slice(&deltas);
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> =
+            postcard::to_vec_cobs(&(21u8 | DELTA, &stripped, deltas)).unwrap();
+        assert_eq!(v[1], 21 | DELTA);
+
+        let d = AxlPacket::decode(21, &mut v).unwrap();
+        assert_eq!(d, p);
+        assert!(d.verify());
+    }
+
+    #[test]
+    fn clipped_samples() {
+        let p = AxlPacketBuilder::new(1_700_000_000_000, 52., ACCEL_MAX, package().data)
+            .clipped_samples(37)
+            .build()
+            .unwrap();
+        assert_eq!(p.clipped_samples, 37);
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::decode(VERSION, &mut v).unwrap(), p);
+
+        let (meta, b64) = p.split();
+        assert!(serde_json::to_string(&meta)
+            .unwrap()
+            .contains(r#""clipped_samples":37"#));
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+    }
+
+    #[test]
+    fn gyro() {
+        let mut p = package();
+        let samples = p.samples();
+
+        let b = |gyro: Vec<u16, { GYRO_SZ }>| {
+            AxlPacketBuilder::new(p.timestamp, p.freq, p.accel_max, p.data.clone())
+                .gyro(gyro)
+                .build()
+        };
+
+        assert!(b(Vec::new()).is_ok());
+
+        let gyro: Vec<u16, { GYRO_SZ }> = (0..samples * SAMPLE_SZ).map(|v| v as u16).collect();
+        assert_eq!(b(gyro.clone()).unwrap().gyro, gyro);
+        assert_eq!(
+            b(gyro.iter().skip(3).copied().collect()).err(),
+            Some(PacketError::Gyro(gyro.len() - 3))
+        );
+
+        // Only the acceleration is sent.
+        p.gyro = gyro;
+        let (meta, b64) = p.split();
+        let mut d = AxlPacket::from_note(&meta, &b64).unwrap();
+        assert!(d.gyro.is_empty());
+        d.gyro = p.gyro.clone();
+        assert_eq!(d, p);
+
+        assert!(p.gyro_rate(u16::MAX / 2).abs() < 1e-3);
+        assert_eq!(p.gyro_rate(u16::MAX), wire::GYRO_MAX);
+    }
+
+    #[test]
+    fn crc() {
+        // Check value of CRC-32 (IEEE), of "123456789".
+        let d = [0x3231, 0x3433, 0x3635, 0x3837];
+        assert_eq!(crc32(&d), 0x9ae0daaf);
+        assert_eq!(crc32(&[]), 0);
+
+        let mut p = package();
+        p.crc = crc32(&p.data);
+        assert_ne!(p.crc, 0);
+        assert!(p.verify());
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        let mut d = AxlPacket::from_cobs(&mut v).unwrap();
+        assert_eq!(d, p);
+        assert!(d.verify());
+
+        let (meta, b64) = d.split();
+        assert_eq!(meta.crc, p.crc);
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // A flipped bit in the samples.
+        d.data[7] ^= 0x10;
+        assert!(!d.verify());
+    }
+
+    #[test]
+    fn decimation_check() {
+        let mut p = package();
+        p.raw_samples = 5120;
+        p.output_samples = 1024;
+        assert_eq!(p.decimation_ratio(), Some(5.));
+        assert!(p.check_decimation(5));
+        assert!(!p.check_decimation(4));
+
+        // Part way into a decimation period at both ends.
+        p.raw_samples = 5124;
+        assert!(p.check_decimation(5));
+        p.raw_samples = 5116;
+        assert!(p.check_decimation(5));
+
+        // A FIFO under-read: samples missing from the filters.
+        p.raw_samples = 5000;
+        assert!(!p.check_decimation(5));
+
+        // Calibration packages are not decimated.
+        p.raw_samples = 1024;
+        assert!(p.check_decimation(1));
+        p.raw_samples = 1025;
+        assert!(!p.check_decimation(1));
+    }
+
+    #[test]
+    fn decimation_from_freq() {
+        let mut p = package();
+
+        for (freq, d) in [(208., 1), (52., 4), (26., 8), (13., 16)] {
+            p.freq = freq;
+            assert_eq!(p.decimation(), d);
+            assert_eq!(p.split().0.decimation, d);
+        }
+    }
+
+    #[test]
+    fn vertical_only() {
+        let mut p = package();
+        p.axes = 0b100;
+        p.data = (0..SAMPLE_NO)
+            .map(|i| A16::from_f32(if i % 2 == 0 { 1.0 } else { -1.0 }).to_u16())
+            .collect();
+
+        assert_eq!((p.width(), p.samples()), (1, SAMPLE_NO));
+        assert_eq!(
+            (p.column(0), p.column(1), p.column(2)),
+            (None, None, Some(0))
+        );
+
+        let s = p.stats();
+        assert_eq!(s.samples, SAMPLE_NO as u32);
+        assert_eq!((s.x_std, s.y_std), (0., 0.));
+        assert!((s.z_std - 1.0).abs() < 1.0e-3);
+        assert!((s.z_max - 1.0).abs() < 1.0e-3);
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut v).unwrap(), p);
+
+        let (mut meta, b64) = p.split();
+        assert_eq!(meta.axes, 0b100);
+        assert_eq!(AxlPacket::from_note(&meta, &b64).unwrap(), p);
+
+        // Horizontal axes only.
+        p.axes = 0b011;
+        assert_eq!((p.width(), p.samples()), (2, SAMPLE_NO / 2));
+        assert_eq!(
+            (p.column(0), p.column(1), p.column(2)),
+            (Some(0), Some(1), None)
+        );
+
+        // The payload is not made up of whole samples of all axes.
+        meta.axes = AXES_ALL;
+        assert_eq!(AxlPacket::from_note(&meta, &b64), Err(DecodeError::Payload));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let p = package();
+
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> =
+            postcard::to_vec_cobs(&(FORMAT_VERSION + 1, &p)).unwrap();
+        assert_eq!(
+            AxlPacket::from_cobs(&mut v),
+            Err(DecodeError::UnsupportedVersion(VERSION + 1))
+        );
 
-        // This does not include the additional size used by COBS.
-        // assert!(AXL_POSTCARD_SZ >= AxlPacket::POSTCARD_MAX_SIZE);
+        let mut v: Vec<u8, { AXL_POSTCARD_SZ }> = p.to_cobs().unwrap();
+        assert_eq!(
+            AxlPacket::decode(VERSION + 1, &mut v),
+            Err(DecodeError::UnsupportedVersion(VERSION + 1))
+        );
     }
 }
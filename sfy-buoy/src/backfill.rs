@@ -0,0 +1,881 @@
+//! Compressed batches of stored packages for backfilling a gap.
+//!
+//! When a range of stored packages is requested (see `StorageManager::queue_requested_packages`)
+//! the packages are read from the SD-card in order and packed into a [`Batch`], which is sent as
+//! a single note to `Notefiles::backfill`, separate from the live packages in `Notefiles::axl`.
+//! A multi-hour gap is recovered in a few notes, rather than one note per package.
+//!
+//! The batch starts with `FORMAT_VERSION`, followed by one entry per package: the postcard
+//! serialized package without its samples, and the samples as the difference from the previous
+//! sample of the same axis (wrapping). The differences are small for the slowly varying
+//! acceleration, and postcard writes them as zigzag varints: mostly one or two bytes, rather than
+//! two bytes for every sample. The entries are self-delimiting, so they are simply concatenated.
+//! The note carries the batch in base64 in the payload, and the range of storage IDs in the body
+//! (see [`BatchMeta`]). `sfypack decode-note --backfill` reassembles the packages, the packages
+//! of a batch of an older version are read with the layout of that version.
+//!
+//! The encoding of the entries is selected by `backfill_compression` in the config (see
+//! [`Compression`]), and is given by `compression` in the body of the note, so that `sfypack`
+//! decodes batches of every buoy. Notes from before the selection have no `compression`, and are
+//! `delta`. The options trade CPU time on the buoy for the size of the notes:
+//!
+//! * `none` writes the samples as they are, two bytes each. Cheapest to encode, but a package
+//!   with all three axes takes more than half a batch.
+//! * `delta` (default) writes the differences as varints, see above. A single pass over the
+//!   samples, and one or two bytes per sample rather than two.
+//! * `lz` compresses each `delta` entry with a small LZ77 compressor (see `lz`), and keeps the
+//!   entry as it is when it does not compress. Repeated runs of differences (e.g. calm seas) are
+//!   removed, at several times the CPU time of `delta`, and 4 kB of stack for the hash table and
+//!   a package worth of stack for the entry while encoding.
+//!
+//! The `ratios` test prints the size of every option for the packages of `tests/data/44.5`, and
+//! the `bench_*` benchmarks the time to encode and decode a batch (`cargo bench backfill`).
+//!
+//! The live packages can be batched the same way, to save the overhead of a note per package on
+//! cellular (see [`LiveBatch`], `live_batch` in the config): the packages that would be sent as
+//! data notes are added to a batch that is sent to `Notefiles::backfill` when it holds `packages`
+//! packages, when it is full, when its first package is `timeout` seconds old, and when the
+//! notecard is shut down (also before a reset), so that no package is left behind on the buoy.
+//!
+//! > Packages are not batched with the `encryption` feature, the requested packages are replayed
+//! > one by one as sealed data notes, and the live packages are sent one by one.
+
+use heapless::Vec;
+
+use crate::axl::{
+    delta_decode, delta_encode, AxlPacket, DecodeError, AXL_SZ, FORMAT_VERSION,
+    LAST_UNTAGGED_VERSION, POSTCARD_MAX_SZ,
+};
+use crate::lz;
+
+/// Capacity of a batch, the size is limited further by `max_note_size` in the config (see
+/// [`Batch::with_limit`]).
+pub const BATCH_SZ: usize = 12 * 1024;
+
+/// Largest `max_note_size`: the base64 payload of a full batch [bytes].
+pub const MAX_NOTE_SIZE: u32 = (BATCH_SZ / 3 * 4) as u32;
+
+/// Smallest `max_note_size`: even noise that does not compress (three bytes per sample) fits in
+/// an empty batch.
+pub const MIN_NOTE_SIZE: u32 = ((1 + LZ_HEADER + POSTCARD_MAX_SZ + 2) / 3 * 4) as u32;
+
+/// Size of a batch with a base64 payload of at most `max_note_size` bytes.
+pub const fn batch_size(max_note_size: u32) -> usize {
+    let sz = max_note_size as usize / 4 * 3;
+
+    if sz < BATCH_SZ {
+        sz
+    } else {
+        BATCH_SZ
+    }
+}
+
+/// Maximum size of the base64 payload of a batch.
+pub const BATCH_OUTN: usize = BATCH_SZ * 4 / 3 + 4;
+
+/// Size of the header of an `lz` entry: the length of the `delta` entry and of the compressed
+/// entry (`u16`, little endian). The length of the compressed entry is 0 when the entry is kept
+/// as it is.
+const LZ_HEADER: usize = 4;
+
+/// Encoding of the entries of a batch.
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// The samples as they are, two bytes each.
+    None,
+
+    /// The samples as the difference from the previous sample of the same axis, as varints.
+    #[default]
+    Delta,
+
+    /// The `delta` entries, each compressed with `lz`.
+    Lz,
+}
+
+impl Compression {
+    /// Code of the compression in the body of the note.
+    pub fn code(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Delta => 1,
+            Compression::Lz => 2,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Compression> {
+        match code {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Delta),
+            2 => Some(Compression::Lz),
+            _ => None,
+        }
+    }
+}
+
+fn delta_code() -> u8 {
+    Compression::Delta.code()
+}
+
+/// Maximum `packages` of a [`LiveBatch`].
+pub const MAX_LIVE_PACKAGES: u8 = 32;
+
+/// Maximum `timeout` of a [`LiveBatch`] [s], one hour.
+pub const MAX_LIVE_TIMEOUT: u32 = 60 * 60;
+
+/// Batching of the live packages. Fields that are not set in an override take the default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct LiveBatch {
+    /// Packages per batch, `1` sends every package as a data note of its own.
+    pub packages: u8,
+
+    /// A batch is sent when its first package is this old, even if not full [s].
+    pub timeout: u32,
+}
+
+impl Default for LiveBatch {
+    fn default() -> LiveBatch {
+        LiveBatch {
+            packages: 1,
+            timeout: 10 * 60,
+        }
+    }
+}
+
+impl LiveBatch {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_LIVE_PACKAGES).contains(&self.packages)
+            && (1..=MAX_LIVE_TIMEOUT).contains(&self.timeout)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.packages > 1
+    }
+
+    /// The `batch` should be sent at `now` [ms]: it holds `packages` packages, it is full (see
+    /// [`Batch::is_full`]), or its first package is older than `timeout`.
+    pub fn is_due(&self, batch: &Batch, now: i64) -> bool {
+        !batch.is_empty()
+            && (batch.packages >= self.packages as u32
+                || batch.is_full()
+                || now - batch.timestamp >= self.timeout as i64 * 1000)
+    }
+}
+
+/// Body of a backfill note.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, PartialEq)]
+pub struct BatchMeta {
+    /// Storage ID of the first and the last package.
+    pub first: u32,
+    pub last: u32,
+
+    /// Number of packages.
+    pub packages: u32,
+
+    /// Timestamp of the first package [ms].
+    pub timestamp: i64,
+
+    /// Length of the base64 payload.
+    pub length: u32,
+
+    /// Format version of the packages.
+    pub version: u8,
+
+    /// Encoding of the entries (see [`Compression::code`]), `delta` when missing.
+    #[serde(default = "delta_code")]
+    pub compression: u8,
+}
+
+/// The batch is full, the package is left for the next batch.
+#[derive(defmt::Format, Debug, PartialEq)]
+pub struct BatchFull;
+
+/// Stored packages, compressed.
+pub struct Batch {
+    buf: Vec<u8, BATCH_SZ>,
+
+    /// Maximum size of the batch, see [`batch_size`].
+    limit: usize,
+
+    /// Storage ID of the first and the last package.
+    pub first: Option<u32>,
+    pub last: Option<u32>,
+
+    /// Number of packages.
+    pub packages: u32,
+
+    /// Timestamp of the first package [ms].
+    pub timestamp: i64,
+
+    /// Encoding of the entries.
+    pub compression: Compression,
+}
+
+impl Default for Batch {
+    fn default() -> Batch {
+        Batch::new()
+    }
+}
+
+/// Write the package with the samples as deltas into `buf`, returns the size of the entry.
+fn encode_deltas(pck: &mut AxlPacket, buf: &mut [u8]) -> Result<usize, BatchFull> {
+    let width = pck.width().max(1);
+    let mut data = core::mem::take(&mut pck.data);
+    delta_encode(&mut data, width);
+
+    let deltas: &[i16] = bytemuck::cast_slice(&data);
+
+    postcard::to_slice(&(&*pck, deltas), buf)
+        .map(|b| b.len())
+        .map_err(|_| BatchFull)
+}
+
+/// Write the entry of the package into `buf`, returns the size of the entry.
+fn encode(
+    compression: Compression,
+    pck: &mut AxlPacket,
+    buf: &mut [u8],
+) -> Result<usize, BatchFull> {
+    match compression {
+        Compression::None => {
+            let mut data = core::mem::take(&mut pck.data);
+            data.iter_mut().for_each(|v| *v = v.to_le());
+            let bytes: &[u8] = bytemuck::cast_slice(&data);
+
+            postcard::to_slice(&(&*pck, bytes), buf)
+                .map(|b| b.len())
+                .map_err(|_| BatchFull)
+        }
+        Compression::Delta => encode_deltas(pck, buf),
+        Compression::Lz => {
+            // Every package fits in the entry, the batch is checked by the compression.
+            let mut entry = [0u8; POSTCARD_MAX_SZ];
+            let raw = encode_deltas(pck, &mut entry)?;
+
+            if buf.len() < LZ_HEADER {
+                return Err(BatchFull);
+            }
+
+            let sz = match lz::compress(&entry[..raw], &mut buf[LZ_HEADER..]) {
+                Ok(sz) if sz < raw => sz,
+                _ => {
+                    buf.get_mut(LZ_HEADER..LZ_HEADER + raw)
+                        .ok_or(BatchFull)?
+                        .copy_from_slice(&entry[..raw]);
+                    0
+                }
+            };
+
+            buf[..2].copy_from_slice(&(raw as u16).to_le_bytes());
+            buf[2..LZ_HEADER].copy_from_slice(&(sz as u16).to_le_bytes());
+
+            Ok(LZ_HEADER + if sz == 0 { raw } else { sz })
+        }
+    }
+}
+
+/// Read the entry of a package of format `version` with the samples as deltas from `buf`,
+/// returns the package and the rest of `buf`.
+fn decode_deltas(version: u32, buf: &[u8]) -> Result<(AxlPacket, &[u8]), DecodeError> {
+    let (mut pck, rest) = AxlPacket::take_bytes(version, buf)?;
+    let (deltas, rest) =
+        postcard::take_from_bytes::<Vec<i16, AXL_SZ>>(rest).map_err(DecodeError::Postcard)?;
+
+    let width = pck.width().max(1);
+    pck.data = deltas.iter().map(|d| *d as u16).collect();
+    delta_decode(&mut pck.data, width);
+
+    Ok((pck, rest))
+}
+
+/// Read the entry of a package of format `version` from `buf`, returns the package and the rest
+/// of `buf`. The package has the layout of its version, followed by the samples.
+fn decode(
+    version: u32,
+    compression: Compression,
+    buf: &[u8],
+) -> Result<(AxlPacket, &[u8]), DecodeError> {
+    match compression {
+        Compression::None => {
+            let (mut pck, rest) = AxlPacket::take_bytes(version, buf)?;
+            let (bytes, rest) = postcard::take_from_bytes::<Vec<u8, { AXL_SZ * 2 }>>(rest)
+                .map_err(DecodeError::Postcard)?;
+
+            if bytes.len() % 2 != 0 {
+                return Err(DecodeError::Postcard(
+                    postcard::Error::DeserializeBadEncoding,
+                ));
+            }
+
+            pck.data = bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect();
+
+            Ok((pck, rest))
+        }
+        Compression::Delta => decode_deltas(version, buf),
+        Compression::Lz => {
+            let header = buf.get(..LZ_HEADER).ok_or(DecodeError::Payload)?;
+            let raw = u16::from_le_bytes([header[0], header[1]]) as usize;
+            let sz = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+            let len = if sz == 0 { raw } else { sz };
+            let block = buf
+                .get(LZ_HEADER..LZ_HEADER + len)
+                .ok_or(DecodeError::Payload)?;
+
+            let mut entry = [0u8; POSTCARD_MAX_SZ];
+            let entry = if sz == 0 {
+                block
+            } else if raw <= entry.len() && lz::decompress(block, &mut entry[..raw]) == Ok(raw) {
+                &entry[..raw]
+            } else {
+                return Err(DecodeError::Payload);
+            };
+
+            match decode_deltas(version, entry)? {
+                (pck, []) => Ok((pck, &buf[LZ_HEADER + len..])),
+                _ => Err(DecodeError::Payload),
+            }
+        }
+    }
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch::with_compression(Compression::default())
+    }
+
+    pub fn with_compression(compression: Compression) -> Batch {
+        Batch::with_limit(compression, MAX_NOTE_SIZE)
+    }
+
+    /// A batch that is sent as a note with a base64 payload of at most `max_note_size` bytes
+    /// (see `max_note_size` in the config), between [`MIN_NOTE_SIZE`] and [`MAX_NOTE_SIZE`].
+    pub fn with_limit(compression: Compression, max_note_size: u32) -> Batch {
+        let mut buf = Vec::new();
+        buf.push(FORMAT_VERSION).unwrap();
+
+        Batch {
+            buf,
+            limit: batch_size(max_note_size.max(MIN_NOTE_SIZE)),
+            first: None,
+            last: None,
+            packages: 0,
+            timestamp: 0,
+            compression,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages == 0
+    }
+
+    /// Size of the batch in bytes.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Maximum size of the batch in bytes.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The next package is not expected to fit: the space left is less than the average size of
+    /// the packages in the batch. The batch is closed rather than failing to add the package.
+    pub fn is_full(&self) -> bool {
+        self.packages > 0
+            && self.limit.saturating_sub(self.buf.len())
+                < (self.buf.len() - 1) / self.packages as usize
+    }
+
+    /// Add a package to the batch. The gyroscope samples are left out, as in the data notes (see
+    /// `AxlPacket::gyro`).
+    pub fn push(&mut self, mut pck: AxlPacket) -> Result<(), BatchFull> {
+        pck.gyro.clear();
+
+        let n = self.buf.len();
+        self.buf.resize_default(self.limit).unwrap();
+
+        match encode(self.compression, &mut pck, &mut self.buf[n..]) {
+            Ok(sz) => {
+                self.buf.truncate(n + sz);
+
+                if self.packages == 0 {
+                    self.first = pck.storage_id;
+                    self.timestamp = pck.timestamp;
+                }
+                self.last = pck.storage_id;
+                self.packages += 1;
+
+                Ok(())
+            }
+            Err(_) => {
+                self.buf.truncate(n);
+                Err(BatchFull)
+            }
+        }
+    }
+
+    /// Packages of the batch, in the order they were added.
+    pub fn packages(&self) -> Packages<'_> {
+        Packages {
+            buf: &self.buf[1..],
+            version: self.buf[0] as u32,
+            compression: self.compression,
+        }
+    }
+
+    /// Split batch into the body and the base64 payload of a backfill note.
+    pub fn split(&self) -> (BatchMeta, Vec<u8, BATCH_OUTN>) {
+        let mut b64: Vec<_, BATCH_OUTN> = Vec::new();
+        b64.resize_default(BATCH_OUTN).unwrap();
+        let written = base64::encode_config_slice(&self.buf, base64::STANDARD, &mut b64);
+        b64.truncate(written);
+
+        let meta = BatchMeta {
+            first: self.first.unwrap_or(0),
+            last: self.last.unwrap_or(0),
+            packages: self.packages,
+            timestamp: self.timestamp,
+            length: b64.len() as u32,
+            version: FORMAT_VERSION,
+            compression: self.compression.code(),
+        };
+
+        (meta, b64)
+    }
+
+    /// Reassemble batch from the body and the base64 payload of a backfill note, the inverse of
+    /// [`Batch::split`].
+    pub fn from_note(meta: &BatchMeta, payload: &[u8]) -> Result<Batch, DecodeError> {
+        if payload.len() != meta.length as usize {
+            return Err(DecodeError::Payload);
+        }
+
+        let compression = Compression::from_code(meta.compression)
+            .ok_or(DecodeError::Compression(meta.compression))?;
+
+        let mut buf = [0u8; BATCH_SZ + 3];
+        if (payload.len() + 3) / 4 * 3 > buf.len() {
+            return Err(DecodeError::Payload);
+        }
+
+        let n = base64::decode_config_slice(payload, base64::STANDARD, &mut buf)
+            .map_err(|_| DecodeError::Payload)?;
+
+        // Batches of older versions are read with the layout of their version.
+        match buf[..n].first() {
+            Some(&v) if v as u32 > LAST_UNTAGGED_VERSION && v <= FORMAT_VERSION => (),
+            Some(&v) => return Err(DecodeError::UnsupportedVersion(v as u32)),
+            None => return Err(DecodeError::Empty),
+        }
+
+        Ok(Batch {
+            buf: Vec::from_slice(&buf[..n]).map_err(|_| DecodeError::Payload)?,
+            limit: BATCH_SZ,
+            first: Some(meta.first),
+            last: Some(meta.last),
+            packages: meta.packages,
+            timestamp: meta.timestamp,
+            compression,
+        })
+    }
+}
+
+/// Iterator over the packages of a [`Batch`].
+pub struct Packages<'a> {
+    buf: &'a [u8],
+    version: u32,
+    compression: Compression,
+}
+
+impl<'a> Iterator for Packages<'a> {
+    type Item = Result<AxlPacket, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+
+        match decode(self.version, self.compression, self.buf) {
+            Ok((pck, rest)) => {
+                self.buf = rest;
+                Some(Ok(pck))
+            }
+            Err(e) => {
+                self.buf = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axl::{AxlPacketBuilder, AXES_ALL, VERSION};
+    use crate::waves::wire::ACCEL_MAX;
+
+    fn package(id: u32, axes: u8) -> AxlPacket {
+        let n = crate::axl::axes_width(axes) * 1024;
+        let data = (0..n).map(|v| (v / 3 + id as usize) as u16).collect();
+
+        AxlPacketBuilder::new(id as i64 * 1000, 52.0, ACCEL_MAX, data)
+            .storage(Some(id), VERSION)
+            .position(0, 5.3, 60.4, 0.)
+            .axes(axes)
+            .build()
+            .unwrap()
+    }
+
+    const COMPRESSIONS: [Compression; 3] = [Compression::None, Compression::Delta, Compression::Lz];
+
+    /// Stored packages of a deployment.
+    fn stored() -> std::vec::Vec<AxlPacket> {
+        let mut buf = std::fs::read("tests/data/44.5").unwrap();
+        buf.chunks_mut(crate::axl::AXL_POSTCARD_SZ)
+            .filter_map(|c| AxlPacket::from_cobs(c).ok())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for c in COMPRESSIONS {
+            let mut b = Batch::with_compression(c);
+            assert!(b.is_empty());
+            assert_eq!(b.packages().count(), 0);
+
+            let pcks = [(10, AXES_ALL), (11, 0b100), (12, 0b001)];
+
+            for (id, axes) in pcks {
+                b.push(package(id, axes)).unwrap();
+            }
+
+            assert_eq!((b.first, b.last, b.packages), (Some(10), Some(12), 3));
+            assert_eq!(b.timestamp, 10_000);
+
+            for (d, (id, axes)) in b.packages().zip(pcks) {
+                assert_eq!(d.unwrap(), package(id, axes));
+            }
+            assert_eq!(b.packages().count(), pcks.len());
+        }
+    }
+
+    #[test]
+    fn codes() {
+        for c in COMPRESSIONS {
+            assert_eq!(Compression::from_code(c.code()), Some(c));
+        }
+        assert_eq!(Compression::from_code(3), None);
+
+        let c: Compression = serde_json_core::from_str(r#""lz""#).unwrap().0;
+        assert_eq!(c, Compression::Lz);
+    }
+
+    #[test]
+    fn note() {
+        for c in COMPRESSIONS {
+            let mut b = Batch::with_compression(c);
+            b.push(package(3, 0b011)).unwrap();
+            b.push(package(4, 0b011)).unwrap();
+
+            let (meta, b64) = b.split();
+            assert_eq!((meta.first, meta.last, meta.packages), (3, 4, 2));
+            assert_eq!((meta.version, meta.compression), (FORMAT_VERSION, c.code()));
+
+            let d = Batch::from_note(&meta, &b64).unwrap();
+            assert_eq!(d.compression, c);
+            assert!(d
+                .packages()
+                .zip(b.packages())
+                .all(|(a, b)| a.unwrap() == b.unwrap()));
+        }
+
+        let mut b = Batch::new();
+        b.push(package(3, AXES_ALL)).unwrap();
+        let (meta, b64) = b.split();
+
+        let mut short = meta.clone();
+        short.length -= 1;
+        assert_eq!(
+            Batch::from_note(&short, &b64[..b64.len() - 1]).err(),
+            Some(DecodeError::Payload)
+        );
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut b = Batch::new();
+        b.push(package(3, AXES_ALL)).unwrap();
+        b.buf[0] = FORMAT_VERSION + 1;
+
+        let (meta, b64) = b.split();
+        assert_eq!(
+            Batch::from_note(&meta, &b64).err(),
+            Some(DecodeError::UnsupportedVersion(FORMAT_VERSION as u32 + 1))
+        );
+    }
+
+    #[test]
+    fn older_version() {
+        let p = package(3, AXES_ALL);
+
+        let mut deltas = p.data.clone();
+        delta_encode(&mut deltas, 3);
+        let deltas: &[i16] = bytemuck::cast_slice(&deltas);
+
+        // An entry of version 6: the package without the samples, followed by the deltas.
+        let v6 = (
+            p.timestamp,
+            p.offset,
+            p.storage_id,
+            p.storage_version,
+            p.position_time,
+            p.lon,
+            p.lat,
+            p.temperature,
+            p.freq,
+            Vec::<u16, 0>::new(),
+        );
+
+        let mut b = Batch::new();
+        b.buf[0] = 6;
+        b.buf.resize_default(BATCH_SZ).unwrap();
+        let n = postcard::to_slice(&(v6, deltas), &mut b.buf[1..])
+            .unwrap()
+            .len();
+        b.buf.truncate(1 + n);
+        b.packages = 1;
+
+        let (meta, b64) = b.split();
+        let d = Batch::from_note(&meta, &b64).unwrap();
+        assert_eq!(d.packages().collect::<std::vec::Vec<_>>(), [Ok(p)]);
+
+        b.buf[0] = LAST_UNTAGGED_VERSION as u8;
+        let (meta, b64) = b.split();
+        assert_eq!(
+            Batch::from_note(&meta, &b64).err(),
+            Some(DecodeError::UnsupportedVersion(LAST_UNTAGGED_VERSION))
+        );
+    }
+
+    #[test]
+    fn unknown_compression() {
+        let mut b = Batch::new();
+        b.push(package(3, AXES_ALL)).unwrap();
+
+        let (mut meta, b64) = b.split();
+        meta.compression = 7;
+        assert_eq!(
+            Batch::from_note(&meta, &b64).err(),
+            Some(DecodeError::Compression(7))
+        );
+        assert_eq!(
+            DecodeError::Compression(7).to_string(),
+            "unknown compression of batch: 7, upgrade sfypack"
+        );
+
+        // Notes from before the compression was selectable are delta encoded.
+        let meta: BatchMeta = serde_json_core::from_str(
+            r#"{"first":3,"last":3,"packages":1,"timestamp":3000,"length":0,"version":6}"#,
+        )
+        .unwrap()
+        .0;
+        assert_eq!(meta.compression, Compression::Delta.code());
+    }
+
+    #[test]
+    fn corrupt_lz() {
+        let mut b = Batch::with_compression(Compression::Lz);
+        b.push(package(3, AXES_ALL)).unwrap();
+
+        let n = b.buf.len();
+        b.buf.truncate(n - 1);
+        assert_eq!(
+            b.packages().collect::<std::vec::Vec<_>>(),
+            [Err(DecodeError::Payload)]
+        );
+    }
+
+    #[test]
+    fn ratios() {
+        let pcks = stored();
+        assert!(!pcks.is_empty());
+
+        let mut sizes = [0; 3];
+
+        for (c, sz) in COMPRESSIONS.iter().zip(sizes.iter_mut()) {
+            let mut b = Batch::with_compression(*c);
+
+            for p in &pcks {
+                if b.push(p.clone()).is_err() {
+                    *sz += b.len();
+                    b = Batch::with_compression(*c);
+                    b.push(p.clone()).unwrap();
+                }
+
+                assert_eq!(b.packages().last().unwrap().unwrap(), *p);
+            }
+            *sz += b.len();
+
+            println!(
+                "{:?}: {} packages: {} bytes ({} bytes per package)",
+                c,
+                pcks.len(),
+                sz,
+                *sz / pcks.len()
+            );
+        }
+
+        // The entries that do not compress with `lz` are kept as they are.
+        assert!(sizes[1] < sizes[0]);
+        assert!(sizes[2] <= sizes[1] + LZ_HEADER * pcks.len());
+    }
+
+    fn bench_push(b: &mut test::Bencher, c: Compression) {
+        let p = stored().remove(0);
+
+        b.iter(|| {
+            let mut batch = Batch::with_compression(c);
+            batch.push(p.clone()).unwrap();
+            test::black_box(batch.len())
+        });
+    }
+
+    fn bench_decode(b: &mut test::Bencher, c: Compression) {
+        let mut batch = Batch::with_compression(c);
+        batch.push(stored().remove(0)).unwrap();
+
+        b.iter(|| test::black_box(batch.packages().count()));
+    }
+
+    #[bench]
+    fn bench_push_none(b: &mut test::Bencher) {
+        bench_push(b, Compression::None)
+    }
+
+    #[bench]
+    fn bench_push_delta(b: &mut test::Bencher) {
+        bench_push(b, Compression::Delta)
+    }
+
+    #[bench]
+    fn bench_push_lz(b: &mut test::Bencher) {
+        bench_push(b, Compression::Lz)
+    }
+
+    #[bench]
+    fn bench_decode_none(b: &mut test::Bencher) {
+        bench_decode(b, Compression::None)
+    }
+
+    #[bench]
+    fn bench_decode_delta(b: &mut test::Bencher) {
+        bench_decode(b, Compression::Delta)
+    }
+
+    #[bench]
+    fn bench_decode_lz(b: &mut test::Bencher) {
+        bench_decode(b, Compression::Lz)
+    }
+
+    #[test]
+    fn full() {
+        // Samples that do not compress.
+        let noise = || {
+            let mut p = package(1, AXES_ALL);
+            let mut x = 0x1234_5678u32;
+            for v in p.data.iter_mut() {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                *v = x as u16;
+            }
+            p
+        };
+
+        for c in COMPRESSIONS {
+            let mut b = Batch::with_compression(c);
+            b.push(noise()).unwrap();
+            assert!(b.len() < BATCH_SZ);
+
+            let n = b.len();
+            assert_eq!(b.push(noise()), Err(BatchFull));
+            assert_eq!((b.len(), b.packages), (n, 1));
+            assert_eq!(b.packages().next().unwrap().unwrap(), noise());
+        }
+
+        // Smooth samples take less space than the uncompressed package.
+        for c in [Compression::Delta, Compression::Lz] {
+            let mut b = Batch::with_compression(c);
+            let mut id = 1;
+            while b.push(package(id, AXES_ALL)).is_ok() {
+                id += 1;
+            }
+
+            assert!(b.packages as usize > BATCH_SZ / (AXL_SZ * 2));
+            assert_eq!(b.packages().count(), b.packages as usize);
+        }
+    }
+
+    #[test]
+    fn max_note_size() {
+        assert_eq!(batch_size(MAX_NOTE_SIZE), BATCH_SZ);
+        assert_eq!(batch_size(u32::MAX), BATCH_SZ);
+        assert!(batch_size(MIN_NOTE_SIZE) >= 1 + LZ_HEADER + POSTCARD_MAX_SZ);
+
+        for c in COMPRESSIONS {
+            let mut b = Batch::with_limit(c, MIN_NOTE_SIZE);
+            assert_eq!(b.limit(), batch_size(MIN_NOTE_SIZE));
+
+            let mut id = 1;
+            while !b.is_full() {
+                b.push(package(id, AXES_ALL)).unwrap();
+                id += 1;
+            }
+
+            // The batch is closed before the next package fails, and fits the note.
+            let (meta, _) = b.split();
+            assert!(meta.length <= MIN_NOTE_SIZE);
+            let live = LiveBatch {
+                packages: MAX_LIVE_PACKAGES,
+                timeout: MAX_LIVE_TIMEOUT,
+            };
+            assert!(live.is_due(&b, b.timestamp));
+        }
+
+        let b = Batch::with_limit(Compression::Delta, 0);
+        assert_eq!(b.limit(), batch_size(MIN_NOTE_SIZE));
+    }
+    #[test]
+    fn live_due() {
+        let live = LiveBatch {
+            packages: 3,
+            timeout: 60,
+        };
+        assert!(live.is_valid() && live.is_enabled());
+        assert!(!LiveBatch::default().is_enabled());
+
+        let mut b = Batch::new();
+        assert!(!live.is_due(&b, i64::MAX));
+
+        b.push(package(10, AXES_ALL)).unwrap();
+        b.push(package(11, AXES_ALL)).unwrap();
+        assert!(!live.is_due(&b, 11_000));
+        assert!(live.is_due(&b, 10_000 + 60_000));
+
+        b.push(package(12, AXES_ALL)).unwrap();
+        assert!(live.is_due(&b, 12_000));
+
+        let invalid = LiveBatch {
+            packages: MAX_LIVE_PACKAGES + 1,
+            ..Default::default()
+        };
+        assert!(!invalid.is_valid());
+    }
+}
@@ -0,0 +1,310 @@
+//! Beacon (beeper or bright LED) for finding the buoy at recovery.
+//!
+//! With the `beacon` feature the firmware drives an output pin from the main loop with the on/off
+//! pattern of `beacon` in the config. The beacon is activated:
+//!
+//! * for `startup` seconds when the main loop starts, to check the beacon before deployment,
+//! * by the `beacon` command (see `cmd`), for `value` minutes (`0` stops the beacon),
+//! * when the position jumps by more than `jump` meters between two accepted fixes, which is not
+//!   expected of a moored buoy (it has broken loose, or been picked up).
+//!
+//! Every activation is logged to notehub with the reason. The beacon is stopped after at most
+//! `max_duration` minutes regardless of how it was activated, so that a forgotten command or a
+//! noisy fix cannot flatten the battery. The pattern is sampled at every iteration of the main
+//! loop, so periods shorter than an iteration are not resolved.
+
+use core::fmt::Write as _;
+
+use crate::fix_average::distance;
+use crate::log::{log_at, Category, Level};
+
+/// Maximum `max_duration` [minutes], one day.
+pub const MAX_DURATION: u16 = 24 * 60;
+
+/// Configuration of the beacon. Fields that are not set in an override take the default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Beacon {
+    /// Time the output is on in every period [ms].
+    pub on: u16,
+
+    /// Time the output is off in every period [ms].
+    pub off: u16,
+
+    /// Longest activation [minutes], at most `MAX_DURATION`.
+    pub max_duration: u16,
+
+    /// Activation when the main loop starts [s], `0` disables.
+    pub startup: u16,
+
+    /// Distance between two accepted fixes that activates the beacon [m], `0` disables.
+    pub jump: u32,
+}
+
+impl Default for Beacon {
+    fn default() -> Beacon {
+        Beacon {
+            on: 500,
+            off: 1500,
+            max_duration: 60,
+            startup: 0,
+            jump: 0,
+        }
+    }
+}
+
+impl Beacon {
+    pub fn is_valid(&self) -> bool {
+        self.on > 0
+            && (1..=MAX_DURATION).contains(&self.max_duration)
+            && self.startup as u32 <= self.max_duration as u32 * 60
+    }
+
+    /// Longest activation [ms].
+    pub fn max_duration_ms(&self) -> i64 {
+        self.max_duration as i64 * 60 * 1000
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    Startup,
+    Command,
+    Jump,
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+struct Activation {
+    start: i64,
+    until: i64,
+    reason: Reason,
+}
+
+/// State of the beacon, updated from the main loop.
+pub struct Controller {
+    config: Beacon,
+    active: Option<Activation>,
+
+    /// Last accepted fix as (lat, lon, position_time).
+    position: Option<(f64, f64, u32)>,
+}
+
+impl Controller {
+    pub fn new(config: Beacon) -> Controller {
+        Controller {
+            config,
+            active: None,
+            position: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Activate the beacon at `now` for `duration` [ms], cut off at `max_duration`. An ongoing
+    /// activation is replaced. Returns the duration in effect [ms].
+    pub fn start(&mut self, now: i64, duration: i64, reason: Reason) -> i64 {
+        let duration = duration.clamp(0, self.config.max_duration_ms());
+
+        if duration == 0 {
+            self.stop();
+            return 0;
+        }
+
+        self.active = Some(Activation {
+            start: now,
+            until: now.saturating_add(duration),
+            reason,
+        });
+
+        let mut msg = heapless::String::<128>::new();
+        write!(
+            &mut msg,
+            "Beacon activated ({:?}) at {} ms for {} s.",
+            reason,
+            now,
+            duration / 1000
+        )
+        .ok();
+        log_at(Category::Location, Level::Warn, &msg);
+
+        duration
+    }
+
+    /// Activate the beacon for `startup` seconds, if enabled.
+    pub fn start_startup(&mut self, now: i64) {
+        if self.config.startup > 0 {
+            self.start(now, self.config.startup as i64 * 1000, Reason::Startup);
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(a) = self.active.take() {
+            crate::clog!(Location, info, "Beacon ({:?}) stopped.", a.reason);
+        }
+    }
+
+    /// Remaining time of the activation at `now` [ms], `0` if not active.
+    pub fn remaining(&self, now: i64) -> i64 {
+        self.active.map(|a| (a.until - now).max(0)).unwrap_or(0)
+    }
+
+    /// Level of the output at `now`. Ends the activation when its duration has passed.
+    pub fn output(&mut self, now: i64) -> bool {
+        let Some(a) = self.active else {
+            return false;
+        };
+
+        if now >= a.until {
+            crate::clog!(
+                Location,
+                info,
+                "Beacon ({:?}) stopped after {} s.",
+                a.reason,
+                (now - a.start) / 1000
+            );
+            self.active = None;
+            return false;
+        }
+
+        let period = self.config.on as i64 + self.config.off as i64;
+        (now - a.start).rem_euclid(period) < self.config.on as i64
+    }
+
+    /// Check an accepted fix for a jump in position, and activate the beacon for `max_duration`
+    /// if it jumped more than `jump`. The same fix is only checked once. Returns `true` if the
+    /// beacon was activated. A `position_time` of `0` is no fix.
+    pub fn check_position(&mut self, now: i64, lat: f64, lon: f64, position_time: u32) -> bool {
+        if self.config.jump == 0 || position_time == 0 {
+            return false;
+        }
+
+        let last = self.position.replace((lat, lon, position_time));
+
+        match last {
+            Some((_, _, t)) if t == position_time => false,
+            Some((lat0, lon0, _)) => {
+                let d = distance((lat0, lon0), (lat, lon));
+
+                if d > self.config.jump as f64 && !self.is_active() {
+                    crate::clog!(Location, warn, "Position jumped {} m.", d);
+                    self.start(now, self.config.max_duration_ms(), Reason::Jump);
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        assert!(Beacon::default().is_valid());
+
+        let b = Beacon {
+            on: 0,
+            ..Default::default()
+        };
+        assert!(!b.is_valid());
+
+        let b = Beacon {
+            max_duration: MAX_DURATION + 1,
+            ..Default::default()
+        };
+        assert!(!b.is_valid());
+
+        let b = Beacon {
+            max_duration: 1,
+            startup: 61,
+            ..Default::default()
+        };
+        assert!(!b.is_valid());
+    }
+
+    #[test]
+    fn pattern() {
+        let mut c = Controller::new(Beacon::default());
+        assert!(!c.output(0));
+
+        assert_eq!(c.start(1000, 10_000, Reason::Command), 10_000);
+        assert!(c.output(1000));
+        assert!(c.output(1499));
+        assert!(!c.output(1500));
+        assert!(!c.output(2999));
+        assert!(c.output(3000));
+        assert_eq!(c.remaining(5000), 6000);
+
+        // Ends after the duration.
+        assert!(!c.output(11_000));
+        assert!(!c.is_active());
+        assert!(!c.output(11_000));
+
+        c.start(0, 10_000, Reason::Command);
+        c.stop();
+        assert!(!c.output(0));
+
+        // Zero stops.
+        c.start(0, 10_000, Reason::Command);
+        assert_eq!(c.start(100, 0, Reason::Command), 0);
+        assert!(!c.is_active());
+    }
+
+    #[test]
+    fn cutoff() {
+        let mut c = Controller::new(Beacon {
+            max_duration: 1,
+            ..Default::default()
+        });
+
+        assert_eq!(c.start(0, 3_600_000, Reason::Command), 60_000);
+        assert!(c.output(59_000));
+        assert!(!c.output(60_000));
+        assert!(!c.is_active());
+    }
+
+    #[test]
+    fn startup() {
+        let mut c = Controller::new(Beacon::default());
+        c.start_startup(0);
+        assert!(!c.is_active());
+
+        let mut c = Controller::new(Beacon {
+            startup: 30,
+            ..Default::default()
+        });
+        c.start_startup(0);
+        assert_eq!(c.remaining(0), 30_000);
+    }
+
+    #[test]
+    fn jump() {
+        let mut c = Controller::new(Beacon::default());
+        assert!(!c.check_position(0, 60.0, 5.0, 1));
+        assert!(!c.check_position(0, 60.001, 5.0, 2));
+
+        let mut c = Controller::new(Beacon {
+            jump: 500,
+            ..Default::default()
+        });
+        assert!(!c.check_position(0, 0.0, 0.0, 0));
+        assert!(!c.check_position(0, 60.0, 5.0, 1));
+
+        // Noise, and the same fix again.
+        assert!(!c.check_position(0, 60.001, 5.0, 2));
+        assert!(!c.check_position(0, 60.001, 5.0, 2));
+
+        // About 1.1 km north.
+        assert!(c.check_position(1000, 60.011, 5.0, 3));
+        assert_eq!(c.remaining(1000), 60 * 60 * 1000);
+
+        // Already active.
+        assert!(!c.check_position(2000, 60.1, 5.0, 4));
+    }
+}
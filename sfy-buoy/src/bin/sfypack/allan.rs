@@ -0,0 +1,256 @@
+//! Noise characterization of the IMU from a capture of a stationary buoy.
+//!
+//! The overlapping Allan deviation of each axis is computed for averaging times `tau` of powers of
+//! two of the sample interval. Two coefficients are read off the curve:
+//!
+//! * White noise (velocity random walk for the accelerometer): the Allan deviation falls with a
+//!   slope of -1/2 in log-log, and the coefficient is the value of that line at `tau = 1 s`
+//!   [m/s^2/sqrt(Hz)].
+//! * Bias instability: the flat bottom of the curve, `min(adev) / sqrt(2 ln 2 / pi)` [m/s^2].
+//!
+//! The samples of the packages are concatenated, so the capture should not have gaps. The
+//! vertical axis has gravity removed, which does not affect the Allan deviation.
+
+use argh::FromArgs;
+use serde_json as json;
+use std::path::PathBuf;
+
+use crate::collection::PackageReader;
+use crate::export::Samples;
+
+/// `sqrt(2 ln 2 / pi)`, the Allan deviation at the bottom of the curve of a bias instability of
+/// one.
+const BIAS_INSTABILITY_FACTOR: f64 = 0.664;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "allan")]
+/// Allan deviation and noise coefficients of a stationary capture.
+pub struct Allan {
+    #[argh(positional, description = "collection files with the capture")]
+    files: Vec<PathBuf>,
+
+    #[argh(switch, description = "input files with raw-data")]
+    raw: bool,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct Point {
+    /// Averaging time [s].
+    pub tau: f64,
+    pub adev: f64,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct AxisReport {
+    pub axis: &'static str,
+    pub samples: usize,
+    pub adev: Vec<Point>,
+
+    /// White noise coefficient [m/s^2/sqrt(Hz)].
+    pub white_noise: Option<f64>,
+
+    /// Bias instability [m/s^2], and the averaging time it is found at [s].
+    pub bias_instability: Option<f64>,
+    pub bias_instability_tau: Option<f64>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Report {
+    pub freq: f32,
+    pub axes: Vec<AxisReport>,
+}
+
+/// Cluster sizes (powers of two) up to half the length of a series of `n` samples.
+pub fn cluster_sizes(n: usize) -> Vec<usize> {
+    std::iter::successors(Some(1usize), |m| Some(m * 2))
+        .take_while(|m| 2 * m < n)
+        .collect()
+}
+
+/// Overlapping Allan deviation of `y` sampled at interval `tau0` [s].
+pub fn allan_deviation(y: &[f64], tau0: f64) -> Vec<Point> {
+    // Integrated series.
+    let theta = std::iter::once(0.)
+        .chain(y.iter().scan(0., |s, v| {
+            *s += v * tau0;
+            Some(*s)
+        }))
+        .collect::<Vec<f64>>();
+
+    let n = theta.len();
+
+    cluster_sizes(y.len())
+        .into_iter()
+        .map(|m| {
+            let tau = m as f64 * tau0;
+
+            let sum = (0..n - 2 * m)
+                .map(|k| theta[k + 2 * m] - 2. * theta[k + m] + theta[k])
+                .map(|d| d * d)
+                .sum::<f64>();
+
+            let avar = sum / (2. * tau * tau * (n - 2 * m) as f64);
+
+            Point {
+                tau,
+                adev: avar.sqrt(),
+            }
+        })
+        .collect()
+}
+
+/// Tolerance of the slope of the white noise region.
+const WHITE_NOISE_SLOPE_TOLERANCE: f64 = 0.1;
+
+/// White noise coefficient: the -1/2 slope through the first point (shortest averaging time)
+/// where the local slope of the curve is within `WHITE_NOISE_SLOPE_TOLERANCE` of -1/2 (or closest
+/// to it), evaluated at `tau = 1 s`. The longest averaging times have few clusters and are noisy,
+/// so the shortest matching averaging time is used.
+pub fn white_noise(adev: &[Point]) -> Option<f64> {
+    let slopes = adev
+        .windows(2)
+        .filter(|w| w[0].adev > 0. && w[1].adev > 0.)
+        .map(|w| {
+            let slope = (w[1].adev.ln() - w[0].adev.ln()) / (w[1].tau.ln() - w[0].tau.ln());
+            ((slope + 0.5).abs(), &w[0])
+        })
+        .collect::<Vec<_>>();
+
+    slopes
+        .iter()
+        .find(|(d, _)| *d < WHITE_NOISE_SLOPE_TOLERANCE)
+        .or_else(|| slopes.iter().min_by(|(a, _), (b, _)| a.total_cmp(b)))
+        .map(|(_, p)| p.adev * p.tau.sqrt())
+}
+
+/// Bias instability and the averaging time at the minimum of the curve.
+pub fn bias_instability(adev: &[Point]) -> Option<(f64, f64)> {
+    adev.iter()
+        .min_by(|a, b| a.adev.total_cmp(&b.adev))
+        .map(|p| (p.adev / BIAS_INSTABILITY_FACTOR, p.tau))
+}
+
+pub fn axis_report(axis: &'static str, y: &[f64], freq: f32) -> AxisReport {
+    let adev = allan_deviation(y, 1. / freq as f64);
+    let bias = bias_instability(&adev);
+
+    AxisReport {
+        axis,
+        samples: y.len(),
+        white_noise: white_noise(&adev),
+        bias_instability: bias.map(|b| b.0),
+        bias_instability_tau: bias.map(|b| b.1),
+        adev,
+    }
+}
+
+impl Allan {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let mut axes: [Vec<f64>; 3] = Default::default();
+        let mut freq = None;
+
+        for f in &self.files {
+            eprintln!("Loading collection from: {:?}", f);
+
+            let mut samples = Samples::new(PackageReader::open(f, self.raw)?);
+
+            for s in samples.by_ref() {
+                let s = s?;
+
+                // Axes left out of the packages are NaN, and left out of the report.
+                for (a, v) in axes.iter_mut().zip([s.x, s.y, s.z]) {
+                    if !v.is_nan() {
+                        a.push(v as f64);
+                    }
+                }
+            }
+
+            match (freq, samples.stats.freq) {
+                (Some(a), Some(b)) if a != b => {
+                    anyhow::bail!("collections have different sample rates: {} and {}", a, b)
+                }
+                (None, f) => freq = f,
+                _ => {}
+            }
+        }
+
+        let freq = freq.ok_or_else(|| anyhow::anyhow!("no samples in capture"))?;
+        let n = axes.iter().map(Vec::len).max().unwrap_or(0);
+        eprintln!("Analyzing {} samples at {} Hz.", n, freq);
+
+        let report = Report {
+            freq,
+            axes: ["x", "y", "z"]
+                .into_iter()
+                .zip(&axes)
+                .filter(|(_, y)| !y.is_empty())
+                .map(|(a, y)| axis_report(a, y, freq))
+                .collect(),
+        };
+
+        println!("{}", json::to_string_pretty(&report)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gaussian white noise with standard deviation `std` (deterministic).
+    fn white(n: usize, std: f64) -> Vec<f64> {
+        let mut s: u64 = 0x2545f4914f6cdd1d;
+        let mut uniform = move || {
+            s ^= s << 13;
+            s ^= s >> 7;
+            s ^= s << 17;
+            (s >> 11) as f64 / (1u64 << 53) as f64
+        };
+
+        (0..n)
+            .map(|_| {
+                let (u1, u2) = (uniform().max(1.0e-12), uniform());
+                std * (-2. * u1.ln()).sqrt() * (2. * std::f64::consts::PI * u2).cos()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clusters() {
+        assert_eq!(cluster_sizes(10), vec![1, 2, 4]);
+        assert!(cluster_sizes(1).is_empty());
+    }
+
+    #[test]
+    fn constant() {
+        let adev = allan_deviation(&[1.0; 100], 0.1);
+        assert!(adev.iter().all(|p| p.adev.abs() < 1.0e-9));
+    }
+
+    #[test]
+    fn white_noise_coefficient() {
+        let freq = 52.;
+        let std = 0.01;
+        let y = white(100_000, std);
+
+        let adev = allan_deviation(&y, 1. / freq);
+
+        // At the sample interval the Allan deviation is the standard deviation.
+        assert!((adev[0].adev - std).abs() / std < 0.05, "{:?}", adev[0]);
+
+        // Slope of -1/2: N = std * sqrt(tau0).
+        let n = white_noise(&adev).unwrap();
+        let expected = std / freq.sqrt();
+        assert!(
+            (n - expected).abs() / expected < 0.1,
+            "{} != {}",
+            n,
+            expected
+        );
+
+        // No bias instability in white noise: the minimum is at the longest averaging time.
+        let (_, tau) = bias_instability(&adev).unwrap();
+        assert!(tau > 50.);
+    }
+}
@@ -0,0 +1,136 @@
+//! Export the full-rate samples around the extreme events (`BURST.LOG` on the SD-card, see
+//! `sfy::burst`) as CSV, one row per sample. Records that cannot be decoded are skipped with a
+//! warning.
+
+use argh::FromArgs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use sfy::burst::Record;
+use sfy::waves::SENSORS_GRAVITY_STANDARD;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "burst")]
+/// Export the bursts around extreme events as CSV.
+pub struct Burst {
+    #[argh(positional, description = "burst file (BURST.LOG)")]
+    file: PathBuf,
+
+    #[argh(option, short = 'o', description = "output file (default: stdout)")]
+    output: Option<PathBuf>,
+}
+
+/// Decode the records of a burst file, the records that could not be decoded are returned as
+/// errors with the index of the record.
+pub fn decode(buf: &mut [u8]) -> Vec<Result<Record, (usize, sfy::axl::DecodeError)>> {
+    buf.split_inclusive_mut(|b| *b == 0)
+        .filter(|r| r.len() > 1)
+        .enumerate()
+        .map(|(i, r)| Record::decode(r).map_err(|e| (i, e)))
+        .collect()
+}
+
+/// Write the samples in m/s², with the index of the record, the (estimated) time of the sample
+/// and the number of the sample relative to the trigger sample.
+pub fn write_csv(records: &[Record], mut w: impl Write) -> anyhow::Result<()> {
+    writeln!(w, "record,timestamp,sample,x,y,z")?;
+
+    let ms2 = |mg: i16| mg as f64 / 1000. * SENSORS_GRAVITY_STANDARD;
+
+    for (i, r) in records.iter().enumerate() {
+        for (j, s) in r.samples.iter().enumerate() {
+            writeln!(
+                w,
+                "{},{},{},{:.3},{:.3},{:.3}",
+                i,
+                r.sample_time(j),
+                j as i64 - r.trigger as i64,
+                ms2(s[0]),
+                ms2(s[1]),
+                ms2(s[2])
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Burst {
+    pub fn run(&self) -> anyhow::Result<()> {
+        eprintln!("Loading bursts from: {:?}", self.file);
+        let mut buf = std::fs::read(&self.file)?;
+
+        let records = decode(&mut buf)
+            .into_iter()
+            .filter_map(|r| {
+                r.inspect_err(|(i, e)| eprintln!("Skipping corrupt record {}: {:?}", i, e))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        eprintln!("Loaded {} bursts.", records.len());
+
+        for (i, r) in records.iter().enumerate() {
+            eprintln!(
+                "{}: {} samples, {:.1} m/s^2 from gravity at {} ms.",
+                i,
+                r.samples.len(),
+                r.peak,
+                r.sample_time(r.trigger as usize)
+            );
+        }
+
+        match &self.output {
+            Some(o) => {
+                let mut w = BufWriter::new(std::fs::File::create(o)?);
+                write_csv(&records, &mut w)?;
+                w.flush()?;
+            }
+            None => write_csv(&records, std::io::stdout().lock())?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sfy::burst::{BURST_RECORD_SZ, BURST_VERSION};
+
+    #[test]
+    fn export() {
+        let r = Record {
+            time: 1_700_000_000_000,
+            freq: 208.,
+            trigger: 1,
+            peak: 29.4,
+            samples: heapless::Vec::from_slice(&[[0, 0, 1000], [0, 0, 4000], [0, 0, -1000]])
+                .unwrap(),
+        };
+
+        let mut buf = Vec::new();
+        let b: heapless::Vec<u8, BURST_RECORD_SZ> =
+            postcard::to_vec_cobs(&(BURST_VERSION, &r)).unwrap();
+        buf.extend_from_slice(&b);
+        buf.extend_from_slice(&[0xaa, 0xbb, 0]);
+        buf.extend_from_slice(&b);
+
+        let records = decode(&mut buf);
+        assert_eq!(records.len(), 3);
+        assert!(records[1].is_err());
+
+        let records = records
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
+        let mut out = Vec::new();
+        write_csv(&records, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1], "0,1699999999991,-1,0.000,0.000,9.807");
+        assert_eq!(lines[2], "0,1699999999996,0,0.000,0.000,39.227");
+        assert_eq!(lines[6], "1,1700000000000,1,0.000,0.000,-9.807");
+    }
+}
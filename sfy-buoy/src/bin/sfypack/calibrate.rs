@@ -0,0 +1,193 @@
+//! Estimate the bias and scale of the accelerometer from a calibration capture (see
+//! `sfy::calibration`). The packages of the capture are picked out of the collections by their
+//! calibration step, all other packages are ignored.
+
+use argh::FromArgs;
+use serde_json as json;
+use std::path::PathBuf;
+
+use sfy::axl::{AxlPacket, AXES_ALL, SAMPLE_SZ};
+use sfy::calibration::{self, Estimate, STEPS};
+
+use crate::collection::Collection;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "calibrate")]
+/// Estimate accelerometer bias and scale from a calibration capture.
+pub struct Calibrate {
+    #[argh(
+        positional,
+        description = "collection files with the calibration capture"
+    )]
+    files: Vec<PathBuf>,
+
+    #[argh(switch, description = "input files with raw-data")]
+    raw: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Step {
+    pub step: u8,
+    pub samples: usize,
+
+    /// Mean acceleration (x, y, z) in m/s^2.
+    pub mean: [f32; 3],
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Report {
+    pub steps: Vec<Step>,
+    pub estimate: Estimate,
+}
+
+/// Mean acceleration of each step, leaving out the first `calibration::SETTLE` ms of each step.
+pub fn step_means(pcks: &[AxlPacket]) -> anyhow::Result<Vec<Step>> {
+    (1..=STEPS)
+        .map(|step| {
+            let pcks = pcks
+                .iter()
+                .filter(|p| p.calibration == step)
+                .collect::<Vec<_>>();
+
+            let start = pcks
+                .iter()
+                .map(|p| p.timestamp)
+                .min()
+                .ok_or_else(|| anyhow::anyhow!("no packages for calibration step {}", step))?;
+
+            let mut sum = [0f64; 3];
+            let mut samples = 0;
+
+            for p in pcks {
+                for (i, s) in p.data.chunks_exact(SAMPLE_SZ).enumerate() {
+                    let t = p.timestamp + (i as f64 * 1000. / p.freq as f64) as i64;
+
+                    if t - start < calibration::SETTLE {
+                        continue;
+                    }
+
+                    for (sum, u) in sum.iter_mut().zip(s) {
+                        *sum += p.accel(*u) as f64;
+                    }
+                    samples += 1;
+                }
+            }
+
+            anyhow::ensure!(
+                samples > 0,
+                "calibration step {} is too short, no samples after settling",
+                step
+            );
+
+            Ok(Step {
+                step,
+                samples,
+                mean: sum.map(|s| (s / samples as f64) as f32),
+            })
+        })
+        .collect()
+}
+
+impl Calibrate {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let mut pcks = Vec::new();
+
+        for f in &self.files {
+            eprintln!("Loading collection from: {:?}", f);
+
+            let c = match self.raw {
+                false => Collection::from_file(f),
+                true => Collection::from_file_raw(f),
+            }?;
+
+            pcks.extend(c.pcks.into_iter().filter(|p| p.calibration != 0));
+        }
+
+        eprintln!("Found {} calibration packages.", pcks.len());
+
+        let steps = step_means(&pcks)?;
+
+        let means = <[[f32; 3]; STEPS as usize]>::try_from(
+            steps.iter().map(|s| s.mean).collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        let report = Report {
+            estimate: calibration::estimate(&means),
+            steps,
+        };
+
+        println!("{}", json::to_string_pretty(&report)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sfy::axl::{AxlPacketBuilder, AXL_SZ};
+    use sfy::calibration::{ORIENTATIONS, STEP_DURATION};
+    use sfy::waves::wire::{ScaledF32, A16, ACCEL_MAX};
+    use sfy::waves::{Frame, SENSORS_GRAVITY_STANDARD};
+
+    /// Start of the captures [ms].
+    const T0: i64 = 1_700_000_000_000;
+
+    fn package(step: u8, timestamp: i64, a: [f32; 3]) -> AxlPacket {
+        let data = (0..AXL_SZ)
+            .map(|i| A16::from_f32(a[i % SAMPLE_SZ]).to_u16())
+            .collect();
+
+        AxlPacketBuilder::new(T0 + timestamp, 208.0, ACCEL_MAX, data)
+            .calibration(step)
+            .frame(Frame::Sensor.code())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn estimate_from_capture() {
+        let g = SENSORS_GRAVITY_STANDARD as f32;
+        let bias = [0.05, -0.1, 0.2];
+
+        // One package is about 4.9 s at 208 Hz.
+        let pcks = ORIENTATIONS
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (axis, up))| {
+                let mut a = bias;
+                a[*axis] += if *up { g } else { -g };
+
+                let start = i as i64 * STEP_DURATION;
+
+                // Turning the buoy.
+                let turning = package(i as u8 + 1, start, [3.0, 3.0, 3.0]);
+
+                (0..11)
+                    .map(move |k| package(i as u8 + 1, start + 5000 * (k + 1), a))
+                    .chain([turning])
+            })
+            .collect::<Vec<_>>();
+
+        let steps = step_means(&pcks).unwrap();
+        assert_eq!(steps.len(), STEPS as usize);
+
+        let means = <[[f32; 3]; STEPS as usize]>::try_from(
+            steps.iter().map(|s| s.mean).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let e = calibration::estimate(&means);
+
+        for i in 0..3 {
+            assert!((e.bias[i] - bias[i]).abs() < 1.0e-3, "{:?}", e);
+            assert!((e.scale[i] - 1.0).abs() < 1.0e-3, "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn missing_step() {
+        let pcks = vec![package(1, 0, [0., 0., 9.8])];
+        assert!(step_means(&pcks).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::ops::Deref;
+use std::path::Path;
+
+use sfy::axl;
+use sfy::axl::{AXL_POSTCARD_SZ as PACKAGE_SZ, GYRO_POSTCARD_SZ};
+use sfy::crypt::{self, Key};
+use sfy::storage::{days, StoredPackage, PACKAGE_SZ as RAW_PACKAGE_SZ};
+use sfy::waves::VecRawAxl;
+
+/// Simulated note event
+#[derive(serde::Serialize)]
+pub struct AxlNote {
+    body: axl::AxlPacketMeta,
+    payload: String,
+    raw: Option<Vec<f32>>,
+}
+
+impl AxlNote {
+    pub fn from(pck: &axl::AxlPacket, raw: Option<Vec<f32>>) -> AxlNote {
+        let (body, b64) = pck.split();
+
+        let payload = String::from_utf8(b64.as_slice().to_vec()).unwrap();
+
+        AxlNote { body, payload, raw }
+    }
+}
+
+/// A package read from a collection file.
+pub struct Package {
+    /// Position of package in collection file.
+    pub index: usize,
+    pub pck: Result<axl::AxlPacket, axl::DecodeError>,
+    pub raw: Option<Vec<f32>>,
+
+    /// The package as stored, before decoding, if kept (see [`PackageReader::keep_bytes`]).
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Reads the packages of a collection one at the time, so that large collections do not need to
+/// be held in memory.
+pub struct PackageReader<R: Read> {
+    r: R,
+
+    /// Format version of the packages in the collection.
+    pub version: u32,
+    index: usize,
+    raw: bool,
+    buf: StoredPackage<Vec<u8>>,
+
+    /// Key for sealed packages (see `sfy::crypt`).
+    key: Option<Key>,
+
+    /// Keep the stored bytes of every package.
+    keep_bytes: bool,
+}
+
+/// Key for sealed packages from `SFY_KEY` (32 hex digits), if set.
+pub fn env_key() -> anyhow::Result<Option<Key>> {
+    match std::env::var("SFY_KEY") {
+        Ok(k) => crypt::parse_key(k.trim())
+            .map(Some)
+            .ok_or_else(|| anyhow::anyhow!("SFY_KEY must be 32 hex digits")),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Format version of a collection from the extension of the file name, e.g.: `44.5`. Files without
+/// a numeric extension are assumed to be of the current version.
+pub fn file_version(p: impl AsRef<Path>) -> u32 {
+    p.as_ref()
+        .extension()
+        .and_then(|s| s.to_str())
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(axl::VERSION)
+}
+
+/// Collection number from the file name, e.g.: `44.5`. Collections named by day (e.g.
+/// `23111402.20`, see `sfy::storage::days`) are not named by their number, and give `None`.
+pub fn collection_number(p: impl AsRef<Path>) -> Option<u32> {
+    p.as_ref()
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .filter(|s| !days::is_day_stem(s))
+        .and_then(|s| s.parse().ok())
+}
+
+impl PackageReader<BufReader<File>> {
+    pub fn open(p: impl AsRef<Path>, raw: bool) -> anyhow::Result<Self> {
+        let version = file_version(&p);
+
+        if version > axl::VERSION {
+            anyhow::bail!(axl::DecodeError::UnsupportedVersion(version).to_string());
+        }
+
+        Ok(PackageReader::new(BufReader::new(File::open(p)?), raw, version).with_key(env_key()?))
+    }
+}
+
+impl<R: Read> PackageReader<R> {
+    pub fn new(r: R, raw: bool, version: u32) -> PackageReader<R> {
+        PackageReader {
+            r,
+            version,
+            index: 0,
+            raw,
+            buf: StoredPackage::new(raw),
+            key: None,
+            keep_bytes: false,
+        }
+    }
+
+    /// Decrypt sealed packages with `key`.
+    pub fn with_key(mut self, key: Option<Key>) -> PackageReader<R> {
+        self.key = key;
+        self
+    }
+
+    /// Read the packages from the larger slots of a collection with gyroscope samples (the `gyro`
+    /// feature, see `sfy::axl::GYRO_POSTCARD_SZ`).
+    pub fn gyro(mut self, gyro: bool) -> PackageReader<R> {
+        if gyro {
+            self.buf = StoredPackage::with_slot(GYRO_POSTCARD_SZ, self.raw);
+        }
+        self
+    }
+
+    /// Keep the stored bytes of every package in [`Package::bytes`], e.g. to write them to a new
+    /// collection.
+    pub fn keep_bytes(mut self) -> PackageReader<R> {
+        self.keep_bytes = true;
+        self
+    }
+}
+
+impl<R: Read> Iterator for PackageReader<R> {
+    type Item = io::Result<Package>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut n = 0;
+
+        while n < self.buf.len() {
+            match self.r.read(&mut self.buf.as_mut_slice()[n..]) {
+                Ok(0) => break,
+                Ok(k) => n += k,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        if n == 0 {
+            return None;
+        }
+
+        if n < self.buf.len() {
+            eprintln!("Warning, collection consists of non-integer number of packages.");
+            return None;
+        }
+
+        let index = self.index;
+        self.index += 1;
+
+        let raw = self.buf.raw().map(|raw| {
+            let raw = VecRawAxl::from_slice(bytemuck::cast_slice(raw)).unwrap();
+            raw.iter().map(|v| (*v).into()).collect::<Vec<f32>>()
+        });
+
+        // The package is decoded in place.
+        let bytes = self.keep_bytes.then(|| self.buf.as_mut_slice().to_vec());
+        let pck = self.buf.decode_sealed(self.version, self.key.as_ref());
+
+        Some(Ok(Package {
+            index,
+            pck,
+            raw,
+            bytes,
+        }))
+    }
+}
+
+/// Decoded packages, with the raw data if read.
+type Packages = Vec<(axl::AxlPacket, Option<Vec<f32>>)>;
+
+#[derive(serde::Serialize)]
+pub struct Collection {
+    pub pcks: Vec<axl::AxlPacket>,
+    pub raw: Option<Vec<Vec<f32>>>,
+
+    /// Byte offsets in the collection file of the packages that failed to decode, and were
+    /// skipped.
+    #[serde(skip)]
+    pub skipped: Vec<usize>,
+
+    /// Byte offsets in the collection file of the packages with samples that do not match their
+    /// CRC (see `AxlPacket::verify`), and were skipped. Packages from before version 19 carry no
+    /// CRC, and are not verified.
+    #[serde(skip)]
+    pub mismatched: Vec<usize>,
+}
+
+impl Collection {
+    /// Read the packages of a collection. Packages that fail to decode (see
+    /// [`Collection::skipped`]) or to verify (see [`Collection::mismatched`]) are skipped, only a
+    /// package of an unsupported version fails the whole load.
+    pub fn from_file(p: impl AsRef<Path>) -> anyhow::Result<Collection> {
+        let (pcks, skipped, mismatched) = Self::read(p, false)?;
+        let pcks = pcks.into_iter().map(|(p, _)| p).collect();
+
+        Ok(Collection {
+            pcks,
+            raw: None,
+            skipped,
+            mismatched,
+        })
+    }
+
+    pub fn from_file_raw(p: impl AsRef<Path>) -> anyhow::Result<Collection> {
+        let (pcks, skipped, mismatched) = Self::read(p, true)?;
+        let (pcks, raw) = pcks.into_iter().map(|(p, raw)| (p, raw.unwrap())).unzip();
+
+        Ok(Collection {
+            pcks,
+            raw: Some(raw),
+            skipped,
+            mismatched,
+        })
+    }
+
+    fn read(p: impl AsRef<Path>, raw: bool) -> anyhow::Result<(Packages, Vec<usize>, Vec<usize>)> {
+        let p = p.as_ref();
+        let sz = std::fs::metadata(p)?.len() as usize;
+        let package_sz = if raw { RAW_PACKAGE_SZ } else { PACKAGE_SZ };
+        let n = sz / package_sz;
+
+        eprintln!("Parsing {} bytes of packages into {} packages..", sz, n);
+
+        let mut pcks = Vec::with_capacity(n);
+        let mut skipped = Vec::new();
+        let mut mismatched = Vec::new();
+
+        for p in PackageReader::open(p, raw)? {
+            let p = p?;
+            let offset = p.index * package_sz;
+
+            match p.pck {
+                Ok(pck) if !pck.verify() => {
+                    eprintln!(
+                        "package {} (at byte {}) does not match its CRC",
+                        p.index, offset
+                    );
+                    mismatched.push(offset);
+                }
+                Ok(pck) => pcks.push((pck, p.raw)),
+                Err(e @ axl::DecodeError::UnsupportedVersion(_)) => anyhow::bail!(e.to_string()),
+                Err(e) => {
+                    eprintln!(
+                        "failed to parse package {} (at byte {}): {}",
+                        p.index, offset, e
+                    );
+                    skipped.push(offset);
+                }
+            }
+        }
+
+        if !skipped.is_empty() {
+            eprintln!("Skipped {} corrupt packages of {}.", skipped.len(), n);
+        }
+
+        if !mismatched.is_empty() {
+            eprintln!(
+                "Skipped {} packages of {} with a CRC mismatch.",
+                mismatched.len(),
+                n
+            );
+        }
+
+        Ok((pcks, skipped, mismatched))
+    }
+
+    /// Gaps in time between consecutive packages, as the index (in `pcks`) of the package after
+    /// the gap and the time between the end of the previous package and the start of this one
+    /// [ms]. Only gaps larger than `tolerance_ms` are returned, overlapping packages give negative
+    /// gaps.
+    pub fn gaps(&self, tolerance_ms: u32) -> Vec<(usize, i64)> {
+        self.pcks
+            .windows(2)
+            .enumerate()
+            .map(|(i, w)| (i + 1, w[1].timestamp - w[0].timestamp - duration_ms(&w[0])))
+            .filter(|(_, gap)| gap.abs() > tolerance_ms as i64)
+            .collect()
+    }
+}
+
+/// Expected duration of package in ms.
+pub fn duration_ms(pck: &axl::AxlPacket) -> i64 {
+    (pck.samples() as f64 * 1000. / pck.freq as f64) as i64
+}
+
+impl Deref for Collection {
+    type Target = Vec<axl::AxlPacket>;
+
+    fn deref(&self) -> &Vec<axl::AxlPacket> {
+        &self.pcks
+    }
+}
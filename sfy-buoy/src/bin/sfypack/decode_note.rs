@@ -0,0 +1,260 @@
+//! Decode data notes as they are delivered by notehub, using the same decoder as the firmware
+//! tests. The output is the same as `sfypack --json` for the collection on the SD-card, so the
+//! firmware output and the cloud output can be compared for identical bytes. Notes from
+//! `backfill.qo` (`--backfill`) hold a batch of packages each, and are decoded to the packages.
+
+use argh::FromArgs;
+use serde_json as json;
+use std::io::Read;
+use std::path::PathBuf;
+
+use sfy::axl::{AxlPacket, AxlPacketMeta};
+use sfy::backfill::{Batch, BatchMeta};
+use sfy::crypt::Key;
+
+use crate::collection::env_key;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "decode-note")]
+/// Decode data notes (body and base64 payload) to packages.
+pub struct DecodeNote {
+    #[argh(
+        positional,
+        description = "file with note event(s) as JSON (one event or a list of events), '-' for stdin"
+    )]
+    file: Option<PathBuf>,
+
+    #[argh(option, description = "base64 payload of note (requires --body)")]
+    payload: Option<String>,
+
+    #[argh(option, description = "body of note as JSON (requires --payload)")]
+    body: Option<String>,
+
+    #[argh(
+        switch,
+        description = "notes are batches of stored packages from backfill.qo"
+    )]
+    backfill: bool,
+}
+
+/// A note event, only the fields needed for decoding are read.
+#[derive(serde::Deserialize)]
+pub struct NoteEvent {
+    pub body: AxlPacketMeta,
+    pub payload: String,
+}
+
+/// A backfill note event (see `sfy::backfill`).
+#[derive(serde::Deserialize)]
+pub struct BackfillEvent {
+    pub body: BatchMeta,
+    pub payload: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Events<E> {
+    One(E),
+    Many(Vec<E>),
+}
+
+impl NoteEvent {
+    /// Decode the note, sealed notes are decrypted with `key`.
+    pub fn decode(&self, key: Option<&Key>) -> anyhow::Result<AxlPacket> {
+        AxlPacket::from_note_sealed(&self.body, self.payload.trim().as_bytes(), key)
+            .map_err(|e| anyhow::anyhow!(e.to_string()))
+    }
+}
+
+impl BackfillEvent {
+    /// Decode the batch of the note to its packages.
+    pub fn decode(&self) -> anyhow::Result<Vec<AxlPacket>> {
+        let batch = Batch::from_note(&self.body, self.payload.trim().as_bytes())
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+        let pcks = batch
+            .packages()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+        anyhow::ensure!(
+            pcks.len() == self.body.packages as usize,
+            "batch has {} packages, expected {}",
+            pcks.len(),
+            self.body.packages
+        );
+
+        Ok(pcks)
+    }
+}
+
+fn parse<E: serde::de::DeserializeOwned>(s: &str) -> anyhow::Result<Vec<E>> {
+    Ok(match json::from_str(s)? {
+        Events::One(e) => vec![e],
+        Events::Many(v) => v,
+    })
+}
+
+/// Parse one note event or a list of note events.
+pub fn parse_events(s: &str) -> anyhow::Result<Vec<NoteEvent>> {
+    parse(s)
+}
+
+/// Parse one backfill note event or a list of backfill note events.
+pub fn parse_backfill_events(s: &str) -> anyhow::Result<Vec<BackfillEvent>> {
+    parse(s)
+}
+
+impl DecodeNote {
+    /// The note event(s) as JSON.
+    fn input(&self) -> anyhow::Result<String> {
+        match (&self.file, &self.body, &self.payload) {
+            (None, Some(body), Some(payload)) => Ok(json::json!({
+                "body": json::from_str::<json::Value>(body)?,
+                "payload": payload,
+            })
+            .to_string()),
+            (Some(file), None, None) => Ok(if file.as_os_str() == "-" {
+                let mut s = String::new();
+                std::io::stdin().read_to_string(&mut s)?;
+                s
+            } else {
+                std::fs::read_to_string(file)?
+            }),
+            _ => anyhow::bail!("specify either a file, or both --body and --payload"),
+        }
+    }
+
+    pub fn run(&self) -> anyhow::Result<()> {
+        let s = self.input()?;
+
+        let pcks = if self.backfill {
+            let events = parse_backfill_events(&s)?;
+            eprintln!("Decoding {} backfill notes.", events.len());
+
+            events
+                .iter()
+                .enumerate()
+                .map(|(i, e)| e.decode().map_err(|e| anyhow::anyhow!("note {}: {}", i, e)))
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                .collect()
+        } else {
+            let events = parse_events(&s)?;
+            let key = env_key()?;
+            eprintln!("Decoding {} notes.", events.len());
+
+            events
+                .iter()
+                .enumerate()
+                .map(|(i, e)| {
+                    e.decode(key.as_ref())
+                        .map_err(|e| anyhow::anyhow!("note {}: {}", i, e))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        };
+
+        println!("{}", json::to_string_pretty(&pcks)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::{AxlNote, Collection};
+    use sfy::axl::AXL_POSTCARD_SZ;
+
+    #[test]
+    fn round_trip_collection() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+
+        let notes = c
+            .pcks
+            .iter()
+            .map(|p| AxlNote::from(p, None))
+            .collect::<Vec<_>>();
+        let s = json::to_string(&notes).unwrap();
+
+        let events = parse_events(&s).unwrap();
+        assert_eq!(events.len(), c.pcks.len());
+
+        for (e, p) in events.iter().zip(&c.pcks) {
+            let d = e.decode(None).unwrap();
+
+            // The temperature is not sent in the body when it is normal.
+            assert_eq!(d.data, p.data);
+            assert_eq!(d.timestamp, p.timestamp);
+            assert_eq!(d.offset, p.offset);
+            assert_eq!(d.storage_id, p.storage_id);
+            assert_eq!(d.lon, p.lon);
+            assert_eq!(d.lat, p.lat);
+            assert_eq!(d.freq, p.freq);
+        }
+    }
+
+    #[test]
+    fn single_event() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+        let s = json::to_string(&AxlNote::from(&c.pcks[0], None)).unwrap();
+
+        let events = parse_events(&s).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].decode(None).unwrap().data, c.pcks[0].data);
+    }
+
+    #[test]
+    fn backfill() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+
+        let mut notes = Vec::new();
+        let mut batch = Batch::new();
+
+        let copy = |p: &AxlPacket| {
+            AxlPacket::from_cobs(&mut p.to_cobs::<AXL_POSTCARD_SZ>().unwrap()).unwrap()
+        };
+
+        for p in &c.pcks {
+            if batch.push(copy(p)).is_err() {
+                notes.push(batch.split());
+                batch = Batch::new();
+                batch.push(copy(p)).unwrap();
+            }
+        }
+        notes.push(batch.split());
+        assert!(notes.len() < c.pcks.len());
+
+        let s = json::to_string(
+            &notes
+                .iter()
+                .map(|(meta, b64)| {
+                    json::json!({
+                        "body": meta,
+                        "payload": std::str::from_utf8(b64).unwrap(),
+                    })
+                })
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        let pcks = parse_backfill_events(&s)
+            .unwrap()
+            .iter()
+            .flat_map(|e| e.decode().unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(pcks == c.pcks);
+    }
+
+    #[test]
+    fn corrupt_payload() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+        let mut n = json::to_value(AxlNote::from(&c.pcks[0], None)).unwrap();
+        n["payload"] = json::Value::from("not base64!");
+
+        let events = parse_events(&n.to_string()).unwrap();
+        assert!(events[0].decode(None).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//! Compare two sets of packages, e.g. the collection on the SD-card of a buoy and the notes that
+//! reached notehub, and report the packages that were lost or that differ.
+//!
+//! Packages are matched by storage ID. Either side is a collection file, or a file of note events
+//! as JSON (by the extension `.json`, decoded as by `decode-note`). Only packages from version 19
+//! carry a CRC (see `manifest`), so packages are compared by a checksum of the decoded samples and
+//! the timestamp.
+//! The body of a note leaves out some fields of the package (e.g. the temperature when it is
+//! normal), these are not compared. Packages without a storage ID (not stored on the SD-card)
+//! cannot be matched and are only counted.
+
+use argh::FromArgs;
+use serde_json as json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use sfy::axl::AxlPacket;
+
+use crate::collection::{env_key, PackageReader};
+use crate::decode_note::{parse_backfill_events, parse_events};
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "diff")]
+/// Compare two collections (or note files) by storage ID, and report lost and differing packages.
+pub struct Diff {
+    #[argh(
+        positional,
+        description = "reference collection, e.g. from the SD-card"
+    )]
+    a: PathBuf,
+
+    #[argh(
+        positional,
+        description = "collection or note events (.json) to compare with"
+    )]
+    b: PathBuf,
+
+    #[argh(switch, description = "input files with raw-data")]
+    raw: bool,
+
+    #[argh(
+        switch,
+        description = "note events are batches of stored packages from backfill.qo"
+    )]
+    backfill: bool,
+}
+
+/// The part of a package that is compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Digest {
+    pub timestamp: i64,
+    pub checksum: u32,
+}
+
+impl Digest {
+    pub fn from(pck: &AxlPacket) -> Digest {
+        Digest {
+            timestamp: pck.timestamp,
+            checksum: checksum(&pck.data),
+        }
+    }
+}
+
+/// FNV-1a of the samples.
+pub fn checksum(data: &[u16]) -> u32 {
+    data.iter()
+        .flat_map(|s| s.to_le_bytes())
+        .fold(0x811c9dc5, |h, b| (h ^ b as u32).wrapping_mul(0x01000193))
+}
+
+/// The packages of one side, by storage ID.
+#[derive(Default)]
+pub struct Side {
+    pub ids: BTreeMap<u32, Digest>,
+
+    /// Packages that could not be decoded.
+    pub corrupt: usize,
+
+    /// Packages repeating the storage ID of an earlier package (the first is kept).
+    pub duplicates: usize,
+
+    /// Packages without storage ID.
+    pub unidentified: usize,
+}
+
+impl Side {
+    pub fn push(&mut self, pck: &AxlPacket) {
+        match pck.storage_id {
+            Some(id) if self.ids.contains_key(&id) => self.duplicates += 1,
+            Some(id) => {
+                self.ids.insert(id, Digest::from(pck));
+            }
+            None => self.unidentified += 1,
+        }
+    }
+
+    /// Read a collection, or note events if the file ends with `.json`.
+    pub fn load(p: &Path, raw: bool, backfill: bool) -> anyhow::Result<Side> {
+        let mut side = Side::default();
+
+        if p.extension().map_or(false, |e| e == "json") {
+            let s = std::fs::read_to_string(p)?;
+
+            if backfill {
+                for e in parse_backfill_events(&s)? {
+                    match e.decode() {
+                        Ok(pcks) => pcks.iter().for_each(|pck| side.push(pck)),
+                        Err(_) => side.corrupt += 1,
+                    }
+                }
+            } else {
+                let key = env_key()?;
+                for e in parse_events(&s)? {
+                    match e.decode(key.as_ref()) {
+                        Ok(pck) => side.push(&pck),
+                        Err(_) => side.corrupt += 1,
+                    }
+                }
+            }
+        } else {
+            for pck in PackageReader::open(p, raw)? {
+                match pck?.pck {
+                    Ok(pck) => side.push(&pck),
+                    Err(e @ sfy::axl::DecodeError::UnsupportedVersion(_)) => {
+                        anyhow::bail!(e.to_string())
+                    }
+                    Err(_) => side.corrupt += 1,
+                }
+            }
+        }
+
+        Ok(side)
+    }
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct Differs {
+    pub id: u32,
+
+    /// Differing fields: `timestamp` and/or `data`.
+    pub fields: Vec<&'static str>,
+}
+
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct Counts {
+    /// Packages with storage ID.
+    pub packages: usize,
+    pub corrupt: usize,
+    pub duplicates: usize,
+    pub unidentified: usize,
+}
+
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct Report {
+    pub a: Counts,
+    pub b: Counts,
+
+    /// Packages in both.
+    pub matched: usize,
+
+    /// Fraction of the packages of `a` missing from `b`.
+    pub loss: f64,
+
+    /// Storage IDs only in `a` (lost), as inclusive ranges.
+    pub only_a: Vec<(u32, u32)>,
+
+    /// Storage IDs only in `b`, as inclusive ranges.
+    pub only_b: Vec<(u32, u32)>,
+    pub differs: Vec<Differs>,
+}
+
+/// Collapse sorted IDs to inclusive ranges of consecutive IDs.
+fn ranges(ids: impl Iterator<Item = u32>) -> Vec<(u32, u32)> {
+    let mut r: Vec<(u32, u32)> = Vec::new();
+    for id in ids {
+        match r.last_mut() {
+            Some((_, end)) if end.checked_add(1) == Some(id) => *end = id,
+            _ => r.push((id, id)),
+        }
+    }
+    r
+}
+
+fn count(r: &[(u32, u32)]) -> usize {
+    r.iter().map(|(s, e)| (e - s) as usize + 1).sum()
+}
+
+pub fn diff(a: &Side, b: &Side) -> Report {
+    let counts = |s: &Side| Counts {
+        packages: s.ids.len(),
+        corrupt: s.corrupt,
+        duplicates: s.duplicates,
+        unidentified: s.unidentified,
+    };
+
+    let only_a = ranges(a.ids.keys().copied().filter(|id| !b.ids.contains_key(id)));
+    let only_b = ranges(b.ids.keys().copied().filter(|id| !a.ids.contains_key(id)));
+
+    let differs = a
+        .ids
+        .iter()
+        .filter_map(|(id, da)| {
+            let db = b.ids.get(id)?;
+            let fields = [
+                ("timestamp", da.timestamp != db.timestamp),
+                ("data", da.checksum != db.checksum),
+            ]
+            .into_iter()
+            .filter_map(|(f, d)| d.then_some(f))
+            .collect::<Vec<_>>();
+
+            (!fields.is_empty()).then_some(Differs { id: *id, fields })
+        })
+        .collect();
+
+    let lost = count(&only_a);
+
+    Report {
+        a: counts(a),
+        b: counts(b),
+        matched: a.ids.len() - lost,
+        loss: if a.ids.is_empty() {
+            0.
+        } else {
+            lost as f64 / a.ids.len() as f64
+        },
+        only_a,
+        only_b,
+        differs,
+    }
+}
+
+impl Diff {
+    pub fn run(&self) -> anyhow::Result<()> {
+        eprintln!("Loading: {:?}", self.a);
+        let a = Side::load(&self.a, self.raw, self.backfill)?;
+        eprintln!("Loading: {:?}", self.b);
+        let b = Side::load(&self.b, self.raw, self.backfill)?;
+
+        let report = diff(&a, &b);
+        eprintln!(
+            "Matched {} of {} packages ({:.1}% lost), {} only in {:?}, {} differ.",
+            report.matched,
+            report.a.packages,
+            report.loss * 100.,
+            count(&report.only_b),
+            self.b,
+            report.differs.len()
+        );
+        println!("{}", json::to_string_pretty(&report)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::Collection;
+
+    #[test]
+    fn compare() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+        let pcks = &c.pcks[..10];
+
+        let mut a = Side::default();
+        pcks.iter().for_each(|p| a.push(p));
+        assert_eq!(a.ids.len(), 10);
+
+        let ids = pcks
+            .iter()
+            .map(|p| p.storage_id.unwrap())
+            .collect::<Vec<_>>();
+
+        // Lost two packages, one arrived twice, and the samples of one were changed on the way.
+        let mut b = Side::default();
+        for (i, p) in pcks.iter().enumerate() {
+            if i != 1 && i != 2 {
+                b.push(p);
+            }
+        }
+        b.push(&pcks[0]);
+        b.ids.get_mut(&ids[4]).unwrap().checksum ^= 1;
+
+        let r = diff(&a, &b);
+        assert_eq!(r.matched, 8);
+        assert_eq!(r.only_a, ranges([ids[1], ids[2]].into_iter()));
+        assert!(r.only_b.is_empty());
+        assert_eq!(r.b.duplicates, 1);
+        assert!((r.loss - 0.2).abs() < 1e-9);
+        assert_eq!(
+            r.differs,
+            [Differs {
+                id: ids[4],
+                fields: vec!["data"]
+            }]
+        );
+
+        let r = diff(&a, &a);
+        assert_eq!((r.matched, r.loss), (10, 0.));
+        assert!(r.differs.is_empty());
+    }
+
+    #[test]
+    fn id_ranges() {
+        assert_eq!(
+            ranges([1, 2, 3, 5, 7, 8].into_iter()),
+            [(1, 3), (5, 5), (7, 8)]
+        );
+        assert_eq!(count(&ranges([1, 2, 3, 5, 7, 8].into_iter())), 6);
+        assert!(ranges(std::iter::empty()).is_empty());
+    }
+}
@@ -0,0 +1,395 @@
+//! Directional wave spectrum from the three axes of the acceleration, as the directional Fourier
+//! coefficients `a1`, `b1`, `a2` and `b2` per frequency (Longuet-Higgins et al., 1963).
+//!
+//! The samples are read one package at the time (see `export::Samples`) and split into windows as
+//! by `spectrogram` (`--window` samples overlapping by `--overlap`, restarted at gaps). The
+//! tapered FFT of every axis gives the co-spectra (`C`) and quad-spectra (`Q`) between the
+//! vertical (`z`) and horizontal (`x`, `y`) channels, averaged over all windows of the collection:
+//!
+//! ```text
+//! a1 = Qzx / sqrt(Czz (Cxx + Cyy))      a2 = (Cxx - Cyy) / (Cxx + Cyy)
+//! b1 = Qzy / sqrt(Czz (Cxx + Cyy))      b2 = 2 Cxy / (Cxx + Cyy)
+//! ```
+//!
+//! with the mean direction `atan2(b1, a1)` and the directional spread
+//! `sqrt(2 (1 - sqrt(a1^2 + b1^2)))` (radians). Integrating the acceleration to displacement
+//! divides every channel by the same `(2 pi f)^2`, which cancels in the coefficients, so they are
+//! computed from the acceleration directly. Only the vertical spectrum is integrated, to the
+//! heave displacement spectral density.
+//!
+//! Assumptions:
+//!
+//! * The samples are in the earth frame (`earth` or `corrected`, see `sfy::waves::frame`) with all
+//!   axes, the vertical positive up. Packages in the sensor frame or without the horizontal axes
+//!   are refused.
+//! * The buoy follows the surface (linear waves), so the horizontal acceleration is that of the
+//!   orbital motion at the surface, in phase quadrature with the heave. A moored or dragged buoy
+//!   has additional horizontal motion that lowers the coherence, and widens the spread.
+//! * The direction is the direction the waves travel towards, from the x-axis towards the y-axis
+//!   (add 180 degrees for the direction they come from). The buoy has no magnetometer, so the
+//!   heading of the horizontal frame is arbitrary and drifts slowly (see `sfy::waves::buf`): the
+//!   mean direction is relative, and only comparable within a record. The spread does not depend
+//!   on the heading, but drift over the record widens it.
+//! * The sea state is stationary over the collection, the spectra are averaged over all windows.
+//!   Split long deployments (e.g. with `sfypack export --split`) to follow the evolution.
+//!
+//! The gyroscope is not stored in the packages, and not used.
+
+use argh::FromArgs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use sfy::waves::Frame;
+
+use crate::collection::PackageReader;
+use crate::export::{Sample, Samples};
+use crate::spectrogram::Stft;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "directional")]
+/// Directional wave spectrum (Fourier coefficients a1, b1, a2, b2) as CSV.
+pub struct Directional {
+    #[argh(positional, description = "collection file")]
+    file: PathBuf,
+
+    #[argh(option, short = 'o', description = "output CSV file")]
+    output: PathBuf,
+
+    #[argh(
+        option,
+        short = 'w',
+        default = "1024",
+        description = "length of the window [samples] (default: 1024)"
+    )]
+    window: usize,
+
+    #[argh(
+        option,
+        description = "overlap of consecutive windows [samples] (default: half the window)"
+    )]
+    overlap: Option<usize>,
+
+    #[argh(switch, description = "input file with raw-data")]
+    raw: bool,
+
+    #[argh(
+        switch,
+        description = "include packages in a different frame of reference than the first package"
+    )]
+    force: bool,
+}
+
+/// Co- and quad-spectra between the axes, summed over the windows.
+#[derive(Debug)]
+pub struct CrossSpectra {
+    pub windows: usize,
+    czz: Vec<f64>,
+    cxx: Vec<f64>,
+    cyy: Vec<f64>,
+    cxy: Vec<f64>,
+    qzx: Vec<f64>,
+    qzy: Vec<f64>,
+}
+
+/// Directional spectrum at one frequency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    /// [Hz].
+    pub frequency: f64,
+
+    /// Spectral density of the heave displacement [m^2/Hz].
+    pub psd: f64,
+
+    pub a1: f64,
+    pub b1: f64,
+    pub a2: f64,
+    pub b2: f64,
+
+    /// Mean direction the waves travel towards, from the x-axis towards the y-axis [deg, 0-360).
+    pub direction: f64,
+
+    /// Directional spread [deg].
+    pub spread: f64,
+}
+
+impl CrossSpectra {
+    pub fn new(stft: &Stft<[f64; 3]>) -> CrossSpectra {
+        let n = stft.frequencies().len();
+
+        CrossSpectra {
+            windows: 0,
+            czz: vec![0.; n],
+            cxx: vec![0.; n],
+            cyy: vec![0.; n],
+            cxy: vec![0.; n],
+            qzx: vec![0.; n],
+            qzy: vec![0.; n],
+        }
+    }
+
+    /// Add a full window of samples (x, y, z).
+    pub fn add(&mut self, stft: &Stft<[f64; 3]>, samples: &[[f64; 3]]) {
+        let [x, y, z] = [0, 1, 2].map(|i| stft.spectrum(samples.iter().map(|s| s[i])));
+
+        for k in 0..self.czz.len() {
+            self.czz[k] += z[k].norm_sqr();
+            self.cxx[k] += x[k].norm_sqr();
+            self.cyy[k] += y[k].norm_sqr();
+            self.cxy[k] += (x[k] * y[k].conj()).re;
+            self.qzx[k] += (z[k] * x[k].conj()).im;
+            self.qzy[k] += (z[k] * y[k].conj()).im;
+        }
+
+        self.windows += 1;
+    }
+
+    /// Directional spectrum at the frequencies of `stft`, leaving out the mean (0 Hz).
+    pub fn rows(&self, stft: &Stft<[f64; 3]>) -> Vec<Row> {
+        stft.frequencies()
+            .into_iter()
+            .enumerate()
+            .skip(1)
+            .map(|(k, frequency)| {
+                let horizontal = self.cxx[k] + self.cyy[k];
+                let vertical = (self.czz[k] * horizontal).sqrt();
+
+                let a1 = self.qzx[k] / vertical;
+                let b1 = self.qzy[k] / vertical;
+                let a2 = (self.cxx[k] - self.cyy[k]) / horizontal;
+                let b2 = 2. * self.cxy[k] / horizontal;
+
+                let w = 2. * std::f64::consts::PI * frequency;
+                let psd = self.czz[k] * stft.density(k) / self.windows as f64 / w.powi(4);
+
+                let r1 = a1.hypot(b1).min(1.);
+
+                Row {
+                    frequency,
+                    psd,
+                    a1,
+                    b1,
+                    a2,
+                    b2,
+                    direction: b1.atan2(a1).to_degrees().rem_euclid(360.),
+                    spread: (2. * (1. - r1)).sqrt().to_degrees(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Cross-spectra of `samples`, averaged over the windows of `stft`.
+pub fn directional(
+    samples: impl Iterator<Item = std::io::Result<Sample>>,
+    stft: &mut Stft<[f64; 3]>,
+) -> anyhow::Result<CrossSpectra> {
+    let mut spectra = CrossSpectra::new(stft);
+
+    for s in samples {
+        let s = s?;
+        anyhow::ensure!(
+            s.x.is_finite() && s.y.is_finite() && s.z.is_finite(),
+            "package at {} does not have all axes",
+            s.timestamp
+        );
+
+        if let Some((_, window)) = stft.window(s.timestamp, [s.x as f64, s.y as f64, s.z as f64]) {
+            spectra.add(stft, &window);
+        }
+    }
+
+    Ok(spectra)
+}
+
+pub fn write_csv(rows: &[Row], mut w: impl Write) -> anyhow::Result<()> {
+    writeln!(w, "frequency,psd,a1,b1,a2,b2,direction,spread")?;
+
+    for r in rows {
+        writeln!(
+            w,
+            "{},{:e},{:.4},{:.4},{:.4},{:.4},{:.1},{:.1}",
+            r.frequency, r.psd, r.a1, r.b1, r.a2, r.b2, r.direction, r.spread
+        )?;
+    }
+
+    Ok(())
+}
+
+impl Directional {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let overlap = self.overlap.unwrap_or(self.window / 2);
+        anyhow::ensure!(self.window >= 2, "window must be at least two samples");
+        anyhow::ensure!(
+            overlap < self.window,
+            "overlap must be less than the window"
+        );
+
+        eprintln!("Loading collection from: {:?}", self.file);
+
+        let mut samples = Samples::new(PackageReader::open(&self.file, self.raw)?);
+        samples.force = self.force;
+
+        // The sample rate and frame are known after the first package has been read.
+        let first = samples.next().transpose()?;
+        let freq = samples
+            .stats
+            .freq
+            .ok_or_else(|| anyhow::anyhow!("no samples in collection"))? as f64;
+
+        anyhow::ensure!(
+            samples.stats.frame != Some(Frame::Sensor.code()),
+            "samples are in the sensor frame, the directional spectrum needs the earth frame"
+        );
+
+        let mut stft = Stft::new(freq, self.window, overlap);
+        let spectra = directional(first.map(Ok).into_iter().chain(samples), &mut stft)?;
+
+        eprintln!(
+            "Averaged {} windows of {} samples ({:.1} s) at {} Hz, resolution {:.4} Hz.",
+            spectra.windows,
+            self.window,
+            self.window as f64 / freq,
+            freq,
+            freq / self.window as f64
+        );
+        anyhow::ensure!(spectra.windows > 0, "no full windows in collection");
+
+        let rows = spectra.rows(&stft);
+
+        if let Some(peak) = rows.iter().max_by(|a, b| a.psd.total_cmp(&b.psd)) {
+            eprintln!(
+                "Peak at {:.3} Hz: direction {:.0} deg, spread {:.0} deg.",
+                peak.frequency, peak.direction, peak.spread
+            );
+        }
+
+        let mut w = BufWriter::new(File::create(&self.output)?);
+        write_csv(&rows, &mut w)?;
+        w.flush()?;
+
+        eprintln!("Wrote directional spectrum to: {:?}", self.output);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const FREQ: f64 = 52.;
+    const WINDOW: usize = 4096;
+
+    /// Acceleration of a buoy following waves of `amplitude` [m] at bin `k`, travelling towards
+    /// `direction` [deg].
+    fn samples(waves: &[(usize, f64, f64)], n: usize) -> Vec<std::io::Result<Sample>> {
+        (0..n)
+            .map(|i| {
+                let t = i as f64 / FREQ;
+                let mut a = [0.; 3];
+
+                for (k, amplitude, direction) in waves {
+                    let w = 2. * PI * *k as f64 * FREQ / WINDOW as f64;
+                    let d = direction.to_radians();
+                    let s = -w * w * amplitude;
+
+                    // Heave, and the horizontal orbital motion a quarter period ahead.
+                    a[0] += s * d.cos() * (w * t).sin();
+                    a[1] += s * d.sin() * (w * t).sin();
+                    a[2] += s * (w * t).cos();
+                }
+
+                Ok(Sample {
+                    timestamp: (t * 1000.).round() as i64,
+                    x: a[0] as f32,
+                    y: a[1] as f32,
+                    z: a[2] as f32,
+                    lat: 0.,
+                    lon: 0.,
+                    seq: 0,
+                    quality: 0,
+                    nearest_fix: false,
+                    dop: 0.,
+                    gyro: [f32::NAN; 3],
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_wave() {
+        let mut stft = Stft::new(FREQ, WINDOW, WINDOW / 2);
+        let spectra =
+            directional(samples(&[(16, 1., 30.)], 4 * WINDOW).into_iter(), &mut stft).unwrap();
+        assert_eq!(spectra.windows, 7);
+
+        let rows = spectra.rows(&stft);
+        assert_eq!(rows.len(), WINDOW / 2);
+
+        let peak = &rows[15];
+        assert_eq!(peak.frequency, 16. * FREQ / WINDOW as f64);
+        assert!(rows.iter().all(|r| r.psd <= peak.psd));
+
+        let (s, c) = 30f64.to_radians().sin_cos();
+        assert!((peak.a1 - c).abs() < 0.01, "{:?}", peak);
+        assert!((peak.b1 - s).abs() < 0.01, "{:?}", peak);
+        assert!((peak.a2 - (c * c - s * s)).abs() < 0.01, "{:?}", peak);
+        assert!((peak.b2 - 2. * s * c).abs() < 0.01, "{:?}", peak);
+        assert!((peak.direction - 30.).abs() < 0.5, "{:?}", peak);
+        assert!(peak.spread < 2., "{:?}", peak);
+
+        // The variance of a wave of amplitude 1 m is 1/2 m^2.
+        let variance = rows[10..20].iter().map(|r| r.psd).sum::<f64>() * FREQ / WINDOW as f64;
+        assert!((variance - 0.5).abs() < 0.05, "{}", variance);
+    }
+
+    #[test]
+    fn swell_and_wind_sea() {
+        let mut stft = Stft::new(FREQ, WINDOW, WINDOW / 2);
+        let spectra = directional(
+            samples(&[(8, 1., 300.), (24, 0.3, 120.)], 3 * WINDOW).into_iter(),
+            &mut stft,
+        )
+        .unwrap();
+
+        let rows = spectra.rows(&stft);
+
+        assert!((rows[7].direction - 300.).abs() < 1., "{:?}", rows[7]);
+        assert!((rows[23].direction - 120.).abs() < 1., "{:?}", rows[23]);
+        assert!(rows[7].psd > rows[23].psd);
+    }
+
+    #[test]
+    fn missing_axes() {
+        let mut s = samples(&[(16, 1., 30.)], 10);
+        s[5].as_mut().unwrap().x = f32::NAN;
+
+        let mut stft = Stft::new(FREQ, 8, 0);
+        assert!(directional(s.into_iter(), &mut stft).is_err());
+    }
+
+    #[test]
+    fn csv() {
+        let rows = [Row {
+            frequency: 0.1,
+            psd: 0.5,
+            a1: 0.5,
+            b1: -0.25,
+            a2: 0.,
+            b2: 1.,
+            direction: 333.4,
+            spread: 20.,
+        }];
+
+        let mut out = Vec::new();
+        write_csv(&rows, &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "frequency,psd,a1,b1,a2,b2,direction,spread\n\
+             0.1,5e-1,0.5000,-0.2500,0.0000,1.0000,333.4,20.0\n"
+        );
+    }
+}
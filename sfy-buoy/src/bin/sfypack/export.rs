@@ -0,0 +1,1097 @@
+//! Export of the samples of a collection, one row per sample.
+//!
+//! The collection is read one package at the time, so large collections do not need to be held
+//! in memory. The time of a sample is calculated from the package timestamp, which is the time
+//! of the sample at `offset`. `seq` is the running number of the sample in the export.
+//!
+//! `quality` holds the quality flags of the package of the sample (see `sfy::quality`), and
+//! `--min-quality` leaves out packages that pass fewer than this number of the quality checks.
+//!
+//! A package carries a single position, the last GPS fix at `position_time`. The position of a
+//! sample is interpolated linearly in time between the fixes before and after it, from this and
+//! the following packages (looking ahead at most `FIX_LOOKAHEAD` packages). When there is no fix
+//! on one side of the sample the nearest fix is used, and `nearest_fix` is set. `dop` is the
+//! dilution of precision of the fix of the package (`0` if unknown), to weight the positions.
+//!
+//! With `--split hourly` or `--split daily` the output is a directory, and the samples are
+//! written to one file per period, named by the start of the period (UTC), e.g.
+//! `2023-11-14T22.csv`. Packages straddling a boundary are split between the files at the sample
+//! where the period changes. If the collection goes back to a period that has already been
+//! written (e.g. after the clock was reset) the file gets a running number, e.g.
+//! `2023-11-14T22.1.csv`.
+//!
+//! The samples of all packages must be in the same frame of reference (see `sfy::waves::frame`)
+//! as the first exported package, the export stops with an error at the first package in another
+//! frame (e.g. a calibration package in the sensor frame, or a change of the lever-arm correction).
+//! With `--force` these packages are exported anyway, with a warning. The frame is written to the
+//! Parquet metadata (`sfy.frame`, `mixed` if the file has packages in different frames).
+//!
+//! Axes left out of a package (see `sfy::axl::AxlPacket::axes`) are exported as `NaN`.
+//!
+//! With `--gyro` the collection is read with the slots of the `gyro` feature, and the angular
+//! rate of every sample (see `sfy::axl::AxlPacket::gyro`) is written to the CSV as `gx`, `gy` and
+//! `gz` [rad/s], `NaN` for packages without it.
+
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, BooleanArray, Float32Array, Float64Array, TimestampMillisecondArray, UInt64Array,
+    UInt8Array,
+};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use parquet::format::KeyValue;
+
+use sfy::axl::{AxlPacket, SAMPLE_SZ};
+use sfy::quality;
+use sfy::waves::Frame;
+
+use crate::collection::{Package, PackageReader};
+
+/// Number of rows in every record batch written to Parquet.
+const BATCH_SZ: usize = 64 * 1024;
+
+/// Maximum number of packages read ahead to find the next fix.
+const FIX_LOOKAHEAD: usize = 64;
+
+pub enum Format {
+    Csv,
+    Parquet,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "parquet" => Ok(Format::Parquet),
+            f => Err(format!("unknown format: {} (expected: csv or parquet)", f)),
+        }
+    }
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+    None,
+    Hourly,
+    Daily,
+}
+
+impl FromStr for Split {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Split, String> {
+        match s {
+            "none" => Ok(Split::None),
+            "hourly" => Ok(Split::Hourly),
+            "daily" => Ok(Split::Daily),
+            f => Err(format!(
+                "unknown split: {} (expected: hourly, daily or none)",
+                f
+            )),
+        }
+    }
+}
+
+impl Split {
+    /// Length of a period [ms], `None` if the output is not split.
+    pub fn length(&self) -> Option<i64> {
+        match self {
+            Split::None => None,
+            Split::Hourly => Some(3600 * 1000),
+            Split::Daily => Some(24 * 3600 * 1000),
+        }
+    }
+
+    /// The period (start and end, [ms]) containing `timestamp` [ms], `None` if the output is not
+    /// split.
+    pub fn period(&self, timestamp: i64) -> Option<Range<i64>> {
+        self.length().map(|l| {
+            let start = timestamp.div_euclid(l) * l;
+            start..start + l
+        })
+    }
+
+    /// Name of the period starting at `start` [ms].
+    pub fn name(&self, start: i64) -> String {
+        let format = match self {
+            Split::Hourly => "%Y-%m-%dT%H",
+            _ => "%Y-%m-%d",
+        };
+
+        NaiveDateTime::from_timestamp_opt(start.div_euclid(1000), 0)
+            .map(|dt| dt.format(format).to_string())
+            .unwrap_or_else(|| start.to_string())
+    }
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "export")]
+/// Export the samples of a collection.
+pub struct Export {
+    #[argh(positional, description = "collection file")]
+    file: PathBuf,
+
+    #[argh(
+        positional,
+        description = "output file, or output directory when the output is split"
+    )]
+    output: PathBuf,
+
+    #[argh(
+        option,
+        default = "Format::Csv",
+        description = "output format: csv or parquet"
+    )]
+    format: Format,
+
+    #[argh(switch, description = "input file with raw-data")]
+    raw: bool,
+
+    #[argh(
+        switch,
+        description = "input file with gyroscope samples, exported as gx, gy, gz to CSV"
+    )]
+    gyro: bool,
+
+    #[argh(
+        option,
+        default = "0",
+        description = "leave out packages passing fewer quality checks than this (0 to 6)"
+    )]
+    min_quality: u8,
+
+    #[argh(
+        option,
+        default = "Split::None",
+        description = "split the output into one file per period: hourly, daily or none"
+    )]
+    split: Split,
+
+    #[argh(
+        switch,
+        description = "export packages in a different frame of reference than the first package"
+    )]
+    force: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Sample {
+    /// Time of sample [ms].
+    pub timestamp: i64,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub lat: f64,
+    pub lon: f64,
+    pub seq: u64,
+
+    /// Quality flags of the package.
+    pub quality: u8,
+
+    /// The position is the nearest fix, there was no fix on both sides of the sample to
+    /// interpolate between.
+    pub nearest_fix: bool,
+
+    /// Dilution of precision of the fix of the package, `0` if unknown.
+    pub dop: f32,
+
+    /// Angular rate (x, y, z) in the body frame [rad/s], `NaN` if the package has none.
+    pub gyro: [f32; 3],
+}
+
+/// A GPS fix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    /// Time of fix [ms].
+    pub time: i64,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Fix {
+    /// The fix of a package, `None` if the package has no fix.
+    pub fn of(pck: &AxlPacket) -> Option<Fix> {
+        (pck.position_time != 0).then(|| Fix {
+            time: pck.position_time as i64 * 1000,
+            lat: pck.lat,
+            lon: pck.lon,
+        })
+    }
+}
+
+/// Position (lat, lon) at `time` [ms], interpolated between the latest fix at or before `time`
+/// and the earliest fix at or after it in `fixes` (ordered by time). When there is a fix on only
+/// one side it is used as is, and the last element is `true`. `None` if there are no fixes.
+pub fn interpolate(fixes: &[Fix], time: i64) -> Option<(f64, f64, bool)> {
+    let a = fixes.iter().rev().find(|f| f.time <= time);
+    let b = fixes.iter().find(|f| f.time >= time);
+
+    match (a, b) {
+        (Some(a), Some(b)) if b.time > a.time => {
+            let w = (time - a.time) as f64 / (b.time - a.time) as f64;
+            Some((
+                a.lat + w * (b.lat - a.lat),
+                a.lon + w * (b.lon - a.lon),
+                false,
+            ))
+        }
+        (Some(a), Some(_)) => Some((a.lat, a.lon, false)),
+        (Some(f), None) | (None, Some(f)) => Some((f.lat, f.lon, true)),
+        (None, None) => None,
+    }
+}
+
+/// Name of the frame of reference with `code` (see `sfy::waves::Frame::code`).
+pub fn frame_name(code: u8) -> String {
+    Frame::from_code(code)
+        .map(|f| f.name().to_string())
+        .unwrap_or_else(|| format!("unknown ({})", code))
+}
+
+/// Statistics of the export, written as metadata.
+#[derive(Default, Debug, Clone)]
+pub struct Stats {
+    pub packages: usize,
+    pub corrupt: usize,
+
+    /// Packages left out because of low quality.
+    pub low_quality: usize,
+
+    /// Packages left out because of clipped samples (see `Samples::max_clipped`).
+    pub clipped: usize,
+    pub samples: u64,
+    pub freq: Option<f32>,
+
+    /// Frame of reference of the first package (`sfy::waves::Frame::code`).
+    pub frame: Option<u8>,
+
+    /// Packages in a different frame than the first, exported with `force`.
+    pub mixed_frames: usize,
+}
+
+/// Iterates over the samples of the packages of a collection.
+pub struct Samples<I: Iterator<Item = std::io::Result<Package>>> {
+    packages: I,
+    pck: Option<AxlPacket>,
+    i: usize,
+    seq: u64,
+
+    /// Packages read ahead to find the next fix.
+    ahead: VecDeque<std::io::Result<Package>>,
+
+    /// The previous and the current fix.
+    fix: [Option<Fix>; 2],
+
+    /// The previous, current and next fix, to interpolate between.
+    fixes: Vec<Fix>,
+
+    /// The samples are limited to this period (see `next_period`), and the first sample outside
+    /// it is held back for the next period.
+    period: Option<Range<i64>>,
+    held: Option<Sample>,
+
+    /// Minimum quality score of packages (see `sfy::quality::score`).
+    pub min_quality: u8,
+
+    /// Leave out packages with more IMU samples at the full scale of the accelerometer (see
+    /// `AxlPacket::clipped_samples`).
+    pub max_clipped: Option<u16>,
+
+    /// Export packages in a different frame of reference than the first, instead of failing.
+    pub force: bool,
+    pub stats: Stats,
+}
+
+impl<I: Iterator<Item = std::io::Result<Package>>> Samples<I> {
+    pub fn new(packages: I) -> Samples<I> {
+        Samples {
+            packages,
+            pck: None,
+            i: 0,
+            seq: 0,
+            ahead: VecDeque::new(),
+            fix: [None; 2],
+            fixes: Vec::new(),
+            period: None,
+            held: None,
+            min_quality: 0,
+            max_clipped: None,
+            force: false,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Update the fixes with the fix of a new package, and look ahead for the next fix.
+    fn update_fixes(&mut self, pck: &AxlPacket) {
+        let [prev, current] = self.fix;
+
+        let (prev, current) = match Fix::of(pck) {
+            Some(f) if current.map_or(true, |c| f.time > c.time) => (current, Some(f)),
+            _ => (prev, current),
+        };
+
+        let after = current.map_or(i64::MIN, |c| c.time);
+        let mut next = None;
+
+        for k in 0..FIX_LOOKAHEAD {
+            if k == self.ahead.len() {
+                match self.packages.next() {
+                    Some(p) => self.ahead.push_back(p),
+                    None => break,
+                }
+            }
+
+            if let Ok(Package { pck: Ok(p), .. }) = &self.ahead[k] {
+                if let Some(f) = Fix::of(p).filter(|f| f.time > after) {
+                    next = Some(f);
+                    break;
+                }
+            }
+        }
+
+        self.fix = [prev, current];
+        self.fixes = [prev, current, next].into_iter().flatten().collect();
+    }
+
+    /// Limit the samples to the period of `split` of the next sample. The iterator ends at the
+    /// first sample outside the period, and continues with the next call. Returns the start of
+    /// the period [ms], `None` when there are no more samples.
+    pub fn next_period(&mut self, split: Split) -> Option<std::io::Result<i64>> {
+        let s = match self.held.take().map(Ok).or_else(|| self.next_sample())? {
+            Ok(s) => s,
+            Err(e) => return Some(Err(e)),
+        };
+
+        self.period = split.period(s.timestamp);
+        let start = self.period.as_ref().map_or(s.timestamp, |p| p.start);
+        self.held = Some(s);
+
+        Some(Ok(start))
+    }
+
+    fn next_sample(&mut self) -> Option<std::io::Result<Sample>> {
+        loop {
+            if let Some(pck) = &self.pck {
+                if self.i < pck.samples() {
+                    let w = pck.width();
+                    let d = &pck.data[self.i * w..(self.i + 1) * w];
+                    let axis = |a| pck.column(a).map_or(f32::NAN, |c| pck.accel(d[c]));
+                    let dt = (self.i as f64 - pck.offset as f64) * 1000. / pck.freq as f64;
+                    let timestamp = pck.timestamp + dt.round() as i64;
+
+                    let (lat, lon, nearest_fix) =
+                        interpolate(&self.fixes, timestamp).unwrap_or((pck.lat, pck.lon, true));
+
+                    let gyro = match pck.gyro.get(self.i * SAMPLE_SZ..(self.i + 1) * SAMPLE_SZ) {
+                        Some(g) => [0, 1, 2].map(|a| pck.gyro_rate(g[a])),
+                        None => [f32::NAN; 3],
+                    };
+
+                    let s = Sample {
+                        timestamp,
+                        x: axis(0),
+                        y: axis(1),
+                        z: axis(2),
+                        lat,
+                        lon,
+                        seq: self.seq,
+                        quality: pck.quality,
+                        nearest_fix,
+                        dop: pck.dop,
+                        gyro,
+                    };
+
+                    self.i += 1;
+                    self.seq += 1;
+
+                    return Some(Ok(s));
+                }
+            }
+
+            let next = match self.ahead.pop_front() {
+                Some(p) => p,
+                None => self.packages.next()?,
+            };
+
+            match next {
+                Ok(p) => {
+                    self.stats.packages += 1;
+                    self.i = 0;
+
+                    if let Ok(pck) = &p.pck {
+                        self.update_fixes(pck);
+                    }
+
+                    match p.pck {
+                        Ok(pck) if quality::score(pck.quality) < self.min_quality => {
+                            self.stats.low_quality += 1;
+                            self.pck = None;
+                        }
+                        Ok(pck) if self.max_clipped.map_or(false, |m| pck.clipped_samples > m) => {
+                            self.stats.clipped += 1;
+                            self.pck = None;
+                        }
+                        Ok(pck) => {
+                            let frame = *self.stats.frame.get_or_insert(pck.frame);
+
+                            if pck.frame != frame {
+                                let msg = format!(
+                                    "package {}: frame {} differs from {} of the first package",
+                                    p.index,
+                                    frame_name(pck.frame),
+                                    frame_name(frame)
+                                );
+
+                                if !self.force {
+                                    return Some(Err(std::io::Error::new(
+                                        std::io::ErrorKind::InvalidData,
+                                        format!("{} (use --force to export anyway)", msg),
+                                    )));
+                                }
+
+                                if self.stats.mixed_frames == 0 {
+                                    eprintln!("{}, exporting anyway.", msg);
+                                }
+                                self.stats.mixed_frames += 1;
+                            }
+
+                            self.stats.freq.get_or_insert(pck.freq);
+                            self.pck = Some(pck);
+                        }
+                        Err(e) => {
+                            eprintln!("package {}: failed to decode, skipping: {}", p.index, e);
+                            self.stats.corrupt += 1;
+                            self.pck = None;
+                        }
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = std::io::Result<Package>>> Iterator for Samples<I> {
+    type Item = std::io::Result<Sample>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = match self.held.take().map(Ok).or_else(|| self.next_sample())? {
+            Ok(s) => s,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if let Some(p) = &self.period {
+            if !p.contains(&s.timestamp) {
+                self.held = Some(s);
+                return None;
+            }
+        }
+
+        self.stats.samples += 1;
+
+        Some(Ok(s))
+    }
+}
+
+impl Export {
+    pub fn run(&self) -> anyhow::Result<()> {
+        eprintln!("Exporting collection from: {:?}", self.file);
+
+        let reader = PackageReader::open(&self.file, self.raw)?.gyro(self.gyro);
+        let version = reader.version;
+        let mut samples = Samples::new(reader);
+        samples.min_quality = self.min_quality;
+        samples.force = self.force;
+
+        if self.split == Split::None {
+            self.write(&mut samples, &self.output, version)?;
+        } else {
+            std::fs::create_dir_all(&self.output)?;
+
+            let mut written = HashSet::new();
+
+            while let Some(start) = samples.next_period(self.split) {
+                let name = self.split.name(start?);
+
+                // The collection went back to a period that has already been written.
+                let name = (0..)
+                    .map(|k| match k {
+                        0 => name.clone(),
+                        k => format!("{}.{}", name, k),
+                    })
+                    .find(|n| written.insert(n.clone()))
+                    .unwrap();
+
+                let output = self
+                    .output
+                    .join(format!("{}.{}", name, self.format.extension()));
+                self.write(&mut samples, &output, version)?;
+            }
+
+            eprintln!("Wrote {} files.", written.len());
+        }
+
+        eprintln!(
+            "Exported {} samples from {} packages ({} corrupt, {} low quality) to: {:?}",
+            samples.stats.samples,
+            samples.stats.packages,
+            samples.stats.corrupt,
+            samples.stats.low_quality,
+            self.output
+        );
+
+        if samples.stats.mixed_frames > 0 {
+            eprintln!(
+                "Warning: {} packages are in another frame than the first package.",
+                samples.stats.mixed_frames
+            );
+        }
+
+        Ok(())
+    }
+
+    fn write<I: Iterator<Item = std::io::Result<Package>>>(
+        &self,
+        samples: &mut Samples<I>,
+        output: &Path,
+        version: u32,
+    ) -> anyhow::Result<()> {
+        match self.format {
+            Format::Csv => write_csv(samples, output, self.gyro),
+            Format::Parquet => write_parquet(samples, output, &self.file, version),
+        }
+    }
+}
+
+/// Write the samples as CSV, with the angular rate (`gx`, `gy`, `gz`) if `gyro`.
+pub fn write_csv(
+    samples: &mut impl Iterator<Item = std::io::Result<Sample>>,
+    output: impl AsRef<Path>,
+    gyro: bool,
+) -> anyhow::Result<()> {
+    let mut w = BufWriter::new(File::create(output)?);
+    write!(w, "timestamp,x,y,z,lat,lon,seq,quality,nearest_fix,dop")?;
+    if gyro {
+        write!(w, ",gx,gy,gz")?;
+    }
+    writeln!(w)?;
+
+    for s in samples {
+        let s = s?;
+        write!(
+            w,
+            "{},{},{},{},{},{},{},{},{},{}",
+            s.timestamp, s.x, s.y, s.z, s.lat, s.lon, s.seq, s.quality, s.nearest_fix as u8, s.dop
+        )?;
+        if gyro {
+            write!(w, ",{},{},{}", s.gyro[0], s.gyro[1], s.gyro[2])?;
+        }
+        writeln!(w)?;
+    }
+
+    w.flush()?;
+
+    Ok(())
+}
+
+fn schema() -> Schema {
+    Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("x", DataType::Float32, false),
+        Field::new("y", DataType::Float32, false),
+        Field::new("z", DataType::Float32, false),
+        Field::new("lat", DataType::Float64, false),
+        Field::new("lon", DataType::Float64, false),
+        Field::new("seq", DataType::UInt64, false),
+        Field::new("quality", DataType::UInt8, false),
+        Field::new("nearest_fix", DataType::Boolean, false),
+        Field::new("dop", DataType::Float32, false),
+    ])
+}
+
+fn batch(schema: &Arc<Schema>, rows: &[Sample]) -> anyhow::Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(rows.iter().map(|s| s.timestamp))
+                .with_timezone("UTC"),
+        ),
+        Arc::new(Float32Array::from_iter_values(rows.iter().map(|s| s.x))),
+        Arc::new(Float32Array::from_iter_values(rows.iter().map(|s| s.y))),
+        Arc::new(Float32Array::from_iter_values(rows.iter().map(|s| s.z))),
+        Arc::new(Float64Array::from_iter_values(rows.iter().map(|s| s.lat))),
+        Arc::new(Float64Array::from_iter_values(rows.iter().map(|s| s.lon))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|s| s.seq))),
+        Arc::new(UInt8Array::from_iter_values(rows.iter().map(|s| s.quality))),
+        Arc::new(BooleanArray::from(
+            rows.iter().map(|s| s.nearest_fix).collect::<Vec<_>>(),
+        )),
+        Arc::new(Float32Array::from_iter_values(rows.iter().map(|s| s.dop))),
+    ];
+
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+pub fn write_parquet<I: Iterator<Item = std::io::Result<Package>>>(
+    samples: &mut Samples<I>,
+    output: impl AsRef<Path>,
+    collection: impl AsRef<Path>,
+    version: u32,
+) -> anyhow::Result<()> {
+    let start = samples.stats.clone();
+    let schema = Arc::new(schema());
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let mut w = ArrowWriter::try_new(File::create(output)?, schema.clone(), Some(props))?;
+    let mut rows = Vec::with_capacity(BATCH_SZ);
+
+    for s in samples.by_ref() {
+        rows.push(s?);
+
+        if rows.len() == BATCH_SZ {
+            w.write(&batch(&schema, &rows)?)?;
+            rows.clear();
+        }
+    }
+
+    if !rows.is_empty() {
+        w.write(&batch(&schema, &rows)?)?;
+    }
+
+    // Statistics of the samples in this file, the export may be split over several files.
+    let stats = &samples.stats;
+    let name = collection
+        .as_ref()
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    for (k, v) in [
+        ("sfy.collection", name),
+        ("sfy.format_version", version.to_string()),
+        (
+            "sfy.packages",
+            (stats.packages - start.packages).to_string(),
+        ),
+        ("sfy.corrupt", (stats.corrupt - start.corrupt).to_string()),
+        (
+            "sfy.low_quality",
+            (stats.low_quality - start.low_quality).to_string(),
+        ),
+        ("sfy.samples", (stats.samples - start.samples).to_string()),
+        (
+            "sfy.freq",
+            stats.freq.map(|f| f.to_string()).unwrap_or_default(),
+        ),
+        (
+            "sfy.frame",
+            match stats.frame {
+                _ if stats.mixed_frames > start.mixed_frames => "mixed".to_string(),
+                Some(f) => frame_name(f),
+                None => String::new(),
+            },
+        ),
+    ] {
+        w.append_key_value_metadata(KeyValue::new(k.to_string(), v));
+    }
+
+    w.close()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use sfy::axl::{AxlPacketBuilder, VERSION};
+    use sfy::waves::wire::ACCEL_MAX;
+
+    /// Package of `samples` samples at `freq`, stored with ID 0.
+    fn package(timestamp: i64, freq: f32, samples: usize) -> AxlPacketBuilder {
+        let data = (0..samples * SAMPLE_SZ).map(|v| v as u16).collect();
+
+        AxlPacketBuilder::new(timestamp, freq, ACCEL_MAX, data)
+            .storage(Some(0), VERSION)
+            .position(0, 5.3, 60.4, 0.)
+    }
+
+    #[test]
+    fn samples_v5() {
+        let mut samples = Samples::new(PackageReader::open("tests/data/44.5", false).unwrap());
+
+        let first = samples.next().unwrap().unwrap();
+        assert_eq!(first.seq, 0);
+
+        let n = 1 + samples.by_ref().count() as u64;
+        assert_eq!(samples.stats.samples, n);
+        assert_eq!(samples.stats.packages, 100);
+        assert_eq!(samples.stats.corrupt, 0);
+    }
+
+    #[test]
+    fn csv_rows() {
+        let c = crate::collection::Collection::from_file("tests/data/73.1").unwrap();
+        let total: usize = c.iter().map(|p| p.samples()).sum();
+
+        let output = std::env::temp_dir().join("sfypack-export-73.csv");
+        let mut samples = Samples::new(PackageReader::open("tests/data/73.1", false).unwrap());
+        write_csv(&mut samples, &output, false).unwrap();
+
+        let rows = std::fs::read_to_string(&output).unwrap().lines().count() - 1;
+        std::fs::remove_file(&output).unwrap();
+
+        assert_eq!(rows, total);
+        assert_eq!(samples.stats.packages, c.len());
+    }
+
+    #[test]
+    fn partial_package() {
+        use sfy::axl::AXL_POSTCARD_SZ;
+
+        // Flushed after 100 samples, see `sfy::waves::FlushPolicy`.
+        let pck = package(1_700_000_000_000, 52.0, 100).build().unwrap();
+
+        let mut buf: Vec<u8> = pck.to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+        buf.resize(AXL_POSTCARD_SZ, 0);
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf), false, VERSION);
+        let samples = Samples::new(reader)
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(samples.len(), 100);
+        assert_eq!(samples[0].timestamp, pck.timestamp);
+        assert_eq!(
+            samples[99].timestamp,
+            pck.timestamp + (99. * 1000. / 52.0f64).round() as i64
+        );
+    }
+
+    #[test]
+    fn gyro_columns() {
+        use sfy::axl::GYRO_POSTCARD_SZ;
+
+        let data: heapless::Vec<u16, { sfy::axl::AXL_SZ }> =
+            (0..10 * SAMPLE_SZ).map(|v| v as u16).collect();
+        let gyro = (0..10 * SAMPLE_SZ).map(|_| u16::MAX).collect();
+        let pck = AxlPacketBuilder::new(1_700_000_000_000, 52.0, ACCEL_MAX, data)
+            .gyro(gyro)
+            .build()
+            .unwrap();
+
+        let mut buf: Vec<u8> = pck.to_cobs::<GYRO_POSTCARD_SZ>().unwrap().to_vec();
+        buf.resize(GYRO_POSTCARD_SZ, 0);
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf), false, VERSION).gyro(true);
+        let output = std::env::temp_dir().join("sfypack-export-gyro.csv");
+        write_csv(&mut Samples::new(reader), &output, true).unwrap();
+
+        let csv = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().ends_with(",dop,gx,gy,gz"));
+        assert_eq!(lines.clone().count(), 10);
+
+        let g = sfy::waves::wire::GYRO_MAX.to_string();
+        assert!(lines.all(|l| l.ends_with(&format!(",{g},{g},{g}"))));
+    }
+
+    #[test]
+    fn interpolate_between_fixes() {
+        let a = Fix {
+            time: 0,
+            lat: 60.0,
+            lon: 5.0,
+        };
+        let b = Fix {
+            time: 1000,
+            lat: 61.0,
+            lon: 4.0,
+        };
+
+        assert_eq!(interpolate(&[a, b], 250), Some((60.25, 4.75, false)));
+        assert_eq!(interpolate(&[a, b], 1000), Some((61.0, 4.0, false)));
+        assert_eq!(interpolate(&[a, b], 2000), Some((61.0, 4.0, true)));
+        assert_eq!(interpolate(&[b], 0), Some((61.0, 4.0, true)));
+        assert_eq!(interpolate(&[], 0), None);
+    }
+
+    #[test]
+    fn interpolated_position() {
+        use sfy::axl::AXL_POSTCARD_SZ;
+
+        let t0 = 1_700_000_000_000;
+
+        // Fix at the start of each package, 1 Hz.
+        let pck = |timestamp: i64, lat, lon, dop| {
+            package(timestamp, 1.0, 10)
+                .position((timestamp / 1000) as u32, lon, lat, dop)
+                .build()
+                .unwrap()
+        };
+
+        let mut buf = Vec::new();
+        for p in [pck(t0, 60.0, 5.0, 1.2), pck(t0 + 10_000, 60.1, 5.2, 2.5)] {
+            let mut b: Vec<u8> = p.to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+            b.resize(AXL_POSTCARD_SZ, 0);
+            buf.extend(b);
+        }
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf), false, VERSION);
+        let s = Samples::new(reader)
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(s.len(), 20);
+
+        // Between the two fixes.
+        for (i, s) in s[..10].iter().enumerate() {
+            assert!((s.lat - (60.0 + 0.01 * i as f64)).abs() < 1.0e-9, "{:?}", s);
+            assert!((s.lon - (5.0 + 0.02 * i as f64)).abs() < 1.0e-9, "{:?}", s);
+            assert!(!s.nearest_fix);
+            assert_eq!(s.dop, 1.2);
+        }
+
+        // At the second fix, and after it with no later fix.
+        assert_eq!(
+            (s[10].lat, s[10].lon, s[10].nearest_fix),
+            (60.1, 5.2, false)
+        );
+        assert!(s[11..]
+            .iter()
+            .all(|s| s.lat == 60.1 && s.lon == 5.2 && s.nearest_fix && s.dop == 2.5));
+    }
+
+    #[test]
+    fn min_quality() {
+        use sfy::axl::AXL_POSTCARD_SZ;
+
+        let pck = |quality| {
+            package(1_700_000_000_000, 52.0, 10)
+                .quality(quality)
+                .build()
+                .unwrap()
+        };
+
+        let mut buf = Vec::new();
+        for q in [0, quality::GPS_STALE, quality::GPS_STALE | quality::CLIPPED] {
+            let mut b: Vec<u8> = pck(q).to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+            b.resize(AXL_POSTCARD_SZ, 0);
+            buf.extend(b);
+        }
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf), false, VERSION);
+        let mut samples = Samples::new(reader);
+        samples.min_quality = quality::CHECKS - 1;
+
+        let s = samples
+            .by_ref()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(s.len(), 20);
+        assert_eq!(s[10].quality, quality::GPS_STALE);
+        assert_eq!(samples.stats.low_quality, 1);
+    }
+
+    #[test]
+    fn max_clipped() {
+        use sfy::axl::AXL_POSTCARD_SZ;
+
+        let pck = |clipped_samples| {
+            package(1_700_000_000_000, 52.0, 10)
+                .clipped_samples(clipped_samples)
+                .build()
+                .unwrap()
+        };
+
+        let mut buf = Vec::new();
+        for c in [0, 3, 40] {
+            let mut b: Vec<u8> = pck(c).to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+            b.resize(AXL_POSTCARD_SZ, 0);
+            buf.extend(b);
+        }
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf), false, VERSION);
+        let mut samples = Samples::new(reader);
+        samples.max_clipped = Some(3);
+
+        let s = samples
+            .by_ref()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(s.len(), 20);
+        assert_eq!(samples.stats.clipped, 1);
+        assert_eq!(samples.stats.packages, 3);
+    }
+
+    #[test]
+    fn mixed_frames() {
+        use sfy::axl::AXL_POSTCARD_SZ;
+
+        let pck = |frame: Frame| {
+            package(1_700_000_000_000, 52.0, 10)
+                .frame(frame.code())
+                .build()
+                .unwrap()
+        };
+
+        let mut buf = Vec::new();
+        for f in [Frame::Earth, Frame::Earth, Frame::Corrected] {
+            let mut b: Vec<u8> = pck(f).to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+            b.resize(AXL_POSTCARD_SZ, 0);
+            buf.extend(b);
+        }
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf.clone()), false, VERSION);
+        let e = Samples::new(reader)
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("corrected"), "{}", e);
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf), false, VERSION);
+        let mut samples = Samples::new(reader);
+        samples.force = true;
+
+        let s = samples
+            .by_ref()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(s.len(), 30);
+        assert_eq!(samples.stats.frame, Some(Frame::Earth.code()));
+        assert_eq!(samples.stats.mixed_frames, 1);
+    }
+
+    #[test]
+    fn vertical_only() {
+        use sfy::axl::AXL_POSTCARD_SZ;
+        use sfy::waves::wire::{ScaledF32, A16};
+
+        let data = (0..10)
+            .map(|i| A16::from_f32(i as f32 / 10.).to_u16())
+            .collect();
+        let pck = AxlPacketBuilder::new(1_700_000_000_000, 52.0, ACCEL_MAX, data)
+            .storage(Some(0), VERSION)
+            .position(0, 5.3, 60.4, 0.)
+            .axes(0b100)
+            .build()
+            .unwrap();
+
+        let mut buf: Vec<u8> = pck.to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+        buf.resize(AXL_POSTCARD_SZ, 0);
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf), false, VERSION);
+        let s = Samples::new(reader)
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(s.len(), 10);
+        assert!(s.iter().all(|s| s.x.is_nan() && s.y.is_nan()));
+        assert!((s[5].z - 0.5).abs() < 1.0e-3);
+        assert_eq!(
+            s[5].timestamp - s[0].timestamp,
+            (5000. / 52.0f64).round() as i64
+        );
+    }
+
+    #[test]
+    fn split_periods() {
+        let t = 1_700_000_000_000; // 2023-11-14 22:13:20
+
+        let p = Split::Hourly.period(t).unwrap();
+        assert_eq!(p, 1_699_999_200_000..1_700_002_800_000);
+        assert_eq!(Split::Hourly.name(p.start), "2023-11-14T22");
+        assert_eq!(
+            Split::Daily.name(Split::Daily.period(t).unwrap().start),
+            "2023-11-14"
+        );
+        assert_eq!(Split::None.period(t), None);
+    }
+
+    #[test]
+    fn split_straddling_package() {
+        use sfy::axl::AXL_POSTCARD_SZ;
+
+        // 10 samples at 1 Hz, 4 before the hour and 6 after.
+        let hour = 1_699_999_200_000;
+        let pck = package(hour - 4000, 1.0, 10).build().unwrap();
+
+        let mut buf: Vec<u8> = pck.to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+        buf.resize(AXL_POSTCARD_SZ, 0);
+
+        let reader = PackageReader::new(std::io::Cursor::new(buf), false, VERSION);
+        let mut samples = Samples::new(reader);
+
+        let mut periods = Vec::new();
+        while let Some(start) = samples.next_period(Split::Hourly) {
+            let s = samples
+                .by_ref()
+                .collect::<std::io::Result<Vec<_>>>()
+                .unwrap();
+            periods.push((start.unwrap(), s));
+        }
+
+        assert_eq!(periods.len(), 2);
+        assert_eq!(periods[0].0, hour - 3600 * 1000);
+        assert_eq!(periods[0].1.len(), 4);
+        assert_eq!(periods[1].0, hour);
+        assert_eq!(periods[1].1.len(), 6);
+        assert_eq!(periods[1].1[0].timestamp, hour);
+        assert_eq!(periods[1].1[0].seq, 4);
+        assert_eq!(samples.stats.samples, 10);
+    }
+
+    #[test]
+    fn export_parquet() {
+        let out = std::env::temp_dir().join("sfypack-test-export.parquet");
+
+        let mut samples = Samples::new(PackageReader::open("tests/data/44.5", false).unwrap());
+        write_parquet(&mut samples, &out, "tests/data/44.5", 5).unwrap();
+
+        let r = ParquetRecordBatchReaderBuilder::try_new(File::open(&out).unwrap()).unwrap();
+
+        let kv = r.metadata().file_metadata().key_value_metadata().unwrap();
+        assert!(kv
+            .iter()
+            .any(|kv| kv.key == "sfy.collection" && kv.value.as_deref() == Some("44.5")));
+        assert!(kv
+            .iter()
+            .any(|kv| kv.key == "sfy.frame" && kv.value.as_deref() == Some("earth")));
+
+        let rows: usize = r.build().unwrap().map(|b| b.unwrap().num_rows()).sum();
+        assert_eq!(rows as u64, samples.stats.samples);
+
+        std::fs::remove_file(&out).unwrap();
+    }
+}
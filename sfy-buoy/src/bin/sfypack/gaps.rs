@@ -0,0 +1,126 @@
+//! Gaps in time between the packages of a collection, to check that the samples are continuous
+//! before they are used for spectral analysis (see `Collection::gaps`).
+//!
+//! Packages are lost e.g. when the IMU FIFO overflows or a full queue discards data. A gap is the
+//! time between the end of a package (its timestamp plus the duration of its samples) and the
+//! start of the next. Gaps within `--tolerance` are jitter of the timestamps, and not listed.
+//! `sfypack manifest` lists the same gaps along with the other integrity issues.
+
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use std::path::PathBuf;
+
+use crate::collection::{duration_ms, Collection};
+use crate::manifest::TIME_TOLERANCE_MS;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "gaps")]
+/// List gaps in time between the packages of a collection.
+pub struct Gaps {
+    #[argh(positional, description = "collection file")]
+    file: PathBuf,
+
+    #[argh(
+        option,
+        default = "TIME_TOLERANCE_MS as u32",
+        description = "largest deviation that is not a gap [ms] (default: 1000)"
+    )]
+    tolerance: u32,
+
+    #[argh(switch, description = "input file with raw-data")]
+    raw: bool,
+}
+
+fn time(timestamp: i64) -> String {
+    NaiveDateTime::from_timestamp_opt(
+        timestamp.div_euclid(1000),
+        (timestamp.rem_euclid(1000) * 1_000_000) as u32,
+    )
+    .map(|t| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+    .unwrap_or_else(|| timestamp.to_string())
+}
+
+impl Gaps {
+    pub fn run(&self) -> anyhow::Result<()> {
+        eprintln!("Loading collection from: {:?}", self.file);
+
+        let c = match self.raw {
+            false => Collection::from_file(&self.file),
+            true => Collection::from_file_raw(&self.file),
+        }?;
+        anyhow::ensure!(!c.is_empty(), "no packages in collection");
+
+        let gaps = c.gaps(self.tolerance);
+
+        println!("index,time,gap_ms");
+        for (i, gap) in &gaps {
+            println!("{},{},{}", i, time(c[*i].timestamp), gap);
+        }
+
+        let first = c.first().unwrap();
+        let last = c.last().unwrap();
+        let span = last.timestamp + duration_ms(last) - first.timestamp;
+        let missing = gaps.iter().map(|(_, g)| g.max(&0)).sum::<i64>();
+
+        eprintln!(
+            "{} gaps in {} packages, {:.1} s missing of {:.1} s ({:.2}%).",
+            gaps.len(),
+            c.len(),
+            missing as f64 / 1000.,
+            span as f64 / 1000.,
+            100. * missing as f64 / span.max(1) as f64
+        );
+
+        if !c.skipped.is_empty() || !c.mismatched.is_empty() {
+            eprintln!(
+                "Warning: {} corrupt packages were skipped, and show up as gaps.",
+                c.skipped.len() + c.mismatched.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first `n` packages of a collection, made continuous.
+    fn continuous(n: usize) -> Collection {
+        let mut c = Collection::from_file("tests/data/44.5").unwrap();
+        c.pcks.truncate(n);
+
+        for i in 1..n {
+            c.pcks[i].timestamp = c.pcks[i - 1].timestamp + duration_ms(&c.pcks[i - 1]);
+        }
+
+        assert_eq!(c.gaps(0), []);
+        c
+    }
+
+    #[test]
+    fn removed_package() {
+        let mut c = continuous(4);
+
+        let removed = c.pcks.remove(2);
+        assert_eq!(
+            c.gaps(TIME_TOLERANCE_MS as u32),
+            [(2, duration_ms(&removed))]
+        );
+    }
+
+    #[test]
+    fn tolerance() {
+        let mut c = continuous(2);
+
+        let d = duration_ms(&c.pcks[0]);
+        c.pcks[1].timestamp = c.pcks[0].timestamp + d + 500;
+        assert_eq!(c.gaps(1000), []);
+        assert_eq!(c.gaps(100), [(1, 500)]);
+
+        // Overlapping packages.
+        c.pcks[1].timestamp = c.pcks[0].timestamp + d - 2000;
+        assert_eq!(c.gaps(1000), [(1, -2000)]);
+    }
+}
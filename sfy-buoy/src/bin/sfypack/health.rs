@@ -0,0 +1,240 @@
+//! Export the health records of the buoy (`HEALTH.LOG` on the SD-card, see `sfy::health`) as CSV,
+//! one row per record. Records that cannot be decoded (e.g. a record cut short by a power loss)
+//! are skipped with a warning.
+
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use sfy::adc::MAX_CHANNELS;
+use sfy::health::Health;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "health")]
+/// Export health records as CSV.
+pub struct HealthLog {
+    #[argh(positional, description = "health file (HEALTH.LOG)")]
+    file: PathBuf,
+
+    #[argh(option, short = 'o', description = "output file (default: stdout)")]
+    output: Option<PathBuf>,
+}
+
+/// Decode the records of a health file, the records that could not be decoded are returned as
+/// errors with the index of the record.
+pub fn decode(buf: &mut [u8]) -> Vec<Result<Health, (usize, sfy::axl::DecodeError)>> {
+    buf.split_inclusive_mut(|b| *b == 0)
+        .filter(|r| r.len() > 1)
+        .enumerate()
+        .map(|(i, r)| Health::decode(r).map_err(|e| (i, e)))
+        .collect()
+}
+
+pub fn write_csv(records: &[Health], mut w: impl Write) -> anyhow::Result<()> {
+    writeln!(
+        w,
+        "timestamp,time,last_sync,sync_attempts,sync_failures,dropped_low,dropped_normal,dropped_critical,note_queue,storage_queue,free_space,reset_cause,reboots,reboots_deployment,gps_sats,gps_fix,profile_interval,profile_imu,profile_location,profile_storage,profile_notecard,ct_conductivity,ct_temperature,ct_salinity,bearer,notecard,card_queue,adc_0,adc_1,adc_2,adc_3"
+    )?;
+
+    for h in records {
+        let time = NaiveDateTime::from_timestamp_opt(
+            h.timestamp.div_euclid(1000),
+            (h.timestamp.rem_euclid(1000) * 1_000_000) as u32,
+        )
+        .map(|t| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+        .unwrap_or_default();
+
+        // Awake time in each phase [ms], empty without the profile.
+        let profile = h
+            .profile
+            .map(|p| {
+                format!(
+                    "{},{},{},{},{}",
+                    p.interval, p.imu, p.location, p.storage, p.notecard
+                )
+            })
+            .unwrap_or_else(|| ",,,,".to_string());
+
+        // Conductivity [mS/cm], temperature [°C] and salinity, empty without the CT sensor.
+        let ct =
+            h.ct.map(|c| format!("{},{},{}", c.conductivity, c.temperature, c.salinity))
+                .unwrap_or_else(|| ",,".to_string());
+
+        // Auxiliary analog channels in the order of the config, empty without the channel.
+        let adc = (0..MAX_CHANNELS)
+            .map(|i| {
+                h.adc
+                    .as_ref()
+                    .and_then(|a| a.get(i))
+                    .map(|v| v.to_string())
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        // Bearer of the connection, empty if not known.
+        let bearer = h
+            .bearer
+            .map(|b| format!("{:?}", b).to_lowercase())
+            .unwrap_or_default();
+
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{},{},{},{},{:#x},{},{},{},{:?},{},{},{},{},{},{}",
+            h.timestamp,
+            time,
+            h.last_sync.map(|t| t.to_string()).unwrap_or_default(),
+            h.sync_attempts,
+            h.sync_failures,
+            h.dropped.low,
+            h.dropped.normal,
+            h.dropped.critical,
+            h.note_queue,
+            h.storage_queue,
+            h.free_space.map(|f| f.to_string()).unwrap_or_default(),
+            h.reset_cause,
+            h.reboots,
+            h.reboots_deployment,
+            h.gps.sats.map(|n| n.to_string()).unwrap_or_default(),
+            h.gps.fix,
+            profile,
+            ct,
+            bearer,
+            h.notecard.map(|n| n.to_string()).unwrap_or_default(),
+            h.card_queue.map(|n| n.to_string()).unwrap_or_default(),
+            adc
+        )?;
+    }
+
+    Ok(())
+}
+
+impl HealthLog {
+    pub fn run(&self) -> anyhow::Result<()> {
+        eprintln!("Loading health records from: {:?}", self.file);
+        let mut buf = std::fs::read(&self.file)?;
+
+        let records = decode(&mut buf)
+            .into_iter()
+            .filter_map(|r| {
+                r.inspect_err(|(i, e)| eprintln!("Skipping corrupt record {}: {:?}", i, e))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        eprintln!("Loaded {} records.", records.len());
+
+        match &self.output {
+            Some(o) => {
+                let mut w = BufWriter::new(std::fs::File::create(o)?);
+                write_csv(&records, &mut w)?;
+                w.flush()?;
+            }
+            None => write_csv(&records, std::io::stdout().lock())?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sfy::health::{HEALTH_RECORD_SZ, HEALTH_VERSION};
+
+    #[test]
+    fn skip_undecodable() {
+        let h = Health {
+            timestamp: 1_700_000_000_123,
+            last_sync: Some(1_699_999_000_000),
+            sync_attempts: 3,
+            sync_failures: 1,
+            note_queue: 2,
+            free_space: Some(1 << 30),
+            reset_cause: 0x2,
+            reboots: 7,
+            reboots_deployment: 2,
+            gps: sfy::gnss::Status {
+                sats: Some(0),
+                fix: sfy::gnss::Fix::NoSatellites,
+            },
+            ..Default::default()
+        };
+
+        let unsupported: heapless::Vec<u8, HEALTH_RECORD_SZ> =
+            postcard::to_vec_cobs(&(HEALTH_VERSION + 1, &h)).unwrap();
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&h.to_cobs().unwrap());
+        buf.extend_from_slice(&unsupported);
+        buf.extend_from_slice(&h.to_cobs().unwrap());
+
+        let records = decode(&mut buf);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].as_ref().unwrap(), &h);
+        assert!(matches!(records[1], Err((1, _))));
+        assert_eq!(records[2].as_ref().unwrap(), &h);
+
+        let records = [h];
+        let mut out = Vec::new();
+        write_csv(&records, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            out.lines().nth(1).unwrap(),
+            "1700000000123,2023-11-14T22:13:20.123Z,1699999000000,3,1,0,0,0,2,0,1073741824,0x2,7,2,0,NoSatellites,,,,,,,,,,,,,,,"
+        );
+
+        let records = [Health {
+            profile: Some(sfy::profile::Breakdown {
+                interval: 3_600_000,
+                imu: 40_000,
+                location: 1_200,
+                storage: 3_500,
+                notecard: 95_000,
+            }),
+            ..Default::default()
+        }];
+        let mut out = Vec::new();
+        write_csv(&records, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out
+            .lines()
+            .nth(1)
+            .unwrap()
+            .ends_with(",3600000,40000,1200,3500,95000,,,,,,,,,,"));
+
+        let records = [Health {
+            ct: Some(sfy::ct::Ct::new(42.914, 15.)),
+            ..Default::default()
+        }];
+        let mut out = Vec::new();
+        write_csv(&records, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.lines().nth(1).unwrap().contains(",,,,,42.914,15,"));
+
+        let records = [Health {
+            bearer: Some(sfy::transport::Bearer::Cell),
+            notecard: Some(1),
+            card_queue: Some(42),
+            ..Default::default()
+        }];
+        let mut out = Vec::new();
+        write_csv(&records, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.lines().nth(1).unwrap().ends_with(",,,cell,1,42,,,,"));
+
+        let records = [Health {
+            adc: Some(heapless::Vec::from_slice(&[12.5, 0.25]).unwrap()),
+            ..Default::default()
+        }];
+        let mut out = Vec::new();
+        write_csv(&records, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.lines().nth(1).unwrap().ends_with(",,12.5,0.25,,"));
+    }
+}
@@ -0,0 +1,181 @@
+//! Export the location log of the buoy (`LOCATION.LOG` on the SD-card, see `sfy::location_log`)
+//! as a CSV timeline, one row per event. The columns that do not apply to an event are left
+//! empty. Records that cannot be decoded are skipped with a warning.
+
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use sfy::location_log::{Event, Record};
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "locations")]
+/// Export the location log as a CSV timeline.
+pub struct LocationLog {
+    #[argh(positional, description = "location log (LOCATION.LOG)")]
+    file: PathBuf,
+
+    #[argh(option, short = 'o', description = "output file (default: stdout)")]
+    output: Option<PathBuf>,
+}
+
+/// Decode the records of a location log, the records that could not be decoded are returned as
+/// errors with the index of the record.
+pub fn decode(buf: &mut [u8]) -> Vec<Result<Record, (usize, sfy::axl::DecodeError)>> {
+    buf.split_inclusive_mut(|b| *b == 0)
+        .filter(|r| r.len() > 1)
+        .enumerate()
+        .map(|(i, r)| Record::decode(r).map_err(|e| (i, e)))
+        .collect()
+}
+
+fn format_time(t: i64) -> String {
+    NaiveDateTime::from_timestamp_opt(t.div_euclid(1000), (t.rem_euclid(1000) * 1_000_000) as u32)
+        .map(|t| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+        .unwrap_or_default()
+}
+
+pub fn write_csv(records: &[Record], mut w: impl Write) -> anyhow::Result<()> {
+    writeln!(
+        w,
+        "timestamp,time,event,retrieved,fixes,failures,rtc_before,rtc_after,rtc_jump,lat,lon,position_time,dop,accepted"
+    )?;
+
+    for r in records {
+        let (event, columns) = match r.event {
+            Event::State {
+                retrieved,
+                fixes,
+                failures,
+            } => (
+                "state",
+                format!("{},{},{},,,,,,,,", retrieved, fixes, failures),
+            ),
+            Event::RtcSet { before, after } => (
+                "rtc_set",
+                format!(",,,{},{},{},,,,,", before, after, after - before),
+            ),
+            Event::Fix {
+                lat,
+                lon,
+                position_time,
+                dop,
+                accepted,
+            } => (
+                "fix",
+                format!(
+                    ",,,,,,{},{},{},{},{}",
+                    lat, lon, position_time, dop, accepted
+                ),
+            ),
+        };
+
+        writeln!(
+            w,
+            "{},{},{},{}",
+            r.timestamp,
+            format_time(r.timestamp),
+            event,
+            columns
+        )?;
+    }
+
+    Ok(())
+}
+
+impl LocationLog {
+    pub fn run(&self) -> anyhow::Result<()> {
+        eprintln!("Loading location log from: {:?}", self.file);
+        let mut buf = std::fs::read(&self.file)?;
+
+        let records = decode(&mut buf)
+            .into_iter()
+            .filter_map(|r| {
+                r.inspect_err(|(i, e)| eprintln!("Skipping corrupt record {}: {:?}", i, e))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        eprintln!("Loaded {} records.", records.len());
+
+        match &self.output {
+            Some(o) => {
+                let mut w = BufWriter::new(std::fs::File::create(o)?);
+                write_csv(&records, &mut w)?;
+                w.flush()?;
+            }
+            None => write_csv(&records, std::io::stdout().lock())?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeline() {
+        let records = [
+            Record {
+                timestamp: 1_000,
+                event: Event::RtcSet {
+                    before: 1_000,
+                    after: 1_700_000_000_000,
+                },
+            },
+            Record {
+                timestamp: 1_700_000_000_123,
+                event: Event::Fix {
+                    lat: 60.5,
+                    lon: 5.25,
+                    position_time: 1_699_999_990,
+                    dop: 1.5,
+                    accepted: true,
+                },
+            },
+            Record {
+                timestamp: 1_700_000_000_200,
+                event: Event::State {
+                    retrieved: true,
+                    fixes: 2,
+                    failures: 0,
+                },
+            },
+        ];
+
+        let mut buf = Vec::new();
+        for r in &records {
+            buf.extend_from_slice(&r.to_cobs().unwrap());
+        }
+        // A record cut short by a power loss.
+        buf.extend_from_slice(&records[1].to_cobs().unwrap()[..8]);
+        buf.push(0);
+
+        let decoded = decode(&mut buf);
+        assert_eq!(decoded.len(), 4);
+        for (d, r) in decoded.iter().zip(&records) {
+            assert_eq!(d.as_ref().unwrap(), r);
+        }
+        assert!(matches!(decoded[3], Err((3, _))));
+
+        let mut out = Vec::new();
+        write_csv(&records, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+
+        assert_eq!(
+            lines[1],
+            "1000,1970-01-01T00:00:01.000Z,rtc_set,,,,1000,1700000000000,1699999999000,,,,,"
+        );
+        assert_eq!(
+            lines[2],
+            "1700000000123,2023-11-14T22:13:20.123Z,fix,,,,,,,60.5,5.25,1699999990,1.5,true"
+        );
+        assert_eq!(
+            lines[3],
+            "1700000000200,2023-11-14T22:13:20.200Z,state,true,2,0,,,,,,,,"
+        );
+    }
+}
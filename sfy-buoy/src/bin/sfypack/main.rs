@@ -0,0 +1,558 @@
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use serde_json as json;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+mod allan;
+mod burst;
+mod calibrate;
+mod collection;
+mod decode_note;
+mod diff;
+mod directional;
+mod export;
+mod gaps;
+mod health;
+mod locations;
+mod manifest;
+mod merge;
+mod postmortem;
+mod repair;
+mod spectrogram;
+mod stats;
+mod thermal;
+
+use collection::{AxlNote, Collection};
+use sfy::axl::AxlPacket;
+
+#[derive(FromArgs)]
+/// Load and print Axl package from binary collection.
+struct SfyPack {
+    #[argh(subcommand)]
+    cmd: Option<Cmd>,
+
+    #[argh(positional, description = "file name")]
+    file: Option<PathBuf>,
+
+    #[argh(switch, short = 'l', description = "list packages")]
+    list: bool,
+
+    #[argh(switch, description = "export to JSON")]
+    json: bool,
+
+    #[argh(
+        option,
+        description = "print the packages to stdout: text (a line per package) or json"
+    )]
+    format: Option<Format>,
+
+    #[argh(switch, description = "simulate a note.add event")]
+    note: bool,
+
+    #[argh(switch, description = "input file with raw-data")]
+    raw: bool,
+}
+
+enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            f => Err(format!("unknown format: {} (expected: text or json)", f)),
+        }
+    }
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Cmd {
+    Manifest(manifest::Manifest),
+    Repair(repair::Repair),
+    Merge(merge::Merge),
+    Export(export::Export),
+    DecodeNote(decode_note::DecodeNote),
+    Diff(diff::Diff),
+    Calibrate(calibrate::Calibrate),
+    Thermal(thermal::Thermal),
+    Allan(allan::Allan),
+    Health(health::HealthLog),
+    Locations(locations::LocationLog),
+    Spectrogram(spectrogram::Spectrogram),
+    Directional(directional::Directional),
+    Postmortem(postmortem::Postmortem),
+    Burst(burst::Burst),
+    Stats(stats::Stats),
+    Gaps(gaps::Gaps),
+}
+
+fn main() -> anyhow::Result<()> {
+    let pck: SfyPack = argh::from_env();
+
+    match &pck.cmd {
+        Some(Cmd::Manifest(m)) => m.run(),
+        Some(Cmd::Repair(r)) => r.run(),
+        Some(Cmd::Merge(m)) => m.run(),
+        Some(Cmd::Export(e)) => e.run(),
+        Some(Cmd::DecodeNote(d)) => d.run(),
+        Some(Cmd::Diff(d)) => d.run(),
+        Some(Cmd::Calibrate(c)) => c.run(),
+        Some(Cmd::Thermal(t)) => t.run(),
+        Some(Cmd::Allan(a)) => a.run(),
+        Some(Cmd::Health(h)) => h.run(),
+        Some(Cmd::Locations(l)) => l.run(),
+        Some(Cmd::Spectrogram(s)) => s.run(),
+        Some(Cmd::Directional(d)) => d.run(),
+        Some(Cmd::Postmortem(p)) => p.run(),
+        Some(Cmd::Burst(b)) => b.run(),
+        Some(Cmd::Stats(s)) => s.run(),
+        Some(Cmd::Gaps(g)) => g.run(),
+        None => pack(pck),
+    }
+}
+
+fn pack(pck: SfyPack) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        [pck.json, pck.note, pck.format.is_some()]
+            .iter()
+            .filter(|o| **o)
+            .count()
+            <= 1,
+        "only one of --json, --note and --format may be specified at the same time"
+    );
+
+    let file = pck
+        .file
+        .ok_or_else(|| anyhow::anyhow!("no collection file specified"))?;
+    eprintln!("Loading collection from: {:?}", file);
+
+    let c = match pck.raw {
+        false => Collection::from_file(&file),
+        true => Collection::from_file_raw(&file),
+    }?;
+    eprintln!("Loaded {} packages.", c.len());
+
+    if let Some(s) = card_summary(&c.pcks) {
+        eprintln!("{}", s);
+    }
+
+    if let Some(s) = clipped_summary(&c.pcks) {
+        eprintln!("{}", s);
+    }
+
+    if c.is_empty() {
+        eprintln!(
+            "No packages in {:?}: the file is empty or shorter than one package ({} bytes).",
+            file,
+            if pck.raw {
+                sfy::storage::PACKAGE_SZ
+            } else {
+                sfy::axl::AXL_POSTCARD_SZ
+            }
+        );
+    }
+
+    if pck.list {
+        eprintln!(
+            "Filter start-up transient: {} samples discarded after IMU reset.",
+            sfy::fir::WARMUP
+        );
+
+        for p in c.iter() {
+            let ts = NaiveDateTime::from_timestamp(
+                p.timestamp / 1000,
+                (p.timestamp % 1000 * 1_000_000).try_into().unwrap(),
+            );
+            eprintln!("{:?}: {:?}", ts, p);
+        }
+
+        eprintln!("Listed {} packages.", c.len());
+    }
+
+    match pck.format {
+        Some(Format::Text) => {
+            for p in c.iter() {
+                println!("{}", summary(p));
+            }
+            println!("{}", total(&c.pcks));
+        }
+        Some(Format::Json) => println!("{}", json::to_string_pretty(&c.pcks)?),
+        None => (),
+    }
+
+    match (pck.json, pck.note) {
+        (true, false) => {
+            if let Some(raw) = &c.raw {
+                println!("{}", json::to_string_pretty(&(&c, &raw)).unwrap());
+            } else {
+                println!("{}", json::to_string_pretty(&c).unwrap());
+            }
+        }
+        (false, true) => {
+            let pcks = if let Some(raw) = c.raw {
+                c.pcks
+                    .iter()
+                    .zip(raw)
+                    .map(|(p, r)| AxlNote::from(p, Some(r)))
+                    .collect::<Vec<AxlNote>>()
+            } else {
+                c.pcks
+                    .iter()
+                    .map(|p| AxlNote::from(p, None))
+                    .collect::<Vec<AxlNote>>()
+            };
+            println!("{}", json::to_string_pretty(&pcks).unwrap());
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn time(timestamp: i64) -> String {
+    NaiveDateTime::from_timestamp_opt(
+        timestamp.div_euclid(1000),
+        (timestamp.rem_euclid(1000) * 1_000_000) as u32,
+    )
+    .map(|t| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+    .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// One line for a package: time, position, number of samples and offset in the IMU FIFO at the
+/// time of the timestamp (`AxlPacket::offset`).
+fn summary(p: &AxlPacket) -> String {
+    format!(
+        "{}  lon: {:.5}  lat: {:.5}  samples: {}  offset: {}",
+        time(p.timestamp),
+        p.lon,
+        p.lat,
+        p.samples(),
+        p.offset
+    )
+}
+
+/// Last line of the text format: the number of packages and samples.
+fn total(pcks: &[AxlPacket]) -> String {
+    format!(
+        "Total: {} packages, {} samples.",
+        pcks.len(),
+        pcks.iter().map(|p| p.samples()).sum::<usize>()
+    )
+}
+
+/// Range of `v` over the packages, and the number of packages that have it.
+fn range(pcks: &[AxlPacket], v: impl Fn(&AxlPacket) -> Option<f32>) -> Option<(f32, f32, usize)> {
+    pcks.iter().filter_map(v).fold(None, |r, v| match r {
+        None => Some((v, v, 1)),
+        Some((lo, hi, n)) => Some((lo.min(v), hi.max(v), n + 1)),
+    })
+}
+
+/// Summary of the battery voltage and the temperature of the board measured by the Notecard,
+/// `None` when no package has them (e.g. packages from before they were recorded).
+fn card_summary(pcks: &[AxlPacket]) -> Option<String> {
+    let battery = range(pcks, |p| p.battery_v);
+    let temperature = range(pcks, |p| p.temperature_c);
+
+    let mut s = Vec::new();
+    if let Some((lo, hi, n)) = battery {
+        s.push(format!("battery: {:.2} - {:.2} V ({} packages)", lo, hi, n));
+    }
+    if let Some((lo, hi, n)) = temperature {
+        s.push(format!(
+            "board temperature: {:.1} - {:.1} C ({} packages)",
+            lo, hi, n
+        ));
+    }
+
+    (!s.is_empty()).then(|| format!("Notecard: {}.", s.join(", ")))
+}
+
+/// Summary of the IMU samples at the full scale of the accelerometer (see
+/// `AxlPacket::clipped_samples`), `None` when no package has any.
+fn clipped_summary(pcks: &[AxlPacket]) -> Option<String> {
+    let clipped = pcks
+        .iter()
+        .map(|p| p.clipped_samples as u64)
+        .filter(|c| *c > 0)
+        .collect::<Vec<_>>();
+
+    let max = clipped.iter().max()?;
+
+    Some(format!(
+        "Clipped: {} of {} packages, {} IMU samples (at most {} in a package).",
+        clipped.len(),
+        pcks.len(),
+        clipped.iter().sum::<u64>(),
+        max
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_collection() {
+        let c = Collection::from_file("tests/data/73.1").unwrap();
+        println!("packages: {}", c.pcks.len());
+
+        let c = Collection::from_file("tests/data/74.1").unwrap();
+        println!("packages: {}", c.pcks.len());
+
+        // for p in c.pcks {
+        //     println!("Package: {:?}", p);
+        // }
+    }
+
+    /// Size of the deployed packages with the samples delta encoded, on the SD-card and in the
+    /// payload of the data notes (`cargo test delta_ratio -- --nocapture`).
+    #[test]
+    fn delta_ratio() {
+        use sfy::axl::{SampleEncoding, AXL_POSTCARD_SZ};
+
+        for f in ["tests/data/73.1", "tests/data/74.1"] {
+            let c = Collection::from_file(f).unwrap();
+            let (mut raw, mut delta) = (0, 0);
+            let (mut note_raw, mut note_delta) = (0, 0);
+
+            for mut pck in c.pcks {
+                let r = pck.to_cobs::<AXL_POSTCARD_SZ>().unwrap();
+                let mut d = pck.to_cobs_delta::<AXL_POSTCARD_SZ>().unwrap();
+                assert!(d.len() <= r.len());
+                raw += r.len();
+                delta += d.len();
+                assert_eq!(AxlPacket::from_cobs(&mut d).unwrap(), pck);
+
+                let (r, _) = pck.split();
+                let (d, b64) = pck.split_encoded(SampleEncoding::Delta);
+                assert!(d.length <= r.length);
+                note_raw += r.length;
+                note_delta += d.length;
+                assert_eq!(AxlPacket::from_note(&d, &b64).unwrap(), pck);
+            }
+
+            println!(
+                "{}: SD-card: {} -> {} bytes ({:.2}), notes: {} -> {} bytes ({:.2})",
+                f,
+                raw,
+                delta,
+                raw as f64 / delta as f64,
+                note_raw,
+                note_delta,
+                note_raw as f64 / note_delta as f64
+            );
+
+            assert!(delta < raw);
+            assert!(note_delta < note_raw);
+        }
+    }
+
+    #[test]
+    fn open_raw() {
+        let c = Collection::from_file_raw("tests/data/14.3").unwrap();
+        println!("packages: {}", c.pcks.len());
+
+        // println!("raw: {:?}", c.raw.unwrap()[0]);
+
+        let last = &c.raw.unwrap()[0];
+        println!("{}", json::to_string(&last).unwrap());
+
+        // doesn't work due to: https://github.com/starkat99/half-rs/issues/60
+        let f = half::f16::from_f32(45.0);
+        println!("{}", json::to_string(&f).unwrap());
+    }
+
+    #[test]
+    fn open_regular_v5() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+        println!("packages: {}", c.pcks.len());
+
+        assert!(c.raw.is_none());
+        assert_eq!(c.pcks.len(), 100);
+    }
+
+    #[test]
+    fn corrupt_package() {
+        use sfy::axl::AXL_POSTCARD_SZ;
+
+        let mut buf = std::fs::read("tests/data/44.5").unwrap();
+        let offset = 50 * AXL_POSTCARD_SZ;
+        buf[offset + 20] = 0;
+
+        let p = std::env::temp_dir().join("sfypack-corrupt.5");
+        std::fs::write(&p, &buf).unwrap();
+        let c = Collection::from_file(&p).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert_eq!(c.pcks.len(), 99);
+        assert_eq!(c.skipped, [offset]);
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        use sfy::axl::{crc32, AXL_POSTCARD_SZ, VERSION};
+
+        let mut buf = Vec::new();
+        for (i, mut pck) in Collection::from_file("tests/data/44.5")
+            .unwrap()
+            .pcks
+            .into_iter()
+            .take(3)
+            .enumerate()
+        {
+            pck.crc = crc32(&pck.data);
+            if i == 1 {
+                pck.data[10] ^= 1;
+            }
+
+            let mut slot = pck.to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+            slot.resize(AXL_POSTCARD_SZ, 0);
+            buf.extend(slot);
+        }
+
+        let p = std::env::temp_dir().join(format!("sfypack-crc.{}", VERSION));
+        std::fs::write(&p, &buf).unwrap();
+        let c = Collection::from_file(&p).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert_eq!(c.pcks.len(), 2);
+        assert_eq!(c.skipped, []);
+        assert_eq!(c.mismatched, [AXL_POSTCARD_SZ]);
+    }
+
+    #[test]
+    fn mixed_encodings() {
+        use sfy::axl::{SampleEncoding, AXL_POSTCARD_SZ, VERSION};
+
+        let pcks = Collection::from_file("tests/data/44.5").unwrap().pcks;
+        let pcks = &pcks[..4];
+
+        let mut buf = Vec::new();
+        for (i, pck) in pcks.iter().enumerate() {
+            let encoding = if i % 2 == 0 {
+                SampleEncoding::Delta
+            } else {
+                SampleEncoding::Raw
+            };
+
+            let mut slot = pck
+                .clone()
+                .to_cobs_encoded::<AXL_POSTCARD_SZ>(encoding)
+                .unwrap()
+                .to_vec();
+            slot.resize(AXL_POSTCARD_SZ, 0);
+            buf.extend(slot);
+        }
+
+        let p = std::env::temp_dir().join(format!("sfypack-delta.{}", VERSION));
+        std::fs::write(&p, &buf).unwrap();
+        let c = Collection::from_file(&p).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert_eq!(c.skipped, []);
+        assert_eq!(c.pcks, pcks);
+    }
+
+    #[test]
+    fn card() {
+        let mut pcks = Collection::from_file("tests/data/44.5").unwrap().pcks;
+        pcks.truncate(3);
+        assert_eq!(card_summary(&pcks), None);
+
+        pcks[0].battery_v = Some(3.9);
+        pcks[2].battery_v = Some(3.71);
+        pcks[1].temperature_c = Some(12.5);
+        assert_eq!(
+            card_summary(&pcks).unwrap(),
+            "Notecard: battery: 3.71 - 3.90 V (2 packages), board temperature: 12.5 - 12.5 C \
+             (1 packages)."
+        );
+
+        // Exported with the packages.
+        let s = json::to_string(&pcks[0]).unwrap();
+        assert!(s.contains(r#""battery_v":3.9"#));
+        assert!(s.contains(r#""temperature_c":null"#));
+    }
+
+    #[test]
+    fn clipped() {
+        let mut pcks = Collection::from_file("tests/data/44.5").unwrap().pcks;
+        pcks.truncate(4);
+        assert_eq!(clipped_summary(&pcks), None);
+
+        pcks[1].clipped_samples = 12;
+        pcks[3].clipped_samples = 3;
+        assert_eq!(
+            clipped_summary(&pcks).unwrap(),
+            "Clipped: 2 of 4 packages, 15 IMU samples (at most 12 in a package)."
+        );
+    }
+
+    #[test]
+    fn text_format() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+        let p = &c.pcks[0];
+
+        let s = summary(p);
+        assert!(s.starts_with(&time(p.timestamp)));
+        assert!(s.ends_with(&format!("samples: {}  offset: {}", p.samples(), p.offset)));
+
+        let samples: usize = c.pcks.iter().map(|p| p.samples()).sum();
+        assert!(samples > 0);
+        assert_eq!(
+            total(&c.pcks),
+            format!("Total: 100 packages, {} samples.", samples)
+        );
+        assert_eq!(total(&[]), "Total: 0 packages, 0 samples.");
+
+        assert!("json".parse::<Format>().is_ok());
+        assert!("csv".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn empty_collection() {
+        let p = std::env::temp_dir().join(format!("sfypack-empty.{}", sfy::axl::VERSION));
+
+        for len in [0, 10] {
+            std::fs::write(&p, vec![0u8; len]).unwrap();
+            let c = Collection::from_file(&p).unwrap();
+            assert!(c.is_empty());
+        }
+
+        std::fs::remove_file(&p).unwrap();
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let p = std::env::temp_dir().join(format!("sfypack-test.{}", sfy::axl::VERSION + 1));
+        std::fs::copy("tests/data/44.5", &p).unwrap();
+
+        let e = Collection::from_file(&p).err().unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert!(e.to_string().contains("upgrade sfypack"));
+    }
+
+    #[ignore]
+    #[test]
+    fn open_raw_v5() {
+        let c = Collection::from_file_raw("tests/data/32.5").unwrap();
+        println!("packages: {}", c.pcks.len());
+
+        assert!(c.raw.is_some());
+        assert_eq!(c.pcks.len(), 32);
+
+        // println!("raw: {:?}", c.raw.unwrap()[0]);
+
+        let last = &c.raw.unwrap()[0];
+        println!("{}", json::to_string(&last).unwrap());
+    }
+}
@@ -0,0 +1,364 @@
+//! Manifest of a collection: one entry per package, and a summary of gaps and integrity issues.
+//!
+//! The `status` of a package is whether it could be decoded. Packages from version 19 carry a CRC
+//! of their samples (see `sfy::axl::AxlPacket::verify`), and a mismatch is listed as an issue.
+//!
+//! Packages with sample counts (see `sfy::axl::AxlPacket::raw_samples`) list the samples from
+//! the IMU per output sample in `decimation`, and packages where it does not match the rate of the
+//! package (see `AxlPacket::check_decimation`) are listed as issues.
+
+use argh::FromArgs;
+use serde_json as json;
+use std::path::{Path, PathBuf};
+
+use sfy::axl::AxlPacket;
+use sfy::storage::COLLECTION_SIZE;
+
+use crate::collection::{collection_number, duration_ms, PackageReader};
+
+/// Maximum deviation from expected time between packages before it is counted as a gap.
+pub const TIME_TOLERANCE_MS: i64 = 1000;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "manifest")]
+/// List packages, gaps and integrity issues in a collection.
+pub struct Manifest {
+    #[argh(positional, description = "collection file")]
+    file: PathBuf,
+
+    #[argh(switch, description = "input file with raw-data")]
+    raw: bool,
+
+    #[argh(switch, description = "output CSV instead of JSON")]
+    csv: bool,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Corrupt,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Entry {
+    /// Position in collection file.
+    pub index: usize,
+    pub id: Option<u32>,
+    pub timestamp: Option<i64>,
+    pub samples: usize,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+
+    /// Age of the position at the time of the package [s], `None` without a position. Stale
+    /// positions are flagged in `quality` (see `sfy::quality::GPS_STALE`).
+    pub fix_age: Option<u32>,
+    pub quality: Option<u8>,
+    pub status: Status,
+
+    /// Number of values at the limits of the scaled range.
+    pub clipped: usize,
+
+    /// Samples from the IMU per output sample, `None` if the sample counts were not recorded.
+    pub decimation: Option<f32>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct IdGap {
+    pub after: u32,
+    pub before: u32,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct TimeGap {
+    /// Index of package following the gap.
+    pub index: usize,
+    pub expected_ms: i64,
+    pub actual_ms: i64,
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct Summary {
+    pub packages: usize,
+    pub corrupt: usize,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub span_s: f64,
+    pub id_gaps: Vec<IdGap>,
+    pub time_gaps: Vec<TimeGap>,
+    pub issues: Vec<String>,
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct ManifestOut {
+    pub packages: Vec<Entry>,
+    pub summary: Summary,
+}
+
+impl Manifest {
+    pub fn run(&self) -> anyhow::Result<()> {
+        eprintln!("Loading collection from: {:?}", self.file);
+        let m = ManifestOut::from_file(&self.file, self.raw)?;
+
+        if self.csv {
+            print!("{}", m.to_csv());
+        } else {
+            println!("{}", json::to_string_pretty(&m)?);
+        }
+
+        Ok(())
+    }
+}
+
+impl ManifestOut {
+    pub fn from_file(p: impl AsRef<Path>, raw: bool) -> anyhow::Result<ManifestOut> {
+        let p = p.as_ref();
+
+        let collection = collection_number(p);
+
+        let mut m = ManifestOut::default();
+        let mut last: Option<AxlPacket> = None;
+
+        let reader = PackageReader::open(p, raw)?;
+        let version = reader.version;
+
+        for pck in reader {
+            let pck = pck?;
+            let s = &mut m.summary;
+
+            s.packages += 1;
+
+            match pck.pck {
+                Ok(cur) => {
+                    if let (Some(c), Some(id)) = (collection, cur.storage_id) {
+                        let expected = c * COLLECTION_SIZE + pck.index as u32;
+                        if id != expected {
+                            s.issues.push(format!(
+                                "package {}: storage id {} does not match position in collection (expected: {})",
+                                pck.index, id, expected
+                            ));
+                        }
+                    }
+
+                    if cur.storage_version != version {
+                        s.issues.push(format!(
+                            "package {}: storage version {} (expected: {})",
+                            pck.index, cur.storage_version, version
+                        ));
+                    }
+
+                    if let Some(last) = &last {
+                        if let (Some(a), Some(b)) = (last.storage_id, cur.storage_id) {
+                            if b != a + 1 {
+                                s.id_gaps.push(IdGap {
+                                    after: a,
+                                    before: b,
+                                });
+                            }
+                        }
+
+                        let expected_ms = duration_ms(last);
+                        let actual_ms = cur.timestamp - last.timestamp;
+
+                        if actual_ms < 0 {
+                            s.issues.push(format!(
+                                "package {}: timestamp goes backwards by {} ms",
+                                pck.index, -actual_ms
+                            ));
+                        }
+
+                        if (actual_ms - expected_ms).abs() > TIME_TOLERANCE_MS {
+                            s.time_gaps.push(TimeGap {
+                                index: pck.index,
+                                expected_ms,
+                                actual_ms,
+                            });
+                        }
+                    }
+
+                    if !cur.verify() {
+                        s.issues.push(format!(
+                            "package {}: samples do not match the CRC ({:#010x})",
+                            pck.index, cur.crc
+                        ));
+                    }
+
+                    let decimation = cur.decimation() as u32;
+                    if !cur.check_decimation(decimation) {
+                        s.issues.push(format!(
+                            "package {}: {} samples from the IMU for {} output samples (expected decimation: {})",
+                            pck.index, cur.raw_samples, cur.output_samples, decimation
+                        ));
+                    }
+
+                    s.start = Some(s.start.map_or(cur.timestamp, |t| t.min(cur.timestamp)));
+                    let end = cur.timestamp + duration_ms(&cur);
+                    s.end = Some(s.end.map_or(end, |t| t.max(end)));
+
+                    m.packages.push(Entry {
+                        index: pck.index,
+                        id: cur.storage_id,
+                        timestamp: Some(cur.timestamp),
+                        samples: cur.samples(),
+                        lat: Some(cur.lat),
+                        lon: Some(cur.lon),
+                        fix_age: (cur.position_time != 0).then(|| cur.fix_age()),
+                        quality: Some(cur.quality),
+                        status: Status::Ok,
+                        clipped: cur.clipped(),
+                        decimation: cur.decimation_ratio(),
+                    });
+
+                    last = Some(cur);
+                }
+                Err(e) => {
+                    s.corrupt += 1;
+                    s.issues
+                        .push(format!("package {}: failed to decode: {}", pck.index, e));
+
+                    m.packages.push(Entry {
+                        index: pck.index,
+                        id: None,
+                        timestamp: None,
+                        samples: 0,
+                        lat: None,
+                        lon: None,
+                        fix_age: None,
+                        quality: None,
+                        status: Status::Corrupt,
+                        clipped: 0,
+                        decimation: None,
+                    });
+                }
+            }
+        }
+
+        if let (Some(start), Some(end)) = (m.summary.start, m.summary.end) {
+            m.summary.span_s = (end - start) as f64 / 1000.;
+        }
+
+        Ok(m)
+    }
+
+    pub fn to_csv(&self) -> String {
+        fn opt<T: ToString>(v: &Option<T>) -> String {
+            v.as_ref().map(|v| v.to_string()).unwrap_or_default()
+        }
+
+        let mut out = String::from(
+            "index,id,timestamp,samples,lat,lon,fix_age,quality,status,clipped,decimation\n",
+        );
+
+        for e in &self.packages {
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{}\n",
+                e.index,
+                opt(&e.id),
+                opt(&e.timestamp),
+                e.samples,
+                opt(&e.lat),
+                opt(&e.lon),
+                opt(&e.fix_age),
+                opt(&e.quality),
+                match e.status {
+                    Status::Ok => "ok",
+                    Status::Corrupt => "corrupt",
+                },
+                e.clipped,
+                opt(&e.decimation)
+            ));
+        }
+
+        let s = &self.summary;
+        out.push_str(&format!("# packages: {}\n", s.packages));
+        out.push_str(&format!("# corrupt: {}\n", s.corrupt));
+        out.push_str(&format!("# start: {}\n", opt(&s.start)));
+        out.push_str(&format!("# end: {}\n", opt(&s.end)));
+        out.push_str(&format!("# span_s: {}\n", s.span_s));
+
+        for g in &s.id_gaps {
+            out.push_str(&format!("# id gap: {} -> {}\n", g.after, g.before));
+        }
+
+        for g in &s.time_gaps {
+            out.push_str(&format!(
+                "# time gap: before package {}, expected: {} ms, actual: {} ms\n",
+                g.index, g.expected_ms, g.actual_ms
+            ));
+        }
+
+        for i in &s.issues {
+            out.push_str(&format!("# issue: {}\n", i));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_v5() {
+        let m = ManifestOut::from_file("tests/data/44.5", false).unwrap();
+
+        assert_eq!(m.packages.len(), 100);
+        assert_eq!(m.summary.packages, 100);
+        assert_eq!(m.summary.corrupt, 0);
+        assert!(m.summary.span_s > 0.);
+
+        let csv = m.to_csv();
+        assert!(csv.starts_with("index,id,"));
+        assert_eq!(csv.lines().filter(|l| !l.starts_with('#')).count(), 101);
+
+        // Version 5 packages have no quality flags, but the age of the position is known.
+        assert!(m.packages.iter().all(|e| e.quality == Some(0)));
+        assert_eq!(m.packages[0].fix_age, Some(12158));
+    }
+    #[test]
+    fn decimation() {
+        use sfy::axl::{AXL_POSTCARD_SZ, VERSION};
+
+        let orig = std::fs::read("tests/data/44.5").unwrap();
+        let mut v5 = PackageReader::new(&orig[..], false, 5).map(|p| p.unwrap().pck.unwrap());
+
+        let mut collection = Vec::new();
+        for i in 0..3 {
+            let mut pck = v5.next().unwrap();
+            pck.storage_id = None;
+            pck.storage_version = VERSION;
+
+            let output = pck.samples() as u32;
+            let d = pck.decimation() as u32;
+            (pck.raw_samples, pck.output_samples) = match i {
+                0 => (output * d + 1, output),
+                // Under-read: samples of the FIFO lost before the filters.
+                1 => (output * d - 3 * d, output),
+                _ => (0, 0),
+            };
+
+            let mut slot = pck.to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+            slot.resize(AXL_POSTCARD_SZ, 0);
+            collection.extend(slot);
+        }
+
+        let p = std::env::temp_dir()
+            .join("sfypack-manifest-decimation")
+            .join(format!("0.{}", VERSION));
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(&p, &collection).unwrap();
+
+        let m = ManifestOut::from_file(&p, false).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        let d = v5.next().unwrap().decimation() as f32;
+        assert!((m.packages[0].decimation.unwrap() - d).abs() < 0.01);
+        assert!(m.packages[1].decimation.unwrap() < d);
+        assert_eq!(m.packages[2].decimation, None);
+
+        // Only the under-read is flagged.
+        assert_eq!(m.summary.issues.len(), 1, "{:?}", m.summary.issues);
+        assert!(m.summary.issues[0].starts_with("package 1: "));
+    }
+}
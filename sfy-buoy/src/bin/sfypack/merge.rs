@@ -0,0 +1,198 @@
+//! Merge the collections of a buoy into one collection sorted by time.
+//!
+//! The data of a deployment arrives as several collections: one per collection on the SD-card,
+//! and the packages resent through the notecard, which overlap and come out of order. The packages
+//! of every collection are loaded as by `sfypack` (packages that do not decode or do not match
+//! their CRC are skipped), sorted by timestamp, and the exact duplicates are dropped: packages
+//! with the same timestamp and the same samples, e.g. a package that was resent. Packages with the
+//! same timestamp and other samples are kept, in the order they were loaded.
+//!
+//! The merged collection is written in the current format version (`sfy::axl::VERSION`), every
+//! package padded to its slot, so the extension of the file name must be that version (e.g.
+//! `merged.22`). Sealed packages are opened with `SFY_KEY` when they are loaded, and written
+//! unsealed. The raw samples are not kept.
+
+use argh::FromArgs;
+use serde_json as json;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use sfy::axl::{AxlPacket, AXL_POSTCARD_SZ, VERSION};
+
+use crate::collection::{file_version, Collection};
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "merge")]
+/// Merge collections into one collection sorted by time, without duplicates.
+pub struct Merge {
+    #[argh(positional, description = "output collection file")]
+    output: PathBuf,
+
+    #[argh(positional, description = "collection files")]
+    files: Vec<PathBuf>,
+}
+
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct Report {
+    /// Packages loaded from the collections.
+    pub packages: usize,
+
+    /// Packages written to the merged collection.
+    pub kept: usize,
+
+    /// Exact duplicates dropped.
+    pub duplicates: usize,
+
+    /// Packages loaded with a timestamp before the package loaded before them.
+    pub out_of_order: usize,
+}
+
+impl Merge {
+    pub fn run(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.files.is_empty(), "no collection files specified");
+        anyhow::ensure!(
+            !self.files.contains(&self.output),
+            "output must differ from the collections"
+        );
+        anyhow::ensure!(
+            file_version(&self.output) == VERSION,
+            "the extension of the output file must be the format version: {}",
+            VERSION
+        );
+
+        let mut pcks = Vec::new();
+        for f in &self.files {
+            eprintln!("Loading collection from: {:?}", f);
+            let c = Collection::from_file(f)?;
+            eprintln!("Loaded {} packages.", c.len());
+
+            pcks.extend(c.pcks);
+        }
+
+        let (report, pcks) = merge(pcks);
+        write(&self.output, &pcks)?;
+
+        eprintln!(
+            "Wrote {} of {} packages to: {:?} ({} duplicates, {} out of order).",
+            report.kept, report.packages, self.output, report.duplicates, report.out_of_order
+        );
+        println!("{}", json::to_string_pretty(&report)?);
+
+        Ok(())
+    }
+}
+
+/// Sort the packages by timestamp and drop the exact duplicates, returning the report and the
+/// packages to keep.
+pub fn merge(mut pcks: Vec<AxlPacket>) -> (Report, Vec<AxlPacket>) {
+    let mut report = Report {
+        packages: pcks.len(),
+        out_of_order: pcks
+            .windows(2)
+            .filter(|w| w[1].timestamp < w[0].timestamp)
+            .count(),
+        ..Default::default()
+    };
+
+    // Stable: packages with the same timestamp stay in the order they were loaded.
+    pcks.sort_by_key(|p| p.timestamp);
+
+    let mut kept: Vec<AxlPacket> = Vec::with_capacity(pcks.len());
+    for p in pcks {
+        let duplicate = kept
+            .iter()
+            .rev()
+            .take_while(|k| k.timestamp == p.timestamp)
+            .any(|k| k.data == p.data && k.gyro == p.gyro);
+
+        if duplicate {
+            report.duplicates += 1;
+        } else {
+            kept.push(p);
+        }
+    }
+
+    report.kept = kept.len();
+
+    (report, kept)
+}
+
+/// Write the packages to a collection, every package padded to its slot.
+pub fn write(p: impl AsRef<Path>, pcks: &[AxlPacket]) -> anyhow::Result<()> {
+    let mut w = std::io::BufWriter::new(std::fs::File::create(p)?);
+
+    for pck in pcks {
+        let mut buf = pck
+            .to_cobs::<AXL_POSTCARD_SZ>()
+            .map_err(|e| anyhow::anyhow!("package at {}: {}", pck.timestamp, e))?
+            .to_vec();
+        buf.resize(AXL_POSTCARD_SZ, 0);
+
+        w.write_all(&buf)?;
+    }
+
+    w.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+        let p = |i: usize| c.pcks[i].clone();
+
+        // Two overlapping collections out of order, with a resent package.
+        let (report, pcks) = merge(vec![p(2), p(0), p(1), p(3), p(1)]);
+
+        assert_eq!(
+            report,
+            Report {
+                packages: 5,
+                kept: 4,
+                duplicates: 1,
+                out_of_order: 2,
+            }
+        );
+        assert!(pcks == [p(0), p(1), p(2), p(3)]);
+    }
+
+    #[test]
+    fn same_timestamp() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+
+        // Other samples at the same time are not a duplicate.
+        let mut other = c.pcks[0].clone();
+        other.data[0] ^= 1;
+
+        let (report, pcks) = merge(vec![c.pcks[0].clone(), other.clone()]);
+
+        assert_eq!((report.kept, report.duplicates), (2, 0));
+        assert!(pcks == [c.pcks[0].clone(), other]);
+    }
+
+    #[test]
+    fn written() {
+        let c = Collection::from_file("tests/data/44.5").unwrap();
+        let (_, pcks) = merge(c.pcks.iter().rev().cloned().collect());
+
+        let p = std::env::temp_dir()
+            .join("sfypack-merge")
+            .join(format!("merged.{}", VERSION));
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        write(&p, &pcks).unwrap();
+
+        assert_eq!(
+            std::fs::metadata(&p).unwrap().len() as usize,
+            pcks.len() * AXL_POSTCARD_SZ
+        );
+
+        let merged = Collection::from_file(&p).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert!(merged.pcks == c.pcks);
+    }
+}
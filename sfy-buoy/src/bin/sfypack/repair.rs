@@ -0,0 +1,236 @@
+//! Salvage a damaged collection: the packages that decode are written to a new collection, in
+//! order, and the rest are reported.
+//!
+//! A package is valid if it decodes and its samples match its CRC (see `manifest`, packages from
+//! before version 19 carry no CRC and are only decoded). Packages are removed if they are not
+//! valid, if they repeat the storage ID of an earlier package, or if
+//! their storage ID belongs to another collection (according to the file name). The remaining
+//! packages are sorted by storage ID (and timestamp), and written as they were stored: sealed
+//! packages stay sealed, and the raw samples are kept with `--raw`.
+//!
+//! The repaired collection is compacted, so a package is no longer at the position of its storage
+//! ID in the file. It is meant for `sfypack` on the host, not for putting back on the SD-card of
+//! the buoy. Keep the version extension of the file name (e.g. `44.5`), it gives the format of the
+//! packages.
+
+use argh::FromArgs;
+use serde_json as json;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use sfy::storage::COLLECTION_SIZE;
+
+use crate::collection::{collection_number, PackageReader};
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "repair")]
+/// Write the valid packages of a damaged collection to a new collection, and report the rest.
+pub struct Repair {
+    #[argh(positional, description = "collection file")]
+    file: PathBuf,
+
+    #[argh(option, short = 'o', description = "output collection file")]
+    output: PathBuf,
+
+    #[argh(switch, description = "input file with raw-data")]
+    raw: bool,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    /// The package could not be decoded, or its samples do not match its CRC.
+    Corrupt,
+
+    /// The storage ID of an earlier package.
+    Duplicate,
+
+    /// The storage ID belongs to another collection.
+    Foreign,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Removed {
+    /// Position in the damaged collection.
+    pub index: usize,
+    pub id: Option<u32>,
+    pub reason: Reason,
+
+    /// Decode error of a corrupt package.
+    pub error: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct Report {
+    /// Packages in the damaged collection.
+    pub packages: usize,
+
+    /// Packages written to the repaired collection.
+    pub kept: usize,
+
+    /// Whether the kept packages were out of order.
+    pub reordered: bool,
+    pub removed: Vec<Removed>,
+}
+
+impl Repair {
+    pub fn run(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.file != self.output,
+            "output must differ from the damaged collection"
+        );
+
+        eprintln!("Loading collection from: {:?}", self.file);
+        let (report, packages) = repair(&self.file, self.raw)?;
+
+        let mut w = std::io::BufWriter::new(std::fs::File::create(&self.output)?);
+        for p in &packages {
+            w.write_all(p)?;
+        }
+        w.flush()?;
+
+        eprintln!(
+            "Wrote {} of {} packages to: {:?}",
+            report.kept, report.packages, self.output
+        );
+        println!("{}", json::to_string_pretty(&report)?);
+
+        Ok(())
+    }
+}
+
+/// Repair the collection at `p`, returning the report and the stored packages to keep, in order.
+pub fn repair(p: impl AsRef<Path>, raw: bool) -> anyhow::Result<(Report, Vec<Vec<u8>>)> {
+    let p = p.as_ref();
+
+    let collection = collection_number(p);
+
+    let mut report = Report::default();
+    let mut ids = HashSet::new();
+    let mut kept = Vec::new();
+
+    for pck in PackageReader::open(p, raw)?.keep_bytes() {
+        let pck = pck?;
+        report.packages += 1;
+
+        let removed = |id, reason, error| Removed {
+            index: pck.index,
+            id,
+            reason,
+            error,
+        };
+
+        match pck.pck {
+            Ok(cur) if !cur.verify() => {
+                report.removed.push(removed(
+                    cur.storage_id,
+                    Reason::Corrupt,
+                    Some("samples do not match the CRC".into()),
+                ));
+            }
+            Ok(cur) => match cur.storage_id {
+                Some(id) if collection.map_or(false, |c| id / COLLECTION_SIZE != c) => {
+                    report
+                        .removed
+                        .push(removed(Some(id), Reason::Foreign, None));
+                }
+                Some(id) if !ids.insert(id) => {
+                    report
+                        .removed
+                        .push(removed(Some(id), Reason::Duplicate, None));
+                }
+                id => kept.push(((id.is_none(), id, cur.timestamp), pck.bytes.unwrap())),
+            },
+            Err(e @ sfy::axl::DecodeError::UnsupportedVersion(_)) => anyhow::bail!(e.to_string()),
+            Err(e) => {
+                report
+                    .removed
+                    .push(removed(None, Reason::Corrupt, Some(e.to_string())));
+            }
+        }
+    }
+
+    report.reordered = kept.windows(2).any(|w| w[0].0 > w[1].0);
+    kept.sort_by_key(|(k, _)| *k);
+    report.kept = kept.len();
+
+    Ok((report, kept.into_iter().map(|(_, b)| b).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sfy::axl::AXL_POSTCARD_SZ;
+
+    #[test]
+    fn damaged() {
+        let orig = std::fs::read("tests/data/44.5").unwrap();
+        let slot = |i: usize| orig[i * AXL_POSTCARD_SZ..(i + 1) * AXL_POSTCARD_SZ].to_vec();
+
+        // Two swapped packages, a repeated package and garbage from an interrupted write.
+        let mut damaged = Vec::new();
+        damaged.extend(slot(0));
+        damaged.extend(slot(2));
+        damaged.extend(slot(1));
+        damaged.extend(slot(1));
+        damaged.extend(vec![0xaa; AXL_POSTCARD_SZ]);
+        damaged.extend(slot(3));
+
+        let p = std::env::temp_dir().join("sfypack-repair").join("44.5");
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(&p, &damaged).unwrap();
+
+        let (report, packages) = repair(&p, false).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert_eq!((report.packages, report.kept), (6, 4));
+        assert!(report.reordered);
+        assert_eq!(
+            report
+                .removed
+                .iter()
+                .map(|r| (r.index, &r.reason))
+                .collect::<Vec<_>>(),
+            [(3, &Reason::Duplicate), (4, &Reason::Corrupt)]
+        );
+        assert_eq!(packages, [slot(0), slot(1), slot(2), slot(3)]);
+    }
+
+    #[test]
+    fn foreign() {
+        let orig = std::fs::read("tests/data/44.5").unwrap();
+
+        // The packages of collection 44 in a file named as collection 45.
+        let p = std::env::temp_dir()
+            .join("sfypack-repair-foreign")
+            .join("45.5");
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(&p, &orig[..2 * AXL_POSTCARD_SZ]).unwrap();
+
+        let (report, packages) = repair(&p, false).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert_eq!(report.kept, 0);
+        assert!(packages.is_empty());
+        assert!(report.removed.iter().all(|r| r.reason == Reason::Foreign));
+    }
+
+    #[test]
+    fn day_file() {
+        let orig = std::fs::read("tests/data/44.5").unwrap();
+
+        // Collections named by day are not named by their number.
+        let p = std::env::temp_dir()
+            .join("sfypack-repair-day")
+            .join("23111400.5");
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(&p, &orig[..2 * AXL_POSTCARD_SZ]).unwrap();
+
+        let (report, _) = repair(&p, false).unwrap();
+        std::fs::remove_file(&p).unwrap();
+
+        assert_eq!(report.kept, 2);
+        assert!(report.removed.is_empty());
+    }
+}
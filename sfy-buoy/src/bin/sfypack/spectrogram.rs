@@ -0,0 +1,395 @@
+//! Spectrogram of the vertical acceleration over a collection, for a quick look at the evolution
+//! of the sea state.
+//!
+//! The samples are read one package at the time (see `export::Samples`) into a window of
+//! `--window` samples. Every full window gives one column of the spectrogram: the mean is removed,
+//! a Hann taper applied, and the one-sided power spectral density [(m/s^2)^2/Hz] computed with an
+//! FFT. The window then advances by `window - overlap` samples. A gap in the samples (e.g. between
+//! bursts, or after an IMU reset) restarts the window, so no window spans a gap.
+//!
+//! The output is either a CSV matrix (one row per window, stamped with the time of the middle of
+//! the window, and one column per frequency) or, when the output ends in `.png`, an image of the
+//! log10 of the power with time to the right and frequency upwards.
+
+use argh::FromArgs;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::collection::PackageReader;
+use crate::export::{Sample, Samples};
+
+/// Size of the PNG [pixels].
+const PNG_SZ: (u32, u32) = (1200, 600);
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "spectrogram")]
+/// Spectrogram of the vertical acceleration as CSV or PNG.
+pub struct Spectrogram {
+    #[argh(positional, description = "collection file")]
+    file: PathBuf,
+
+    #[argh(
+        option,
+        short = 'o',
+        description = "output file, PNG if it ends in .png, CSV otherwise"
+    )]
+    output: PathBuf,
+
+    #[argh(
+        option,
+        short = 'w',
+        default = "1024",
+        description = "length of the window [samples] (default: 1024)"
+    )]
+    window: usize,
+
+    #[argh(
+        option,
+        description = "overlap of consecutive windows [samples] (default: half the window)"
+    )]
+    overlap: Option<usize>,
+
+    #[argh(switch, description = "input file with raw-data")]
+    raw: bool,
+
+    #[argh(
+        switch,
+        description = "include packages in a different frame of reference than the first package"
+    )]
+    force: bool,
+}
+
+/// One column of the spectrogram.
+#[derive(Debug)]
+pub struct Column {
+    /// Time of the middle of the window [ms].
+    pub timestamp: i64,
+
+    /// Power spectral density at the frequencies of `Stft::frequencies` [(m/s^2)^2/Hz].
+    pub psd: Vec<f64>,
+}
+
+/// Short-time FFT over a stream of samples, of one channel (`f64`) or several channels.
+pub struct Stft<S = f64> {
+    freq: f64,
+    window: usize,
+    hop: usize,
+    taper: Vec<f64>,
+    fft: Arc<dyn Fft<f64>>,
+
+    /// Samples of the current window, as (timestamp, sample).
+    buf: VecDeque<(i64, S)>,
+}
+
+impl<S: Copy> Stft<S> {
+    /// Windows of `window` samples overlapping by `overlap` samples, for samples at `freq` [Hz].
+    pub fn new(freq: f64, window: usize, overlap: usize) -> Stft<S> {
+        assert!(window >= 2, "window must be at least two samples");
+        assert!(overlap < window, "overlap must be less than the window");
+
+        let taper = (0..window)
+            .map(|i| {
+                let x = std::f64::consts::PI * i as f64 / (window - 1) as f64;
+                x.sin().powi(2)
+            })
+            .collect();
+
+        Stft {
+            freq,
+            window,
+            hop: window - overlap,
+            taper,
+            fft: FftPlanner::new().plan_fft_forward(window),
+            buf: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Frequencies of the columns [Hz], from 0 to the Nyquist frequency.
+    pub fn frequencies(&self) -> Vec<f64> {
+        (0..=self.window / 2)
+            .map(|k| k as f64 * self.freq / self.window as f64)
+            .collect()
+    }
+
+    /// Add a sample at `timestamp` [ms], returns the time of the middle of the window [ms] and
+    /// the samples of the window when it is full.
+    pub fn window(&mut self, timestamp: i64, s: S) -> Option<(i64, Vec<S>)> {
+        // More than one and a half sample interval since the last sample: restart the window.
+        if let Some((last, _)) = self.buf.back() {
+            if (timestamp - last) as f64 > 1500. / self.freq {
+                self.buf.clear();
+            }
+        }
+
+        self.buf.push_back((timestamp, s));
+
+        if self.buf.len() < self.window {
+            return None;
+        }
+
+        let middle = (self.buf[0].0 + self.buf[self.window - 1].0) / 2;
+        let samples = self.buf.iter().map(|(_, s)| *s).collect();
+
+        self.buf.drain(..self.hop);
+
+        Some((middle, samples))
+    }
+
+    /// FFT of the samples `x` of a full window with the mean removed and the taper applied, from
+    /// 0 to the Nyquist frequency.
+    pub fn spectrum(&self, x: impl Iterator<Item = f64>) -> Vec<Complex<f64>> {
+        let x = x.collect::<Vec<_>>();
+        assert_eq!(x.len(), self.window);
+
+        let mean = x.iter().sum::<f64>() / self.window as f64;
+
+        let mut x = x
+            .iter()
+            .zip(&self.taper)
+            .map(|(z, w)| Complex::new((z - mean) * w, 0.))
+            .collect::<Vec<_>>();
+
+        self.fft.process(&mut x);
+        x.truncate(self.window / 2 + 1);
+        x
+    }
+
+    /// Scale of the (cross-)power of bin `k` of `spectrum` to one-sided spectral density [1/Hz].
+    pub fn density(&self, k: usize) -> f64 {
+        let nyquist = self.window / 2;
+
+        // The power of the negative frequencies is folded into the positive.
+        let one_sided = if k == 0 || (k == nyquist && self.window % 2 == 0) {
+            1.
+        } else {
+            2.
+        };
+
+        one_sided / (self.freq * self.taper.iter().map(|w| w * w).sum::<f64>())
+    }
+}
+
+impl Stft<f64> {
+    /// Add a sample at `timestamp` [ms], returns a column when the window is full.
+    pub fn push(&mut self, timestamp: i64, z: f64) -> Option<Column> {
+        let (timestamp, samples) = self.window(timestamp, z)?;
+
+        Some(Column {
+            timestamp,
+            psd: self.psd(&samples),
+        })
+    }
+
+    /// One-sided power spectral density of a window.
+    fn psd(&self, samples: &[f64]) -> Vec<f64> {
+        self.spectrum(samples.iter().copied())
+            .iter()
+            .enumerate()
+            .map(|(k, c)| c.norm_sqr() * self.density(k))
+            .collect()
+    }
+}
+
+/// Spectrogram of the vertical acceleration of `samples`.
+pub fn spectrogram(
+    samples: impl Iterator<Item = std::io::Result<Sample>>,
+    stft: &mut Stft,
+) -> std::io::Result<Vec<Column>> {
+    let mut columns = Vec::new();
+
+    for s in samples {
+        let s = s?;
+        columns.extend(stft.push(s.timestamp, s.z as f64));
+    }
+
+    Ok(columns)
+}
+
+pub fn write_csv(columns: &[Column], frequencies: &[f64], mut w: impl Write) -> anyhow::Result<()> {
+    write!(w, "timestamp")?;
+    for f in frequencies {
+        write!(w, ",{}", f)?;
+    }
+    writeln!(w)?;
+
+    for c in columns {
+        write!(w, "{}", c.timestamp)?;
+        for p in &c.psd {
+            write!(w, ",{:e}", p)?;
+        }
+        writeln!(w)?;
+    }
+
+    Ok(())
+}
+
+/// Image of the log10 of the power, scaled between the smallest and largest power.
+pub fn write_png(columns: &[Column], output: &Path) -> anyhow::Result<()> {
+    use plotters::prelude::*;
+
+    let log = |p: f64| p.max(f64::MIN_POSITIVE).log10();
+    let (min, max) = columns
+        .iter()
+        .flat_map(|c| c.psd.iter().map(|p| log(*p)))
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), p| {
+            (a.min(p), b.max(p))
+        });
+    let range = (max - min).max(f64::EPSILON);
+
+    let root = BitMapBackend::new(output, PNG_SZ).into_drawing_area();
+    root.fill(&BLACK)?;
+
+    let (w, h) = (PNG_SZ.0 as f64, PNG_SZ.1 as f64);
+    let cw = w / columns.len().max(1) as f64;
+
+    for (i, c) in columns.iter().enumerate() {
+        let ch = h / c.psd.len() as f64;
+
+        for (k, p) in c.psd.iter().enumerate() {
+            let v = (log(*p) - min) / range;
+
+            // Blue (low) to red (high).
+            let color = HSLColor((1. - v) * 2. / 3., 1., 0.5);
+
+            let x0 = (i as f64 * cw) as i32;
+            let x1 = ((i + 1) as f64 * cw).ceil() as i32;
+            let y0 = (h - (k + 1) as f64 * ch) as i32;
+            let y1 = (h - k as f64 * ch).ceil() as i32;
+
+            root.draw(&Rectangle::new([(x0, y0), (x1, y1)], color.filled()))?;
+        }
+    }
+
+    root.present()?;
+
+    Ok(())
+}
+
+impl Spectrogram {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let overlap = self.overlap.unwrap_or(self.window / 2);
+        anyhow::ensure!(self.window >= 2, "window must be at least two samples");
+        anyhow::ensure!(
+            overlap < self.window,
+            "overlap must be less than the window"
+        );
+
+        eprintln!("Loading collection from: {:?}", self.file);
+
+        let mut samples = Samples::new(PackageReader::open(&self.file, self.raw)?);
+        samples.force = self.force;
+
+        // The sample rate is known after the first package has been read.
+        let first = samples.next().transpose()?;
+        let freq = samples
+            .stats
+            .freq
+            .ok_or_else(|| anyhow::anyhow!("no samples in collection"))? as f64;
+
+        let mut stft = Stft::new(freq, self.window, overlap);
+        let columns = spectrogram(first.map(Ok).into_iter().chain(samples), &mut stft)?;
+
+        eprintln!(
+            "Computed {} windows of {} samples ({:.1} s) at {} Hz, resolution {:.4} Hz.",
+            columns.len(),
+            self.window,
+            self.window as f64 / freq,
+            freq,
+            freq / self.window as f64
+        );
+        anyhow::ensure!(!columns.is_empty(), "no full windows in collection");
+
+        if self.output.extension().map_or(false, |e| e == "png") {
+            write_png(&columns, &self.output)?;
+        } else {
+            let mut w = BufWriter::new(File::create(&self.output)?);
+            write_csv(&columns, &stft.frequencies(), &mut w)?;
+            w.flush()?;
+        }
+
+        eprintln!("Wrote spectrogram to: {:?}", self.output);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sine_peak() {
+        let freq = 52.;
+        let f0 = 0.2;
+        let mut stft: Stft = Stft::new(freq, 1024, 512);
+
+        let columns = (0..4096)
+            .filter_map(|i| {
+                let t = i as f64 / freq;
+                let z = (2. * std::f64::consts::PI * f0 * t).sin();
+                stft.push((t * 1000.).round() as i64, z)
+            })
+            .collect::<Vec<_>>();
+
+        // Windows start every 512 samples.
+        assert_eq!(columns.len(), (4096 - 1024) / 512 + 1);
+
+        let frequencies = stft.frequencies();
+        assert_eq!(frequencies.len(), 513);
+        assert_eq!(frequencies[512], freq / 2.);
+
+        for c in &columns {
+            let (k, _) = c
+                .psd
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap();
+            assert!(
+                (frequencies[k] - f0).abs() <= freq / 1024.,
+                "{}",
+                frequencies[k]
+            );
+
+            // The variance of a unit sine is 1/2.
+            let variance = c.psd.iter().sum::<f64>() * freq / 1024.;
+            assert!((variance - 0.5).abs() < 0.05, "{}", variance);
+        }
+    }
+
+    #[test]
+    fn gap_restarts_window() {
+        let mut stft: Stft = Stft::new(10., 8, 0);
+
+        let mut t = 0;
+        let mut columns = 0;
+        for i in 0..20 {
+            // A gap of 10 s after 6 samples.
+            t += if i == 6 { 10_000 } else { 100 };
+            columns += stft.push(t, 0.).is_some() as usize;
+        }
+
+        // 14 samples after the gap: one full window.
+        assert_eq!(columns, 1);
+    }
+
+    #[test]
+    fn csv_matrix() {
+        let columns = [Column {
+            timestamp: 1000,
+            psd: vec![0.5, 0.25],
+        }];
+
+        let mut out = Vec::new();
+        write_csv(&columns, &[0., 0.5], &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp,0,0.5\n1000,5e-1,2.5e-1\n"
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Bulk wave statistics from the vertical acceleration: significant wave height `Hm0`, peak period
+//! `Tp` and mean period `Tm01`.
+//!
+//! The power spectral density of the vertical acceleration is averaged over the windows of the
+//! collection as by `spectrogram` (`--window` samples overlapping by `--overlap`, restarted at
+//! gaps), at the sample rate stored in the packages. The acceleration is integrated to heave
+//! displacement in the frequency domain, dividing the density by `(2 pi f)^4`:
+//!
+//! ```text
+//! S(f) = Saa(f) / (2 pi f)^4,    mn = sum f^n S(f) df
+//! Hm0 = 4 sqrt(m0),    Tp = 1 / f_peak,    Tm01 = m0 / m1
+//! ```
+//!
+//! The integration amplifies the low frequencies without bound, where the acceleration is
+//! dominated by sensor noise and drift (e.g. of the bias, or the tilt of the vertical). The
+//! spectrum is therefore cut off below `--cutoff` (default `DEFAULT_CUTOFF`, 0.05 Hz or 20 s,
+//! longer than most swell), which acts as an ideal high-pass filter applied after integration.
+//! The mean of every window is removed before the FFT. Frequencies above the Nyquist frequency of
+//! the packages are not resolved.
+//!
+//! Packages with clipped samples (see `AxlPacket::clipped_samples`) bias the spectrum, and can be
+//! left out with `--max-clipped`, the gaps they leave restart the windows.
+//!
+//! The sea state is assumed stationary over the collection, split long deployments (e.g. with
+//! `sfypack export --split`) to follow the evolution.
+
+use argh::FromArgs;
+use std::path::PathBuf;
+
+use crate::collection::PackageReader;
+use crate::export::{Sample, Samples};
+use crate::spectrogram::Stft;
+
+/// Frequencies below are cut off before the moments are computed [Hz].
+pub const DEFAULT_CUTOFF: f64 = 0.05;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "stats")]
+/// Significant wave height, peak and mean period from the vertical acceleration.
+pub struct Stats {
+    #[argh(positional, description = "collection file")]
+    file: PathBuf,
+
+    #[argh(
+        option,
+        short = 'w',
+        default = "4096",
+        description = "length of the window [samples] (default: 4096)"
+    )]
+    window: usize,
+
+    #[argh(
+        option,
+        description = "overlap of consecutive windows [samples] (default: half the window)"
+    )]
+    overlap: Option<usize>,
+
+    #[argh(
+        option,
+        default = "DEFAULT_CUTOFF",
+        description = "high-pass cut-off of the displacement spectrum [Hz] (default: 0.05)"
+    )]
+    cutoff: f64,
+
+    #[argh(
+        option,
+        description = "leave out packages with more clipped IMU samples than this (0 for any)"
+    )]
+    max_clipped: Option<u16>,
+
+    #[argh(switch, description = "input file with raw-data")]
+    raw: bool,
+
+    #[argh(
+        switch,
+        description = "include packages in a different frame of reference than the first package"
+    )]
+    force: bool,
+}
+
+/// Bulk wave statistics of a collection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaveStats {
+    /// Number of windows averaged.
+    pub windows: usize,
+
+    /// Significant wave height, `4 sqrt(m0)` [m].
+    pub hm0: f64,
+
+    /// Peak period [s].
+    pub tp: f64,
+
+    /// Mean period, `m0 / m1` [s].
+    pub tm01: f64,
+}
+
+/// Wave statistics of the vertical acceleration of `samples`, over the windows of `stft`, with
+/// the displacement spectrum cut off below `cutoff` [Hz]. Returns `None` without any full window,
+/// or without energy above the cut-off.
+pub fn stats(
+    samples: impl Iterator<Item = std::io::Result<Sample>>,
+    stft: &mut Stft,
+    cutoff: f64,
+) -> std::io::Result<Option<WaveStats>> {
+    let frequencies = stft.frequencies();
+    let mut psd = vec![0.; frequencies.len()];
+    let mut windows = 0;
+
+    for s in samples {
+        let s = s?;
+
+        if let Some(c) = stft.push(s.timestamp, s.z as f64) {
+            psd.iter_mut().zip(&c.psd).for_each(|(a, p)| *a += p);
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        return Ok(None);
+    }
+
+    let df = frequencies[1] - frequencies[0];
+
+    // Heave displacement spectrum, above the cut-off.
+    let displacement = frequencies
+        .iter()
+        .zip(&psd)
+        .filter(|(f, _)| **f >= cutoff && **f > 0.)
+        .map(|(f, p)| {
+            let w = 2. * std::f64::consts::PI * f;
+            (*f, p / windows as f64 / w.powi(4))
+        })
+        .collect::<Vec<_>>();
+
+    let m0 = displacement.iter().map(|(_, s)| s * df).sum::<f64>();
+    let m1 = displacement.iter().map(|(f, s)| f * s * df).sum::<f64>();
+
+    let Some((fp, _)) = displacement
+        .iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .filter(|_| m0 > 0.)
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(WaveStats {
+        windows,
+        hm0: 4. * m0.sqrt(),
+        tp: 1. / fp,
+        tm01: m0 / m1,
+    }))
+}
+
+impl Stats {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let overlap = self.overlap.unwrap_or(self.window / 2);
+        anyhow::ensure!(self.window >= 2, "window must be at least two samples");
+        anyhow::ensure!(
+            overlap < self.window,
+            "overlap must be less than the window"
+        );
+        anyhow::ensure!(self.cutoff >= 0., "cutoff must not be negative");
+
+        eprintln!("Loading collection from: {:?}", self.file);
+
+        let mut samples = Samples::new(PackageReader::open(&self.file, self.raw)?);
+        samples.force = self.force;
+        samples.max_clipped = self.max_clipped;
+
+        // The sample rate is known after the first package has been read.
+        let first = samples.next().transpose()?;
+        let freq = samples
+            .stats
+            .freq
+            .ok_or_else(|| anyhow::anyhow!("no samples in collection"))? as f64;
+
+        let mut stft = Stft::new(freq, self.window, overlap);
+        let stats = stats(
+            first.map(Ok).into_iter().chain(samples.by_ref()),
+            &mut stft,
+            self.cutoff,
+        )?
+        .ok_or_else(|| anyhow::anyhow!("no full windows with waves in collection"))?;
+
+        eprintln!(
+            "Averaged {} windows of {} samples ({:.1} s) at {} Hz, cut-off {} Hz.",
+            stats.windows,
+            self.window,
+            self.window as f64 / freq,
+            freq,
+            self.cutoff
+        );
+
+        if samples.stats.clipped > 0 {
+            eprintln!(
+                "Left out {} of {} packages with clipped samples.",
+                samples.stats.clipped, samples.stats.packages
+            );
+        }
+
+        println!("Hm0: {:.3} m", stats.hm0);
+        println!("Tp: {:.2} s", stats.tp);
+        println!("Tm01: {:.2} s", stats.tm01);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const FREQ: f64 = 52.;
+    const WINDOW: usize = 4096;
+
+    /// Vertical acceleration of a buoy following waves of `amplitude` [m] at bin `k`, and a
+    /// constant offset `bias` [m/s^2].
+    fn samples(waves: &[(usize, f64)], bias: f64, n: usize) -> Vec<std::io::Result<Sample>> {
+        (0..n)
+            .map(|i| {
+                let t = i as f64 / FREQ;
+                let z = bias
+                    + waves
+                        .iter()
+                        .map(|(k, amplitude)| {
+                            let w = 2. * PI * *k as f64 * FREQ / WINDOW as f64;
+                            -w * w * amplitude * (w * t).cos()
+                        })
+                        .sum::<f64>();
+
+                Ok(Sample {
+                    timestamp: (t * 1000.).round() as i64,
+                    x: 0.,
+                    y: 0.,
+                    z: z as f32,
+                    lat: 0.,
+                    lon: 0.,
+                    seq: 0,
+                    quality: 0,
+                    nearest_fix: false,
+                    dop: 0.,
+                    gyro: [f32::NAN; 3],
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_wave() {
+        // 16 bins, about 0.2 Hz.
+        let f0 = 16. * FREQ / WINDOW as f64;
+
+        let mut stft = Stft::new(FREQ, WINDOW, WINDOW / 2);
+        let s = stats(
+            samples(&[(16, 1.)], 0.5, 4 * WINDOW).into_iter(),
+            &mut stft,
+            DEFAULT_CUTOFF,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(s.windows, 7);
+
+        // The variance of a wave of amplitude 1 m is 1/2 m^2: Hm0 = 4 sqrt(1/2).
+        let hm0 = 4. * 0.5f64.sqrt();
+        assert!((s.hm0 - hm0).abs() < 0.05 * hm0, "{:?}", s);
+        assert!((s.tp - 1. / f0).abs() < 1e-9, "{:?}", s);
+        assert!((s.tm01 - 1. / f0).abs() < 0.05 / f0, "{:?}", s);
+    }
+
+    #[test]
+    fn cutoff() {
+        // 0.025 Hz, below the cut-off, and about 0.2 Hz.
+        let waves = [(2, 1.), (16, 0.5)];
+
+        let mut stft = Stft::new(FREQ, WINDOW, WINDOW / 2);
+        let s = stats(
+            samples(&waves, 0., 4 * WINDOW).into_iter(),
+            &mut stft,
+            DEFAULT_CUTOFF,
+        )
+        .unwrap()
+        .unwrap();
+
+        let hm0 = 4. * (0.5f64 * 0.5 * 0.5).sqrt();
+        assert!((s.hm0 - hm0).abs() < 0.05 * hm0, "{:?}", s);
+        assert!((s.tp - WINDOW as f64 / 16. / FREQ).abs() < 1e-9, "{:?}", s);
+
+        // Without the cut-off the long wave dominates.
+        let mut stft = Stft::new(FREQ, WINDOW, WINDOW / 2);
+        let s = stats(samples(&waves, 0., 4 * WINDOW).into_iter(), &mut stft, 0.)
+            .unwrap()
+            .unwrap();
+        assert!((s.tp - WINDOW as f64 / 2. / FREQ).abs() < 1e-9, "{:?}", s);
+    }
+
+    /// The waves are recovered from the packages of the IMU, through the filters of the buoy.
+    #[cfg(feature = "host-tests")]
+    #[test]
+    fn synthetic_imu() {
+        use sfy::axl::{AXL_POSTCARD_SZ, VERSION};
+        use sfy::waves::synthetic::{record, NoDelay, SyntheticImu, Wave};
+        use sfy::waves::{Waves, OUTPUT_FREQ};
+
+        let freq = OUTPUT_FREQ as f64;
+        let f0 = 16. * freq / WINDOW as f64;
+
+        let config = sfy::config::Config::default();
+        let imu = SyntheticImu::new(config.imu_address, config.accel_range)
+            .wave(Wave::displacement(2, 1., f0));
+        let mut waves = Waves::new(imu, &config, &mut NoDelay).unwrap();
+
+        // The first package holds the transient of the filters.
+        let mut collection = Vec::new();
+        for p in record(&mut waves, 1_700_000_000_000, 14)
+            .unwrap()
+            .iter()
+            .skip(1)
+        {
+            let mut slot = p.0.to_cobs::<AXL_POSTCARD_SZ>().unwrap().to_vec();
+            slot.resize(AXL_POSTCARD_SZ, 0);
+            collection.extend(slot);
+        }
+
+        let reader = PackageReader::new(std::io::Cursor::new(collection), false, VERSION);
+        let mut stft = Stft::new(freq, WINDOW, WINDOW / 2);
+        let s = stats(Samples::new(reader), &mut stft, DEFAULT_CUTOFF)
+            .unwrap()
+            .unwrap();
+        assert_eq!(s.windows, 5);
+
+        let hm0 = 4. * 0.5f64.sqrt();
+        assert!((s.hm0 - hm0).abs() < 0.05 * hm0, "{:?}", s);
+        assert!((s.tp - 1. / f0).abs() < 1e-9, "{:?}", s);
+    }
+
+    #[test]
+    fn no_windows() {
+        let mut stft = Stft::new(FREQ, WINDOW, WINDOW / 2);
+        assert_eq!(
+            stats(samples(&[(16, 1.)], 0., 100).into_iter(), &mut stft, 0.).unwrap(),
+            None
+        );
+
+        let mut stft = Stft::new(FREQ, WINDOW, WINDOW / 2);
+        assert_eq!(
+            stats(samples(&[], 0., WINDOW).into_iter(), &mut stft, 0.).unwrap(),
+            None
+        );
+    }
+}
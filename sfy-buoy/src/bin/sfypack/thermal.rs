@@ -0,0 +1,250 @@
+//! Fit the temperature table of the accelerometer calibration (`accel_thermal`, see
+//! `sfy::waves::thermal`) from calibration captures at different temperatures, e.g. in a thermal
+//! chamber.
+//!
+//! The calibration packages are split into captures: a capture ends when the step goes back, or
+//! when no package follows within a step. The bias and scale of every capture are estimated as by
+//! `calibrate`, at the mean temperature of the packages of the capture. Keep the temperature
+//! steady during each capture, and the captures at least a degree apart.
+
+use argh::FromArgs;
+use serde_json as json;
+use std::path::PathBuf;
+
+use sfy::axl::AxlPacket;
+use sfy::calibration::{self, Estimate, STEPS, STEP_DURATION};
+use sfy::config::MAX_ACCEL_BIAS;
+use sfy::waves::thermal::{self, Point, MAX_POINTS};
+
+use crate::calibrate::step_means;
+use crate::collection::Collection;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "thermal")]
+/// Fit the temperature table of the accelerometer calibration from captures at different
+/// temperatures.
+pub struct Thermal {
+    #[argh(
+        positional,
+        description = "collection files with the calibration captures"
+    )]
+    files: Vec<PathBuf>,
+
+    #[argh(switch, description = "input files with raw-data")]
+    raw: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Capture {
+    /// Timestamp of the first package [ms].
+    pub start: i64,
+    pub packages: usize,
+
+    /// Mean temperature of the packages [C].
+    pub temperature: f32,
+    pub estimate: Estimate,
+}
+
+/// The table as config override.
+#[derive(serde::Serialize, Debug)]
+pub struct Table {
+    pub accel_thermal: Vec<Point>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Report {
+    pub captures: Vec<Capture>,
+    pub config: Table,
+}
+
+/// Split calibration packages into captures.
+pub fn captures(mut pcks: Vec<AxlPacket>) -> Vec<Vec<AxlPacket>> {
+    pcks.sort_by_key(|p| p.timestamp);
+
+    let mut captures: Vec<Vec<AxlPacket>> = Vec::new();
+    for p in pcks {
+        match captures.last_mut() {
+            Some(c)
+                if c.last().map_or(false, |l| {
+                    p.calibration >= l.calibration && p.timestamp - l.timestamp <= STEP_DURATION
+                }) =>
+            {
+                c.push(p)
+            }
+            _ => captures.push(vec![p]),
+        }
+    }
+    captures
+}
+
+pub fn fit(pcks: Vec<AxlPacket>) -> anyhow::Result<Report> {
+    let mut captures = captures(pcks)
+        .into_iter()
+        .map(|c| {
+            let steps = step_means(&c)?;
+            let means = <[[f32; 3]; STEPS as usize]>::try_from(
+                steps.iter().map(|s| s.mean).collect::<Vec<_>>(),
+            )
+            .unwrap();
+
+            Ok(Capture {
+                start: c[0].timestamp,
+                packages: c.len(),
+                temperature: (c.iter().map(|p| p.temperature as f64).sum::<f64>() / c.len() as f64)
+                    as f32,
+                estimate: calibration::estimate(&means),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    captures.sort_by(|a, b| a.temperature.total_cmp(&b.temperature));
+
+    anyhow::ensure!(!captures.is_empty(), "no calibration captures");
+    anyhow::ensure!(
+        captures.len() <= MAX_POINTS,
+        "{} captures, the table holds at most {} points",
+        captures.len(),
+        MAX_POINTS
+    );
+
+    let table = captures
+        .iter()
+        .map(|c| Point {
+            temperature: c.temperature,
+            bias: c.estimate.bias,
+            scale: c.estimate.scale,
+        })
+        .collect::<Vec<_>>();
+
+    anyhow::ensure!(
+        thermal::is_valid(&table, MAX_ACCEL_BIAS),
+        "invalid table, two captures at the same temperature or bias too large: {:?}",
+        table
+    );
+
+    Ok(Report {
+        captures,
+        config: Table {
+            accel_thermal: table,
+        },
+    })
+}
+
+impl Thermal {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let mut pcks = Vec::new();
+
+        for f in &self.files {
+            eprintln!("Loading collection from: {:?}", f);
+
+            let c = match self.raw {
+                false => Collection::from_file(f),
+                true => Collection::from_file_raw(f),
+            }?;
+
+            pcks.extend(c.pcks.into_iter().filter(|p| p.calibration != 0));
+        }
+
+        eprintln!("Found {} calibration packages.", pcks.len());
+
+        let report = fit(pcks)?;
+        eprintln!(
+            "Fitted {} points from {:.1} C to {:.1} C.",
+            report.captures.len(),
+            report.captures[0].temperature,
+            report.captures[report.captures.len() - 1].temperature
+        );
+
+        println!("{}", json::to_string_pretty(&report)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sfy::axl::{AxlPacketBuilder, AXL_SZ, SAMPLE_SZ};
+    use sfy::calibration::ORIENTATIONS;
+    use sfy::waves::wire::{ScaledF32, A16, ACCEL_MAX};
+    use sfy::waves::{Frame, SENSORS_GRAVITY_STANDARD};
+
+    /// Start of the captures [ms].
+    const T0: i64 = 1_700_000_000_000;
+
+    fn package(step: u8, timestamp: i64, temperature: f32, a: [f32; 3]) -> AxlPacket {
+        let data = (0..AXL_SZ)
+            .map(|i| A16::from_f32(a[i % SAMPLE_SZ]).to_u16())
+            .collect();
+
+        AxlPacketBuilder::new(T0 + timestamp, 208.0, ACCEL_MAX, data)
+            .temperature(temperature)
+            .calibration(step)
+            .frame(Frame::Sensor.code())
+            .build()
+            .unwrap()
+    }
+
+    /// A capture starting at `start` with packages every 5 s.
+    fn capture(start: i64, temperature: f32, bias: [f32; 3]) -> Vec<AxlPacket> {
+        let g = SENSORS_GRAVITY_STANDARD as f32;
+
+        ORIENTATIONS
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (axis, up))| {
+                let mut a = bias;
+                a[*axis] += if *up { g } else { -g };
+
+                let start = start + i as i64 * STEP_DURATION;
+                (0..12).map(move |k| package(i as u8 + 1, start + 5000 * k, temperature, a))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fit_table() {
+        let hour = 3600 * 1000;
+
+        // The warm capture first.
+        let mut pcks = capture(0, 35.2, [0.1, -0.1, 0.3]);
+        pcks.extend(capture(hour, 4.8, [0.05, -0.1, 0.2]));
+
+        let r = fit(pcks).unwrap();
+        assert_eq!(r.captures.len(), 2);
+        let t = &r.config.accel_thermal;
+        assert_eq!(t.len(), 2);
+        assert_eq!((t[0].temperature, t[1].temperature), (4.8, 35.2));
+        assert_eq!(r.captures[0].start, T0 + hour);
+
+        for (p, bias) in t.iter().zip([[0.05, -0.1, 0.2], [0.1, -0.1, 0.3]]) {
+            for i in 0..3 {
+                assert!((p.bias[i] - bias[i]).abs() < 1.0e-3, "{:?}", p);
+                assert!((p.scale[i] - 1.0).abs() < 1.0e-3, "{:?}", p);
+            }
+        }
+
+        let j = json::to_string(&r.config).unwrap();
+        assert!(j.starts_with(r#"{"accel_thermal":[{"temperature":4.8,"#));
+    }
+
+    #[test]
+    fn back_to_back() {
+        // The second capture starts right after the first.
+        let mut pcks = capture(0, 5., [0.; 3]);
+        pcks.extend(capture(STEPS as i64 * STEP_DURATION, 5.5, [0.; 3]));
+        assert_eq!(captures(pcks).len(), 2);
+
+        // Same temperature.
+        let mut pcks = capture(0, 5., [0.; 3]);
+        pcks.extend(capture(STEPS as i64 * STEP_DURATION, 5., [0.; 3]));
+        assert!(fit(pcks).is_err());
+    }
+
+    #[test]
+    fn interrupted() {
+        let mut pcks = capture(0, 5., [0.; 3]);
+        pcks.retain(|p| p.calibration < 4);
+        assert!(fit(pcks).is_err());
+    }
+}
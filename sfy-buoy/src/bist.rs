@@ -0,0 +1,462 @@
+//! Built-in self-test at boot.
+//!
+//! Before a freshly deployed buoy is trusted, the self-test gives a single go / no-go report of the
+//! hardware ([`BistReport`]), rather than problems being discovered from the data days later:
+//!
+//! * IMU: the WHO_AM_I register and the self-test of the accelerometer and the gyroscope (see
+//!   `waves::self_test`) of every IMU. The IMU is tested during the setup, before the FIFO is
+//!   enabled, so the buoy should be still while it boots.
+//! * Notecard: reachable, with the version of its firmware (`card.version`).
+//! * SD-card (with the `storage` feature): a file is written and read back (`BIST_TEST_FILE`),
+//!   and the estimated free space is above `min_free_space`.
+//! * RTC: the crystal is running at the right rate (see `clock::rate_ok`), and the time has been
+//!   synchronized from the Notecard.
+//! * Battery: the voltage measured by the Notecard (`card.voltage`) is at least `min_voltage`.
+//!
+//! The report is logged (at `warn` when it is a no-go), appended to the setup log on the SD-card
+//! and sent to `BIST_FILE` with an immediate sync. A failed check does not stop the buoy, it
+//! continues in a degraded mode: a missing IMU or Notecard already fails the setup, every other
+//! failure is only flagged. The self-test is enabled by default, and is configured with `bist` in
+//! the config: `{ "bist": { "enabled": true, "min_voltage": 3.3 } }`.
+
+use core::fmt::{Debug, Write as _};
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Read, Write, WriteRead},
+};
+use heapless::{String, Vec};
+
+use crate::clock;
+use crate::log::{self, Category, Level};
+use crate::note::Notecarrier;
+use crate::waves::self_test::{SelfTest, WHO_AM_I};
+use crate::waves::Waves;
+
+/// Notefile of the report.
+pub const BIST_FILE: &str = "bist.qo";
+
+/// File written and read back on the SD-card.
+pub const BIST_TEST_FILE: &str = "BIST.BIN";
+
+/// IMUs in the report (two with the `redundant-imu` feature).
+pub const MAX_IMUS: usize = 2;
+
+/// Maximum length of the firmware versions.
+pub const VERSION_SZ: usize = 40;
+
+/// Maximum `min_voltage` [V].
+pub const MAX_VOLTAGE: f32 = 6.0;
+
+/// Configuration of the self-test. Fields that are not set in an override take the default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Bist {
+    pub enabled: bool,
+
+    /// Lowest battery voltage that passes [V].
+    pub min_voltage: f32,
+}
+
+impl Default for Bist {
+    fn default() -> Bist {
+        Bist {
+            enabled: true,
+            min_voltage: 3.3,
+        }
+    }
+}
+
+impl Bist {
+    pub fn is_valid(&self) -> bool {
+        (0.0..=MAX_VOLTAGE).contains(&self.min_voltage)
+    }
+}
+
+#[derive(serde::Serialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pass,
+    Fail,
+
+    /// Not checked in this build.
+    Skipped,
+}
+
+impl Outcome {
+    pub fn of(ok: bool) -> Outcome {
+        if ok {
+            Outcome::Pass
+        } else {
+            Outcome::Fail
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "fail",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(serde::Serialize, defmt::Format, Debug, Clone, PartialEq)]
+pub struct ImuCheck {
+    pub address: u8,
+
+    /// `None` if it could not be read.
+    pub who_am_i: Option<u8>,
+
+    /// `None` if the self-test failed on an I2C error.
+    pub self_test: Option<SelfTest>,
+    pub outcome: Outcome,
+}
+
+impl ImuCheck {
+    pub fn new(address: u8, who_am_i: Option<u8>, self_test: Option<SelfTest>) -> ImuCheck {
+        ImuCheck {
+            address,
+            who_am_i,
+            self_test,
+            outcome: Outcome::of(
+                who_am_i == Some(WHO_AM_I) && self_test.map_or(false, |st| st.passed()),
+            ),
+        }
+    }
+}
+
+/// Check the IMU of `waves`, before the FIFO is enabled.
+pub fn check_imu<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>>(
+    waves: &mut Waves<I2C>,
+    delay: &mut impl DelayMs<u16>,
+) -> ImuCheck {
+    let who_am_i = waves
+        .who_am_i()
+        .inspect_err(|e| {
+            defmt::error!(
+                "BIST: failed to read WHO_AM_I: {:?}",
+                defmt::Debug2Format(e)
+            )
+        })
+        .ok();
+    let self_test = waves
+        .self_test(delay)
+        .inspect_err(|e| defmt::error!("BIST: IMU self-test failed: {:?}", defmt::Debug2Format(e)))
+        .ok();
+
+    ImuCheck::new(waves.address, who_am_i, self_test)
+}
+
+/// Check of the SD-card.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct SdCheck {
+    /// The test file was written and read back.
+    pub write: bool,
+
+    /// Estimated free space [bytes], `None` if the card is not ready.
+    pub free_space: Option<u64>,
+    pub min_free_space: u64,
+}
+
+impl SdCheck {
+    pub fn outcome(&self) -> Outcome {
+        Outcome::of(self.write && self.free_space.map_or(false, |f| f >= self.min_free_space))
+    }
+}
+
+/// Check of the Notecard, `None` where the request failed.
+#[derive(defmt::Format, Debug, Clone, Default, PartialEq)]
+pub struct NotecardCheck {
+    pub version: Option<String<VERSION_SZ>>,
+
+    /// Battery voltage [V].
+    pub voltage: Option<f32>,
+}
+
+#[derive(serde::Serialize, defmt::Format, Debug, Clone, PartialEq)]
+pub struct BistReport {
+    /// Version of the firmware of the buoy.
+    pub firmware: String<VERSION_SZ>,
+
+    pub imu: Vec<ImuCheck, MAX_IMUS>,
+
+    pub notecard: Outcome,
+
+    /// Version of the firmware of the Notecard.
+    pub notecard_version: Option<String<VERSION_SZ>>,
+
+    pub sd: Outcome,
+
+    /// Estimated free space on the SD-card [bytes].
+    pub free_space: Option<u64>,
+
+    /// The RTC crystal runs at the right rate.
+    pub rtc: Outcome,
+
+    /// The time has been synchronized.
+    pub time: Outcome,
+
+    pub battery: Outcome,
+
+    /// [V].
+    pub voltage: Option<f32>,
+
+    /// No check failed.
+    pub go: bool,
+}
+
+impl BistReport {
+    /// Report from the results of the checks, `sd` is `None` without the `storage` feature.
+    pub fn new(
+        firmware: &str,
+        imu: Vec<ImuCheck, MAX_IMUS>,
+        notecard: NotecardCheck,
+        sd: Option<SdCheck>,
+        rtc_ok: bool,
+        time_synced: bool,
+        bist: &Bist,
+    ) -> BistReport {
+        let mut r = BistReport {
+            firmware: String::new(),
+            notecard: Outcome::of(notecard.version.is_some()),
+            notecard_version: notecard.version,
+            sd: sd.map_or(Outcome::Skipped, |sd| sd.outcome()),
+            free_space: sd.and_then(|sd| sd.free_space),
+            rtc: Outcome::of(rtc_ok),
+            time: Outcome::of(time_synced),
+            battery: Outcome::of(notecard.voltage.map_or(false, |v| v >= bist.min_voltage)),
+            voltage: notecard.voltage,
+            go: false,
+            imu,
+        };
+        r.firmware
+            .push_str(firmware.get(..VERSION_SZ).unwrap_or(firmware))
+            .ok();
+
+        r.go = !r.imu.is_empty()
+            && r.imu
+                .iter()
+                .map(|i| i.outcome)
+                .chain([r.notecard, r.sd, r.rtc, r.time, r.battery])
+                .all(|o| o != Outcome::Fail);
+
+        r
+    }
+
+    /// One line summary for the log.
+    pub fn summary(&self) -> String<256> {
+        let mut s = String::new();
+
+        write!(
+            &mut s,
+            "BIST {} (v{}):",
+            if self.go { "go" } else { "NO-GO" },
+            self.firmware
+        )
+        .ok();
+
+        for i in &self.imu {
+            write!(&mut s, " imu {:#x}: {}", i.address, i.outcome.as_str()).ok();
+            if i.who_am_i != Some(WHO_AM_I) {
+                write!(&mut s, " (who_am_i: {:?})", i.who_am_i).ok();
+            }
+            s.push(',').ok();
+        }
+
+        write!(&mut s, " notecard: {}", self.notecard.as_str()).ok();
+        if let Some(v) = &self.notecard_version {
+            write!(&mut s, " ({})", v).ok();
+        }
+
+        write!(&mut s, ", sd: {}", self.sd.as_str()).ok();
+        if let Some(f) = self.free_space {
+            write!(&mut s, " ({} MB free)", f / (1024 * 1024)).ok();
+        }
+
+        write!(
+            &mut s,
+            ", rtc: {}, time: {}, battery: {}",
+            self.rtc.as_str(),
+            self.time.as_str(),
+            self.battery.as_str()
+        )
+        .ok();
+        if let Some(v) = self.voltage {
+            write!(&mut s, " ({:.2} V)", v).ok();
+        }
+        s.push('.').ok();
+
+        s
+    }
+}
+
+/// Run the checks of the Notecard, the RTC and the battery, and assemble the report with the
+/// checks of the IMU(s) (see [`check_imu`]) and the SD-card (see `StorageManager::check_sd`).
+/// The report is logged and sent to notehub.
+pub fn run_bist<T: Read + Write>(
+    note: &mut Notecarrier<T>,
+    imu: Vec<ImuCheck, MAX_IMUS>,
+    sd: Option<SdCheck>,
+    firmware: &str,
+    delay: &mut impl DelayMs<u16>,
+) -> BistReport {
+    let bist = note.config().bist;
+
+    let notecard = NotecardCheck {
+        version: note
+            .card_version(delay)
+            .inspect_err(|e| defmt::error!("BIST: failed to read Notecard version: {:?}", e))
+            .ok(),
+        voltage: note
+            .voltage(delay)
+            .inspect_err(|e| defmt::error!("BIST: failed to read voltage: {:?}", e))
+            .ok(),
+    };
+
+    let report = BistReport::new(
+        firmware,
+        imu,
+        notecard,
+        sd,
+        !clock::time_degraded(),
+        clock::time_synced(),
+        &bist,
+    );
+
+    let summary = report.summary();
+    log::log_at(
+        Category::Note,
+        if report.go { Level::Info } else { Level::Warn },
+        &summary,
+    );
+
+    note.send_bist(&report, delay)
+        .inspect_err(|e| defmt::error!("BIST: failed to send report: {:?}", e))
+        .ok();
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn imu(outcome: bool) -> ImuCheck {
+        let st = SelfTest {
+            accel: [500.; 3],
+            gyro: if outcome { [300.; 3] } else { [0.; 3] },
+        };
+        ImuCheck::new(0x6a, Some(WHO_AM_I), Some(st))
+    }
+
+    fn sd(free_space: Option<u64>) -> SdCheck {
+        SdCheck {
+            write: true,
+            free_space,
+            min_free_space: 64 * 1024 * 1024,
+        }
+    }
+
+    fn report(imus: &[ImuCheck], voltage: Option<f32>, sd: Option<SdCheck>) -> BistReport {
+        BistReport::new(
+            "1.2.3",
+            Vec::from_slice(imus).unwrap(),
+            NotecardCheck {
+                version: Some(String::from("notecard-5.3.1")),
+                voltage,
+            },
+            sd,
+            true,
+            true,
+            &Bist::default(),
+        )
+    }
+
+    #[test]
+    fn go() {
+        let r = report(&[imu(true)], Some(3.9), Some(sd(Some(1 << 32))));
+        assert!(r.go);
+        assert_eq!(
+            r.summary(),
+            "BIST go (v1.2.3): imu 0x6a: pass, notecard: pass (notecard-5.3.1), sd: pass (4096 MB \
+             free), rtc: pass, time: pass, battery: pass (3.90 V)."
+        );
+
+        // Without the `storage` feature.
+        let r = report(&[imu(true)], Some(3.9), None);
+        assert_eq!(r.sd, Outcome::Skipped);
+        assert!(r.go);
+    }
+
+    #[test]
+    fn no_go() {
+        // Low battery.
+        let r = report(&[imu(true)], Some(3.1), Some(sd(Some(1 << 32))));
+        assert_eq!(r.battery, Outcome::Fail);
+        assert!(!r.go);
+        assert!(r.summary().starts_with("BIST NO-GO"));
+
+        // The voltage could not be read.
+        assert!(!report(&[imu(true)], None, None).go);
+
+        // Full SD-card.
+        let r = report(&[imu(true)], Some(3.9), Some(sd(Some(1024))));
+        assert_eq!(r.sd, Outcome::Fail);
+        assert!(!r.go);
+
+        let mut failed = sd(Some(1 << 32));
+        failed.write = false;
+        assert_eq!(failed.outcome(), Outcome::Fail);
+        assert_eq!(sd(None).outcome(), Outcome::Fail);
+
+        // One of two IMUs failed its self-test.
+        let r = report(&[imu(true), imu(false)], Some(3.9), None);
+        assert_eq!(r.imu[1].outcome, Outcome::Fail);
+        assert!(!r.go);
+
+        // Wrong device.
+        let i = ImuCheck::new(0x6a, Some(0x6c), imu(true).self_test);
+        assert_eq!(i.outcome, Outcome::Fail);
+        let r = report(&[i], Some(3.9), None);
+        assert!(r
+            .summary()
+            .contains("imu 0x6a: fail (who_am_i: Some(108)),"));
+
+        assert!(!report(&[], Some(3.9), None).go);
+    }
+
+    #[test]
+    fn config() {
+        assert!(Bist::default().is_valid());
+        assert!(!Bist {
+            min_voltage: f32::NAN,
+            ..Bist::default()
+        }
+        .is_valid());
+        assert!(!Bist {
+            min_voltage: 12.,
+            ..Bist::default()
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn serialize() {
+        let r = report(&[imu(true)], Some(3.9), None);
+
+        let mut buf = [0u8; 512];
+        let n = serde_json_core::to_slice(&r, &mut buf).unwrap();
+        let s = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(s.starts_with(r#"{"firmware":"1.2.3","imu":[{"address":106,"who_am_i":107,"#));
+        assert!(
+            s.contains(r#""sd":"skipped","free_space":null,"rtc":"pass","#),
+            "{}",
+            s
+        );
+        assert!(
+            s.ends_with(r#""battery":"pass","voltage":3.9,"go":true}"#),
+            "{}",
+            s
+        );
+    }
+}
@@ -0,0 +1,546 @@
+//! Bursts of full-rate samples around extreme events.
+//!
+//! The routine packages are decimated to the output rate, which smooths the peaks of rare extreme
+//! waves and impacts. With a `threshold` in `burst` in the config, the accelerometer samples read
+//! from the IMU (at the IMU rate, in the frame of the sensor and before the bias is removed) are
+//! pushed to a [`Detector`], which keeps a ring of the last `pre + post` samples, at most
+//! [`CAPACITY`] (about 5 s at the IMU rate).
+//!
+//! The trigger fires on the first sample where the magnitude of the acceleration deviates from
+//! gravity by at least `threshold` [m/s²], `| |a| - g | >= threshold`, regardless of the
+//! orientation of the buoy. The ring then holds the `pre` samples before the trigger (fewer if the
+//! trigger fires less than `pre` samples after the detector was armed), and the capture is
+//! complete after `post` samples from the trigger sample on. The complete burst is handed to the
+//! main loop, which appends it to `BURST_FILE` on the SD-card (with the `storage` feature) and logs
+//! a notice to notehub with the peak and the time of the burst (with `notice`). The detector is
+//! armed again once the burst has been handled, samples are not recorded in the meantime, so
+//! bursts do not overlap.
+//!
+//! The file is a sequence of records, each a postcard serialized `(BURST_VERSION, Record)` with
+//! COBS framing (zero terminated), like the post-mortem records. The samples are in mg, oldest
+//! first. `sfypack burst` exports the records as CSV.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use heapless::Vec;
+
+use crate::axl::DecodeError;
+use crate::waves::SENSORS_GRAVITY_STANDARD;
+
+/// Burst records on the SD-card.
+pub const BURST_FILE: &str = "BURST.LOG";
+
+/// Format version of the burst records, increase when `Record` changes.
+pub const BURST_VERSION: u32 = 1;
+
+/// Maximum number of samples in a burst (`pre + post`), about 5 s at the IMU rate.
+pub const CAPACITY: usize = 1024;
+
+/// Maximum size of a serialized and COBS framed record.
+pub const BURST_RECORD_SZ: usize = 9 * 1024 + 128;
+
+/// Maximum `threshold` [m/s²], the full scale of the accelerometer at ±16 g.
+pub const MAX_THRESHOLD: f32 = 16. * SENSORS_GRAVITY_STANDARD as f32;
+
+/// Not recording.
+const DISABLED: u8 = 0;
+
+/// Recording the pre-trigger samples, waiting for the trigger.
+const ARMED: u8 = 1;
+
+/// Recording the post-trigger samples.
+const TRIGGERED: u8 = 2;
+
+/// All samples recorded, waiting for the time of the read.
+const CAPTURED: u8 = 3;
+
+/// Waiting to be handled by the main loop.
+const READY: u8 = 4;
+
+/// Configuration of the bursts. Fields that are not set in an override take the default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Burst {
+    /// Deviation of the magnitude of the acceleration from gravity that triggers a burst [m/s²],
+    /// `0` disables.
+    pub threshold: f32,
+
+    /// Samples before the trigger.
+    pub pre: u16,
+
+    /// Samples from the trigger sample on, at least one.
+    pub post: u16,
+
+    /// Log a notice to notehub for every burst.
+    pub notice: bool,
+}
+
+impl Default for Burst {
+    fn default() -> Burst {
+        Burst {
+            threshold: 0.,
+            pre: 256,
+            post: 768,
+            notice: true,
+        }
+    }
+}
+
+impl Burst {
+    pub fn is_valid(&self) -> bool {
+        (0.0..=MAX_THRESHOLD).contains(&self.threshold)
+            && self.post > 0
+            && self.pre as usize + self.post as usize <= CAPACITY
+    }
+}
+
+fn to_mg(a: f64) -> i16 {
+    (a / SENSORS_GRAVITY_STANDARD * 1000.).clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+fn to_ms2(mg: i16) -> f32 {
+    mg as f32 / 1000. * SENSORS_GRAVITY_STANDARD as f32
+}
+
+/// Trigger and ring buffer of the samples of a burst [mg].
+///
+/// The samples are pushed from the IMU interrupt, and the complete burst is read from the main
+/// loop. The `state` hands the samples over: the interrupt only touches them until the burst is
+/// `READY`, and the main loop only until it is armed again.
+pub struct Detector {
+    state: AtomicU8,
+
+    /// [m/s²].
+    threshold: f32,
+
+    /// Samples in the ring, `pre + post`.
+    len: usize,
+    post: usize,
+
+    /// Position of the next sample.
+    head: usize,
+
+    /// Samples pushed since the detector was armed, saturating.
+    count: usize,
+
+    /// Post-trigger samples left to record.
+    left: usize,
+
+    /// Largest deviation from gravity since the trigger [m/s²].
+    peak: f32,
+
+    /// Time of the read that completed the burst [ms].
+    time: i64,
+
+    /// Sample rate [Hz].
+    freq: f32,
+    samples: [[i16; 3]; CAPACITY],
+}
+
+static mut DETECTOR: Detector = Detector::new();
+
+/// The detector of the IMU interrupt.
+///
+/// # Safety
+///
+/// The detector is shared with the IMU interrupt: only start it before the interrupts are
+/// enabled, and use [`drain`] afterwards.
+pub unsafe fn detector() -> &'static mut Detector {
+    &mut *core::ptr::addr_of_mut!(DETECTOR)
+}
+
+/// Push a sample [m/s²], from the IMU interrupt.
+pub fn push(a: [f64; 3]) {
+    unsafe { detector() }.push(a);
+}
+
+/// Time [ms] of the last read from the IMU, from the IMU interrupt.
+pub fn mark(now: i64) {
+    unsafe { detector() }.mark(now);
+}
+
+/// Handle a complete burst with `f`, from the main loop, and arm the detector again. Returns
+/// `false` if there was no burst.
+pub fn drain(f: impl FnOnce(&Capture<'_>)) -> bool {
+    unsafe { detector() }.drain(f)
+}
+
+impl Detector {
+    pub const fn new() -> Detector {
+        Detector {
+            state: AtomicU8::new(DISABLED),
+            threshold: 0.,
+            len: 0,
+            post: 0,
+            head: 0,
+            count: 0,
+            left: 0,
+            peak: 0.,
+            time: 0,
+            freq: 0.,
+            samples: [[0; 3]; CAPACITY],
+        }
+    }
+
+    /// Start the detector with the `config` at `freq` [Hz], discarding any burst. A `threshold`
+    /// of `0` disables it.
+    pub fn start(&mut self, config: &Burst, freq: f32) {
+        self.state.store(DISABLED, Ordering::Release);
+
+        self.threshold = config.threshold;
+        self.len = (config.pre as usize + config.post as usize).min(CAPACITY);
+        self.post = (config.post as usize).clamp(1, self.len.max(1));
+        self.freq = freq;
+
+        if config.threshold > 0. && config.is_valid() {
+            self.arm();
+        }
+    }
+
+    fn arm(&mut self) {
+        self.head = 0;
+        self.count = 0;
+        self.left = 0;
+        self.peak = 0.;
+        self.time = 0;
+        self.state.store(ARMED, Ordering::Release);
+    }
+
+    pub fn push(&mut self, a: [f64; 3]) {
+        let state = self.state.load(Ordering::Acquire);
+
+        if !(state == ARMED || state == TRIGGERED) || self.len == 0 || self.len > CAPACITY {
+            return;
+        }
+
+        self.samples[self.head] = a.map(to_mg);
+        self.head = (self.head + 1) % self.len;
+        self.count = self.count.saturating_add(1);
+
+        let deviation =
+            libm::fabs(libm::sqrt(a.iter().map(|v| v * v).sum::<f64>()) - SENSORS_GRAVITY_STANDARD)
+                as f32;
+
+        if state == ARMED {
+            // NaN never triggers.
+            if deviation < self.threshold || deviation.is_nan() {
+                return;
+            }
+
+            self.left = self.post;
+        }
+
+        self.peak = self.peak.max(deviation);
+        self.left -= 1;
+
+        let next = if self.left == 0 { CAPTURED } else { TRIGGERED };
+        self.state.store(next, Ordering::Release);
+    }
+
+    pub fn mark(&mut self, now: i64) {
+        if self.state.load(Ordering::Acquire) == CAPTURED {
+            self.time = now;
+            self.state.store(READY, Ordering::Release);
+        }
+    }
+
+    /// The complete burst, `None` if there is none.
+    pub fn ready(&self) -> Option<Capture<'_>> {
+        (self.state.load(Ordering::Acquire) == READY).then_some(Capture(self))
+    }
+
+    /// Handle a complete burst with `f` and arm the detector again.
+    pub fn drain(&mut self, f: impl FnOnce(&Capture<'_>)) -> bool {
+        match self.ready() {
+            Some(c) => {
+                f(&c);
+                self.arm();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for Detector {
+    fn default() -> Detector {
+        Detector::new()
+    }
+}
+
+/// A complete burst, serialized as a [`Record`].
+#[derive(Clone, Copy)]
+pub struct Capture<'a>(&'a Detector);
+
+impl<'a> Capture<'a> {
+    /// Time of the read from the IMU that completed the burst [ms].
+    pub fn time(&self) -> i64 {
+        self.0.time
+    }
+
+    /// Sample rate [Hz].
+    pub fn freq(&self) -> f32 {
+        self.0.freq
+    }
+
+    /// Largest deviation of the magnitude of the acceleration from gravity [m/s²].
+    pub fn peak(&self) -> f32 {
+        self.0.peak
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count.min(self.0.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Index of the trigger sample.
+    pub fn trigger(&self) -> usize {
+        self.len() - self.0.post
+    }
+
+    /// The samples [mg], oldest first.
+    pub fn samples(&self) -> impl Iterator<Item = [i16; 3]> + 'a {
+        let d: &'a Detector = self.0;
+        let (s, head) = (&d.samples[..d.len], d.head);
+
+        // Once the ring has wrapped the oldest samples are after the head.
+        let wrapped = if d.count >= d.len {
+            &s[head..]
+        } else {
+            &s[..0]
+        };
+
+        wrapped.iter().chain(&s[..head]).copied()
+    }
+
+    /// Serialize as a record of the burst file.
+    pub fn to_cobs(&self) -> Result<Vec<u8, BURST_RECORD_SZ>, postcard::Error> {
+        postcard::to_vec_cobs(&(BURST_VERSION, self))
+    }
+}
+
+impl serde::Serialize for Capture<'_> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        struct Samples<'a>(Capture<'a>);
+
+        impl serde::Serialize for Samples<'_> {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                s.collect_seq(self.0.samples())
+            }
+        }
+
+        let mut r = s.serialize_struct("Record", 5)?;
+        r.serialize_field("time", &self.time())?;
+        r.serialize_field("freq", &self.freq())?;
+        r.serialize_field("trigger", &(self.trigger() as u16))?;
+        r.serialize_field("peak", &self.peak())?;
+        r.serialize_field("samples", &Samples(*self))?;
+        r.end()
+    }
+}
+
+impl core::fmt::Display for Capture<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Burst: {:.1} m/s^2 from gravity at {} ms, {} samples ({:.1} s).",
+            self.peak(),
+            self.time(),
+            self.len(),
+            self.len() as f32 / self.freq()
+        )
+    }
+}
+
+/// A record of the burst file.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub struct Record {
+    pub time: i64,
+    pub freq: f32,
+
+    /// Index of the trigger sample.
+    pub trigger: u16,
+
+    /// [m/s²].
+    pub peak: f32,
+
+    /// Samples [mg], oldest first.
+    pub samples: Vec<[i16; 3], CAPACITY>,
+}
+
+impl Record {
+    /// Decode a COBS framed record of the burst file, the buffer is decoded in place.
+    pub fn decode(buf: &mut [u8]) -> Result<Record, DecodeError> {
+        let n = cobs::decode_in_place(buf).map_err(|_| DecodeError::Cobs)?;
+
+        if n == 0 {
+            return Err(DecodeError::Empty);
+        }
+
+        let (version, buf) =
+            postcard::take_from_bytes::<u32>(&buf[..n]).map_err(DecodeError::Postcard)?;
+
+        match version {
+            BURST_VERSION => postcard::from_bytes(buf).map_err(DecodeError::Postcard),
+            _ => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Time of a sample [ms], the last sample is at `time`.
+    pub fn sample_time(&self, i: usize) -> i64 {
+        let back = (self.samples.len() - 1 - i) as f32 * 1000. / self.freq;
+        self.time - back as i64
+    }
+
+    /// Largest magnitude of the acceleration [m/s²].
+    pub fn max(&self) -> f32 {
+        self.samples
+            .iter()
+            .map(|s| libm::sqrtf(s.iter().map(|v| to_ms2(*v) * to_ms2(*v)).sum()))
+            .fold(0., f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+
+    const G: f64 = SENSORS_GRAVITY_STANDARD;
+
+    fn detector(threshold: f32, pre: u16, post: u16) -> Box<Detector> {
+        let mut d = Box::new(Detector::new());
+        d.start(
+            &Burst {
+                threshold,
+                pre,
+                post,
+                notice: true,
+            },
+            208.,
+        );
+        d
+    }
+
+    #[test]
+    fn spike() {
+        let mut d = detector(20., 4, 3);
+
+        // Still water, with some noise below the threshold.
+        for i in 0..10 {
+            d.push([0., 0.1 * i as f64, G + 5.]);
+        }
+        d.mark(1000);
+        assert!(d.ready().is_none());
+
+        // A spike of 4 g.
+        d.push([0., 0., 4. * G]);
+        d.push([0., 0., -G]);
+        assert!(d.ready().is_none());
+        d.push([0., 0., G]);
+
+        // Not recorded after the capture is complete.
+        d.push([0., 0., 10. * G]);
+
+        assert!(d.ready().is_none());
+        d.mark(2000);
+
+        let c = d.ready().unwrap();
+        assert_eq!(c.time(), 2000);
+        assert_eq!(c.len(), 7);
+        assert_eq!(c.trigger(), 4);
+        assert!((c.peak() - 3. * G as f32).abs() < 1e-3);
+        assert_eq!(
+            c.samples().map(|s| s[2]).collect::<std::vec::Vec<_>>(),
+            [1509, 1509, 1509, 1509, 4000, -1000, 1000]
+        );
+
+        let mut b = c.to_cobs().unwrap();
+        let r = Record::decode(&mut b).unwrap();
+        assert_eq!(r.trigger, 4);
+        assert_eq!(r.samples.len(), 7);
+        assert_eq!(r.samples[4], [0, 0, 4000]);
+        assert_eq!(r.sample_time(6), 2000);
+        assert!((r.max() - 4. * G as f32).abs() < 1e-3);
+
+        // Armed again once handled.
+        let mut n = 0;
+        assert!(d.drain(|c| n = c.len()));
+        assert_eq!(n, 7);
+        assert!(!d.drain(|_| ()));
+
+        d.push([0., 0., 4. * G]);
+        assert!(d.ready().is_none());
+    }
+
+    #[test]
+    fn early_trigger() {
+        // Fewer pre-trigger samples than configured.
+        let mut d = detector(20., 4, 2);
+        d.push([0., 0., G]);
+        d.push([30., 0., 0.]);
+        d.push([0., 0., G]);
+        d.mark(1000);
+
+        let c = d.ready().unwrap();
+        assert_eq!(c.len(), 3);
+        assert_eq!(c.trigger(), 1);
+        assert_eq!(
+            c.samples().map(|s| s[0]).collect::<std::vec::Vec<_>>(),
+            [0, 3059, 0]
+        );
+    }
+
+    #[test]
+    fn free_fall() {
+        // A sudden drop triggers as well.
+        let mut d = detector(9., 0, 1);
+        d.push([0., 0., 0.]);
+        d.mark(1000);
+        assert_eq!(d.ready().unwrap().trigger(), 0);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut d = detector(0., 4, 4);
+        d.push([0., 0., 100. * G]);
+        d.push([f64::NAN; 3]);
+        d.mark(1000);
+        assert!(d.ready().is_none());
+
+        let mut d = detector(20., 4, 4);
+        d.push([f64::NAN; 3]);
+        d.mark(1000);
+        assert!(d.ready().is_none());
+    }
+
+    #[test]
+    fn config() {
+        assert!(Burst::default().is_valid());
+
+        let b = Burst {
+            threshold: 30.,
+            pre: 512,
+            post: 512,
+            ..Burst::default()
+        };
+        assert!(b.is_valid());
+
+        assert!(!Burst { pre: 513, ..b }.is_valid());
+        assert!(!Burst { post: 0, ..b }.is_valid());
+        assert!(!Burst {
+            threshold: f32::NAN,
+            ..b
+        }
+        .is_valid());
+        assert!(!Burst {
+            threshold: 200.,
+            ..b
+        }
+        .is_valid());
+    }
+}
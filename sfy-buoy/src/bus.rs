@@ -0,0 +1,201 @@
+//! Access to the I2C buses.
+//!
+//! The Notecard and the IMU are on separate buses, each used from a single context:
+//!
+//! * The Notecard (IOM4) from the main loop, and the CT sensor on the same bus with the `ct`
+//!   feature (see `ct`).
+//! * The IMU (IOM3) from the `RTC` interrupt, after it has been set up in the main loop before
+//!   the interrupt is enabled (see `Imu`).
+//!
+//! A bus must never be used from both the main loop and an interrupt without a lock: an interrupt
+//! that starts a transaction while the main loop is in the middle of one corrupts both. When two
+//! devices share a bus (the two IMUs with the `redundant-imu` feature, or the Notecard and the CT
+//! sensor with the `ct` feature) the bus is shared through `shared_bus::CortexMMutex`, which
+//! holds the bus in a critical section for every transaction and is safe from any context.
+//! `shared_bus::BusManagerSimple` is not: it must not be used for a bus that is reached from an
+//! interrupt.
+//!
+//! Two devices at the same address cannot share a bus: with the `redundant-notecard` feature the
+//! second Notecard is on a bus of its own (IOM2), also used from the main loop only, and the two
+//! buses are combined in an [`Either`] so that both Notecards have the same type (see `failover`).
+//!
+//! Every bus is wrapped in a [`Guarded`] with a flag of its own, which is set for the duration
+//! of a transaction. Starting a transaction while the flag is set means that the bus is used
+//! from two contexts at the same time, and trips a debug assertion.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+/// An I2C bus that asserts that it is never used concurrently (in debug builds).
+pub struct Guarded<I2C> {
+    i2c: I2C,
+
+    /// Set while a transaction is in progress on the bus, one flag per bus.
+    busy: &'static AtomicBool,
+}
+
+impl<I2C> Guarded<I2C> {
+    pub fn new(i2c: I2C, busy: &'static AtomicBool) -> Guarded<I2C> {
+        Guarded { i2c, busy }
+    }
+
+    pub fn into_inner(self) -> I2C {
+        self.i2c
+    }
+
+    fn transaction<R>(&mut self, f: impl FnOnce(&mut I2C) -> R) -> R {
+        let busy = self.busy.swap(true, Ordering::Acquire);
+        debug_assert!(!busy, "concurrent access to I2C bus");
+
+        let r = f(&mut self.i2c);
+
+        self.busy.store(false, Ordering::Release);
+        r
+    }
+}
+
+impl<I2C: Write> Write for Guarded<I2C> {
+    type Error = I2C::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.transaction(|i2c| i2c.write(address, bytes))
+    }
+}
+
+impl<I2C: Read> Read for Guarded<I2C> {
+    type Error = I2C::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.transaction(|i2c| i2c.read(address, buffer))
+    }
+}
+
+impl<I2C: WriteRead> WriteRead for Guarded<I2C> {
+    type Error = I2C::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.transaction(|i2c| i2c.write_read(address, bytes, buffer))
+    }
+}
+
+/// One of two buses of different types, with the same error.
+pub enum Either<A, B> {
+    A(A),
+    B(B),
+}
+
+impl<A: Write, B: Write<Error = A::Error>> Write for Either<A, B> {
+    type Error = A::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        match self {
+            Either::A(i2c) => i2c.write(address, bytes),
+            Either::B(i2c) => i2c.write(address, bytes),
+        }
+    }
+}
+
+impl<A: Read, B: Read<Error = A::Error>> Read for Either<A, B> {
+    type Error = A::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        match self {
+            Either::A(i2c) => i2c.read(address, buffer),
+            Either::B(i2c) => i2c.read(address, buffer),
+        }
+    }
+}
+
+impl<A: WriteRead, B: WriteRead<Error = A::Error>> WriteRead for Either<A, B> {
+    type Error = A::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        match self {
+            Either::A(i2c) => i2c.write_read(address, bytes, buffer),
+            Either::B(i2c) => i2c.write_read(address, bytes, buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Mock {
+        fail: bool,
+    }
+
+    impl Write for Mock {
+        type Error = ();
+
+        fn write(&mut self, _address: u8, _bytes: &[u8]) -> Result<(), ()> {
+            if self.fail {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl WriteRead for Mock {
+        type Error = ();
+
+        fn write_read(&mut self, _address: u8, _bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            buffer.fill(0xaa);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sequential() {
+        static BUSY: AtomicBool = AtomicBool::new(false);
+
+        let mut b = Guarded::new(Mock { fail: false }, &BUSY);
+        b.write(0x6a, &[1]).unwrap();
+
+        let mut buf = [0u8; 2];
+        b.write_read(0x6a, &[1], &mut buf).unwrap();
+        assert_eq!(buf, [0xaa; 2]);
+        assert!(!BUSY.load(Ordering::Relaxed));
+
+        // The bus is released after a failed transaction.
+        let mut b = Guarded::new(Mock { fail: true }, &BUSY);
+        assert_eq!(b.write(0x6a, &[1]), Err(()));
+        assert!(!BUSY.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn either() {
+        static BUSY: AtomicBool = AtomicBool::new(false);
+
+        let mut a: Either<Guarded<Mock>, Mock> =
+            Either::A(Guarded::new(Mock { fail: false }, &BUSY));
+        let mut b: Either<Guarded<Mock>, Mock> = Either::B(Mock { fail: true });
+
+        assert_eq!(a.write(0x17, &[1]), Ok(()));
+        assert_eq!(b.write(0x17, &[1]), Err(()));
+        assert!(!BUSY.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[should_panic(expected = "concurrent access to I2C bus")]
+    fn concurrent() {
+        static BUSY: AtomicBool = AtomicBool::new(false);
+
+        // A transaction in progress in another context.
+        BUSY.store(true, Ordering::Relaxed);
+
+        let mut b = Guarded::new(Mock { fail: false }, &BUSY);
+        b.write(0x6a, &[1]).ok();
+    }
+}
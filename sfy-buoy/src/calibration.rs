@@ -0,0 +1,134 @@
+//! Calibration capture of the accelerometer.
+//!
+//! The `calibrate` command switches the IMU to a calibration capture for a fixed procedure: the
+//! buoy is held still in six known orientations, one after the other, for `STEP_DURATION` each.
+//! During the capture the acceleration is stored unfiltered, in the body frame of the buoy (not
+//! rotated, gravity not removed), at the IMU rate. Every package is tagged with the step it was
+//! captured in (`AxlPacket::calibration`), and packages never span two steps. After the last step
+//! the IMU returns to normal operation and the filters are reset.
+//!
+//! At rest the accelerometer measures `+g` along the axis pointing up, and `-g` when the same
+//! axis is pointing down. From the mean of each axis in the two opposite orientations the bias and
+//! scale of the axis are estimated (see [`estimate`], used by `sfypack calibrate`):
+//!
+//! ```text
+//! bias  = (up + down) / 2
+//! scale = (up - down) / (2 g)
+//! ```
+//!
+//! The first `SETTLE` of every step is left for turning the buoy and is not used for the
+//! estimate.
+
+use crate::waves::SENSORS_GRAVITY_STANDARD;
+
+/// Number of steps (orientations) in the procedure.
+pub const STEPS: u8 = 6;
+
+/// Duration of each step [ms].
+pub const STEP_DURATION: i64 = 60_000;
+
+/// Time at the start of each step for turning the buoy, not used for the estimate [ms].
+pub const SETTLE: i64 = 20_000;
+
+/// Axis (0: x, 1: y, 2: z) pointing up, and whether it is pointing up (`true`) or down, in each
+/// step.
+pub const ORIENTATIONS: [(usize, bool); STEPS as usize] = [
+    (2, true),
+    (2, false),
+    (0, true),
+    (0, false),
+    (1, true),
+    (1, false),
+];
+
+/// A running calibration procedure.
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// Start of procedure [ms].
+    pub start: i64,
+}
+
+impl Calibration {
+    pub fn new(start: i64) -> Calibration {
+        Calibration { start }
+    }
+
+    /// Step (`1..=STEPS`) at `now` [ms], `None` when the procedure is complete.
+    pub fn step(&self, now: i64) -> Option<u8> {
+        let step = (now - self.start).max(0) / STEP_DURATION;
+
+        (step < STEPS as i64).then(|| step as u8 + 1)
+    }
+
+    /// End of procedure [ms].
+    pub fn end(&self) -> i64 {
+        self.start + STEPS as i64 * STEP_DURATION
+    }
+}
+
+/// Bias [m/s^2] and scale factor of each axis (x, y, z). The corrected acceleration is
+/// `(a - bias) / scale`.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub bias: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+/// Estimate bias and scale from the mean acceleration [m/s^2] of each step (in the order of
+/// `ORIENTATIONS`).
+pub fn estimate(means: &[[f32; 3]; STEPS as usize]) -> Estimate {
+    let mut up = [0f32; 3];
+    let mut down = [0f32; 3];
+
+    for (m, (axis, is_up)) in means.iter().zip(ORIENTATIONS) {
+        if is_up {
+            up[axis] = m[axis];
+        } else {
+            down[axis] = m[axis];
+        }
+    }
+
+    let g = SENSORS_GRAVITY_STANDARD as f32;
+
+    Estimate {
+        bias: core::array::from_fn(|i| (up[i] + down[i]) / 2.),
+        scale: core::array::from_fn(|i| (up[i] - down[i]) / (2. * g)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        let c = Calibration::new(1000);
+
+        assert_eq!(c.step(1000), Some(1));
+        assert_eq!(c.step(1000 + STEP_DURATION - 1), Some(1));
+        assert_eq!(c.step(1000 + STEP_DURATION), Some(2));
+        assert_eq!(c.step(c.end() - 1), Some(STEPS));
+        assert_eq!(c.step(c.end()), None);
+    }
+
+    #[test]
+    fn estimate_bias_scale() {
+        let g = SENSORS_GRAVITY_STANDARD as f32;
+        let bias = [0.1, -0.2, 0.3];
+        let scale = [1.01, 0.98, 1.0];
+
+        let means = ORIENTATIONS.map(|(axis, up)| {
+            let mut m = [0.; 3];
+            let a = if up { g } else { -g };
+            m[axis] = a * scale[axis] + bias[axis];
+            m
+        });
+
+        let e = estimate(&means);
+
+        for i in 0..3 {
+            assert!((e.bias[i] - bias[i]).abs() < 1.0e-5);
+            assert!((e.scale[i] - scale[i]).abs() < 1.0e-5);
+        }
+    }
+}
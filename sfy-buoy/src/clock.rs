@@ -0,0 +1,509 @@
+//! Detection of faults of the RTC oscillator.
+//!
+//! The RTC is driven by an external 32 kHz crystal. If the crystal fails the RTC stops, or runs at
+//! a wrong rate, and all timestamps are wrong. The rate of the RTC is checked against an
+//! independent reference:
+//!
+//! * At boot against the cycle counter of the MCU (driven by the internal high-frequency
+//!   oscillator).
+//! * Every time the time is retrieved from the Notecard (GPS or network time), against the
+//!   elapsed Notecard time since the RTC was last set.
+//!
+//! On a fault the firmware switches the RTC to the internal (less accurate) LFRC oscillator,
+//! logs an error to the Notecard and flags the reduced time accuracy in the data notes.
+//!
+//! Between the times the RTC is set, the rate of the crystal varies with temperature. With a
+//! temperature coefficient configured (`rtc_temp_coeff`, ppm/°C) the timestamps of the packages
+//! are corrected by a linear model of the rate error around `DRIFT_REFERENCE_TEMPERATURE`,
+//! integrated over the time since the RTC was last set (see [`DriftCorrection`]). The die
+//! temperature of the IMU is used as the temperature of the crystal.
+//!
+//! Every read of the RTC is validated (see [`TimeGuard`]): the RTC is set to
+//! `MIN_PLAUSIBLE_TIME` at boot, and its two digit year rolls over to 1900 at
+//! `MAX_PLAUSIBLE_TIME`. A read outside of this range (a glitch, a rollover) or a failed read is
+//! replaced by the last plausible time, so that a single bad read does not end up in the
+//! timestamps or the intervals of the firmware.
+
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+
+/// Maximum relative deviation of the RTC rate from the reference. A good crystal is within some
+/// tens of ppm, the tolerance only needs to catch a stopped or grossly wrong oscillator.
+pub const MAX_RATE_ERROR: f64 = 0.1;
+
+/// Minimum reference interval for a meaningful check against the Notecard time [ms].
+pub const MIN_CHECK_INTERVAL: i64 = 60_000;
+
+/// Set when the RTC has been switched to the fallback oscillator.
+static TIME_DEGRADED: AtomicBool = AtomicBool::new(false);
+
+pub fn time_degraded() -> bool {
+    TIME_DEGRADED.load(Ordering::Relaxed)
+}
+
+pub fn set_time_degraded() {
+    TIME_DEGRADED.store(true, Ordering::Relaxed);
+}
+
+/// Set when the RTC has been set from the Notecard time.
+static TIME_SYNCED: AtomicBool = AtomicBool::new(false);
+
+pub fn time_synced() -> bool {
+    TIME_SYNCED.load(Ordering::Relaxed)
+}
+
+/// The RTC has been set from the Notecard time, stepping it by `step` [ms] (the new time minus
+/// the old time).
+pub fn set_time_synced(step: i64) {
+    STEP_HI.store((step >> 32) as i32, Ordering::Relaxed);
+    STEP_LO.store(step as u32, Ordering::Relaxed);
+    TIME_SYNCED.store(true, Ordering::Relaxed);
+    RTC_SET.fetch_add(1, Ordering::Release);
+}
+
+/// Number of times the RTC has been set from the Notecard time.
+static RTC_SET: AtomicU32 = AtomicU32::new(0);
+
+pub fn rtc_set_count() -> u32 {
+    RTC_SET.load(Ordering::Acquire)
+}
+
+/// Step of the RTC the last time it was set, upper and lower halves [ms]. Written by the main
+/// loop before `RTC_SET` is incremented, so a change of the count is seen with the whole step.
+static STEP_HI: AtomicI32 = AtomicI32::new(0);
+static STEP_LO: AtomicU32 = AtomicU32::new(0);
+
+/// Step of the RTC [ms] the last time it was set, `0` if it has not been set.
+pub fn last_step() -> i64 {
+    ((STEP_HI.load(Ordering::Relaxed) as i64) << 32) | STEP_LO.load(Ordering::Relaxed) as i64
+}
+
+/// Largest settle period after the RTC has been set [s], see [`StepWatch`].
+pub const MAX_RTC_SETTLE: u16 = 3600;
+
+/// Watches for the RTC being set while a package is collected.
+///
+/// The samples of a package are timed by the IMU from the time of the first sample, which is
+/// read from the RTC. A package that was started before the RTC was set, and taken after, is
+/// stamped with the old time: it is moved by the step of the RTC, so that it lines up with the
+/// following packages (see `quality::RTC_STEP`). Packages starting within the settle period
+/// (`rtc_settle` in the config) after the step are flagged as well.
+#[derive(Clone, Default)]
+pub struct StepWatch {
+    /// `rtc_set_count` at the last check.
+    set: u32,
+
+    /// Settle period after a step [ms].
+    settle: i64,
+
+    /// Time of the check that found the last step [ms], after the step.
+    last: Option<i64>,
+}
+
+impl StepWatch {
+    pub fn new(settle: u16) -> StepWatch {
+        StepWatch {
+            set: rtc_set_count(),
+            settle: settle as i64 * 1000,
+            last: None,
+        }
+    }
+
+    /// Step of the RTC [ms] since the previous check at `now` [ms], `None` if it has not been
+    /// set.
+    pub fn check(&mut self, now: i64) -> Option<i64> {
+        self.update(rtc_set_count(), last_step(), now)
+    }
+
+    fn update(&mut self, set: u32, step: i64, now: i64) -> Option<i64> {
+        if set == self.set {
+            return None;
+        }
+
+        self.set = set;
+        self.last = Some(now);
+
+        Some(step)
+    }
+
+    /// A package starting at `timestamp` [ms] starts within the settle period after the last
+    /// step.
+    pub fn settling(&self, timestamp: i64) -> bool {
+        self.last.map_or(false, |last| {
+            (last..last + self.settle).contains(&timestamp)
+        })
+    }
+}
+
+/// Earliest plausible time of the RTC, it is set to this at boot (2020-01-01) [s].
+pub const MIN_PLAUSIBLE_TIME: i64 = 1_577_836_800;
+
+/// Latest plausible time of the RTC, where its year rolls over (2100-01-01) [s].
+pub const MAX_PLAUSIBLE_TIME: i64 = 4_102_444_800;
+
+/// Number of implausible reads of the RTC since boot.
+static IMPLAUSIBLE: AtomicU32 = AtomicU32::new(0);
+
+pub fn implausible_count() -> u32 {
+    IMPLAUSIBLE.load(Ordering::Relaxed)
+}
+
+/// Is the RTC time `t` [ms] within `MIN_PLAUSIBLE_TIME` and `MAX_PLAUSIBLE_TIME`.
+pub fn plausible(t: i64) -> bool {
+    (MIN_PLAUSIBLE_TIME * 1000..MAX_PLAUSIBLE_TIME * 1000).contains(&t)
+}
+
+/// A validated read of the RTC.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct RtcTime {
+    /// Time read from the RTC [ms], `None` if the read failed.
+    pub raw: Option<i64>,
+
+    /// Validated time [ms]: the raw time if it is plausible, otherwise the last plausible time.
+    pub time: i64,
+
+    /// Whether the raw time is plausible.
+    pub plausible: bool,
+}
+
+/// Replaces implausible reads of the RTC by the last plausible time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeGuard {
+    /// Last plausible time [ms].
+    last: Option<i64>,
+
+    /// Implausible reads since the last plausible one.
+    implausible: u32,
+}
+
+impl TimeGuard {
+    pub fn new() -> TimeGuard {
+        TimeGuard::default()
+    }
+
+    /// Validate the time `raw` [ms] read from the RTC. An implausible time is replaced by the last
+    /// plausible time, or `MIN_PLAUSIBLE_TIME` if there has not been one, and counted.
+    pub fn validate(&mut self, raw: Option<i64>) -> RtcTime {
+        match raw {
+            Some(t) if plausible(t) => {
+                self.last = Some(t);
+                self.implausible = 0;
+
+                RtcTime {
+                    raw,
+                    time: t,
+                    plausible: true,
+                }
+            }
+            _ => {
+                IMPLAUSIBLE.fetch_add(1, Ordering::Relaxed);
+                self.implausible = self.implausible.saturating_add(1);
+
+                RtcTime {
+                    raw,
+                    time: self.last.unwrap_or(MIN_PLAUSIBLE_TIME * 1000),
+                    plausible: false,
+                }
+            }
+        }
+    }
+
+    /// Implausible reads since the last plausible one, `0` if the last read was plausible.
+    pub fn implausible(&self) -> u32 {
+        self.implausible
+    }
+}
+
+/// Temperature where the rate error of the drift model is zero [°C].
+pub const DRIFT_REFERENCE_TEMPERATURE: f32 = 25.0;
+
+/// Maximum magnitude of the temperature coefficient of the RTC crystal [ppm/°C].
+pub const MAX_TEMP_COEFF: f32 = 5.0;
+
+/// Rate error of the RTC at `temperature` [°C] with the coefficient `temp_coeff` [ppm/°C].
+/// Positive when the RTC runs fast.
+pub fn drift_ppm(temp_coeff: f32, temperature: f32) -> f32 {
+    temp_coeff * (temperature - DRIFT_REFERENCE_TEMPERATURE)
+}
+
+/// Temperature compensation of the RTC drift since the RTC was last set.
+#[derive(Clone, Default)]
+pub struct DriftCorrection {
+    /// Temperature coefficient [ppm/°C], 0 disables the correction.
+    temp_coeff: f32,
+
+    /// RTC time of the last update [ms].
+    last: Option<i64>,
+
+    /// Accumulated correction since the RTC was set [ms].
+    correction: f64,
+
+    /// `rtc_set_count` at the last update.
+    set: u32,
+}
+
+impl DriftCorrection {
+    pub fn new(temp_coeff: f32) -> DriftCorrection {
+        DriftCorrection {
+            temp_coeff,
+            set: rtc_set_count(),
+            ..Default::default()
+        }
+    }
+
+    /// Corrected time of the RTC time `rtc_now` [ms], with the crystal at `temperature` [°C]
+    /// since the previous call. The correction starts over when the RTC has been set.
+    pub fn correct(&mut self, rtc_now: i64, temperature: f32) -> i64 {
+        self.update(rtc_set_count(), rtc_now, temperature)
+    }
+
+    fn update(&mut self, set: u32, rtc_now: i64, temperature: f32) -> i64 {
+        if self.temp_coeff == 0. {
+            return rtc_now;
+        }
+
+        if set != self.set {
+            self.set = set;
+            self.last = None;
+            self.correction = 0.;
+        }
+
+        if let Some(last) = self.last.filter(|last| rtc_now > *last) {
+            let ppm = drift_ppm(self.temp_coeff, temperature) as f64;
+            self.correction -= (rtc_now - last) as f64 * ppm * 1.0e-6;
+        }
+
+        self.last = Some(rtc_now);
+
+        rtc_now + libm::round(self.correction) as i64
+    }
+}
+
+/// Is the elapsed time of the RTC within `MAX_RATE_ERROR` of the reference.
+pub fn rate_ok(rtc_elapsed_ms: i64, ref_elapsed_ms: i64) -> bool {
+    if ref_elapsed_ms <= 0 {
+        return true;
+    }
+
+    let rate = rtc_elapsed_ms as f64 / ref_elapsed_ms as f64;
+    libm::fabs(rate - 1.0) <= MAX_RATE_ERROR
+}
+
+/// Checks the rate of the RTC every time it is set from a reference time.
+#[derive(Clone, Default)]
+pub struct ClockMonitor {
+    /// Reference time the RTC was last set to [ms].
+    last: Option<i64>,
+
+    /// A fault has been detected, and not yet taken.
+    fault: bool,
+}
+
+impl ClockMonitor {
+    pub fn new() -> ClockMonitor {
+        ClockMonitor::default()
+    }
+
+    /// Check the RTC time (`rtc_now`) against the reference time (`ref_now`) before the RTC is
+    /// set to the reference time. Returns `false` if the RTC is running at the wrong rate.
+    pub fn check(&mut self, rtc_now: i64, ref_now: i64) -> bool {
+        let ok = match self.last {
+            Some(last) if ref_now - last >= MIN_CHECK_INTERVAL => {
+                rate_ok(rtc_now - last, ref_now - last)
+            }
+            _ => true,
+        };
+
+        self.last = Some(ref_now);
+
+        if !ok {
+            self.fault = true;
+        }
+
+        ok
+    }
+
+    /// Returns whether a fault has been detected since the last call.
+    pub fn take_fault(&mut self) -> bool {
+        core::mem::take(&mut self.fault)
+    }
+}
+
+/// Keeps the timestamps of the packages monotonic (`monotonic` in the config).
+///
+/// The RTC may be set backwards from the GPS time, and the packages after the step then start
+/// before the end of the packages before it. A package is never stamped before the end of the
+/// previous package: it is moved to one sample interval after the last sample of the previous
+/// package, and the time read from the RTC is kept in `AxlPacket::rtc_timestamp`. The packages are
+/// stamped late, by up to the step, until the RTC has caught up with them. The last package is
+/// not kept across reboots.
+#[derive(Clone, Default)]
+pub struct Monotonic {
+    /// Time of the last sample of the previous package [ms].
+    last: Option<i64>,
+}
+
+impl Monotonic {
+    pub fn new() -> Monotonic {
+        Monotonic::default()
+    }
+
+    /// Timestamp [ms] of a package of `samples` samples at `freq` [Hz], read from the RTC at
+    /// `timestamp`: moved forward if it is not after the end of the previous package. Empty
+    /// packages are not moved, and do not move the following packages.
+    pub fn stamp(&mut self, timestamp: i64, samples: usize, freq: f32) -> i64 {
+        if samples == 0 {
+            return timestamp;
+        }
+
+        let interval = 1000. / freq as f64;
+
+        let timestamp = match self.last {
+            Some(last) if timestamp <= last => last + interval.round() as i64,
+            _ => timestamp,
+        };
+
+        self.last = Some(timestamp + ((samples - 1) as f64 * interval) as i64);
+
+        timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monotonic() {
+        let mut m = Monotonic::new();
+
+        // 1024 samples at 52 Hz take 19.67 s.
+        assert_eq!(m.stamp(100_000, 1024, 52.), 100_000);
+        assert_eq!(m.stamp(119_692, 1024, 52.), 119_692);
+
+        // The RTC was set back by 5 s.
+        let last = 119_692 + (1023. * 1000. / 52.) as i64;
+        assert_eq!(m.stamp(134_384, 1024, 52.), last + 19);
+        let moved = last + 19 + (1023. * 1000. / 52.) as i64;
+
+        // Until the RTC catches up.
+        assert_eq!(m.stamp(moved - 10, 0, 52.), moved - 10);
+        assert_eq!(m.stamp(moved, 512, 52.), moved + 19);
+        assert_eq!(m.stamp(moved + 30_000, 1024, 52.), moved + 30_000);
+
+        // Steps forward are kept.
+        assert_eq!(m.stamp(moved + 100_000, 1024, 52.), moved + 100_000);
+    }
+
+    #[test]
+    fn rate() {
+        assert!(rate_ok(1000, 1000));
+        assert!(rate_ok(1050, 1000));
+        assert!(!rate_ok(0, 1000));
+        assert!(!rate_ok(2000, 1000));
+        assert!(rate_ok(0, 0));
+    }
+
+    #[test]
+    fn stopped_rtc() {
+        let mut m = ClockMonitor::new();
+        let t0 = 1_700_000_000_000;
+
+        // First time the RTC is set, nothing to compare with.
+        assert!(m.check(0, t0));
+
+        // Good crystal.
+        assert!(m.check(t0 + 600_000 + 10, t0 + 600_000));
+        assert!(!m.take_fault());
+
+        // Too short interval to check.
+        assert!(m.check(t0 + 600_000, t0 + 610_000));
+
+        // RTC has stopped.
+        assert!(!m.check(t0 + 610_000, t0 + 1_210_000));
+        assert!(m.take_fault());
+        assert!(!m.take_fault());
+    }
+
+    #[test]
+    fn steps() {
+        let t0 = 1_700_000_000_000;
+
+        let mut w = StepWatch::new(60);
+        assert_eq!(w.update(0, 0, t0), None);
+        assert!(!w.settling(t0));
+
+        // Set back by 1.5 s.
+        assert_eq!(w.update(1, -1500, t0), Some(-1500));
+        assert_eq!(w.update(1, -1500, t0 + 20_000), None);
+
+        assert!(w.settling(t0));
+        assert!(w.settling(t0 + 59_999));
+        assert!(!w.settling(t0 + 60_000));
+        assert!(!w.settling(t0 - 20_000));
+
+        // Without a settle period only the straddling package is moved.
+        let mut w = StepWatch::new(0);
+        assert_eq!(w.update(3, 1 << 40, t0), Some(1 << 40));
+        assert!(!w.settling(t0));
+    }
+
+    #[test]
+    fn implausible_times() {
+        let mut g = TimeGuard::new();
+        let t0 = 1_700_000_000_000;
+
+        // Failed read before any plausible time.
+        let t = g.validate(None);
+        assert_eq!(t.time, MIN_PLAUSIBLE_TIME * 1000);
+        assert!(!t.plausible);
+
+        assert_eq!(g.validate(Some(t0)).time, t0);
+
+        // Far future, rolled over and failed reads hold the last plausible time.
+        let n = implausible_count();
+        for raw in [
+            Some(MAX_PLAUSIBLE_TIME * 1000),
+            Some(-2_208_988_800_000),
+            None,
+        ] {
+            let t = g.validate(raw);
+            assert_eq!((t.raw, t.time, t.plausible), (raw, t0, false));
+        }
+        assert_eq!(g.implausible(), 3);
+        assert!(implausible_count() >= n + 3);
+
+        // Set back by the Notecard is plausible.
+        assert_eq!(g.validate(Some(t0 - 60_000)).time, t0 - 60_000);
+        assert_eq!(g.implausible(), 0);
+    }
+
+    #[test]
+    fn drift_correction() {
+        assert_eq!(drift_ppm(-0.5, 25.0), 0.);
+        assert_eq!(drift_ppm(-0.5, 5.0), 10.);
+
+        let t0 = 1_700_000_000_000;
+
+        // Disabled.
+        let mut d = DriftCorrection::new(0.);
+        assert_eq!(d.update(0, t0 + 3_600_000, 5.0), t0 + 3_600_000);
+
+        // 10 ppm fast in cold water: 36 ms over one hour.
+        let mut d = DriftCorrection::new(-0.5);
+        for k in 0..=180 {
+            let now = t0 + k * 20_000;
+            let c = d.update(0, now, 5.0);
+
+            if k % 5 == 0 {
+                assert_eq!(c, now - k / 5);
+            }
+        }
+
+        // At the reference temperature the correction holds.
+        assert_eq!(d.update(0, t0 + 7_200_000, 25.0), t0 + 7_200_000 - 36);
+
+        // The RTC has been set.
+        assert_eq!(d.update(1, t0 + 7_300_000, 5.0), t0 + 7_300_000);
+        assert_eq!(d.update(1, t0 + 7_400_000, 5.0), t0 + 7_400_000 - 1);
+    }
+}
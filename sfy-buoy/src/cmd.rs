@@ -0,0 +1,256 @@
+//! Commands sent to the buoy over the Notecard.
+//!
+//! A command is issued by adding (or updating) the note `command` in the `cmd.db` notefile of
+//! the device on notehub, e.g.:
+//!
+//! ```json
+//! { "cmd": "reset", "key": "<BUOYCMDKEY>" }
+//! ```
+//!
+//! The note is read and deleted by the buoy in the next notecard iteration of the main loop,
+//! and the command is acknowledged with a note to `cmd.qo`. Commands without the correct `key`
+//! are discarded so that stray notes cannot reboot the buoy. The key is set with `BUOYCMDKEY` at
+//! build time, without it every command is discarded: the serial number, or anything else that is
+//! sent in notes, would not keep out anyone who can see the device on notehub.
+//!
+//! The command is a single note in a DB notefile rather than an inbound queue (`.qi`): a command
+//! that is sent again while the buoy is out of coverage replaces the pending one, rather than
+//! being carried out twice (e.g. two resets) when the buoy syncs. The note is read by its ID, as
+//! the other notes the buoy reads.
+//!
+//! Commands that take an argument carry it in `value`, e.g.:
+//!
+//! ```json
+//! { "cmd": "sync-period", "value": 10, "key": "<BUOYCMDKEY>" }
+//! ```
+//!
+//! and the acknowledgement carries the setting in effect after the command.
+
+/// Notefile commands are read from.
+pub const CMD_FILE: &str = "cmd.db";
+
+/// Note ID of the command in `CMD_FILE`.
+pub const CMD_NOTE: &str = "command";
+
+/// Notefile acknowledgements are sent to.
+pub const CMD_ACK_FILE: &str = "cmd.qo";
+
+/// The key required in the command note, commands are disabled when it is not set.
+pub const CMD_KEY: Option<&str> = option_env!("BUOYCMDKEY");
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Command {
+    /// Flush storage, acknowledge and reset the device.
+    Reset,
+
+    /// Reset and re-configure the Notecard.
+    ReinitNotecard,
+
+    /// Reset the IMU and filters (done in the IMU interrupt).
+    ReinitImu,
+
+    /// Send queued log messages and sync.
+    DumpLogs,
+
+    /// Start the calibration capture of the accelerometer (done in the IMU interrupt), see
+    /// `calibration`.
+    Calibrate,
+
+    /// Set the maximum time between outbound syncs to `value` minutes. Applied to the notecard
+    /// immediately and persisted in the config override (see `Notecarrier::set_sync_period`).
+    SyncPeriod,
+
+    /// Start a new deployment: reset the number of reboots since the start of the deployment (see
+    /// `reboots`).
+    StartDeployment,
+
+    /// Retrieve time and position now, rather than at the next location interval. The fix is
+    /// sent back in the acknowledgement (see [`Fix`]).
+    Locate,
+
+    /// Redo the full setup of the Notecard, also when it is provisioned (see `provision`).
+    Provision,
+
+    /// Activate the beacon for `value` minutes (`max_duration` without a value), `0` stops it.
+    /// The acknowledgement carries the minutes in effect (see `beacon`).
+    Beacon,
+
+    /// Leave safe mode and reset to start normally (see `safe_mode`). Acknowledged as not ok when
+    /// not in safe mode.
+    ExitSafeMode,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default, defmt::Format, PartialEq)]
+pub struct CommandNote {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmd: Option<Command>,
+
+    /// Argument of the command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<heapless::String<32>>,
+}
+
+impl CommandNote {
+    /// Returns the command if the note carries the correct key.
+    pub fn authenticated(&self) -> Option<Command> {
+        self.authenticated_with(CMD_KEY)
+    }
+
+    fn authenticated_with(&self, cmd_key: Option<&str>) -> Option<Command> {
+        match (&self.key, cmd_key) {
+            (Some(key), Some(cmd_key)) if key.as_str() == cmd_key => self.cmd,
+            _ => None,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Default)]
+pub struct CommandAck {
+    pub cmd: Option<Command>,
+    pub ok: bool,
+
+    /// The setting in effect after the command, for commands that change one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<u32>,
+
+    /// The fix retrieved by the `locate` command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<Fix>,
+}
+
+/// Fix retrieved by the `locate` command.
+#[derive(serde::Serialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    /// Position of the fix from the Notecard, not averaged.
+    pub lat: f64,
+    pub lon: f64,
+
+    /// Time of the fix [s].
+    pub position_time: u32,
+
+    /// Dilution of precision of the fix, `0` if unknown.
+    pub dop: f32,
+
+    /// The fix was used, and not rejected by `max_dop`.
+    pub accepted: bool,
+
+    /// Position in use after the fix (averaged with the previous fixes with `position_average`).
+    pub filtered_lat: f64,
+    pub filtered_lon: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_command() {
+        let c: CommandNote =
+            serde_json::from_str(r#"{ "cmd": "reinit-imu", "key": "cain" }"#).unwrap();
+        assert_eq!(c.cmd, Some(Command::ReinitImu));
+
+        let c: CommandNote =
+            serde_json::from_str(r#"{ "cmd": "calibrate", "key": "cain" }"#).unwrap();
+        assert_eq!(c.cmd, Some(Command::Calibrate));
+
+        let c: CommandNote =
+            serde_json::from_str(r#"{ "cmd": "sync-period", "value": 10, "key": "cain" }"#)
+                .unwrap();
+        assert_eq!(c.cmd, Some(Command::SyncPeriod));
+        assert_eq!(c.value, Some(10));
+
+        let c: CommandNote =
+            serde_json::from_str(r#"{ "cmd": "start-deployment", "key": "cain" }"#).unwrap();
+        assert_eq!(c.cmd, Some(Command::StartDeployment));
+
+        let c: CommandNote =
+            serde_json::from_str(r#"{ "cmd": "provision", "key": "cain" }"#).unwrap();
+        assert_eq!(c.cmd, Some(Command::Provision));
+
+        let c: CommandNote =
+            serde_json::from_str(r#"{ "cmd": "beacon", "value": 30, "key": "cain" }"#).unwrap();
+        assert_eq!(c.cmd, Some(Command::Beacon));
+        assert_eq!(c.value, Some(30));
+
+        let c: CommandNote =
+            serde_json::from_str(r#"{ "cmd": "exit-safe-mode", "key": "cain" }"#).unwrap();
+        assert_eq!(c.cmd, Some(Command::ExitSafeMode));
+    }
+
+    #[test]
+    fn ack_value() {
+        let a = CommandAck {
+            cmd: Some(Command::SyncPeriod),
+            ok: true,
+            value: Some(10),
+            fix: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            r#"{"cmd":"sync-period","ok":true,"value":10}"#
+        );
+
+        let a = CommandAck {
+            cmd: Some(Command::Reset),
+            ok: true,
+            value: None,
+            fix: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            r#"{"cmd":"reset","ok":true}"#
+        );
+
+        let a = CommandAck {
+            cmd: Some(Command::Locate),
+            ok: true,
+            value: None,
+            fix: Some(Fix {
+                lat: 60.5,
+                lon: 5.25,
+                position_time: 1_700_000_000,
+                dop: 1.5,
+                accepted: true,
+                filtered_lat: 60.5,
+                filtered_lon: 5.25,
+            }),
+        };
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            r#"{"cmd":"locate","ok":true,"fix":{"lat":60.5,"lon":5.25,"position_time":1700000000,"dop":1.5,"accepted":true,"filtered_lat":60.5,"filtered_lon":5.25}}"#
+        );
+    }
+
+    #[test]
+    fn reject_wrong_key() {
+        let mut c = CommandNote {
+            cmd: Some(Command::Reset),
+            value: None,
+            key: None,
+        };
+        assert_eq!(c.authenticated_with(Some("cain")), None);
+
+        c.key = Some("wrong".into());
+        assert_eq!(c.authenticated_with(Some("cain")), None);
+
+        c.key = Some("cain".into());
+        assert_eq!(c.authenticated_with(Some("cain")), Some(Command::Reset));
+    }
+
+    #[test]
+    fn disabled_without_key() {
+        let c = CommandNote {
+            cmd: Some(Command::Reset),
+            value: None,
+            key: Some(crate::note::BUOYSN.into()),
+        };
+        assert_eq!(c.authenticated_with(None), None);
+
+        let c = CommandNote { key: None, ..c };
+        assert_eq!(c.authenticated_with(None), None);
+    }
+}
@@ -0,0 +1,2106 @@
+//! Run-time configuration of the buoy.
+//!
+//! The configuration is resolved once at boot, with the following precedence (later sources
+//! override earlier):
+//!
+//! 1. Compiled defaults (constants and build-time environment variables, e.g. `BUOYPR` and
+//!    `GPS_PERIOD`).
+//! 2. The JSON file `SFY.CFG` in the root of the SD-card (requires the `storage` feature).
+//! 3. The environment variable `sfy_config` on the Notecard, set for the fleet or the device on
+//!    notehub.
+//! 4. The note `config` in the `config.db` notefile on the Notecard, set from notehub.
+//!
+//! Every source may specify any subset of the fields, e.g.:
+//!
+//! ```json
+//! { "gps_period": 120, "sync_period": 20 }
+//! ```
+//!
+//! An override that results in an invalid configuration is rejected as a whole, and the
+//! configuration from the previous source is kept.
+//!
+//! The sample rate, the FIR filter and the queue sizes are decided at compile time (features
+//! `fir`, `20Hz` and `raw`) and cannot be changed here.
+
+use crate::adc;
+use crate::axl::SampleEncoding;
+use crate::backfill::{Compression, LiveBatch, MAX_NOTE_SIZE, MIN_NOTE_SIZE};
+use crate::beacon::Beacon;
+use crate::bist::Bist;
+use crate::burst::Burst;
+use crate::clock::{MAX_RTC_SETTLE, MAX_TEMP_COEFF};
+use crate::deployment;
+use crate::filter::{DecimationMode, FirFilter};
+use crate::gate::MotionGate;
+use crate::log::LogTime;
+use crate::note::GPS_PERIOD;
+use crate::postmortem;
+use crate::queue::{CardQueue, QueueFull, QueuePolicy};
+use crate::quiet::QuietHours;
+use crate::safe_mode;
+use crate::transport::Transport;
+use crate::urgency::Urgencies;
+use crate::waves::dlpf::{AccelLpf, GyroLpf};
+use crate::waves::gap;
+use crate::waves::thermal;
+use crate::waves::wire::ACCEL_MAX;
+use crate::waves::BiasRemoval;
+use crate::waves::FifoFormat;
+use crate::waves::Retry;
+use crate::waves::SENSORS_GRAVITY_STANDARD;
+use crate::weighting::Weighting;
+use static_assertions as sa;
+
+/// Config file on SD-card.
+pub const CONFIG_FILE: &str = "SFY.CFG";
+
+/// Notefile with the notehub config override.
+pub const CONFIG_NOTEFILE: &str = "config.db";
+
+/// Note ID of the config override in `CONFIG_NOTEFILE`.
+pub const CONFIG_NOTE: &str = "config";
+
+/// Notecard environment variable with a config override, in the same JSON as `CONFIG_FILE`.
+pub const CONFIG_ENV: &str = "sfy_config";
+
+/// Maximum size of config file.
+pub const CONFIG_SZ: usize = 1024;
+
+/// Default I2C address of the Notecard.
+pub const NOTECARD_ADDRESS: u8 = 0x17;
+
+/// Default I2C address of the IMU (`0x6b` when SA0 is pulled high).
+pub const IMU_ADDRESS: u8 = 0x6a;
+
+/// Default I2C address of the second IMU, with the `redundant-imu` feature.
+#[cfg(feature = "redundant-imu")]
+pub const IMU_ADDRESS_SECONDARY: u8 = 0x6b;
+
+/// Default failed syncs in a row before failing over to the other Notecard, with the
+/// `redundant-notecard` feature.
+#[cfg(feature = "redundant-notecard")]
+pub const FAILOVER_SYNCS: u8 = 3;
+
+/// Maximum accelerometer bias of each axis [m/s^2].
+pub const MAX_ACCEL_BIAS: f32 = 1.0;
+
+/// Maximum of `bias_window` [s].
+pub const MAX_BIAS_WINDOW: u32 = 600;
+
+/// Maximum offset of the IMU from the center of buoyancy along each axis [m].
+pub const MAX_LEVER_ARM: f32 = 5.0;
+
+/// Maximum of `location_fixes` and `location_failures`.
+pub const MAX_LOCATION_DEBOUNCE: u32 = 10;
+
+/// Default warm-up of the IMU and the GPS after power-on [s].
+pub const DEFAULT_WARMUP: u16 = 30;
+
+/// Maximum warm-up [s].
+pub const MAX_WARMUP: u16 = 600;
+
+/// Maximum spacing of the requests to the Notecard [ms].
+pub const MAX_REQUEST_SPACING: u16 = 5000;
+
+/// Full scale of accelerometer. Note that acceleration is scaled to ±2 g on the wire
+/// (`waves::wire::ACCEL_MAX`) unless the scale follows the range (see [`AccelScale`]).
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccelRange {
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+impl AccelRange {
+    /// Full scale [m/s^2].
+    pub fn full_scale(&self) -> f32 {
+        use AccelRange::*;
+
+        let g = match self {
+            G2 => 2.,
+            G4 => 4.,
+            G8 => 8.,
+            G16 => 16.,
+        };
+
+        g * SENSORS_GRAVITY_STANDARD as f32
+    }
+}
+
+/// Scale of the acceleration samples in the packages (16 bit counts).
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccelScale {
+    /// ±2 g (`waves::wire::ACCEL_MAX`) regardless of the range, samples beyond are clipped.
+    Fixed,
+
+    /// The full scale of `accel_range`, at a coarser step for the larger ranges.
+    Range,
+}
+
+/// Products sent over the Notecard, every package is stored to the SD-card regardless (with the
+/// `storage` feature).
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Products {
+    /// Full time series of acceleration (`axl.qo`).
+    pub timeseries: bool,
+
+    /// Statistics of every package (`stats.qo`).
+    pub stats: bool,
+}
+
+impl Default for Products {
+    fn default() -> Products {
+        Products {
+            timeseries: true,
+            stats: false,
+        }
+    }
+}
+
+/// Time to wait for the response to a request to the Notecard [ms]. Getting the location can
+/// take much longer than other requests when the signal is weak. Fields that are not set in an
+/// override take the default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Timeouts {
+    /// `card.location`.
+    pub location: u32,
+
+    /// `card.time`.
+    pub time: u32,
+
+    /// All other requests.
+    pub request: u32,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            location: 15_000,
+            time: 5_000,
+            request: 5_000,
+        }
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Notehub product UID.
+    pub product: heapless::String<64>,
+
+    /// Period of GPS fixes on the Notecard [s].
+    pub gps_period: u32,
+
+    /// Interval between retrieving location and time from the Notecard [s].
+    pub location_interval: u32,
+
+    /// Longest interval between the attempts while the location keeps failing [s], the interval
+    /// backs off from `location_interval` (see `location_backoff`). 0 disables the backoff.
+    pub location_backoff: u32,
+
+    /// Number of consecutive GPS fixes to average, for moored buoys. 1 disables averaging.
+    pub position_average: u32,
+
+    /// Consecutive good location attempts before the location is retrieved, and consecutive
+    /// failed attempts before it is tried again (see `LocationState`).
+    pub location_fixes: u32,
+    pub location_failures: u32,
+
+    /// Fixes with a dilution of precision above this are not used. 0 disables.
+    pub max_dop: f32,
+
+    /// Maximum age of the position of a package before it is flagged as stale [s], see
+    /// `quality::GPS_STALE`.
+    pub gps_stale_age: u32,
+
+    /// Log a warning when the position becomes stale.
+    pub gps_stale_warn: bool,
+
+    /// Maximum time between outbound syncs [minutes].
+    pub sync_period: u32,
+
+    /// Daily window without transmissions (see `quiet`).
+    pub quiet_hours: QuietHours,
+
+    /// Motion events of the accelerometer of the Notecard between checks that raise an impact
+    /// alarm (see `tamper`). 0 disables.
+    pub motion_threshold: u32,
+
+    /// Temperature coefficient of the RTC crystal [ppm/°C], 0 disables the drift correction (see
+    /// `clock::DriftCorrection`).
+    pub rtc_temp_coeff: f32,
+
+    /// Period after the RTC has been set during which the timestamps of the packages are not
+    /// trusted [s]: packages starting in it are flagged (`quality::RTC_STEP`), `0` disables.
+    pub rtc_settle: u16,
+
+    /// Keep the timestamps of the packages monotonic when the RTC is set backwards, the time of
+    /// the RTC is kept in the package (see `clock::Monotonic`).
+    pub monotonic: bool,
+
+    pub accel_range: AccelRange,
+
+    /// Scale of the acceleration samples in the packages.
+    pub accel_scale: AccelScale,
+
+    /// Bandwidth of the on-chip low-pass filter of the accelerometer (see `waves::dlpf`).
+    pub accel_lpf: AccelLpf,
+
+    /// On-chip low-pass filter (LPF1) of the gyroscope.
+    pub gyro_lpf: GyroLpf,
+
+    /// Removal of the acceleration offset (see `waves::bias`).
+    pub bias_removal: BiasRemoval,
+
+    /// Accelerometer bias (x, y, z) in the body frame [m/s^2], for `BiasRemoval::Calibration`.
+    pub accel_bias: [f32; 3],
+
+    /// Accelerometer bias and scale by temperature, for `BiasRemoval::Calibration`, used instead
+    /// of `accel_bias` when not empty (see `waves::thermal`).
+    pub accel_thermal: thermal::Table,
+
+    /// Window the bias is estimated over while the buoy is still, for `BiasRemoval::Still` [s].
+    pub bias_window: u32,
+
+    /// Position (x, y, z) of the IMU relative to the center of buoyancy in the body frame [m], see
+    /// `waves::lever_arm`. Zero disables the correction.
+    pub lever_arm: [f32; 3],
+
+    /// Axes of the acceleration to filter and store (bit 0: x, bit 1: y, bit 2: z, see
+    /// `AxlPacket::axes`). Disabled axes are left out of the packages, e.g. `4` for the vertical
+    /// only.
+    pub axes: u8,
+
+    /// Warm-up after power-on [s]: the samples of the IMU and the GPS fixes are discarded until
+    /// it has passed, `0` disables (see `AxlPacket::warmup`).
+    pub warmup: u16,
+
+    /// Duration of the deployment from the first time sync [hours], after which the buoy stops
+    /// sampling and stays in standby until reset. At most `deployment::MAX_DURATION`, `0` for no
+    /// limit (see `deployment`).
+    pub deployment_duration: u32,
+
+    /// Self-test at boot, reported in `bist.qo` (see `bist`).
+    pub bist: Bist,
+
+    /// Bursts of full-rate samples around extreme events (see `burst`).
+    pub burst: Burst,
+
+    /// Pattern and activation of the beacon, with the `beacon` feature (see `beacon`).
+    pub beacon: Beacon,
+
+    /// Safe mode after repeated fault resets, with the `storage` feature (see `safe_mode`).
+    pub safe_mode: safe_mode::Config,
+
+    /// Auxiliary analog channels read at every health report, with the `adc` feature (see
+    /// `adc`).
+    pub adc: heapless::Vec<adc::Channel, { adc::MAX_CHANNELS }>,
+
+    /// Accelerometer samples kept for the post-mortem of a panic or hard fault, at most
+    /// `postmortem::CAPACITY`, `0` disables (see `postmortem`).
+    pub postmortem: u16,
+
+    /// Longest gap in the FIFO of the IMU that is filled by interpolation [samples], at most
+    /// `waves::gap::MAX_GAP`, `0` disables (see `waves::gap`). Longer gaps reset the IMU.
+    pub max_gap: u8,
+
+    /// Sensors batched into the FIFO of the IMU, which also decides how the FIFO is decoded (see
+    /// `waves::format`).
+    pub fifo_format: FifoFormat,
+
+    /// Record the samples read from the IMU and the samples out of the decimation in every
+    /// package (`AxlPacket::raw_samples` and `output_samples`), to verify the decimation.
+    pub sample_counts: bool,
+
+    /// Stop storing to SD-card when free space is below this [bytes].
+    pub min_free_space: u64,
+
+    /// Remove the oldest collections when free space is below `min_free_space`, rather than stop
+    /// storing (see `storage::ring`).
+    pub ring_buffer: bool,
+
+    /// Read every package back from the SD-card after it is written and compare it to what was
+    /// written, a mismatch counts as an error of the card (see `storage::recovery`).
+    pub verify_writes: bool,
+
+    /// What gives way when the data queues back up: the SD-card or the live transmission (see
+    /// `queue::QueuePolicy`).
+    pub queue_policy: QueuePolicy,
+
+    /// What the IMU does with a package that does not fit in the full data queue, and the storage
+    /// manager with a package that does not fit in the notecard queue (see `queue::QueueFull`).
+    pub queue_full: QueueFull,
+
+    /// Backpressure from the notes waiting on the notecard to be synced (see
+    /// `queue::CardQueue`).
+    pub card_queue: CardQueue,
+
+    pub products: Products,
+
+    /// Only send the time series when there is motion (see `gate`).
+    pub motion_gate: MotionGate,
+
+    /// Weight of clipped and interpolated samples in the statistics (see `weighting`).
+    pub stats_weighting: Weighting,
+
+    /// Sync urgency of every kind of outbound note (see `urgency`).
+    pub urgency: Urgencies,
+
+    /// Connection of the Notecard to notehub, set when it is provisioned (see `transport`).
+    pub transport: Transport,
+
+    /// I2C address of the Notecard. Only the default address is supported by the driver.
+    pub notecard_address: u8,
+
+    /// I2C address of the IMU.
+    pub imu_address: u8,
+
+    /// Retries of the setup of the IMU and of enabling its FIFO (see `waves::retry`).
+    pub imu_retry: Retry,
+
+    /// I2C address of the second IMU, must differ from `imu_address`.
+    #[cfg(feature = "redundant-imu")]
+    pub imu_address_secondary: u8,
+
+    /// Failed sync attempts in a row before failing over to the other Notecard, from `1` to the
+    /// length of the sync history (see `failover`).
+    #[cfg(feature = "redundant-notecard")]
+    pub failover_syncs: u8,
+
+    /// Time on the secondary Notecard before the primary is tried again [minutes], `0` to stay
+    /// on the secondary.
+    #[cfg(feature = "redundant-notecard")]
+    pub failover_retry: u32,
+
+    pub timeouts: Timeouts,
+
+    /// Reset the board when the main loop stalls for this long [s], 0 disables the watchdog (see
+    /// `watchdog`).
+    pub watchdog: u32,
+
+    /// Minimum time between the requests that send notes to the Notecard [ms] (see `pace`).
+    pub request_spacing: u16,
+
+    /// Decimation from the IMU rate to the output rate: FIR filter or block averaging (see
+    /// `filter::DecimationMode`).
+    pub decimation_mode: DecimationMode,
+
+    /// Coefficients of the anti-aliasing FIR filter (see `filter::FirFilter`).
+    pub fir_filter: FirFilter,
+
+    /// Decimation from the IMU rate (`waves::FREQ`) to the output rate, `0` for the decimation of
+    /// the build (`waves::DECIMATION`). See [`Config::decimation`].
+    pub decimation: u8,
+
+    /// Format and time zone of the time stamps of the event log on the SD-card (see `log`).
+    pub log_time: LogTime,
+
+    /// Flush packages every this number of samples, 0 flushes when the buffer is full (see
+    /// `waves::FlushPolicy`).
+    pub flush_samples: u32,
+
+    /// Flush packages at this interval [ms], possibly before they are full. 0 disables.
+    pub flush_interval: u32,
+
+    /// Keep reading the IMU into a second buffer while a full buffer waits to be taken (see
+    /// `waves::buf`).
+    pub double_buffer: bool,
+
+    /// Maximum number of stored packages queued for the notecard per replay of a request.
+    pub replay_batch: u32,
+
+    /// Encoding of the packages in the batches of replayed packages (see
+    /// `backfill::Compression`).
+    pub backfill_compression: Compression,
+
+    /// Encoding of the samples of the packages on the SD-card and in the data notes (see
+    /// `axl::SampleEncoding`).
+    pub sample_encoding: SampleEncoding,
+
+    /// Batching of the live packages into backfill notes, rather than a data note per package
+    /// (see `backfill::LiveBatch`).
+    pub live_batch: LiveBatch,
+
+    /// Maximum size of the base64 payload of a backfill note [bytes], the batches of replayed and
+    /// live packages are closed before they grow past it (see `backfill::Batch::with_limit`).
+    pub max_note_size: u32,
+
+    /// Do not add packages that the notecard has already accepted again, unless replayed (see
+    /// `dedup`).
+    pub dedup: bool,
+
+    /// Name collections on the SD-card by UTC day, closing them at midnight (see
+    /// `storage::days`).
+    pub day_files: bool,
+
+    /// Add a summary to the body of every data note for the notehub event viewer: the age of the
+    /// position fix and a rough significant wave height (see `axl::AxlPacket::hs`). Not sent
+    /// with the `encryption` feature.
+    pub note_summary: bool,
+
+    /// Window length of spike removal filter [samples].
+    #[cfg(feature = "despike")]
+    pub despike_window: u32,
+}
+
+/// Partial configuration, any field set overrides the current config.
+#[derive(serde::Serialize, serde::Deserialize, Default, defmt::Format, Debug, PartialEq)]
+pub struct ConfigOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<heapless::String<64>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_period: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location_interval: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location_backoff: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position_average: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location_fixes: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location_failures: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_dop: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_stale_age: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps_stale_warn: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_period: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<QuietHours>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motion_threshold: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtc_temp_coeff: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtc_settle: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monotonic: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accel_range: Option<AccelRange>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accel_scale: Option<AccelScale>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accel_lpf: Option<AccelLpf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gyro_lpf: Option<GyroLpf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bias_removal: Option<BiasRemoval>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accel_bias: Option<[f32; 3]>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accel_thermal: Option<thermal::Table>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bias_window: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lever_arm: Option<[f32; 3]>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub axes: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment_duration: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bist: Option<Bist>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<Burst>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub beacon: Option<Beacon>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safe_mode: Option<safe_mode::Config>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adc: Option<heapless::Vec<adc::Channel, { adc::MAX_CHANNELS }>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postmortem: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_gap: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fifo_format: Option<FifoFormat>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_counts: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_free_space: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ring_buffer: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_writes: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_policy: Option<QueuePolicy>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_full: Option<QueueFull>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub card_queue: Option<CardQueue>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub products: Option<Products>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motion_gate: Option<MotionGate>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats_weighting: Option<Weighting>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub urgency: Option<Urgencies>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<Transport>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notecard_address: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imu_address: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imu_retry: Option<Retry>,
+
+    #[cfg(feature = "redundant-imu")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imu_address_secondary: Option<u8>,
+
+    #[cfg(feature = "redundant-notecard")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover_syncs: Option<u8>,
+
+    #[cfg(feature = "redundant-notecard")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover_retry: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeouts: Option<Timeouts>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watchdog: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_spacing: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decimation_mode: Option<DecimationMode>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fir_filter: Option<FirFilter>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decimation: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_time: Option<LogTime>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flush_samples: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flush_interval: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub double_buffer: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_batch: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backfill_compression: Option<Compression>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_encoding: Option<SampleEncoding>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub live_batch: Option<LiveBatch>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_note_size: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dedup: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub day_files: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_summary: Option<bool>,
+
+    #[cfg(feature = "despike")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub despike_window: Option<u32>,
+}
+
+#[derive(defmt::Format, Debug, PartialEq)]
+pub enum ConfigError {
+    Parse,
+    EmptyProduct,
+    ProductTooLong(usize),
+    GpsPeriod(u32),
+    LocationInterval(u32),
+    LocationBackoff(u32),
+    PositionAverage(u32),
+    LocationFixes(u32),
+    LocationFailures(u32),
+    MaxDop,
+    GpsStaleAge(u32),
+    SyncPeriod(u32),
+    QuietHours,
+    RtcTempCoeff,
+    RtcSettle(u16),
+    NoProducts,
+    MotionGate,
+    StatsWeighting,
+    I2CAddress(u8),
+    ImuRetry,
+    #[cfg(feature = "redundant-imu")]
+    ImuAddressSecondary(u8),
+    #[cfg(feature = "redundant-notecard")]
+    FailoverSyncs(u8),
+    Timeout(u32),
+    Watchdog(u32),
+    RequestSpacing(u16),
+    Decimation(u8),
+    UtcOffset(i16),
+    FlushSamples(u32),
+    FlushInterval(u32),
+    ReplayBatch(u32),
+    LiveBatch,
+    MaxNoteSize(u32),
+    AccelBias,
+    AccelThermal,
+    BiasWindow(u32),
+    LeverArm,
+    Axes(u8),
+    Warmup(u16),
+    DeploymentDuration(u32),
+    Bist,
+    Burst,
+    Beacon,
+    SafeMode,
+    Adc,
+    CardQueue,
+    Postmortem(u16),
+    MaxGap(u8),
+    #[cfg(feature = "despike")]
+    DespikeWindow(u32),
+}
+
+// The compiled default product must fit `Config::product`.
+sa::const_assert!(env!("BUOYPR", "Specify notehub project").len() <= 64);
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            product: env!("BUOYPR", "Specify notehub project").into(),
+            gps_period: GPS_PERIOD,
+            location_interval: 60,
+            location_backoff: 30 * 60,
+            position_average: 1,
+            location_fixes: 2,
+            location_failures: 3,
+            max_dop: 0.,
+            gps_stale_age: crate::quality::GPS_STALE_AGE,
+            gps_stale_warn: false,
+            sync_period: 40,
+            quiet_hours: QuietHours::default(),
+            motion_threshold: 0,
+            rtc_temp_coeff: 0.,
+            rtc_settle: 0,
+            monotonic: false,
+            accel_range: AccelRange::G2,
+            accel_scale: AccelScale::Fixed,
+            accel_lpf: AccelLpf::Odr4,
+            gyro_lpf: GyroLpf::Off,
+            bias_removal: BiasRemoval::Off,
+            accel_bias: [0.; 3],
+            accel_thermal: thermal::Table::new(),
+            bias_window: 30,
+            lever_arm: [0.; 3],
+            axes: crate::axl::AXES_ALL,
+            warmup: DEFAULT_WARMUP,
+            deployment_duration: 0,
+            bist: Bist::default(),
+            burst: Burst::default(),
+            beacon: Beacon::default(),
+            safe_mode: safe_mode::Config::default(),
+            adc: heapless::Vec::new(),
+            postmortem: postmortem::CAPACITY as u16,
+            max_gap: gap::DEFAULT_MAX_GAP,
+            fifo_format: FifoFormat::AccelGyro,
+            sample_counts: false,
+            min_free_space: 64 * 1024 * 1024,
+            ring_buffer: false,
+            verify_writes: false,
+            queue_policy: QueuePolicy::Storage,
+            queue_full: QueueFull::DropNewest,
+            card_queue: CardQueue::default(),
+            products: Products::default(),
+            motion_gate: MotionGate::default(),
+            stats_weighting: Weighting::default(),
+            urgency: Urgencies::default(),
+            transport: crate::transport::TRANSPORT,
+            notecard_address: NOTECARD_ADDRESS,
+            imu_address: IMU_ADDRESS,
+            imu_retry: Retry::default(),
+            #[cfg(feature = "redundant-imu")]
+            imu_address_secondary: IMU_ADDRESS_SECONDARY,
+            #[cfg(feature = "redundant-notecard")]
+            failover_syncs: FAILOVER_SYNCS,
+            #[cfg(feature = "redundant-notecard")]
+            failover_retry: 24 * 60,
+            timeouts: Timeouts::default(),
+            watchdog: crate::watchdog::DEFAULT_TIMEOUT,
+            request_spacing: 50,
+            decimation_mode: DecimationMode::Fir,
+            fir_filter: FirFilter::default(),
+            decimation: 0,
+            log_time: LogTime::default(),
+            flush_samples: 0,
+            flush_interval: 0,
+            double_buffer: true,
+            replay_batch: 100,
+            backfill_compression: Compression::Delta,
+            sample_encoding: SampleEncoding::Raw,
+            live_batch: LiveBatch::default(),
+            max_note_size: MAX_NOTE_SIZE,
+            dedup: true,
+            day_files: false,
+            note_summary: false,
+            #[cfg(feature = "despike")]
+            despike_window: crate::despike::WINDOW as u32,
+        }
+    }
+}
+
+impl Config {
+    /// Full scale of the acceleration samples in the packages [m/s^2], see `accel_scale`.
+    pub fn accel_max(&self) -> f32 {
+        match self.accel_scale {
+            AccelScale::Fixed => ACCEL_MAX,
+            AccelScale::Range => self.accel_range.full_scale(),
+        }
+    }
+
+    /// Decimation from the IMU rate to the output rate (`decimation`, or `waves::DECIMATION` if
+    /// not set). The output rate, and the rate of the packages, is `waves::FREQ / decimation`.
+    pub fn decimation(&self) -> u8 {
+        match self.decimation {
+            0 => crate::waves::DECIMATION,
+            d => d,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        use ConfigError::*;
+
+        if self.product.is_empty() {
+            return Err(EmptyProduct);
+        }
+
+        if !(10..=24 * 3600).contains(&self.gps_period) {
+            return Err(GpsPeriod(self.gps_period));
+        }
+
+        if !(10..=24 * 3600).contains(&self.location_interval) {
+            return Err(LocationInterval(self.location_interval));
+        }
+
+        if self.location_backoff != 0
+            && !(self.location_interval..=24 * 3600).contains(&self.location_backoff)
+        {
+            return Err(LocationBackoff(self.location_backoff));
+        }
+
+        if !(1..=crate::fix_average::MAX_WINDOW as u32).contains(&self.position_average) {
+            return Err(PositionAverage(self.position_average));
+        }
+
+        if !(1..=MAX_LOCATION_DEBOUNCE).contains(&self.location_fixes) {
+            return Err(LocationFixes(self.location_fixes));
+        }
+
+        if !(1..=MAX_LOCATION_DEBOUNCE).contains(&self.location_failures) {
+            return Err(LocationFailures(self.location_failures));
+        }
+
+        if !self.max_dop.is_finite() || self.max_dop < 0. {
+            return Err(MaxDop);
+        }
+
+        if !(60..=7 * 24 * 3600).contains(&self.gps_stale_age) {
+            return Err(GpsStaleAge(self.gps_stale_age));
+        }
+
+        if !(1..=24 * 60).contains(&self.sync_period) {
+            return Err(SyncPeriod(self.sync_period));
+        }
+
+        if !self.quiet_hours.is_valid() {
+            return Err(ConfigError::QuietHours);
+        }
+
+        if !self.rtc_temp_coeff.is_finite() || self.rtc_temp_coeff.abs() > MAX_TEMP_COEFF {
+            return Err(RtcTempCoeff);
+        }
+
+        if self.rtc_settle > MAX_RTC_SETTLE {
+            return Err(RtcSettle(self.rtc_settle));
+        }
+
+        if !self.products.timeseries && !self.products.stats {
+            return Err(NoProducts);
+        }
+
+        if !self.motion_gate.is_valid() {
+            return Err(ConfigError::MotionGate);
+        }
+
+        if !self.stats_weighting.is_valid() {
+            return Err(ConfigError::StatsWeighting);
+        }
+
+        // 7-bit addresses, excluding the reserved ranges.
+        for a in [self.notecard_address, self.imu_address] {
+            if !(0x08..=0x77).contains(&a) {
+                return Err(I2CAddress(a));
+            }
+        }
+
+        if !self.imu_retry.is_valid() {
+            return Err(ImuRetry);
+        }
+
+        #[cfg(feature = "redundant-imu")]
+        if !(0x08..=0x77).contains(&self.imu_address_secondary)
+            || self.imu_address_secondary == self.imu_address
+        {
+            return Err(ImuAddressSecondary(self.imu_address_secondary));
+        }
+
+        #[cfg(feature = "redundant-notecard")]
+        if !(1..=crate::sync_history::SYNC_HISTORY_SZ as u8).contains(&self.failover_syncs) {
+            return Err(FailoverSyncs(self.failover_syncs));
+        }
+
+        let t = &self.timeouts;
+        for t in [t.location, t.time, t.request] {
+            if !(100..=120_000).contains(&t) {
+                return Err(Timeout(t));
+            }
+        }
+
+        // The watchdog must not run out while the Notecard waits for the GPS, the time and a
+        // request in the same turn of the main loop.
+        if !crate::watchdog::is_valid(self.watchdog)
+            || (self.watchdog != 0 && self.watchdog * 1000 <= t.location + t.time + t.request)
+        {
+            return Err(Watchdog(self.watchdog));
+        }
+
+        if self.request_spacing > MAX_REQUEST_SPACING {
+            return Err(RequestSpacing(self.request_spacing));
+        }
+
+        // The output rate is an integer rate, within the cut-off of the FIR filter.
+        let d = self.decimation();
+        if d > crate::waves::MAX_DECIMATION
+            || crate::waves::FREQ.value() as u32 % d as u32 != 0
+            || (cfg!(feature = "fir")
+                && self.decimation_mode == DecimationMode::Fir
+                && d > self.fir_filter.max_decimation())
+        {
+            return Err(Decimation(self.decimation));
+        }
+
+        if !self.log_time.is_valid() {
+            return Err(UtcOffset(self.log_time.utc_offset));
+        }
+
+        if self.flush_samples > crate::axl::SAMPLE_NO as u32 {
+            return Err(FlushSamples(self.flush_samples));
+        }
+
+        // Only one flush policy at the time.
+        if self.flush_interval != 0 && (self.flush_interval < 1000 || self.flush_samples != 0) {
+            return Err(FlushInterval(self.flush_interval));
+        }
+
+        if !self
+            .accel_bias
+            .iter()
+            .all(|b| b.is_finite() && b.abs() < MAX_ACCEL_BIAS)
+        {
+            return Err(AccelBias);
+        }
+
+        if !thermal::is_valid(&self.accel_thermal, MAX_ACCEL_BIAS) {
+            return Err(AccelThermal);
+        }
+
+        if self.bias_window == 0 || self.bias_window > MAX_BIAS_WINDOW {
+            return Err(BiasWindow(self.bias_window));
+        }
+
+        if !self
+            .lever_arm
+            .iter()
+            .all(|r| r.is_finite() && r.abs() <= MAX_LEVER_ARM)
+        {
+            return Err(LeverArm);
+        }
+
+        if self.axes == 0 || self.axes & !crate::axl::AXES_ALL != 0 {
+            return Err(Axes(self.axes));
+        }
+
+        if self.warmup > MAX_WARMUP {
+            return Err(Warmup(self.warmup));
+        }
+
+        if self.deployment_duration > deployment::MAX_DURATION {
+            return Err(DeploymentDuration(self.deployment_duration));
+        }
+
+        if !self.bist.is_valid() {
+            return Err(ConfigError::Bist);
+        }
+
+        if !self.burst.is_valid() {
+            return Err(ConfigError::Burst);
+        }
+
+        if !self.beacon.is_valid() {
+            return Err(ConfigError::Beacon);
+        }
+
+        if !self.safe_mode.is_valid() {
+            return Err(ConfigError::SafeMode);
+        }
+
+        if !adc::is_valid(&self.adc) {
+            return Err(ConfigError::Adc);
+        }
+
+        if !self.card_queue.is_valid() {
+            return Err(ConfigError::CardQueue);
+        }
+
+        if self.postmortem as usize > postmortem::CAPACITY {
+            return Err(Postmortem(self.postmortem));
+        }
+
+        if self.max_gap as usize > gap::MAX_GAP {
+            return Err(MaxGap(self.max_gap));
+        }
+
+        if !(1..=1000).contains(&self.replay_batch) {
+            return Err(ReplayBatch(self.replay_batch));
+        }
+
+        if !self.live_batch.is_valid() {
+            return Err(ConfigError::LiveBatch);
+        }
+
+        if !(MIN_NOTE_SIZE..=MAX_NOTE_SIZE).contains(&self.max_note_size) {
+            return Err(MaxNoteSize(self.max_note_size));
+        }
+
+        #[cfg(feature = "despike")]
+        if !(3..=crate::despike::MAX_WINDOW as u32).contains(&self.despike_window) {
+            return Err(DespikeWindow(self.despike_window));
+        }
+
+        Ok(())
+    }
+
+    /// Apply override, the config is left unchanged if the result is not valid.
+    pub fn apply(&mut self, o: &ConfigOverride) -> Result<(), ConfigError> {
+        let mut c = self.clone();
+
+        if let Some(product) = &o.product {
+            c.product = product.clone();
+        }
+
+        c.gps_period = o.gps_period.unwrap_or(c.gps_period);
+        c.location_interval = o.location_interval.unwrap_or(c.location_interval);
+        c.location_backoff = o.location_backoff.unwrap_or(c.location_backoff);
+        c.position_average = o.position_average.unwrap_or(c.position_average);
+        c.location_fixes = o.location_fixes.unwrap_or(c.location_fixes);
+        c.location_failures = o.location_failures.unwrap_or(c.location_failures);
+        c.max_dop = o.max_dop.unwrap_or(c.max_dop);
+        c.gps_stale_age = o.gps_stale_age.unwrap_or(c.gps_stale_age);
+        c.gps_stale_warn = o.gps_stale_warn.unwrap_or(c.gps_stale_warn);
+        c.sync_period = o.sync_period.unwrap_or(c.sync_period);
+        c.quiet_hours = o.quiet_hours.unwrap_or(c.quiet_hours);
+        c.motion_threshold = o.motion_threshold.unwrap_or(c.motion_threshold);
+        c.rtc_temp_coeff = o.rtc_temp_coeff.unwrap_or(c.rtc_temp_coeff);
+        c.rtc_settle = o.rtc_settle.unwrap_or(c.rtc_settle);
+        c.monotonic = o.monotonic.unwrap_or(c.monotonic);
+        c.accel_range = o.accel_range.unwrap_or(c.accel_range);
+        c.accel_scale = o.accel_scale.unwrap_or(c.accel_scale);
+        c.accel_lpf = o.accel_lpf.unwrap_or(c.accel_lpf);
+        c.gyro_lpf = o.gyro_lpf.unwrap_or(c.gyro_lpf);
+        c.bias_removal = o.bias_removal.unwrap_or(c.bias_removal);
+        c.accel_bias = o.accel_bias.unwrap_or(c.accel_bias);
+        if let Some(t) = &o.accel_thermal {
+            c.accel_thermal = t.clone();
+        }
+        c.bias_window = o.bias_window.unwrap_or(c.bias_window);
+        c.lever_arm = o.lever_arm.unwrap_or(c.lever_arm);
+        c.axes = o.axes.unwrap_or(c.axes);
+        c.warmup = o.warmup.unwrap_or(c.warmup);
+        c.deployment_duration = o.deployment_duration.unwrap_or(c.deployment_duration);
+        c.bist = o.bist.unwrap_or(c.bist);
+        c.burst = o.burst.unwrap_or(c.burst);
+        c.beacon = o.beacon.unwrap_or(c.beacon);
+        c.safe_mode = o.safe_mode.unwrap_or(c.safe_mode);
+        if let Some(adc) = &o.adc {
+            c.adc = adc.clone();
+        }
+        c.postmortem = o.postmortem.unwrap_or(c.postmortem);
+        c.max_gap = o.max_gap.unwrap_or(c.max_gap);
+        c.fifo_format = o.fifo_format.unwrap_or(c.fifo_format);
+        c.sample_counts = o.sample_counts.unwrap_or(c.sample_counts);
+        c.min_free_space = o.min_free_space.unwrap_or(c.min_free_space);
+        c.ring_buffer = o.ring_buffer.unwrap_or(c.ring_buffer);
+        c.verify_writes = o.verify_writes.unwrap_or(c.verify_writes);
+        c.queue_policy = o.queue_policy.unwrap_or(c.queue_policy);
+        c.queue_full = o.queue_full.unwrap_or(c.queue_full);
+        c.card_queue = o.card_queue.unwrap_or(c.card_queue);
+        c.products = o.products.unwrap_or(c.products);
+        c.motion_gate = o.motion_gate.unwrap_or(c.motion_gate);
+        c.stats_weighting = o.stats_weighting.unwrap_or(c.stats_weighting);
+        c.urgency = o.urgency.unwrap_or(c.urgency);
+        c.transport = o.transport.unwrap_or(c.transport);
+        c.notecard_address = o.notecard_address.unwrap_or(c.notecard_address);
+        c.imu_address = o.imu_address.unwrap_or(c.imu_address);
+        c.imu_retry = o.imu_retry.unwrap_or(c.imu_retry);
+
+        c.timeouts = o.timeouts.unwrap_or(c.timeouts);
+        c.watchdog = o.watchdog.unwrap_or(c.watchdog);
+        c.request_spacing = o.request_spacing.unwrap_or(c.request_spacing);
+        c.decimation_mode = o.decimation_mode.unwrap_or(c.decimation_mode);
+        c.fir_filter = o.fir_filter.unwrap_or(c.fir_filter);
+        c.decimation = o.decimation.unwrap_or(c.decimation);
+        c.log_time = o.log_time.unwrap_or(c.log_time);
+        c.flush_samples = o.flush_samples.unwrap_or(c.flush_samples);
+        c.flush_interval = o.flush_interval.unwrap_or(c.flush_interval);
+        c.double_buffer = o.double_buffer.unwrap_or(c.double_buffer);
+        c.replay_batch = o.replay_batch.unwrap_or(c.replay_batch);
+        c.backfill_compression = o.backfill_compression.unwrap_or(c.backfill_compression);
+        c.sample_encoding = o.sample_encoding.unwrap_or(c.sample_encoding);
+        c.live_batch = o.live_batch.unwrap_or(c.live_batch);
+        c.max_note_size = o.max_note_size.unwrap_or(c.max_note_size);
+        c.dedup = o.dedup.unwrap_or(c.dedup);
+        c.day_files = o.day_files.unwrap_or(c.day_files);
+        c.note_summary = o.note_summary.unwrap_or(c.note_summary);
+
+        #[cfg(feature = "despike")]
+        {
+            c.despike_window = o.despike_window.unwrap_or(c.despike_window);
+        }
+
+        #[cfg(feature = "redundant-imu")]
+        {
+            c.imu_address_secondary = o.imu_address_secondary.unwrap_or(c.imu_address_secondary);
+        }
+
+        #[cfg(feature = "redundant-notecard")]
+        {
+            c.failover_syncs = o.failover_syncs.unwrap_or(c.failover_syncs);
+            c.failover_retry = o.failover_retry.unwrap_or(c.failover_retry);
+        }
+
+        c.validate()?;
+        *self = c;
+
+        Ok(())
+    }
+
+    /// Parse and apply a JSON override.
+    pub fn apply_json(&mut self, buf: &[u8]) -> Result<(), ConfigError> {
+        let (o, _) =
+            serde_json_core::from_slice::<ConfigOverride>(buf).map_err(|_| ConfigError::Parse)?;
+
+        self.apply(&o)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        assert_eq!(Config::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn apply_partial() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "gps_period": 120, "accel_range": "g4", "accel_lpf": "odr10" }"#)
+            .unwrap();
+
+        assert_eq!(c.gps_period, 120);
+        assert_eq!(c.accel_range, AccelRange::G4);
+        assert_eq!(c.accel_lpf, AccelLpf::Odr10);
+        assert_eq!(c.gyro_lpf, GyroLpf::Off);
+        assert_eq!(c.sync_period, Config::default().sync_period);
+    }
+
+    #[test]
+    fn layered() {
+        // SD-card, environment variable, config note.
+        let mut c = Config::default();
+        c.apply_json(br#"{ "gps_period": 120, "sync_period": 20 }"#)
+            .unwrap();
+        c.apply_json(br#"{ "location_interval": 300, "sync_period": 30 }"#)
+            .unwrap();
+        assert_eq!(
+            c.apply_json(br#"{ "location_interval": 0 }"#),
+            Err(ConfigError::LocationInterval(0))
+        );
+        c.apply_json(br#"{ "sync_period": 60 }"#).unwrap();
+
+        assert_eq!(c.gps_period, 120);
+        assert_eq!(c.location_interval, 300);
+        assert_eq!(c.sync_period, 60);
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let mut c = Config::default();
+
+        assert_eq!(
+            c.apply_json(br#"{ "gps_period": 120, "sync_period": 0 }"#),
+            Err(ConfigError::SyncPeriod(0))
+        );
+        assert_eq!(c, Config::default());
+
+        assert_eq!(c.apply_json(b"{ gps_period"), Err(ConfigError::Parse));
+        assert_eq!(c.apply_json(b""), Err(ConfigError::Parse));
+
+        assert_eq!(
+            c.apply_json(br#"{ "products": { "timeseries": false, "stats": false } }"#),
+            Err(ConfigError::NoProducts)
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "imu_address": 120 }"#),
+            Err(ConfigError::I2CAddress(120))
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "timeouts": { "location": 0 } }"#),
+            Err(ConfigError::Timeout(0))
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "flush_samples": 100, "flush_interval": 10000 }"#),
+            Err(ConfigError::FlushInterval(10000))
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "replay_batch": 0 }"#),
+            Err(ConfigError::ReplayBatch(0))
+        );
+    }
+
+    #[test]
+    fn accel_scale() {
+        let mut c = Config::default();
+        assert_eq!(c.accel_max(), ACCEL_MAX);
+
+        c.apply_json(br#"{ "accel_range": "g8", "accel_scale": "range" }"#)
+            .unwrap();
+        assert_eq!(c.accel_scale, AccelScale::Range);
+        assert_eq!(c.accel_max(), 4. * ACCEL_MAX);
+    }
+
+    #[test]
+    fn location_debounce() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "location_fixes": 3, "location_failures": 5 }"#)
+            .unwrap();
+        assert_eq!((c.location_fixes, c.location_failures), (3, 5));
+
+        assert_eq!(
+            c.apply_json(br#"{ "location_fixes": 0 }"#),
+            Err(ConfigError::LocationFixes(0))
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "location_failures": 11 }"#),
+            Err(ConfigError::LocationFailures(11))
+        );
+        assert_eq!((c.location_fixes, c.location_failures), (3, 5));
+    }
+
+    #[test]
+    fn location_backoff() {
+        let mut c = Config::default();
+        assert_eq!(c.location_backoff, 1800);
+
+        c.apply_json(br#"{ "location_backoff": 0 }"#).unwrap();
+        assert_eq!(c.location_backoff, 0);
+
+        // Not below the interval it backs off from.
+        assert_eq!(
+            c.apply_json(br#"{ "location_interval": 120, "location_backoff": 60 }"#),
+            Err(ConfigError::LocationBackoff(60))
+        );
+        assert_eq!((c.location_interval, c.location_backoff), (60, 0));
+    }
+
+    #[test]
+    fn gps_stale_age() {
+        let mut c = Config::default();
+        assert_eq!(c.gps_stale_age, crate::quality::GPS_STALE_AGE);
+
+        c.apply_json(br#"{ "gps_stale_age": 600, "gps_stale_warn": true }"#)
+            .unwrap();
+        assert_eq!((c.gps_stale_age, c.gps_stale_warn), (600, true));
+
+        assert_eq!(
+            c.apply_json(br#"{ "gps_stale_age": 10 }"#),
+            Err(ConfigError::GpsStaleAge(10))
+        );
+        assert_eq!(c.gps_stale_age, 600);
+    }
+
+    #[test]
+    fn quiet_hours() {
+        let mut c = Config::default();
+        assert!(!c.quiet_hours.is_enabled());
+
+        c.apply_json(br#"{ "quiet_hours": { "start": 1320, "end": 360, "health": true } }"#)
+            .unwrap();
+        assert_eq!(
+            c.quiet_hours,
+            QuietHours {
+                start: 1320,
+                end: 360,
+                health: true,
+                log: false
+            }
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "quiet_hours": { "start": 1440, "end": 360 } }"#),
+            Err(ConfigError::QuietHours)
+        );
+        assert_eq!(c.quiet_hours.start, 1320);
+    }
+
+    #[test]
+    fn motion_threshold() {
+        let mut c = Config::default();
+        assert_eq!(c.motion_threshold, 0);
+
+        c.apply_json(br#"{ "motion_threshold": 200 }"#).unwrap();
+        assert_eq!(c.motion_threshold, 200);
+    }
+
+    #[test]
+    fn motion_gate() {
+        let mut c = Config::default();
+        assert!(!c.motion_gate.is_enabled());
+
+        c.apply_json(br#"{ "motion_gate": { "threshold": 0.05, "heartbeat": 7200 } }"#)
+            .unwrap();
+        assert_eq!(
+            c.motion_gate,
+            MotionGate {
+                threshold: 0.05,
+                hysteresis: MotionGate::default().hysteresis,
+                heartbeat: 7200
+            }
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "motion_gate": { "threshold": 0.05, "heartbeat": 10 } }"#),
+            Err(ConfigError::MotionGate)
+        );
+        assert_eq!(c.motion_gate.heartbeat, 7200);
+    }
+
+    #[test]
+    fn stats_weighting() {
+        let mut c = Config::default();
+        assert_eq!(c.stats_weighting, Weighting::default());
+
+        c.apply_json(br#"{ "stats_weighting": { "weight": 0.0 } }"#)
+            .unwrap();
+        assert_eq!(c.stats_weighting.weight, 0.);
+        assert_eq!(c.stats_weighting.min_good, 0.);
+
+        assert_eq!(
+            c.apply_json(br#"{ "stats_weighting": { "min_good": 1.5 } }"#),
+            Err(ConfigError::StatsWeighting)
+        );
+        assert_eq!(c.stats_weighting.weight, 0.);
+    }
+
+    #[test]
+    fn imu_retry() {
+        let mut c = Config::default();
+        assert_eq!((c.imu_retry.retries, c.imu_retry.delay), (3, 500));
+
+        c.apply_json(br#"{ "imu_retry": { "retries": 5 } }"#)
+            .unwrap();
+        assert_eq!((c.imu_retry.retries, c.imu_retry.delay), (5, 500));
+
+        assert_eq!(
+            c.apply_json(br#"{ "imu_retry": { "delay": 60000 } }"#),
+            Err(ConfigError::ImuRetry)
+        );
+        assert_eq!(c.imu_retry.delay, 500);
+    }
+
+    #[test]
+    fn urgency() {
+        use crate::urgency::Urgency;
+
+        let mut c = Config::default();
+        assert_eq!(c.urgency.alarm, Urgency::Urgent);
+
+        c.apply_json(br#"{ "urgency": { "data": "low", "health": "urgent" } }"#)
+            .unwrap();
+        assert_eq!(c.urgency.data, Urgency::Low);
+        assert_eq!(c.urgency.health, Urgency::Urgent);
+        assert_eq!(c.urgency.stats, Urgency::Normal);
+
+        assert!(c
+            .apply_json(br#"{ "urgency": { "data": "later" } }"#)
+            .is_err());
+        assert_eq!(c.urgency.data, Urgency::Low);
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut c = Config::default();
+        assert!(!c.ring_buffer);
+
+        c.apply_json(br#"{ "ring_buffer": true, "min_free_space": 1048576 }"#)
+            .unwrap();
+        assert!(c.ring_buffer);
+        assert_eq!(c.min_free_space, 1024 * 1024);
+    }
+
+    #[test]
+    fn verify_writes() {
+        let mut c = Config::default();
+        assert!(!c.verify_writes);
+
+        c.apply_json(br#"{ "verify_writes": true }"#).unwrap();
+        assert!(c.verify_writes);
+    }
+
+    #[test]
+    fn queue_policy() {
+        let mut c = Config::default();
+        assert_eq!(c.queue_policy, QueuePolicy::Storage);
+
+        c.apply_json(br#"{ "queue_policy": "live" }"#).unwrap();
+        assert_eq!(c.queue_policy, QueuePolicy::Live);
+
+        assert!(c.apply_json(br#"{ "queue_policy": "both" }"#).is_err());
+        assert_eq!(c.queue_policy, QueuePolicy::Live);
+    }
+
+    #[test]
+    fn queue_full() {
+        let mut c = Config::default();
+        assert_eq!(c.queue_full, QueueFull::DropNewest);
+
+        c.apply_json(br#"{ "queue_full": "drop_oldest" }"#).unwrap();
+        assert_eq!(c.queue_full, QueueFull::DropOldest);
+
+        c.apply_json(br#"{ "queue_full": "hold" }"#).unwrap();
+        assert_eq!(c.queue_full, QueueFull::Hold);
+
+        assert!(c.apply_json(br#"{ "queue_full": "grow" }"#).is_err());
+        assert_eq!(c.queue_full, QueueFull::Hold);
+    }
+
+    #[test]
+    fn card_queue() {
+        let mut c = Config::default();
+        assert_eq!(c.card_queue, CardQueue::default());
+
+        c.apply_json(br#"{ "card_queue": { "high": 40, "slow": 20 } }"#)
+            .unwrap();
+        assert_eq!(
+            (c.card_queue.high, c.card_queue.slow, c.card_queue.spacing),
+            (40, 20, CardQueue::default().spacing)
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "card_queue": { "high": 10, "slow": 20 } }"#),
+            Err(ConfigError::CardQueue)
+        );
+        assert_eq!(c.card_queue.high, 40);
+
+        c.apply_json(br#"{ "card_queue": { "high": 0, "slow": 0 } }"#)
+            .unwrap();
+        assert!(!c.card_queue.is_enabled());
+    }
+
+    #[test]
+    fn double_buffer() {
+        let mut c = Config::default();
+        assert!(c.double_buffer);
+
+        c.apply_json(br#"{ "double_buffer": false }"#).unwrap();
+        assert!(!c.double_buffer);
+    }
+
+    #[test]
+    fn backfill_compression() {
+        let mut c = Config::default();
+        assert_eq!(c.backfill_compression, Compression::Delta);
+
+        c.apply_json(br#"{ "backfill_compression": "lz" }"#)
+            .unwrap();
+        assert_eq!(c.backfill_compression, Compression::Lz);
+
+        assert!(c
+            .apply_json(br#"{ "backfill_compression": "zstd" }"#)
+            .is_err());
+        assert_eq!(c.backfill_compression, Compression::Lz);
+    }
+
+    #[test]
+    fn sample_encoding() {
+        let mut c = Config::default();
+        assert_eq!(c.sample_encoding, SampleEncoding::Raw);
+
+        c.apply_json(br#"{ "sample_encoding": "delta" }"#).unwrap();
+        assert_eq!(c.sample_encoding, SampleEncoding::Delta);
+
+        assert!(c.apply_json(br#"{ "sample_encoding": "lz" }"#).is_err());
+        assert_eq!(c.sample_encoding, SampleEncoding::Delta);
+    }
+
+    #[test]
+    fn live_batch() {
+        let mut c = Config::default();
+        assert!(!c.live_batch.is_enabled());
+
+        c.apply_json(br#"{ "live_batch": { "packages": 8 } }"#)
+            .unwrap();
+        assert_eq!(
+            (c.live_batch.packages, c.live_batch.timeout),
+            (8, LiveBatch::default().timeout)
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "live_batch": { "packages": 0 } }"#),
+            Err(ConfigError::LiveBatch)
+        );
+        assert_eq!(
+            c.apply_json(br#"{ "live_batch": { "packages": 8, "timeout": 0 } }"#),
+            Err(ConfigError::LiveBatch)
+        );
+        assert_eq!(c.live_batch.packages, 8);
+    }
+
+    #[test]
+    fn max_note_size() {
+        let mut c = Config::default();
+        assert_eq!(c.max_note_size, MAX_NOTE_SIZE);
+
+        c.apply_json(br#"{ "max_note_size": 14000 }"#).unwrap();
+        assert_eq!(c.max_note_size, 14000);
+
+        assert_eq!(
+            c.apply_json(br#"{ "max_note_size": 4096 }"#),
+            Err(ConfigError::MaxNoteSize(4096))
+        );
+        assert_eq!(
+            c.apply_json(br#"{ "max_note_size": 65536 }"#),
+            Err(ConfigError::MaxNoteSize(65536))
+        );
+        assert_eq!(c.max_note_size, 14000);
+    }
+
+    #[test]
+    fn dedup() {
+        let mut c = Config::default();
+        assert!(c.dedup);
+
+        c.apply_json(br#"{ "dedup": false }"#).unwrap();
+        assert!(!c.dedup);
+    }
+
+    #[test]
+    fn day_files() {
+        let mut c = Config::default();
+        assert!(!c.day_files);
+
+        c.apply_json(br#"{ "day_files": true }"#).unwrap();
+        assert!(c.day_files);
+    }
+
+    #[test]
+    fn note_summary() {
+        let mut c = Config::default();
+        assert!(!c.note_summary);
+
+        c.apply_json(br#"{ "note_summary": true }"#).unwrap();
+        assert!(c.note_summary);
+    }
+
+    #[test]
+    fn max_dop() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "max_dop": 2.5 }"#).unwrap();
+        assert_eq!(c.max_dop, 2.5);
+
+        assert_eq!(
+            c.apply_json(br#"{ "max_dop": -1.0 }"#),
+            Err(ConfigError::MaxDop)
+        );
+        assert_eq!(c.max_dop, 2.5);
+    }
+
+    #[cfg(feature = "redundant-imu")]
+    #[test]
+    fn imu_address_secondary() {
+        let mut c = Config::default();
+        assert_eq!(
+            c.apply_json(br#"{ "imu_address_secondary": 106 }"#),
+            Err(ConfigError::ImuAddressSecondary(0x6a))
+        );
+
+        c.apply_json(br#"{ "imu_address": 107, "imu_address_secondary": 106 }"#)
+            .unwrap();
+        assert_eq!((c.imu_address, c.imu_address_secondary), (0x6b, 0x6a));
+    }
+
+    #[cfg(feature = "redundant-notecard")]
+    #[test]
+    fn failover() {
+        let mut c = Config::default();
+        assert_eq!((c.failover_syncs, c.failover_retry), (3, 24 * 60));
+
+        c.apply_json(br#"{ "failover_syncs": 5, "failover_retry": 0 }"#)
+            .unwrap();
+        assert_eq!((c.failover_syncs, c.failover_retry), (5, 0));
+
+        assert_eq!(
+            c.apply_json(br#"{ "failover_syncs": 0 }"#),
+            Err(ConfigError::FailoverSyncs(0))
+        );
+        assert_eq!(
+            c.apply_json(br#"{ "failover_syncs": 9 }"#),
+            Err(ConfigError::FailoverSyncs(9))
+        );
+        assert_eq!(c.failover_syncs, 5);
+    }
+
+    #[test]
+    fn rtc_temp_coeff() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "rtc_temp_coeff": -0.5 }"#).unwrap();
+        assert_eq!(c.rtc_temp_coeff, -0.5);
+
+        assert_eq!(
+            c.apply_json(br#"{ "rtc_temp_coeff": 10.0 }"#),
+            Err(ConfigError::RtcTempCoeff)
+        );
+        assert_eq!(c.rtc_temp_coeff, -0.5);
+    }
+
+    #[test]
+    fn bias_removal() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "bias_removal": "calibration", "accel_bias": [0.05, -0.1, 0.2] }"#)
+            .unwrap();
+
+        assert_eq!(c.bias_removal, BiasRemoval::Calibration);
+        assert_eq!(c.accel_bias, [0.05, -0.1, 0.2]);
+
+        assert_eq!(
+            c.apply_json(br#"{ "accel_bias": [0.0, 0.0, 9.8] }"#),
+            Err(ConfigError::AccelBias)
+        );
+    }
+
+    #[test]
+    fn bias_window() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "bias_removal": "still", "bias_window": 60 }"#)
+            .unwrap();
+
+        assert_eq!(c.bias_removal, BiasRemoval::Still);
+        assert_eq!(c.bias_window, 60);
+
+        assert_eq!(
+            c.apply_json(br#"{ "bias_window": 0 }"#),
+            Err(ConfigError::BiasWindow(0))
+        );
+        assert_eq!(c.bias_window, 60);
+    }
+
+    #[test]
+    fn accel_thermal() {
+        let mut c = Config::default();
+        c.apply_json(
+            br#"{ "accel_thermal": [
+                { "temperature": 5.0, "bias": [0.05, -0.1, 0.2], "scale": [1.0, 1.01, 0.99] },
+                { "temperature": 25.0, "bias": [0.06, -0.1, 0.25], "scale": [1.0, 1.01, 1.0] }
+            ] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(c.accel_thermal.len(), 2);
+        assert_eq!(c.accel_thermal[1].bias, [0.06, -0.1, 0.25]);
+
+        // Not sorted by temperature.
+        assert_eq!(
+            c.apply_json(
+                br#"{ "accel_thermal": [
+                { "temperature": 25.0, "bias": [0.0, 0.0, 0.0], "scale": [1.0, 1.0, 1.0] },
+                { "temperature": 5.0, "bias": [0.0, 0.0, 0.0], "scale": [1.0, 1.0, 1.0] } ] }"#
+            ),
+            Err(ConfigError::AccelThermal)
+        );
+
+        c.apply_json(br#"{ "accel_thermal": [] }"#).unwrap();
+        assert!(c.accel_thermal.is_empty());
+    }
+
+    #[test]
+    fn lever_arm() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "lever_arm": [0.0, 0.0, 0.4] }"#)
+            .unwrap();
+        assert_eq!(c.lever_arm, [0.0, 0.0, 0.4]);
+
+        assert_eq!(
+            c.apply_json(br#"{ "lever_arm": [0.0, 6.0, 0.0] }"#),
+            Err(ConfigError::LeverArm)
+        );
+        assert_eq!(c.lever_arm, [0.0, 0.0, 0.4]);
+    }
+
+    #[test]
+    fn axes() {
+        let mut c = Config::default();
+        assert_eq!(c.axes, crate::axl::AXES_ALL);
+
+        c.apply_json(br#"{ "axes": 4 }"#).unwrap();
+        assert_eq!(c.axes, 0b100);
+
+        assert_eq!(c.apply_json(br#"{ "axes": 0 }"#), Err(ConfigError::Axes(0)));
+        assert_eq!(c.apply_json(br#"{ "axes": 8 }"#), Err(ConfigError::Axes(8)));
+        assert_eq!(c.axes, 0b100);
+    }
+
+    #[test]
+    fn warmup() {
+        let mut c = Config::default();
+        assert_eq!(c.warmup, DEFAULT_WARMUP);
+
+        c.apply_json(br#"{ "warmup": 0 }"#).unwrap();
+        assert_eq!(c.warmup, 0);
+
+        assert_eq!(
+            c.apply_json(br#"{ "warmup": 601 }"#),
+            Err(ConfigError::Warmup(601))
+        );
+        assert_eq!(c.warmup, 0);
+    }
+
+    #[test]
+    fn deployment_duration() {
+        let mut c = Config::default();
+        assert_eq!(c.deployment_duration, 0);
+
+        c.apply_json(br#"{ "deployment_duration": 720 }"#).unwrap();
+        assert_eq!(c.deployment_duration, 720);
+
+        assert_eq!(
+            c.apply_json(br#"{ "deployment_duration": 100000 }"#),
+            Err(ConfigError::DeploymentDuration(100000))
+        );
+        assert_eq!(c.deployment_duration, 720);
+    }
+
+    #[test]
+    fn bist() {
+        let mut c = Config::default();
+        assert!(c.bist.enabled);
+
+        c.apply_json(br#"{ "bist": { "min_voltage": 3.6 } }"#)
+            .unwrap();
+        assert_eq!(
+            c.bist,
+            Bist {
+                enabled: true,
+                min_voltage: 3.6
+            }
+        );
+
+        c.apply_json(br#"{ "bist": { "enabled": false } }"#)
+            .unwrap();
+        assert!(!c.bist.enabled);
+
+        assert_eq!(
+            c.apply_json(br#"{ "bist": { "min_voltage": 24.0 } }"#),
+            Err(ConfigError::Bist)
+        );
+        assert!(!c.bist.enabled);
+    }
+
+    #[test]
+    fn adc() {
+        let mut c = Config::default();
+        assert!(c.adc.is_empty());
+
+        c.apply_json(br#"{ "adc": [ { "pad": 16, "scale": 11.0 }, { "pad": 29 } ] }"#)
+            .unwrap();
+        assert_eq!(c.adc.len(), 2);
+        assert_eq!((c.adc[0].pad, c.adc[0].scale), (16, 11.));
+        assert_eq!((c.adc[1].pad, c.adc[1].scale), (29, 1.));
+
+        assert_eq!(
+            c.apply_json(br#"{ "adc": [ { "pad": 6 } ] }"#),
+            Err(ConfigError::Adc)
+        );
+        assert_eq!(c.adc.len(), 2);
+
+        c.apply_json(br#"{ "adc": [] }"#).unwrap();
+        assert!(c.adc.is_empty());
+    }
+
+    #[test]
+    fn burst() {
+        let mut c = Config::default();
+        assert_eq!(c.burst.threshold, 0.);
+
+        c.apply_json(br#"{ "burst": { "threshold": 30.0, "pre": 512 } }"#)
+            .unwrap();
+        assert_eq!(
+            (c.burst.threshold, c.burst.pre, c.burst.post),
+            (30., 512, 768)
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "burst": { "pre": 1000, "post": 1000 } }"#),
+            Err(ConfigError::Burst)
+        );
+        assert_eq!(c.burst.pre, 512);
+    }
+
+    #[test]
+    fn beacon() {
+        let mut c = Config::default();
+        assert_eq!(c.beacon.jump, 0);
+
+        c.apply_json(br#"{ "beacon": { "on": 200, "jump": 1000 } }"#)
+            .unwrap();
+        assert_eq!(
+            (c.beacon.on, c.beacon.off, c.beacon.jump),
+            (200, 1500, 1000)
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "beacon": { "max_duration": 0 } }"#),
+            Err(ConfigError::Beacon)
+        );
+        assert_eq!(c.beacon.on, 200);
+    }
+
+    #[test]
+    fn safe_mode() {
+        let mut c = Config::default();
+        assert_eq!(c.safe_mode.resets, 0);
+
+        c.apply_json(br#"{ "safe_mode": { "resets": 5 } }"#)
+            .unwrap();
+        assert_eq!(
+            (
+                c.safe_mode.resets,
+                c.safe_mode.window,
+                c.safe_mode.heartbeat
+            ),
+            (5, 30, 60)
+        );
+
+        assert_eq!(
+            c.apply_json(br#"{ "safe_mode": { "heartbeat": 0 } }"#),
+            Err(ConfigError::SafeMode)
+        );
+        assert_eq!(c.safe_mode.resets, 5);
+    }
+
+    #[test]
+    fn partial_timeouts() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "timeouts": { "location": 60000 } }"#)
+            .unwrap();
+
+        assert_eq!(c.timeouts.location, 60_000);
+        assert_eq!(c.timeouts.request, Timeouts::default().request);
+    }
+
+    #[test]
+    fn request_spacing() {
+        let mut c = Config::default();
+        c.apply_json(br#"{ "request_spacing": 0 }"#).unwrap();
+        assert_eq!(c.request_spacing, 0);
+
+        assert_eq!(
+            c.apply_json(br#"{ "request_spacing": 5001 }"#),
+            Err(ConfigError::RequestSpacing(5001))
+        );
+        assert_eq!(c.request_spacing, 0);
+    }
+
+    #[test]
+    fn watchdog() {
+        let mut c = Config::default();
+        assert_eq!(c.watchdog, crate::watchdog::DEFAULT_TIMEOUT);
+
+        c.apply_json(br#"{ "watchdog": 0 }"#).unwrap();
+        assert_eq!(c.watchdog, 0);
+
+        c.apply_json(br#"{ "watchdog": 3600 }"#).unwrap();
+        assert_eq!(c.watchdog, 3600);
+
+        assert_eq!(
+            c.apply_json(br#"{ "watchdog": 60 }"#),
+            Err(ConfigError::Watchdog(60))
+        );
+        assert_eq!(
+            c.apply_json(br#"{ "watchdog": 5000 }"#),
+            Err(ConfigError::Watchdog(5000))
+        );
+
+        // Shorter than the slowest waits for the Notecard.
+        c.apply_json(
+            br#"{ "timeouts": { "location": 120000, "time": 120000, "request": 120000 } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            c.apply_json(br#"{ "watchdog": 300 }"#),
+            Err(ConfigError::Watchdog(300))
+        );
+        assert_eq!(c.watchdog, 3600);
+    }
+
+    #[test]
+    fn log_time() {
+        use crate::log::TimeFormat;
+
+        let mut c = Config::default();
+        assert_eq!(c.log_time.format, TimeFormat::Iso8601);
+        assert_eq!(c.log_time.utc_offset, 0);
+
+        c.apply_json(br#"{ "log_time": { "utc_offset": -180 } }"#)
+            .unwrap();
+        assert_eq!(c.log_time.format, TimeFormat::Iso8601);
+        assert_eq!(c.log_time.utc_offset, -180);
+
+        c.apply_json(br#"{ "log_time": { "format": "unix" } }"#)
+            .unwrap();
+        assert_eq!(c.log_time.format, TimeFormat::Unix);
+
+        assert_eq!(
+            c.apply_json(br#"{ "log_time": { "utc_offset": 900 } }"#),
+            Err(ConfigError::UtcOffset(900))
+        );
+        assert_eq!(c.log_time.format, TimeFormat::Unix);
+    }
+
+    #[test]
+    fn decimation_mode() {
+        let mut c = Config::default();
+        assert_eq!(c.decimation_mode, DecimationMode::Fir);
+
+        c.apply_json(br#"{ "decimation_mode": "average" }"#)
+            .unwrap();
+        assert_eq!(c.decimation_mode, DecimationMode::Average);
+
+        assert!(c.apply_json(br#"{ "decimation_mode": "median" }"#).is_err());
+        assert_eq!(c.decimation_mode, DecimationMode::Average);
+    }
+
+    #[test]
+    fn decimation() {
+        let mut c = Config::default();
+        assert_eq!(c.fir_filter, FirFilter::default());
+        assert_eq!(c.decimation, 0);
+        assert_eq!(c.decimation(), crate::waves::DECIMATION);
+
+        c.apply_json(br#"{ "fir_filter": "hz20", "decimation": 8 }"#)
+            .unwrap();
+        assert_eq!(c.fir_filter, FirFilter::Hz20);
+        assert_eq!(c.decimation(), 8);
+
+        // Not an integer output rate.
+        assert_eq!(
+            c.apply_json(br#"{ "decimation": 3 }"#),
+            Err(ConfigError::Decimation(3))
+        );
+        assert_eq!(
+            c.apply_json(br#"{ "decimation": 32 }"#),
+            Err(ConfigError::Decimation(32))
+        );
+
+        // Beyond the cut-off of the FIR filter.
+        #[cfg(feature = "fir")]
+        assert_eq!(
+            c.apply_json(br#"{ "fir_filter": "hz50" }"#),
+            Err(ConfigError::Decimation(8))
+        );
+        assert_eq!(c.fir_filter, FirFilter::Hz20);
+
+        // Averaging is not limited by the FIR filter.
+        c.apply_json(
+            br#"{ "decimation_mode": "average", "fir_filter": "hz50", "decimation": 16 }"#,
+        )
+        .unwrap();
+        assert_eq!(c.decimation(), 16);
+
+        assert!(c.apply_json(br#"{ "fir_filter": "hz10" }"#).is_err());
+    }
+
+    #[test]
+    fn rtc_settle() {
+        let mut c = Config::default();
+        assert_eq!(c.rtc_settle, 0);
+
+        c.apply_json(br#"{ "rtc_settle": 60 }"#).unwrap();
+        assert_eq!(c.rtc_settle, 60);
+
+        assert_eq!(
+            c.apply_json(br#"{ "rtc_settle": 3601 }"#),
+            Err(ConfigError::RtcSettle(3601))
+        );
+        assert_eq!(c.rtc_settle, 60);
+    }
+
+    #[test]
+    fn monotonic() {
+        let mut c = Config::default();
+        assert!(!c.monotonic);
+
+        c.apply_json(br#"{ "monotonic": true }"#).unwrap();
+        assert!(c.monotonic);
+    }
+
+    #[test]
+    fn transport() {
+        let mut c = Config::default();
+
+        c.apply_json(br#"{ "transport": "cell-ntn" }"#).unwrap();
+        assert_eq!(c.transport, Transport::CellNtn);
+
+        c.apply_json(br#"{ "transport": "-" }"#).unwrap();
+        assert_eq!(c.transport, Transport::Default);
+
+        assert!(c.apply_json(br#"{ "transport": "lora" }"#).is_err());
+        assert_eq!(c.transport, Transport::Default);
+    }
+
+    #[test]
+    fn postmortem() {
+        let mut c = Config::default();
+        assert_eq!(c.postmortem as usize, postmortem::CAPACITY);
+
+        c.apply_json(br#"{ "postmortem": 0 }"#).unwrap();
+        assert_eq!(c.postmortem, 0);
+
+        assert_eq!(
+            c.apply_json(br#"{ "postmortem": 1025 }"#),
+            Err(ConfigError::Postmortem(1025))
+        );
+        assert_eq!(c.postmortem, 0);
+    }
+
+    #[test]
+    fn max_gap() {
+        let mut c = Config::default();
+        assert_eq!(c.max_gap, gap::DEFAULT_MAX_GAP);
+
+        c.apply_json(br#"{ "max_gap": 0 }"#).unwrap();
+        assert_eq!(c.max_gap, 0);
+
+        assert_eq!(
+            c.apply_json(br#"{ "max_gap": 9 }"#),
+            Err(ConfigError::MaxGap(9))
+        );
+        assert_eq!(c.max_gap, 0);
+    }
+
+    #[test]
+    fn fifo_format() {
+        let mut c = Config::default();
+        assert_eq!(c.fifo_format, FifoFormat::AccelGyro);
+
+        c.apply_json(br#"{ "fifo_format": "accel_gyro_timestamp" }"#)
+            .unwrap();
+        assert_eq!(c.fifo_format, FifoFormat::AccelGyroTimestamp);
+
+        assert_eq!(
+            c.apply_json(br#"{ "fifo_format": "accel" }"#),
+            Err(ConfigError::Parse)
+        );
+        assert_eq!(c.fifo_format, FifoFormat::AccelGyroTimestamp);
+    }
+
+    #[test]
+    fn sample_counts() {
+        let mut c = Config::default();
+        assert!(!c.sample_counts);
+
+        c.apply_json(br#"{ "sample_counts": true }"#).unwrap();
+        assert!(c.sample_counts);
+    }
+}
@@ -0,0 +1,384 @@
+//! Encryption of the packages (feature `encryption`), on the SD-card and in the data notes.
+//!
+//! A package is sealed with AES-128-GCM: the tagged package (as serialized in the collections,
+//! see [`AxlPacket::decode`]) is encrypted and authenticated, and stored as:
+//!
+//! ```text
+//! SEALED (1 byte) | nonce (12 bytes) | encrypted tagged package | authentication tag (16 bytes)
+//! ```
+//!
+//! `SEALED` takes the place of the format version tag, so sealed and plain packages can be told
+//! apart in a collection, and the format version of the package is kept inside the seal. On the
+//! SD-card the sealed package is COBS framed like a plain package. In a data note the body only
+//! carries the timestamp, the storage ID and the length (`sealed` is set), and the payload is the
+//! base64 encoded sealed package.
+//!
+//! The key is built into the firmware from `BUOYKEY` (32 hex digits). `sfypack` decrypts with the
+//! key in `SFY_KEY`. The nonce is the timestamp of the package, the low bits of the reboot count
+//! (see [`set_boot`]) and a counter, so use one key per buoy: buoys sharing a key may repeat a
+//! nonce. The reboot count must be persisted before it is used, otherwise the nonces of a boot
+//! could repeat those of the boot before: packages are not sealed (and so neither stored nor
+//! sent) until it is set, which requires the `storage` feature and a working SD-card. The raw
+//! samples stored with the `raw` feature are not encrypted.
+
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce, Tag};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use heapless::Vec;
+
+use crate::axl::{
+    AxlPacket, AxlPacketMeta, DecodeError, ACCEL_POSTCARD_SZ, FORMAT_VERSION,
+    LAST_UNTAGGED_VERSION, SEALED, VERSION,
+};
+
+pub type Key = [u8; 16];
+
+pub const NONCE_SZ: usize = 12;
+pub const TAG_SZ: usize = 16;
+
+/// Size of a sealed package on top of the tagged package.
+pub const OVERHEAD: usize = 1 + NONCE_SZ + TAG_SZ;
+
+/// Maximum length of the base64 payload of a sealed package. The notes do not carry the gyroscope
+/// samples, so the payload is bounded by the slot of a package without them.
+pub const SEALED_OUTN: usize = ACCEL_POSTCARD_SZ * 4 / 3 + 4;
+
+/// Key of the buoy, from `BUOYKEY`.
+#[cfg(feature = "encryption")]
+pub const KEY: Key = match parse_key(env!(
+    "BUOYKEY",
+    "Specify encryption key (BUOYKEY, 32 hex digits)"
+)) {
+    Some(k) => k,
+    None => panic!("BUOYKEY must be 32 hex digits"),
+};
+
+#[derive(Debug, defmt::Format, PartialEq)]
+pub enum CryptError {
+    Serialize,
+    Encrypt,
+
+    /// The reboot count has not been set (see [`set_boot`]).
+    Boot,
+}
+
+const fn hex(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a key of 32 hex digits.
+pub const fn parse_key(s: &str) -> Option<Key> {
+    let b = s.as_bytes();
+    if b.len() != 32 {
+        return None;
+    }
+
+    let mut k = [0u8; 16];
+    let mut i = 0;
+    while i < 16 {
+        let (Some(h), Some(l)) = (hex(b[2 * i]), hex(b[2 * i + 1])) else {
+            return None;
+        };
+        k[i] = (h << 4) | l;
+        i += 1;
+    }
+
+    Some(k)
+}
+
+static BOOT: AtomicU32 = AtomicU32::new(0);
+static BOOT_SET: AtomicBool = AtomicBool::new(false);
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Set the reboot count (see `reboots`), part of the nonces of this boot. Only set it once the
+/// count of this boot has been written to the SD-card.
+pub fn set_boot(boot: u32) {
+    BOOT.store(boot, Ordering::Relaxed);
+    BOOT_SET.store(true, Ordering::Release);
+}
+
+/// Nonce for sealing a package with `timestamp`: the timestamp, the low 16 bits of the reboot
+/// count and the low 16 bits of a counter. Fails with [`CryptError::Boot`] before the reboot
+/// count is set.
+pub fn nonce(timestamp: i64) -> Result<[u8; NONCE_SZ], CryptError> {
+    let boot = BOOT_SET
+        .load(Ordering::Acquire)
+        .then(|| BOOT.load(Ordering::Relaxed));
+
+    nonce_of(timestamp, boot, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Nonce of package number `count` of `boot` with `timestamp`, see [`nonce`].
+fn nonce_of(timestamp: i64, boot: Option<u32>, count: u32) -> Result<[u8; NONCE_SZ], CryptError> {
+    let boot = boot.ok_or(CryptError::Boot)? as u16;
+
+    let mut n = [0u8; NONCE_SZ];
+    n[..8].copy_from_slice(&timestamp.to_le_bytes());
+    n[8..10].copy_from_slice(&boot.to_le_bytes());
+    n[10..].copy_from_slice(&(count as u16).to_le_bytes());
+    Ok(n)
+}
+
+fn cipher(key: &Key) -> Aes128Gcm {
+    Aes128Gcm::new(key.into())
+}
+
+/// Decrypt a sealed package in place, returns the tagged package.
+pub fn open<'a>(key: &Key, b: &'a mut [u8]) -> Result<&'a [u8], DecodeError> {
+    if b.len() < OVERHEAD || b[0] != SEALED {
+        return Err(DecodeError::Unseal);
+    }
+
+    let (head, rest) = b.split_at_mut(1 + NONCE_SZ);
+    let (body, tag) = rest.split_at_mut(rest.len() - TAG_SZ);
+
+    cipher(key)
+        .decrypt_in_place_detached(
+            Nonce::from_slice(&head[1..]),
+            &[],
+            body,
+            Tag::from_slice(tag),
+        )
+        .map_err(|_| DecodeError::Unseal)?;
+
+    Ok(body)
+}
+
+impl AxlPacket {
+    /// Serialize and seal the package with `key`.
+    pub fn to_sealed<const N: usize>(
+        &self,
+        key: &Key,
+        nonce: [u8; NONCE_SZ],
+    ) -> Result<Vec<u8, N>, CryptError> {
+        let mut b: Vec<u8, N> = Vec::new();
+        b.resize_default(N).map_err(|_| CryptError::Serialize)?;
+
+        b[0] = SEALED;
+        b[1..1 + NONCE_SZ].copy_from_slice(&nonce);
+
+        let end = 1
+            + NONCE_SZ
+            + postcard::to_slice(&(FORMAT_VERSION, self), &mut b[1 + NONCE_SZ..N - TAG_SZ])
+                .map_err(|_| CryptError::Serialize)?
+                .len();
+
+        let tag = cipher(key)
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), &[], &mut b[1 + NONCE_SZ..end])
+            .map_err(|_| CryptError::Encrypt)?;
+
+        b[end..end + TAG_SZ].copy_from_slice(&tag);
+        b.truncate(end + TAG_SZ);
+
+        Ok(b)
+    }
+
+    /// Serialize, seal and COBS frame the package, as stored in the collections.
+    pub fn to_sealed_cobs<const N: usize>(
+        &self,
+        key: &Key,
+        nonce: [u8; NONCE_SZ],
+    ) -> Result<Vec<u8, N>, CryptError> {
+        let sealed: Vec<u8, N> = self.to_sealed(key, nonce)?;
+
+        let mut b: Vec<u8, N> = Vec::new();
+        b.resize_default(cobs::max_encoding_length(sealed.len()) + 1)
+            .map_err(|_| CryptError::Serialize)?;
+
+        let n = cobs::encode(&sealed, &mut b);
+        b.truncate(n);
+        b.push(0).map_err(|_| CryptError::Serialize)?;
+
+        Ok(b)
+    }
+
+    /// Deserialize a COBS framed package stored with format `version` that may be sealed, see
+    /// [`AxlPacket::decode`]. Sealed packages are decrypted with `key`, without a key they fail
+    /// with `DecodeError::Sealed`.
+    pub fn decode_sealed(
+        version: u32,
+        buf: &mut [u8],
+        key: Option<&Key>,
+    ) -> Result<AxlPacket, DecodeError> {
+        if version > VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let n = cobs::decode_in_place(buf).map_err(|_| DecodeError::Cobs)?;
+        let buf = &mut buf[..n];
+
+        match (buf.first(), key) {
+            (Some(&SEALED), Some(key)) if version > LAST_UNTAGGED_VERSION => {
+                AxlPacket::decode_bytes(version, open(key, buf)?)
+            }
+            _ => AxlPacket::decode_bytes(version, buf),
+        }
+    }
+
+    /// Split the package into the body and the payload of a sealed data note.
+    pub fn split_sealed(
+        &self,
+        key: &Key,
+        nonce: [u8; NONCE_SZ],
+    ) -> Result<(AxlPacketMeta, Vec<u8, SEALED_OUTN>), CryptError> {
+        let sealed: Vec<u8, ACCEL_POSTCARD_SZ> = self.to_sealed(key, nonce)?;
+
+        let mut b64: Vec<u8, SEALED_OUTN> = Vec::new();
+        b64.resize_default(SEALED_OUTN).unwrap();
+        let written = base64::encode_config_slice(&sealed, base64::STANDARD, &mut b64);
+        b64.truncate(written);
+
+        let meta = AxlPacketMeta {
+            timestamp: self.timestamp,
+            storage_id: self.storage_id,
+            storage_version: self.storage_version,
+            length: b64.len() as u32,
+            imu_freq: crate::waves::FREQ.value(),
+            decimation: self.decimation(),
+            sealed: true,
+            scale_x: 1.,
+            scale_y: 1.,
+            scale_z: 1.,
+            ..Default::default()
+        };
+
+        Ok((meta, b64))
+    }
+
+    /// Reassemble package from a data note, see [`AxlPacket::from_note`]. Sealed notes are
+    /// decrypted with `key`, without a key they fail with `DecodeError::Sealed`.
+    pub fn from_note_sealed(
+        meta: &AxlPacketMeta,
+        payload: &[u8],
+        key: Option<&Key>,
+    ) -> Result<AxlPacket, DecodeError> {
+        let key = match (meta.sealed, key) {
+            (false, _) => return AxlPacket::from_note(meta, payload),
+            (true, None) => return Err(DecodeError::Sealed),
+            (true, Some(key)) => key,
+        };
+
+        if payload.len() != meta.length as usize || payload.len() > SEALED_OUTN {
+            return Err(DecodeError::Payload);
+        }
+
+        let mut buf = [0u8; ACCEL_POSTCARD_SZ + 3];
+        let n = base64::decode_config_slice(payload, base64::STANDARD, &mut buf)
+            .map_err(|_| DecodeError::Payload)?;
+
+        AxlPacket::decode_bytes(VERSION, open(key, &mut buf[..n])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axl::{AxlPacketBuilder, AXL_POSTCARD_SZ, AXL_SZ};
+    use crate::waves::wire::ACCEL_MAX;
+
+    const K: Key = [7u8; 16];
+
+    fn package() -> AxlPacket {
+        let data = (0..AXL_SZ).map(|v| (v * 21) as u16).collect();
+
+        AxlPacketBuilder::new(1_700_000_000_000, 52.0, ACCEL_MAX, data)
+            .offset(3)
+            .storage(Some(42), VERSION)
+            .position(1_700_000_000, 5.3, 60.4, 1.2)
+            .temperature(12.0)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn key() {
+        assert_eq!(
+            parse_key("000102030405060708090a0b0c0d0eFF"),
+            Some([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 255])
+        );
+        assert_eq!(parse_key("0001"), None);
+        assert_eq!(parse_key("000102030405060708090a0b0c0d0exx"), None);
+    }
+
+    #[test]
+    fn nonces_differ() {
+        set_boot(3);
+        assert_ne!(nonce(0).unwrap(), nonce(0).unwrap());
+        assert_ne!(nonce_of(0, Some(3), 0), nonce_of(0, Some(4), 0));
+    }
+
+    #[test]
+    fn no_boot() {
+        // Without the `storage` feature, or when the reboot count could not be read and written,
+        // the boot is not set and nothing is sealed.
+        assert_eq!(nonce_of(0, None, 0), Err(CryptError::Boot));
+        assert_eq!(
+            nonce_of(0, None, 0).and_then(|n| package().to_sealed::<AXL_POSTCARD_SZ>(&K, n)),
+            Err(CryptError::Boot)
+        );
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let p = package();
+
+        let mut b: Vec<u8, AXL_POSTCARD_SZ> = p
+            .to_sealed_cobs(&K, nonce_of(p.timestamp, Some(1), 0).unwrap())
+            .unwrap();
+        let mut c = b.clone();
+        let mut d = b.clone();
+
+        // The position is not readable without the key.
+        assert!(!b.windows(8).any(|w| w == p.lat.to_le_bytes()));
+
+        assert_eq!(
+            AxlPacket::decode_sealed(VERSION, &mut b, Some(&K)).unwrap(),
+            p
+        );
+        assert_eq!(
+            AxlPacket::decode_sealed(VERSION, &mut c, Some(&[8u8; 16])),
+            Err(DecodeError::Unseal)
+        );
+        assert_eq!(
+            AxlPacket::decode_sealed(VERSION, &mut d, None),
+            Err(DecodeError::Sealed)
+        );
+    }
+
+    #[test]
+    fn plain_package() {
+        let p = package();
+        let mut b: Vec<u8, AXL_POSTCARD_SZ> = p.to_cobs().unwrap();
+
+        assert_eq!(
+            AxlPacket::decode_sealed(VERSION, &mut b, Some(&K)).unwrap(),
+            p
+        );
+    }
+
+    #[test]
+    fn sealed_note() {
+        let p = package();
+        let (meta, b64) = p
+            .split_sealed(&K, nonce_of(p.timestamp, Some(1), 0).unwrap())
+            .unwrap();
+
+        assert!(meta.sealed);
+        assert_eq!(meta.lat, 0.0);
+
+        assert_eq!(
+            AxlPacket::from_note_sealed(&meta, &b64, Some(&K)).unwrap(),
+            p
+        );
+        assert_eq!(
+            AxlPacket::from_note_sealed(&meta, &b64, None),
+            Err(DecodeError::Sealed)
+        );
+        assert!(AxlPacket::from_note(&meta, &b64).is_err());
+    }
+}
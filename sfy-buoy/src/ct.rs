@@ -0,0 +1,235 @@
+//! Conductivity and temperature (CT) of the sea water.
+//!
+//! With the `ct` feature of the firmware a CT sensor on the bus of the Notecard is read at every
+//! health report, and the conductivity, temperature and practical salinity are attached to the
+//! report (`Health::ct`, in the note and in `HEALTH.LOG`). The sensor is the pair of Atlas
+//! Scientific EZO circuits: EZO-EC (conductivity, `EC_ADDRESS`) and EZO-RTD (temperature,
+//! `RTD_ADDRESS`), both in I2C mode. The circuits are probed at boot, and the buoy runs without
+//! the readings if they do not answer.
+//!
+//! The circuits take ASCII commands, and answer after a processing delay with a response code
+//! followed by an ASCII string terminated by a zero. The temperature compensation of the EZO-EC is
+//! set to 25 °C at boot, which leaves the conductivity uncompensated: the salinity is computed
+//! from the conductivity at the temperature of the water.
+
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Read, Write},
+};
+
+/// I2C address of the EZO-EC (default).
+pub const EC_ADDRESS: u8 = 0x64;
+
+/// I2C address of the EZO-RTD (default).
+pub const RTD_ADDRESS: u8 = 0x66;
+
+/// Processing delay of a reading [ms].
+const READ_DELAY: u16 = 600;
+
+/// Processing delay of other commands [ms].
+const COMMAND_DELAY: u16 = 300;
+
+/// Maximum length of a response.
+const RESPONSE_SZ: usize = 32;
+
+/// Conductivity of standard seawater at salinity 35, 15 °C (IPTS-68) and atmospheric pressure
+/// [mS/cm].
+pub const C_35_15_0: f64 = 42.914;
+
+/// A reading of the CT sensor.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Ct {
+    /// Conductivity [mS/cm].
+    pub conductivity: f32,
+
+    /// Temperature [°C, ITS-90].
+    pub temperature: f32,
+
+    /// Practical salinity (PSS-78), `NaN` outside of the valid range of the scale (2 to 42).
+    pub salinity: f32,
+}
+
+impl Ct {
+    pub fn new(conductivity: f32, temperature: f32) -> Ct {
+        let s = salinity(conductivity as f64, temperature as f64, 0.);
+
+        Ct {
+            conductivity,
+            temperature,
+            salinity: if (2. ..=42.).contains(&s) {
+                s as f32
+            } else {
+                f32::NAN
+            },
+        }
+    }
+}
+
+/// Practical salinity (PSS-78) of conductivity `c` [mS/cm] at temperature `t` [°C, ITS-90] and
+/// pressure `p` [dbar, relative to the atmosphere] (UNESCO technical papers in marine science 44,
+/// 1983). The scale is defined from 2 to 42.
+pub fn salinity(c: f64, t: f64, p: f64) -> f64 {
+    const A: [f64; 6] = [0.0080, -0.1692, 25.3851, 14.0941, -7.0261, 2.7081];
+    const B: [f64; 6] = [0.0005, -0.0056, -0.0066, -0.0375, 0.0636, -0.0144];
+    const K: f64 = 0.0162;
+    const C: [f64; 5] = [0.6766097, 2.00564e-2, 1.104259e-4, -6.9698e-7, 1.0031e-9];
+    const D: [f64; 4] = [3.426e-2, 4.464e-4, 4.215e-1, -3.107e-3];
+    const E: [f64; 3] = [2.070e-5, -6.370e-10, 3.989e-15];
+
+    // The scale is defined with IPTS-68.
+    let t = 1.00024 * t;
+    let r = c / C_35_15_0;
+
+    let rt = C[0] + t * (C[1] + t * (C[2] + t * (C[3] + t * C[4])));
+    let rp = 1.
+        + p * (E[0] + p * (E[1] + p * E[2])) / (1. + t * (D[0] + t * D[1]) + (D[2] + D[3] * t) * r);
+
+    let x = libm::sqrt(r / (rp * rt));
+
+    let poly = |k: &[f64; 6]| k.iter().rev().fold(0., |s, k| s * x + k);
+    let ds = (t - 15.) / (1. + K * (t - 15.)) * poly(&B);
+
+    poly(&A) + ds
+}
+
+#[derive(Debug, defmt::Format)]
+pub enum CtError<E: core::fmt::Debug> {
+    I2C(E),
+
+    /// The circuit answered with an error (`2`: syntax error, `254`: still processing, `255`: no
+    /// data).
+    Response(u8),
+
+    /// The response could not be parsed.
+    Parse,
+
+    /// The circuit at the address is not of the expected type.
+    Device,
+}
+
+impl<E: core::fmt::Debug> From<E> for CtError<E> {
+    fn from(e: E) -> CtError<E> {
+        CtError::I2C(e)
+    }
+}
+
+/// The EZO-EC and EZO-RTD circuits.
+pub struct CtSensor<I2C> {
+    i2c: I2C,
+}
+
+impl<E: core::fmt::Debug, I2C: Read<Error = E> + Write<Error = E>> CtSensor<I2C> {
+    /// Probe for the circuits, and set up the EZO-EC. Fails if either of the circuits does not
+    /// answer or is of another type.
+    pub fn probe(i2c: I2C, delay: &mut impl DelayMs<u16>) -> Result<CtSensor<I2C>, CtError<E>> {
+        let mut ct = CtSensor { i2c };
+        let mut buf = [0u8; RESPONSE_SZ];
+
+        // Device information: `?I,<type>,<firmware>`.
+        for (address, kind) in [(EC_ADDRESS, "EC"), (RTD_ADDRESS, "RTD")] {
+            let info = ct.command(address, b"i", COMMAND_DELAY, delay, &mut buf)?;
+
+            if info.split(',').nth(1) != Some(kind) {
+                return Err(CtError::Device);
+            }
+        }
+
+        ct.command(EC_ADDRESS, b"T,25", COMMAND_DELAY, delay, &mut buf)?;
+
+        Ok(ct)
+    }
+
+    /// Read the temperature and the conductivity, about 1.2 s.
+    pub fn read(&mut self, delay: &mut impl DelayMs<u16>) -> Result<Ct, CtError<E>> {
+        let mut buf = [0u8; RESPONSE_SZ];
+
+        let t = self.command(RTD_ADDRESS, b"R", READ_DELAY, delay, &mut buf)?;
+        let t = parse(t)?;
+
+        // `EC[,TDS,S,SG]` [uS/cm], depending on the enabled outputs.
+        let c = self.command(EC_ADDRESS, b"R", READ_DELAY, delay, &mut buf)?;
+        let c = parse(c.split(',').next().unwrap_or(""))?;
+
+        Ok(Ct::new(c / 1000., t))
+    }
+
+    /// Send `cmd` and read the response after `wait` [ms].
+    fn command<'a>(
+        &mut self,
+        address: u8,
+        cmd: &[u8],
+        wait: u16,
+        delay: &mut impl DelayMs<u16>,
+        buf: &'a mut [u8; RESPONSE_SZ],
+    ) -> Result<&'a str, CtError<E>> {
+        self.i2c.write(address, cmd)?;
+        delay.delay_ms(wait);
+        self.i2c.read(address, buf)?;
+
+        response(buf)
+    }
+}
+
+/// The string of a response.
+fn response<E: core::fmt::Debug>(buf: &[u8]) -> Result<&str, CtError<E>> {
+    match buf.first() {
+        Some(1) => {
+            let s = &buf[1..];
+            let n = s.iter().position(|b| *b == 0).unwrap_or(s.len());
+
+            core::str::from_utf8(&s[..n])
+                .map(str::trim)
+                .map_err(|_| CtError::Parse)
+        }
+        Some(&code) => Err(CtError::Response(code)),
+        None => Err(CtError::Parse),
+    }
+}
+
+fn parse<E: core::fmt::Debug>(s: &str) -> Result<f32, CtError<E>> {
+    s.parse::<f32>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or(CtError::Parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn salinity_check_values() {
+        // Standard seawater (IPTS-68 temperatures).
+        assert!((salinity(C_35_15_0, 15. / 1.00024, 0.) - 35.).abs() < 1e-6);
+
+        // Check value of PSS-78: R = 1.888091, 40 °C, 10 000 dbar.
+        let s = salinity(1.888091 * C_35_15_0, 40. / 1.00024, 10_000.);
+        assert!((s - 40.).abs() < 1e-4, "{s}");
+
+        // Fresher and colder water.
+        let s = salinity(0.6990725 * C_35_15_0, 5. / 1.00024, 0.);
+        assert!((s - 31.021).abs() < 1e-3, "{s}");
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(Ct::new(0.0, 10.).salinity.is_nan());
+        assert!(Ct::new(0.05, 10.).salinity.is_nan());
+        assert!((Ct::new(42.914, 15.).salinity - 35.).abs() < 0.01);
+    }
+
+    #[test]
+    fn responses() {
+        let response = response::<()>;
+
+        assert_eq!(response(b"\x0112.34\0\0\0").ok(), Some("12.34"));
+        assert_eq!(response(b"\x01?I,EC,2.10\0").ok(), Some("?I,EC,2.10"));
+        assert!(matches!(response(b"\xfe\0"), Err(CtError::Response(254))));
+        assert!(matches!(response(b"\x02"), Err(CtError::Response(2))));
+        assert!(matches!(response(b""), Err(CtError::Parse)));
+
+        assert_eq!(parse::<()>("35120").ok(), Some(35120.));
+        assert!(parse::<()>("nan").is_err());
+        assert!(parse::<()>("").is_err());
+    }
+}
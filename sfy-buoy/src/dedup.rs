@@ -0,0 +1,85 @@
+//! Suppression of duplicate packages on the way to the notecard.
+//!
+//! After a reset of the notecard, or a retry of a request that did in fact reach the notecard, the
+//! same package may be added twice, and shows up twice in the collection. The storage ID of the
+//! last package the notecard accepted is tracked in [`ACKED`], and packages with a storage ID at
+//! or below it are not added again (`dedup` in the config). Packages replayed on request from
+//! notehub are stored packages by design, and are always added. Packages that were not stored on
+//! the SD-card have no storage ID and cannot be checked.
+//!
+//! The last accepted ID is kept in the snapshot (see `storage::snapshot`), so that the check
+//! holds across reboots. The snapshot is written periodically, so a package accepted just before
+//! a reboot may still be added again.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Storage ID of the last package accepted by the notecard, shared between the notecard (which
+/// updates it) and the storage (which keeps it in the snapshot).
+pub struct Acked(AtomicU32);
+
+/// Last package accepted by the notecard since boot, or restored from the snapshot.
+pub static ACKED: Acked = Acked::new();
+
+impl Acked {
+    pub const fn new() -> Acked {
+        // The ID is stored plus one, `0` for none.
+        Acked(AtomicU32::new(0))
+    }
+
+    pub fn get(&self) -> Option<u32> {
+        self.0.load(Ordering::Relaxed).checked_sub(1)
+    }
+
+    /// Record that the package with `id` was accepted by the notecard, or restore the last ID
+    /// from before a reboot. The highest ID is kept.
+    pub fn ack(&self, id: Option<u32>) {
+        if let Some(id) = id {
+            self.0.fetch_max(id.saturating_add(1), Ordering::Relaxed);
+        }
+    }
+}
+
+/// The package with storage ID `id` has already been accepted by the notecard (the last is
+/// `acked`), and is not part of the request being replayed (`replay`, first and last ID).
+pub fn is_duplicate(id: Option<u32>, acked: Option<u32>, replay: Option<(u32, u32)>) -> bool {
+    match (id, acked) {
+        (Some(id), Some(acked)) => {
+            id <= acked && !replay.map_or(false, |(start, end)| (start..=end).contains(&id))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate() {
+        assert!(!is_duplicate(Some(10), None, None));
+        assert!(!is_duplicate(None, Some(10), None));
+        assert!(!is_duplicate(Some(11), Some(10), None));
+        assert!(is_duplicate(Some(10), Some(10), None));
+        assert!(is_duplicate(Some(3), Some(10), None));
+
+        // Replayed.
+        assert!(!is_duplicate(Some(3), Some(10), Some((2, 5))));
+        assert!(is_duplicate(Some(6), Some(10), Some((2, 5))));
+    }
+
+    #[test]
+    fn acked() {
+        let a = Acked::new();
+        assert_eq!(a.get(), None);
+
+        a.ack(None);
+        assert_eq!(a.get(), None);
+
+        a.ack(Some(0));
+        assert_eq!(a.get(), Some(0));
+
+        a.ack(Some(12));
+        a.ack(Some(5));
+        assert_eq!(a.get(), Some(12));
+    }
+}
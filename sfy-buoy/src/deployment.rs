@@ -0,0 +1,111 @@
+//! Maximum duration of a deployment.
+//!
+//! Some deployments must stop collecting after a fixed duration, rather than keep sampling and
+//! sending until the battery is flat. With `deployment_duration` in the config (hours, `0` for no
+//! limit) the deployment starts at the first time the RTC is synchronized from the Notecard, and
+//! once the duration has passed the buoy stops for good: the IMU is stopped after the partial
+//! buffer is flushed, the queues are drained to the SD-card and the Notecard, a final log note is
+//! synced, and the buoy stays in standby (IMU powered down, the Notecard in minimum mode and the
+//! MCU sleeping) until it is reset.
+//!
+//! With the `storage` feature the start of the deployment is kept in `DEPLOYMENT_FILE` on the
+//! SD-card, so that a reboot does not restart the duration: a stopped buoy that is reset stops
+//! again as soon as the time is synchronized. Without the `storage` feature the deployment starts
+//! at every boot. The `start-deployment` command (see `cmd`) starts a new deployment.
+//!
+//! The duration of an ongoing deployment can be changed from notehub by setting
+//! `deployment_duration` in the `config` note of `config.db` (see `config`): the note is read
+//! again when the duration has passed, before stopping, and a valid duration in it is applied.
+
+/// Start of the deployment on the SD-card.
+pub const DEPLOYMENT_FILE: &str = "DEPLOY.BIN";
+
+/// Max size of serialized deployment.
+pub const DEPLOYMENT_SZ: usize = 16;
+
+/// Maximum `deployment_duration` [hours], ten years.
+pub const MAX_DURATION: u32 = 10 * 365 * 24;
+
+const HOUR: i64 = 3600 * 1000;
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, PartialEq)]
+pub struct Deployment {
+    /// Start of the deployment [ms], `0` until the time has been synchronized.
+    pub start: i64,
+}
+
+impl Deployment {
+    /// Start the deployment at `now` if it has not started and the time is `synced`. Returns
+    /// `true` if it was started, and should be persisted.
+    pub fn check_start(&mut self, now: i64, synced: bool) -> bool {
+        if self.start != 0 || !synced {
+            return false;
+        }
+
+        self.start = now;
+        true
+    }
+
+    /// Start a new deployment at the next synchronized time.
+    pub fn restart(&mut self) {
+        self.start = 0;
+    }
+
+    /// Time since the start of the deployment [ms], `None` if it has not started.
+    pub fn elapsed(&self, now: i64) -> Option<i64> {
+        (self.start != 0).then(|| now - self.start)
+    }
+
+    /// The deployment has lasted `duration` hours at `now`. Never with `duration` `0`, or before
+    /// the deployment has started.
+    pub fn expired(&self, duration: u32, now: i64) -> bool {
+        duration != 0
+            && self
+                .elapsed(now)
+                .map_or(false, |e| e >= duration as i64 * HOUR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expire() {
+        let mut d = Deployment::default();
+        let t0 = 1_700_000_000_000;
+
+        // Not before the time is synchronized.
+        assert!(!d.check_start(1000, false));
+        assert!(!d.expired(1, t0 + 100 * HOUR));
+
+        assert!(d.check_start(t0, true));
+        assert!(!d.check_start(t0 + HOUR, true));
+        assert_eq!(d.start, t0);
+
+        assert_eq!(d.elapsed(t0 + HOUR), Some(HOUR));
+        assert!(!d.expired(24, t0 + 24 * HOUR - 1));
+        assert!(d.expired(24, t0 + 24 * HOUR));
+
+        // Extended.
+        assert!(!d.expired(48, t0 + 24 * HOUR));
+
+        // No limit.
+        assert!(!d.expired(0, t0 + 1000 * HOUR));
+
+        d.restart();
+        assert_eq!(d.elapsed(t0), None);
+        assert!(!d.expired(24, t0 + 24 * HOUR));
+    }
+
+    #[test]
+    fn serialize() {
+        let d = Deployment {
+            start: 1_700_000_000_000,
+        };
+
+        let mut buf = [0u8; DEPLOYMENT_SZ];
+        let b = postcard::to_slice(&d, &mut buf).unwrap();
+        assert_eq!(postcard::from_bytes::<Deployment>(b).unwrap(), d);
+    }
+}
@@ -0,0 +1,154 @@
+//! Removal of impulsive outliers (spikes) with a causal Hampel filter.
+//!
+//! Every new sample is compared to the median of the last `window` samples (including itself). If
+//! it deviates from the median by more than `THRESHOLD` times the (scaled) median absolute
+//! deviation (MAD) it is replaced by the median. Single-sample spikes are in this way removed
+//! before they are smeared out over neighbouring samples by the linear FIR filter. The filter is
+//! causal, so it neither delays the signal nor changes the number of samples.
+//!
+//! The cost is a sort of the window twice per sample and axis.
+
+use core::cmp::Ordering;
+use heapless::Deque;
+
+/// Maximum window length.
+pub const MAX_WINDOW: usize = 15;
+
+/// Default window length.
+pub const WINDOW: usize = 7;
+
+/// Number of MADs a sample may deviate from the median before it is replaced.
+pub const THRESHOLD: f32 = 3.0;
+
+/// Makes the MAD a consistent estimator of the standard deviation for normally distributed data.
+const MAD_SCALE: f32 = 1.4826;
+
+/// Lower bound on the MAD [m/s^2], so that a flat signal does not make every small deviation an
+/// outlier.
+const MIN_MAD: f32 = 0.01;
+
+/// Buffers where a larger fraction of the samples were replaced are flagged, since that may
+/// indicate a hardware fault.
+pub const MAX_CORRECTED_FRACTION: f32 = 0.01;
+
+pub struct Hampel {
+    buf: Deque<f32, MAX_WINDOW>,
+    window: usize,
+
+    /// Samples since last `take_counts`.
+    samples: usize,
+
+    /// Replaced samples since last `take_counts`.
+    corrected: usize,
+}
+
+fn median(v: &mut [f32]) -> f32 {
+    v.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+    let n = v.len();
+    if n % 2 == 1 {
+        v[n / 2]
+    } else {
+        (v[n / 2 - 1] + v[n / 2]) / 2.
+    }
+}
+
+impl Hampel {
+    /// New filter with window length `window` (clamped to `3..=MAX_WINDOW`).
+    pub fn new(window: usize) -> Hampel {
+        Hampel {
+            buf: Deque::new(),
+            window: window.clamp(3, MAX_WINDOW),
+            samples: 0,
+            corrected: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.samples = 0;
+        self.corrected = 0;
+    }
+
+    /// Returns number of (samples, corrected samples) since last call, and resets them.
+    pub fn take_counts(&mut self) -> (usize, usize) {
+        let c = (self.samples, self.corrected);
+        self.samples = 0;
+        self.corrected = 0;
+        c
+    }
+
+    pub fn filter(&mut self, x: f32) -> f32 {
+        if self.buf.len() == self.window {
+            self.buf.pop_front();
+        }
+        self.buf.push_back(x).unwrap();
+        self.samples += 1;
+
+        let n = self.buf.len();
+        if n < 3 {
+            return x;
+        }
+
+        let mut w = [0f32; MAX_WINDOW];
+        for (w, v) in w.iter_mut().zip(self.buf.iter()) {
+            *w = *v;
+        }
+
+        let w = &mut w[..n];
+        let m = median(w);
+
+        for v in w.iter_mut() {
+            *v = (*v - m).abs();
+        }
+        let mad = median(w).max(MIN_MAD);
+
+        if (x - m).abs() > THRESHOLD * MAD_SCALE * mad {
+            self.corrected += 1;
+            m
+        } else {
+            x
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swell(i: usize) -> f32 {
+        let t = i as f32 / 208.;
+        2. * (2. * core::f32::consts::PI * 0.1 * t).sin()
+            + 0.3 * (2. * core::f32::consts::PI * 0.7 * t).sin()
+    }
+
+    #[test]
+    fn clean_swell_untouched() {
+        let mut h = Hampel::new(WINDOW);
+
+        for i in 0..10_000 {
+            let x = swell(i);
+            assert_eq!(h.filter(x), x);
+        }
+
+        assert_eq!(h.take_counts(), (10_000, 0));
+    }
+
+    #[test]
+    fn remove_spikes() {
+        let mut h = Hampel::new(WINDOW);
+
+        for i in 0..10_000 {
+            let x = swell(i);
+            let spike = if i % 97 == 50 { 15. } else { 0. };
+
+            let y = h.filter(x + spike);
+            assert!((y - x).abs() < 0.1, "sample {}: {} vs {}", i, y, x);
+        }
+
+        let (n, c) = h.take_counts();
+        assert_eq!(n, 10_000);
+        assert_eq!(c, (0..10_000).filter(|i| i % 97 == 50).count());
+        assert_eq!(h.take_counts(), (0, 0));
+    }
+}
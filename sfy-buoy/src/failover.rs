@@ -0,0 +1,190 @@
+//! Two Notecards for redundancy (feature `redundant-notecard`), e.g. on different carriers.
+//!
+//! The primary Notecard is used until it cannot connect: when `failover_syncs` sync attempts in a
+//! row have failed since it became active, an alarm is logged and the buoy continues on the
+//! secondary Notecard. After `failover_retry` minutes on the secondary the primary is tried
+//! again, and the buoy fails over again if it still cannot connect. The active Notecard is
+//! reported in the health note (`notecard`, `0` for the primary).
+//!
+//! Every request goes to the active Notecard (through `Deref`), so the queues (packages, log,
+//! replay) are drained through whichever Notecard is up. Notes already added to the Notecard that
+//! failed stay in its outbound queue, and are sent if it connects again. The standby Notecard is
+//! set up at boot like the active one and keeps its hub mode, so that it is ready to take over.
+//!
+//! Both Notecards answer at the same I2C address, so each is on a bus of its own (see
+//! `bus::Either`). Both are only used from the main loop.
+
+use core::ops::{Deref, DerefMut};
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::log::{self, Category, Level};
+use crate::note::Notecarrier;
+
+/// A connection to notehub that can fail over.
+pub trait Link {
+    /// Failed sync attempts in a row (since the last sync that completed), of the attempts started
+    /// at or after `since` [ms].
+    fn failed_syncs(&self, since: i64) -> usize;
+}
+
+impl<I2C: Read + Write> Link for Notecarrier<I2C> {
+    fn failed_syncs(&self, since: i64) -> usize {
+        self.sync_history
+            .attempts()
+            .filter(|a| a.started >= since)
+            .fold(0, |n, a| if a.ok { 0 } else { n + 1 })
+    }
+}
+
+pub struct RedundantNotecarrier<L: Link> {
+    /// Primary and secondary Notecard, `None` if it could not be set up at boot.
+    links: [Option<L>; 2],
+
+    /// The Notecard the requests go to.
+    active: usize,
+
+    /// Time the active Notecard was switched to [ms], `None` until the first check.
+    since: Option<i64>,
+
+    /// Failed syncs in a row before failing over.
+    failover_syncs: u8,
+
+    /// Time on the secondary before the primary is tried again [ms], `0` to stay.
+    failover_retry: i64,
+}
+
+impl<L: Link> RedundantNotecarrier<L> {
+    /// Set up with the Notecards that came up at boot, `None` if neither did.
+    pub fn new(
+        primary: Option<L>,
+        secondary: Option<L>,
+        failover_syncs: u8,
+        failover_retry: u32,
+    ) -> Option<RedundantNotecarrier<L>> {
+        let links = [primary, secondary];
+        let active = links.iter().position(Option::is_some)?;
+
+        if links.iter().any(Option::is_none) {
+            log::log_at(
+                Category::Note,
+                Level::Error,
+                "Only one Notecard found, running without redundancy.",
+            );
+        }
+
+        Some(RedundantNotecarrier {
+            links,
+            active,
+            since: None,
+            failover_syncs,
+            failover_retry: failover_retry as i64 * 60_000,
+        })
+    }
+
+    /// The Notecard the requests go to (`0` for the primary).
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    fn switch(&mut self, now: i64, msg: &str) {
+        self.active = 1 - self.active;
+        self.since = Some(now);
+
+        defmt::error!("Switched to Notecard {}.", self.active);
+        log::log_at(Category::Note, Level::Error, msg);
+    }
+
+    /// Fail over to the other Notecard if the active one cannot connect, or go back to the
+    /// primary when it is due to be tried again. Returns whether the active Notecard changed.
+    pub fn check_failover(&mut self, now: i64) -> bool {
+        let since = *self.since.get_or_insert(now);
+
+        if self.links[1 - self.active].is_none() {
+            return false;
+        }
+
+        if self.failed_syncs(since) >= self.failover_syncs as usize {
+            let msg = match self.active {
+                0 => "Primary Notecard cannot connect, failing over to the secondary Notecard.",
+                _ => "Secondary Notecard cannot connect, failing over to the primary Notecard.",
+            };
+            self.switch(now, msg);
+
+            true
+        } else if self.active == 1 && self.failover_retry > 0 && now - since >= self.failover_retry
+        {
+            self.switch(now, "Trying the primary Notecard again.");
+
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<L: Link> Deref for RedundantNotecarrier<L> {
+    type Target = L;
+
+    fn deref(&self) -> &Self::Target {
+        self.links[self.active].as_ref().unwrap()
+    }
+}
+
+impl<L: Link> DerefMut for RedundantNotecarrier<L> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.links[self.active].as_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sync attempts as (started, ok).
+    struct Mock(Vec<(i64, bool)>);
+
+    impl Link for Mock {
+        fn failed_syncs(&self, since: i64) -> usize {
+            self.0
+                .iter()
+                .filter(|a| a.0 >= since)
+                .fold(0, |n, a| if a.1 { 0 } else { n + 1 })
+        }
+    }
+
+    #[test]
+    fn failover() {
+        let mut n =
+            RedundantNotecarrier::new(Some(Mock(vec![])), Some(Mock(vec![])), 3, 60).unwrap();
+        assert!(!n.check_failover(0));
+        assert_eq!(n.active(), 0);
+
+        n.0.extend([(10, false), (20, false), (30, true), (40, false)]);
+        assert!(!n.check_failover(50));
+
+        n.0.extend([(60, false), (70, false)]);
+        assert!(n.check_failover(80));
+        assert_eq!(n.active(), 1);
+
+        // The secondary connects, and is used until the primary is tried again.
+        n.0.push((90, true));
+        assert!(!n.check_failover(100));
+        assert!(n.check_failover(80 + 60 * 60_000));
+        assert_eq!(n.active(), 0);
+
+        // Only the failures after the switch count.
+        assert!(!n.check_failover(80 + 60 * 60_000 + 10));
+    }
+
+    #[test]
+    fn single() {
+        assert!(RedundantNotecarrier::<Mock>::new(None, None, 3, 0).is_none());
+
+        let mut n = RedundantNotecarrier::new(None, Some(Mock(vec![])), 3, 0).unwrap();
+        assert_eq!(n.active(), 1);
+
+        n.0.extend([(10, false), (20, false), (30, false)]);
+        assert!(!n.check_failover(40));
+        assert_eq!(n.active(), 1);
+    }
+}
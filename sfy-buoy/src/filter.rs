@@ -0,0 +1,645 @@
+//! Pipeline of filter stages, run on every axis of the rotated acceleration.
+//!
+//! Every stage implements [`FilterStage`]: it takes one sample at a time and returns an output
+//! sample, or none if it decimates. The stages are run in order by a [`Pipeline`], each on the
+//! output of the previous one. The default pipeline is decided at build time (see
+//! [`Pipeline::default`]):
+//!
+//! 1. Spike removal (`despike::Hampel`, with the `despike` feature).
+//! 2. Anti-aliasing FIR filter and decimation (`fir::Decimator`, with the `fir` feature).
+//!
+//! The decimation can instead be done by block averaging ([`Average`], see [`DecimationMode`]).
+//! The coefficients of the FIR filter ([`FirFilter`]) and the decimation are set in the config,
+//! see [`Pipeline::with_filter`].
+//!
+//! Other pipelines can be set at runtime with `ImuBuf::set_pipeline`, e.g. with the high-pass
+//! [`DcBlock`] in front of the FIR filter.
+//!
+//! Every stage reports its group delay, in samples at its input rate, and the decimation of its
+//! output. The pipeline adds them up to the total delay of the filtering (see
+//! [`Pipeline::delay`]), the time by which the output lags the samples of the IMU. The packages
+//! are stamped with the time of the first sample read from the IMU (see `Waves::take_buf`), the
+//! delay is not subtracted. The start-up transients of the stages (see [`FilterStage::is_warm`])
+//! are discarded and accounted for in the timestamp.
+
+use heapless::Vec;
+
+#[cfg(feature = "despike")]
+use crate::despike;
+#[cfg(feature = "fir")]
+use crate::fir;
+
+/// Maximum number of stages in a pipeline.
+pub const MAX_STAGES: usize = 4;
+
+/// A stage of the filter pipeline.
+pub trait FilterStage {
+    /// Filter a sample, returns the output sample if the stage outputs one for this input.
+    fn process(&mut self, x: f32) -> Option<f32>;
+
+    /// Group delay [samples at the input rate of the stage].
+    fn delay(&self) -> f32;
+
+    /// Number of input samples per output sample.
+    fn decimation(&self) -> u8 {
+        1
+    }
+
+    /// The output is no longer affected by the start-up transient of the stage.
+    fn is_warm(&self) -> bool {
+        true
+    }
+
+    fn reset(&mut self);
+}
+
+#[cfg(feature = "despike")]
+impl FilterStage for despike::Hampel {
+    fn process(&mut self, x: f32) -> Option<f32> {
+        Some(self.filter(x))
+    }
+
+    /// The filter is causal and replaces a spike with the median of the window, it does not delay
+    /// the signal.
+    fn delay(&self) -> f32 {
+        0.
+    }
+
+    fn reset(&mut self) {
+        despike::Hampel::reset(self)
+    }
+}
+
+#[cfg(feature = "fir")]
+impl FilterStage for fir::Decimator {
+    fn process(&mut self, x: f32) -> Option<f32> {
+        self.decimate(x)
+    }
+
+    /// Half the length of the (linear phase) filter.
+    fn delay(&self) -> f32 {
+        (fir::NTAP / 2) as f32
+    }
+
+    fn decimation(&self) -> u8 {
+        self.factor()
+    }
+
+    fn is_warm(&self) -> bool {
+        fir::Decimator::is_warm(self)
+    }
+
+    fn reset(&mut self) {
+        fir::Decimator::reset(self)
+    }
+}
+
+/// How the acceleration is decimated from the IMU rate to the output rate (`decimation_mode` in
+/// the config).
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DecimationMode {
+    /// Anti-aliasing FIR filter, keeping every `decimation`'th sample (`fir::Decimator`).
+    #[default]
+    Fir,
+
+    /// Oversample and average: the mean of every block of `decimation` samples ([`Average`]).
+    Average,
+}
+
+/// Coefficients of the anti-aliasing FIR filter (`fir_filter` in the config), named after the
+/// modules in `fir`. The decimation (`decimation` in the config) must keep the output Nyquist
+/// frequency above the cut-off of the filter (see [`FirFilter::max_decimation`]).
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(not(feature = "20Hz"), derive(Default))]
+pub enum FirFilter {
+    /// Cut-off at 26 Hz, for output at 52 Hz (`fir::hz50`). The default.
+    #[cfg_attr(not(feature = "20Hz"), default)]
+    Hz50,
+
+    /// Cut-off at 13 Hz, for output at 26 Hz (`fir::hz20`). The default with the `20Hz` feature.
+    Hz20,
+}
+
+#[cfg(feature = "20Hz")]
+impl Default for FirFilter {
+    fn default() -> FirFilter {
+        FirFilter::Hz20
+    }
+}
+
+impl FirFilter {
+    /// Cut-off frequency of the filter [Hz].
+    pub fn cutoff(&self) -> f32 {
+        match self {
+            FirFilter::Hz50 => 26.,
+            FirFilter::Hz20 => 13.,
+        }
+    }
+
+    /// Maximum decimation of the IMU rate (`waves::FREQ`) within the cut-off of the filter.
+    pub fn max_decimation(&self) -> u8 {
+        (crate::waves::FREQ.value() / self.cutoff() / 2.) as u8
+    }
+
+    #[cfg(feature = "fir")]
+    fn fir(&self) -> fir::FIR {
+        match self {
+            FirFilter::Hz50 => fir::FIR::with_coeffs(&fir::hz50::COEFFS, fir::hz50::CUTOFF),
+            FirFilter::Hz20 => fir::FIR::with_coeffs(&fir::hz20::COEFFS, fir::hz20::CUTOFF),
+        }
+    }
+}
+
+/// Oversampling and averaging: outputs the mean of every block of `n` samples.
+///
+/// Averaging `n` samples reduces uncorrelated (white) noise by `√n`: the noise floor drops by
+/// `10 log10(n)` dB, 6 dB at `n = 4` (52 Hz from 208 Hz). An anti-aliasing filter with the
+/// cut-off at the output Nyquist frequency removes as much white noise, the average trades the
+/// sharp cut-off of the FIR filter for a short delay (`(n - 1) / 2` samples), no start-up
+/// transient and fewer operations per sample. Its response is a sinc with the first zero at the
+/// output rate, so it attenuates less above the output Nyquist frequency and relies more on the
+/// low-pass filter of the accelerometer (`accel_lpf`) against aliasing.
+pub struct Average {
+    n: u8,
+    m: u8,
+    sum: f32,
+}
+
+impl Average {
+    pub fn new(n: u8) -> Average {
+        Average {
+            n: n.max(1),
+            m: 0,
+            sum: 0.,
+        }
+    }
+}
+
+impl FilterStage for Average {
+    fn process(&mut self, x: f32) -> Option<f32> {
+        self.sum += x;
+        self.m += 1;
+
+        if self.m == self.n {
+            let y = self.sum / self.n as f32;
+            self.sum = 0.;
+            self.m = 0;
+
+            Some(y)
+        } else {
+            None
+        }
+    }
+
+    /// The middle of the block.
+    fn delay(&self) -> f32 {
+        (self.n - 1) as f32 / 2.
+    }
+
+    fn decimation(&self) -> u8 {
+        self.n
+    }
+
+    fn reset(&mut self) {
+        self.m = 0;
+        self.sum = 0.;
+    }
+}
+
+/// First order high-pass filter removing the constant offset (DC blocker):
+/// `y[n] = x[n] - x[n-1] + pole * y[n-1]`. The cut-off is at about `(1 - pole) * fs / 2π`.
+pub struct DcBlock {
+    pole: f32,
+    x: f32,
+    y: f32,
+
+    /// Samples since reset.
+    n: u32,
+}
+
+impl DcBlock {
+    /// DC blocker with a cut-off at about `cutoff` [Hz] for the sample rate `freq` [Hz].
+    pub fn new(cutoff: f32, freq: f32) -> DcBlock {
+        DcBlock {
+            pole: 1. - 2. * core::f32::consts::PI * cutoff / freq,
+            x: 0.,
+            y: 0.,
+            n: 0,
+        }
+    }
+
+    /// Time constant of the filter [samples].
+    fn time_constant(&self) -> f32 {
+        1. / (1. - self.pole)
+    }
+}
+
+impl FilterStage for DcBlock {
+    fn process(&mut self, x: f32) -> Option<f32> {
+        // Start from the first sample, so that a large offset does not ring.
+        if self.n == 0 {
+            self.x = x;
+        }
+
+        self.y = x - self.x + self.pole * self.y;
+        self.x = x;
+        self.n = self.n.saturating_add(1);
+
+        Some(self.y)
+    }
+
+    /// The delay of the filter is negligible well above the cut-off.
+    fn delay(&self) -> f32 {
+        0.
+    }
+
+    /// Warm after five time constants.
+    fn is_warm(&self) -> bool {
+        self.n as f32 >= 5. * self.time_constant()
+    }
+
+    fn reset(&mut self) {
+        self.x = 0.;
+        self.y = 0.;
+        self.n = 0;
+    }
+}
+
+/// A stage of a [`Pipeline`]. The stages are stored in place (there is no heap), the pipeline
+/// takes the size of its largest stage for every stage.
+#[allow(clippy::large_enum_variant)]
+pub enum Stage {
+    #[cfg(feature = "despike")]
+    Despike(despike::Hampel),
+
+    #[cfg(feature = "fir")]
+    Fir(fir::Decimator),
+
+    Average(Average),
+
+    DcBlock(DcBlock),
+}
+
+impl Stage {
+    fn get(&self) -> &dyn FilterStage {
+        match self {
+            #[cfg(feature = "despike")]
+            Stage::Despike(s) => s,
+            #[cfg(feature = "fir")]
+            Stage::Fir(s) => s,
+            Stage::Average(s) => s,
+            Stage::DcBlock(s) => s,
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut dyn FilterStage {
+        match self {
+            #[cfg(feature = "despike")]
+            Stage::Despike(s) => s,
+            #[cfg(feature = "fir")]
+            Stage::Fir(s) => s,
+            Stage::Average(s) => s,
+            Stage::DcBlock(s) => s,
+        }
+    }
+}
+
+/// Ordered stages of filtering of one axis.
+pub struct Pipeline {
+    stages: Vec<Stage, MAX_STAGES>,
+}
+
+impl Default for Pipeline {
+    /// Spike removal (with the `despike` feature) and decimation to `waves::DECIMATION` (with the
+    /// `fir` feature).
+    fn default() -> Pipeline {
+        Pipeline::with_decimation(DecimationMode::Fir)
+    }
+}
+
+impl Pipeline {
+    /// Spike removal (with the `despike` feature) and decimation to `waves::DECIMATION` by `mode`.
+    /// Without the `fir` feature there is no decimation, and the mode is not used.
+    pub fn with_decimation(mode: DecimationMode) -> Pipeline {
+        Pipeline::with_filter(mode, FirFilter::default(), crate::waves::DECIMATION)
+    }
+
+    /// Spike removal (with the `despike` feature) and decimation by `decimation` by `mode`, with
+    /// the coefficients `filter` for the FIR filter. Without the `fir` feature the FIR filter
+    /// does not decimate. Panics if `decimation` is more than `filter` allows (see
+    /// [`FirFilter::max_decimation`]).
+    pub fn with_filter(mode: DecimationMode, filter: FirFilter, decimation: u8) -> Pipeline {
+        let mut p = Pipeline::new();
+
+        #[cfg(feature = "despike")]
+        p.push(Stage::Despike(despike::Hampel::new(despike::WINDOW)))
+            .ok();
+
+        #[cfg(not(feature = "fir"))]
+        let _ = filter;
+
+        if decimation > 1 {
+            match mode {
+                #[cfg(feature = "fir")]
+                DecimationMode::Fir => p.push(Stage::Fir(filter.fir().into_decimator(decimation))),
+                #[cfg(not(feature = "fir"))]
+                DecimationMode::Fir => Ok(()),
+                DecimationMode::Average => p.push(Stage::Average(Average::new(decimation))),
+            }
+            .ok();
+        }
+
+        p
+    }
+
+    /// Empty pipeline, passes the samples through.
+    pub fn new() -> Pipeline {
+        Pipeline { stages: Vec::new() }
+    }
+
+    /// Add stage to the end of the pipeline, fails if the pipeline is full.
+    pub fn push(&mut self, stage: Stage) -> Result<(), Stage> {
+        self.stages.push(stage)
+    }
+
+    pub fn stages(&self) -> impl Iterator<Item = &Stage> {
+        self.stages.iter()
+    }
+
+    pub fn stages_mut(&mut self) -> impl Iterator<Item = &mut Stage> {
+        self.stages.iter_mut()
+    }
+
+    /// Run a sample through the stages, returns the output of the last stage if any.
+    pub fn process(&mut self, x: f32) -> Option<f32> {
+        self.stages
+            .iter_mut()
+            .try_fold(x, |x, s| s.get_mut().process(x))
+    }
+
+    /// Total decimation of the pipeline.
+    pub fn decimation(&self) -> u32 {
+        self.stages
+            .iter()
+            .map(|s| s.get().decimation() as u32)
+            .product()
+    }
+
+    /// Total group delay [s] at the input sample rate `freq` [Hz].
+    pub fn delay(&self, freq: f32) -> f32 {
+        self.stages
+            .iter()
+            .fold((0., freq), |(d, freq), s| {
+                let s = s.get();
+                (d + s.delay() / freq, freq / s.decimation() as f32)
+            })
+            .0
+    }
+
+    /// None of the stages are affected by their start-up transient.
+    pub fn is_warm(&self) -> bool {
+        self.stages.iter().all(|s| s.get().is_warm())
+    }
+
+    pub fn reset(&mut self) {
+        for s in &mut self.stages {
+            s.get_mut().reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let mut p = Pipeline::new();
+        assert_eq!(p.process(1.5), Some(1.5));
+        assert_eq!(p.decimation(), 1);
+        assert_eq!(p.delay(208.), 0.);
+        assert!(p.is_warm());
+    }
+
+    #[test]
+    fn dc_block() {
+        let freq = 52.;
+        let mut s = DcBlock::new(0.01, freq);
+
+        // A constant offset with a wave of 10 s period.
+        let wave = |i: usize| (2. * core::f32::consts::PI * 0.1 * i as f32 / freq).sin();
+
+        let mut out = std::vec::Vec::new();
+        for i in 0..20_000 {
+            let y = s.process(9.81 + wave(i)).unwrap();
+            if s.is_warm() {
+                out.push((wave(i), y));
+            }
+        }
+
+        assert!(!out.is_empty());
+        let mean = out.iter().map(|(_, y)| y).sum::<f32>() / out.len() as f32;
+        assert!(mean.abs() < 0.05, "{mean}");
+
+        // The wave passes (nearly) unchanged.
+        let max = out.iter().map(|(_, y)| y.abs()).fold(0., f32::max);
+        assert!((max - 1.).abs() < 0.05, "{max}");
+
+        s.reset();
+        assert!(!s.is_warm());
+        assert_eq!(s.process(5.), Some(0.));
+    }
+
+    #[cfg(feature = "fir")]
+    #[test]
+    fn fir_stage() {
+        let mut s = fir::FIR::new().into_decimator(crate::waves::DECIMATION);
+        let mut d = fir::FIR::new().into_decimator(crate::waves::DECIMATION);
+
+        assert_eq!(s.delay() / fir::FREQ, fir::DELAY);
+        assert_eq!(FilterStage::decimation(&s), crate::waves::DECIMATION);
+
+        for i in 0..1000 {
+            let x = (i as f32 * 0.1).sin();
+            assert_eq!(s.process(x), d.decimate(x));
+        }
+        assert!(FilterStage::is_warm(&s));
+    }
+
+    #[cfg(feature = "despike")]
+    #[test]
+    fn despike_stage() {
+        let mut s = despike::Hampel::new(despike::WINDOW);
+
+        for i in 0..100 {
+            let x = if i == 50 { 15. } else { 0. };
+            assert_eq!(s.process(x), Some(0.));
+        }
+        assert_eq!(s.delay(), 0.);
+    }
+
+    #[cfg(feature = "fir")]
+    #[test]
+    fn composed() {
+        let mut p = Pipeline::new();
+        p.push(Stage::DcBlock(DcBlock::new(0.01, fir::FREQ)))
+            .ok()
+            .unwrap();
+        p.push(Stage::Fir(
+            fir::FIR::new().into_decimator(crate::waves::DECIMATION),
+        ))
+        .ok()
+        .unwrap();
+
+        let mut dc = DcBlock::new(0.01, fir::FREQ);
+        let mut d = fir::FIR::new().into_decimator(crate::waves::DECIMATION);
+
+        let mut n = 0;
+        for i in 0..4096 {
+            let x = 1. + (i as f32 * 0.05).sin();
+            let y = p.process(x);
+            assert_eq!(y, dc.process(x).and_then(|x| d.decimate(x)));
+            n += y.is_some() as usize;
+        }
+
+        assert_eq!(n, 4096 / crate::waves::DECIMATION as usize);
+        assert_eq!(p.decimation(), crate::waves::DECIMATION as u32);
+        assert_eq!(p.delay(fir::FREQ), fir::DELAY);
+
+        p.reset();
+        assert!(!p.is_warm());
+    }
+
+    #[test]
+    fn default() {
+        let p = Pipeline::default();
+
+        assert_eq!(
+            p.stages().count(),
+            cfg!(feature = "despike") as usize + cfg!(feature = "fir") as usize
+        );
+        assert_eq!(p.decimation(), crate::waves::DECIMATION as u32);
+
+        #[cfg(feature = "fir")]
+        assert_eq!(p.delay(fir::FREQ), fir::DELAY);
+    }
+
+    /// Pseudo-random white noise, uniform in `[-1, 1)`.
+    fn noise(n: usize) -> std::vec::Vec<f32> {
+        let mut x = 0x2545f491u32;
+        (0..n)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                (x as f32 / u32::MAX as f32) * 2. - 1.
+            })
+            .collect()
+    }
+
+    fn std_dev(v: &[f32]) -> f32 {
+        let m = v.iter().sum::<f32>() / v.len() as f32;
+        (v.iter().map(|v| (v - m).powi(2)).sum::<f32>() / v.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn average() {
+        let mut s = Average::new(4);
+        assert_eq!(s.delay(), 1.5);
+        assert_eq!(FilterStage::decimation(&s), 4);
+
+        let y: std::vec::Vec<_> = [1., 2., 3., 4., 5., 6., 7., 8., 9.]
+            .into_iter()
+            .filter_map(|x| s.process(x))
+            .collect();
+        assert_eq!(y, [2.5, 6.5]);
+
+        s.reset();
+        assert_eq!(s.process(1.), None);
+    }
+
+    /// Averaging 4 samples of a noisy constant signal halves the noise (√4).
+    #[test]
+    fn average_reduces_noise() {
+        let n = 4;
+        let x: std::vec::Vec<f32> = noise(4 * 16384).iter().map(|e| 0.5 + e).collect();
+
+        let mut s = Average::new(n);
+        let y: std::vec::Vec<f32> = x.iter().filter_map(|x| s.process(*x)).collect();
+        assert_eq!(y.len(), x.len() / n as usize);
+
+        let ratio = std_dev(&x) / std_dev(&y);
+        assert!((ratio - (n as f32).sqrt()).abs() < 0.1, "{ratio}");
+
+        // The signal is kept.
+        let mean = y.iter().sum::<f32>() / y.len() as f32;
+        assert!((mean - 0.5).abs() < 0.01, "{mean}");
+    }
+
+    #[test]
+    fn average_pipeline() {
+        let p = Pipeline::with_decimation(DecimationMode::Average);
+        assert_eq!(p.decimation(), crate::waves::DECIMATION as u32);
+        assert!(p.is_warm());
+
+        #[cfg(feature = "fir")]
+        {
+            let freq = crate::waves::FREQ.value();
+            let delay = (crate::waves::DECIMATION - 1) as f32 / 2. / freq;
+            assert_eq!(p.delay(freq), delay);
+            assert!(p.delay(freq) < Pipeline::default().delay(freq));
+        }
+    }
+
+    #[test]
+    fn fir_filters() {
+        assert_eq!(FirFilter::Hz50.max_decimation(), 4);
+        assert_eq!(FirFilter::Hz20.max_decimation(), 8);
+
+        #[cfg(feature = "fir")]
+        assert_eq!(
+            FirFilter::default().max_decimation(),
+            crate::waves::DECIMATION
+        );
+
+        let p = Pipeline::with_filter(DecimationMode::Average, FirFilter::Hz50, 8);
+        assert_eq!(p.decimation(), 8);
+    }
+
+    #[cfg(feature = "fir")]
+    #[test]
+    fn with_filter() {
+        let mut p = Pipeline::with_filter(DecimationMode::Fir, FirFilter::Hz20, 8);
+        assert_eq!(p.decimation(), 8);
+        assert_eq!(p.delay(fir::FREQ), fir::DELAY);
+
+        let n = (0..4096)
+            .filter_map(|i| p.process((i as f32 * 0.05).sin()))
+            .count();
+        assert_eq!(n, 4096 / 8);
+
+        // The same decimation with the other coefficients.
+        let p = Pipeline::with_filter(DecimationMode::Fir, FirFilter::Hz50, 4);
+        assert_eq!(p.decimation(), 4);
+    }
+
+    #[cfg(feature = "fir")]
+    #[test]
+    #[should_panic]
+    fn with_filter_beyond_cutoff() {
+        Pipeline::with_filter(DecimationMode::Fir, FirFilter::Hz50, 8);
+    }
+
+    #[test]
+    fn full() {
+        let mut p = Pipeline::new();
+        for _ in 0..MAX_STAGES {
+            assert!(p.push(Stage::DcBlock(DcBlock::new(0.01, 52.))).is_ok());
+        }
+        assert!(p.push(Stage::DcBlock(DcBlock::new(0.01, 52.))).is_err());
+    }
+}
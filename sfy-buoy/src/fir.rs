@@ -1,5 +1,18 @@
+//! Anti-aliasing FIR filter and decimator.
+//!
+//! The filter is implemented in pure Rust using `core::simd`, and does not depend on CMSIS-DSP. It
+//! therefore runs the same on the host (tests) as on the Artemis. CMSIS-DSP is only linked in
+//! `sfy-artemis` to provide math functions (e.g. `sinf`). There is no FFT on the buoy: spectra
+//! are computed on the data server.
+//!
+//! The coefficients are chosen when the filter is made ([`FIR::with_coeffs`]): `hz50` for output
+//! at up to 52 Hz, and `hz20` for output at up to 26 Hz (see `filter::FirFilter`). The default of
+//! the build (`COEFFS`) is `hz20` with the `20Hz` feature. Every set has the same length (`NTAP`),
+//! so that the filters take the same room whatever coefficients they run.
+
 use core::simd::{f32x4, SimdFloat};
 use heapless::Deque;
+use static_assertions as sa;
 
 /// Sample rate.
 pub const FREQ: f32 = 208.0;
@@ -34,33 +47,57 @@ pub mod hz20 {
     // pub const TRUE_CUTOFF: f32 = 8.0;
 }
 
+sa::const_assert_eq!(hz50::NTAP, hz20::NTAP);
+
 #[cfg(feature = "20Hz")]
 pub use hz20::*;
 
 #[cfg(not(feature = "20Hz"))]
 pub use hz50::*;
 
-/// Maximum decimation given `CUTOFF` and sample rate (`FREQ`).
+/// Maximum decimation given `CUTOFF` and sample rate (`FREQ`). The default decimation is
+/// `waves::DECIMATION`, which must match.
 pub const DECIMATE: u8 = (FREQ / CUTOFF / 2.) as u8;
 
-/// Output frequency after decimation.
-pub const OUT_FREQ: f32 = FREQ / DECIMATE as f32;
-
 /// The delay (in seconds) introduced by the filter: half the length of the filter.
 pub const DELAY: f32 = (NTAP / 2) as f32 / FREQ;
 
+/// Number of decimated output samples affected by the start-up transient of the filter. The
+/// filter is initialized with zeros, so outputs are not valid until the full length of the filter
+/// has been filled with samples. These are discarded after a reset of the filter.
+pub const WARMUP: usize = (NTAP - 1 + DECIMATE as usize - 1) / DECIMATE as usize;
+
 /// A running FIR filter with pre-computed coefficients.
 pub struct FIR {
     samples: Deque<f32, NTAP>,
+    coeffs: &'static [f32; NTAP],
+
+    /// Cut-off of the coefficients [Hz].
+    cutoff: f32,
 }
 
 impl FIR {
+    /// Filter with the coefficients of the build (`COEFFS`).
     pub fn new() -> FIR {
+        FIR::with_coeffs(&COEFFS, CUTOFF)
+    }
+
+    /// Filter with `coeffs`, designed for a cut-off at `cutoff` [Hz].
+    pub fn with_coeffs(coeffs: &'static [f32; NTAP], cutoff: f32) -> FIR {
         let mut samples = Deque::new();
 
         while samples.push_back(0.0).is_ok() {}
 
-        FIR { samples }
+        FIR {
+            samples,
+            coeffs,
+            cutoff,
+        }
+    }
+
+    /// Maximum decimation within the cut-off of the coefficients.
+    pub fn max_decimation(&self) -> u8 {
+        (FREQ / self.cutoff / 2.) as u8
     }
 
     /// Update filter with new sample value, apply filter and output current filtered value.
@@ -84,10 +121,10 @@ impl FIR {
 
         // debug_assert_eq!(self.samples.len() % 4, 0);
         // debug_assert_eq!(COEFFS.len() % 4, 0);
-        debug_assert_eq!(COEFFS.len(), self.samples.len());
+        debug_assert_eq!(self.coeffs.len(), self.samples.len());
 
         let (f, b) = self.samples.as_slices();
-        let (cf, cb) = COEFFS.split_at(f.len());
+        let (cf, cb) = self.coeffs.split_at(f.len());
 
         debug_assert_eq!(f.len(), cf.len());
         debug_assert_eq!(b.len(), cb.len());
@@ -139,8 +176,20 @@ impl FIR {
         while self.samples.push_back(0.0).is_ok() {}
     }
 
-    pub fn into_decimator(self) -> Decimator {
-        Decimator { fir: self, m: 0 }
+    /// Decimator outputting every `decimate`'th sample. Panics if `decimate` is more than the
+    /// cut-off of the filter allows (see [`FIR::max_decimation`]).
+    pub fn into_decimator(self, decimate: u8) -> Decimator {
+        assert!(
+            (1..=self.max_decimation()).contains(&decimate),
+            "decimation beyond the cut-off of the filter"
+        );
+
+        Decimator {
+            fir: self,
+            decimate,
+            m: 0,
+            n: 0,
+        }
     }
 }
 
@@ -148,16 +197,21 @@ impl FIR {
 /// every M'th sample.
 pub struct Decimator {
     fir: FIR,
+    decimate: u8,
     m: u8,
+
+    /// Number of samples since reset (saturates at `NTAP`).
+    n: usize,
 }
 
 impl Decimator {
     /// Update filter with new sample. A filtered output value is calculated and returned
-    /// _if_ `DECIMATE` samples has passed. Otherwise `None` is returned.
+    /// _if_ `decimate` samples has passed. Otherwise `None` is returned.
     pub fn decimate(&mut self, v: f32) -> Option<f32> {
         self.fir.put(v);
+        self.n = (self.n + 1).min(NTAP);
 
-        if self.m % DECIMATE == 0 {
+        if self.m % self.decimate == 0 {
             self.m = 1;
 
             Some(self.fir.value())
@@ -167,8 +221,20 @@ impl Decimator {
         }
     }
 
+    /// Decimation factor.
+    pub fn factor(&self) -> u8 {
+        self.decimate
+    }
+
+    /// The filter has been filled with samples since the last reset, and the output is no longer
+    /// affected by the start-up transient.
+    pub fn is_warm(&self) -> bool {
+        self.n >= NTAP
+    }
+
     pub fn reset(&mut self) {
         self.m = 0;
+        self.n = 0;
         self.fir.reset();
     }
 }
@@ -274,7 +340,7 @@ mod tests {
     #[test]
     fn decimate() {
         let mut f = FIR::new();
-        let mut d = FIR::new().into_decimator();
+        let mut d = FIR::new().into_decimator(DECIMATE);
 
         let fs = FREQ;
         let dt = 1. / fs;
@@ -286,7 +352,7 @@ mod tests {
             .collect::<Vec<_>>();
 
         println!("decimate: {}", DECIMATE);
-        println!("out_freq: {}", OUT_FREQ);
+        println!("out_freq: {}", FREQ / DECIMATE as f32);
 
         let sf = s
             .iter()
@@ -298,9 +364,91 @@ mod tests {
         assert_eq!(df.len(), 4096 / DECIMATE as usize);
     }
 
+    #[test]
+    fn decimate_factor() {
+        let mut d = FIR::new().into_decimator(2);
+        assert_eq!(d.factor(), 2);
+
+        let n = (0..4096).filter_map(|i| d.decimate(i as f32)).count();
+        assert_eq!(n, 4096 / 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn decimate_beyond_cutoff() {
+        FIR::new().into_decimator(DECIMATE + 1);
+    }
+
+    #[test]
+    fn coefficient_sets() {
+        let f = FIR::with_coeffs(&hz50::COEFFS, hz50::CUTOFF);
+        assert_eq!(f.max_decimation(), 4);
+
+        let f = FIR::with_coeffs(&hz20::COEFFS, hz20::CUTOFF);
+        assert_eq!(f.max_decimation(), 8);
+
+        let mut f = FIR::with_coeffs(&hz20::COEFFS, hz20::CUTOFF);
+        let mut d = FIR::with_coeffs(&hz20::COEFFS, hz20::CUTOFF).into_decimator(8);
+
+        let dt = 1. / FREQ;
+        let s = (0..4096)
+            .map(|i| 2. * (2. * i as f32 * dt * 2. * std::f32::consts::PI).sin())
+            .collect::<Vec<_>>();
+
+        let sf = s.iter().map(|s| f.filter(*s)).collect::<Vec<_>>();
+        for (s, sf) in s.iter().zip(sf.iter().skip(128 / 2)).skip(128) {
+            assert!((s - sf).abs() < 0.02);
+        }
+
+        let df = s.iter().filter_map(|s| d.decimate(*s)).collect::<Vec<_>>();
+        assert_eq!(sf.into_iter().step_by(8).collect::<Vec<_>>(), df);
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let mut f = FIR::new();
+
+        for i in 0..1024 {
+            let v = (i as f32 * 0.1).sin() + 0.3 * (i as f32 * 1.3).cos();
+            let o = f.filter(v);
+
+            let scalar = f
+                .samples
+                .iter()
+                .zip(&COEFFS)
+                .fold(0.0, |a, (s, c)| a + (s * c));
+
+            assert!(
+                (o - scalar).abs() < 1.0e-5,
+                "simd: {}, scalar: {}",
+                o,
+                scalar
+            );
+        }
+    }
+
+    #[test]
+    fn warmup_step() {
+        let mut d = FIR::new().into_decimator(DECIMATE);
+
+        let first = d.decimate(1.0).unwrap();
+        assert!((first - 1.0).abs() > 0.5, "first output is not transient");
+        d.reset();
+
+        let out = (0..4096)
+            .filter_map(|_| d.decimate(1.0).filter(|_| d.is_warm()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(out.len(), 4096 / DECIMATE as usize - WARMUP);
+
+        for o in out {
+            assert!((o - 1.0).abs() < 1.0e-3, "transient in output: {}", o);
+        }
+    }
+
     #[bench]
     fn decimate_cycle(b: &mut Bencher) {
-        let mut d = FIR::new().into_decimator();
+        let mut d = FIR::new().into_decimator(DECIMATE);
         let fs = FREQ;
         let dt = 1. / fs;
 
@@ -319,7 +467,7 @@ mod tests {
 
     #[bench]
     fn decimate_many(b: &mut Bencher) {
-        let mut d = FIR::new().into_decimator();
+        let mut d = FIR::new().into_decimator(DECIMATE);
         let fs = FREQ;
         let dt = 1. / fs;
 
@@ -0,0 +1,144 @@
+//! Averaging of consecutive GPS fixes for moored (stationary) buoys.
+//!
+//! The position of a moored buoy is essentially constant, so the running mean over the last
+//! `window` fixes has less scatter than the single fixes. If a new fix is further than
+//! `MOVEMENT_THRESHOLD` from the current mean the buoy is assumed to have moved (e.g. it has
+//! broken loose), the averaged fixes are discarded and averaging starts again from the new fix.
+//!
+//! With a window of 1 (the default, for drifting buoys) fixes are passed through unchanged.
+
+use heapless::Deque;
+
+/// Maximum number of averaged fixes.
+pub const MAX_WINDOW: usize = 16;
+
+/// Distance from the mean at which a fix is taken as movement rather than noise [m].
+pub const MOVEMENT_THRESHOLD: f64 = 50.0;
+
+/// Mean radius of the earth [m].
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+#[derive(Clone)]
+pub struct FixAverage {
+    /// Accepted fixes as (lat, lon).
+    fixes: Deque<(f64, f64), MAX_WINDOW>,
+    window: usize,
+}
+
+/// Approximate distance between two nearby positions [m].
+pub(crate) fn distance((lat0, lon0): (f64, f64), (lat1, lon1): (f64, f64)) -> f64 {
+    let x = (lon1 - lon0).to_radians() * libm::cos(((lat0 + lat1) / 2.).to_radians());
+    let y = (lat1 - lat0).to_radians();
+
+    EARTH_RADIUS * libm::sqrt(x * x + y * y)
+}
+
+impl FixAverage {
+    /// New average over `window` fixes (clamped to `1..=MAX_WINDOW`).
+    pub fn new(window: usize) -> FixAverage {
+        FixAverage {
+            fixes: Deque::new(),
+            window: window.clamp(1, MAX_WINDOW),
+        }
+    }
+
+    /// Number of fixes in the current average.
+    pub fn len(&self) -> usize {
+        self.fixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fixes.is_empty()
+    }
+
+    fn mean(&self) -> (f64, f64) {
+        let n = self.fixes.len() as f64;
+        let (lat, lon) = self
+            .fixes
+            .iter()
+            .fold((0., 0.), |(a, b), (lat, lon)| (a + lat, b + lon));
+
+        (lat / n, lon / n)
+    }
+
+    /// Add a fix and return the averaged position as (lat, lon).
+    pub fn push(&mut self, lat: f64, lon: f64) -> (f64, f64) {
+        if !self.fixes.is_empty() && distance(self.mean(), (lat, lon)) > MOVEMENT_THRESHOLD {
+            defmt::warn!("New fix is far from averaged position, restarting average.");
+            self.fixes.clear();
+        }
+
+        if self.fixes.len() == self.window {
+            self.fixes.pop_front();
+        }
+        self.fixes.push_back((lat, lon)).unwrap();
+
+        self.mean()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic noise in [-1, 1).
+    fn noise(state: &mut u32) -> f64 {
+        *state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (*state >> 8) as f64 / (1u32 << 23) as f64 - 1.
+    }
+
+    #[test]
+    fn disabled() {
+        let mut a = FixAverage::new(1);
+
+        assert_eq!(a.push(60.0, 5.0), (60.0, 5.0));
+        assert_eq!(a.push(60.0001, 5.0001), (60.0001, 5.0001));
+        assert_eq!(a.len(), 1);
+    }
+
+    #[test]
+    fn noisy_fixes() {
+        let truth = (60.39, 5.32);
+        let mut a = FixAverage::new(10);
+        let mut s = 1;
+
+        let mut raw_err = 0.;
+        let mut avg_err = 0.;
+
+        for i in 0..1000 {
+            // About ±10 m of scatter.
+            let lat = truth.0 + 1.0e-4 * noise(&mut s);
+            let lon = truth.1 + 2.0e-4 * noise(&mut s);
+
+            let p = a.push(lat, lon);
+
+            if i >= 10 {
+                raw_err += distance(truth, (lat, lon));
+                avg_err += distance(truth, p);
+            }
+        }
+
+        assert_eq!(a.len(), 10);
+        assert!(
+            avg_err < raw_err / 2.,
+            "averaged: {}, raw: {}",
+            avg_err,
+            raw_err
+        );
+    }
+
+    #[test]
+    fn movement_restarts() {
+        let mut a = FixAverage::new(10);
+
+        for _ in 0..10 {
+            a.push(60.0, 5.0);
+        }
+        assert_eq!(a.len(), 10);
+
+        // About 1 km north.
+        let p = a.push(60.01, 5.0);
+        assert_eq!(p, (60.01, 5.0));
+        assert_eq!(a.len(), 1);
+    }
+}
@@ -0,0 +1,208 @@
+//! Motion gate: the time series is only sent when there is motion.
+//!
+//! For long deployments in mostly calm conditions the full time series of a calm sea is not worth
+//! the data. With the gate enabled, the motion of every package is the largest standard deviation
+//! (the RMS about the mean) of the acceleration of its axes. The sea turns calm when the motion
+//! drops below `threshold`, and the time series of calm packages is not sent over the Notecard.
+//! The sea is not calm again before the motion rises above `threshold * (1 + hysteresis)`, so
+//! that a motion around the threshold does not flip the gate for every package. A full package is
+//! still sent at least every `heartbeat` seconds in calm, so that it is seen that the buoy is
+//! alive.
+//!
+//! The decision for every package is recorded in the statistics note (`gate` in `stats.qo`), which
+//! is sent for every package while the gate is enabled: calm packages are summarized by their
+//! statistics. Every package is stored to the SD-card regardless (with the `storage` feature),
+//! and can be requested later.
+
+/// Motion gate. Fields that are not set in an override take the default value, the default is
+/// disabled.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct MotionGate {
+    /// Motion below which the sea is calm [m/s^2], `0` disables the gate.
+    pub threshold: f32,
+
+    /// Fraction of `threshold` the motion must rise above it to leave calm.
+    pub hysteresis: f32,
+
+    /// Maximum time between full packages in calm [s].
+    pub heartbeat: u32,
+}
+
+impl Default for MotionGate {
+    fn default() -> MotionGate {
+        MotionGate {
+            threshold: 0.,
+            hysteresis: 0.2,
+            heartbeat: 3600,
+        }
+    }
+}
+
+impl MotionGate {
+    pub fn is_enabled(&self) -> bool {
+        self.threshold > 0.
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.threshold.is_finite()
+            && self.threshold >= 0.
+            && (0. ..=1.).contains(&self.hysteresis)
+            && (60..=7 * 24 * 3600).contains(&self.heartbeat)
+    }
+}
+
+/// Decision for a package.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    /// Motion, or the gate is disabled: the package is sent.
+    Motion,
+
+    /// Calm: only the statistics are sent.
+    Calm,
+
+    /// Calm, but the heartbeat is due: the package is sent.
+    Heartbeat,
+}
+
+impl Decision {
+    /// Code in the statistics note.
+    pub fn code(&self) -> u8 {
+        match self {
+            Decision::Motion => 0,
+            Decision::Calm => 1,
+            Decision::Heartbeat => 2,
+        }
+    }
+
+    /// The time series is sent.
+    pub fn send(&self) -> bool {
+        *self != Decision::Calm
+    }
+}
+
+/// Tracks the gate across packages.
+#[derive(Debug, Default, Clone)]
+pub struct Gate {
+    calm: bool,
+
+    /// Timestamp of the last package sent [ms].
+    last_sent: Option<i64>,
+}
+
+impl Gate {
+    pub fn new() -> Gate {
+        Gate::default()
+    }
+
+    /// Calm at the last package.
+    pub fn is_calm(&self) -> bool {
+        self.calm
+    }
+
+    /// Decide for a package with `motion` [m/s^2] at `timestamp` [ms]. Disabling the gate ends
+    /// the calm.
+    pub fn update(&mut self, gate: &MotionGate, motion: f32, timestamp: i64) -> Decision {
+        self.calm = gate.is_enabled()
+            && if self.calm {
+                motion <= gate.threshold * (1. + gate.hysteresis)
+            } else {
+                motion < gate.threshold
+            };
+
+        let decision = match self.last_sent {
+            _ if !self.calm => Decision::Motion,
+            Some(t) if timestamp - t < gate.heartbeat as i64 * 1000 => Decision::Calm,
+            _ => Decision::Heartbeat,
+        };
+
+        if decision.send() {
+            self.last_sent = Some(timestamp);
+        }
+
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gate() -> MotionGate {
+        MotionGate {
+            threshold: 0.1,
+            hysteresis: 0.5,
+            heartbeat: 60,
+        }
+    }
+
+    #[test]
+    fn disabled() {
+        let g = MotionGate::default();
+        assert!(!g.is_enabled());
+        assert!(g.is_valid());
+
+        let mut s = Gate::new();
+        assert_eq!(s.update(&g, 0., 0), Decision::Motion);
+        assert!(!s.is_calm());
+    }
+
+    #[test]
+    fn hysteresis() {
+        let g = gate();
+        let mut s = Gate::new();
+
+        assert_eq!(s.update(&g, 0.2, 0), Decision::Motion);
+        assert_eq!(s.update(&g, 0.1, 1000), Decision::Motion);
+
+        // Calm below the threshold, a package was sent less than a heartbeat ago.
+        assert_eq!(s.update(&g, 0.05, 2000), Decision::Calm);
+        assert!(s.is_calm());
+
+        // Stays calm up to `threshold * (1 + hysteresis)`.
+        assert_eq!(s.update(&g, 0.12, 3000), Decision::Calm);
+        assert_eq!(s.update(&g, 0.14, 4000), Decision::Calm);
+        assert_eq!(s.update(&g, 0.16, 5000), Decision::Motion);
+        assert!(!s.is_calm());
+
+        // Disabling the gate ends the calm.
+        s.update(&g, 0., 6000);
+        assert!(s.is_calm());
+        assert_eq!(s.update(&MotionGate::default(), 0., 7000), Decision::Motion);
+        assert!(!s.is_calm());
+    }
+
+    #[test]
+    fn heartbeat() {
+        let g = gate();
+        let mut s = Gate::new();
+
+        // No package sent since boot.
+        assert_eq!(s.update(&g, 0., 0), Decision::Heartbeat);
+
+        let d = (1..=130)
+            .map(|i| s.update(&g, 0., i * 1000))
+            .collect::<heapless::Vec<_, 130>>();
+
+        assert_eq!(d[59], Decision::Heartbeat);
+        assert_eq!(d[119], Decision::Heartbeat);
+        assert_eq!(d.iter().filter(|d| d.send()).count(), 2);
+    }
+
+    #[test]
+    fn valid() {
+        let mut g = gate();
+        assert!(g.is_valid());
+
+        g.hysteresis = 1.5;
+        assert!(!g.is_valid());
+
+        g = gate();
+        g.heartbeat = 0;
+        assert!(!g.is_valid());
+
+        g = gate();
+        g.threshold = f32::NAN;
+        assert!(!g.is_valid());
+    }
+}
@@ -0,0 +1,147 @@
+//! Satellite visibility from the `card.location` response of the Notecard.
+//!
+//! The Notecard does not report the satellites as a field of its own, but in the `status` of the
+//! location response, e.g.:
+//!
+//! ```text
+//! GPS updated (58 sec, 41dB SNR, 9 sats) {gps-active} {gps-signal} {gps-sats} {gps}
+//! GPS search (111 sec, 32/33 dB SNR, 0/1 sats) {gps-active} {gps-signal} {gps-sats}
+//! ```
+//!
+//! The count (`n sats`, or `used/in view sats`) and the flags in braces tell apart a receiver that
+//! sees no satellites at all (an antenna or placement problem) from one that sees satellites but
+//! does not get a (good enough) fix, see [`Fix`].
+
+/// State of the GPS at the last location response.
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+pub enum Fix {
+    /// No location response yet, or an empty status.
+    #[default]
+    Unknown,
+
+    /// The GPS is not active (e.g. between periodic fixes), and there is no fix.
+    Off,
+
+    /// The GPS is active, but no satellites are visible.
+    NoSatellites,
+
+    /// Satellites are visible, but there is no fix or the fix is not accurate enough.
+    Weak,
+
+    /// Got a fix.
+    Fix,
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+pub struct Status {
+    /// Number of satellites in the status, the larger number when both the used and the visible
+    /// satellites are given. `None` if not reported.
+    pub sats: Option<u8>,
+    pub fix: Fix,
+}
+
+/// Number of satellites in the status, the number before ` sats` (`n` or `n/m`).
+fn sats(status: &str) -> Option<u8> {
+    let end = status.find(" sats")?;
+    let start = status[..end]
+        .rfind(|c: char| !(c.is_ascii_digit() || c == '/'))
+        .map_or(0, |i| i + 1);
+
+    status[start..end]
+        .split('/')
+        .map(|n| n.parse::<u8>().ok())
+        .try_fold(None, |m: Option<u8>, n| Some(m.max(Some(n?))))?
+}
+
+impl Status {
+    /// Parse the `status` of a location response, `position` is whether the response has a
+    /// position.
+    pub fn parse(status: &str, position: bool) -> Status {
+        let sats = sats(status);
+        let has = |flag: &str| status.contains(flag);
+
+        let fix = if position || has("{gps}") {
+            Fix::Fix
+        } else if sats.unwrap_or(0) > 0 || has("{gps-sats}") || has("{gps-signal}") {
+            Fix::Weak
+        } else if has("{gps-active}") || sats.is_some() {
+            Fix::NoSatellites
+        } else if status.is_empty() {
+            Fix::Unknown
+        } else {
+            Fix::Off
+        };
+
+        Status { sats, fix }
+    }
+
+    /// A fix that is not used (e.g. too high dilution of precision) counts as weak.
+    pub fn rejected(self) -> Status {
+        match self.fix {
+            Fix::Fix => Status {
+                fix: Fix::Weak,
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_status() {
+        let s = Status::parse(
+            "GPS updated (58 sec, 41dB SNR, 9 sats) {gps-active} {gps-signal} {gps-sats} {gps}",
+            true,
+        );
+        assert_eq!(
+            s,
+            Status {
+                sats: Some(9),
+                fix: Fix::Fix
+            }
+        );
+
+        let s = Status::parse(
+            "GPS search (111 sec, 32/33 dB SNR, 0/1 sats) {gps-active} {gps-signal} {gps-sats}",
+            false,
+        );
+        assert_eq!(
+            s,
+            Status {
+                sats: Some(1),
+                fix: Fix::Weak
+            }
+        );
+        assert_eq!(s.rejected(), s);
+
+        let s = Status::parse("GPS search (30 sec, 0/0 sats) {gps-active}", false);
+        assert_eq!(
+            s,
+            Status {
+                sats: Some(0),
+                fix: Fix::NoSatellites
+            }
+        );
+
+        assert_eq!(
+            Status::parse("GPS inactive {gps-inactive}", false).fix,
+            Fix::Off
+        );
+        assert_eq!(Status::parse("", false), Status::default());
+    }
+
+    #[test]
+    fn rejected_fix() {
+        let s = Status::parse("GPS updated (5 sec, 3 sats) {gps}", true);
+        assert_eq!(s.rejected().fix, Fix::Weak);
+        assert_eq!(s.rejected().sats, Some(3));
+    }
+}
@@ -0,0 +1,822 @@
+//! Health of the buoy.
+//!
+//! A [`Health`] report is sent to `health.qo` every `note::HEALTH_INTERVAL`, and with the `storage`
+//! feature the same report is appended to `HEALTH_FILE` on the SD-card, so that the health
+//! timeline of a deployment is kept also when the buoy cannot connect.
+//!
+//! The file is a sequence of records, each a postcard serialized `(HEALTH_VERSION, Health)` with
+//! COBS framing (zero terminated), like the packages in the collections. The fields are the same
+//! as in the note. `sfypack health` exports the records as CSV. Records of version 1 (without the
+//! reboot counters), version 2 (without the GPS status), version 3 (without the profile), version 4
+//! (without the CT reading), version 5 (without the bearer), version 6 (without the active
+//! Notecard), version 7 (without the Notecard queue) and version 8 (without the auxiliary
+//! analog channels) are still decoded, and upgraded one version at a time to the current layout.
+
+use heapless::Vec;
+
+use crate::adc::MAX_CHANNELS;
+use crate::axl::DecodeError;
+use crate::ct::Ct;
+use crate::gnss;
+use crate::profile::Breakdown;
+use crate::queue::Dropped;
+use crate::transport::Bearer;
+
+/// Health records on the SD-card.
+pub const HEALTH_FILE: &str = "HEALTH.LOG";
+
+/// Format version of the health records, increase when `Health` changes. The previous layout is
+/// kept as `HealthV<n>`, with an upgrade to the next version.
+pub const HEALTH_VERSION: u32 = 9;
+
+/// Maximum size of a serialized and COBS framed record.
+pub const HEALTH_RECORD_SZ: usize = 192;
+
+/// Summary of the state of the buoy.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, PartialEq)]
+pub struct Health {
+    pub timestamp: i64,
+
+    /// Time of last completed sync [ms].
+    pub last_sync: Option<i64>,
+
+    /// Sync attempts in the history, and how many of them failed.
+    pub sync_attempts: u32,
+    pub sync_failures: u32,
+
+    /// Items dropped from the queues since boot.
+    pub dropped: Dropped,
+
+    /// Packages waiting for the notecard.
+    pub note_queue: u32,
+
+    /// Packages waiting to be stored, 0 without the `storage` feature.
+    pub storage_queue: u32,
+
+    /// Estimated free space on the SD-card [bytes], `None` if not known.
+    pub free_space: Option<u64>,
+
+    /// Cause of the last reset: the status register of the reset generator of the MCU
+    /// (`RSTGEN.STAT`).
+    pub reset_cause: u32,
+
+    /// Reboots over the lifetime of the SD-card and since the start of the deployment (see
+    /// `reboots`).
+    pub reboots: u32,
+    pub reboots_deployment: u32,
+
+    /// Satellites and GPS state at the last location response (see `gnss`).
+    pub gps: gnss::Status,
+
+    /// Awake time of the MCU since the last report, `None` without the `profiling` feature (see
+    /// `profile`).
+    pub profile: Option<Breakdown>,
+
+    /// Conductivity, temperature and salinity of the sea water, `None` without the `ct` feature
+    /// or when the sensor is not found (see `ct`).
+    pub ct: Option<Ct>,
+
+    /// Bearer of the connection to notehub, `None` if not known (see `transport`).
+    pub bearer: Option<Bearer>,
+
+    /// Active Notecard (`0` for the primary), `None` without the `redundant-notecard` feature
+    /// (see `failover`).
+    pub notecard: Option<u8>,
+
+    /// Notes waiting on the Notecard to be synced, `None` if not known (see `queue::CardQueue`).
+    pub card_queue: Option<u32>,
+
+    /// Readings of the auxiliary analog channels in the order of the config, `None` without the
+    /// `adc` feature or without channels (see `adc`).
+    pub adc: Option<Vec<f32, MAX_CHANNELS>>,
+}
+
+/// Health record version 1.
+#[derive(serde::Deserialize)]
+struct HealthV1 {
+    timestamp: i64,
+    last_sync: Option<i64>,
+    sync_attempts: u32,
+    sync_failures: u32,
+    dropped: Dropped,
+    note_queue: u32,
+    storage_queue: u32,
+    free_space: Option<u64>,
+    reset_cause: u32,
+}
+
+impl From<HealthV1> for HealthV2 {
+    fn from(h: HealthV1) -> HealthV2 {
+        HealthV2 {
+            timestamp: h.timestamp,
+            last_sync: h.last_sync,
+            sync_attempts: h.sync_attempts,
+            sync_failures: h.sync_failures,
+            dropped: h.dropped,
+            note_queue: h.note_queue,
+            storage_queue: h.storage_queue,
+            free_space: h.free_space,
+            reset_cause: h.reset_cause,
+            reboots: 0,
+            reboots_deployment: 0,
+        }
+    }
+}
+
+/// Health record version 2.
+#[derive(serde::Deserialize)]
+struct HealthV2 {
+    timestamp: i64,
+    last_sync: Option<i64>,
+    sync_attempts: u32,
+    sync_failures: u32,
+    dropped: Dropped,
+    note_queue: u32,
+    storage_queue: u32,
+    free_space: Option<u64>,
+    reset_cause: u32,
+    reboots: u32,
+    reboots_deployment: u32,
+}
+
+impl From<HealthV2> for HealthV3 {
+    fn from(h: HealthV2) -> HealthV3 {
+        HealthV3 {
+            timestamp: h.timestamp,
+            last_sync: h.last_sync,
+            sync_attempts: h.sync_attempts,
+            sync_failures: h.sync_failures,
+            dropped: h.dropped,
+            note_queue: h.note_queue,
+            storage_queue: h.storage_queue,
+            free_space: h.free_space,
+            reset_cause: h.reset_cause,
+            reboots: h.reboots,
+            reboots_deployment: h.reboots_deployment,
+            gps: gnss::Status::default(),
+        }
+    }
+}
+
+/// Health record version 3.
+#[derive(serde::Deserialize)]
+struct HealthV3 {
+    timestamp: i64,
+    last_sync: Option<i64>,
+    sync_attempts: u32,
+    sync_failures: u32,
+    dropped: Dropped,
+    note_queue: u32,
+    storage_queue: u32,
+    free_space: Option<u64>,
+    reset_cause: u32,
+    reboots: u32,
+    reboots_deployment: u32,
+    gps: gnss::Status,
+}
+
+impl From<HealthV3> for HealthV4 {
+    fn from(h: HealthV3) -> HealthV4 {
+        HealthV4 {
+            timestamp: h.timestamp,
+            last_sync: h.last_sync,
+            sync_attempts: h.sync_attempts,
+            sync_failures: h.sync_failures,
+            dropped: h.dropped,
+            note_queue: h.note_queue,
+            storage_queue: h.storage_queue,
+            free_space: h.free_space,
+            reset_cause: h.reset_cause,
+            reboots: h.reboots,
+            reboots_deployment: h.reboots_deployment,
+            gps: h.gps,
+            profile: None,
+        }
+    }
+}
+
+/// Health record version 4.
+#[derive(serde::Deserialize)]
+struct HealthV4 {
+    timestamp: i64,
+    last_sync: Option<i64>,
+    sync_attempts: u32,
+    sync_failures: u32,
+    dropped: Dropped,
+    note_queue: u32,
+    storage_queue: u32,
+    free_space: Option<u64>,
+    reset_cause: u32,
+    reboots: u32,
+    reboots_deployment: u32,
+    gps: gnss::Status,
+    profile: Option<Breakdown>,
+}
+
+impl From<HealthV4> for HealthV5 {
+    fn from(h: HealthV4) -> HealthV5 {
+        HealthV5 {
+            timestamp: h.timestamp,
+            last_sync: h.last_sync,
+            sync_attempts: h.sync_attempts,
+            sync_failures: h.sync_failures,
+            dropped: h.dropped,
+            note_queue: h.note_queue,
+            storage_queue: h.storage_queue,
+            free_space: h.free_space,
+            reset_cause: h.reset_cause,
+            reboots: h.reboots,
+            reboots_deployment: h.reboots_deployment,
+            gps: h.gps,
+            profile: h.profile,
+            ct: None,
+        }
+    }
+}
+
+/// Health record version 5.
+#[derive(serde::Deserialize)]
+struct HealthV5 {
+    timestamp: i64,
+    last_sync: Option<i64>,
+    sync_attempts: u32,
+    sync_failures: u32,
+    dropped: Dropped,
+    note_queue: u32,
+    storage_queue: u32,
+    free_space: Option<u64>,
+    reset_cause: u32,
+    reboots: u32,
+    reboots_deployment: u32,
+    gps: gnss::Status,
+    profile: Option<Breakdown>,
+    ct: Option<Ct>,
+}
+
+impl From<HealthV5> for HealthV6 {
+    fn from(h: HealthV5) -> HealthV6 {
+        HealthV6 {
+            timestamp: h.timestamp,
+            last_sync: h.last_sync,
+            sync_attempts: h.sync_attempts,
+            sync_failures: h.sync_failures,
+            dropped: h.dropped,
+            note_queue: h.note_queue,
+            storage_queue: h.storage_queue,
+            free_space: h.free_space,
+            reset_cause: h.reset_cause,
+            reboots: h.reboots,
+            reboots_deployment: h.reboots_deployment,
+            gps: h.gps,
+            profile: h.profile,
+            ct: h.ct,
+            bearer: None,
+        }
+    }
+}
+
+/// Health record version 6.
+#[derive(serde::Deserialize)]
+struct HealthV6 {
+    timestamp: i64,
+    last_sync: Option<i64>,
+    sync_attempts: u32,
+    sync_failures: u32,
+    dropped: Dropped,
+    note_queue: u32,
+    storage_queue: u32,
+    free_space: Option<u64>,
+    reset_cause: u32,
+    reboots: u32,
+    reboots_deployment: u32,
+    gps: gnss::Status,
+    profile: Option<Breakdown>,
+    ct: Option<Ct>,
+    bearer: Option<Bearer>,
+}
+
+impl From<HealthV6> for HealthV7 {
+    fn from(h: HealthV6) -> HealthV7 {
+        HealthV7 {
+            timestamp: h.timestamp,
+            last_sync: h.last_sync,
+            sync_attempts: h.sync_attempts,
+            sync_failures: h.sync_failures,
+            dropped: h.dropped,
+            note_queue: h.note_queue,
+            storage_queue: h.storage_queue,
+            free_space: h.free_space,
+            reset_cause: h.reset_cause,
+            reboots: h.reboots,
+            reboots_deployment: h.reboots_deployment,
+            gps: h.gps,
+            profile: h.profile,
+            ct: h.ct,
+            bearer: h.bearer,
+            notecard: None,
+        }
+    }
+}
+
+/// Health record version 7.
+#[derive(serde::Deserialize)]
+struct HealthV7 {
+    timestamp: i64,
+    last_sync: Option<i64>,
+    sync_attempts: u32,
+    sync_failures: u32,
+    dropped: Dropped,
+    note_queue: u32,
+    storage_queue: u32,
+    free_space: Option<u64>,
+    reset_cause: u32,
+    reboots: u32,
+    reboots_deployment: u32,
+    gps: gnss::Status,
+    profile: Option<Breakdown>,
+    ct: Option<Ct>,
+    bearer: Option<Bearer>,
+    notecard: Option<u8>,
+}
+
+impl From<HealthV7> for HealthV8 {
+    fn from(h: HealthV7) -> HealthV8 {
+        HealthV8 {
+            timestamp: h.timestamp,
+            last_sync: h.last_sync,
+            sync_attempts: h.sync_attempts,
+            sync_failures: h.sync_failures,
+            dropped: h.dropped,
+            note_queue: h.note_queue,
+            storage_queue: h.storage_queue,
+            free_space: h.free_space,
+            reset_cause: h.reset_cause,
+            reboots: h.reboots,
+            reboots_deployment: h.reboots_deployment,
+            gps: h.gps,
+            profile: h.profile,
+            ct: h.ct,
+            bearer: h.bearer,
+            notecard: h.notecard,
+            card_queue: None,
+        }
+    }
+}
+
+/// Health record version 8.
+#[derive(serde::Deserialize)]
+struct HealthV8 {
+    timestamp: i64,
+    last_sync: Option<i64>,
+    sync_attempts: u32,
+    sync_failures: u32,
+    dropped: Dropped,
+    note_queue: u32,
+    storage_queue: u32,
+    free_space: Option<u64>,
+    reset_cause: u32,
+    reboots: u32,
+    reboots_deployment: u32,
+    gps: gnss::Status,
+    profile: Option<Breakdown>,
+    ct: Option<Ct>,
+    bearer: Option<Bearer>,
+    notecard: Option<u8>,
+    card_queue: Option<u32>,
+}
+
+impl From<HealthV8> for Health {
+    fn from(h: HealthV8) -> Health {
+        Health {
+            timestamp: h.timestamp,
+            last_sync: h.last_sync,
+            sync_attempts: h.sync_attempts,
+            sync_failures: h.sync_failures,
+            dropped: h.dropped,
+            note_queue: h.note_queue,
+            storage_queue: h.storage_queue,
+            free_space: h.free_space,
+            reset_cause: h.reset_cause,
+            reboots: h.reboots,
+            reboots_deployment: h.reboots_deployment,
+            gps: h.gps,
+            profile: h.profile,
+            ct: h.ct,
+            bearer: h.bearer,
+            notecard: h.notecard,
+            card_queue: h.card_queue,
+            adc: None,
+        }
+    }
+}
+
+/// A record deserialized with the layout of its version, upgraded one version at a time to the
+/// current layout (see [`Versioned::upgrade`]).
+enum Versioned {
+    V1(HealthV1),
+    V2(HealthV2),
+    V3(HealthV3),
+    V4(HealthV4),
+    V5(HealthV5),
+    V6(HealthV6),
+    V7(HealthV7),
+    V8(HealthV8),
+    Current(Health),
+}
+
+impl Versioned {
+    /// Upgrade the record to the layout of the next version.
+    fn upgrade(self) -> Versioned {
+        use Versioned::*;
+
+        match self {
+            V1(h) => V2(h.into()),
+            V2(h) => V3(h.into()),
+            V3(h) => V4(h.into()),
+            V4(h) => V5(h.into()),
+            V5(h) => V6(h.into()),
+            V6(h) => V7(h.into()),
+            V7(h) => V8(h.into()),
+            V8(h) => Current(h.into()),
+            Current(h) => Current(h),
+        }
+    }
+
+    /// Upgrade the record to the current layout.
+    fn current(mut self) -> Health {
+        loop {
+            match self {
+                Versioned::Current(h) => return h,
+                v => self = v.upgrade(),
+            }
+        }
+    }
+}
+
+impl Health {
+    /// Serialize as a record of the health file.
+    pub fn to_cobs(&self) -> Result<Vec<u8, HEALTH_RECORD_SZ>, postcard::Error> {
+        postcard::to_vec_cobs(&(HEALTH_VERSION, self))
+    }
+
+    /// Decode a COBS framed record of the health file, the buffer is decoded in place.
+    pub fn decode(buf: &mut [u8]) -> Result<Health, DecodeError> {
+        let n = cobs::decode_in_place(buf).map_err(|_| DecodeError::Cobs)?;
+
+        if n == 0 {
+            return Err(DecodeError::Empty);
+        }
+
+        let (version, buf) =
+            postcard::take_from_bytes::<u32>(&buf[..n]).map_err(DecodeError::Postcard)?;
+
+        use postcard::from_bytes as from;
+        use Versioned::*;
+
+        match version {
+            1 => from(buf).map(V1),
+            2 => from(buf).map(V2),
+            3 => from(buf).map(V3),
+            4 => from(buf).map(V4),
+            5 => from(buf).map(V5),
+            6 => from(buf).map(V6),
+            7 => from(buf).map(V7),
+            8 => from(buf).map(V8),
+            HEALTH_VERSION => from(buf).map(Current),
+            _ => return Err(DecodeError::UnsupportedVersion(version)),
+        }
+        .map(Versioned::current)
+        .map_err(DecodeError::Postcard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let h = Health {
+            timestamp: i64::MAX,
+            last_sync: Some(i64::MAX),
+            sync_attempts: u32::MAX,
+            sync_failures: u32::MAX,
+            dropped: Dropped {
+                low: u32::MAX,
+                normal: u32::MAX,
+                critical: u32::MAX,
+            },
+            note_queue: u32::MAX,
+            storage_queue: u32::MAX,
+            free_space: Some(u64::MAX),
+            reset_cause: u32::MAX,
+            reboots: u32::MAX,
+            reboots_deployment: u32::MAX,
+            gps: gnss::Status {
+                sats: Some(u8::MAX),
+                fix: gnss::Fix::Fix,
+            },
+            profile: Some(Breakdown {
+                interval: u32::MAX,
+                imu: u32::MAX,
+                location: u32::MAX,
+                storage: u32::MAX,
+                notecard: u32::MAX,
+            }),
+            ct: Some(Ct {
+                conductivity: f32::MAX,
+                temperature: f32::MIN,
+                salinity: 35.,
+            }),
+            bearer: Some(Bearer::Ntn),
+            notecard: Some(u8::MAX),
+            card_queue: Some(u32::MAX),
+            adc: Some(Vec::from_slice(&[f32::MAX; MAX_CHANNELS]).unwrap()),
+        };
+
+        let mut b = h.to_cobs().unwrap();
+        assert_eq!(b.last(), Some(&0));
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+
+    #[test]
+    fn version_1() {
+        let h = Health {
+            timestamp: 1_700_000_000_000,
+            sync_attempts: 2,
+            reset_cause: 0x2,
+            ..Default::default()
+        };
+
+        // Version 1 is version 2 without the reboot counters at the end.
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> = postcard::to_vec_cobs(&(
+            1u32,
+            h.timestamp,
+            h.last_sync,
+            h.sync_attempts,
+            h.sync_failures,
+            h.dropped,
+            h.note_queue,
+            h.storage_queue,
+            h.free_space,
+            h.reset_cause,
+        ))
+        .unwrap();
+
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+
+    #[test]
+    fn version_2() {
+        let h = Health {
+            timestamp: 1_700_000_000_000,
+            sync_attempts: 2,
+            reboots: 4,
+            reboots_deployment: 1,
+            ..Default::default()
+        };
+
+        // Version 2 is version 3 without the GPS status at the end.
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> = postcard::to_vec_cobs(&(
+            2u32,
+            h.timestamp,
+            h.last_sync,
+            h.sync_attempts,
+            h.sync_failures,
+            h.dropped,
+            h.note_queue,
+            h.storage_queue,
+            h.free_space,
+            h.reset_cause,
+            h.reboots,
+            h.reboots_deployment,
+        ))
+        .unwrap();
+
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+
+    #[test]
+    fn version_3() {
+        let h = Health {
+            timestamp: 1_700_000_000_000,
+            reboots: 4,
+            gps: gnss::Status {
+                sats: Some(7),
+                fix: gnss::Fix::Fix,
+            },
+            ..Default::default()
+        };
+
+        // Version 3 is version 4 without the profile at the end.
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> = postcard::to_vec_cobs(&(
+            3u32,
+            h.timestamp,
+            h.last_sync,
+            h.sync_attempts,
+            h.sync_failures,
+            h.dropped,
+            h.note_queue,
+            h.storage_queue,
+            h.free_space,
+            h.reset_cause,
+            h.reboots,
+            h.reboots_deployment,
+            &h.gps,
+        ))
+        .unwrap();
+
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+
+    #[test]
+    fn version_4() {
+        let h = Health {
+            timestamp: 1_700_000_000_000,
+            reboots: 4,
+            profile: Some(Breakdown {
+                interval: 60_000,
+                imu: 10,
+                location: 20,
+                storage: 30,
+                notecard: 40,
+            }),
+            ..Default::default()
+        };
+
+        // Version 4 is version 5 without the CT reading at the end.
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> = postcard::to_vec_cobs(&(
+            4u32,
+            h.timestamp,
+            h.last_sync,
+            h.sync_attempts,
+            h.sync_failures,
+            h.dropped,
+            h.note_queue,
+            h.storage_queue,
+            h.free_space,
+            h.reset_cause,
+            h.reboots,
+            h.reboots_deployment,
+            &h.gps,
+            h.profile,
+        ))
+        .unwrap();
+
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> =
+            postcard::to_vec_cobs(&(HEALTH_VERSION + 1, Health::default())).unwrap();
+
+        assert!(matches!(
+            Health::decode(&mut b),
+            Err(DecodeError::UnsupportedVersion(v)) if v == HEALTH_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn version_5() {
+        let h = Health {
+            timestamp: 1_700_000_000_000,
+            reboots: 4,
+            ct: Some(Ct::new(42.914, 15.)),
+            ..Default::default()
+        };
+
+        // Version 5 is version 6 without the bearer at the end.
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> = postcard::to_vec_cobs(&(
+            5u32,
+            h.timestamp,
+            h.last_sync,
+            h.sync_attempts,
+            h.sync_failures,
+            h.dropped,
+            h.note_queue,
+            h.storage_queue,
+            h.free_space,
+            h.reset_cause,
+            h.reboots,
+            h.reboots_deployment,
+            &h.gps,
+            h.profile,
+            h.ct,
+        ))
+        .unwrap();
+
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+
+    #[test]
+    fn version_6() {
+        let h = Health {
+            timestamp: 1_700_000_000_000,
+            reboots: 4,
+            bearer: Some(Bearer::Cell),
+            ..Default::default()
+        };
+
+        // Version 6 is version 7 without the active Notecard at the end.
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> = postcard::to_vec_cobs(&(
+            6u32,
+            h.timestamp,
+            h.last_sync,
+            h.sync_attempts,
+            h.sync_failures,
+            h.dropped,
+            h.note_queue,
+            h.storage_queue,
+            h.free_space,
+            h.reset_cause,
+            h.reboots,
+            h.reboots_deployment,
+            &h.gps,
+            h.profile,
+            h.ct,
+            h.bearer,
+        ))
+        .unwrap();
+
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+
+    #[test]
+    fn version_7() {
+        let h = Health {
+            timestamp: 1_700_000_000_000,
+            note_queue: 3,
+            notecard: Some(1),
+            ..Default::default()
+        };
+
+        // Version 7 is version 8 without the Notecard queue at the end (split in two tuples, which
+        // postcard serializes the same as one).
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> = postcard::to_vec_cobs(&(
+            7u32,
+            (
+                h.timestamp,
+                h.last_sync,
+                h.sync_attempts,
+                h.sync_failures,
+                h.dropped,
+                h.note_queue,
+                h.storage_queue,
+                h.free_space,
+            ),
+            (
+                h.reset_cause,
+                h.reboots,
+                h.reboots_deployment,
+                &h.gps,
+                h.profile,
+                h.ct,
+                h.bearer,
+                h.notecard,
+            ),
+        ))
+        .unwrap();
+
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+
+    #[test]
+    fn version_8() {
+        let h = Health {
+            timestamp: 1_700_000_000_000,
+            note_queue: 3,
+            card_queue: Some(120),
+            ..Default::default()
+        };
+
+        // Version 8 is version 9 without the auxiliary analog channels at the end.
+        let mut b: Vec<u8, HEALTH_RECORD_SZ> = postcard::to_vec_cobs(&(
+            8u32,
+            (
+                h.timestamp,
+                h.last_sync,
+                h.sync_attempts,
+                h.sync_failures,
+                h.dropped,
+                h.note_queue,
+                h.storage_queue,
+                h.free_space,
+            ),
+            (
+                h.reset_cause,
+                h.reboots,
+                h.reboots_deployment,
+                &h.gps,
+                h.profile,
+                h.ct,
+                h.bearer,
+                h.notecard,
+                h.card_queue,
+            ),
+        ))
+        .unwrap();
+
+        assert_eq!(Health::decode(&mut b).unwrap(), h);
+    }
+}
@@ -9,6 +9,9 @@
 #[cfg(test)]
 extern crate test;
 
+#[cfg(feature = "std")]
+extern crate std;
+
 #[allow(unused_imports)]
 use defmt::{debug, error, info, trace, warn};
 
@@ -17,30 +20,69 @@ use chrono::NaiveDateTime;
 use core::cell::RefCell;
 use core::fmt::Debug;
 use core::ops::DerefMut;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use cortex_m::interrupt::{free, Mutex};
 use embedded_hal::blocking::{
     delay::DelayMs,
     i2c::{Read, Write, WriteRead},
 };
 
-#[cfg(feature = "storage")]
-use embedded_hal::{blocking::spi::Transfer, digital::v2::OutputPin};
-
 use rtcc::DateTimeAccess;
 
+pub mod adc;
 pub mod axl;
+pub mod backfill;
+pub mod beacon;
+pub mod bist;
+pub mod burst;
+pub mod bus;
+pub mod calibration;
+pub mod clock;
+pub mod cmd;
+pub mod config;
+#[cfg(feature = "decrypt")]
+pub mod crypt;
+pub mod ct;
+pub mod dedup;
+pub mod deployment;
+#[cfg(feature = "despike")]
+pub mod despike;
+#[cfg(feature = "redundant-notecard")]
+pub mod failover;
+pub mod filter;
 #[cfg(feature = "fir")]
 pub mod fir;
+pub mod fix_average;
+pub mod gate;
+pub mod gnss;
+pub mod health;
+pub mod location_log;
 pub mod log;
+pub mod lz;
 pub mod note;
+pub mod pace;
+pub mod panic_log;
+pub mod postmortem;
+pub mod profile;
+pub mod provision;
+pub mod quality;
+pub mod queue;
+pub mod quiet;
+pub mod reboots;
+pub mod safe_mode;
 #[cfg(feature = "storage")]
 pub mod storage;
+pub mod sync_history;
+pub mod tamper;
+pub mod trace;
+pub mod transport;
+pub mod urgency;
+pub mod watchdog;
 pub mod waves;
+pub mod weighting;
 
 use axl::AxlPacket;
 #[cfg(feature = "storage")]
-use storage::Storage;
-#[cfg(feature = "storage")]
 use waves::AxlPacketT;
 
 #[cfg(feature = "storage")]
@@ -50,91 +92,277 @@ pub type ImuAxlPacketT = waves::AxlPacketT;
 pub type ImuAxlPacketT = axl::AxlPacket;
 
 // With 'raw' enabled 3 * 2 more samples (compared to processed samples)
-// need to be queued.
+// need to be queued. The capacities of the queues must be powers of 2 (see
+// `queue::DataQueue`).
 #[cfg(feature = "raw")]
-pub const STORAGEQ_SZ: usize = 3;
+pub const STORAGEQ_SZ: usize = 2;
 #[cfg(feature = "raw")]
-pub const NOTEQ_SZ: usize = 6;
+pub const NOTEQ_SZ: usize = 4;
 
 #[cfg(not(feature = "raw"))]
-pub const STORAGEQ_SZ: usize = 12;
+pub const STORAGEQ_SZ: usize = 16;
 #[cfg(all(not(feature = "raw"), feature = "storage"))]
-pub const NOTEQ_SZ: usize = 12;
+pub const NOTEQ_SZ: usize = 8;
 
 #[cfg(feature = "storage")]
 pub const IMUQ_SZ: usize = STORAGEQ_SZ;
 
 #[cfg(all(not(feature = "raw"), not(feature = "storage")))]
-pub const NOTEQ_SZ: usize = 24;
+pub const NOTEQ_SZ: usize = 16;
 
 #[cfg(not(feature = "storage"))]
 pub const IMUQ_SZ: usize = NOTEQ_SZ;
 
-/// These queues are filled up by the IMU interrupt in read batches of time-series. It is then consumed
-/// the main thread and first drained to the SD storage (if enabled), and then queued for the notecard.
+// Packages held by the IMU when its queue is full (see `queue::QueueFull`). The slots are
+// reserved in the IMU whether they are used or not: about 6.2 kB per package, and with 'raw' the
+// raw samples on top (`RAW_AXL_BYTE_SZ`).
+#[cfg(feature = "raw")]
+pub const OVERFLOW_SZ: usize = 1;
+
+#[cfg(not(feature = "raw"))]
+pub const OVERFLOW_SZ: usize = 4;
+
+// Packages held by the storage manager when the notecard queue is full (see `queue::QueueFull`),
+// reserved like the IMU overflow (without the raw samples).
+#[cfg(feature = "storage")]
+pub const NOTE_OVERFLOW_SZ: usize = 2;
+
+// These queues are filled up by the IMU interrupt (see [`Imu`]) in read batches of time-series.
+// They are consumed by the main thread and first drained to the SD storage (if enabled), and then
+// queued for the notecard. The queues are split into their endpoints once at boot (see
+// [`split_queues`]), and the endpoints live as long as the firmware: the producer stays in the
+// `Imu` through resets and re-initializations of the IMU, which only reset the IMU itself.
 
 /// Queue from IMU to Storage
 #[cfg(feature = "storage")]
-pub static mut STORAGEQ: heapless::spsc::Queue<AxlPacketT, STORAGEQ_SZ> =
-    heapless::spsc::Queue::new();
+static mut STORAGEQ: queue::DataQueue<AxlPacketT, STORAGEQ_SZ> = queue::DataQueue::new();
 
 /// Queue from Storage to Notecard
-pub static mut NOTEQ: heapless::spsc::Queue<AxlPacket, NOTEQ_SZ> = heapless::spsc::Queue::new();
+static mut NOTEQ: queue::DataQueue<AxlPacket, NOTEQ_SZ> = queue::DataQueue::new();
+
+/// The queues have been split into their endpoints.
+static QUEUES_SPLIT: AtomicBool = AtomicBool::new(false);
+
+/// The endpoints of the data queues.
+pub struct Queues {
+    /// From the IMU: to the storage with the `storage` feature, otherwise to the notecard.
+    pub imu: queue::Producer<'static, ImuAxlPacketT, IMUQ_SZ>,
+
+    /// From the IMU to the storage.
+    #[cfg(feature = "storage")]
+    pub storage: queue::Consumer<'static, AxlPacketT, STORAGEQ_SZ>,
+
+    /// From the storage to the notecard.
+    #[cfg(feature = "storage")]
+    pub note_producer: queue::Producer<'static, AxlPacket, NOTEQ_SZ>,
+
+    /// To the notecard.
+    pub note: queue::Consumer<'static, AxlPacket, NOTEQ_SZ>,
+}
+
+/// Split the data queues into their endpoints. Only the first call gets the endpoints, later calls
+/// return `None`: a queue with a second producer or consumer would be corrupted.
+pub fn split_queues() -> Option<Queues> {
+    if QUEUES_SPLIT.swap(true, Ordering::AcqRel) {
+        return None;
+    }
+
+    // Safety: the queues are only split here, and only once.
+    #[cfg(feature = "storage")]
+    {
+        let (imu, storage) = unsafe { STORAGEQ.split() };
+        let (note_producer, note) = unsafe { NOTEQ.split() };
+
+        return Some(Queues {
+            imu,
+            storage,
+            note_producer,
+            note,
+        });
+    }
+
+    #[cfg(not(feature = "storage"))]
+    {
+        let (imu, note) = unsafe { NOTEQ.split() };
+
+        return Some(Queues { imu, note });
+    }
+}
 
 pub struct SharedState<D: DateTimeAccess> {
     pub rtc: D,
     pub position_time: u32,
     pub lon: f64,
     pub lat: f64,
+
+    /// Satellites and GPS state at the last location response, kept between fixes.
+    pub gps: gnss::Status,
+
+    /// Validation of the reads of the RTC, see [`SharedState::rtc_time`].
+    pub time: clock::TimeGuard,
+}
+
+/// Start of the epoch, the time when the RTC cannot be read.
+fn epoch() -> NaiveDateTime {
+    NaiveDateTime::from_timestamp_opt(0, 0).unwrap()
 }
 
 pub trait State {
-    fn now(&self) -> NaiveDateTime;
+    /// Returns now, or `None` if the state is not set up yet or is borrowed elsewhere.
+    fn try_now(&self) -> Option<NaiveDateTime>;
+
+    /// Returns now, posistion_time, lat, lon, or `None` if the state is not set up yet or is
+    /// borrowed elsewhere.
+    fn try_get(&self) -> Option<(NaiveDateTime, u32, f64, f64)>;
+
+    /// Returns now, the start of the epoch if the state is not available (see `try_now`).
+    fn now(&self) -> NaiveDateTime {
+        self.try_now().unwrap_or_else(|| {
+            error!("Shared state not available, using epoch.");
+            epoch()
+        })
+    }
+
+    /// Returns now in milliseconds since the epoch, or `None` (see `try_now`).
+    fn try_now_millis(&self) -> Option<i64> {
+        self.try_now().map(|t| t.timestamp_millis())
+    }
+
+    /// Returns now in milliseconds since the epoch, see `now`.
+    fn now_millis(&self) -> i64 {
+        self.now().timestamp_millis()
+    }
+
+    /// Returns now, posistion_time, lat, lon. The start of the epoch and no position if the state
+    /// is not available (see `try_get`).
+    fn get(&self) -> (NaiveDateTime, u32, f64, f64) {
+        self.try_get().unwrap_or_else(|| {
+            error!("Shared state not available, using epoch and no position.");
+            (epoch(), 0, 0., 0.)
+        })
+    }
+}
 
-    /// Returns now, posistion_time, lat, lon.
-    fn get(&self) -> (NaiveDateTime, u32, f64, f64);
+/// Access to the shared state behind the mutex that does not panic.
+pub trait WithState<D: DateTimeAccess> {
+    /// Run `f` on the shared state. Returns `None` without running `f` if the state has not been
+    /// set up yet, or is already borrowed (e.g. re-entrantly from an interrupt).
+    fn with_state<R>(&self, f: impl FnOnce(&mut SharedState<D>) -> R) -> Option<R>;
 }
 
 impl<D: DateTimeAccess> SharedState<D> {
-    fn now(&mut self) -> NaiveDateTime {
-        self.rtc
-            .datetime()
-            .unwrap_or(NaiveDateTime::from_timestamp_opt(0, 0).unwrap())
+    /// Read the RTC, `None` if the read failed. Not validated, see `rtc_time`.
+    pub fn raw_now(&mut self) -> Option<NaiveDateTime> {
+        self.rtc.datetime().ok()
+    }
+
+    /// Read and validate the RTC (see `clock::TimeGuard`). The first of a run of implausible
+    /// reads is logged.
+    pub fn rtc_time(&mut self) -> clock::RtcTime {
+        let raw = self.raw_now().map(|t| t.timestamp_millis());
+        let t = self.time.validate(raw);
+
+        if self.time.implausible() == 1 {
+            use core::fmt::Write as _;
+
+            let mut msg = heapless::String::<128>::new();
+            match raw {
+                Some(raw) => write!(&mut msg, "Implausible RTC time: {} ms", raw),
+                None => write!(&mut msg, "Failed to read RTC"),
+            }
+            .ok();
+            write!(&mut msg, ", using: {} ms.", t.time).ok();
+            log::log_at(log::Category::Location, log::Level::Error, &msg);
+        }
+
+        t
+    }
+
+    /// Validated time of the RTC.
+    pub fn now(&mut self) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp_millis(self.now_millis()).unwrap_or(epoch())
+    }
+
+    /// Validated time of the RTC in milliseconds since the epoch.
+    pub fn now_millis(&mut self) -> i64 {
+        self.rtc_time().time
     }
 
     fn get(&mut self) -> (NaiveDateTime, u32, f64, f64) {
-        (
-            self.rtc
-                .datetime()
-                .unwrap_or(NaiveDateTime::from_timestamp_opt(0, 0).unwrap()),
-            self.position_time,
-            self.lat,
-            self.lon,
-        )
+        (self.now(), self.position_time, self.lat, self.lon)
     }
 }
 
-impl<D: DateTimeAccess> State for Mutex<RefCell<Option<SharedState<D>>>> {
-    fn now(&self) -> NaiveDateTime {
+impl<D: DateTimeAccess> WithState<D> for Mutex<RefCell<Option<SharedState<D>>>> {
+    fn with_state<R>(&self, f: impl FnOnce(&mut SharedState<D>) -> R) -> Option<R> {
         free(|cs| {
-            let mut state = self.borrow(cs).borrow_mut();
-            let state: &mut _ = state.deref_mut().as_mut().unwrap();
-
-            state.now()
+            let mut state = self.borrow(cs).try_borrow_mut().ok()?;
+            state.deref_mut().as_mut().map(f)
         })
     }
+}
 
-    fn get(&self) -> (NaiveDateTime, u32, f64, f64) {
-        free(|cs| {
-            let mut state = self.borrow(cs).borrow_mut();
-            let state: &mut _ = state.deref_mut().as_mut().unwrap();
+impl<D: DateTimeAccess> State for Mutex<RefCell<Option<SharedState<D>>>> {
+    fn try_now(&self) -> Option<NaiveDateTime> {
+        self.with_state(|s| s.now())
+    }
 
-            state.get()
-        })
+    fn try_get(&self) -> Option<(NaiveDateTime, u32, f64, f64)> {
+        self.with_state(|s| s.get())
     }
 }
 
-#[derive(Clone)]
+/// Dilution of precision of the current position (bits of `f32`), `0` if unknown. Set together with
+/// the position in `SharedState` by `Location::check_retrieve`, and recorded in the packages.
+static POSITION_DOP: AtomicU32 = AtomicU32::new(0);
+
+/// Dilution of precision of the current position, `0` if unknown.
+pub fn position_dop() -> f32 {
+    f32::from_bits(POSITION_DOP.load(Ordering::Relaxed))
+}
+
+/// `f32::NAN`, a reading of the Notecard that is not available.
+const NAN_BITS: u32 = 0x7fc0_0000;
+
+/// Battery voltage [V] and temperature of the board [°C] measured by the Notecard (bits of
+/// `f32`), `NaN` if not read. Set by `Notecarrier::read_card` in the notecard iteration, and
+/// recorded in the packages.
+static BATTERY_V: AtomicU32 = AtomicU32::new(NAN_BITS);
+static TEMPERATURE_C: AtomicU32 = AtomicU32::new(NAN_BITS);
+
+/// The last battery voltage [V] and temperature of the board [°C] read from the Notecard.
+pub fn card_readings() -> (Option<f32>, Option<f32>) {
+    let r = |a: &AtomicU32| Some(f32::from_bits(a.load(Ordering::Relaxed))).filter(|v| !v.is_nan());
+
+    (r(&BATTERY_V), r(&TEMPERATURE_C))
+}
+
+pub fn set_card_readings(battery_v: Option<f32>, temperature_c: Option<f32>) {
+    let bits = |v: Option<f32>| v.map_or(NAN_BITS, f32::to_bits);
+
+    BATTERY_V.store(bits(battery_v), Ordering::Relaxed);
+    TEMPERATURE_C.store(bits(temperature_c), Ordering::Relaxed);
+}
+
+/// Interval before the next attempt at the location after `failures` consecutive failed attempts
+/// [ms]: `interval` doubles with every failure after the first, up to `max`. A `max` of `0`
+/// disables the backoff.
+pub fn location_backoff(interval: i64, failures: u32, max: i64) -> i64 {
+    if max == 0 || failures <= 1 {
+        return interval;
+    }
+
+    interval
+        .saturating_mul(1 << (failures - 1).min(32))
+        .min(max)
+        .max(interval)
+}
+
+/// State of the location, with the time of the last attempt [ms]. The state is debounced: it
+/// changes to `Retrieved` after `location_fixes` consecutive attempts with both time and position,
+/// and back to `Trying` after `location_failures` consecutive attempts without. While `Trying` the
+/// attempts back off after failures (see [`location_backoff`]).
+#[derive(Clone, Debug, PartialEq)]
 pub enum LocationState {
     Trying(i64),
     Retrieved(i64),
@@ -147,20 +375,79 @@ pub struct Location {
     pub position_time: u32,
     pub time: u32,
 
+    /// Dilution of precision of the last fix, `0` if unknown.
+    pub dop: f32,
+
+    /// Satellites and GPS state at the last location response.
+    pub gps: gnss::Status,
+
+    /// Fixes with a dilution of precision above this are not used, `0` disables.
+    pub max_dop: f32,
+
     pub state: LocationState,
+
+    /// Consecutive good and failed attempts.
+    pub fixes: u32,
+    pub failures: u32,
+
+    /// Consecutive good attempts to enter `Retrieved`, and failed attempts to leave it.
+    pub fixes_required: u32,
+    pub failures_tolerated: u32,
+
+    /// Interval between retrieving location and time [ms].
+    pub interval: i64,
+
+    /// Longest interval between the attempts while `Trying` [ms], `0` disables the backoff (see
+    /// [`location_backoff`]).
+    pub backoff: i64,
+
+    /// Average of the last fixes, for moored buoys.
+    pub average: fix_average::FixAverage,
+
+    /// Checks the rate of the RTC against the Notecard time.
+    pub clock: clock::ClockMonitor,
+
+    /// Changes of state, RTC updates and fixes, waiting to be written to the SD-card (see
+    /// `location_log`).
+    pub events: location_log::EventLog,
+
+    /// Warm-up of the GPS after power-on [ms], see `Config::warmup`.
+    pub warmup: i64,
+
+    /// Fixes are discarded until this time of the RTC [ms], moved along when the RTC is set.
+    pub warmup_until: i64,
 }
 
 impl Location {
-    pub fn new() -> Location {
+    pub fn new(config: &config::Config) -> Location {
         Location {
             lat: 0.0,
             lon: 0.0,
             position_time: 0,
             time: 0,
+            dop: 0.0,
+            gps: gnss::Status::default(),
+            max_dop: config.max_dop,
             state: LocationState::Trying(-999),
+            fixes: 0,
+            failures: 0,
+            fixes_required: config.location_fixes,
+            failures_tolerated: config.location_failures,
+            interval: config.location_interval as i64 * 1000,
+            backoff: config.location_backoff as i64 * 1000,
+            average: fix_average::FixAverage::new(config.position_average as usize),
+            clock: clock::ClockMonitor::new(),
+            events: location_log::EventLog::new(),
+            warmup: config.warmup as i64 * 1000,
+            warmup_until: 0,
         }
     }
 
+    /// Start the warm-up of the GPS at `now` [ms], before the first location is retrieved.
+    pub fn start_warmup(&mut self, now: i64) {
+        self.warmup_until = now.saturating_add(self.warmup);
+    }
+
     /// Get latest time and position.
     ///
     /// > NOTE: This function is called very frequently and should not communicate with the
@@ -171,94 +458,441 @@ impl Location {
         delay: &mut impl DelayMs<u16>,
         note: &mut note::Notecarrier<T>,
     ) -> Result<(), notecard::NoteError> {
-        use notecard::card::res::{Location, Time};
         use LocationState::*;
 
-        const LOCATION_DIFF: i64 = 1 * 60_000; // [ms]: 1 minute
-
-        let now = state.now().timestamp_millis();
+        let now = state.now_millis();
 
+        // The location is refreshed at the interval once retrieved, the backoff only applies to
+        // the attempts at getting it.
         match self.state {
-            Retrieved(t) | Trying(t) if (now - t) > LOCATION_DIFF => {
-                let gps = note.card().location(delay)?.wait(delay)?;
-                let tm = note.card().time(delay)?.wait(delay);
-
-                info!("Location: {:?}, Time: {:?}", gps, tm);
-
-                if let Ok(Time {
-                    time: Some(time), ..
-                }) = tm
-                {
-                    info!("Got time, setting RTC.");
-                    let dt = NaiveDateTime::from_timestamp_opt(time as i64, 0)
-                        .ok_or_else(|| notecard::NoteError::NotecardErr("Bad time".into()))?;
-                    self.time = time;
-
-                    free(|cs| {
-                        let mut state = state.borrow(cs).borrow_mut();
-                        let state: &mut _ = state.deref_mut().as_mut().unwrap();
-
-                        state.rtc.set_datetime(&dt).ok();
-                    });
+            Retrieved(t) if (now - t) > self.interval => {
+                self.retrieve(now, state, delay, note)?;
+            }
+            Trying(t) if (now - t) > self.retry_interval() => {
+                self.retrieve(now, state, delay, note)?;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Interval before the next attempt while `Trying` [ms], backed off after failed attempts.
+    pub fn retry_interval(&self) -> i64 {
+        location_backoff(self.interval, self.failures, self.backoff)
+    }
+
+    /// Retrieve time and position now, regardless of the interval (the `locate` command). Returns
+    /// the fix from the Notecard, with the position in use after it, or `None` if there was no
+    /// fix.
+    pub fn force_retrieve<T: Read + Write, D: DateTimeAccess>(
+        &mut self,
+        state: &Mutex<RefCell<Option<SharedState<D>>>>,
+        delay: &mut impl DelayMs<u16>,
+        note: &mut note::Notecarrier<T>,
+    ) -> Result<Option<cmd::Fix>, notecard::NoteError> {
+        crate::clog!(Location, info, "Fix requested, retrieving location now.");
+
+        let now = state.now_millis();
+        let fix = self.retrieve(now, state, delay, note)?;
+
+        crate::clog!(Location, info, "Requested fix: {:?}", fix);
+
+        Ok(fix)
+    }
+
+    /// Request time and location from the Notecard, set the RTC and the position.
+    fn retrieve<T: Read + Write, D: DateTimeAccess>(
+        &mut self,
+        now: i64,
+        state: &Mutex<RefCell<Option<SharedState<D>>>>,
+        delay: &mut impl DelayMs<u16>,
+        note: &mut note::Notecarrier<T>,
+    ) -> Result<Option<cmd::Fix>, notecard::NoteError> {
+        use note::WaitTimeout;
+        use notecard::card::res::{Location, Time};
+
+        let timeouts = note.config().timeouts;
+        let gps = match note
+            .card()
+            .location(delay)?
+            .wait_for(delay, timeouts.location)
+        {
+            Ok(gps) => gps,
+            Err(notecard::NoteError::TimeOut) => {
+                // Weak signal, not a problem with the Notecard: try again at the next
+                // interval.
+                crate::clog!(
+                    Location,
+                    warn,
+                    "Location request timed out, retrying later."
+                );
+                self.transition(false, now);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let tm = note.card().time(delay)?.wait_for(delay, timeouts.time);
+
+        crate::clog!(Location, info, "Location: {:?}, Time: {:?}", gps, tm);
+
+        if let Ok(Time {
+            time: Some(time), ..
+        }) = tm
+        {
+            crate::clog!(Location, info, "Got time, setting RTC.");
+            let dt = NaiveDateTime::from_timestamp_opt(time as i64, 0)
+                .ok_or_else(|| notecard::NoteError::NotecardErr("Bad time".into()))?;
+            self.time = time;
+
+            let after = time as i64 * 1000;
+            let clock = &mut self.clock;
+            let set = state.with_state(|state| {
+                let before = state.now_millis();
+                if !clock.check(before, after) {
+                    error!("RTC is not advancing at the expected rate.");
                 }
 
-                if let Location {
-                    lat: Some(lat),
-                    lon: Some(lon),
-                    time: Some(position_time),
-                    ..
-                } = gps
-                {
-                    info!("Got location, setting position.");
-
-                    self.lat = lat;
-                    self.lon = lon;
-                    self.position_time = position_time;
-
-                    free(|cs| {
-                        let mut state = state.borrow(cs).borrow_mut();
-                        let state: &mut _ = state.deref_mut().as_mut().unwrap();
-
-                        state.position_time = position_time;
-                        state.lat = lat;
-                        state.lon = lon;
-                    });
+                state.rtc.set_datetime(&dt).is_ok().then(|| {
+                    clock::set_time_synced(after - before);
+                    before
+                })
+            });
+
+            match set {
+                Some(Some(before)) => {
+                    // The warm-up is counted from when it started, not from the RTC.
+                    if self.warmup_until > before {
+                        self.warmup_until = self.warmup_until.saturating_add(after - before);
+                    }
+
+                    self.events
+                        .push(before, location_log::Event::RtcSet { before, after })
                 }
+                Some(None) => {}
+                None => error!("Shared state not available, RTC not set."),
+            }
+        }
 
-                if let (Ok(Time { time: Some(_), .. }), Location { lat: Some(_), .. }) = (tm, gps) {
-                    info!("Both time and location retrieved.");
-                    free(|cs| {
-                        let mut state = state.borrow(cs).borrow_mut();
-                        let state: &mut _ = state.deref_mut().as_mut().unwrap();
-                        self.state = Retrieved(state.now().timestamp_millis());
-                    });
-                } else {
-                    self.state = Trying(now);
+        let dop = gps.dop.map(|d| d as f32).unwrap_or(0.0);
+        let accurate = self.max_dop == 0.0 || dop <= self.max_dop;
+        let warm = state.now_millis() >= self.warmup_until;
+        let accepted = accurate && warm;
+
+        if !warm {
+            crate::clog!(
+                Location,
+                info,
+                "GPS warming up until: {}, discarding fix.",
+                self.warmup_until
+            );
+        }
+
+        if !accurate {
+            crate::clog!(
+                Location,
+                warn,
+                "Dilution of precision of fix too high: {} > {}, keeping position.",
+                dop,
+                self.max_dop
+            );
+        }
+
+        let mut gps_status =
+            gnss::Status::parse(&gps.status, gps.lat.is_some() && gps.lon.is_some());
+        if !accepted {
+            gps_status = gps_status.rejected();
+        }
+
+        match gps_status.fix {
+            gnss::Fix::NoSatellites => crate::clog!(
+                Location,
+                warn,
+                "No satellites visible, check the antenna and its placement."
+            ),
+            gnss::Fix::Weak => crate::clog!(
+                Location,
+                warn,
+                "Weak GPS: {:?} satellites, no usable fix.",
+                gps_status.sats
+            ),
+            _ => crate::clog!(Location, info, "GPS: {:?}", gps_status),
+        }
+
+        self.gps = gps_status;
+        if state.with_state(|state| state.gps = gps_status).is_none() {
+            error!("Shared state not available, GPS status not set.");
+        }
+
+        // The Notecard may leave out the time of the fix, or the position, in marginal
+        // conditions. A position without the time of the fix is taken to be at the time from the
+        // Notecard, or else the RTC.
+        let fix = match gps {
+            Location {
+                lat: Some(lat),
+                lon: Some(lon),
+                time,
+                ..
+            } => {
+                let position_time = time.unwrap_or_else(|| match &tm {
+                    Ok(Time { time: Some(t), .. }) => *t,
+                    _ => (state.now_millis() / 1000) as u32,
+                });
+
+                if time.is_none() {
+                    crate::clog!(
+                        Location,
+                        warn,
+                        "Location without time of fix, using: {}",
+                        position_time
+                    );
                 }
+
+                Some((lat, lon, position_time))
             }
-            _ => (),
+            Location {
+                lat: None,
+                lon: None,
+                ..
+            } => None,
+            _ => {
+                crate::clog!(Location, warn, "Location with only one of lat and lon.");
+                None
+            }
+        };
+
+        if let Some((lat, lon, position_time)) = fix {
+            self.events.push(
+                state.now_millis(),
+                location_log::Event::Fix {
+                    lat,
+                    lon,
+                    position_time,
+                    dop,
+                    accepted,
+                },
+            );
         }
 
-        Ok(())
+        if let Some((lat, lon, position_time)) = fix.filter(|_| accepted) {
+            crate::clog!(
+                Location,
+                info,
+                "Got location (dop: {}), setting position.",
+                dop
+            );
+
+            let (lat, lon) = self.average.push(lat, lon);
+            crate::clog!(
+                Location,
+                debug,
+                "Averaged position over {} fixes: {}, {}",
+                self.average.len(),
+                lat,
+                lon
+            );
+
+            self.lat = lat;
+            self.lon = lon;
+            self.position_time = position_time;
+            self.dop = dop;
+
+            let set = state.with_state(|state| {
+                state.position_time = position_time;
+                state.lat = lat;
+                state.lon = lon;
+                POSITION_DOP.store(dop.to_bits(), Ordering::Relaxed);
+            });
+
+            if set.is_none() {
+                error!("Shared state not available, position not set.");
+            }
+        }
+
+        // A partial response is used, but the location is only retrieved when both the time and
+        // a position were, after the warm-up of the GPS.
+        if let (Ok(Time { time: Some(_), .. }), Some(_)) = (tm, fix.filter(|_| warm)) {
+            crate::clog!(Location, info, "Both time and location retrieved.");
+            self.transition(true, state.now_millis());
+        } else {
+            self.transition(false, now);
+        }
+
+        Ok(fix.map(|(lat, lon, position_time)| cmd::Fix {
+            lat,
+            lon,
+            position_time,
+            dop,
+            accepted,
+            filtered_lat: self.lat,
+            filtered_lon: self.lon,
+        }))
+    }
+
+    /// Count a good or failed attempt at `now`, and change the state when enough consecutive
+    /// attempts agree.
+    fn transition(&mut self, good: bool, now: i64) {
+        use LocationState::*;
+
+        if good {
+            self.fixes = self.fixes.saturating_add(1);
+            self.failures = 0;
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            self.fixes = 0;
+        }
+
+        let retrieved = matches!(self.state, Retrieved(_));
+
+        self.state = match self.state {
+            Trying(_) if self.fixes >= self.fixes_required => {
+                crate::clog!(
+                    Location,
+                    info,
+                    "Location retrieved after {} good attempts.",
+                    self.fixes
+                );
+                Retrieved(now)
+            }
+            Retrieved(_) if self.failures >= self.failures_tolerated => {
+                crate::clog!(
+                    Location,
+                    warn,
+                    "Location lost after {} failed attempts, trying again.",
+                    self.failures
+                );
+                Trying(now)
+            }
+            Trying(_) => Trying(now),
+            Retrieved(_) => Retrieved(now),
+        };
+
+        if !good && matches!(self.state, Trying(_)) && self.retry_interval() > self.interval {
+            crate::clog!(
+                Location,
+                info,
+                "Location failed {} times in a row, next attempt in {} s.",
+                self.failures,
+                self.retry_interval() / 1000
+            );
+        }
+
+        if matches!(self.state, Retrieved(_)) != retrieved {
+            self.events.push(
+                now,
+                location_log::Event::State {
+                    retrieved: !retrieved,
+                    fixes: self.fixes,
+                    failures: self.failures,
+                },
+            );
+        }
     }
 }
 
+/// Drains the IMU FIFO and pushes full buffers to the queue.
+///
+/// `check_retrieve` is called from the `RTC` interrupt, which fires on the RTC alarm every 100 ms.
+/// The IMU keeps samples in its own FIFO (512 samples, about 2.46 s at 208 Hz), so the samples are
+/// timed by the IMU and the interrupt period only needs to be well below the time it takes to
+/// fill the FIFO. The jitter of the interrupt does not affect the sample timing, only how many
+/// samples are read at a time. The cost is that the MCU wakes up ten times per second regardless
+/// of how many samples are ready. The FIFO watermark interrupt pins of the IMU are not used.
+/// The IMU(s) read by [`Imu`].
+#[cfg(not(feature = "redundant-imu"))]
+pub type ImuWaves<I> = waves::Waves<I>;
+
+/// The IMU(s) read by [`Imu`].
+#[cfg(feature = "redundant-imu")]
+pub type ImuWaves<I> = waves::redundant::RedundantImu<I>;
+
 pub struct Imu<E: Debug + defmt::Format, I: Write<Error = E> + WriteRead<Error = E>> {
-    pub queue: heapless::spsc::Producer<'static, ImuAxlPacketT, IMUQ_SZ>,
-    waves: waves::Waves<I>,
+    /// The producer of the IMU queue (see [`split_queues`]). It is kept through resets of the IMU
+    /// (see [`Imu::reset`]), packages in flight stay in the queue.
+    queue: queue::Producer<'static, ImuAxlPacketT, IMUQ_SZ>,
+
+    /// Packages that did not fit in the full queue.
+    overflow: queue::Overflow<ImuAxlPacketT, OVERFLOW_SZ>,
+    waves: ImuWaves<I>,
     last_read: i64,
+
+    /// Running calibration capture.
+    calibration: Option<calibration::Calibration>,
+
+    /// Temperature compensation of the RTC drift.
+    drift: clock::DriftCorrection,
+
+    /// Sample pairs left of the warm-up after power-on, `None` after the warm-up. Packages are
+    /// discarded during the warm-up.
+    warmup: Option<u32>,
+
+    /// Timestamps of the packages kept monotonic, `None` when disabled (see `Config::monotonic`).
+    monotonic: Option<clock::Monotonic>,
 }
 
 impl<E: Debug + defmt::Format, I: Write<Error = E> + WriteRead<Error = E>> Imu<E, I> {
+    /// Read from `waves`, discarding the samples of the first `warmup` seconds (see
+    /// `Config::warmup`). Packages that do not fit in the full queue are handled by `queue_full`,
+    /// and the timestamps are kept from going backwards with `monotonic`.
     pub fn new(
-        waves: waves::Waves<I>,
-        queue: heapless::spsc::Producer<'static, ImuAxlPacketT, IMUQ_SZ>,
+        waves: ImuWaves<I>,
+        queue: queue::Producer<'static, ImuAxlPacketT, IMUQ_SZ>,
+        drift: clock::DriftCorrection,
+        warmup: u16,
+        queue_full: queue::QueueFull,
+        monotonic: bool,
     ) -> Imu<E, I> {
         Imu {
             queue,
+            overflow: queue::Overflow::new(queue_full),
             waves,
             last_read: 0,
+            calibration: None,
+            drift,
+            warmup: (warmup > 0).then(|| (warmup as f32 * waves::FREQ.value()) as u32),
+            monotonic: monotonic.then(clock::Monotonic::new),
+        }
+    }
+
+    /// The samples are discarded while warming up after power-on.
+    pub fn warming_up(&self) -> bool {
+        self.warmup.is_some()
+    }
+
+    /// End the warm-up once its samples have been read: the samples in the buffer and the FIFO
+    /// are discarded so that the first package starts clean. Does nothing otherwise.
+    pub fn check_warmup(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), waves::ImuError<E>> {
+        if self.warmup != Some(0) {
+            return Ok(());
         }
+
+        // Sampling goes on after a failure here, the IMU is reset if it keeps failing.
+        self.warmup = None;
+
+        self.waves.discard(delay)?;
+        self.waves.take_buf(now, position_time, lon, lat)?; // buf is empty, this sets time and FIFO offset.
+        self.last_read = now; // prevent TooFewSamples to be triggered.
+
+        log::log_at(
+            log::Category::Imu,
+            log::Level::Info,
+            "IMU warm-up complete, samples are kept from now on.",
+        );
+
+        Ok(())
+    }
+
+    /// Time of the RTC time `rtc_now` [ms] corrected for the temperature drift of the RTC, using
+    /// the last temperature of the IMU.
+    pub fn correct_time(&mut self, rtc_now: i64) -> i64 {
+        self.drift.correct(rtc_now, self.waves.temperature)
     }
 
     /// Read samples and check for full buffers. Return number of sample pairs consumed from IMU.
@@ -269,28 +903,43 @@ impl<E: Debug + defmt::Format, I: Write<Error = E> + WriteRead<Error = E>> Imu<E
         lon: f64,
         lat: f64,
     ) -> Result<u32, waves::ImuError<E>> {
-        trace!("Polling IMU.. (now: {})", now,);
-
-        let mut samples = self.waves.read_and_filter()?;
-
-        if self.waves.is_full() {
-            trace!("waves buffer is full, pushing to queue..");
-            let pck = self.waves.take_buf(now, position_time, lon, lat)?;
+        crate::clog!(Imu, trace, "Polling IMU.. (now: {})", now,);
 
-            #[cfg(not(feature = "storage"))]
-            let pck = pck.0;
+        if !self.overflow.is_empty() {
+            self.overflow.drain(&mut self.queue);
+            self.update_pressure();
+        }
 
-            trace!("collect remaining samples, to avoid overrun.");
-            samples += self.waves.read_and_filter()?;
+        let mut samples = self.waves.read_and_filter()?;
+        postmortem::mark(now);
+        burst::mark(now);
+
+        if let Some(c) = self.calibration {
+            let step = c.step(now).unwrap_or(0);
+
+            if step != self.waves.calibration() {
+                samples += self.flush(now, position_time, lon, lat)?;
+                self.waves.set_calibration(step);
+
+                if step == 0 {
+                    self.calibration = None;
+                    log::log_at(
+                        log::Category::Imu,
+                        log::Level::Warn,
+                        "Calibration capture complete, returning to normal operation.",
+                    );
+                } else {
+                    crate::clog!(Imu, info, "Calibration capture: step {}.", step);
+                }
+            }
+        }
 
-            self.queue
-                .enqueue(pck)
-                .inspect_err(|_| {
-                    error!("queue is full, discarding data.");
+        if self.waves.should_flush(now) {
+            samples += self.flush(now, position_time, lon, lat)?;
+        }
 
-                    log::log("Queue is full: discarding package.");
-                })
-                .ok();
+        if let Some(left) = &mut self.warmup {
+            *left = left.saturating_sub(samples);
         }
 
         if samples == 0 {
@@ -308,181 +957,2094 @@ impl<E: Debug + defmt::Format, I: Write<Error = E> + WriteRead<Error = E>> Imu<E
         Ok(samples)
     }
 
-    pub fn reset(
+    /// Take the buffer and push it to the queue. Returns the number of sample pairs consumed from
+    /// the IMU after the buffer was taken.
+    fn flush(
         &mut self,
         now: i64,
         position_time: u32,
         lon: f64,
         lat: f64,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<(), waves::ImuError<E>> {
-        self.waves.reset(delay)?;
-        self.waves.take_buf(now, position_time, lon, lat)?; // buf is empty, this sets time and offset.
-        self.waves.enable_fifo(delay)?;
-        self.last_read = now; // prevent TooFewSamples to be triggered.
+    ) -> Result<u32, waves::ImuError<E>> {
+        crate::clog!(
+            Imu,
+            trace,
+            "flushing waves buffer ({} samples), pushing to queue..",
+            self.waves.len()
+        );
+        let pck = self.waves.take_buf(now, position_time, lon, lat)?;
 
-        Ok(())
+        crate::clog!(Imu, trace, "collect remaining samples, to avoid overrun.");
+        let samples = self.waves.read_and_filter()?;
+
+        self.enqueue(pck);
+
+        Ok(samples)
     }
-}
 
-#[cfg(feature = "storage")]
-pub struct StorageManager<Spi: Transfer<u8>, CS: OutputPin>
-where
-    <Spi as Transfer<u8>>::Error: Debug,
-{
-    storage: Storage<Spi, CS>,
-    pub storage_queue: heapless::spsc::Consumer<'static, AxlPacketT, STORAGEQ_SZ>,
-    pub note_queue: heapless::spsc::Producer<'static, AxlPacket, NOTEQ_SZ>,
-}
+    /// Push package to the queue, the package is discarded during the warm-up. When the queue is
+    /// full the package is held or a package is discarded, depending on `queue_full`.
+    fn enqueue(&mut self, mut pck: waves::AxlPacketT) {
+        if self.warmup.is_some() {
+            crate::clog!(Imu, debug, "Warming up, discarding package.");
+            return;
+        }
 
-#[cfg(feature = "storage")]
-impl<Spi: Transfer<u8>, CS: OutputPin> StorageManager<Spi, CS>
-where
-    <Spi as Transfer<u8>>::Error: Debug,
-{
-    pub fn new(
-        storage: Storage<Spi, CS>,
-        storage_queue: heapless::spsc::Consumer<'static, AxlPacketT, STORAGEQ_SZ>,
-        note_queue: heapless::spsc::Producer<'static, AxlPacket, NOTEQ_SZ>,
-    ) -> StorageManager<Spi, CS> {
-        StorageManager {
-            storage,
-            storage_queue,
-            note_queue,
+        if let Some(m) = &mut self.monotonic {
+            let p = &mut pck.0;
+            let t = m.stamp(p.timestamp, p.data.len() / p.width().max(1), p.freq);
+
+            if t != p.timestamp {
+                crate::clog!(
+                    Imu,
+                    info,
+                    "Timestamp before previous package, moving {} forward by {} ms.",
+                    p.timestamp,
+                    t - p.timestamp
+                );
+                p.timestamp = t;
+            }
         }
-    }
 
-    /// Drain data queue from IMU to SD card and queue the processed data for the notecard.
-    ///
-    /// > NOTE: This function is called very frequently and should not communicate with the Notecard.
-    pub fn drain_queue<I2C: Read + Write>(
-        &mut self,
-        _note: &mut note::Notecarrier<I2C>,
-        _delay: &mut impl DelayMs<u16>,
-    ) -> Result<Option<u32>, storage::StorageErr> {
-        let mut e: Result<Option<u32>, storage::StorageErr> = Ok(None);
+        #[cfg(not(feature = "storage"))]
+        let pck = pck.0;
 
-        if let Some(mut pck) = self.storage_queue.dequeue() {
-            defmt::info!(
-                "Storing package: {:?} (sz queue length: {})",
-                pck.0,
-                self.storage_queue.len()
-            );
-            e = self
-                .storage
-                .store(&mut pck)
-                .inspect_err(|err| {
-                    defmt::error!("Failed to save package: {}", err);
-                })
-                .map(|id| Some(id));
+        if let Some(dropped) = self.overflow.enqueue(&mut self.queue, pck) {
+            use core::fmt::Write as _;
 
-            self.note_queue
-                .enqueue(pck.0)
-                .inspect_err(|pck| {
-                    defmt::error!("queue is full, discarding data: {}", pck.data.len());
-                })
-                .ok();
+            #[cfg(feature = "storage")]
+            let dropped = dropped.0;
+
+            error!("queue is full, discarding data: {}", dropped.timestamp);
+            queue::DROPPED.record(queue::Priority::Low);
+
+            let mut msg = heapless::String::<128>::new();
+            write!(
+                &mut msg,
+                "Queue is full: discarding {} package (timestamp: {}).",
+                self.overflow.policy.dropped(),
+                dropped.timestamp
+            )
+            .ok();
+            log::log_at(log::Category::Imu, log::Level::Warn, &msg);
         }
 
-        e
+        self.update_pressure();
     }
 
-    /// XXX: Currently disabled.
-    pub fn queue_requested_packages<I2C: Read + Write>(
+    /// The storage queue is under pressure when it is almost full, or packages are held.
+    fn update_pressure(&self) {
+        #[cfg(feature = "storage")]
+        queue::STORAGE_PRESSURE.store(
+            queue::under_pressure(self.queue.len(), self.queue.capacity())
+                || !self.overflow.is_empty(),
+            core::sync::atomic::Ordering::Relaxed,
+        );
+    }
+
+    /// Read the remaining samples in the FIFO and push the partially filled buffer to the queue,
+    /// e.g. at the end of a burst or before a reset. Nothing is pushed if the buffer is empty.
+    pub fn flush_partial(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+    ) -> Result<(), waves::ImuError<E>> {
+        self.waves.read_and_filter()?;
+
+        // The remaining samples may have filled the buffer.
+        if self.waves.should_flush(now) {
+            self.flush(now, position_time, lon, lat)?;
+        }
+
+        if let Some(pck) = self.waves.flush_partial(now, position_time, lon, lat)? {
+            crate::clog!(Imu, debug, "flushed partial buffer, pushing to queue..");
+            self.enqueue(pck);
+        }
+
+        Ok(())
+    }
+
+    /// Start the calibration capture (see [`calibration`]), the current buffer is flushed first.
+    /// A running capture is restarted.
+    pub fn calibrate(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+    ) -> Result<(), waves::ImuError<E>> {
+        self.flush(now, position_time, lon, lat)?;
+
+        let c = calibration::Calibration::new(now);
+        self.calibration = Some(c);
+        self.waves.set_calibration(c.step(now).unwrap_or(0));
+
+        log::log_at(
+            log::Category::Imu,
+            log::Level::Warn,
+            "Calibration capture started: unfiltered body-frame acceleration until procedure is complete.",
+        );
+
+        Ok(())
+    }
+
+    /// Reset the IMU and the filters, e.g. to recover from a FIFO overrun. The samples in the
+    /// buffer are valid up to the failure and are pushed to the queue as a short package first,
+    /// the FIFO is not read since it may hold samples from after the overrun. The queue and the
+    /// held packages are not touched by the reset, this is also the re-initialization of the IMU
+    /// by command.
+    pub fn reset(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), waves::ImuError<E>> {
+        match self.waves.flush_partial(now, position_time, lon, lat) {
+            Ok(Some(pck)) => {
+                crate::clog!(
+                    Imu,
+                    warn,
+                    "flushed short package before reset, pushing to queue.."
+                );
+                self.enqueue(pck);
+            }
+            Ok(None) => (),
+            Err(e) => {
+                error!("failed to flush buffer before reset, discarding: {:?}", e);
+            }
+        }
+
+        self.waves.reset(delay)?;
+        self.waves.take_buf(now, position_time, lon, lat)?; // buf is empty, this sets time and FIFO offset.
+        self.waves
+            .start_fifo(delay)
+            .map_err(waves::ImuError::FifoEnable)?;
+        self.last_read = now; // prevent TooFewSamples to be triggered.
+
+        Ok(())
+    }
+
+    /// Read the configuration and status registers of the IMU, for diagnosing failures.
+    pub fn dump_registers(&mut self) -> Result<waves::Registers, E> {
+        self.waves.dump_registers()
+    }
+
+    /// Power down the IMU before a sleep window. The partially filled buffer is flushed first
+    /// (see [`Imu::flush_partial`]).
+    pub fn sleep(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+    ) -> Result<(), waves::ImuError<E>> {
+        self.flush_partial(now, position_time, lon, lat)?;
+        self.waves.power_down()?;
+
+        Ok(())
+    }
+
+    /// Power up the IMU after a sleep window, see [`waves::Waves::power_up`].
+    pub fn wake(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), waves::ImuError<E>> {
+        self.waves.power_up(delay)?;
+        self.waves.take_buf(now, position_time, lon, lat)?; // buf is empty, this sets time and FIFO offset.
+        self.last_read = now; // prevent TooFewSamples to be triggered.
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "storage")]
+pub struct StorageManager<S: storage::StorageBackend> {
+    storage: S,
+    pub storage_queue: queue::Consumer<'static, AxlPacketT, STORAGEQ_SZ>,
+    pub note_queue: queue::Producer<'static, AxlPacket, NOTEQ_SZ>,
+
+    /// Packages that did not fit in the full notecard queue (see `queue::QueueFull`).
+    overflow: queue::Overflow<AxlPacket, NOTE_OVERFLOW_SZ>,
+
+    /// Packages are only forwarded to the notecard when the free space on the card is below this
+    /// (bytes).
+    pub min_free_space: u64,
+    low_space: bool,
+
+    /// Remove the oldest collections to make room, rather than stop storing (see
+    /// `storage::ring`).
+    pub ring_buffer: bool,
+
+    /// What gives way when the storage queue is under pressure (see `queue::QueuePolicy`).
+    pub queue_policy: queue::QueuePolicy,
+
+    /// Packages not written to the SD-card because of the queue policy, since the last warning.
+    unstored: u32,
+
+    /// Last storage ID written (or restored from snapshot).
+    last_id: Option<u32>,
+
+    /// Maximum number of stored packages queued per replay.
+    pub replay_batch: u32,
+
+    /// Encoding of the batches of replayed packages.
+    pub compression: backfill::Compression,
+
+    /// Maximum size of the payload of a batch of replayed packages [bytes].
+    pub max_note_size: u32,
+
+    /// Request being replayed.
+    request: Option<ReplayRequest>,
+
+    /// Errors of the card, re-initialized after repeated errors (see `storage::recovery`).
+    recovery: storage::recovery::Recovery,
+}
+
+/// Share of the notecard queue that may be filled with replayed packages, the rest is kept for
+/// live packages.
+#[cfg(feature = "storage")]
+pub const REPLAY_SHARE: usize = NOTEQ_SZ / 2;
+
+/// A request for stored packages, and when it was first seen.
+#[cfg(feature = "storage")]
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub struct ReplayRequest {
+    pub start: u32,
+    pub end: u32,
+
+    /// Time the request was first seen [ms].
+    pub since: i64,
+}
+
+/// Update of the range of requested packages that have been sent (the `storage-info` note).
+#[cfg(feature = "storage")]
+#[derive(Debug, defmt::Format, PartialEq)]
+pub struct ReplayUpdate {
+    pub sent_id: Option<u32>,
+    pub clear_request: bool,
+}
+
+#[cfg(feature = "storage")]
+impl<S: storage::StorageBackend> StorageManager<S> {
+    pub fn new(
+        mut storage: S,
+        storage_queue: queue::Consumer<'static, AxlPacketT, STORAGEQ_SZ>,
+        note_queue: queue::Producer<'static, AxlPacket, NOTEQ_SZ>,
+        config: &config::Config,
+    ) -> StorageManager<S> {
+        storage.set_day_files(config.day_files);
+        storage.set_sample_encoding(config.sample_encoding);
+        storage.set_verify_writes(config.verify_writes);
+
+        StorageManager {
+            storage,
+            storage_queue,
+            note_queue,
+            overflow: queue::Overflow::new(config.queue_full),
+            min_free_space: config.min_free_space,
+            low_space: false,
+            ring_buffer: config.ring_buffer,
+            queue_policy: config.queue_policy,
+            unstored: 0,
+            last_id: None,
+            replay_batch: config.replay_batch,
+            compression: config.backfill_compression,
+            max_note_size: config.max_note_size,
+            request: None,
+            recovery: storage::recovery::Recovery::new(),
+        }
+    }
+
+    /// Restore state from snapshot taken before reboot.
+    pub fn restore(&mut self, s: &storage::snapshot::Snapshot) {
+        self.last_id = s.storage_id;
+        dedup::ACKED.ack(s.acked_id);
+
+        if let (Some(last), Some(next)) = (s.storage_id, self.storage.next_id()) {
+            if next <= last {
+                defmt::error!(
+                    "Next storage ID ({}) is not greater than last ID before reboot ({}).",
+                    next,
+                    last
+                );
+            }
+        }
+    }
+
+    /// Write a snapshot of the current state to the SD-card.
+    pub fn write_snapshot(&mut self, state: &impl State) -> Result<(), storage::StorageErr> {
+        let (now, position_time, lat, lon) = state.get();
+
+        let s = storage::snapshot::Snapshot {
+            timestamp: now.timestamp_millis(),
+            position_time,
+            lon,
+            lat,
+            storage_id: self.last_id,
+            acked_id: dedup::ACKED.get(),
+        };
+
+        defmt::debug!("Writing snapshot: {:?}", s);
+        self.storage.write_snapshot(&s)
+    }
+
+    /// Shut down storage before a planned sleep or reset: the storage queue is drained to the
+    /// card (and forwarded to the notecard queue), the snapshot is written, and then the card is
+    /// released (see [`storage::Storage::shutdown`]). The event log is written along with the
+    /// snapshot. The snapshot must be written before the card is released, the next access
+    /// initializes the card again. Drain the notecard queue and shut down the notecard afterwards
+    /// (see [`note::Notecarrier::shutdown`]).
+    pub fn shutdown(&mut self, state: &impl State) -> Result<(), storage::StorageErr> {
+        defmt::info!("Shutting down storage..");
+
+        while self.storage_queue.len() > 0 {
+            self.drain_logged("shutdown");
+        }
+
+        self.drain_event_log()
+            .inspect_err(|e| defmt::error!("Failed to write event log: {:?}", e))
+            .ok();
+
+        let r = self.write_snapshot(state);
+        self.storage.shutdown();
+
+        r
+    }
+
+    /// Drain the storage queue to the card (and forward the packages to the notecard queue) until
+    /// it is empty or `deadline` [ms] passes, at the time given by `clock`. Returns whether the
+    /// queue was emptied. Without a clock the deadline can not be checked, and only the packages
+    /// already queued are drained.
+    pub fn drain_until(&mut self, clock: impl Fn() -> Option<i64>, deadline: i64) -> bool {
+        let mut queued = self.storage_queue.len();
+
+        while self.storage_queue.len() > 0 {
+            match clock() {
+                Some(now) if now >= deadline => return false,
+                None if queued == 0 => return false,
+                _ => {}
+            }
+
+            queued = queued.saturating_sub(1);
+            self.drain_logged("flush");
+        }
+
+        true
+    }
+
+    /// Drain one package of the storage queue for `what` (e.g. a replay), logging when it could
+    /// not be stored. The package is forwarded to the notecard queue all the same, and the card
+    /// is re-initialized or given up after repeated errors (see `storage::recovery`).
+    fn drain_logged(&mut self, what: &str) {
+        if let Err(e) = self.drain_queue() {
+            use core::fmt::Write as _;
+
+            let mut msg = heapless::String::<128>::new();
+            write!(&mut msg, "Failed to store package during {}: {:?}", what, e).ok();
+            log::log_at(log::Category::Storage, log::Level::Error, &msg);
+        }
+    }
+
+    /// Flush the packages on their way out before the buoy is powered down, e.g. on low battery:
+    /// the storage queue is drained to the card, and the packages are sent to the notecard, until
+    /// both queues are empty or `timeout` [ms] has passed. Returns whether the queues were emptied.
+    ///
+    /// Push the partial buffer of the IMU into the queue first (see [`Imu::flush_partial`]). When
+    /// the card has failed the packages are forwarded straight to the notecard queue. Without
+    /// storage use [`note::Notecarrier::flush_queue`].
+    pub fn flush<I2C: Read + Write>(
         &mut self,
         note: &mut note::Notecarrier<I2C>,
+        queue: &mut queue::Consumer<'static, AxlPacket, NOTEQ_SZ>,
         delay: &mut impl DelayMs<u16>,
+        timeout: u32,
+    ) -> bool {
+        // Without a clock the queues are drained once.
+        let deadline = note.now_millis().map_or(0, |now| now + timeout as i64);
+
+        defmt::info!("Flushing queues (timeout: {} ms)..", timeout);
+
+        loop {
+            let stored = self.drain_until(|| note.now_millis(), deadline);
+            let sent = note
+                .flush_queue(queue, delay, deadline)
+                .inspect_err(|e| defmt::error!("Failed to flush notecard queue: {:?}", e))
+                .unwrap_or(false);
+
+            // Held packages are queued once the notecard queue has room.
+            self.overflow.drain(&mut self.note_queue);
+
+            if stored && sent && self.overflow.is_empty() && !queue.ready() {
+                return true;
+            }
+
+            if note.now_millis().map_or(true, |now| now >= deadline) {
+                defmt::warn!(
+                    "Deadline passed while flushing: storage queue sz: {}, note queue sz: {}",
+                    self.storage_queue.len(),
+                    queue.len()
+                );
+                return false;
+            }
+        }
+    }
+
+    /// The notecard queue is full, or packages are held until there is room.
+    fn note_full(&self) -> bool {
+        !self.note_queue.ready() || !self.overflow.is_empty()
+    }
+
+    /// Count the outcome of a package write or read at `now` [ms], and re-initialize the card
+    /// after repeated errors (see `storage::recovery`).
+    fn check_access<T>(&mut self, r: &Result<T, storage::StorageErr>, now: i64) {
+        use core::fmt::Write as _;
+        use storage::recovery::{Action, MAX_REINITS};
+
+        let e = match r {
+            Ok(_) => {
+                self.recovery.ok();
+                return;
+            }
+            Err(e) => e,
+        };
+
+        let mut msg = heapless::String::<128>::new();
+
+        match self.recovery.error(e, now) {
+            Action::Continue => {}
+            Action::Reinit => {
+                write!(
+                    &mut msg,
+                    "SD-card keeps failing ({:?}), re-initializing it ({} of {}).",
+                    e, self.recovery.reinits, MAX_REINITS
+                )
+                .ok();
+                log::log_at(log::Category::Storage, log::Level::Warn, &msg);
+
+                self.storage.reinit();
+            }
+            Action::GiveUp => {
+                write!(
+                    &mut msg,
+                    "SD-card does not recover, packages are only sent until reset."
+                )
+                .ok();
+                log::log_at(log::Category::Storage, log::Level::Error, &msg);
+            }
+        }
+    }
+
+    /// Write the sync history to the SD-card.
+    pub fn write_sync_history(
+        &mut self,
+        h: &sync_history::SyncHistory,
     ) -> Result<(), storage::StorageErr> {
-        // Send additional requested packages from SD-card.
-        if let Some(next_id) = self.storage.next_id() {
-            if let Ok((
-                Some(note::StorageIdInfo { sent_id }),
-                Some(note::RequestData {
-                    request_start: Some(request_start),
-                    request_end: Some(request_end),
-                }),
-            )) = note.read_storage_info(delay)
-            {
-                let sent_id = sent_id.unwrap_or(request_start);
-                let request_end = request_end.min(next_id.saturating_sub(1));
-
-                if sent_id < request_end {
-                    defmt::info!("Request, sending range: {} -> {}", sent_id, request_end);
-                    for id in (sent_id..=request_end).take(100) {
-                        let pck = self.storage.get(id);
-
-                        defmt::debug!("Sending stored package: {:?}", pck);
-
-                        match pck {
-                            Ok(pck) => {
-                                match self.note_queue.enqueue(pck) {
-                                    Ok(_) => {
-                                        // Update range of sent packages.
-                                        note.write_storage_info(
-                                            delay,
-                                            Some(id),
-                                            if id >= request_end { true } else { false },
-                                        )
-                                        .inspect_err(|e| {
-                                            defmt::error!("Failed to set storageinfo: {:?}", e)
-                                        })
-                                        .ok();
-                                    }
-                                    Err(_) => {
-                                        defmt::trace!(
-                                            "Notecard queue is full, not adding more packages."
-                                        );
-                                        break;
-                                    } // queue is full.
-                                }
-                            }
-                            Err(storage::StorageErr::GenericSdMmmcErr(
-                                embedded_sdmmc::Error::FileNotFound,
-                            )) => {
-                                let new_id = ((id / storage::COLLECTION_SIZE) + 1)
-                                    * storage::COLLECTION_SIZE;
-
-                                defmt::debug!(
-                                    "File does not exist, advancing range by full collection: {} -> {}.",
-                                    id, new_id
-                                );
-
-                                note.write_storage_info(
-                                    delay,
-                                    Some(new_id),
-                                    if new_id >= request_end { true } else { false },
-                                )
-                                .inspect_err(|e| {
-                                    defmt::error!("Failed to set storageinfo: {:?}", e)
-                                })
-                                .ok();
-
-                                break;
-                            }
-                            Err(e) => {
-                                defmt::error!(
-                                    "Failed to read from SD-card: {:?}, clearing request.",
-                                    e
-                                );
-                                note.write_storage_info(delay, None, true)
-                                    .inspect_err(|e| {
-                                        defmt::error!("Failed to set storageinfo: {:?}", e)
-                                    })
-                                    .ok();
-                                return Err(e);
-                            }
-                        }
+        self.storage.write_sync_history(h)
+    }
+
+    /// Write the reboot counters to the SD-card.
+    pub fn write_reboots(&mut self, r: &reboots::Reboots) -> Result<(), storage::StorageErr> {
+        self.storage.write_reboots(r)
+    }
+
+    /// Write the start of the deployment to the SD-card.
+    pub fn write_deployment(
+        &mut self,
+        d: &deployment::Deployment,
+    ) -> Result<(), storage::StorageErr> {
+        self.storage.write_deployment(d)
+    }
+
+    /// Write the safe mode record to the SD-card.
+    pub fn write_safe_mode(&mut self, s: &safe_mode::SafeMode) -> Result<(), storage::StorageErr> {
+        self.storage.write_safe_mode(s)
+    }
+
+    /// Append a health record to the SD-card.
+    pub fn append_health(&mut self, h: &health::Health) -> Result<(), storage::StorageErr> {
+        self.storage.append_health(h)
+    }
+
+    /// Append the records of the location log to the SD-card (see `location_log`). The records
+    /// are removed from the log once they are written, and kept for the next time otherwise.
+    pub fn append_location_events(
+        &mut self,
+        log: &mut location_log::EventLog,
+    ) -> Result<(), storage::StorageErr> {
+        while let Some(r) = log.peek() {
+            self.storage.append_location_event(r)?;
+            log.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Append a burst around an extreme event to the SD-card (see `burst`).
+    pub fn append_burst(&mut self, b: &burst::Capture) -> Result<(), storage::StorageErr> {
+        self.storage.append_burst(b)
+    }
+
+    /// Append a line to the setup log on the SD-card (see `storage::SETUP_LOG_FILE`).
+    pub fn append_setup_log(&mut self, msg: &str) -> Result<(), storage::StorageErr> {
+        self.storage.append_setup_log(msg)
+    }
+
+    /// Append the lines waiting for the event log to the SD-card (see `log`). The lines that
+    /// could not be written are kept for the next time.
+    pub fn drain_event_log(&mut self) -> Result<(), storage::StorageErr> {
+        log::drain_event_log(|line| self.storage.append_event_log(line))
+    }
+
+    /// Estimated free space on the SD-card (bytes), `None` if the card is not ready.
+    pub fn free_space(&self) -> Option<u64> {
+        self.storage.free_space()
+    }
+
+    /// Check the SD-card for the self-test at boot: write and read back a test file with `token`
+    /// (e.g. the time), and compare the free space against `min_free_space`.
+    pub fn check_sd(&mut self, token: u32) -> bist::SdCheck {
+        let write = self
+            .storage
+            .write_test(token)
+            .inspect_err(|e| defmt::error!("BIST: SD-card write test failed: {:?}", e))
+            .is_ok();
+
+        bist::SdCheck {
+            write,
+            free_space: self.storage.free_space(),
+            min_free_space: self.min_free_space,
+        }
+    }
+
+    /// Check estimated free space on card against `min_free_space`, warns once when it drops
+    /// below. With `ring_buffer` the oldest collections are removed first, see
+    /// [`StorageManager::reclaim`].
+    fn check_free_space(&mut self) -> bool {
+        use core::fmt::Write as _;
+
+        if self.ring_buffer {
+            self.reclaim();
+        }
+
+        match self.storage.free_space() {
+            Some(free) if free < self.min_free_space => {
+                if !self.low_space {
+                    let mut msg = heapless::String::<256>::new();
+                    write!(
+                        &mut msg,
+                        "Low free space on SD-card: {} bytes (minimum: {}), not storing packages.",
+                        free, self.min_free_space
+                    )
+                    .ok();
+                    log::log_at(log::Category::Storage, log::Level::Warn, &msg);
+                }
+
+                self.low_space = true;
+                false
+            }
+            _ => {
+                self.low_space = false;
+                true
+            }
+        }
+    }
+
+    /// Remove the oldest collections while the free space is below `min_free_space`, at most
+    /// `ring::MAX_REMOVED` at a time (see `storage::ring`).
+    fn reclaim(&mut self) {
+        use core::fmt::Write as _;
+
+        for _ in 0..storage::ring::MAX_REMOVED {
+            match self.storage.free_space() {
+                Some(free) if free < self.min_free_space => {}
+                _ => return,
+            }
+
+            match self.storage.remove_oldest() {
+                Ok(Some(c)) => {
+                    let mut msg = heapless::String::<256>::new();
+                    write!(
+                        &mut msg,
+                        "Low free space on SD-card, removed oldest collection: {}.",
+                        c
+                    )
+                    .ok();
+                    log::log_at(log::Category::Storage, log::Level::Warn, &msg);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    defmt::error!("Failed to remove oldest collection: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Drain data queue from IMU to SD card and queue the processed data for the notecard. While
+    /// the storage queue is under pressure the queue policy may forward the package without
+    /// storing it (see `queue::QueuePolicy`), unless the notecard is paused by its outbound queue
+    /// (see `queue::CardQueue`).
+    ///
+    /// > NOTE: This function is called very frequently and should not communicate with the Notecard.
+    pub fn drain_queue(&mut self) -> Result<Option<u32>, storage::StorageErr> {
+        let mut e: Result<Option<u32>, storage::StorageErr> = Ok(None);
+
+        let pressure =
+            queue::under_pressure(self.storage_queue.len(), self.storage_queue.capacity());
+
+        // Held packages first.
+        self.overflow.drain(&mut self.note_queue);
+
+        if let Some(mut pck) = self.storage_queue.dequeue() {
+            defmt::info!(
+                "Storing package: {:?} (sz queue length: {})",
+                pck.0,
+                self.storage_queue.len()
+            );
+
+            let card_pressure = queue::CARD_PRESSURE.load(Ordering::Relaxed);
+            let store = self
+                .queue_policy
+                .store(pressure, self.note_full() || card_pressure);
+            if !store {
+                self.unstored += 1;
+            } else if self.unstored > 0 {
+                use core::fmt::Write as _;
+
+                let mut msg = heapless::String::<256>::new();
+                write!(
+                    &mut msg,
+                    "Storage queue under pressure: {} packages not stored on SD-card.",
+                    self.unstored
+                )
+                .ok();
+                log::log_at(log::Category::Storage, log::Level::Warn, &msg);
+                self.unstored = 0;
+            }
+
+            if store && !self.recovery.failed && self.check_free_space() {
+                let r = self.storage.store(&mut pck);
+                self.check_access(&r, pck.0.timestamp);
+
+                e = match r {
+                    Ok(id) => {
+                        self.last_id = Some(id);
+                        Ok(Some(id))
                     }
-                } else {
-                    // Request done, clearing.
-                    defmt::info!("Request complete, deleting request.");
-                    note.write_storage_info(delay, None, true)
-                        .inspect_err(|e| defmt::error!("Failed to set storageinfo: {:?}", e))
+                    // The package could not be serialized, the card is fine: the package is
+                    // dropped from storage, and still sent.
+                    Err(storage::StorageErr::SerializationError) => {
+                        use core::fmt::Write as _;
+
+                        let mut msg = heapless::String::<256>::new();
+                        write!(
+                            &mut msg,
+                            "Package not stored on SD-card, serialization failed (timestamp: {}).",
+                            pck.0.timestamp
+                        )
                         .ok();
-                }
+                        log::log_at(log::Category::Storage, log::Level::Error, &msg);
+
+                        Ok(None)
+                    }
+                    Err(err) => {
+                        defmt::error!("Failed to save package: {}", err);
+                        Err(err)
+                    }
+                };
+            }
+
+            // The gyroscope samples are only stored on the SD-card, also in sealed notes.
+            pck.0.gyro.clear();
+
+            if let Some(dropped) = self.overflow.enqueue(&mut self.note_queue, pck.0) {
+                use core::fmt::Write as _;
+
+                defmt::error!(
+                    "queue is full, discarding data: {:?} ({})",
+                    dropped.storage_id,
+                    dropped.timestamp
+                );
+                queue::DROPPED.record(queue::Priority::Low);
+
+                let mut msg = heapless::String::<128>::new();
+                write!(
+                    &mut msg,
+                    "Notecard queue is full: discarding {} package (ID: {:?}, timestamp: {}).",
+                    self.overflow.policy.dropped(),
+                    dropped.storage_id,
+                    dropped.timestamp
+                )
+                .ok();
+                log::log_at(log::Category::Storage, log::Level::Info, &msg);
+            }
+        }
+
+        queue::STORAGE_PRESSURE.store(
+            queue::under_pressure(self.storage_queue.len(), self.storage_queue.capacity()),
+            core::sync::atomic::Ordering::Relaxed,
+        );
+
+        e
+    }
+
+    /// Serve the request for stored packages on the notecard (`request-data` in `storage.db`),
+    /// if any, and record the range that has been sent. Called in the notecard iteration of the
+    /// main loop.
+    pub fn queue_requested_packages<I2C: Read + Write>(
+        &mut self,
+        now: i64,
+        note: &mut note::Notecarrier<I2C>,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), storage::StorageErr> {
+        if self.storage.next_id().is_none() {
+            return Ok(());
+        }
+
+        // Send additional requested packages from SD-card. The sent range is not recorded until
+        // the first part of a request has been sent.
+        if let Ok((
+            info,
+            Some(note::RequestData {
+                request_start: Some(request_start),
+                request_end: Some(request_end),
+            }),
+        )) = note.read_storage_info(delay)
+        {
+            #[cfg(not(feature = "encryption"))]
+            let send = |batch: &backfill::Batch| note.send_backfill(batch, delay).map(|_| ());
+
+            #[cfg(feature = "encryption")]
+            let send = |_: &backfill::Batch| Ok(());
+
+            let sent_id = info.and_then(|i| i.sent_id);
+            let r = self.serve_request(now, sent_id, request_start, request_end, send);
+
+            let update = match &r {
+                Ok(update) => update.as_ref(),
+                Err(_) => Some(&ReplayUpdate {
+                    sent_id: None,
+                    clear_request: true,
+                }),
+            };
+
+            if let Some(u) = update {
+                note.write_storage_info(delay, u.sent_id, u.clear_request)
+                    .inspect_err(|e| defmt::error!("Failed to set storageinfo: {:?}", e))
+                    .ok();
             }
+
+            r?;
         }
 
         Ok(())
     }
+
+    /// Serve a request for the stored packages from `sent_id` (or `request_start`) up to
+    /// `request_end`: as compressed batches to the backfill notefile with `send`, or with the
+    /// `encryption` feature replayed as sealed data notes (see `replay`). Returns the update of
+    /// the sent range, if any.
+    pub fn serve_request(
+        &mut self,
+        now: i64,
+        sent_id: Option<u32>,
+        request_start: u32,
+        request_end: u32,
+        send: impl FnOnce(&backfill::Batch) -> Result<(), notecard::NoteError>,
+    ) -> Result<Option<ReplayUpdate>, storage::StorageErr> {
+        #[cfg(not(feature = "encryption"))]
+        {
+            self.send_backfill(now, send, sent_id, request_start, request_end)
+        }
+
+        #[cfg(feature = "encryption")]
+        {
+            let _ = send;
+            self.replay(now, sent_id, request_start, request_end)
+        }
+    }
+
+    /// Queue stored packages from `sent_id` (or `request_start`) up to `request_end` for the
+    /// notecard, at most `replay_batch` at the time. Live packages waiting in the storage queue
+    /// are drained before every stored package, and stored packages fill at most `REPLAY_SHARE`
+    /// of the notecard queue, so that a large request does not delay current data. Returns the
+    /// update of the sent range, if any.
+    pub fn replay(
+        &mut self,
+        now: i64,
+        sent_id: Option<u32>,
+        request_start: u32,
+        request_end: u32,
+    ) -> Result<Option<ReplayUpdate>, storage::StorageErr> {
+        let Some(next_id) = self.storage.next_id() else {
+            return Ok(None);
+        };
+
+        let age = self.track_request(now, request_start, request_end);
+
+        let sent_id = sent_id.unwrap_or(request_start);
+        let request_end = request_end.min(next_id.saturating_sub(1));
+
+        if sent_id >= request_end {
+            // Request done, clearing.
+            defmt::info!("Request complete, deleting request.");
+            self.complete_request(now);
+            return Ok(Some(ReplayUpdate {
+                sent_id: None,
+                clear_request: true,
+            }));
+        }
+
+        crate::clog!(
+            Storage,
+            info,
+            "Request, sending range: {} -> {} (outstanding for {} s)",
+            sent_id,
+            request_end,
+            age / 1000
+        );
+        let mut update = None;
+
+        for id in (sent_id..=request_end).take(self.replay_batch as usize) {
+            // Live packages first.
+            while self.storage_queue.len() > 0 {
+                self.drain_logged("replay");
+            }
+
+            if self.note_queue.len() >= REPLAY_SHARE || !self.overflow.is_empty() {
+                defmt::trace!("Notecard queue share for replay is full, not adding more packages.");
+                break;
+            }
+
+            let pck = self.storage.get(id);
+            self.check_access(&pck, now);
+
+            defmt::debug!("Sending stored package: {:?}", pck);
+
+            match pck {
+                Ok(mut pck) => match self.note_queue.enqueue({
+                    pck.gyro.clear();
+                    pck
+                }) {
+                    Ok(_) => {
+                        // Update range of sent packages.
+                        update = Some(ReplayUpdate {
+                            sent_id: Some(id),
+                            clear_request: id >= request_end,
+                        });
+                    }
+                    Err(_) => {
+                        defmt::trace!("Notecard queue is full, not adding more packages.");
+                        break;
+                    }
+                },
+                Err(storage::StorageErr::Verify) => {
+                    self.skip_corrupt(id);
+
+                    update = Some(ReplayUpdate {
+                        sent_id: Some(id),
+                        clear_request: id >= request_end,
+                    });
+                }
+                Err(storage::StorageErr::GenericSdMmmcErr(embedded_sdmmc::Error::FileNotFound)) => {
+                    let new_id = ((id / storage::COLLECTION_SIZE) + 1) * storage::COLLECTION_SIZE;
+
+                    defmt::debug!(
+                        "File does not exist, advancing range by full collection: {} -> {}.",
+                        id,
+                        new_id
+                    );
+
+                    update = Some(ReplayUpdate {
+                        sent_id: Some(new_id),
+                        clear_request: new_id >= request_end,
+                    });
+
+                    break;
+                }
+                Err(e) => {
+                    defmt::error!("Failed to read from SD-card: {:?}, clearing request.", e);
+                    self.request = None;
+                    return Err(e);
+                }
+            }
+        }
+
+        if update.as_ref().map_or(false, |u| u.clear_request) {
+            self.complete_request(now);
+        }
+
+        Ok(update)
+    }
+
+    /// Send stored packages from `sent_id` (or `request_start`) up to `request_end` as one
+    /// compressed batch (see `backfill`). The update of the sent range covers the whole batch, and
+    /// is only returned once the notecard has accepted the note: if the note fails the same range
+    /// is read again the next time.
+    #[cfg(not(feature = "encryption"))]
+    fn send_backfill(
+        &mut self,
+        now: i64,
+        send: impl FnOnce(&backfill::Batch) -> Result<(), notecard::NoteError>,
+        sent_id: Option<u32>,
+        request_start: u32,
+        request_end: u32,
+    ) -> Result<Option<ReplayUpdate>, storage::StorageErr> {
+        let (batch, update) = match self.backfill(now, sent_id, request_start, request_end)? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+
+        if !batch.is_empty() {
+            if let Err(e) = send(&batch) {
+                crate::clog!(Storage, error, "Failed to send backfill: {:?}", e);
+                return Ok(None);
+            }
+        }
+
+        if update.clear_request {
+            self.complete_request(now);
+        }
+
+        Ok(Some(update))
+    }
+
+    /// Read stored packages from `sent_id` (or `request_start`) up to `request_end` into a
+    /// compressed batch, until the batch is full. Returns the batch and the update of the sent
+    /// range when the batch is sent, if any. The batch is empty when the request is complete, or
+    /// when the rest of a collection is missing on the SD-card.
+    pub fn backfill(
+        &mut self,
+        now: i64,
+        sent_id: Option<u32>,
+        request_start: u32,
+        request_end: u32,
+    ) -> Result<Option<(backfill::Batch, ReplayUpdate)>, storage::StorageErr> {
+        let next_id = match self.storage.next_id() {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        let age = self.track_request(now, request_start, request_end);
+
+        let sent_id = sent_id.unwrap_or(request_start);
+        let request_end = request_end.min(next_id.saturating_sub(1));
+        let mut batch = backfill::Batch::with_limit(self.compression, self.max_note_size);
+
+        if sent_id >= request_end {
+            defmt::info!("Request complete, deleting request.");
+            self.complete_request(now);
+            return Ok(Some((
+                batch,
+                ReplayUpdate {
+                    sent_id: None,
+                    clear_request: true,
+                },
+            )));
+        }
+
+        crate::clog!(
+            Storage,
+            info,
+            "Request, batching range: {} -> {} (outstanding for {} s)",
+            sent_id,
+            request_end,
+            age / 1000
+        );
+        let mut update = None;
+
+        for id in sent_id..=request_end {
+            let pck = self.storage.get(id);
+            self.check_access(&pck, now);
+
+            match pck {
+                Ok(pck) => {
+                    if batch.push(pck).is_err() {
+                        crate::clog!(
+                            Storage,
+                            info,
+                            "Batch is full: {} packages in {} of {} bytes (max_note_size: {}).",
+                            batch.packages,
+                            batch.len(),
+                            batch.limit(),
+                            self.max_note_size
+                        );
+                        break;
+                    }
+
+                    update = Some(ReplayUpdate {
+                        sent_id: Some(id),
+                        clear_request: id >= request_end,
+                    });
+                }
+                Err(storage::StorageErr::Verify) => {
+                    self.skip_corrupt(id);
+
+                    update = Some(ReplayUpdate {
+                        sent_id: Some(id),
+                        clear_request: id >= request_end,
+                    });
+                }
+                Err(storage::StorageErr::GenericSdMmmcErr(embedded_sdmmc::Error::FileNotFound)) => {
+                    let new_id = ((id / storage::COLLECTION_SIZE) + 1) * storage::COLLECTION_SIZE;
+
+                    defmt::debug!(
+                        "File does not exist, advancing range by full collection: {} -> {}.",
+                        id,
+                        new_id
+                    );
+
+                    update = Some(ReplayUpdate {
+                        sent_id: Some(new_id),
+                        clear_request: new_id >= request_end,
+                    });
+
+                    break;
+                }
+                Err(e) => {
+                    defmt::error!("Failed to read from SD-card: {:?}, clearing request.", e);
+                    self.request = None;
+                    return Err(e);
+                }
+            }
+        }
+
+        defmt::debug!(
+            "Batched {} stored packages: {} bytes.",
+            batch.packages,
+            batch.len()
+        );
+
+        Ok(update.map(|u| (batch, u)))
+    }
+
+    /// A stored package that does not read back as it was written is skipped by the replay, rather
+    /// than sent as data.
+    fn skip_corrupt(&self, id: u32) {
+        use core::fmt::Write as _;
+
+        let mut msg = heapless::String::<128>::new();
+        write!(
+            &mut msg,
+            "Stored package is corrupt, not sent (ID: {}).",
+            id
+        )
+        .ok();
+        log::log_at(log::Category::Storage, log::Level::Error, &msg);
+    }
+
+    /// Start tracking a new request, returns how long the current request has been outstanding
+    /// [ms].
+    fn track_request(&mut self, now: i64, start: u32, end: u32) -> i64 {
+        match self.request {
+            Some(r) if r.start == start && r.end == end => now - r.since,
+            _ => {
+                use core::fmt::Write as _;
+
+                let mut msg = heapless::String::<256>::new();
+                write!(&mut msg, "Replaying stored packages: {} -> {}.", start, end).ok();
+                log::log_at(log::Category::Storage, log::Level::Info, &msg);
+
+                self.request = Some(ReplayRequest {
+                    start,
+                    end,
+                    since: now,
+                });
+
+                0
+            }
+        }
+    }
+
+    fn complete_request(&mut self, now: i64) {
+        use core::fmt::Write as _;
+
+        if let Some(r) = self.request.take() {
+            let mut msg = heapless::String::<256>::new();
+            write!(
+                &mut msg,
+                "Replay of stored packages {} -> {} complete after {} s.",
+                r.start,
+                r.end,
+                (now - r.since) / 1000
+            )
+            .ok();
+            log::log_at(log::Category::Storage, log::Level::Info, &msg);
+        }
+    }
+
+    /// Request being replayed.
+    pub fn request(&self) -> Option<&ReplayRequest> {
+        self.request.as_ref()
+    }
+}
+
+#[cfg(all(test, feature = "storage"))]
+mod tests {
+    use super::*;
+    use axl::AxlPacketBuilder;
+    use queue::{Consumer, DataQueue, Producer};
+    use storage::{mem::MemStorage, StorageBackend};
+
+    fn package(timestamp: i64) -> AxlPacketT {
+        let data = (0..axl::AXL_SZ).map(|v| v as u16).collect();
+        let mut p = AxlPacketBuilder::new(timestamp.max(1), 52.0, waves::wire::ACCEL_MAX, data)
+            .position(0, 5.3, 60.4, 0.)
+            .build()
+            .unwrap();
+
+        // The packages are numbered from 0 by their timestamp, which the builder refuses.
+        p.timestamp = timestamp;
+        p.rtc_timestamp = timestamp;
+
+        #[cfg(feature = "raw")]
+        return (p, waves::VecRawAxl::new());
+
+        #[cfg(not(feature = "raw"))]
+        return (p,);
+    }
+
+    fn manager(
+        storage: MemStorage,
+    ) -> (
+        StorageManager<MemStorage>,
+        Producer<'static, AxlPacketT, STORAGEQ_SZ>,
+        Consumer<'static, AxlPacket, NOTEQ_SZ>,
+    ) {
+        let (sp, sc) = Box::leak(Box::new(DataQueue::new())).split();
+        let (np, nc) = Box::leak(Box::new(DataQueue::new())).split();
+
+        let m = StorageManager::new(storage, sc, np, &config::Config::default());
+
+        (m, sp, nc)
+    }
+
+    fn fill(m: &mut StorageManager<MemStorage>, n: u32) {
+        for i in 0..n {
+            m.storage.store(&mut package(i as i64)).unwrap();
+        }
+    }
+
+    #[test]
+    fn drain_stores_and_forwards() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(u64::MAX));
+
+        assert_eq!(m.drain_queue().unwrap(), None);
+
+        sq.enqueue(package(1)).ok().unwrap();
+        sq.enqueue(package(2)).ok().unwrap();
+
+        assert_eq!(m.drain_queue().unwrap(), Some(0));
+        assert_eq!(m.drain_queue().unwrap(), Some(1));
+        assert_eq!(m.last_id, Some(1));
+        assert_eq!(m.storage.len(), 2);
+
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(0));
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(1));
+
+        assert_eq!(m.storage.get(1).unwrap().timestamp, 2);
+    }
+
+    #[test]
+    fn stored_delta_encoded() {
+        let (mut m, mut sq, _nq) = manager(MemStorage::new(u64::MAX));
+        m.storage.set_sample_encoding(axl::SampleEncoding::Delta);
+
+        let pck = package(1);
+        let data = pck.0.data.clone();
+        sq.enqueue(pck).ok().unwrap();
+        assert_eq!(m.drain_queue().unwrap(), Some(0));
+
+        assert_eq!(m.storage.get(0).unwrap().data, data);
+    }
+
+    #[test]
+    fn gyro_only_stored() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(u64::MAX));
+
+        let mut pck = package(1);
+        pck.0.data.truncate(10 * axl::SAMPLE_SZ);
+        pck.0.gyro = (0..10 * axl::SAMPLE_SZ).map(|v| v as u16).collect();
+        let gyro = pck.0.gyro.clone();
+
+        sq.enqueue(pck).ok().unwrap();
+        assert_eq!(m.drain_queue().unwrap(), Some(0));
+
+        assert!(nq.dequeue().unwrap().gyro.is_empty());
+        assert_eq!(m.storage.get(0).unwrap().gyro, gyro);
+
+        // Nor when the package is replayed.
+        m.replay(0, None, 0, 0).unwrap().unwrap();
+        assert!(nq.dequeue().unwrap().gyro.is_empty());
+    }
+
+    #[test]
+    fn queues_survive_imu_reset() {
+        // The only test splitting the queues.
+        let q = split_queues().unwrap();
+        assert!(split_queues().is_none());
+
+        let (mut p, mut nq) = (q.imu, q.note);
+        let mut m = StorageManager::new(
+            MemStorage::new(u64::MAX),
+            q.storage,
+            q.note_producer,
+            &config::Config::default(),
+        );
+        let mut o: queue::Overflow<AxlPacketT, OVERFLOW_SZ> =
+            queue::Overflow::new(queue::QueueFull::Hold);
+
+        // Packages in flight at the reset: the queue is full, packages are held, and the main loop
+        // has drained one.
+        let n = (STORAGEQ_SZ + OVERFLOW_SZ) as i64;
+        for t in 0..n {
+            assert!(o.enqueue(&mut p, package(t)).is_none());
+        }
+        assert_eq!(o.len(), OVERFLOW_SZ);
+        m.drain_queue().unwrap();
+
+        // The reset pushes the partial buffer through the same producer (see `Imu::reset`).
+        assert!(o.enqueue(&mut p, package(n)).is_none());
+
+        let mut forwarded = std::vec::Vec::new();
+        while m.storage_queue.ready() || !o.is_empty() {
+            o.drain(&mut p);
+            m.drain_queue().unwrap();
+            while let Some(pck) = nq.dequeue() {
+                forwarded.push(pck.timestamp);
+            }
+        }
+
+        let stored = (0..m.storage.len() as u32)
+            .map(|id| m.storage.get(id).unwrap().timestamp)
+            .collect::<std::vec::Vec<_>>();
+        assert_eq!(stored, (0..=n).collect::<std::vec::Vec<_>>());
+        assert_eq!(forwarded, stored);
+    }
+
+    #[test]
+    fn drain_low_space() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(1024));
+
+        sq.enqueue(package(1)).ok().unwrap();
+
+        // Not stored, but still forwarded to the notecard.
+        assert_eq!(m.drain_queue().unwrap(), None);
+        assert!(m.storage.is_empty());
+        assert!(m.low_space);
+        assert_eq!(nq.dequeue().unwrap().storage_id, None);
+    }
+
+    #[test]
+    fn drain_ring_buffer() {
+        use storage::PACKAGE_SZ;
+
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(500 * PACKAGE_SZ as u64));
+        m.min_free_space = 100 * PACKAGE_SZ as u64;
+        m.ring_buffer = true;
+
+        for i in 0..1000 {
+            sq.enqueue(package(i)).ok().unwrap();
+            assert_eq!(m.drain_queue().unwrap(), Some(i as u32));
+            while nq.dequeue().is_some() {}
+        }
+
+        // The newest packages are kept, the IDs keep counting up.
+        assert!(!m.low_space);
+        assert_eq!(m.storage.len(), 400);
+        assert_eq!(m.storage.next_id(), Some(1000));
+        assert_eq!(m.storage.get(999).unwrap().timestamp, 999);
+        assert_eq!(m.storage.get(600).unwrap().timestamp, 600);
+        assert!(matches!(
+            m.storage.get(599),
+            Err(storage::StorageErr::GenericSdMmmcErr(
+                embedded_sdmmc::Error::FileNotFound
+            ))
+        ));
+
+        // A request for removed packages skips to the oldest kept.
+        let mut u = m.replay(0, None, 0, 999).unwrap().unwrap();
+        while u.sent_id.unwrap() < 600 {
+            assert!(nq.dequeue().is_none());
+            u = m.replay(0, u.sent_id, 0, 999).unwrap().unwrap();
+        }
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(600));
+    }
+
+    #[test]
+    fn drain_note_queue_full() {
+        let (mut m, mut sq, nq) = manager(MemStorage::new(u64::MAX));
+
+        for i in 0..=NOTEQ_SZ {
+            sq.enqueue(package(i as i64)).ok().unwrap();
+            m.drain_queue().unwrap();
+        }
+
+        assert_eq!(nq.len(), NOTEQ_SZ);
+
+        // The package that did not fit is still stored.
+        assert_eq!(m.storage.len(), NOTEQ_SZ + 1);
+        assert!(queue::DROPPED.get().low >= 1);
+    }
+
+    #[test]
+    fn drain_note_queue_drop_oldest() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        m.overflow.policy = queue::QueueFull::DropOldest;
+
+        let n = NOTEQ_SZ + NOTE_OVERFLOW_SZ + 2;
+        for i in 0..n {
+            sq.enqueue(package(i as i64)).ok().unwrap();
+            m.drain_queue().unwrap();
+        }
+        assert_eq!(m.storage.len(), n);
+        assert_eq!(m.overflow.len(), NOTE_OVERFLOW_SZ);
+
+        let mut sent = Vec::new();
+        while sent.len() < NOTEQ_SZ + NOTE_OVERFLOW_SZ {
+            m.drain_queue().unwrap();
+            sent.extend(nq.dequeue().map(|p| p.timestamp));
+        }
+
+        // The oldest queued packages gave way to the newest.
+        assert_eq!(sent, (2..n as i64).collect::<Vec<_>>());
+        assert!(m.overflow.is_empty());
+    }
+
+    #[test]
+    fn event_log() {
+        let (mut m, _sq, _nq) = manager(MemStorage::new(u64::MAX));
+
+        log::log("event log line");
+        m.drain_event_log().unwrap();
+
+        let line = m
+            .storage
+            .event_log
+            .lines()
+            .find(|l| l.ends_with("event log line"))
+            .unwrap();
+
+        // The clock has not been set.
+        assert!(line.starts_with("1970-01-01T00:00:00Z [uncalibrated-time] "));
+    }
+
+    #[test]
+    fn check_sd() {
+        let (mut m, _sq, _nq) = manager(MemStorage::new(u64::MAX));
+        assert_eq!(m.check_sd(1).outcome(), bist::Outcome::Pass);
+
+        let (mut m, _sq, _nq) = manager(MemStorage::new(1024));
+        let sd = m.check_sd(1);
+        assert!(sd.write);
+        assert_eq!(sd.outcome(), bist::Outcome::Fail);
+
+        let (mut m, _sq, _nq) = manager(MemStorage::new(u64::MAX));
+        m.storage.ready = false;
+        let sd = m.check_sd(1);
+        assert!(!sd.write);
+        assert_eq!(sd.free_space, None);
+    }
+
+    /// Fill the storage queue, returns the number of packages.
+    fn fill_queue(sq: &mut Producer<'static, AxlPacketT, STORAGEQ_SZ>) -> usize {
+        let mut n = 0;
+        while sq.enqueue(package(n as i64)).is_ok() {
+            n += 1;
+        }
+        n
+    }
+
+    #[test]
+    fn pressure_storage_policy() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        assert_eq!(m.queue_policy, queue::QueuePolicy::Storage);
+
+        while m.note_queue.enqueue(package(0).0).is_ok() {}
+        let n = fill_queue(&mut sq);
+
+        while m.storage_queue.ready() {
+            m.drain_queue().unwrap();
+        }
+
+        // Every package is stored, the live packages that did not fit are dropped.
+        assert_eq!(m.storage.len(), n);
+        assert_eq!(nq.len(), NOTEQ_SZ);
+        while let Some(p) = nq.dequeue() {
+            assert_eq!(p.storage_id, None);
+        }
+    }
+
+    #[test]
+    fn pressure_live_policy() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        m.queue_policy = queue::QueuePolicy::Live;
+
+        let n = fill_queue(&mut sq);
+        while m.storage_queue.ready() {
+            m.drain_queue().unwrap();
+        }
+
+        // Forwarded without being stored while under pressure.
+        let stored = m.storage.len();
+        assert!(stored < n);
+
+        for i in 0..n {
+            let p = nq.dequeue().unwrap();
+            assert_eq!(p.storage_id.is_some(), i >= n - stored);
+        }
+
+        // Nothing is gained by not storing when the notecard queue is full.
+        while m.note_queue.enqueue(package(0).0).is_ok() {}
+        let n = fill_queue(&mut sq);
+        while m.storage_queue.ready() {
+            m.drain_queue().unwrap();
+        }
+        assert_eq!(m.storage.len(), stored + n);
+
+        // Or when the notecard is holding back because of its outbound queue.
+        while nq.dequeue().is_some() {}
+        queue::CARD_PRESSURE.store(true, Ordering::Relaxed);
+        let stored = m.storage.len();
+        let n = fill_queue(&mut sq);
+        while m.storage_queue.ready() {
+            m.drain_queue().unwrap();
+        }
+        queue::CARD_PRESSURE.store(false, Ordering::Relaxed);
+        assert_eq!(m.storage.len(), stored + n);
+    }
+
+    #[test]
+    fn replay_advances_range() {
+        let (mut m, _sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        fill(&mut m, 30);
+
+        // Limited by the share of the notecard queue for replayed packages.
+        let u = m.replay(0, None, 2, 20).unwrap().unwrap();
+        let sent = 2 + REPLAY_SHARE as u32 - 1;
+        assert_eq!(
+            u,
+            ReplayUpdate {
+                sent_id: Some(sent),
+                clear_request: false
+            }
+        );
+
+        for id in 2..=sent {
+            assert_eq!(nq.dequeue().unwrap().storage_id, Some(id));
+        }
+
+        // Continue until the end of the request.
+        let mut sent_id = u.sent_id;
+        loop {
+            let u = m.replay(0, sent_id, 2, 20).unwrap().unwrap();
+            while nq.dequeue().is_some() {}
+
+            if u.clear_request {
+                assert_eq!(u.sent_id, Some(20));
+                break;
+            }
+
+            sent_id = u.sent_id;
+        }
+
+        assert_eq!(
+            m.replay(0, Some(20), 2, 20).unwrap(),
+            Some(ReplayUpdate {
+                sent_id: None,
+                clear_request: true
+            })
+        );
+    }
+
+    #[test]
+    fn replay_interleaves_live() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        fill(&mut m, 30);
+
+        sq.enqueue(package(100)).ok().unwrap();
+
+        m.replay(0, None, 2, 20).unwrap().unwrap();
+
+        // The live package is stored and queued before the stored packages.
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(30));
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(2));
+    }
+
+    #[test]
+    fn replay_batch() {
+        let (mut m, _sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        fill(&mut m, 30);
+        m.replay_batch = 1;
+
+        assert_eq!(
+            m.replay(0, None, 2, 20).unwrap(),
+            Some(ReplayUpdate {
+                sent_id: Some(2),
+                clear_request: false
+            })
+        );
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(2));
+        assert!(nq.dequeue().is_none());
+    }
+
+    #[test]
+    fn replay_tracks_request() {
+        let (mut m, _sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        fill(&mut m, 30);
+
+        let mut u = m.replay(1000, None, 2, 5).unwrap().unwrap();
+        assert_eq!(m.request().unwrap().since, 1000);
+
+        while !u.clear_request {
+            while nq.dequeue().is_some() {}
+            u = m.replay(60_000, u.sent_id, 2, 5).unwrap().unwrap();
+            assert!(u.clear_request || m.request().unwrap().since == 1000);
+        }
+
+        assert_eq!(m.request(), None);
+
+        // A new request is tracked from when it is first seen.
+        m.replay(70_000, None, 10, 20).unwrap();
+        assert_eq!(m.request().unwrap().since, 70_000);
+    }
+
+    #[test]
+    fn replay_skips_missing_collection() {
+        let (mut m, _sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        fill(&mut m, 210);
+        m.storage.remove_collection(1);
+
+        assert_eq!(
+            m.replay(0, Some(150), 0, 205).unwrap(),
+            Some(ReplayUpdate {
+                sent_id: Some(200),
+                clear_request: false
+            })
+        );
+        assert!(nq.dequeue().is_none());
+
+        let u = m.replay(0, Some(200), 0, 205).unwrap().unwrap();
+        assert!(u.sent_id.unwrap() > 200);
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(200));
+    }
+
+    #[test]
+    fn replay_across_day_files() {
+        let (mut m, _sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        m.storage.set_day_files(true);
+
+        // Half a collection before midnight (2023-11-14T23:59:00Z), the rest after.
+        let midnight = 1_700_006_400_000;
+        for i in 0..100 {
+            m.storage
+                .store(&mut package(midnight - 60_000 + i * 20_000))
+                .unwrap();
+        }
+
+        let days = m.storage.days.clone();
+        assert_eq!(days.len(), 2);
+        assert_eq!(&days[0].date, b"231114");
+        assert_eq!(&days[1].date, b"231115");
+        assert_eq!(m.storage.get(2).unwrap().timestamp, midnight - 20_000);
+        assert_eq!(m.storage.get(100).unwrap().timestamp, midnight);
+
+        // The rest of the first collection is skipped.
+        assert_eq!(
+            m.replay(0, Some(2), 0, 150).unwrap(),
+            Some(ReplayUpdate {
+                sent_id: Some(100),
+                clear_request: false
+            })
+        );
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(2));
+        assert!(nq.dequeue().is_none());
+
+        let u = m.replay(0, Some(100), 0, 150).unwrap().unwrap();
+        assert!(u.sent_id.unwrap() > 100);
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(100));
+    }
+
+    #[test]
+    fn backfill_advances_by_batch() {
+        let (mut m, _sq, mut nq) = manager(MemStorage::new(u64::MAX));
+        fill(&mut m, 30);
+
+        let (b, u) = m.backfill(0, None, 2, 20).unwrap().unwrap();
+        assert!(b.packages > 1);
+        assert_eq!(b.first, Some(2));
+        assert_eq!(
+            u,
+            ReplayUpdate {
+                sent_id: b.last,
+                clear_request: false
+            }
+        );
+
+        // Stored packages are not queued as data notes.
+        assert!(nq.dequeue().is_none());
+
+        for (p, id) in b.packages().zip(2..) {
+            assert_eq!(p.unwrap(), m.storage.get(id).unwrap());
+        }
+
+        // Continue from the end of the batch until the end of the request.
+        let mut sent_id = u.sent_id;
+        loop {
+            let (b, u) = m.backfill(0, sent_id, 2, 20).unwrap().unwrap();
+            assert_eq!(b.first, sent_id);
+
+            if u.clear_request {
+                assert_eq!((b.last, u.sent_id), (Some(20), Some(20)));
+                break;
+            }
+
+            sent_id = u.sent_id;
+        }
+
+        let (b, u) = m.backfill(0, Some(20), 2, 20).unwrap().unwrap();
+        assert!(b.is_empty());
+        assert_eq!(
+            u,
+            ReplayUpdate {
+                sent_id: None,
+                clear_request: true
+            }
+        );
+        assert_eq!(m.request(), None);
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    #[test]
+    fn serve_request_sends_batches() {
+        let (mut m, _sq, _nq) = manager(MemStorage::new(u64::MAX));
+        fill(&mut m, 30);
+
+        // The range is not advanced when the notecard does not take the batch.
+        let u = m
+            .serve_request(0, None, 2, 20, |_| Err(notecard::NoteError::TimeOut))
+            .unwrap();
+        assert_eq!(u, None);
+
+        let mut sent = std::vec::Vec::new();
+        let mut sent_id = None;
+        loop {
+            let u = m
+                .serve_request(0, sent_id, 2, 20, |b| {
+                    sent.extend(b.packages().map(|p| p.unwrap().storage_id.unwrap()));
+                    Ok(())
+                })
+                .unwrap()
+                .unwrap();
+
+            if u.clear_request {
+                break;
+            }
+
+            sent_id = u.sent_id;
+        }
+
+        // A batch starts with the last package of the batch before.
+        sent.dedup();
+        assert_eq!(sent, (2..=20).collect::<std::vec::Vec<_>>());
+        assert_eq!(m.request(), None);
+    }
+
+    #[test]
+    fn backfill_skips_missing_collection() {
+        let (mut m, _sq, _nq) = manager(MemStorage::new(u64::MAX));
+        fill(&mut m, 210);
+        m.storage.remove_collection(1);
+
+        let (b, u) = m.backfill(0, Some(150), 0, 205).unwrap().unwrap();
+        assert!(b.is_empty());
+        assert_eq!(
+            u,
+            ReplayUpdate {
+                sent_id: Some(200),
+                clear_request: false
+            }
+        );
+
+        let (b, _) = m.backfill(0, Some(200), 0, 205).unwrap().unwrap();
+        assert_eq!(b.first, Some(200));
+    }
+
+    struct FixedState;
+
+    impl State for FixedState {
+        fn try_now(&self) -> Option<NaiveDateTime> {
+            NaiveDateTime::from_timestamp_opt(1_700_000_000, 0)
+        }
+
+        fn try_get(&self) -> Option<(NaiveDateTime, u32, f64, f64)> {
+            Some((self.now(), 1_699_999_990, 60.4, 5.3))
+        }
+    }
+
+    struct UnsetState;
+
+    impl State for UnsetState {
+        fn try_now(&self) -> Option<NaiveDateTime> {
+            None
+        }
+
+        fn try_get(&self) -> Option<(NaiveDateTime, u32, f64, f64)> {
+            None
+        }
+    }
+
+    /// RTC returning the times in `reads` [s], `None` for a failed read.
+    struct GlitchyRtc {
+        reads: std::vec::IntoIter<Option<i64>>,
+    }
+
+    impl DateTimeAccess for GlitchyRtc {
+        type Error = ();
+
+        fn datetime(&mut self) -> Result<NaiveDateTime, ()> {
+            self.reads
+                .next()
+                .flatten()
+                .and_then(|t| NaiveDateTime::from_timestamp_opt(t, 0))
+                .ok_or(())
+        }
+
+        fn set_datetime(&mut self, _: &NaiveDateTime) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn implausible_rtc() {
+        let t0 = 1_700_000_000;
+        let mut s = SharedState {
+            rtc: GlitchyRtc {
+                reads: std::vec![
+                    Some(t0),
+                    Some(5_000_000_000),
+                    None,
+                    Some(-1),
+                    Some(t0 + 1),
+                    None
+                ]
+                .into_iter(),
+            },
+            position_time: 0,
+            lon: 0.,
+            lat: 0.,
+            gps: Default::default(),
+            time: Default::default(),
+        };
+
+        assert_eq!(s.now_millis(), t0 * 1000);
+
+        // Far future.
+        let t = s.rtc_time();
+        assert_eq!((t.raw, t.time), (Some(5_000_000_000_000), t0 * 1000));
+
+        // Failed read, and rolled over.
+        assert_eq!(s.now_millis(), t0 * 1000);
+        assert_eq!(s.now().timestamp(), t0);
+
+        assert_eq!(s.now().timestamp(), t0 + 1);
+        assert_eq!(s.raw_now(), None);
+    }
+
+    #[test]
+    fn unavailable_state() {
+        assert_eq!(UnsetState.now(), epoch());
+        assert_eq!(UnsetState.get(), (epoch(), 0, 0., 0.));
+        assert_eq!(FixedState.get().1, 1_699_999_990);
+    }
+
+    #[test]
+    fn shutdown_drains_and_snapshots() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(u64::MAX));
+
+        sq.enqueue(package(1)).ok().unwrap();
+        sq.enqueue(package(2)).ok().unwrap();
+
+        m.shutdown(&FixedState).unwrap();
+
+        assert_eq!(m.storage.len(), 2);
+        assert_eq!(nq.len(), 2);
+        assert!(nq.dequeue().is_some());
+
+        // Snapshot written before the card was released.
+        let snap = m.storage.snapshot.as_ref().unwrap();
+        assert_eq!(snap.storage_id, Some(1));
+        assert_eq!(snap.timestamp, 1_700_000_000_000);
+        assert_eq!(m.storage.next_id(), None);
+    }
+
+    #[test]
+    fn drain_until_deadline() {
+        let (mut m, mut sq, mut nq) = manager(MemStorage::new(u64::MAX));
+
+        sq.enqueue(package(1)).ok().unwrap();
+        sq.enqueue(package(2)).ok().unwrap();
+
+        // Deadline passed: nothing drained.
+        assert!(!m.drain_until(|| Some(1000), 1000));
+        assert_eq!(m.storage_queue.len(), 2);
+
+        assert!(m.drain_until(|| Some(0), 1000));
+        assert_eq!(m.storage.len(), 2);
+        assert_eq!(nq.len(), 2);
+        assert!(nq.dequeue().is_some());
+
+        // Without a clock the queued packages are drained.
+        sq.enqueue(package(3)).ok().unwrap();
+        assert!(m.drain_until(|| None, 0));
+        assert_eq!(m.storage.len(), 3);
+    }
+
+    #[test]
+    fn drain_until_failed_storage() {
+        let mut s = MemStorage::new(u64::MAX);
+        s.fail = u32::MAX;
+
+        let (mut m, mut sq, mut nq) = manager(s);
+        m.recovery.failed = true;
+
+        sq.enqueue(package(1)).ok().unwrap();
+
+        // Forwarded to the notecard queue without the card.
+        assert!(m.drain_until(|| Some(0), 1000));
+        assert_eq!(m.storage.len(), 0);
+        assert_eq!(nq.len(), 1);
+    }
+
+    #[test]
+    fn drain_until_logs_errors() {
+        let mut s = MemStorage::new(u64::MAX);
+        s.fail = 1;
+
+        let (mut m, mut sq, nq) = manager(s);
+        sq.enqueue(package(1)).ok().unwrap();
+
+        assert!(m.drain_until(|| Some(0), 1000));
+        assert_eq!((m.storage.len(), nq.len()), (0, 1));
+
+        m.drain_event_log().unwrap();
+        assert!(m
+            .storage
+            .event_log
+            .lines()
+            .any(|l| l.contains("Failed to store package during flush")));
+    }
+
+    #[test]
+    fn storage_reinit() {
+        use storage::recovery::ERRORS;
+
+        let mut s = MemStorage::new(u64::MAX);
+        s.fail = ERRORS;
+
+        let (mut m, mut sq, mut nq) = manager(s);
+
+        for i in 0..ERRORS {
+            sq.enqueue(package(i as i64)).ok().unwrap();
+            assert!(m.drain_queue().is_err());
+        }
+        assert_eq!(m.storage.reinits, 1);
+
+        // The card works again.
+        sq.enqueue(package(ERRORS as i64)).ok().unwrap();
+        assert!(m.drain_queue().is_ok());
+        assert_eq!(m.storage.len(), 1);
+        assert_eq!((m.recovery.errors, m.recovery.reinits), (0, 0));
+
+        // Every package is still forwarded.
+        assert_eq!(nq.len(), ERRORS as usize + 1);
+    }
+
+    #[test]
+    fn storage_give_up() {
+        use storage::recovery::{ERRORS, INTERVAL, MAX_REINITS};
+
+        let mut s = MemStorage::new(u64::MAX);
+        s.fail = u32::MAX;
+
+        let (mut m, mut sq, mut nq) = manager(s);
+
+        let n = ERRORS * (MAX_REINITS + 1);
+        for i in 0..n {
+            sq.enqueue(package(i as i64 * INTERVAL)).ok().unwrap();
+            m.drain_queue().ok();
+            nq.dequeue().unwrap();
+        }
+        assert_eq!(m.storage.reinits, MAX_REINITS);
+        assert!(m.recovery.failed);
+
+        // Only forwarded from now on.
+        sq.enqueue(package(n as i64 * INTERVAL)).ok().unwrap();
+        assert_eq!(m.drain_queue().unwrap(), None);
+        assert_eq!(nq.dequeue().unwrap().storage_id, None);
+        assert_eq!(m.storage.len(), 0);
+    }
+
+    #[test]
+    fn verify_writes_reinit() {
+        use storage::recovery::ERRORS;
+
+        let mut s = MemStorage::new(u64::MAX);
+        s.corrupt = ERRORS;
+
+        let (mut m, mut sq, mut nq) = manager(s);
+        m.storage.set_verify_writes(true);
+
+        for i in 0..ERRORS {
+            sq.enqueue(package(i as i64)).ok().unwrap();
+            assert!(matches!(m.drain_queue(), Err(storage::StorageErr::Verify)));
+        }
+        assert_eq!(m.storage.reinits, 1);
+
+        // The card keeps the packages again.
+        sq.enqueue(package(ERRORS as i64)).ok().unwrap();
+        assert_eq!(m.drain_queue().unwrap(), Some(ERRORS));
+
+        // Every package is still forwarded.
+        assert_eq!(nq.len(), ERRORS as usize + 1);
+    }
+
+    #[test]
+    fn replay_skips_corrupt() {
+        let (mut m, _sq, mut nq) = manager(MemStorage::new(u64::MAX));
+
+        for i in 0..10 {
+            let mut p = package(i);
+            p.0.crc = axl::crc32(&p.0.data);
+            m.storage.store(&mut p).unwrap();
+        }
+
+        m.storage.corrupt = 1;
+
+        assert_eq!(
+            m.replay(0, None, 2, 3).unwrap(),
+            Some(ReplayUpdate {
+                sent_id: Some(3),
+                clear_request: true
+            })
+        );
+
+        // The corrupt package is skipped, the next is sent.
+        assert_eq!(nq.dequeue().unwrap().storage_id, Some(3));
+        assert!(nq.dequeue().is_none());
+        assert!(matches!(m.storage.get(2), Ok(_)));
+    }
+
+    #[test]
+    fn replay_storage_not_ready() {
+        let mut s = MemStorage::new(u64::MAX);
+        s.ready = false;
+
+        let (mut m, _sq, _nq) = manager(s);
+        assert_eq!(m.replay(0, None, 0, 10).unwrap(), None);
+    }
+
+    #[test]
+    fn location_backoff_schedule() {
+        const MINUTE: i64 = 60_000;
+
+        let schedule = (0..8)
+            .map(|f| location_backoff(MINUTE, f, 30 * MINUTE) / MINUTE)
+            .collect::<std::vec::Vec<_>>();
+        assert_eq!(schedule, [1, 1, 2, 4, 8, 16, 30, 30]);
+
+        assert_eq!(location_backoff(MINUTE, u32::MAX, 30 * MINUTE), 30 * MINUTE);
+
+        // Disabled.
+        assert_eq!(location_backoff(MINUTE, 10, 0), MINUTE);
+    }
+
+    #[test]
+    fn location_retry_interval() {
+        use LocationState::*;
+
+        let mut l = Location::new(&config::Config::default());
+        assert_eq!((l.interval, l.backoff), (60_000, 1_800_000));
+
+        for t in 1..=4 {
+            l.transition(false, t);
+        }
+        assert_eq!(l.state, Trying(4));
+        assert_eq!(l.retry_interval(), 8 * 60_000);
+
+        // Reset by a good attempt, before the location is retrieved.
+        l.transition(true, 5);
+        assert_eq!(l.state, Trying(5));
+        assert_eq!(l.retry_interval(), l.interval);
+
+        // A single failure after the location is retrieved does not back off.
+        l.transition(true, 6);
+        l.transition(false, 7);
+        assert_eq!(l.state, Retrieved(7));
+        assert_eq!(l.retry_interval(), l.interval);
+    }
+
+    #[test]
+    fn location_debounce() {
+        use LocationState::*;
+
+        let mut l = Location::new(&config::Config::default());
+        assert_eq!((l.fixes_required, l.failures_tolerated), (2, 3));
+
+        // A single lucky fix is not enough.
+        l.transition(true, 1);
+        assert_eq!(l.state, Trying(1));
+        l.transition(false, 2);
+        l.transition(true, 3);
+        assert_eq!(l.state, Trying(3));
+        l.transition(true, 4);
+        assert_eq!(l.state, Retrieved(4));
+
+        // Failures below the tolerance keep the location.
+        l.transition(false, 5);
+        l.transition(false, 6);
+        l.transition(true, 7);
+        l.transition(false, 8);
+        l.transition(false, 9);
+        assert_eq!(l.state, Retrieved(9));
+        l.transition(false, 10);
+        assert_eq!(l.state, Trying(10));
+
+        // Only the changes of state are logged.
+        let mut events = std::vec::Vec::new();
+        while let Some(r) = l.events.pop() {
+            events.push(r);
+        }
+
+        use location_log::{Event, Record};
+        assert_eq!(
+            events,
+            [
+                Record {
+                    timestamp: 4,
+                    event: Event::State {
+                        retrieved: true,
+                        fixes: 2,
+                        failures: 0
+                    }
+                },
+                Record {
+                    timestamp: 10,
+                    event: Event::State {
+                        retrieved: false,
+                        fixes: 0,
+                        failures: 3
+                    }
+                },
+            ]
+        );
+    }
 }
@@ -0,0 +1,206 @@
+//! Event log of the location and the time of the buoy.
+//!
+//! Every change of `LocationState`, every time the RTC is set from the Notecard, and every fix
+//! that is accepted or rejected (see `Location::retrieve`) is recorded with the time of the RTC
+//! when it happened. With the `storage` feature the records are appended to `LOCATION_LOG_FILE`
+//! on the SD-card by the main loop, so that discontinuities in the timestamps of a deployment can
+//! be traced back to the RTC being set. `sfypack locations` exports the records as a timeline.
+//!
+//! The file is a sequence of records, each a postcard serialized `(LOCATION_LOG_VERSION, Record)`
+//! with COBS framing (zero terminated), like the health records (see `health`).
+
+use heapless::Vec;
+
+use crate::axl::DecodeError;
+
+/// Location records on the SD-card.
+pub const LOCATION_LOG_FILE: &str = "LOCATION.LOG";
+
+/// Format version of the records, increase when `Record` changes.
+pub const LOCATION_LOG_VERSION: u32 = 1;
+
+/// Maximum size of a serialized and COBS framed record.
+pub const LOCATION_RECORD_SZ: usize = 64;
+
+/// Records kept until they are written, the oldest are dropped when the SD-card is not available.
+pub const EVENTS_SZ: usize = 16;
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The location state changed, after `fixes` consecutive good or `failures` consecutive
+    /// failed attempts.
+    State {
+        retrieved: bool,
+        fixes: u32,
+        failures: u32,
+    },
+
+    /// The RTC was set from the time of the Notecard [ms].
+    RtcSet { before: i64, after: i64 },
+
+    /// A fix from the Notecard (time of the fix in seconds), `accepted` is `false` when it was
+    /// rejected for the dilution of precision (see `Config::max_dop`) or during the warm-up (see
+    /// `Config::warmup`).
+    Fix {
+        lat: f64,
+        lon: f64,
+        position_time: u32,
+        dop: f32,
+        accepted: bool,
+    },
+}
+
+/// An event at the time `timestamp` of the RTC [ms].
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    pub timestamp: i64,
+    pub event: Event,
+}
+
+impl Record {
+    /// Serialize as a record of the location file.
+    pub fn to_cobs(&self) -> Result<Vec<u8, LOCATION_RECORD_SZ>, postcard::Error> {
+        postcard::to_vec_cobs(&(LOCATION_LOG_VERSION, self))
+    }
+
+    /// Decode a COBS framed record of the location file, the buffer is decoded in place.
+    pub fn decode(buf: &mut [u8]) -> Result<Record, DecodeError> {
+        let n = cobs::decode_in_place(buf).map_err(|_| DecodeError::Cobs)?;
+
+        if n == 0 {
+            return Err(DecodeError::Empty);
+        }
+
+        let (version, buf) =
+            postcard::take_from_bytes::<u32>(&buf[..n]).map_err(DecodeError::Postcard)?;
+
+        match version {
+            LOCATION_LOG_VERSION => postcard::from_bytes(buf).map_err(DecodeError::Postcard),
+            _ => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// Records waiting to be written to the SD-card.
+#[derive(Clone, Default)]
+pub struct EventLog {
+    records: Vec<Record, EVENTS_SZ>,
+
+    /// Records dropped because the log was full.
+    pub dropped: u32,
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        EventLog::default()
+    }
+
+    pub fn push(&mut self, timestamp: i64, event: Event) {
+        crate::clog!(
+            Location,
+            debug,
+            "Location event: {}: {:?}",
+            timestamp,
+            event
+        );
+
+        if self.records.is_full() {
+            self.records.remove(0);
+            self.dropped = self.dropped.saturating_add(1);
+        }
+
+        self.records.push(Record { timestamp, event }).ok();
+    }
+
+    /// The oldest record, remove it with `pop` once it is written.
+    pub fn peek(&self) -> Option<&Record> {
+        self.records.first()
+    }
+
+    pub fn pop(&mut self) -> Option<Record> {
+        if self.records.is_empty() {
+            None
+        } else {
+            Some(self.records.remove(0))
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let events = [
+            Event::State {
+                retrieved: true,
+                fixes: u32::MAX,
+                failures: u32::MAX,
+            },
+            Event::RtcSet {
+                before: i64::MIN,
+                after: i64::MAX,
+            },
+            Event::Fix {
+                lat: -90.0,
+                lon: 180.0,
+                position_time: u32::MAX,
+                dop: 99.9,
+                accepted: false,
+            },
+        ];
+
+        for event in events {
+            let r = Record {
+                timestamp: i64::MAX,
+                event,
+            };
+
+            let mut b = r.to_cobs().unwrap();
+            assert_eq!(b.last(), Some(&0));
+            assert_eq!(Record::decode(&mut b).unwrap(), r);
+        }
+
+        let mut b: Vec<u8, LOCATION_RECORD_SZ> = postcard::to_vec_cobs(&(
+            LOCATION_LOG_VERSION + 1,
+            &Record {
+                timestamp: 0,
+                event: events[0],
+            },
+        ))
+        .unwrap();
+        assert_eq!(
+            Record::decode(&mut b),
+            Err(DecodeError::UnsupportedVersion(LOCATION_LOG_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn oldest_dropped() {
+        let mut l = EventLog::new();
+
+        for t in 0..(EVENTS_SZ as i64 + 2) {
+            l.push(
+                t,
+                Event::RtcSet {
+                    before: t,
+                    after: t,
+                },
+            );
+        }
+
+        assert_eq!((l.len(), l.dropped), (EVENTS_SZ, 2));
+        assert_eq!(l.peek().map(|r| r.timestamp), Some(2));
+        assert_eq!(l.pop().map(|r| r.timestamp), Some(2));
+        assert_eq!(l.len(), EVENTS_SZ - 1);
+    }
+}
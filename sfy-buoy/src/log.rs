@@ -1,28 +1,409 @@
+//! Logging to `defmt` and to the log queue sent back over the Notecard.
+//!
+//! Messages in the main subsystems are logged with a [`Category`] through the [`clog!`] macro (or
+//! [`log_at`] for messages that should also be sent over the Notecard). The level of every
+//! category can be changed at run-time by setting the note `levels` in the `log.db` notefile on
+//! notehub, e.g.:
+//!
+//! ```json
+//! { "imu": "trace", "note": "warn" }
+//! ```
+//!
+//! The note is read at boot and in every notecard iteration. The run-time levels only filter
+//! further: messages below the compile-time `DEFMT_LOG` level are stripped from the firmware
+//! regardless. Messages at `warn` or `error` passed to [`log_at`] are also queued for the
+//! Notecard, with critical priority so that they are not dropped in favour of routine messages
+//! (see [`crate::queue`]).
+//!
+//! With the `storage` feature the messages queued for the Notecard are also appended to
+//! `EVENT.LOG` on the SD-card, one line per message prefixed with the time of the RTC when the
+//! message was logged (see [`LogTime`], `log_time` in the config):
+//!
+//! ```text
+//! 2024-05-01T12:00:03Z Low free space on SD-card: ..
+//! 1970-01-01T00:02:11Z [uncalibrated-time] Failed to retrieve location: ..
+//! ```
+//!
+//! Lines logged before the RTC has been set (the RTC is before [`MIN_CALIBRATED_TIME`]) are
+//! marked with `[uncalibrated-time]`.
+
 use blues_notecard::NoteError;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicI16, AtomicI32, AtomicPtr, AtomicU8, Ordering};
 use embedded_hal::blocking::{
     delay::DelayMs,
     i2c::{Read, Write},
 };
-use heapless::{mpmc::Q4 as Queue, String};
+use heapless::String;
 
 use crate::note::Notecarrier;
+use crate::queue::{Priority, PriorityQueue};
+
+/// Capacity of log queue for every priority.
+pub const LOGQ_SZ: usize = 4;
+
+pub type LogQueue = PriorityQueue<String<256>, LOGQ_SZ>;
 
 /// Log message queue for messages to be sent back over notecard.
-static LOGQ: Queue<String<256>> = Queue::new();
+static LOGQ: LogQueue = PriorityQueue::new();
+
+/// Length of a line of the event log on the SD-card: the time and the message.
+pub const LOG_LINE_SZ: usize = 320;
+
+pub type LogLine = String<LOG_LINE_SZ>;
+
+/// Capacity of the queue of lines for the event log on the SD-card, must be a power of 2.
+#[cfg(feature = "storage")]
+pub const SDLOGQ_SZ: usize = 8;
+
+/// Lines waiting to be appended to the event log on the SD-card.
+#[cfg(feature = "storage")]
+static SDLOGQ: heapless::mpmc::MpMcQueue<LogLine, SDLOGQ_SZ> = heapless::mpmc::MpMcQueue::new();
+
+/// Times before this are taken as an RTC that has not been set (2020-01-01) [s].
+pub const MIN_CALIBRATED_TIME: i64 = 1_577_836_800;
+
+/// Largest offset from UTC [minutes].
+pub const MAX_UTC_OFFSET: i16 = 14 * 60;
+
+/// Format of the time of the lines of the event log.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum TimeFormat {
+    /// ISO 8601, e.g. `2024-05-01T12:00:03Z` (UTC) or `2024-05-01T14:00:03+02:00`.
+    Iso8601 = 0,
+
+    /// Seconds since the epoch, always UTC.
+    Unix,
+}
+
+/// Time stamps of the lines of the event log.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct LogTime {
+    pub format: TimeFormat,
+
+    /// Offset of the time zone from UTC [minutes], e.g. `120` for UTC+2. Not used with `unix`.
+    pub utc_offset: i16,
+}
+
+impl Default for LogTime {
+    fn default() -> LogTime {
+        LogTime {
+            format: TimeFormat::Iso8601,
+            utc_offset: 0,
+        }
+    }
+}
+
+impl LogTime {
+    pub fn is_valid(&self) -> bool {
+        self.utc_offset.abs() <= MAX_UTC_OFFSET
+    }
+
+    /// Write the time `t` [s] followed by a space, and the uncalibrated mark if the RTC has not
+    /// been set.
+    pub fn write<W: core::fmt::Write>(&self, w: &mut W, t: i64) -> core::fmt::Result {
+        match self.format {
+            TimeFormat::Unix => write!(w, "{}", t)?,
+            TimeFormat::Iso8601 => {
+                let dt = NaiveDateTime::from_timestamp_opt(t + self.utc_offset as i64 * 60, 0)
+                    .unwrap_or_default();
+
+                write!(
+                    w,
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    dt.year(),
+                    dt.month(),
+                    dt.day(),
+                    dt.hour(),
+                    dt.minute(),
+                    dt.second()
+                )?;
+
+                match self.utc_offset {
+                    0 => w.write_char('Z')?,
+                    o => write!(
+                        w,
+                        "{}{:02}:{:02}",
+                        if o < 0 { '-' } else { '+' },
+                        o.abs() / 60,
+                        o.abs() % 60
+                    )?,
+                }
+            }
+        }
+
+        if t < MIN_CALIBRATED_TIME {
+            w.write_str(" [uncalibrated-time]")?;
+        }
+
+        w.write_char(' ')
+    }
+}
+
+static TIME_FORMAT: AtomicU8 = AtomicU8::new(TimeFormat::Iso8601 as u8);
+static UTC_OFFSET: AtomicI16 = AtomicI16::new(0);
+
+/// Seconds since the epoch, kept up to date by the RTC interrupt (`COUNT` of the firmware).
+static CLOCK: AtomicPtr<AtomicI32> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Set the clock of the time stamps of the event log.
+pub fn set_clock(clock: &'static AtomicI32) {
+    CLOCK.store(
+        clock as *const AtomicI32 as *mut AtomicI32,
+        Ordering::Relaxed,
+    );
+}
+
+/// Set the format of the time stamps of the event log.
+pub fn set_log_time(t: LogTime) {
+    TIME_FORMAT.store(t.format as u8, Ordering::Relaxed);
+    UTC_OFFSET.store(t.utc_offset, Ordering::Relaxed);
+}
+
+pub fn log_time() -> LogTime {
+    LogTime {
+        format: match TIME_FORMAT.load(Ordering::Relaxed) {
+            0 => TimeFormat::Iso8601,
+            _ => TimeFormat::Unix,
+        },
+        utc_offset: UTC_OFFSET.load(Ordering::Relaxed),
+    }
+}
+
+/// Current time of the clock [s], 0 if it has not been set.
+fn now() -> i64 {
+    let clock = CLOCK.load(Ordering::Relaxed);
+
+    if clock.is_null() {
+        0
+    } else {
+        // Safety: the pointer is only ever set from a `&'static AtomicI32`.
+        unsafe { (*clock).load(Ordering::Relaxed) as i64 }
+    }
+}
+
+/// Line of the event log with `msg` logged at `t` [s].
+pub fn log_line(time: &LogTime, t: i64, msg: &str) -> LogLine {
+    let mut line = LogLine::new();
+    time.write(&mut line, t).ok();
+
+    // A message that does not fit is cut at the end.
+    for c in msg.chars() {
+        if line.push(c).is_err() {
+            break;
+        }
+    }
+
+    line
+}
+
+/// Notefile with run-time log levels.
+pub const LOG_LEVELS_FILE: &str = "log.db";
+
+/// Note ID of the log levels in `LOG_LEVELS_FILE`.
+pub const LOG_LEVELS_NOTE: &str = "levels";
+
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq, PartialOrd,
+)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum Level {
+    Off = 0,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn from_u8(v: u8) -> Level {
+        use Level::*;
+
+        match v {
+            0 => Off,
+            1 => Error,
+            2 => Warn,
+            3 => Info,
+            4 => Debug,
+            _ => Trace,
+        }
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Imu = 0,
+    Note,
+    Storage,
+    Location,
+}
+
+/// Default run-time level of all categories.
+pub const DEFAULT_LEVEL: Level = Level::Info;
+
+static LEVELS: [AtomicU8; 4] = [const { AtomicU8::new(DEFAULT_LEVEL as u8) }; 4];
+
+pub fn level(cat: Category) -> Level {
+    Level::from_u8(LEVELS[cat as usize].load(Ordering::Relaxed))
+}
+
+pub fn set_level(cat: Category, level: Level) {
+    LEVELS[cat as usize].store(level as u8, Ordering::Relaxed);
+}
+
+/// Is a message at `level` enabled for `cat`.
+pub fn enabled(cat: Category, level: Level) -> bool {
+    level != Level::Off && level <= self::level(cat)
+}
+
+/// Log levels set from notehub, categories that are not set keep their level.
+#[derive(serde::Serialize, serde::Deserialize, Default, defmt::Format, Debug, PartialEq)]
+pub struct LogLevels {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imu: Option<Level>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<Level>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<Level>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Level>,
+}
+
+impl LogLevels {
+    pub fn apply(&self) {
+        for (cat, level) in [
+            (Category::Imu, self.imu),
+            (Category::Note, self.note),
+            (Category::Storage, self.storage),
+            (Category::Location, self.location),
+        ] {
+            if let Some(level) = level {
+                if level != self::level(cat) {
+                    #[cfg(not(test))]
+                    defmt::info!("Setting log level of {:?} to: {:?}", cat, level);
+                    set_level(cat, level);
+                }
+            }
+        }
+    }
+}
+
+/// Log with `defmt` if `level` is enabled for the category, e.g.:
+///
+/// ```ignore
+/// clog!(Imu, debug, "samples: {}", n);
+/// ```
+#[macro_export]
+macro_rules! clog {
+    ($cat:ident, error, $($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Category::$cat, $crate::log::Level::Error) {
+            defmt::error!($($arg)+);
+        }
+    };
+    ($cat:ident, warn, $($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Category::$cat, $crate::log::Level::Warn) {
+            defmt::warn!($($arg)+);
+        }
+    };
+    ($cat:ident, info, $($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Category::$cat, $crate::log::Level::Info) {
+            defmt::info!($($arg)+);
+        }
+    };
+    ($cat:ident, debug, $($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Category::$cat, $crate::log::Level::Debug) {
+            defmt::debug!($($arg)+);
+        }
+    };
+    ($cat:ident, trace, $($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Category::$cat, $crate::log::Level::Trace) {
+            defmt::trace!($($arg)+);
+        }
+    };
+}
+
+/// Log `msg` with `defmt` if `level` is enabled for the category, and queue it for the Notecard
+/// if it is a warning or an error.
+#[allow(unused)]
+pub fn log_at(cat: Category, level: Level, msg: &str) {
+    if !enabled(cat, level) {
+        return;
+    }
+
+    #[cfg(not(test))]
+    match level {
+        Level::Off => {}
+        Level::Error => defmt::error!("{:?}: {}", cat, msg),
+        Level::Warn => defmt::warn!("{:?}: {}", cat, msg),
+        Level::Info => defmt::info!("{:?}: {}", cat, msg),
+        Level::Debug => defmt::debug!("{:?}: {}", cat, msg),
+        Level::Trace => defmt::trace!("{:?}: {}", cat, msg),
+    }
+
+    if level <= Level::Warn {
+        enqueue(Priority::Critical, msg);
+    }
+}
 
 #[allow(unused)]
 pub fn log(msg: &str) {
     #[cfg(not(test))]
     defmt::debug!("logq: {}", msg);
+    enqueue(Priority::Normal, msg);
+}
+
+/// Append `msg` to the event log on the SD-card only (with the `storage` feature), without
+/// queueing it for the Notecard.
+#[allow(unused)]
+pub fn event(msg: &str) {
+    // The oldest line is dropped when the queue is full, as in the log queue.
+    #[cfg(feature = "storage")]
+    {
+        let mut line = log_line(&log_time(), now(), msg);
+        while let Err(l) = SDLOGQ.enqueue(line) {
+            line = l;
+
+            if let Some(_old) = SDLOGQ.dequeue() {
+                #[cfg(not(test))]
+                defmt::error!("event log queue is full, dropped oldest line: {}", _old);
+            }
+        }
+    }
+}
+
+fn enqueue(prio: Priority, msg: &str) {
+    event(msg);
+
     let mut s = String::new();
     s.push_str(msg).ok();
 
-    LOGQ.enqueue(s)
-        .inspect_err(|e| {
-            #[cfg(not(test))]
-            defmt::error!("failed to queue message: {:?}", e)
-        })
-        .ok();
+    if let Some(_e) = LOGQ.enqueue(prio, s) {
+        #[cfg(not(test))]
+        defmt::error!("log queue is full, dropped oldest message: {:?}", _e);
+    }
+}
+
+/// Pass the lines waiting for the event log on the SD-card to `f`. A line that fails is put back
+/// (at the end of the queue) and the draining stops.
+#[cfg(feature = "storage")]
+pub fn drain_event_log<E>(mut f: impl FnMut(&str) -> Result<(), E>) -> Result<(), E> {
+    while let Some(line) = SDLOGQ.dequeue() {
+        if let Err(e) = f(&line) {
+            SDLOGQ.enqueue(line).ok();
+            return Err(e);
+        }
+    }
+
+    Ok(())
 }
 
 pub fn drain_log<I: Read + Write>(
@@ -32,6 +413,40 @@ pub fn drain_log<I: Read + Write>(
     note.drain_log(&LOGQ, delay)
 }
 
+/// Log message of a panic on one line, cut to fit. Failed assertions (`assert!`, and
+/// `debug_assert!` in debug builds) start with `assertion:` rather than `panic:`, so that they
+/// stand out in the log of pre-release firmware.
+pub fn panic_message(info: &impl core::fmt::Display) -> String<256> {
+    // The rest of a message that does not fit is dropped.
+    let mut full = String::<256>::new();
+    write!(&mut full, "{}", info).ok();
+
+    let mut msg = String::new();
+    if full.contains("assertion") {
+        msg.push_str("assertion: ").ok();
+    } else {
+        msg.push_str("panic: ").ok();
+    }
+
+    for c in full.chars().map(|c| if c == '\n' { ' ' } else { c }) {
+        if msg.push(c).is_err() {
+            break;
+        }
+    }
+
+    msg
+}
+
+/// Queue the message of a panic (see [`panic_message`]) for the Notecard, with critical priority
+/// so that it is not dropped before [`panic_drain_log`].
+pub fn log_panic(info: &impl core::fmt::Display) {
+    let msg = panic_message(info);
+
+    #[cfg(not(test))]
+    defmt::error!("{}", msg.as_str());
+    enqueue(Priority::Critical, &msg);
+}
+
 /// Tries to send the remaining queue to notecard in case of panic or HardFault. Must be wrapped
 /// in free() to avoid multiple access.
 pub unsafe fn panic_drain_log<IOM: Read + Write>(
@@ -60,6 +475,94 @@ pub unsafe fn panic_drain_log<IOM: Read + Write>(
 mod tests {
     use super::*;
 
+    #[test]
+    fn panic_messages() {
+        assert_eq!(
+            panic_message(&"panicked at src/lib.rs:10:5:\nassertion failed: n > 1"),
+            "assertion: panicked at src/lib.rs:10:5: assertion failed: n > 1"
+        );
+        assert_eq!(
+            panic_message(&"panicked at src/lib.rs:10:5:\nassertion `left == right` failed"),
+            "assertion: panicked at src/lib.rs:10:5: assertion `left == right` failed"
+        );
+        assert_eq!(
+            panic_message(&"panicked at src/lib.rs:12:9:\nIMU has failed"),
+            "panic: panicked at src/lib.rs:12:9: IMU has failed"
+        );
+
+        let long = panic_message(&"x".repeat(300));
+        assert_eq!(long.len(), 256);
+        assert!(long.starts_with("panic: xxx"));
+    }
+
+    #[test]
+    fn time_formats() {
+        let t = 1_714_564_803; // 2024-05-01T12:00:03Z
+
+        let line = |time: LogTime, t| log_line(&time, t, "msg");
+
+        assert_eq!(line(LogTime::default(), t), "2024-05-01T12:00:03Z msg");
+        assert_eq!(
+            line(
+                LogTime {
+                    format: TimeFormat::Iso8601,
+                    utc_offset: 120
+                },
+                t
+            ),
+            "2024-05-01T14:00:03+02:00 msg"
+        );
+        assert_eq!(
+            line(
+                LogTime {
+                    format: TimeFormat::Iso8601,
+                    utc_offset: -210
+                },
+                t
+            ),
+            "2024-05-01T08:30:03-03:30 msg"
+        );
+        assert_eq!(
+            line(
+                LogTime {
+                    format: TimeFormat::Unix,
+                    utc_offset: 120
+                },
+                t
+            ),
+            "1714564803 msg"
+        );
+
+        // The lines sort by time.
+        assert!(line(LogTime::default(), t) < line(LogTime::default(), t + 1));
+    }
+
+    #[test]
+    fn uncalibrated_time() {
+        assert_eq!(
+            log_line(&LogTime::default(), 131, "msg"),
+            "1970-01-01T00:02:11Z [uncalibrated-time] msg"
+        );
+    }
+
+    #[test]
+    fn long_message_is_cut() {
+        let msg = "a".repeat(LOG_LINE_SZ);
+        let line = log_line(&LogTime::default(), 1_714_564_803, &msg);
+        assert_eq!(line.len(), LOG_LINE_SZ);
+        assert!(line.starts_with("2024-05-01T12:00:03Z aaa"));
+    }
+
+    #[test]
+    fn log_time_is_valid() {
+        assert!(LogTime::default().is_valid());
+        assert!(!LogTime {
+            format: TimeFormat::Iso8601,
+            utc_offset: 15 * 60
+        }
+        .is_valid());
+    }
+
     #[test]
     fn log_short_mesg() {
         log("asdfasdf");
@@ -82,6 +585,26 @@ mod tests {
         write!(&mut s, "test: {}", "rrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrrr").ok();
     }
 
+    #[test]
+    fn levels() {
+        assert_eq!(level(Category::Storage), DEFAULT_LEVEL);
+        assert!(enabled(Category::Storage, Level::Info));
+        assert!(!enabled(Category::Storage, Level::Debug));
+
+        let l: LogLevels = serde_json::from_str(r#"{ "storage": "trace" }"#).unwrap();
+        assert_eq!(l.imu, None);
+        l.apply();
+
+        assert_eq!(level(Category::Storage), Level::Trace);
+        assert!(enabled(Category::Storage, Level::Trace));
+
+        set_level(Category::Storage, Level::Off);
+        assert!(!enabled(Category::Storage, Level::Error));
+        assert!(!enabled(Category::Storage, Level::Off));
+
+        set_level(Category::Storage, DEFAULT_LEVEL);
+    }
+
     #[test]
     fn exhaust_queue() {
         for _ in 0..256 {
@@ -0,0 +1,249 @@
+//! A small LZ77 compressor in the style of the LZ4 block format, for the `lz` compression of
+//! backfill batches (see `backfill::Compression`).
+//!
+//! The compressed block is a list of sequences. A sequence starts with a token: the high nibble
+//! is the number of literals, the low nibble the length of the match minus [`MIN_MATCH`]. A
+//! nibble of 15 is continued by bytes that are added to it, until a byte below 255. The literals
+//! follow, then the offset back to the match (`u16`, little endian) and the continued match
+//! length. The last sequence has only literals, and ends the block.
+//!
+//! Matches are found through a single hash table of the last position of every 4-byte prefix,
+//! without chains: the compression is modest, but takes one pass and a fixed 4 kB of stack.
+
+/// Shortest match that is encoded as a match.
+pub const MIN_MATCH: usize = 4;
+
+/// Longest distance back to a match.
+pub const MAX_OFFSET: usize = u16::MAX as usize;
+
+const HASH_BITS: u32 = 10;
+
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub enum LzError {
+    /// The output does not fit in the buffer.
+    Overflow,
+
+    /// The compressed block is truncated, or refers before the start of the output.
+    Corrupt,
+}
+
+fn hash(v: u32) -> usize {
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn read32(buf: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]])
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    n: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn push(&mut self, b: u8) -> Result<(), LzError> {
+        *self.buf.get_mut(self.n).ok_or(LzError::Overflow)? = b;
+        self.n += 1;
+        Ok(())
+    }
+
+    fn extend(&mut self, s: &[u8]) -> Result<(), LzError> {
+        self.buf
+            .get_mut(self.n..self.n + s.len())
+            .ok_or(LzError::Overflow)?
+            .copy_from_slice(s);
+        self.n += s.len();
+        Ok(())
+    }
+
+    /// Continuation of a nibble of 15.
+    fn length(&mut self, mut n: usize) -> Result<(), LzError> {
+        while n >= 255 {
+            self.push(255)?;
+            n -= 255;
+        }
+        self.push(n as u8)
+    }
+
+    fn sequence(&mut self, literals: &[u8], m: Option<(usize, usize)>) -> Result<(), LzError> {
+        let ml = m.map(|(_, len)| len - MIN_MATCH).unwrap_or(0);
+        self.push(((literals.len().min(15) as u8) << 4) | ml.min(15) as u8)?;
+
+        if literals.len() >= 15 {
+            self.length(literals.len() - 15)?;
+        }
+        self.extend(literals)?;
+
+        if let Some((offset, _)) = m {
+            self.extend(&(offset as u16).to_le_bytes())?;
+
+            if ml >= 15 {
+                self.length(ml - 15)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Compress `src` into `dst`, returns the length of the compressed block.
+pub fn compress(src: &[u8], dst: &mut [u8]) -> Result<usize, LzError> {
+    // Last position + 1 of every hash, 0 is none.
+    let mut table = [0u32; 1 << HASH_BITS];
+    let mut w = Writer { buf: dst, n: 0 };
+
+    let mut anchor = 0;
+    let mut i = 0;
+
+    while i + MIN_MATCH <= src.len() {
+        let h = hash(read32(src, i));
+        let candidate = table[h] as usize;
+        table[h] = i as u32 + 1;
+
+        if candidate > 0 {
+            let c = candidate - 1;
+
+            if i - c <= MAX_OFFSET && src[c..c + MIN_MATCH] == src[i..i + MIN_MATCH] {
+                let mut len = MIN_MATCH;
+                while i + len < src.len() && src[c + len] == src[i + len] {
+                    len += 1;
+                }
+
+                w.sequence(&src[anchor..i], Some((i - c, len)))?;
+                i += len;
+                anchor = i;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    w.sequence(&src[anchor..], None)?;
+
+    Ok(w.n)
+}
+
+/// Decompress the block `src` into `dst`, returns the length of the output.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Result<usize, LzError> {
+    let mut i = 0;
+    let mut o = 0;
+
+    let length = |i: &mut usize, mut n: usize| -> Result<usize, LzError> {
+        loop {
+            let b = *src.get(*i).ok_or(LzError::Corrupt)?;
+            *i += 1;
+            n += b as usize;
+
+            if b < 255 {
+                return Ok(n);
+            }
+        }
+    };
+
+    loop {
+        let token = *src.get(i).ok_or(LzError::Corrupt)?;
+        i += 1;
+
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals = length(&mut i, literals)?;
+        }
+
+        let l = src.get(i..i + literals).ok_or(LzError::Corrupt)?;
+        dst.get_mut(o..o + literals)
+            .ok_or(LzError::Overflow)?
+            .copy_from_slice(l);
+        i += literals;
+        o += literals;
+
+        if i == src.len() {
+            return Ok(o);
+        }
+
+        let offset = src.get(i..i + 2).ok_or(LzError::Corrupt)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        i += 2;
+
+        if offset == 0 || offset > o {
+            return Err(LzError::Corrupt);
+        }
+
+        let mut len = (token & 0xf) as usize;
+        if len == 15 {
+            len = length(&mut i, len)?;
+        }
+        len += MIN_MATCH;
+
+        if o + len > dst.len() {
+            return Err(LzError::Overflow);
+        }
+
+        // The match may overlap the output it is copied to.
+        let start = o - offset;
+        for k in 0..len {
+            dst[o + k] = dst[start + k];
+        }
+        o += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(src: &[u8]) -> usize {
+        let mut c = [0u8; 4096];
+        let n = compress(src, &mut c).unwrap();
+
+        let mut d = [0u8; 4096];
+        let m = decompress(&c[..n], &mut d).unwrap();
+        assert_eq!(&d[..m], src);
+
+        n
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(round_trip(&[]), 1);
+        assert_eq!(round_trip(&[1, 2, 3]), 4);
+
+        // Runs are overlapping matches.
+        assert!(round_trip(&[7; 1000]) < 10);
+
+        let repeated: std::vec::Vec<u8> = (0..2000).map(|i| (i % 37) as u8).collect();
+        assert!(round_trip(&repeated) < 60);
+
+        // Literals that do not compress, with continued lengths.
+        let mut x = 0x1234_5678u32;
+        let noise: std::vec::Vec<u8> = (0..1500)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        assert!(round_trip(&noise) <= noise.len() + 1 + noise.len() / 255 + 1);
+    }
+
+    #[test]
+    fn errors() {
+        let src = [5u8; 300];
+        let mut c = [0u8; 64];
+        let n = compress(&src, &mut c).unwrap();
+
+        assert_eq!(compress(&src, &mut [0u8; 4]), Err(LzError::Overflow));
+
+        let mut d = [0u8; 300];
+        assert_eq!(decompress(&c[..n], &mut d[..299]), Err(LzError::Overflow));
+        assert_eq!(decompress(&c[..n - 1], &mut d), Err(LzError::Corrupt));
+        assert_eq!(decompress(&[], &mut d), Err(LzError::Corrupt));
+
+        // Offset before the start of the output.
+        assert_eq!(
+            decompress(&[0x10, 1, 2, 0, 0], &mut d),
+            Err(LzError::Corrupt)
+        );
+    }
+}
@@ -1,8 +1,25 @@
-use crate::axl::{AxlPacket, AXL_OUTN};
-use blues_notecard::{self as notecard, NoteError, Notecard, NotecardConfig};
+use crate::axl::{AxlPacket, AXL_OUTN, FILLED_STR_SZ};
+use crate::cmd::{self, Command, CommandAck, CommandNote};
+use crate::config::{self, Config, ConfigOverride};
+use crate::gate::{Decision, Gate};
+pub use crate::health::Health;
+use crate::log::{self, LogLevels};
+use crate::pace::{Pacer, TIMEOUTS};
+use crate::provision::{self, Provision};
+use crate::queue::{self, CardState, CARD_PRESSURE, DROPPED, STORAGE_PRESSURE};
+use crate::quiet::{Quiet, Transition};
+use crate::sync_history::SyncHistory;
+use crate::tamper::{Alarm, Motion, Tamper};
+use crate::trace::Traced;
+use crate::transport::{Bearer, Transport};
+use crate::urgency::Urgency;
+pub use blues_notecard::NoteError;
+use blues_notecard::{self as notecard, Notecard, NotecardConfig};
+use core::fmt::Write as _;
 use core::ops::{Deref, DerefMut};
 use embedded_hal::blocking::delay::DelayMs;
 use embedded_hal::blocking::i2c::{Read, Write};
+use serde::de::DeserializeOwned;
 
 use crate::NOTEQ_SZ;
 
@@ -20,8 +37,336 @@ include!(concat!(env!("OUT_DIR"), "/config.rs"));
 /// Initialize sync when storage use is above this percentage.
 pub const NOTECARD_STORAGE_INIT_SYNC: u32 = 65;
 
+/// Interval between polls for the response to a request [ms].
+const POLL_INTERVAL: u16 = 25;
+
+/// Maximum time to wait for the final sync on shutdown [ms].
+pub const SHUTDOWN_SYNC_TIMEOUT: u16 = 30_000;
+
+/// Maximum time to flush the queues before a shutdown [ms] (see [`Notecarrier::flush_queue`]).
+pub const FLUSH_TIMEOUT: u32 = 120_000;
+
+/// The sync period is shortened by at most `1 / SYNC_JITTER_DIV` of the period (see
+/// [`sync_jitter`]).
+pub const SYNC_JITTER_DIV: u32 = 10;
+
+/// Maximum jitter of the sync period [minutes].
+pub const MAX_SYNC_JITTER: u32 = 30;
+
+/// Per-device jitter [minutes] subtracted from the sync period (`sync_period`, minutes), so that
+/// a fleet of identically configured buoys does not sync in lock-step and congest a shared cell.
+/// Derived from the hardware ID of the device (`device_id`), so it is stable across reboots. At
+/// most a tenth of the period (`SYNC_JITTER_DIV`) and `MAX_SYNC_JITTER`, the period is never
+/// longer than configured.
+pub fn sync_jitter(device_id: u64, sync_period: u32) -> u32 {
+    let max = (sync_period / SYNC_JITTER_DIV).min(MAX_SYNC_JITTER);
+
+    // FNV-1a
+    let h = device_id
+        .to_le_bytes()
+        .iter()
+        .fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ *b as u64).wrapping_mul(0x100000001b3)
+        });
+
+    (h % (max as u64 + 1)) as u32
+}
+
+/// The Notecard rejected a note because the body does not match the template of the notefile,
+/// e.g. after a firmware update changed the body and the templates were not set up again. The
+/// error message of the Notecard mentions the template.
+pub fn is_template_mismatch(e: &NoteError) -> bool {
+    match e {
+        NoteError::NotecardErr(msg) => msg.contains("template"),
+        _ => false,
+    }
+}
+
+/// Wait for the response to a request with a configurable timeout (see `config::Timeouts`),
+/// rather than the fixed timeout of `FutureResponse::wait`.
+pub trait WaitTimeout<T> {
+    /// Poll for the response for up to `timeout` ms. No response in time fails with
+    /// `NoteError::TimeOut`, any other error is a communication or Notecard error and is returned
+    /// immediately.
+    fn wait_for(self, delay: &mut impl DelayMs<u16>, timeout: u32) -> Result<T, NoteError>;
+}
+
+impl<T: DeserializeOwned, I2C: Read + Write> WaitTimeout<T>
+    for notecard::FutureResponse<'_, T, I2C>
+{
+    fn wait_for(mut self, delay: &mut impl DelayMs<u16>, timeout: u32) -> Result<T, NoteError> {
+        let mut waited = 0;
+
+        loop {
+            if let Some(r) = self.poll(delay)? {
+                crate::clog!(Note, debug, "Response after {} ms.", waited);
+                return Ok(r);
+            }
+
+            if waited >= timeout {
+                crate::clog!(Note, warn, "No response after {} ms, timing out.", waited);
+                TIMEOUTS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+                return Err(NoteError::TimeOut);
+            }
+
+            delay.delay_ms(POLL_INTERVAL);
+            waited += POLL_INTERVAL as u32;
+        }
+    }
+}
+
+/// Health note is sent at this interval [ms].
+pub const HEALTH_INTERVAL: i64 = 60 * 60_000;
+
 pub struct Notecarrier<I2C: Read + Write> {
-    note: Notecard<I2C>,
+    /// The bus traces the requests and responses when enabled (see [`crate::trace`]).
+    note: Notecard<Traced<I2C>>,
+    config: Config,
+
+    /// Hardware ID of the device, seeds the sync jitter (see [`sync_jitter`]).
+    device_id: u64,
+
+    /// Serial number of the buoy on notehub.
+    serial: &'static str,
+
+    /// Continuous connection to notehub, rather than periodic syncs.
+    continuous: bool,
+
+    notefiles: Notefiles,
+
+    /// Syncs with notehub, updated by `check_and_sync`.
+    pub sync_history: SyncHistory,
+
+    /// Quiet hours, updated by `check_quiet`.
+    quiet: Quiet,
+
+    /// Impact alarms, updated by `check_motion`.
+    tamper: Tamper,
+
+    /// Motion gate, updated for every package by `drain_queue`.
+    gate: Gate,
+
+    /// Request of stored packages being replayed (first and last ID), updated by
+    /// `read_storage_info`. Replayed packages are not suppressed as duplicates (see `dedup`).
+    replay: Option<(u32, u32)>,
+
+    /// Notes waiting on the notecard to be synced at the last check, `None` if not known (see
+    /// `queue::CardQueue`).
+    card_queue: Option<u32>,
+
+    /// Time now [ms] from the RTC, for pacing the requests (see [`crate::pace`]).
+    clock: fn() -> Option<i64>,
+    pacer: Pacer,
+
+    /// Live packages waiting to be sent together, see `backfill::LiveBatch`.
+    #[cfg(not(feature = "encryption"))]
+    live: crate::backfill::Batch,
+}
+
+/// Outbound notefiles.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Notefiles {
+    /// Data packages.
+    pub axl: &'static str,
+
+    /// Statistics of the packages.
+    pub stats: &'static str,
+
+    /// Health notes (see [`Health`]).
+    pub health: &'static str,
+
+    /// Compressed batches of stored packages (see [`crate::backfill`]).
+    pub backfill: &'static str,
+}
+
+impl Default for Notefiles {
+    fn default() -> Notefiles {
+        Notefiles {
+            axl: "axl.qo",
+            stats: "stats.qo",
+            health: "health.qo",
+            backfill: "backfill.qo",
+        }
+    }
+}
+
+/// Set up a [`Notecarrier`] step by step. Everything that is not set takes the default: the
+/// compiled default config (see [`Config`]), `BUOYSN`, the sync mode of the `continuous` feature
+/// and the default notefiles. The config is still overridden by the `sfy_config` environment
+/// variable and the `config.db` note on the notecard when built.
+///
+/// ```ignore
+/// let note = NotecarrierBuilder::new()
+///     .product("com.example:buoys")?
+///     .sync_period(20)
+///     .gps_period(120)
+///     .device_id(device_id)
+///     .build(i2c, &mut delay)?;
+/// ```
+pub struct NotecarrierBuilder {
+    config: Config,
+    device_id: u64,
+    serial: &'static str,
+    continuous: bool,
+    notefiles: Notefiles,
+    clock: fn() -> Option<i64>,
+
+    /// Delays between the chunks and segments of a request to the notecard on the I2C bus [ms].
+    chunk_delay: u16,
+    segment_delay: u16,
+}
+
+impl Default for NotecarrierBuilder {
+    fn default() -> NotecarrierBuilder {
+        NotecarrierBuilder {
+            config: Config::default(),
+            device_id: 0,
+            serial: BUOYSN,
+            continuous: cfg!(feature = "continuous"),
+            notefiles: Notefiles::default(),
+            clock: || None,
+            chunk_delay: 5,
+            segment_delay: 20,
+        }
+    }
+}
+
+impl NotecarrierBuilder {
+    pub fn new() -> NotecarrierBuilder {
+        NotecarrierBuilder::default()
+    }
+
+    /// Start from `config` (e.g. read from the SD-card), the other setters override its fields.
+    pub fn config(mut self, config: Config) -> NotecarrierBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Notehub product UID, at most 64 bytes.
+    pub fn product(mut self, product: &str) -> Result<NotecarrierBuilder, config::ConfigError> {
+        self.config.product.clear();
+        self.config
+            .product
+            .push_str(product)
+            .map_err(|_| config::ConfigError::ProductTooLong(product.len()))?;
+        Ok(self)
+    }
+
+    /// Serial number of the buoy on notehub.
+    pub fn serial(mut self, serial: &'static str) -> NotecarrierBuilder {
+        self.serial = serial;
+        self
+    }
+
+    /// Hardware ID of the device, seeds the sync jitter (see [`sync_jitter`]).
+    pub fn device_id(mut self, device_id: u64) -> NotecarrierBuilder {
+        self.device_id = device_id;
+        self
+    }
+
+    /// Maximum time between outbound syncs [minutes].
+    pub fn sync_period(mut self, minutes: u32) -> NotecarrierBuilder {
+        self.config.sync_period = minutes;
+        self
+    }
+
+    /// Keep a continuous connection to notehub and sync every note immediately, rather than
+    /// periodic syncs. The GPS is not used in continuous mode.
+    pub fn continuous(mut self, continuous: bool) -> NotecarrierBuilder {
+        self.continuous = continuous;
+        self
+    }
+
+    /// Period of periodic GPS fixes [s].
+    pub fn gps_period(mut self, seconds: u32) -> NotecarrierBuilder {
+        self.config.gps_period = seconds;
+        self
+    }
+
+    pub fn notefiles(mut self, notefiles: Notefiles) -> NotecarrierBuilder {
+        self.notefiles = notefiles;
+        self
+    }
+
+    /// Time to wait for responses from the notecard (see `config::Timeouts`).
+    pub fn timeouts(mut self, timeouts: config::Timeouts) -> NotecarrierBuilder {
+        self.config.timeouts = timeouts;
+        self
+    }
+
+    /// Time now [ms], `None` if not available, for pacing the requests to the notecard (see
+    /// [`crate::pace`]). Without a clock the full spacing is waited before every paced request.
+    pub fn clock(mut self, clock: fn() -> Option<i64>) -> NotecarrierBuilder {
+        self.clock = clock;
+        self
+    }
+
+    /// Delays between the chunks and segments of a request on the I2C bus [ms], increase them if
+    /// requests to the notecard fail on a slow or long bus.
+    pub fn delays(mut self, chunk_delay: u16, segment_delay: u16) -> NotecarrierBuilder {
+        self.chunk_delay = chunk_delay;
+        self.segment_delay = segment_delay;
+        self
+    }
+
+    /// Probe and set up the notecard.
+    pub fn build<I2C: Read + Write>(
+        self,
+        mut i2c: I2C,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Notecarrier<I2C>, NoteError> {
+        let address = self.config.notecard_address;
+
+        defmt::debug!("probing notecard at: {:#x}..", address);
+        if i2c.write(address, &[]).is_err() {
+            defmt::error!("No notecard answering at address {:#x}", address);
+            return Err(NoteError::NotecardErr("no notecard at address".into()));
+        }
+
+        if address != config::NOTECARD_ADDRESS {
+            defmt::error!(
+                "Notecard at non-default address {:#x} is not supported by the driver.",
+                address
+            );
+            return Err(NoteError::NotecardErr(
+                "unsupported notecard address".into(),
+            ));
+        }
+
+        let note = Notecard::new_with_config(
+            Traced::new(i2c, address),
+            NotecardConfig {
+                chunk_delay: self.chunk_delay,
+                segment_delay: self.segment_delay,
+                ..Default::default()
+            },
+        );
+
+        let mut n = Notecarrier {
+            note,
+            config: self.config,
+            device_id: self.device_id,
+            serial: self.serial,
+            continuous: self.continuous,
+            notefiles: self.notefiles,
+            sync_history: SyncHistory::new(),
+            quiet: Quiet::new(),
+            tamper: Tamper::new(),
+            gate: Gate::new(),
+            replay: None,
+            card_queue: None,
+            clock: self.clock,
+            pacer: Pacer::new(),
+            #[cfg(not(feature = "encryption"))]
+            live: crate::backfill::Batch::with_limit(
+                self.config.backfill_compression,
+                self.config.max_note_size,
+            ),
+        };
+        n.setup(delay)?;
+
+        Ok(n)
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Default, defmt::Format, PartialEq)]
@@ -40,35 +385,181 @@ pub struct RequestData {
 }
 
 impl<I2C: Read + Write> Notecarrier<I2C> {
-    pub fn new(i2c: I2C, delay: &mut impl DelayMs<u16>) -> Result<Notecarrier<I2C>, NoteError> {
-        let mut note = Notecard::new_with_config(
-            i2c,
-            NotecardConfig {
-                chunk_delay: 5,
-                segment_delay: 20,
-                ..Default::default()
-            },
+    /// Set up the notecard. The `config` is overridden by the `sfy_config` environment variable
+    /// and the `config.db` note on the notecard (if any), use `config()` to get the effective
+    /// config. `device_id` is a hardware ID of the device, used to spread the syncs of a fleet
+    /// (see [`sync_jitter`]). Everything else takes the defaults, see [`NotecarrierBuilder`].
+    pub fn new(
+        i2c: I2C,
+        config: Config,
+        device_id: u64,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Notecarrier<I2C>, NoteError> {
+        NotecarrierBuilder::new()
+            .config(config)
+            .device_id(device_id)
+            .build(i2c, delay)
+    }
+
+    /// Initialize and configure the notecard.
+    fn setup(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
+        self.note.initialize(delay)?;
+
+        match self.read_env_config(delay) {
+            Ok(Some(t)) => {
+                defmt::info!("Applying config override from environment: {}", t.as_str());
+                self.config
+                    .apply_json(t.as_bytes())
+                    .inspect_err(|e| defmt::error!("Invalid config environment variable: {:?}", e))
+                    .ok();
+            }
+            Ok(None) => {}
+            Err(e) => defmt::error!("Failed to read config environment variable: {:?}", e),
+        }
+
+        match self.read_config(delay) {
+            Ok(Some(o)) => {
+                defmt::info!("Applying config override from notecard: {:?}", o);
+                self.config
+                    .apply(&o)
+                    .inspect_err(|e| defmt::error!("Invalid config override: {:?}", e))
+                    .ok();
+            }
+            Ok(None) => {}
+            Err(e) => defmt::error!("Failed to read config override: {:?}", e),
+        }
+
+        match self.read_log_levels(delay) {
+            Ok(Some(l)) => l.apply(),
+            Ok(None) => {}
+            Err(e) => defmt::error!("Failed to read log levels: {:?}", e),
+        }
+
+        if self.config.transport.satellite() && self.config.products.timeseries {
+            log::log_at(
+                log::Category::Note,
+                log::Level::Warn,
+                "Satellite transport with the time series enabled, check the data budget.",
+            );
+        }
+
+        let outbound = self.outbound();
+        defmt::info!(
+            "Sync period: {} minutes (configured: {}, jitter: {})",
+            outbound,
+            self.config.sync_period,
+            self.config.sync_period - outbound
         );
-        note.initialize(delay)?;
+
+        let provision = self.provision_record();
+        let stored = self
+            .read_provision(delay)
+            .inspect_err(|e| defmt::error!("Failed to read provisioning record: {:?}", e))
+            .unwrap_or(None);
+
+        match provision::Boot::of(stored, provision) {
+            provision::Boot::Restore => {
+                defmt::info!("Notecard is provisioned ({:?}), skipping setup.", provision);
+
+                // The hub is left in minimum mode by `shutdown` before a planned reset.
+                self.set_hub(self.hub_mode(), Some(self.outbound()), delay)?;
+            }
+            provision::Boot::Provision => {
+                defmt::info!("Provisioning notecard: {:?} (was: {:?})", provision, stored);
+                self.provision(delay)?;
+            }
+        }
+
+        let version = self
+            .note
+            .card()
+            .version(delay)?
+            .wait_for(delay, self.config.timeouts.request)?;
+        defmt::info!("Notecard version: {:?}", version);
+
+        defmt::info!("initializing initial sync ..");
+        self.note
+            .hub()
+            .sync(delay, false)?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        Ok(())
+    }
+
+    /// Provisioning record of the current setup (see `provision`).
+    fn provision_record(&self) -> Provision {
+        Provision::new(&provision::Setup {
+            product: self.config.product.as_str(),
+            serial: self.serial,
+            continuous: self.continuous,
+            outbound: self.outbound(),
+            gps_period: self.config.gps_period,
+            transport: self.config.transport.as_str(),
+            note_summary: self.config.note_summary,
+        })
+    }
+
+    /// Read the provisioning record from the notecard. Returns `Ok(None)` if there is none.
+    pub fn read_provision(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Option<Provision>, NoteError> {
+        Ok(self
+            .note
+            .note()
+            .get(
+                delay,
+                provision::PROVISION_FILE,
+                provision::PROVISION_NOTE,
+                false,
+                false,
+            )?
+            .wait_for(delay, self.config.timeouts.request)
+            .map(|r| r.body)
+            .unwrap_or(None))
+    }
+
+    fn write_provision(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
+        let p = self.provision_record();
+
+        self.note
+            .note()
+            .update(
+                delay,
+                provision::PROVISION_FILE,
+                provision::PROVISION_NOTE,
+                Some(p),
+                None,
+                false,
+            )?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        Ok(())
+    }
+
+    /// Do the full setup of the notecard (`hub.set`, transport, location and templates), and store
+    /// the provisioning record. Used on the first boot, when the setup changes and by the
+    /// `provision` command.
+    pub fn provision(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
+        let outbound = self.outbound();
+        let mode = self.hub_mode();
+        let note = &mut self.note;
 
         // Location mode is not supported when in continuous mode.
-        #[cfg(feature = "continuous")]
-        note.card()
-            .location_mode(delay, Some("off"), None, None, None, None, None, None, None)?
-            .wait(delay)?;
+        if self.continuous {
+            note.card()
+                .location_mode(delay, Some("off"), None, None, None, None, None, None, None)?
+                .wait_for(delay, self.config.timeouts.request)?;
+        }
 
         note.hub()
             .set(
                 delay,
-                Some(env!("BUOYPR", "Specify notehub project")),
+                Some(self.config.product.as_str()),
                 None,
-                if cfg!(feature = "continuous") {
-                    Some(notecard::hub::req::HubMode::Continuous)
-                } else {
-                    Some(notecard::hub::req::HubMode::Periodic)
-                },
-                Some(BUOYSN),
-                Some(40), // max time between out-going sync in minutes.
+                Some(mode),
+                Some(self.serial),
+                Some(outbound), // max time between out-going sync in minutes.
                 None,
                 None,
                 None,
@@ -76,14 +567,160 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
                 Some(false),
                 None,
             )?
-            .wait(delay)?;
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        // Older notecards do not know `card.transport`, and only have the default transport.
+        let transport = self.config.transport;
+        if let Err(e) = note
+            .card()
+            .transport(delay, Some(transport.as_str()))
+            .and_then(|r| r.wait_for(delay, self.config.timeouts.request))
+        {
+            let mut msg = heapless::String::<128>::new();
+            write!(
+                &mut msg,
+                "Failed to set transport {}: {:?}",
+                transport.as_str(),
+                e
+            )
+            .ok();
+
+            let level = match transport {
+                Transport::Default => log::Level::Warn,
+                _ => log::Level::Error,
+            };
+            log::log_at(log::Category::Note, level, &msg);
+        }
+
+        if !self.continuous {
+            note.card()
+                .location_mode(
+                    delay,
+                    Some("periodic"),
+                    Some(self.config.gps_period),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                )?
+                .wait_for(delay, self.config.timeouts.request)?;
+        }
 
-        #[cfg(not(feature = "continuous"))]
         note.card()
-            .location_mode(
+            .location_track(delay, true, true, false, Some(1), None)?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        self.setup_templates(delay)?;
+        self.write_provision(delay)?;
+
+        defmt::info!("Notecard provisioned.");
+
+        Ok(())
+    }
+
+    /// Configured mode of the hub.
+    fn hub_mode(&self) -> notecard::hub::req::HubMode {
+        if self.continuous {
+            notecard::hub::req::HubMode::Continuous
+        } else {
+            notecard::hub::req::HubMode::Periodic
+        }
+    }
+
+    /// Wait until `request_spacing` has passed since the last paced request (see [`crate::pace`]).
+    /// Timeouts since the last paced request are logged.
+    fn pace(&mut self, delay: &mut impl DelayMs<u16>) {
+        let spacing = self
+            .config
+            .card_queue
+            .spacing(self.card_queue, self.config.request_spacing);
+        let wait = self.pacer.wait(spacing, (self.clock)());
+
+        if wait > 0 {
+            delay.delay_ms(wait);
+        }
+
+        let timeouts = self.pacer.sent((self.clock)());
+        if timeouts > 0 {
+            let mut msg = heapless::String::<128>::new();
+            write!(
+                &mut msg,
+                "{} notecard request(s) timed out with a spacing of {} ms ({} since boot).",
+                timeouts,
+                spacing,
+                TIMEOUTS.load(core::sync::atomic::Ordering::Relaxed)
+            )
+            .ok();
+            log::log_at(log::Category::Note, log::Level::Warn, &msg);
+        }
+    }
+
+    /// Whether a note of `urgency` is synced as it is added (see [`crate::urgency`]).
+    fn sync_notes(&self, urgency: Urgency) -> bool {
+        urgency.sync(self.continuous, self.is_quiet())
+    }
+
+    /// In the quiet hours at the last `check_quiet` (see [`crate::quiet`]).
+    pub fn is_quiet(&self) -> bool {
+        self.quiet.is_active()
+    }
+
+    /// Check the quiet hours at `now` (ms): the hub is set to minimum mode when the window starts,
+    /// and the configured mode is restored and a sync started when it ends. The check is repeated
+    /// on the next call if the Notecard fails.
+    pub fn check_quiet(
+        &mut self,
+        now: i64,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), NoteError> {
+        let last = self.quiet.clone();
+
+        let t = match self.quiet.update(&self.config.quiet_hours, now) {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
+        crate::clog!(Note, info, "Quiet hours: {:?}", t);
+
+        self.set_quiet(t, delay).inspect_err(|_| self.quiet = last)
+    }
+
+    fn set_quiet(&mut self, t: Transition, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
+        let (mode, outbound) = match t {
+            Transition::Start => (notecard::hub::req::HubMode::Minimum, None),
+            Transition::End | Transition::Outside => (self.hub_mode(), Some(self.outbound())),
+        };
+
+        self.set_hub(mode, outbound, delay)?;
+
+        if t == Transition::End {
+            self.note
+                .hub()
+                .sync(delay, false)?
+                .wait_for(delay, self.config.timeouts.request)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the mode of the hub, and the maximum time between outbound syncs [minutes] if given.
+    fn set_hub(
+        &mut self,
+        mode: notecard::hub::req::HubMode,
+        outbound: Option<u32>,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), NoteError> {
+        self.note
+            .hub()
+            .set(
                 delay,
-                Some("periodic"),
-                Some(GPS_PERIOD),
+                None,
+                None,
+                Some(mode),
+                None,
+                outbound,
                 None,
                 None,
                 None,
@@ -91,22 +728,163 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
                 None,
                 None,
             )?
-            .wait(delay)?;
+            .wait_for(delay, self.config.timeouts.request)?;
 
-        note.card()
-            .location_track(delay, true, true, false, Some(1), None)?
-            .wait(delay)?;
+        Ok(())
+    }
 
-        let version = note.card().version(delay)?.wait(delay)?;
-        defmt::info!("Notecard version: {:?}", version);
+    /// The effective config.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Maximum time between outbound syncs set on the notecard [minutes]: the configured period
+    /// with the jitter of this device subtracted.
+    pub fn outbound(&self) -> u32 {
+        self.config.sync_period - sync_jitter(self.device_id, self.config.sync_period)
+    }
 
-        let mut n = Notecarrier { note };
-        n.setup_templates(delay)?;
+    /// Read the config override in the environment variable `CONFIG_ENV` (set for the fleet,
+    /// project or device on notehub). `None` when the variable is not set, empty, or longer than
+    /// `CONFIG_SZ`. A failed request is an error, not an unset variable.
+    pub fn read_env_config(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Option<heapless::String<{ config::CONFIG_SZ }>>, NoteError> {
+        let text = self
+            .note
+            .env()
+            .get(delay, config::CONFIG_ENV)?
+            .wait_for(delay, self.config.timeouts.request)?
+            .text;
+
+        Ok(text.and_then(|t| {
+            let mut s = heapless::String::new();
+            s.push_str(t.trim())
+                .inspect_err(|_| defmt::error!("Config environment variable too long."))
+                .ok()?;
+            (!s.is_empty()).then_some(s)
+        }))
+    }
 
-        defmt::info!("initializing initial sync ..");
-        n.note.hub().sync(delay, false)?.wait(delay)?;
+    /// Read the config override set on notehub.
+    pub fn read_config(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Option<ConfigOverride>, NoteError> {
+        Ok(self
+            .note
+            .note()
+            .get(
+                delay,
+                config::CONFIG_NOTEFILE,
+                config::CONFIG_NOTE,
+                false,
+                false,
+            )?
+            .wait_for(delay, self.config.timeouts.request)
+            .map(|r| r.body)
+            .unwrap_or(None))
+    }
 
-        Ok(n)
+    /// Read `deployment_duration` again from the config override on the notecard, so that the
+    /// duration of an ongoing deployment can be changed from notehub (see `deployment`). A valid
+    /// duration is applied. Returns the duration in effect [hours].
+    pub fn refresh_deployment_duration(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<u32, NoteError> {
+        if let Some(hours) = self.read_config(delay)?.and_then(|o| o.deployment_duration) {
+            let mut c = self.config.clone();
+            c.deployment_duration = hours;
+
+            match c.validate() {
+                Ok(()) => self.config.deployment_duration = hours,
+                Err(e) => defmt::warn!(
+                    "Invalid deployment duration: {} ({:?}), keeping current.",
+                    hours,
+                    e
+                ),
+            }
+        }
+
+        Ok(self.config.deployment_duration)
+    }
+
+    /// Set the maximum time between outbound syncs [minutes]. The period (less the jitter of the
+    /// device, see [`sync_jitter`]) is applied to the notecard right away, and persisted in the config override on the notecard (the `config`
+    /// note in `config.db`, which is synced back to notehub) so that it is kept after a reboot.
+    /// Other fields of the override are kept. Returns `Ok(false)` and keeps the current period if
+    /// `minutes` is not a valid period.
+    pub fn set_sync_period(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+        minutes: u32,
+    ) -> Result<bool, NoteError> {
+        let mut c = self.config.clone();
+        c.sync_period = minutes;
+
+        if let Err(e) = c.validate() {
+            defmt::warn!(
+                "Invalid sync period: {} ({:?}), keeping current.",
+                minutes,
+                e
+            );
+            return Ok(false);
+        }
+
+        self.note
+            .hub()
+            .set(
+                delay,
+                None,
+                None,
+                None,
+                None,
+                Some(minutes - sync_jitter(self.device_id, minutes)),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        self.config.sync_period = minutes;
+
+        let mut o = self.read_config(delay)?.unwrap_or_default();
+        o.sync_period = Some(minutes);
+
+        self.note
+            .note()
+            .update(
+                delay,
+                config::CONFIG_NOTEFILE,
+                config::CONFIG_NOTE,
+                Some(o),
+                None,
+                false,
+            )?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        // The notecard is set up with the new period, keep it provisioned.
+        self.write_provision(delay)
+            .inspect_err(|e| defmt::error!("Failed to write provisioning record: {:?}", e))
+            .ok();
+
+        defmt::info!("Sync period set to: {} minutes.", minutes);
+
+        Ok(true)
+    }
+
+    /// Reset the notecard communication and re-do the full configuration.
+    pub fn reinit(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
+        defmt::warn!("Re-initializing notecard..");
+        self.note.reset(delay)?;
+        delay.delay_ms(100u16);
+
+        self.setup(delay)
     }
 
     /// Initiate sync and wait for it to complete (or time out).
@@ -116,12 +894,19 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
         timeout_ms: u16,
     ) -> Result<bool, NoteError> {
         defmt::info!("sync..");
-        self.note.hub().sync(delay, true)?.wait(delay)?;
+        self.note
+            .hub()
+            .sync(delay, true)?
+            .wait_for(delay, self.config.timeouts.request)?;
 
         for _ in 0..(timeout_ms / 1000) {
             delay.delay_ms(1000u16);
             defmt::debug!("querying sync status..");
-            let status = self.note.hub().sync_status(delay)?.wait(delay);
+            let status = self
+                .note
+                .hub()
+                .sync_status(delay)?
+                .wait_for(delay, self.config.timeouts.request);
             defmt::debug!("status: {:?}", status);
 
             if let Ok(status) = status {
@@ -135,10 +920,46 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
         Ok(false)
     }
 
+    /// Quiesce the notecard before a planned sleep or reset: queued log messages are sent, a
+    /// final sync is attempted (waiting for up to `SHUTDOWN_SYNC_TIMEOUT`), and the hub is set to
+    /// minimum mode so that the card does not connect on its own while the buoy is asleep. Notes
+    /// that did not make it out are kept on the card and sent on the next sync. Returns whether
+    /// the final sync completed.
+    ///
+    /// Drain the packages queued for the notecard before calling this. The configured mode is
+    /// restored by [`Notecarrier::reinit`], and at boot also when the setup is skipped (see
+    /// [`provision::Boot`]).
+    pub fn shutdown(&mut self, delay: &mut impl DelayMs<u16>) -> Result<bool, NoteError> {
+        defmt::info!("Shutting down notecard..");
+
+        #[cfg(not(feature = "encryption"))]
+        self.flush_live(delay)
+            .inspect_err(|e| defmt::error!("Failed to send live batch before shutdown: {:?}", e))
+            .ok();
+
+        log::drain_log(self, delay)
+            .inspect_err(|e| defmt::error!("Failed to send log before shutdown: {:?}", e))
+            .ok();
+
+        let synced = self
+            .sync_and_wait(delay, SHUTDOWN_SYNC_TIMEOUT)
+            .inspect_err(|e| defmt::error!("Final sync failed: {:?}", e))
+            .unwrap_or(false);
+
+        if !synced {
+            defmt::warn!("Final sync did not complete, notes are kept on the notecard.");
+        }
+
+        self.set_hub(notecard::hub::req::HubMode::Minimum, None, delay)?;
+
+        Ok(synced)
+    }
+
     /// Set up note templates for sensor data and other messages, this will save space and
     /// bandwidth.
     fn setup_templates(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
         defmt::debug!("setting up templates..");
+        let timeout = self.config.timeouts.request;
 
         #[derive(serde::Serialize, Default)]
         struct AxlPacketMetaTemplate {
@@ -155,8 +976,46 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
 
             freq: f32,
             length: u32,
+
+            #[serde(skip_serializing_if = "Option::is_none")]
+            fix_age: Option<u32>,
+
+            #[serde(skip_serializing_if = "Option::is_none")]
+            hs: Option<f32>,
+
+            time_degraded: bool,
+            calibration: u8,
+            quality: u8,
+            accel_lpf: u16,
+            gyro_lpf: i8,
+            bias_mode: u8,
+            bias_x: f32,
+            bias_y: f32,
+            bias_z: f32,
+            scale_x: f32,
+            scale_y: f32,
+            scale_z: f32,
+            dop: f32,
+            accel_max: f32,
+            frame: u8,
+            axes: u8,
+            warmup: u16,
+            filled: heapless::String<FILLED_STR_SZ>,
+            rtc_shift: u32,
+            raw_samples: u32,
+            output_samples: u32,
+            crc: u32,
+            battery_v: f32,
+            temperature_c: f32,
+            clipped_samples: u32,
+            imu_freq: f32,
+            decimation: u8,
+
+            #[cfg(feature = "encryption")]
+            sealed: bool,
         }
 
+        let summary = self.config.note_summary && !cfg!(feature = "encryption");
         let meta_template = AxlPacketMetaTemplate {
             timestamp: 18,
             offset: 14,
@@ -171,68 +1030,469 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
 
             freq: 14.1,
             length: 14,
+
+            // The summary is only sent in the clear, see `send`.
+            fix_age: summary.then_some(14),
+            hs: summary.then_some(14.1),
+
+            time_degraded: true,
+            calibration: 11,
+            quality: 11,
+            accel_lpf: 12,
+            gyro_lpf: 11,
+            bias_mode: 11,
+            bias_x: 14.1,
+            bias_y: 14.1,
+            bias_z: 14.1,
+            scale_x: 14.1,
+            scale_y: 14.1,
+            scale_z: 14.1,
+            dop: 14.1,
+            accel_max: 14.1,
+            frame: 11,
+            axes: 11,
+            warmup: 12,
+            // Strings are given by their longest value.
+            filled: (0..FILLED_STR_SZ).map(|_| 'x').collect(),
+            rtc_shift: 18,
+            raw_samples: 14,
+            output_samples: 14,
+            // Takes the full range of `u32`.
+            crc: 18,
+            battery_v: 14.1,
+            temperature_c: 14.1,
+            clipped_samples: 14,
+            imu_freq: 14.1,
+            decimation: 11,
+
+            #[cfg(feature = "encryption")]
+            sealed: true,
+        };
+
+        #[cfg(not(feature = "encryption"))]
+        let payload_sz = AXL_OUTN;
+
+        #[cfg(feature = "encryption")]
+        let payload_sz = crate::crypt::SEALED_OUTN;
+
+        defmt::debug!("setting up template for AxlPacketMeta");
+        let notefiles = self.notefiles;
+        self.note()
+            .template(
+                delay,
+                Some(notefiles.axl),
+                Some(meta_template),
+                Some(payload_sz as u32),
+            )?
+            .wait_for(delay, timeout)?;
+
+        #[derive(serde::Serialize, Default)]
+        struct AxlStatsTemplate {
+            timestamp: u32,
+            storage_id: u32,
+
+            position_time: u32,
+            lon: f32,
+            lat: f32,
+
+            freq: f32,
+            samples: u32,
+
+            x_std: f32,
+            y_std: f32,
+            z_std: f32,
+            z_max: f32,
+
+            clipped: u32,
+            good: f32,
+            rejected: bool,
+            quality: u8,
+            gate: u8,
+        }
+
+        let stats_template = AxlStatsTemplate {
+            timestamp: 18,
+            storage_id: 14,
+
+            position_time: 14,
+            lon: 18.1,
+            lat: 18.1,
+
+            freq: 14.1,
+            samples: 14,
+
+            x_std: 14.1,
+            y_std: 14.1,
+            z_std: 14.1,
+            z_max: 14.1,
+
+            clipped: 14,
+            good: 14.1,
+            rejected: true,
+            quality: 11,
+            gate: 11,
         };
 
-        defmt::debug!("setting up template for AxlPacketMeta");
-        self.note()
-            .template(
-                delay,
-                Some("axl.qo"),
-                Some(meta_template),
-                Some(AXL_OUTN as u32),
-            )?
-            .wait(delay)?;
+        defmt::debug!("setting up template for AxlStats");
+        self.note()
+            .template(delay, Some(notefiles.stats), Some(stats_template), None)?
+            .wait_for(delay, timeout)?;
+
+        #[cfg(not(feature = "encryption"))]
+        {
+            use crate::backfill::{BatchMeta, BATCH_OUTN};
+
+            let backfill_template = BatchMeta {
+                first: 14,
+                last: 14,
+                packages: 14,
+                timestamp: 18,
+                length: 14,
+                version: 11,
+                compression: 11,
+            };
+
+            defmt::debug!("setting up template for BatchMeta");
+            self.note()
+                .template(
+                    delay,
+                    Some(notefiles.backfill),
+                    Some(backfill_template),
+                    Some(BATCH_OUTN as u32),
+                )?
+                .wait_for(delay, timeout)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn send(
+        &mut self,
+        pck: &AxlPacket,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<usize, NoteError> {
+        #[cfg(not(feature = "encryption"))]
+        let (mut meta, b64) = pck.split_encoded(self.config.sample_encoding);
+
+        // The summary would give away the position and the sea state, so it is not sent with
+        // sealed packages.
+        #[cfg(not(feature = "encryption"))]
+        if self.config.note_summary {
+            meta.fix_age = Some(pck.fix_age());
+            meta.hs = Some(pck.hs());
+        }
+
+        // The whole package is sealed in the payload, see `crypt`.
+        #[cfg(feature = "encryption")]
+        let (meta, b64) = crate::crypt::nonce(pck.timestamp)
+            .and_then(|n| pck.split_sealed(&crate::crypt::KEY, n))
+            .map_err(|_| NoteError::NotecardErr("failed to seal package".into()))?;
+
+        let file = self.notefiles.axl;
+        let sync = self.sync_notes(self.config.urgency.data);
+        let timeout = self.config.timeouts.request;
+        let mut retried = false;
+
+        let r = loop {
+            self.pace(delay);
+
+            let r = self
+                .note
+                .note()
+                .add(
+                    delay,
+                    Some(file),
+                    None,
+                    Some(&meta),
+                    Some(core::str::from_utf8(&b64).unwrap()),
+                    sync,
+                )
+                .and_then(|f| f.wait_for(delay, timeout));
+
+            match r {
+                Err(e) if !retried && is_template_mismatch(&e) => {
+                    self.recover_templates(file, &e, delay)?;
+                    retried = true;
+                }
+                r => break r?,
+            }
+        };
+
+        defmt::info!(
+            "Sent data package: {}, bytes: {} (note: {:?})",
+            pck.storage_id,
+            b64.len(),
+            r
+        );
+        crate::dedup::ACKED.ack(pck.storage_id);
+
+        Ok(b64.len())
+    }
+
+    pub fn send_stats(
+        &mut self,
+        pck: &AxlPacket,
+        gate: Decision,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), NoteError> {
+        let mut stats = pck.stats_weighted(&self.config.stats_weighting);
+        stats.gate = gate.code();
+
+        // The position is only sent sealed.
+        #[cfg(feature = "encryption")]
+        {
+            stats.position_time = 0;
+            stats.lon = 0.;
+            stats.lat = 0.;
+        }
+
+        let file = self.notefiles.stats;
+        let sync = self.sync_notes(self.config.urgency.stats);
+        let timeout = self.config.timeouts.request;
+        let mut retried = false;
+
+        loop {
+            self.pace(delay);
+
+            let r = self
+                .note
+                .note()
+                .add(delay, Some(file), None, Some(&stats), None, sync)
+                .and_then(|f| f.wait_for(delay, timeout));
+
+            match r {
+                Err(e) if !retried && is_template_mismatch(&e) => {
+                    self.recover_templates(file, &e, delay)?;
+                    retried = true;
+                }
+                r => {
+                    r?;
+                    break;
+                }
+            }
+        }
+
+        defmt::info!("Sent stats for package: {}", pck.storage_id);
+
+        Ok(())
+    }
+
+    /// Send a batch of stored packages to the backfill notefile, separate from the live packages.
+    #[cfg(not(feature = "encryption"))]
+    pub fn send_backfill(
+        &mut self,
+        batch: &crate::backfill::Batch,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<usize, NoteError> {
+        let (meta, b64) = batch.split();
+
+        let file = self.notefiles.backfill;
+        let sync = self.sync_notes(self.config.urgency.backfill);
+        let timeout = self.config.timeouts.request;
+        let mut retried = false;
+
+        loop {
+            self.pace(delay);
+
+            let r = self
+                .note
+                .note()
+                .add(
+                    delay,
+                    Some(file),
+                    None,
+                    Some(&meta),
+                    Some(core::str::from_utf8(&b64).unwrap()),
+                    sync,
+                )
+                .and_then(|f| f.wait_for(delay, timeout));
+
+            match r {
+                Err(e) if !retried && is_template_mismatch(&e) => {
+                    self.recover_templates(file, &e, delay)?;
+                    retried = true;
+                }
+                r => {
+                    r?;
+                    break;
+                }
+            }
+        }
+
+        crate::clog!(
+            Note,
+            info,
+            "Sent backfill: {} -> {} ({} packages), bytes: {}",
+            meta.first,
+            meta.last,
+            meta.packages,
+            b64.len()
+        );
+
+        Ok(b64.len())
+    }
+
+    /// A note to `file` was rejected with the template mismatch `e` (see [`is_template_mismatch`]):
+    /// set up the templates again before the note is retried once. Logged so that the mismatch is
+    /// seen on notehub.
+    fn recover_templates(
+        &mut self,
+        file: &str,
+        e: &NoteError,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), NoteError> {
+        let mut msg = heapless::String::<256>::new();
+        write!(
+            &mut msg,
+            "Note for {} does not match the template: {:?}, setting up templates again.",
+            file, e
+        )
+        .ok();
+        log::log_at(log::Category::Note, log::Level::Warn, &msg);
+
+        self.setup_templates(delay)
+            .inspect_err(|e| crate::clog!(Note, error, "Failed to set up templates: {:?}", e))?;
+
+        log::log_at(
+            log::Category::Note,
+            log::Level::Warn,
+            "Templates set up again, retrying note.",
+        );
+
+        Ok(())
+    }
+
+    /// Send log messages. In the quiet hours the messages are synced right away if so configured.
+    pub fn drain_log(
+        &mut self,
+        queue: &log::LogQueue,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), NoteError> {
+        let mut sent = false;
+
+        while let Some((_, msg)) = queue.dequeue() {
+            defmt::info!("logging message: {}", msg);
+            self.note
+                .hub()
+                .log(delay, msg.as_str(), false, false)?
+                .wait_for(delay, self.config.timeouts.request)?;
+            sent = true;
+        }
+
+        if sent && self.is_quiet() && self.config.quiet_hours.log {
+            defmt::info!("quiet hours: syncing log messages.");
+            self.note
+                .hub()
+                .sync(delay, false)?
+                .wait_for(delay, self.config.timeouts.request)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the run-time log levels (see [`crate::log`]).
+    pub fn read_log_levels(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Option<LogLevels>, NoteError> {
+        Ok(self
+            .note
+            .note()
+            .get(
+                delay,
+                log::LOG_LEVELS_FILE,
+                log::LOG_LEVELS_NOTE,
+                false,
+                false,
+            )?
+            .wait_for(delay, self.config.timeouts.request)
+            .map(|r| r.body)
+            .unwrap_or(None))
+    }
+
+    /// Read and delete pending command, with its argument (if any). Commands without the correct
+    /// key are discarded.
+    pub fn read_command(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Option<(Command, Option<u32>)>, NoteError> {
+        let c: Option<CommandNote> = self
+            .note
+            .note()
+            .get(delay, cmd::CMD_FILE, cmd::CMD_NOTE, false, false)?
+            .wait_for(delay, self.config.timeouts.request)
+            .map(|r| r.body)
+            .unwrap_or(None);
+
+        if let Some(c) = c {
+            defmt::info!("Received command: {:?} (value: {:?})", c.cmd, c.value);
+
+            self.note
+                .note()
+                .delete(delay, cmd::CMD_FILE, cmd::CMD_NOTE)
+                .and_then(|r| r.wait_for(delay, self.config.timeouts.request))
+                .inspect_err(|e| defmt::error!("Failed to delete command: {:?}", e))
+                .ok();
+
+            let cmd = c.authenticated();
+            if cmd::CMD_KEY.is_none() {
+                defmt::warn!("Commands are disabled (no BUOYCMDKEY), discarding.");
+            } else if cmd.is_none() {
+                defmt::warn!("Command not authenticated, discarding.");
+            }
 
-        Ok(())
+            Ok(cmd.map(|cmd| (cmd, c.value)))
+        } else {
+            Ok(None)
+        }
     }
 
-    pub fn send(
+    /// Acknowledge an executed command, with the setting in effect after the command (if it
+    /// changes one).
+    pub fn ack_command(
         &mut self,
-        pck: &AxlPacket,
         delay: &mut impl DelayMs<u16>,
-    ) -> Result<usize, NoteError> {
-        let (meta, b64) = pck.split();
-
-        let r = self
-            .note
-            .note()
-            .add(
-                delay,
-                Some("axl.qo"),
-                None,
-                Some(meta),
-                Some(core::str::from_utf8(&b64).unwrap()),
-                if cfg!(feature = "continuous") {
-                    true
-                } else {
-                    false
-                },
-            )?
-            .wait(delay)?;
-
-        defmt::info!(
-            "Sent data package: {}, bytes: {} (note: {:?})",
-            pck.storage_id,
-            b64.len(),
-            r
-        );
+        cmd: Command,
+        ok: bool,
+        value: Option<u32>,
+    ) -> Result<(), NoteError> {
+        self.send_ack(
+            delay,
+            CommandAck {
+                cmd: Some(cmd),
+                ok,
+                value,
+                fix: None,
+            },
+        )
+    }
 
-        Ok(b64.len())
+    /// Acknowledge the `locate` command with the retrieved fix, `ok` is false if there was no fix.
+    pub fn ack_locate(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+        fix: Option<cmd::Fix>,
+    ) -> Result<(), NoteError> {
+        self.send_ack(
+            delay,
+            CommandAck {
+                cmd: Some(Command::Locate),
+                ok: fix.is_some(),
+                value: None,
+                fix,
+            },
+        )
     }
 
-    /// Send log messages
-    pub fn drain_log(
+    fn send_ack(
         &mut self,
-        queue: &heapless::mpmc::Q4<heapless::String<256>>,
         delay: &mut impl DelayMs<u16>,
+        ack: CommandAck,
     ) -> Result<(), NoteError> {
-        while let Some(msg) = queue.dequeue() {
-            defmt::info!("logging message: {}", msg);
-            self.note
-                .hub()
-                .log(delay, msg.as_str(), false, false)?
-                .wait(delay)?;
-        }
+        self.note
+            .note()
+            .add(delay, Some(cmd::CMD_ACK_FILE), None, Some(ack), None, true)?
+            .wait_for(delay, self.config.timeouts.request)?;
 
         Ok(())
     }
@@ -245,7 +1505,7 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
             .note
             .note()
             .get(delay, "storage.dbx", "storage-info", false, false)?
-            .wait(delay)
+            .wait_for(delay, self.config.timeouts.request)
             .map(|r| r.body)
             .unwrap_or(None);
 
@@ -253,10 +1513,18 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
             .note
             .note()
             .get(delay, "storage.db", "request-data", false, false)?
-            .wait(delay)
+            .wait_for(delay, self.config.timeouts.request)
             .map(|r| r.body)
             .unwrap_or(None);
 
+        self.replay = match &d {
+            Some(RequestData {
+                request_start: Some(start),
+                request_end: Some(end),
+            }) => Some((*start, *end)),
+            _ => None,
+        };
+
         Ok((r, d))
     }
 
@@ -271,11 +1539,12 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
             self.note
                 .note()
                 .delete(delay, "storage.db", "request-data")
-                .and_then(|r| r.wait(delay))
+                .and_then(|r| r.wait_for(delay, self.config.timeouts.request))
                 .inspect_err(|e| defmt::error!("Failed to delete request-data: {:?}", e))
                 .ok();
 
             sent_id = None;
+            self.replay = None;
         }
 
         let current_info = self.read_storage_info(delay).ok().map(|(c, _)| c).flatten();
@@ -291,7 +1560,7 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
             self.note
                 .note()
                 .delete(delay, "storage.dbx", "storage-info")
-                .and_then(|r| r.wait(delay))
+                .and_then(|r| r.wait_for(delay, self.config.timeouts.request))
                 .inspect_err(|e| defmt::error!("Failed to delete storage-info: {:?}", e))
                 .ok();
 
@@ -305,16 +1574,84 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
                     None,
                     false,
                 )?
-                .wait(delay)?;
+                .wait_for(delay, self.config.timeouts.request)?;
         }
 
         Ok(())
     }
 
-    /// Send queued packages to the notecard.
+    /// Notes waiting on the notecard to be synced (`file.stats`), the depth of the outbound queue
+    /// of the notecard.
+    pub fn card_queue(&mut self, delay: &mut impl DelayMs<u16>) -> Result<u32, NoteError> {
+        let r = self
+            .note
+            .file()
+            .stats(delay, None)?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        Ok(r.changes)
+    }
+
+    /// Check the depth of the outbound queue of the notecard against `card_queue` (see
+    /// [`crate::queue::CardQueue`]). When the request fails the last known depth is used.
+    fn check_card_queue(&mut self, delay: &mut impl DelayMs<u16>) -> CardState {
+        let cq = self.config.card_queue;
+
+        if !cq.is_enabled() {
+            self.card_queue = None;
+            CARD_PRESSURE.store(false, core::sync::atomic::Ordering::Relaxed);
+            return CardState::Normal;
+        }
+
+        self.pace(delay);
+        match self.card_queue(delay) {
+            Ok(pending) => self.card_queue = Some(pending),
+            Err(e) => defmt::error!("Failed to read notecard queue: {:?}", e),
+        }
+
+        let state = self.card_queue.map_or(CardState::Normal, |p| cq.state(p));
+        crate::clog!(
+            Note,
+            debug,
+            "notecard queue: {:?} ({:?})",
+            self.card_queue,
+            state
+        );
+
+        let paused = state == CardState::Paused;
+        if CARD_PRESSURE.swap(paused, core::sync::atomic::Ordering::Relaxed) != paused {
+            let mut msg = heapless::String::<128>::new();
+            write!(
+                &mut msg,
+                "Notecard queue {} ({:?} notes pending, high: {}).",
+                if paused {
+                    "is deep, pausing the packages until it has synced"
+                } else {
+                    "has drained, resuming the packages"
+                },
+                self.card_queue,
+                cq.high
+            )
+            .ok();
+            log::log_at(
+                log::Category::Note,
+                if paused {
+                    log::Level::Warn
+                } else {
+                    log::Level::Info
+                },
+                &msg,
+            );
+        }
+
+        state
+    }
+
+    /// Send queued packages to the notecard. Nothing is sent while the outbound queue of the
+    /// notecard is deep (see `queue::CardQueue`).
     pub fn drain_queue(
         &mut self,
-        queue: &mut heapless::spsc::Consumer<'static, AxlPacket, NOTEQ_SZ>,
+        queue: &mut queue::Consumer<'static, AxlPacket, NOTEQ_SZ>,
         delay: &mut impl DelayMs<u16>,
     ) -> Result<usize, NoteError> {
         // Sending packages takes a long time (16-17 seconds). Only 1 package is sent at a time
@@ -323,6 +1660,10 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
 
         let mut tsz = 0;
 
+        if queue.ready() && self.check_card_queue(delay) == CardState::Paused {
+            return Ok(0);
+        }
+
         while let Some(pck) = queue.dequeue() {
             // #[cfg(not(feature = "continuous"))]
             // {
@@ -337,8 +1678,27 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
             //     }
             // }
 
+            // Packages already accepted are neither sent, nor counted by the gate.
+            let acked = crate::dedup::ACKED.get();
+            if self.config.dedup && crate::dedup::is_duplicate(pck.storage_id, acked, self.replay) {
+                let mut msg = heapless::String::<128>::new();
+                write!(
+                    &mut msg,
+                    "Suppressed duplicate package: {:?} (last accepted: {:?}).",
+                    pck.storage_id, acked
+                )
+                .ok();
+                log::log_at(log::Category::Note, log::Level::Warn, &msg);
+                continue;
+            }
+
             // TODO: if status was over 75 last time, don't spam notecard with status requests.
-            let status = self.note.card().status(delay)?.wait(delay)?;
+            self.pace(delay);
+            let status = self
+                .note
+                .card()
+                .status(delay)?
+                .wait_for(delay, self.config.timeouts.request)?;
 
             if status.storage > 75 {
                 // wait until notecard has synced.
@@ -346,56 +1706,504 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
                 return Ok(0);
             }
 
-            defmt::info!(
+            crate::clog!(
+                Note,
+                info,
                 "sending package: note queue sz (after dequeue): {}",
                 queue.len()
             );
-            match self.send(&pck, delay) {
-                Ok(sz) => {
-                    tsz += sz;
+
+            let gate = self.update_gate(&pck);
+
+            // The statistics record the decision of the gate for every package.
+            if self.config.products.stats || self.config.motion_gate.is_enabled() {
+                self.send_stats(&pck, gate, delay)
+                    .inspect_err(|e| defmt::error!("Error while sending stats: {:?}", e))
+                    .ok();
+            }
+
+            if !self.config.products.timeseries || !gate.send() {
+                continue;
+            }
+
+            #[cfg(not(feature = "encryption"))]
+            if self.config.live_batch.is_enabled() {
+                let now = pck.timestamp;
+                if self.live.push(pck.clone()).is_err() {
+                    self.log_full_live();
+                    tsz += self.flush_live(delay)?;
+                    self.live.push(pck).ok();
                 }
-                Err(e) => {
-                    defmt::error!(
-                        "Error while sending package to notecard: {:?}, retrying..",
-                        e
+
+                if !self.config.live_batch.is_due(&self.live, now) {
+                    continue;
+                }
+
+                if self.live.is_full() {
+                    self.log_full_live();
+                }
+
+                tsz += self.flush_live(delay)?;
+            } else {
+                tsz += self.send_package(&pck, delay)?;
+            }
+
+            #[cfg(feature = "encryption")]
+            {
+                tsz += self.send_package(&pck, delay)?;
+            }
+
+            if self.card_queue.map(|p| self.config.card_queue.state(p)) == Some(CardState::Paused) {
+                crate::clog!(
+                    Note,
+                    warn,
+                    "notecard queue is deep, pausing the note queue."
+                );
+                break;
+            }
+
+            // Give way to the storage queue (see `queue::QueuePolicy`).
+            let pressure = STORAGE_PRESSURE.load(core::sync::atomic::Ordering::Relaxed);
+            if self.config.queue_policy.yield_to_storage(pressure) {
+                crate::clog!(
+                    Note,
+                    warn,
+                    "storage queue under pressure, pausing the note queue: note queue sz: {}",
+                    queue.len()
+                );
+                break;
+            }
+        }
+
+        // A batch that is not filled up is still sent when it times out.
+        #[cfg(not(feature = "encryption"))]
+        if let Some(now) = (self.clock)() {
+            if self.config.live_batch.is_due(&self.live, now) {
+                tsz += self.flush_live(delay)?;
+            }
+        }
+
+        Ok(tsz)
+    }
+
+    /// Now [ms], from the clock of the notecarrier (see [`NotecarrierBuilder::clock`]).
+    pub fn now_millis(&self) -> Option<i64> {
+        (self.clock)()
+    }
+
+    /// Send queued packages to the notecard until the queue is empty or `deadline` [ms] passes,
+    /// e.g. before the buoy is powered down on low battery. Returns whether the queue was
+    /// emptied. Without a clock the deadline can not be checked, and the queue is drained once.
+    ///
+    /// With storage use [`crate::StorageManager::flush`], which drains the storage queue as well.
+    pub fn flush_queue(
+        &mut self,
+        queue: &mut queue::Consumer<'static, AxlPacket, NOTEQ_SZ>,
+        delay: &mut impl DelayMs<u16>,
+        deadline: i64,
+    ) -> Result<bool, NoteError> {
+        while queue.ready() {
+            let sz = self.drain_queue(queue, delay)?;
+
+            if !queue.ready() {
+                break;
+            }
+
+            match (self.clock)() {
+                Some(now) if now < deadline => {}
+                _ => {
+                    crate::clog!(
+                        Note,
+                        warn,
+                        "deadline passed while flushing: note queue sz: {}",
+                        queue.len()
                     );
-                    match self.send(&pck, delay) {
-                        Ok(sz) => {
-                            tsz += sz;
-                        }
-                        Err(e) => {
-                            defmt::error!("Error while sending package to notecard: {:?}, discarding package.", e);
-                            return Err(e);
-                        }
+                    return Ok(false);
+                }
+            }
+
+            // The notecard is full or paused, give it time to sync.
+            if sz == 0 {
+                delay.delay_ms(1000u16);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Send a package as a data note, retrying once.
+    fn send_package(
+        &mut self,
+        pck: &AxlPacket,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<usize, NoteError> {
+        let sz = match self.send(pck, delay) {
+            Ok(sz) => sz,
+            Err(e) => {
+                defmt::error!(
+                    "Error while sending package to notecard: {:?}, retrying..",
+                    e
+                );
+                match self.send(pck, delay) {
+                    Ok(sz) => sz,
+                    Err(e) => {
+                        defmt::error!(
+                            "Error while sending package to notecard: {:?}, discarding package.",
+                            e
+                        );
+                        return Err(e);
                     }
                 }
             }
+        };
+
+        // Every package is one more note waiting on the notecard, until the next check.
+        self.card_queue = self.card_queue.map(|p| p + 1);
+
+        Ok(sz)
+    }
+
+    /// Log that the batch of live packages is closed because it is full.
+    #[cfg(not(feature = "encryption"))]
+    fn log_full_live(&self) {
+        crate::clog!(
+            Note,
+            info,
+            "Live batch is full: {} packages in {} of {} bytes (max_note_size: {}), sending.",
+            self.live.packages,
+            self.live.len(),
+            self.live.limit(),
+            self.config.max_note_size
+        );
+    }
+
+    /// Send the batch of live packages (see `backfill::LiveBatch`) to the backfill notefile,
+    /// retrying once, and start a new batch. The packages are discarded if it fails.
+    #[cfg(not(feature = "encryption"))]
+    pub fn flush_live(&mut self, delay: &mut impl DelayMs<u16>) -> Result<usize, NoteError> {
+        if self.live.is_empty() {
+            return Ok(0);
+        }
+
+        let batch = core::mem::replace(
+            &mut self.live,
+            crate::backfill::Batch::with_limit(
+                self.config.backfill_compression,
+                self.config.max_note_size,
+            ),
+        );
+
+        let sz = match self.send_backfill(&batch, delay) {
+            Ok(sz) => sz,
+            Err(e) => {
+                defmt::error!(
+                    "Error while sending live batch to notecard: {:?}, retrying..",
+                    e
+                );
+                self.send_backfill(&batch, delay).inspect_err(|e| {
+                    defmt::error!(
+                        "Error while sending live batch to notecard: {:?}, discarding {} packages.",
+                        e,
+                        batch.packages
+                    )
+                })?
+            }
+        };
+
+        crate::dedup::ACKED.ack(batch.last);
+        self.card_queue = self.card_queue.map(|p| p + 1);
+
+        Ok(sz)
+    }
+
+    /// Decide whether the time series of `pck` is sent (see [`crate::gate`]), entering and
+    /// leaving calm is logged.
+    fn update_gate(&mut self, pck: &AxlPacket) -> Decision {
+        let was_calm = self.gate.is_calm();
+        let motion = pck.motion();
+        let decision = self
+            .gate
+            .update(&self.config.motion_gate, motion, pck.timestamp);
+
+        if self.gate.is_calm() != was_calm {
+            let mut msg = heapless::String::<256>::new();
+            write!(
+                &mut msg,
+                "Motion gate: {} (motion: {} m/s^2, threshold: {} m/s^2).",
+                if was_calm {
+                    "motion, sending time series"
+                } else {
+                    "calm, only sending statistics"
+                },
+                motion,
+                self.config.motion_gate.threshold
+            )
+            .ok();
+            log::log_at(log::Category::Note, log::Level::Info, &msg);
         }
 
-        Ok(tsz)
+        if decision == Decision::Heartbeat {
+            crate::clog!(Note, debug, "Motion gate: calm, sending heartbeat package.");
+        }
+
+        decision
+    }
+
+    /// Health at `now` as far as known here: the syncs and the dropped items. The rest of the
+    /// fields are filled in by the caller.
+    pub fn health(&self, now: i64) -> Health {
+        Health {
+            timestamp: now,
+            last_sync: self.sync_history.last_sync(),
+            sync_attempts: self.sync_history.attempts().count() as u32,
+            sync_failures: self.sync_history.failures() as u32,
+            dropped: DROPPED.get(),
+            card_queue: self.card_queue,
+            ..Default::default()
+        }
+    }
+
+    /// Send the health note (see [`Health`]) with the configured urgency. In the quiet hours the
+    /// note is synced right away if so configured.
+    pub fn send_health(
+        &mut self,
+        health: &Health,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), NoteError> {
+        crate::clog!(Note, info, "Sending health: {:?}", health);
+
+        let sync = self.sync_notes(self.config.urgency.health)
+            || (self.is_quiet() && self.config.quiet_hours.health);
+
+        self.pace(delay);
+
+        self.note
+            .note()
+            .add(
+                delay,
+                Some(self.notefiles.health),
+                None,
+                Some(health),
+                None,
+                sync,
+            )?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        Ok(())
+    }
+
+    /// Bearer of the connection to notehub as reported by the notecard (`card.transport`), `None`
+    /// if it is not known (see [`crate::transport`]).
+    pub fn bearer(&mut self, delay: &mut impl DelayMs<u16>) -> Result<Option<Bearer>, NoteError> {
+        let r = self
+            .note
+            .card()
+            .transport(delay, None)?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        Ok(r.method.as_deref().and_then(Bearer::from_method))
+    }
+
+    /// Motion since the last call, from the accelerometer of the Notecard (`card.motion`, see
+    /// [`crate::tamper`]).
+    pub fn motion(&mut self, delay: &mut impl DelayMs<u16>) -> Result<Motion, NoteError> {
+        let r = self
+            .note
+            .card()
+            .motion(delay)?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        Ok(Motion {
+            count: r.count.unwrap_or(0),
+            free_fall: r.alert.unwrap_or(false),
+        })
+    }
+
+    /// Version of the firmware of the notecard (`card.version`).
+    pub fn card_version(
+        &mut self,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<heapless::String<{ crate::bist::VERSION_SZ }>, NoteError> {
+        let r = self
+            .note
+            .card()
+            .version(delay)?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        let mut version = heapless::String::new();
+        version
+            .push_str(
+                r.version
+                    .get(..crate::bist::VERSION_SZ)
+                    .unwrap_or(&r.version),
+            )
+            .ok();
+
+        Ok(version)
+    }
+
+    /// Voltage of the battery measured by the notecard (`card.voltage`) [V].
+    pub fn voltage(&mut self, delay: &mut impl DelayMs<u16>) -> Result<f32, NoteError> {
+        let r = self
+            .note
+            .card()
+            .voltage(delay)?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        Ok(r.value)
+    }
+
+    /// Temperature of the board measured by the notecard (`card.temp`) [°C].
+    pub fn temperature(&mut self, delay: &mut impl DelayMs<u16>) -> Result<f32, NoteError> {
+        let r = self
+            .note
+            .card()
+            .temp(delay)?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        Ok(r.value)
+    }
+
+    /// Read the battery voltage and the temperature of the board, recorded in the following
+    /// packages (see `AxlPacket::battery_v`). A reading that fails is left out, as is a voltage
+    /// that is not positive (the Notecard has no voltage before the battery is charged).
+    pub fn read_card(&mut self, delay: &mut impl DelayMs<u16>) {
+        let battery_v = self
+            .voltage(delay)
+            .inspect_err(|e| defmt::error!("Failed to read voltage: {:?}", e))
+            .ok()
+            .filter(|v| *v > 0.);
+
+        let temperature_c = self
+            .temperature(delay)
+            .inspect_err(|e| defmt::error!("Failed to read temperature: {:?}", e))
+            .ok()
+            .filter(|t| t.is_finite());
+
+        defmt::debug!(
+            "Battery: {:?} V, temperature: {:?} C",
+            battery_v,
+            temperature_c
+        );
+        crate::set_card_readings(battery_v, temperature_c);
+    }
+
+    /// Send the report of the self-test at boot to `BIST_FILE`, synced immediately.
+    pub fn send_bist(
+        &mut self,
+        report: &crate::bist::BistReport,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), NoteError> {
+        self.note
+            .note()
+            .add(
+                delay,
+                Some(crate::bist::BIST_FILE),
+                None,
+                Some(report),
+                None,
+                true,
+            )?
+            .wait_for(delay, self.config.timeouts.request)?;
+
+        defmt::info!("Sent self-test report, go: {}", report.go);
+
+        Ok(())
+    }
+
+    /// Check the motion at `now` (ms) against `motion_threshold`, and send an alarm (an alert log
+    /// message, with the urgency of `urgency.alarm`) if reached.
+    pub fn check_motion(
+        &mut self,
+        now: i64,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Option<Alarm>, NoteError> {
+        let threshold = self.config.motion_threshold;
+        if threshold == 0 {
+            return Ok(None);
+        }
+
+        let m = self.motion(delay)?;
+        crate::clog!(Note, debug, "card.motion: {:?}", m);
+
+        let alarm = self.tamper.check(threshold, now, m);
+
+        if let Some(a) = alarm {
+            let mut msg = heapless::String::<64>::new();
+            write!(&mut msg, "tamper alarm: {:?}", a).ok();
+            defmt::warn!("{}", msg.as_str());
+
+            let sync = self.sync_notes(self.config.urgency.alarm);
+            self.note
+                .hub()
+                .log(delay, msg.as_str(), true, sync)?
+                .wait_for(delay, self.config.timeouts.request)?;
+        }
+
+        Ok(alarm)
     }
 
-    /// Check if notecard is filling up, and initiate sync in that case.
-    pub fn check_and_sync(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
-        let status = self.note.card().status(delay)?.wait(delay)?;
-        defmt::trace!("card.status: {}", status);
+    /// Check if notecard is filling up, and initiate sync in that case (not in the quiet hours).
+    /// The sync status is recorded in `sync_history` at `now` (ms).
+    pub fn check_and_sync(
+        &mut self,
+        now: i64,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<(), NoteError> {
+        let status = self
+            .note
+            .card()
+            .status(delay)?
+            .wait_for(delay, self.config.timeouts.request)?;
+        crate::clog!(Note, trace, "card.status: {}", status);
 
-        let sync_status = self.note.hub().sync_status(delay)?.wait(delay)?;
-        defmt::trace!("hub.sync_status: {}", sync_status);
+        let sync_status = self
+            .note
+            .hub()
+            .sync_status(delay)?
+            .wait_for(delay, self.config.timeouts.request)?;
+        crate::clog!(Note, trace, "hub.sync_status: {}", sync_status);
+
+        if let Some(a) = self
+            .sync_history
+            .update(now, sync_status.requested, sync_status.completed)
+        {
+            if a.ok {
+                crate::clog!(Note, info, "Sync attempt completed: {:?}", a);
+            } else {
+                log::log_at(
+                    log::Category::Note,
+                    log::Level::Warn,
+                    "Sync attempt failed.",
+                );
+            }
+        }
 
         #[cfg(debug_assertions)]
         {
-            let wireless = self.note.card().wireless(delay).and_then(|r| r.wait(delay));
-            defmt::trace!("card.wireless: {}", wireless);
+            let wireless = self
+                .note
+                .card()
+                .wireless(delay)
+                .and_then(|r| r.wait_for(delay, self.config.timeouts.request));
+            crate::clog!(Note, trace, "card.wireless: {}", wireless);
         }
 
         if status.storage > NOTECARD_STORAGE_INIT_SYNC as usize {
-            if sync_status.requested.is_none() {
+            if self.is_quiet() {
+                defmt::warn!(
+                    "notecard is more than {}% full, not syncing in quiet hours.",
+                    NOTECARD_STORAGE_INIT_SYNC
+                );
+            } else if sync_status.requested.is_none() {
                 defmt::warn!(
                     "notecard is more than {}% full, initiating sync.",
                     NOTECARD_STORAGE_INIT_SYNC
                 );
-                self.note.hub().sync(delay, false)?.wait(delay)?;
+                self.note
+                    .hub()
+                    .sync(delay, false)?
+                    .wait_for(delay, self.config.timeouts.request)?;
             }
             defmt::info!(
                 "notecard is filling up ({}%): sync status: {:?}",
@@ -409,7 +2217,7 @@ impl<I2C: Read + Write> Notecarrier<I2C> {
 }
 
 impl<I2C: Read + Write> Deref for Notecarrier<I2C> {
-    type Target = Notecard<I2C>;
+    type Target = Notecard<Traced<I2C>>;
 
     fn deref(&self) -> &Self::Target {
         &self.note
@@ -424,9 +2232,85 @@ impl<I2C: Read + Write> DerefMut for Notecarrier<I2C> {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::axl::AXL_SZ;
     use half::f16;
 
+    #[test]
+    fn sync_jitter_bounded() {
+        for id in 0..1000u64 {
+            assert_eq!(sync_jitter(id, 40), sync_jitter(id, 40));
+            assert!(sync_jitter(id, 40) <= 4);
+            assert!(sync_jitter(id, 24 * 60) <= MAX_SYNC_JITTER);
+            assert_eq!(sync_jitter(id, 9), 0);
+        }
+    }
+
+    #[test]
+    fn sync_jitter_spread() {
+        // Consecutive IDs (e.g. a batch of chips) are spread over all the possible jitters.
+        let mut counts = [0usize; 5];
+        for id in 0x1234_0000u64..0x1234_0000 + 500 {
+            counts[sync_jitter(id, 40) as usize] += 1;
+        }
+
+        assert!(counts.iter().all(|c| *c > 50), "{:?}", counts);
+    }
+
+    #[test]
+    fn template_mismatch() {
+        assert!(is_template_mismatch(&NoteError::NotecardErr(
+            "error adding note: field does not match template".into()
+        )));
+        assert!(!is_template_mismatch(&NoteError::NotecardErr(
+            "no notecard at address".into()
+        )));
+        assert!(!is_template_mismatch(&NoteError::TimeOut));
+    }
+
+    #[test]
+    fn builder() {
+        let b = NotecarrierBuilder::new();
+        assert_eq!(b.config, Config::default());
+        assert_eq!(b.serial, BUOYSN);
+        assert_eq!(b.continuous, cfg!(feature = "continuous"));
+        assert_eq!(b.notefiles.axl, "axl.qo");
+
+        let b = NotecarrierBuilder::new()
+            .config(Config::default())
+            .product("com.example:test")
+            .unwrap()
+            .sync_period(20)
+            .gps_period(120)
+            .continuous(true)
+            .notefiles(Notefiles {
+                axl: "test.qo",
+                ..Default::default()
+            })
+            .delays(10, 40);
+
+        assert_eq!(b.config.product.as_str(), "com.example:test");
+        assert_eq!(b.config.sync_period, 20);
+        assert_eq!(b.config.gps_period, 120);
+        assert!(b.continuous);
+        assert_eq!(b.notefiles.axl, "test.qo");
+        assert_eq!(b.notefiles.stats, "stats.qo");
+        assert_eq!(b.notefiles.backfill, "backfill.qo");
+        assert_eq!((b.chunk_delay, b.segment_delay), (10, 40));
+    }
+
+    #[test]
+    fn builder_product_too_long() {
+        let product = "com.example:".repeat(6);
+        assert_eq!(product.len(), 72);
+
+        assert_eq!(
+            NotecarrierBuilder::new().product(&product).err(),
+            Some(config::ConfigError::ProductTooLong(72))
+        );
+        assert!(NotecarrierBuilder::new().product(&product[..64]).is_ok());
+    }
+
     #[test]
     fn read_transmitted_data_package() {
         use std::fs;
@@ -0,0 +1,93 @@
+//! Pacing of the requests to the Notecard.
+//!
+//! Draining a large queue sends requests to the Notecard back to back, which can saturate it:
+//! requests start timing out, and the failures end in a reset (see the fatal errors of the main
+//! loop). The requests of the main loop that send notes are paced to at least `request_spacing`
+//! (in the config, ms) apart, measured on the RTC (see `NotecarrierBuilder::clock`). Without a
+//! clock the full spacing is waited before every paced request.
+//!
+//! Every request that times out is counted in [`TIMEOUTS`], and the timeouts since the previous
+//! paced request are logged with the spacing in use, so that it can be seen whether the spacing
+//! needs to be increased.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Requests to the Notecard that timed out since boot.
+pub static TIMEOUTS: AtomicU32 = AtomicU32::new(0);
+
+/// Spacing of the requests.
+#[derive(Debug, Default, Clone)]
+pub struct Pacer {
+    /// Time of the last paced request [ms].
+    last: Option<i64>,
+
+    /// `TIMEOUTS` at the last paced request.
+    timeouts: u32,
+}
+
+impl Pacer {
+    pub fn new() -> Pacer {
+        Pacer {
+            last: None,
+            timeouts: TIMEOUTS.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Time to wait [ms] before a request at `now` [ms], so that it is at least `spacing` [ms]
+    /// after the last one. The full spacing without `now`.
+    pub fn wait(&self, spacing: u16, now: Option<i64>) -> u16 {
+        match (self.last, now) {
+            (Some(last), Some(now)) => {
+                (last + spacing as i64 - now).clamp(0, spacing as i64) as u16
+            }
+            (None, Some(_)) => 0,
+            (_, None) => spacing,
+        }
+    }
+
+    /// Record a request at `now` [ms], returns the number of timeouts since the last request.
+    pub fn sent(&mut self, now: Option<i64>) -> u32 {
+        let timeouts = TIMEOUTS.load(Ordering::Relaxed);
+        let new = timeouts.wrapping_sub(self.timeouts);
+
+        self.last = now;
+        self.timeouts = timeouts;
+
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spacing() {
+        let mut p = Pacer::new();
+        assert_eq!(p.wait(50, Some(1000)), 0);
+
+        p.sent(Some(1000));
+        assert_eq!(p.wait(50, Some(1010)), 40);
+        assert_eq!(p.wait(50, Some(1050)), 0);
+        assert_eq!(p.wait(50, Some(2000)), 0);
+
+        // The RTC was set back.
+        assert_eq!(p.wait(50, Some(0)), 50);
+
+        // No clock.
+        assert_eq!(p.wait(50, None), 50);
+        p.sent(None);
+        assert_eq!(p.wait(50, Some(1000)), 0);
+
+        assert_eq!(p.wait(0, Some(1000)), 0);
+    }
+
+    #[test]
+    fn timeouts() {
+        let mut p = Pacer::new();
+        p.sent(Some(0));
+
+        TIMEOUTS.fetch_add(2, Ordering::Relaxed);
+        assert!(p.sent(Some(100)) >= 2);
+    }
+}
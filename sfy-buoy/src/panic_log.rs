@@ -0,0 +1,308 @@
+//! The last panic, kept in RAM across the reset and appended to `PANIC_LOG_FILE` at the next boot.
+//!
+//! With the `storage` feature the panic handler records the panic message, the clock of the RTC
+//! interrupt (`COUNT`), and the time and the last known position of the shared state in a
+//! [`Record`] right before the MCU is reset. The panic may have interrupted a transfer on the
+//! SPI bus of the SD-card, or happened inside the storage, so like the post-mortem (see
+//! [`crate::postmortem`]) the record is kept in RAM that is not initialized at boot (the `.uninit`
+//! section) instead of written from the panic handler. At the next boot it is appended as a line
+//! to `PANIC_LOG_FILE`, in the format of the event log:
+//!
+//! ```text
+//! 2024-05-01T12:00:03Z time: 1714564803120, position: 60.39300 5.32400 (1714564500), panic: ..
+//! ```
+//!
+//! The record is only read once, and it is discarded when the checksum does not match (e.g. after
+//! a power loss). A second panic before the record is written keeps the first one.
+//!
+//! The file is rotated: when a line does not fit in `PANIC_LOG_SZ` the file is cut down to the last
+//! `PANIC_LOG_KEEP` bytes (at a line boundary) before the line is appended.
+
+use chrono::NaiveDateTime;
+use core::fmt::Write as _;
+use core::mem::MaybeUninit;
+use heapless::String;
+
+use crate::log::{LogLine, LogTime};
+
+/// Panics on the SD-card.
+pub const PANIC_LOG_FILE: &str = "PANIC.LOG";
+
+/// Maximum size of `PANIC_LOG_FILE` [bytes].
+pub const PANIC_LOG_SZ: u32 = 32 * 1024;
+
+/// Bytes kept at the end of `PANIC_LOG_FILE` when it is rotated.
+pub const PANIC_LOG_KEEP: usize = 1024;
+
+/// Length of the panic message, as from `log::panic_message`.
+pub const MSG_SZ: usize = 256;
+
+/// `magic` of a record of a panic.
+const RECORDED: u32 = 0x504e_4943;
+
+/// The last panic.
+///
+/// Every bit pattern is a valid record, so that it can be read from uninitialized RAM. The content
+/// is only trusted through [`Record::take`].
+#[repr(C)]
+pub struct Record {
+    magic: u32,
+
+    /// Clock of the RTC interrupt [s].
+    count: i32,
+
+    /// Time of the RTC [ms], `i64::MIN` if the shared state was not available.
+    time: i64,
+    position_time: u32,
+    len: u32,
+    lat: f64,
+    lon: f64,
+    checksum: u32,
+    msg: [u8; MSG_SZ],
+}
+
+#[cfg_attr(target_os = "none", link_section = ".uninit.PANICLOG")]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+/// The record kept across resets.
+///
+/// # Safety
+///
+/// The record is written by the panic handler: only access it before the interrupts are enabled.
+pub unsafe fn record() -> &'static mut Record {
+    &mut *(core::ptr::addr_of_mut!(RECORD) as *mut Record)
+}
+
+/// Record a panic, from the panic handler right before the reset. `state` is the time, the
+/// position time, latitude and longitude of the shared state (`State::try_get`).
+///
+/// # Safety
+///
+/// Interrupts whatever was running when the panic happened.
+pub unsafe fn set(msg: &str, count: i32, state: Option<(NaiveDateTime, u32, f64, f64)>) {
+    record().set(msg, count, state);
+}
+
+/// FNV-1a.
+fn fnv(h: u32, b: &[u8]) -> u32 {
+    b.iter()
+        .fold(h, |h, b| (h ^ *b as u32).wrapping_mul(0x0100_0193))
+}
+
+impl Record {
+    #[cfg(test)]
+    fn new() -> Record {
+        Record {
+            magic: 0,
+            count: 0,
+            time: 0,
+            position_time: 0,
+            len: 0,
+            lat: 0.,
+            lon: 0.,
+            checksum: 0,
+            msg: [0; MSG_SZ],
+        }
+    }
+
+    pub fn set(&mut self, msg: &str, count: i32, state: Option<(NaiveDateTime, u32, f64, f64)>) {
+        if self.magic == RECORDED && self.checksum == self.sum() {
+            return;
+        }
+
+        let (time, position_time, lat, lon) = state
+            .map(|(t, pt, lat, lon)| (t.timestamp_millis(), pt, lat, lon))
+            .unwrap_or((i64::MIN, 0, 0., 0.));
+
+        // The message is cut at a character boundary.
+        let mut len = msg.len().min(MSG_SZ);
+        while !msg.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        self.msg[..len].copy_from_slice(&msg.as_bytes()[..len]);
+        self.len = len as u32;
+        self.count = count;
+        self.time = time;
+        self.position_time = position_time;
+        self.lat = lat;
+        self.lon = lon;
+        self.magic = RECORDED;
+        self.checksum = self.sum();
+    }
+
+    fn sum(&self) -> u32 {
+        let h = [self.magic, self.count as u32, self.position_time, self.len]
+            .iter()
+            .fold(0x811c_9dc5, |h, v| fnv(h, &v.to_le_bytes()));
+        let h = fnv(h, &self.time.to_le_bytes());
+        let h = fnv(h, &self.lat.to_le_bytes());
+        let h = fnv(h, &self.lon.to_le_bytes());
+
+        fnv(h, &self.msg[..(self.len as usize).min(MSG_SZ)])
+    }
+
+    /// The recorded panic, `None` if there is none or it does not check out. The record is
+    /// cleared.
+    pub fn take(&mut self) -> Option<Entry> {
+        let valid =
+            self.magic == RECORDED && self.len as usize <= MSG_SZ && self.checksum == self.sum();
+        self.magic = 0;
+
+        if !valid {
+            return None;
+        }
+
+        let msg = core::str::from_utf8(&self.msg[..self.len as usize]).ok()?;
+
+        Some(Entry {
+            count: self.count,
+            time: (self.time != i64::MIN).then_some(self.time),
+            position_time: self.position_time,
+            lat: self.lat,
+            lon: self.lon,
+            msg: msg.into(),
+        })
+    }
+}
+
+/// A recorded panic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Clock of the RTC interrupt [s].
+    pub count: i32,
+
+    /// Time of the RTC [ms], `None` if the shared state was not available.
+    pub time: Option<i64>,
+    pub position_time: u32,
+    pub lat: f64,
+    pub lon: f64,
+    pub msg: String<MSG_SZ>,
+}
+
+impl Entry {
+    /// Line of `PANIC_LOG_FILE`, without the newline.
+    pub fn line(&self, time: &LogTime) -> LogLine {
+        let mut rest = LogLine::new();
+
+        match self.time {
+            Some(t) => write!(&mut rest, "time: {}, ", t),
+            None => write!(&mut rest, "time: unknown, "),
+        }
+        .ok();
+        write!(
+            &mut rest,
+            "position: {:.5} {:.5} ({}), {}",
+            self.lat,
+            self.lon,
+            self.position_time,
+            self.msg.as_str()
+        )
+        .ok();
+
+        crate::log::log_line(time, self.count as i64, &rest)
+    }
+}
+
+/// The part of `tail` (the end of the file) that is kept when the file is rotated: from the start
+/// of the first complete line.
+pub fn keep(tail: &[u8]) -> &[u8] {
+    match tail.iter().position(|b| *b == b'\n') {
+        Some(i) => &tail[i + 1..],
+        None => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Option<(NaiveDateTime, u32, f64, f64)> {
+        Some((
+            NaiveDateTime::from_timestamp_millis(1_714_564_803_120).unwrap(),
+            1_714_564_500,
+            60.393,
+            5.324,
+        ))
+    }
+
+    #[test]
+    fn taken_once() {
+        let mut r = Record::new();
+        assert!(r.take().is_none());
+
+        r.set("panic: at src/lib.rs:1:1", 1_714_564_803, state());
+        let e = r.take().unwrap();
+        assert_eq!(e.count, 1_714_564_803);
+        assert_eq!(e.time, Some(1_714_564_803_120));
+        assert_eq!(e.position_time, 1_714_564_500);
+        assert_eq!(e.msg.as_str(), "panic: at src/lib.rs:1:1");
+
+        assert!(r.take().is_none());
+    }
+
+    #[test]
+    fn first_kept() {
+        let mut r = Record::new();
+        r.set("panic: first", 10, None);
+        r.set("panic: second", 11, state());
+
+        let e = r.take().unwrap();
+        assert_eq!(e.msg.as_str(), "panic: first");
+        assert_eq!(e.time, None);
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut r = Record::new();
+        r.set("panic: x", 10, None);
+        r.msg[0] = b'P';
+        assert!(r.take().is_none());
+
+        let mut r = Record::new();
+        r.magic = RECORDED;
+        r.len = u32::MAX;
+        assert!(r.take().is_none());
+    }
+
+    #[test]
+    fn long_message() {
+        let mut r = Record::new();
+        let msg = "ø".repeat(MSG_SZ);
+        r.set(&msg, 10, None);
+
+        let e = r.take().unwrap();
+        assert_eq!(e.msg.len(), MSG_SZ);
+        assert!(e.msg.chars().all(|c| c == 'ø'));
+    }
+
+    #[test]
+    fn line() {
+        let mut r = Record::new();
+        r.set("panic: at src/lib.rs:1:1", 1_714_564_803, state());
+        let e = r.take().unwrap();
+
+        assert_eq!(
+            e.line(&LogTime::default()).as_str(),
+            "2024-05-01T12:00:03Z time: 1714564803120, position: 60.39300 5.32400 (1714564500), \
+             panic: at src/lib.rs:1:1"
+        );
+
+        let mut r = Record::new();
+        r.set("panic: x", 131, None);
+        let e = r.take().unwrap();
+
+        assert_eq!(
+            e.line(&LogTime::default()).as_str(),
+            "1970-01-01T00:02:11Z [uncalibrated-time] time: unknown, position: 0.00000 0.00000 \
+             (0), panic: x"
+        );
+    }
+
+    #[test]
+    fn rotated() {
+        assert_eq!(keep(b"ne 1\nline 2\nline 3\n"), b"line 2\nline 3\n");
+        assert_eq!(keep(b"\nline 2\n"), b"line 2\n");
+        assert_eq!(keep(b"no newline"), b"");
+    }
+}
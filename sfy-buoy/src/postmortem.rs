@@ -0,0 +1,436 @@
+//! Post-mortem of a reset: the acceleration leading up to a panic or hard fault.
+//!
+//! The accelerometer samples read from the IMU (at the IMU rate, in the frame of the sensor and
+//! before the bias is removed) are pushed to a [`Ring`] of the last `postmortem` (in the config)
+//! samples, at most [`CAPACITY`], `0` disables it. A panic or a hard fault freezes the ring right
+//! before the MCU is reset. There is no time to write it to the SD-card from the fault handlers,
+//! instead the ring is kept in RAM that is not initialized at boot (the `.uninit` section), which
+//! is retained across the software reset. At the next boot a frozen ring is appended to
+//! `POSTMORTEM_FILE` on the SD-card (with the `storage` feature) and summarized in a log message
+//! (the fault, the time of the last samples and the peak acceleration), before it is started
+//! again.
+//!
+//! The ring does not survive a power loss, and it is discarded when the checksum does not match
+//! (e.g. the RAM was overwritten by the bootloader). Resets by the watchdog or a brownout do not
+//! freeze it.
+//!
+//! The file is a sequence of records, each a postcard serialized `(POSTMORTEM_VERSION, Record)`
+//! with COBS framing (zero terminated), like the health records. The samples are in mg, oldest
+//! first. `sfypack postmortem` exports the records as CSV.
+
+use core::mem::MaybeUninit;
+use heapless::Vec;
+
+use crate::axl::DecodeError;
+use crate::waves::SENSORS_GRAVITY_STANDARD;
+
+/// Post-mortem records on the SD-card.
+pub const POSTMORTEM_FILE: &str = "POSTMORT.LOG";
+
+/// Format version of the post-mortem records, increase when `Record` changes.
+pub const POSTMORTEM_VERSION: u32 = 1;
+
+/// Maximum number of samples in the ring, about 5 s at the IMU rate.
+pub const CAPACITY: usize = 1024;
+
+/// Maximum size of a serialized and COBS framed record.
+pub const POSTMORTEM_RECORD_SZ: usize = 9 * 1024 + 128;
+
+/// `magic` of a ring that is recording.
+const RUNNING: u32 = 0x504d_5255;
+
+/// `magic` of a ring frozen by a fault.
+const FROZEN: u32 = 0x504d_4652;
+
+/// Fault that froze the ring.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fault {
+    Panic,
+    HardFault,
+}
+
+impl Fault {
+    fn from_u32(f: u32) -> Option<Fault> {
+        match f {
+            0 => Some(Fault::Panic),
+            1 => Some(Fault::HardFault),
+            _ => None,
+        }
+    }
+}
+
+/// Ring buffer of the most recent accelerometer samples [mg].
+///
+/// Every bit pattern is a valid ring, so that it can be read from uninitialized RAM. The content
+/// is only trusted through [`Ring::frozen`].
+#[repr(C)]
+pub struct Ring {
+    magic: u32,
+
+    /// Samples kept, at most `CAPACITY`.
+    len: u32,
+
+    /// Position of the next sample.
+    head: u32,
+
+    /// Samples pushed since the start, saturating.
+    count: u32,
+
+    /// Time of the last read from the IMU [ms].
+    time: i64,
+
+    /// Sample rate [Hz].
+    freq: f32,
+
+    fault: u32,
+    checksum: u32,
+    samples: [[i16; 3]; CAPACITY],
+}
+
+#[cfg_attr(target_os = "none", link_section = ".uninit.POSTMORTEM")]
+static mut RING: MaybeUninit<Ring> = MaybeUninit::uninit();
+
+/// The ring kept across resets.
+///
+/// # Safety
+///
+/// The ring is shared with the IMU interrupt and the fault handlers: only access it before the
+/// interrupts are enabled.
+pub unsafe fn ring() -> &'static mut Ring {
+    &mut *(core::ptr::addr_of_mut!(RING) as *mut Ring)
+}
+
+/// Push a sample [m/s²], from the IMU interrupt. The ring is started before the interrupts are
+/// enabled.
+pub fn push(a: [f64; 3]) {
+    unsafe { ring() }.push(a);
+}
+
+/// Time [ms] of the last read from the IMU, from the IMU interrupt.
+pub fn mark(now: i64) {
+    unsafe { ring() }.mark(now);
+}
+
+/// Freeze the ring, from the fault handlers right before the reset.
+///
+/// # Safety
+///
+/// Interrupts the IMU interrupt if the fault happened in it.
+pub unsafe fn freeze(fault: Fault) {
+    ring().freeze(fault);
+}
+
+fn to_mg(a: f64) -> i16 {
+    (a / SENSORS_GRAVITY_STANDARD * 1000.).clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+fn to_ms2(mg: i16) -> f32 {
+    mg as f32 / 1000. * SENSORS_GRAVITY_STANDARD as f32
+}
+
+/// FNV-1a.
+fn fnv(h: u32, b: &[u8]) -> u32 {
+    b.iter()
+        .fold(h, |h, b| (h ^ *b as u32).wrapping_mul(0x0100_0193))
+}
+
+impl Ring {
+    #[cfg(test)]
+    fn new() -> Ring {
+        Ring {
+            magic: 0,
+            len: 0,
+            head: 0,
+            count: 0,
+            time: 0,
+            freq: 0.,
+            fault: 0,
+            checksum: 0,
+            samples: [[0; 3]; CAPACITY],
+        }
+    }
+
+    /// Start recording the last `len` samples at `freq` [Hz], discarding the previous content.
+    pub fn start(&mut self, len: u16, freq: f32) {
+        self.len = (len as usize).min(CAPACITY) as u32;
+        self.head = 0;
+        self.count = 0;
+        self.time = 0;
+        self.freq = freq;
+        self.fault = 0;
+        self.checksum = 0;
+        self.magic = if self.len > 0 { RUNNING } else { 0 };
+    }
+
+    pub fn push(&mut self, a: [f64; 3]) {
+        let (len, head) = (self.len as usize, self.head as usize);
+
+        if self.magic != RUNNING || len > CAPACITY || head >= len {
+            return;
+        }
+
+        self.samples[head] = a.map(to_mg);
+        self.head = ((head + 1) % len) as u32;
+        self.count = self.count.saturating_add(1);
+    }
+
+    pub fn mark(&mut self, now: i64) {
+        if self.magic == RUNNING {
+            self.time = now;
+        }
+    }
+
+    /// Freeze the samples, a ring that is not recording is left as it is (e.g. frozen by an
+    /// earlier fault and not yet written).
+    pub fn freeze(&mut self, fault: Fault) {
+        if self.magic == RUNNING && self.len as usize <= CAPACITY {
+            self.fault = fault as u32;
+            self.magic = FROZEN;
+            self.checksum = self.sum();
+        }
+    }
+
+    fn sum(&self) -> u32 {
+        let h = [self.magic, self.len, self.head, self.count, self.fault]
+            .iter()
+            .fold(0x811c_9dc5, |h, v| fnv(h, &v.to_le_bytes()));
+        let h = fnv(h, &self.time.to_le_bytes());
+        let h = fnv(h, &self.freq.to_le_bytes());
+
+        self.samples[..self.len as usize]
+            .iter()
+            .flatten()
+            .fold(h, |h, s| fnv(h, &s.to_le_bytes()))
+    }
+
+    /// The samples frozen by a fault, `None` if the ring was not frozen or does not check out.
+    pub fn frozen(&self) -> Option<Dump<'_>> {
+        if self.magic != FROZEN
+            || self.len == 0
+            || self.len as usize > CAPACITY
+            || self.head >= self.len
+            || self.checksum != self.sum()
+        {
+            return None;
+        }
+
+        Some(Dump {
+            time: self.time,
+            fault: Fault::from_u32(self.fault)?,
+            freq: self.freq,
+            samples: Samples(self),
+        })
+    }
+}
+
+/// The samples of a frozen ring, oldest first.
+#[derive(Clone, Copy)]
+pub struct Samples<'a>(&'a Ring);
+
+impl<'a> Samples<'a> {
+    pub fn len(&self) -> usize {
+        (self.0.count as usize).min(self.0.len as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = [i16; 3]> + 'a {
+        let r: &'a Ring = self.0;
+        let (s, head) = (&r.samples[..r.len as usize], r.head as usize);
+
+        // Once the ring has wrapped the oldest samples are after the head.
+        let wrapped = if self.len() == s.len() {
+            &s[head..]
+        } else {
+            &s[..0]
+        };
+
+        wrapped.iter().chain(&s[..head]).copied()
+    }
+}
+
+impl serde::Serialize for Samples<'_> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(self.iter())
+    }
+}
+
+/// A frozen ring, serialized as a [`Record`].
+#[derive(serde::Serialize, Clone, Copy)]
+pub struct Dump<'a> {
+    /// Time of the last read from the IMU before the fault [ms].
+    pub time: i64,
+    pub fault: Fault,
+
+    /// Sample rate [Hz].
+    pub freq: f32,
+    pub samples: Samples<'a>,
+}
+
+impl Dump<'_> {
+    /// Largest magnitude of the acceleration [m/s²].
+    pub fn peak(&self) -> f32 {
+        self.samples
+            .iter()
+            .map(|s| libm::sqrtf(s.iter().map(|v| to_ms2(*v) * to_ms2(*v)).sum()))
+            .fold(0., f32::max)
+    }
+
+    /// Serialize as a record of the post-mortem file.
+    pub fn to_cobs(&self) -> Result<Vec<u8, POSTMORTEM_RECORD_SZ>, postcard::Error> {
+        postcard::to_vec_cobs(&(POSTMORTEM_VERSION, self))
+    }
+}
+
+impl core::fmt::Display for Dump<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Post-mortem: {:?} at {} ms, {} samples ({:.1} s) before the reset, peak: {:.1} m/s^2.",
+            self.fault,
+            self.time,
+            self.samples.len(),
+            self.samples.len() as f32 / self.freq,
+            self.peak()
+        )
+    }
+}
+
+/// A record of the post-mortem file.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub struct Record {
+    pub time: i64,
+    pub fault: Fault,
+    pub freq: f32,
+
+    /// Samples [mg], oldest first.
+    pub samples: Vec<[i16; 3], CAPACITY>,
+}
+
+impl Record {
+    /// Decode a COBS framed record of the post-mortem file, the buffer is decoded in place.
+    pub fn decode(buf: &mut [u8]) -> Result<Record, DecodeError> {
+        let n = cobs::decode_in_place(buf).map_err(|_| DecodeError::Cobs)?;
+
+        if n == 0 {
+            return Err(DecodeError::Empty);
+        }
+
+        let (version, buf) =
+            postcard::take_from_bytes::<u32>(&buf[..n]).map_err(DecodeError::Postcard)?;
+
+        match version {
+            POSTMORTEM_VERSION => postcard::from_bytes(buf).map_err(DecodeError::Postcard),
+            _ => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Time of a sample [ms], the last sample is at `time`.
+    pub fn sample_time(&self, i: usize) -> i64 {
+        let back = (self.samples.len() - 1 - i) as f32 * 1000. / self.freq;
+        self.time - back as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+
+    fn ring() -> Box<Ring> {
+        Box::new(Ring::new())
+    }
+
+    #[test]
+    fn wraps() {
+        let mut r = ring();
+        r.start(4, 208.);
+
+        for i in 0..6 {
+            r.push([0., 0., i as f64 * SENSORS_GRAVITY_STANDARD]);
+        }
+        r.mark(1000);
+        assert!(r.frozen().is_none());
+
+        r.freeze(Fault::Panic);
+        let d = r.frozen().unwrap();
+        assert_eq!(d.time, 1000);
+        assert_eq!(d.fault, Fault::Panic);
+        assert_eq!(d.samples.len(), 4);
+        assert_eq!(
+            d.samples.iter().map(|s| s[2]).collect::<std::vec::Vec<_>>(),
+            [2000, 3000, 4000, 5000]
+        );
+        assert!((d.peak() - 5. * SENSORS_GRAVITY_STANDARD as f32).abs() < 1e-3);
+
+        // Not full.
+        r.start(4, 208.);
+        r.push([SENSORS_GRAVITY_STANDARD, 0., 0.]);
+        r.freeze(Fault::HardFault);
+        let d = r.frozen().unwrap();
+        assert_eq!(
+            d.samples.iter().collect::<std::vec::Vec<_>>(),
+            [[1000, 0, 0]]
+        );
+    }
+
+    #[test]
+    fn frozen_once() {
+        let mut r = ring();
+        r.start(CAPACITY as u16, 208.);
+        r.push([1., 2., 3.]);
+        r.freeze(Fault::HardFault);
+
+        // A second fault before the ring is written keeps the first one.
+        r.freeze(Fault::Panic);
+        assert_eq!(r.frozen().unwrap().fault, Fault::HardFault);
+
+        // Corrupted.
+        r.samples[0][0] += 1;
+        assert!(r.frozen().is_none());
+
+        // Disabled.
+        r.start(0, 208.);
+        r.push([1., 2., 3.]);
+        r.freeze(Fault::Panic);
+        assert!(r.frozen().is_none());
+    }
+
+    #[test]
+    fn garbage() {
+        let mut r = ring();
+        r.magic = RUNNING;
+        r.len = u32::MAX;
+        r.head = 7;
+
+        r.push([1., 2., 3.]);
+        r.freeze(Fault::Panic);
+        assert!(r.frozen().is_none());
+    }
+
+    #[test]
+    fn record() {
+        let mut r = ring();
+        r.start(CAPACITY as u16, 208.);
+
+        for _ in 0..CAPACITY + 10 {
+            r.push([-400., 400., f64::NAN]);
+        }
+        r.mark(1_700_000_000_000);
+        r.freeze(Fault::Panic);
+
+        let d = r.frozen().unwrap();
+        let mut b = d.to_cobs().unwrap();
+        let rec = Record::decode(&mut b).unwrap();
+
+        assert_eq!(rec.time, 1_700_000_000_000);
+        assert_eq!(rec.fault, Fault::Panic);
+        assert_eq!(rec.samples.len(), CAPACITY);
+        assert_eq!(rec.samples[0], [i16::MIN, i16::MAX, 0]);
+        assert_eq!(rec.sample_time(CAPACITY - 1), 1_700_000_000_000);
+        assert_eq!(rec.sample_time(CAPACITY - 209), 1_700_000_000_000 - 1000);
+    }
+}
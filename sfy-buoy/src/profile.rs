@@ -0,0 +1,166 @@
+//! Profile of where the MCU spends its awake time (`profiling` feature of the firmware).
+//!
+//! The time is measured with the cycle counter of the MCU (`DWT::CYCCNT`, enabled at boot for the
+//! RTC check, see `clock`) and accumulated per [`Phase`]:
+//!
+//! * `Imu`: reading the IMU FIFO and filtering, in the RTC interrupt. The interrupt adds its
+//!   cycles with [`add_imu`].
+//! * `Location`, `Storage` and `Notecard`: the main loop marks the end of each phase with
+//!   [`Profiler::lap`], the cycles since the previous mark are added to the phase. The phases of
+//!   the main loop include the time of any interrupts that preempted them, so the IMU time is
+//!   also counted in the phase it interrupted.
+//!
+//! The breakdown since the last one is taken with the health note (every `note::HEALTH_INTERVAL`),
+//! logged, and sent in `Health::profile`, to find what keeps the MCU awake (e.g. the blocking
+//! Notecard requests). Without the feature nothing is measured, and `Health::profile` is `None`.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Cycles spent in the IMU interrupt since the last `Profiler::start`. Folded into the profile by
+/// the main loop, which wakes up several times a second, long before the counter can overflow.
+static IMU_CYCLES: AtomicU32 = AtomicU32::new(0);
+
+/// Add the cycles of one round of the IMU interrupt.
+pub fn add_imu(cycles: u32) {
+    IMU_CYCLES.fetch_add(cycles, Ordering::Relaxed);
+}
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Imu,
+    Location,
+    Storage,
+    Notecard,
+}
+
+pub const PHASES: usize = 4;
+
+/// Awake time of each phase over an interval.
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+pub struct Breakdown {
+    /// Length of the interval [ms].
+    pub interval: u32,
+
+    /// Time in each phase [ms].
+    pub imu: u32,
+    pub location: u32,
+    pub storage: u32,
+    pub notecard: u32,
+}
+
+impl Breakdown {
+    /// Total awake time [ms].
+    pub fn awake(&self) -> u32 {
+        self.imu + self.location + self.storage + self.notecard
+    }
+}
+
+impl core::fmt::Display for Breakdown {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(
+            fmt,
+            "profile: awake {} of {} ms: imu: {}, location: {}, storage: {}, notecard: {} ms",
+            self.awake(),
+            self.interval,
+            self.imu,
+            self.location,
+            self.storage,
+            self.notecard
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    /// Cycles in each phase since the last breakdown.
+    cycles: [u64; PHASES],
+
+    /// Cycle count at the end of the last phase.
+    mark: u32,
+
+    /// Start of the interval [ms].
+    since: i64,
+}
+
+impl Profiler {
+    pub fn new(now: i64) -> Profiler {
+        Profiler {
+            cycles: [0; PHASES],
+            mark: 0,
+            since: now,
+        }
+    }
+
+    /// Start of an iteration of the main loop at the cycle count `cycles`, the time since the last
+    /// mark (asleep) is not counted.
+    pub fn start(&mut self, cycles: u32) {
+        self.mark = cycles;
+        self.cycles[Phase::Imu as usize] += IMU_CYCLES.swap(0, Ordering::Relaxed) as u64;
+    }
+
+    /// End of `phase` at the cycle count `cycles`. The counter wraps around (about every 90 s at
+    /// 48 MHz), which is fine as long as a phase is shorter than that.
+    pub fn lap(&mut self, phase: Phase, cycles: u32) {
+        self.cycles[phase as usize] += cycles.wrapping_sub(self.mark) as u64;
+        self.mark = cycles;
+    }
+
+    /// Breakdown since the last one at `now` [ms], with the cycle counter running at `hz`. Starts
+    /// a new interval.
+    pub fn take(&mut self, now: i64, hz: u32) -> Breakdown {
+        let ms = |c: u64| (c / (hz as u64 / 1000)) as u32;
+        let [imu, location, storage, notecard] = self.cycles.map(ms);
+
+        let b = Breakdown {
+            interval: (now - self.since).clamp(0, u32::MAX as i64) as u32,
+            imu,
+            location,
+            storage,
+            notecard,
+        };
+
+        self.cycles = [0; PHASES];
+        self.since = now;
+
+        b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HZ: u32 = 48_000_000;
+
+    #[test]
+    fn laps() {
+        let mut p = Profiler::new(0);
+
+        add_imu(48_000 * 5);
+        p.start(1_000);
+        p.lap(Phase::Storage, 1_000 + 48_000);
+        p.lap(Phase::Notecard, 1_000 + 48_000 * 11);
+
+        // Asleep, not counted.
+        p.start(u32::MAX - 48_000 + 1);
+        p.lap(Phase::Location, 48_000 * 2);
+
+        let b = p.take(60_000, HZ);
+        assert_eq!(
+            b,
+            Breakdown {
+                interval: 60_000,
+                imu: 5,
+                location: 3,
+                storage: 1,
+                notecard: 10,
+            }
+        );
+        assert_eq!(b.awake(), 19);
+
+        // A new interval.
+        assert_eq!(p.take(61_000, HZ).awake(), 0);
+    }
+}
@@ -0,0 +1,174 @@
+//! Provisioning of the Notecard.
+//!
+//! The setup of the Notecard (`hub.set`, `card.transport`, the location mode and tracking, and the
+//! note templates) is kept by the Notecard across reboots, so it only needs to be done on the first
+//! boot and when the setup changes. After the setup a [`Provision`] record is stored in the
+//! local-only notefile `PROVISION_FILE` on the Notecard. At boot the setup is skipped if the stored
+//! record matches the record of the current setup: the same `PROVISION_VERSION` and the same
+//! fingerprint of the settings that go into the setup (product, serial, mode, sync period, GPS
+//! period, transport, firmware version, the summary in the data notes and the features that change
+//! the templates). A new Notecard has no record, and is set up.
+//!
+//! The mode and the outbound period of the hub are set at every boot also when the setup is
+//! skipped (see [`Boot`]): they are changed without changing the setup, to minimum mode before a
+//! planned reset (see `Notecarrier::shutdown`) and in the quiet hours.
+//!
+//! The `provision` command (see `cmd`) forces the full setup.
+
+/// Local-only notefile (not synced to notehub) with the provisioning record.
+pub const PROVISION_FILE: &str = "provision.dbx";
+
+/// Note ID of the record in `PROVISION_FILE`.
+pub const PROVISION_NOTE: &str = "state";
+
+/// Version of the setup of the Notecard, increase when the setup changes (e.g. the templates) so
+/// that provisioned Notecards are set up again.
+pub const PROVISION_VERSION: u32 = 15;
+
+/// Settings that go into the setup of the Notecard.
+pub struct Setup<'a> {
+    pub product: &'a str,
+    pub serial: &'a str,
+    pub continuous: bool,
+
+    /// Maximum time between outbound syncs, less the jitter [minutes].
+    pub outbound: u32,
+    pub gps_period: u32,
+
+    /// Method of `card.transport` (see `transport`).
+    pub transport: &'a str,
+
+    /// The data notes carry a summary (`note_summary` in the config), which changes their
+    /// template.
+    pub note_summary: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Provision {
+    pub version: u32,
+    pub fingerprint: u32,
+}
+
+/// FNV-1a.
+fn hash(h: u32, b: &[u8]) -> u32 {
+    b.iter()
+        .fold(h, |h, b| (h ^ *b as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Features that change the templates, bit 0 is left for `Setup::note_summary`.
+fn features() -> u8 {
+    (cfg!(feature = "encryption") as u8) << 1
+}
+
+impl Provision {
+    pub fn new(s: &Setup) -> Provision {
+        let mut h = 0x811c_9dc5;
+
+        // Separate the strings, so that moving characters between them changes the hash.
+        for b in [
+            s.product.as_bytes(),
+            &[0],
+            s.serial.as_bytes(),
+            &[0],
+            s.transport.as_bytes(),
+            &[0],
+        ] {
+            h = hash(h, b);
+        }
+
+        h = hash(h, env!("CARGO_PKG_VERSION").as_bytes());
+        h = hash(h, &[s.continuous as u8, features() | s.note_summary as u8]);
+        h = hash(h, &s.outbound.to_le_bytes());
+        h = hash(h, &s.gps_period.to_le_bytes());
+
+        Provision {
+            version: PROVISION_VERSION,
+            fingerprint: h,
+        }
+    }
+}
+
+/// Setup of the Notecard at boot.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Boot {
+    /// No record, or the record of another setup: the full setup.
+    Provision,
+
+    /// The record matches the current setup: only the mode and the outbound period of the hub
+    /// are set.
+    Restore,
+}
+
+impl Boot {
+    /// Setup at boot with the `stored` record on the Notecard and the record of the `current`
+    /// setup.
+    pub fn of(stored: Option<Provision>, current: Provision) -> Boot {
+        if stored == Some(current) {
+            Boot::Restore
+        } else {
+            Boot::Provision
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> Setup<'static> {
+        Setup {
+            product: "com.met.no:sfy",
+            serial: "WAVEBUG01",
+            continuous: false,
+            outbound: 38,
+            gps_period: 60,
+            transport: "-",
+            note_summary: false,
+        }
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(Provision::new(&setup()), Provision::new(&setup()));
+        assert_eq!(Provision::new(&setup()).version, PROVISION_VERSION);
+    }
+
+    #[test]
+    fn changed() {
+        let p = Provision::new(&setup());
+
+        let mut s = setup();
+        s.outbound = 39;
+        assert_ne!(Provision::new(&s), p);
+
+        let mut s = setup();
+        s.continuous = true;
+        assert_ne!(Provision::new(&s), p);
+
+        let mut s = setup();
+        s.product = "com.met.no:sf";
+        s.serial = "yWAVEBUG01";
+        assert_ne!(Provision::new(&s), p);
+
+        let mut s = setup();
+        s.transport = "cell-ntn";
+        assert_ne!(Provision::new(&s), p);
+
+        let mut s = setup();
+        s.note_summary = true;
+        assert_ne!(Provision::new(&s), p);
+    }
+
+    #[test]
+    fn boot_after_shutdown() {
+        let p = Provision::new(&setup());
+
+        // The shutdown before a planned reset leaves the record, the hub mode is restored at boot.
+        assert_eq!(Boot::of(Some(p), p), Boot::Restore);
+
+        let mut s = setup();
+        s.gps_period = 120;
+        assert_eq!(Boot::of(Some(p), Provision::new(&s)), Boot::Provision);
+        assert_eq!(Boot::of(None, p), Boot::Provision);
+    }
+}
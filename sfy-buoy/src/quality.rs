@@ -0,0 +1,113 @@
+//! Quality flags of a package.
+//!
+//! Every package carries a bitfield (`AxlPacket::quality`) summarizing the diagnostics of the
+//! package, so that quality control downstream is a single column. A flag is set when a problem
+//! was detected, `0` means that no problems were detected:
+//!
+//! | Bit | Flag               | Meaning                                                           |
+//! |-----|--------------------|-------------------------------------------------------------------|
+//! | 0   | `CLIPPED`          | Samples at the limits of the scaled range.                        |
+//! | 1   | `FIFO_OVERRUN`     | The IMU FIFO overran: the package holds the samples up to the     |
+//! |     |                    | overrun, the samples after were lost.                             |
+//! | 2   | `TIME_UNSYNCED`    | The RTC had not been set from the Notecard, or runs on the        |
+//! |     |                    | fallback oscillator (see `clock`), when the package started.      |
+//! | 3   | `FILTER_TRANSIENT` | The filters were (re-)started in the package: the start-up        |
+//! |     |                    | transient of the FIR is discarded, but the orientation estimate   |
+//! |     |                    | may not have converged.                                           |
+//! | 4   | `GPS_STALE`        | No position, or the position is older than `gps_stale_age` in the |
+//! |     |                    | configuration (default `GPS_STALE_AGE`).                          |
+//! | 5   | `IMU_DISAGREE`     | The two IMUs disagreed (feature `redundant-imu`, see              |
+//! |     |                    | `waves::redundant`), never set with a single IMU.                 |
+//! | 6   | `RTC_STEP`         | The RTC was set while the package was collected, and the package  |
+//! |     |                    | was moved by the step, or the package started within `rtc_settle` |
+//! |     |                    | after the RTC was set (see `clock::StepWatch`).                   |
+//!
+//! The score of a package is the number of checks passed (`CHECKS` for a clean package), see
+//! [`score`].
+
+use crate::axl::AxlPacket;
+use crate::clock;
+
+pub const CLIPPED: u8 = 1 << 0;
+pub const FIFO_OVERRUN: u8 = 1 << 1;
+pub const TIME_UNSYNCED: u8 = 1 << 2;
+pub const FILTER_TRANSIENT: u8 = 1 << 3;
+pub const GPS_STALE: u8 = 1 << 4;
+pub const IMU_DISAGREE: u8 = 1 << 5;
+pub const RTC_STEP: u8 = 1 << 6;
+
+/// Number of checks.
+pub const CHECKS: u8 = 7;
+
+/// Default maximum age of position at the time of the package [s].
+pub const GPS_STALE_AGE: u32 = 3600;
+
+/// Flags for the time of the first sample of a package.
+pub fn time_flags() -> u8 {
+    if clock::time_synced() && !clock::time_degraded() {
+        0
+    } else {
+        TIME_UNSYNCED
+    }
+}
+
+/// Flags that follow from the contents of the package, the position is stale when older than
+/// `stale_age` [s].
+pub fn package_flags(pck: &AxlPacket, stale_age: u32) -> u8 {
+    let mut q = 0;
+
+    if pck.clipped() > 0 {
+        q |= CLIPPED;
+    }
+
+    if pck.position_time == 0 || pck.fix_age() > stale_age {
+        q |= GPS_STALE;
+    }
+
+    q
+}
+
+/// Number of checks passed, from `0` to `CHECKS`.
+pub fn score(quality: u8) -> u8 {
+    CHECKS - (quality & ((1 << CHECKS) - 1)).count_ones() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axl::{AxlPacketBuilder, AXL_SZ};
+
+    fn package() -> AxlPacket {
+        let data = (0..AXL_SZ).map(|_| u16::MAX / 2).collect();
+
+        AxlPacketBuilder::new(100_000_000, 52.0, crate::waves::wire::ACCEL_MAX, data)
+            .position(99_990, 5.3, 60.4, 0.)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn clean() {
+        let p = package();
+        assert_eq!(package_flags(&p, GPS_STALE_AGE), 0);
+        assert_eq!(score(0), CHECKS);
+    }
+
+    #[test]
+    fn flags() {
+        let mut p = package();
+        p.data[4] = u16::MAX;
+        assert_eq!(package_flags(&p, GPS_STALE_AGE), CLIPPED);
+
+        p.position_time = 0;
+        assert_eq!(package_flags(&p, GPS_STALE_AGE), CLIPPED | GPS_STALE);
+
+        p.data[4] = u16::MAX / 2;
+        p.position_time = 100_000 - GPS_STALE_AGE - 1;
+        assert_eq!(package_flags(&p, GPS_STALE_AGE), GPS_STALE);
+        assert_eq!(package_flags(&p, GPS_STALE_AGE + 1), 0);
+
+        assert_eq!(score(CLIPPED | GPS_STALE), CHECKS - 2);
+        assert_eq!(score(0xff), 0);
+    }
+}
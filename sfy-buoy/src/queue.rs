@@ -0,0 +1,667 @@
+//! Queues with a drop policy aware of the priority of the items.
+//!
+//! Every priority has its own fixed capacity in a [`PriorityQueue`], so a flood of low-priority
+//! items (e.g. routine log messages while the modem is offline) can never crowd out critical
+//! ones (warnings and errors). Items are dequeued highest priority first. When the queue of a
+//! priority is full the oldest item of that priority is evicted to make room for the new one,
+//! since the newest state is usually the most useful after an outage.
+//!
+//! Every dropped item, in these queues or in the data queues (which hold only one kind of item
+//! and are shared with the IMU interrupt), is counted per priority in [`DROPPED`].
+//!
+//! The data queues are not priority queues: every item is a package of the time series, of
+//! [`Priority::Low`], which can be requested from the SD-card later. With the `raw` feature the
+//! raw samples travel with their package, and are dropped with it. Health notes are sent by the
+//! main loop as they are made, and warnings and errors go through the log queue, so neither is
+//! dropped when the data queues are full.
+//!
+//! The queues are lock-free and can be used from interrupts.
+//!
+//! When the Notecard is offline for long, the data queues back up: the main loop spends its time
+//! on the Notecard while the IMU fills the storage queue, and the notecard queue is full. The
+//! [`QueuePolicy`] (`queue_policy` in the config) decides what gives way under this pressure:
+//! the SD-card (`live`) or the live transmission (`storage`, default).
+//!
+//! When the storage queue is full nonetheless, the IMU holds the packages that do not fit in a
+//! small [`Overflow`] and moves them to the queue as it is drained. [`QueueFull`] (`queue_full` in
+//! the config) decides which package is dropped when the overflow is full too, or whether the
+//! overflow is used at all. The storage manager does the same with the packages that do not fit
+//! in the full notecard queue. The data queues are [`DataQueue`]s, where the producer can take
+//! the oldest package from the head of the queue (see [`Producer::evict`]).
+//!
+//! The Notecard has an outbound queue of its own, which fills up when the connection is poor.
+//! The depth of this queue (the notes waiting to be synced, from `file.stats`) is checked before
+//! packages are sent to the Notecard, and [`CardQueue`] (`card_queue` in the config) turns it into
+//! backpressure: above `slow` pending notes the notes are added at most every `spacing` ms, and
+//! above `high` no more packages are added until the Notecard has synced. While the Notecard is
+//! paused ([`CARD_PRESSURE`]) every package is written to the SD-card, also with the `live` queue
+//! policy.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use heapless::mpmc::MpMcQueue;
+use heapless::Deque;
+
+#[derive(serde::Serialize, defmt::Format, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Bulk data that can be recovered from the SD-card (time series).
+    Low = 0,
+
+    /// Routine messages.
+    Normal,
+
+    /// Warnings and errors about the health of the buoy.
+    Critical,
+}
+
+const PRIORITIES: usize = 3;
+
+/// Number of dropped items per priority.
+pub struct DropCounts([AtomicU32; PRIORITIES]);
+
+/// Items dropped since boot.
+pub static DROPPED: DropCounts = DropCounts::new();
+
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+pub struct Dropped {
+    pub low: u32,
+    pub normal: u32,
+    pub critical: u32,
+}
+
+impl DropCounts {
+    pub const fn new() -> DropCounts {
+        DropCounts([const { AtomicU32::new(0) }; PRIORITIES])
+    }
+
+    pub fn record(&self, prio: Priority) {
+        self.0[prio as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Dropped {
+        Dropped {
+            low: self.0[Priority::Low as usize].load(Ordering::Relaxed),
+            normal: self.0[Priority::Normal as usize].load(Ordering::Relaxed),
+            critical: self.0[Priority::Critical as usize].load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Free slots left in the storage queue when it is under pressure: the IMU drops the next
+/// packages unless the queue is drained.
+pub const PRESSURE_FREE: usize = 1;
+
+/// The storage queue is under pressure, updated by the IMU and the storage.
+pub static STORAGE_PRESSURE: AtomicBool = AtomicBool::new(false);
+
+/// A queue with `len` items of `capacity` is under pressure.
+pub fn under_pressure(len: usize, capacity: usize) -> bool {
+    len + PRESSURE_FREE >= capacity
+}
+
+/// The outbound queue of the Notecard is deep, updated by the main loop.
+pub static CARD_PRESSURE: AtomicBool = AtomicBool::new(false);
+
+/// Maximum `card_queue.spacing` [ms].
+pub const MAX_CARD_SPACING: u16 = 10_000;
+
+/// Backpressure from the outbound queue of the Notecard. Fields that are not set in an override
+/// take the default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct CardQueue {
+    /// Pending notes where no more packages are added, `0` disables the backpressure.
+    pub high: u32,
+
+    /// Pending notes where the notes are added at most every `spacing`, at most `high`.
+    pub slow: u32,
+
+    /// Spacing of the requests that add notes while slowed down [ms].
+    pub spacing: u16,
+}
+
+impl Default for CardQueue {
+    fn default() -> CardQueue {
+        CardQueue {
+            high: 100,
+            slow: 50,
+            spacing: 1000,
+        }
+    }
+}
+
+/// State of the outbound queue of the Notecard.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum CardState {
+    Normal,
+
+    /// Notes are added at most every `spacing`.
+    Slow,
+
+    /// No more packages are added.
+    Paused,
+}
+
+impl CardQueue {
+    pub fn is_valid(&self) -> bool {
+        self.slow <= self.high && self.spacing <= MAX_CARD_SPACING
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.high > 0
+    }
+
+    /// State with `pending` notes on the Notecard.
+    pub fn state(&self, pending: u32) -> CardState {
+        if !self.is_enabled() {
+            CardState::Normal
+        } else if pending >= self.high {
+            CardState::Paused
+        } else if pending >= self.slow {
+            CardState::Slow
+        } else {
+            CardState::Normal
+        }
+    }
+
+    /// Spacing of the requests that add notes [ms] with `pending` notes, at least `spacing`.
+    pub fn spacing(&self, pending: Option<u32>, spacing: u16) -> u16 {
+        match pending.map(|p| self.state(p)) {
+            Some(CardState::Slow | CardState::Paused) => spacing.max(self.spacing),
+            _ => spacing,
+        }
+    }
+}
+
+/// What gives way when the storage queue is under pressure.
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum QueuePolicy {
+    /// Every package is written to the SD-card. The main loop stops sending packages to the
+    /// Notecard to drain the storage queue, and the live packages that do not fit in the full
+    /// notecard queue are dropped (they can be backfilled from the SD-card). The live
+    /// transmission lags.
+    #[default]
+    Storage,
+
+    /// The packages are forwarded to the notecard queue without being written to the SD-card
+    /// while the storage queue is under pressure, and the main loop keeps sending packages to the
+    /// Notecard. The SD-card gets gaps.
+    Live,
+}
+
+impl QueuePolicy {
+    /// Write the package to the SD-card, rather than only forwarding it to the notecard queue.
+    pub fn store(&self, pressure: bool, note_full: bool) -> bool {
+        match self {
+            QueuePolicy::Storage => true,
+            QueuePolicy::Live => !pressure || note_full,
+        }
+    }
+
+    /// Stop sending packages to the Notecard, so that the storage queue can be drained.
+    pub fn yield_to_storage(&self, pressure: bool) -> bool {
+        match self {
+            QueuePolicy::Storage => pressure,
+            QueuePolicy::Live => false,
+        }
+    }
+}
+
+/// What the IMU does with a package that does not fit in the full data queue.
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueFull {
+    /// The package is dropped, the overflow is not used.
+    #[default]
+    DropNewest,
+
+    /// The package is held in the overflow until there is room in the queue. When the overflow is
+    /// full too the package is dropped.
+    Hold,
+
+    /// The package is held in the overflow until there is room in the queue. When the overflow is
+    /// full the oldest package in the queue is dropped to make room, so the freshest data is kept.
+    DropOldest,
+}
+
+impl QueueFull {
+    /// The package that is dropped, for the log.
+    pub fn dropped(&self) -> &'static str {
+        match self {
+            QueueFull::DropOldest => "oldest queued",
+            _ => "new",
+        }
+    }
+}
+
+/// Queue of data packages with capacity `N`, a power of 2. It is split once into a [`Producer`]
+/// and a [`Consumer`] like a `heapless::spsc::Queue`, but it is backed by a `MpMcQueue` so that
+/// the producer can dequeue the oldest package when the queue is full (see [`Producer::evict`]).
+pub struct DataQueue<T, const N: usize> {
+    queue: MpMcQueue<T, N>,
+
+    /// Packages in the queue, may be off by one while a package is being moved.
+    len: AtomicUsize,
+}
+
+impl<T, const N: usize> DataQueue<T, N> {
+    pub const fn new() -> DataQueue<T, N> {
+        DataQueue {
+            queue: MpMcQueue::new(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        (Producer { queue: self }, Consumer { queue: self })
+    }
+
+    fn enqueue(&self, item: T) -> Result<(), T> {
+        self.queue.enqueue(item)?;
+        self.len.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    fn dequeue(&self) -> Option<T> {
+        let item = self.queue.dequeue()?;
+        self.len.fetch_sub(1, Ordering::AcqRel);
+        Some(item)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire).min(N)
+    }
+}
+
+/// The end of a [`DataQueue`] that packages are added to.
+pub struct Producer<'a, T, const N: usize> {
+    queue: &'a DataQueue<T, N>,
+}
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    pub fn enqueue(&mut self, item: T) -> Result<(), T> {
+        self.queue.enqueue(item)
+    }
+
+    /// Take the oldest package from the head of the queue to make room, `None` if the consumer
+    /// emptied the queue in the meantime.
+    pub fn evict(&mut self) -> Option<T> {
+        self.queue.dequeue()
+    }
+
+    /// There is room in the queue.
+    pub fn ready(&self) -> bool {
+        self.len() < N
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+}
+
+/// The end of a [`DataQueue`] that packages are taken from.
+pub struct Consumer<'a, T, const N: usize> {
+    queue: &'a DataQueue<T, N>,
+}
+
+impl<T, const N: usize> Consumer<'_, T, N> {
+    pub fn dequeue(&mut self) -> Option<T> {
+        self.queue.dequeue()
+    }
+
+    /// There are packages in the queue.
+    pub fn ready(&self) -> bool {
+        !self.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+}
+
+/// Items that did not fit in a full queue, held in order until there is room.
+pub struct Overflow<T, const N: usize> {
+    held: Deque<T, N>,
+    pub policy: QueueFull,
+}
+
+impl<T, const N: usize> Overflow<T, N> {
+    pub const fn new(policy: QueueFull) -> Overflow<T, N> {
+        Overflow {
+            held: Deque::new(),
+            policy,
+        }
+    }
+
+    /// Number of held items.
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// Move the held items to `queue`, oldest first, as long as there is room.
+    pub fn drain<const Q: usize>(&mut self, queue: &mut Producer<'_, T, Q>) {
+        while queue.ready() {
+            let Some(item) = self.held.pop_front() else {
+                break;
+            };
+            queue.enqueue(item).ok();
+        }
+    }
+
+    /// Enqueue `item` in `queue` after the held items. An item that does not fit is held, or an
+    /// item is dropped as decided by the policy. Returns the dropped item.
+    pub fn enqueue<const Q: usize>(
+        &mut self,
+        queue: &mut Producer<'_, T, Q>,
+        item: T,
+    ) -> Option<T> {
+        self.drain(queue);
+
+        let item = if self.held.is_empty() {
+            match queue.enqueue(item) {
+                Ok(()) => return None,
+                Err(item) => item,
+            }
+        } else {
+            item
+        };
+
+        match self.policy {
+            QueueFull::DropNewest => Some(item),
+            QueueFull::Hold => self.held.push_back(item).err(),
+            QueueFull::DropOldest => {
+                // The held packages came after the packages in the queue, the oldest one is at the
+                // head of the queue.
+                let mut oldest = None;
+                if self.held.is_full() {
+                    oldest = queue.evict();
+                    self.drain(queue);
+                }
+
+                let item = if self.held.is_empty() {
+                    match queue.enqueue(item) {
+                        Ok(()) => return oldest,
+                        Err(item) => item,
+                    }
+                } else {
+                    item
+                };
+
+                self.held.push_back(item).err().or(oldest)
+            }
+        }
+    }
+}
+
+/// Queue with capacity `N` for every priority. `N` must be a power of 2.
+pub struct PriorityQueue<T, const N: usize> {
+    queues: [MpMcQueue<T, N>; PRIORITIES],
+}
+
+impl<T, const N: usize> PriorityQueue<T, N> {
+    pub const fn new() -> PriorityQueue<T, N> {
+        PriorityQueue {
+            queues: [MpMcQueue::new(), MpMcQueue::new(), MpMcQueue::new()],
+        }
+    }
+
+    /// Enqueue item, evicting the oldest item of the same priority if full. Returns the evicted
+    /// item.
+    pub fn enqueue(&self, prio: Priority, item: T) -> Option<T> {
+        let q = &self.queues[prio as usize];
+        let mut item = item;
+        let mut evicted = None;
+
+        // Retry in case the queue is filled up again by an interrupt after evicting.
+        loop {
+            match q.enqueue(item) {
+                Ok(()) => return evicted,
+                Err(i) => {
+                    item = i;
+
+                    if let Some(old) = q.dequeue() {
+                        DROPPED.record(prio);
+                        evicted = Some(old);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Dequeue the oldest item of the highest priority.
+    pub fn dequeue(&self) -> Option<(Priority, T)> {
+        use Priority::*;
+
+        [Critical, Normal, Low]
+            .into_iter()
+            .find_map(|p| self.queues[p as usize].dequeue().map(|i| (p, i)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn card_queue() {
+        let c = CardQueue::default();
+        assert!(c.is_valid());
+
+        assert_eq!(c.state(0), CardState::Normal);
+        assert_eq!(c.state(49), CardState::Normal);
+        assert_eq!(c.state(50), CardState::Slow);
+        assert_eq!(c.state(100), CardState::Paused);
+
+        assert_eq!(c.spacing(None, 50), 50);
+        assert_eq!(c.spacing(Some(10), 50), 50);
+        assert_eq!(c.spacing(Some(60), 50), 1000);
+        assert_eq!(c.spacing(Some(60), 2000), 2000);
+
+        let off = CardQueue {
+            high: 0,
+            slow: 0,
+            ..c
+        };
+        assert_eq!(off.state(u32::MAX), CardState::Normal);
+        assert_eq!(off.spacing(Some(u32::MAX), 50), 50);
+
+        assert!(!CardQueue { slow: 101, ..c }.is_valid());
+        assert!(!CardQueue {
+            spacing: 10_001,
+            ..c
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn highest_priority_first() {
+        let q: PriorityQueue<u32, 4> = PriorityQueue::new();
+
+        q.enqueue(Priority::Low, 1);
+        q.enqueue(Priority::Critical, 2);
+        q.enqueue(Priority::Normal, 3);
+        q.enqueue(Priority::Critical, 4);
+
+        assert_eq!(q.dequeue(), Some((Priority::Critical, 2)));
+        assert_eq!(q.dequeue(), Some((Priority::Critical, 4)));
+        assert_eq!(q.dequeue(), Some((Priority::Normal, 3)));
+        assert_eq!(q.dequeue(), Some((Priority::Low, 1)));
+        assert_eq!(q.dequeue(), None);
+    }
+
+    #[test]
+    fn low_priority_does_not_crowd_out_critical() {
+        let q: PriorityQueue<u32, 4> = PriorityQueue::new();
+        let before = DROPPED.get();
+
+        q.enqueue(Priority::Critical, 0);
+
+        for i in 0..100 {
+            q.enqueue(Priority::Low, i);
+        }
+
+        let after = DROPPED.get();
+        assert!(after.low - before.low >= 96);
+
+        assert_eq!(q.dequeue(), Some((Priority::Critical, 0)));
+
+        // The newest low-priority items are kept.
+        assert_eq!(q.dequeue(), Some((Priority::Low, 96)));
+    }
+
+    #[test]
+    fn policies() {
+        use QueuePolicy::*;
+
+        assert!(!under_pressure(0, 11));
+        assert!(!under_pressure(9, 11));
+        assert!(under_pressure(10, 11));
+        assert!(under_pressure(11, 11));
+
+        for (pressure, note_full) in [(false, false), (false, true), (true, false), (true, true)] {
+            assert!(Storage.store(pressure, note_full));
+            assert!(!Live.yield_to_storage(pressure));
+        }
+
+        assert!(Storage.yield_to_storage(true));
+        assert!(!Storage.yield_to_storage(false));
+
+        // Nothing is gained by not storing a package that does not fit in the notecard queue.
+        assert!(!Live.store(true, false));
+        assert!(Live.store(true, true));
+        assert!(Live.store(false, false));
+    }
+
+    #[test]
+    fn evict_oldest() {
+        let q: PriorityQueue<u32, 2> = PriorityQueue::new();
+
+        assert_eq!(q.enqueue(Priority::Normal, 1), None);
+        assert_eq!(q.enqueue(Priority::Normal, 2), None);
+        assert_eq!(q.enqueue(Priority::Normal, 3), Some(1));
+
+        assert_eq!(q.dequeue(), Some((Priority::Normal, 2)));
+        assert_eq!(q.dequeue(), Some((Priority::Normal, 3)));
+    }
+
+    /// The IMU enqueues packages while the main loop is blocked on the Notecard, and only drains
+    /// the queue afterwards.
+    fn congested(policy: QueueFull) -> (Vec<u32>, Vec<u32>) {
+        let mut q: DataQueue<u32, 4> = DataQueue::new();
+        let (mut p, mut c) = q.split();
+        let mut o: Overflow<u32, 3> = Overflow::new(policy);
+
+        let mut dropped = Vec::new();
+        let mut stored = Vec::new();
+
+        // Four packages fit in the queue.
+        for i in 0..10 {
+            dropped.extend(o.enqueue(&mut p, i));
+        }
+
+        while let Some(i) = c.dequeue() {
+            stored.push(i);
+        }
+
+        // The held packages follow as the queue is drained.
+        o.drain(&mut p);
+        dropped.extend(o.enqueue(&mut p, 10));
+        while let Some(i) = c.dequeue() {
+            stored.push(i);
+        }
+
+        assert!(o.is_empty());
+        (stored, dropped)
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            congested(QueueFull::DropNewest),
+            (vec![0, 1, 2, 3, 10], vec![4, 5, 6, 7, 8, 9])
+        );
+        assert_eq!(
+            congested(QueueFull::Hold),
+            (vec![0, 1, 2, 3, 4, 5, 6, 10], vec![7, 8, 9])
+        );
+        assert_eq!(
+            congested(QueueFull::DropOldest),
+            (vec![3, 4, 5, 6, 7, 8, 9, 10], vec![0, 1, 2])
+        );
+    }
+
+    #[test]
+    fn evict_queued() {
+        let mut q: DataQueue<u32, 2> = DataQueue::new();
+        let (mut p, mut c) = q.split();
+        let mut o: Overflow<u32, 1> = Overflow::new(QueueFull::DropOldest);
+
+        for i in 0..3 {
+            assert_eq!(o.enqueue(&mut p, i), None);
+        }
+        assert!(!p.ready());
+
+        // The head of the queue gives way, the held package moves up.
+        assert_eq!(o.enqueue(&mut p, 3), Some(0));
+        assert_eq!((p.len(), o.len()), (2, 1));
+
+        assert_eq!(c.dequeue(), Some(1));
+        assert_eq!(c.dequeue(), Some(2));
+        assert!(c.is_empty());
+
+        o.drain(&mut p);
+        assert_eq!(c.dequeue(), Some(3));
+        assert!(!c.ready());
+    }
+
+    #[test]
+    fn overflow_keeps_order() {
+        let mut q: DataQueue<u32, 2> = DataQueue::new();
+        let (mut p, mut c) = q.split();
+        let mut o: Overflow<u32, 2> = Overflow::new(QueueFull::Hold);
+
+        for i in 0..4 {
+            assert_eq!(o.enqueue(&mut p, i), None);
+        }
+        assert_eq!(o.len(), 2);
+
+        // One slot is freed, the new package queues behind the held ones.
+        assert_eq!(c.dequeue(), Some(0));
+        assert_eq!(o.enqueue(&mut p, 4), None);
+        assert_eq!(o.len(), 2);
+
+        let mut stored = Vec::new();
+        while stored.len() < 4 {
+            o.drain(&mut p);
+            stored.extend(c.dequeue());
+        }
+        assert_eq!(stored, [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,190 @@
+//! Quiet hours: a daily window without transmissions.
+//!
+//! The window is given in minutes after midnight of the RTC, which runs on UTC (it is set from
+//! the Notecard, see `clock`), and may wrap past midnight (e.g. `start: 1320, end: 360` is 22:00 to
+//! 06:00). A window with `start == end` is disabled.
+//!
+//! Sampling, storing to the SD-card and queuing notes on the Notecard go on as usual in the window,
+//! but the hub of the Notecard is set to minimum mode so that it does not connect on its own, and
+//! the sync when the Notecard is filling up (see `Notecarrier::check_and_sync`) is skipped. The
+//! notes pile up on the Notecard until it is 75% full, after which the packages are only kept on
+//! the SD-card (and can be requested later). When the window ends the configured hub mode is
+//! restored and a sync is started, which sends the buffered notes.
+//!
+//! Health notes and log messages (which carry the warnings and errors of the buoy) can be
+//! configured to still be sent in the window, each of them then starts a sync of its own.
+
+/// Minutes in a day.
+pub const DAY: u16 = 24 * 60;
+
+/// Daily window without transmissions. Fields that are not set in an override take the default
+/// value, the default is disabled.
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+#[serde(default)]
+pub struct QuietHours {
+    /// Start of the window [minutes after midnight, UTC].
+    pub start: u16,
+
+    /// End of the window [minutes after midnight, UTC], the window is disabled if equal to
+    /// `start`.
+    pub end: u16,
+
+    /// Send health notes in the window.
+    pub health: bool,
+
+    /// Send log messages in the window.
+    pub log: bool,
+}
+
+impl QuietHours {
+    pub fn is_enabled(&self) -> bool {
+        self.start != self.end
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.start < DAY && self.end < DAY
+    }
+
+    /// Whether `now` [ms since epoch] is in the window.
+    pub fn contains(&self, now: i64) -> bool {
+        let m = (now / 60_000).rem_euclid(DAY as i64) as u16;
+
+        if self.start <= self.end {
+            (self.start..self.end).contains(&m)
+        } else {
+            m >= self.start || m < self.end
+        }
+    }
+}
+
+/// Change of the quiet state.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    /// Entered the window, stop syncing.
+    Start,
+
+    /// Left the window, restore the hub mode and sync.
+    End,
+
+    /// First check after boot outside the window: the hub mode is unknown (it may have been left
+    /// in minimum mode before a reset), restore it without a sync.
+    Outside,
+}
+
+/// Tracks the quiet state across checks.
+#[derive(Debug, Default, Clone)]
+pub struct Quiet {
+    active: Option<bool>,
+}
+
+impl Quiet {
+    pub fn new() -> Quiet {
+        Quiet::default()
+    }
+
+    /// In the window at the last check.
+    pub fn is_active(&self) -> bool {
+        self.active.unwrap_or(false)
+    }
+
+    /// Check `hours` at `now` [ms since epoch], returns the transition since the last check if
+    /// any. Disabling the window ends it.
+    pub fn update(&mut self, hours: &QuietHours, now: i64) -> Option<Transition> {
+        let quiet = hours.is_enabled() && hours.contains(now);
+
+        match self.active.replace(quiet) {
+            Some(was) if was == quiet => None,
+            _ if quiet => Some(Transition::Start),
+            Some(_) => Some(Transition::End),
+            None if hours.is_enabled() => Some(Transition::Outside),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `h:m` on some day [ms since epoch].
+    fn at(h: i64, m: i64) -> i64 {
+        1_700_006_400_000 + (h * 60 + m) * 60_000
+    }
+
+    #[test]
+    fn window() {
+        let q = QuietHours {
+            start: 9 * 60,
+            end: 17 * 60,
+            ..Default::default()
+        };
+        assert!(q.is_enabled());
+        assert!(!q.contains(at(8, 59)));
+        assert!(q.contains(at(9, 0)));
+        assert!(q.contains(at(16, 59)));
+        assert!(!q.contains(at(17, 0)));
+    }
+
+    #[test]
+    fn wraps_midnight() {
+        let q = QuietHours {
+            start: 22 * 60,
+            end: 6 * 60,
+            ..Default::default()
+        };
+        assert!(q.contains(at(22, 0)));
+        assert!(q.contains(at(23, 59)));
+        assert!(q.contains(at(24, 0)));
+        assert!(q.contains(at(29, 59)));
+        assert!(!q.contains(at(30, 0)));
+        assert!(!q.contains(at(21, 59)));
+    }
+
+    #[test]
+    fn disabled() {
+        let q = QuietHours::default();
+        assert!(!q.is_enabled());
+        assert!(q.is_valid());
+
+        let mut s = Quiet::new();
+        assert_eq!(s.update(&q, at(12, 0)), None);
+        assert!(!s.is_active());
+
+        assert!(!QuietHours {
+            start: DAY,
+            end: 0,
+            ..Default::default()
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn transitions() {
+        let q = QuietHours {
+            start: 22 * 60,
+            end: 6 * 60,
+            ..Default::default()
+        };
+        let mut s = Quiet::new();
+
+        assert_eq!(s.update(&q, at(21, 0)), Some(Transition::Outside));
+        assert_eq!(s.update(&q, at(21, 30)), None);
+        assert_eq!(s.update(&q, at(22, 5)), Some(Transition::Start));
+        assert!(s.is_active());
+        assert_eq!(s.update(&q, at(26, 0)), None);
+        assert_eq!(s.update(&q, at(30, 1)), Some(Transition::End));
+        assert!(!s.is_active());
+
+        // Booting in the window.
+        let mut s = Quiet::new();
+        assert_eq!(s.update(&q, at(23, 0)), Some(Transition::Start));
+
+        // Disabling the window ends it.
+        assert_eq!(
+            s.update(&QuietHours::default(), at(23, 5)),
+            Some(Transition::End)
+        );
+    }
+}
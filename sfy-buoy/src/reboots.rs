@@ -0,0 +1,141 @@
+//! Reboot counter, persisted across power cycles.
+//!
+//! The cause of every reset is classified from the reset status register of the MCU
+//! (`RSTGEN.STAT`) and counted at boot. With the `storage` feature the counters are kept in
+//! `REBOOTS_FILE` on the SD-card: the number of reboots over the lifetime of the card, the number
+//! since the start of the deployment (reset by the `start-deployment` command, see `cmd`) and the
+//! number of reboots for each cause. Without the `storage` feature only the current boot is
+//! counted.
+//!
+//! Panics, hard faults and the `reset` command all reset the MCU through `SCB::sys_reset`, so they
+//! are all counted as [`ResetCause::Software`]. The log note sent before the reset tells them
+//! apart.
+
+/// Reboot counters on the SD-card.
+pub const REBOOTS_FILE: &str = "REBOOTS.BIN";
+
+/// Max size of serialized counters.
+pub const REBOOTS_SZ: usize = 64;
+
+/// Number of reset causes.
+pub const CAUSES: usize = 7;
+
+/// Reset status bits of `RSTGEN.STAT`.
+const EXRSTAT: u32 = 1 << 0;
+const PORSTAT: u32 = 1 << 1;
+const BORSTAT: u32 = 1 << 2;
+const SWRSTAT: u32 = 1 << 3;
+const POIRSTAT: u32 = 1 << 4;
+const DBGRSTAT: u32 = 1 << 5;
+const WDRSTAT: u32 = 1 << 6;
+const BOUSTAT: u32 = 1 << 7;
+const BOCSTAT: u32 = 1 << 8;
+const BOFSTAT: u32 = 1 << 9;
+const BOBSTAT: u32 = 1 << 10;
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResetCause {
+    PowerOn,
+    External,
+    Brownout,
+    Watchdog,
+    /// Panic, hard fault or the `reset` command.
+    Software,
+    Debugger,
+    Unknown,
+}
+
+impl ResetCause {
+    /// Classify the reset status register. Several bits may be set, the most specific cause is
+    /// used.
+    pub fn from_stat(stat: u32) -> ResetCause {
+        use ResetCause::*;
+
+        if stat & WDRSTAT != 0 {
+            Watchdog
+        } else if stat & (BORSTAT | BOUSTAT | BOCSTAT | BOFSTAT | BOBSTAT) != 0 {
+            Brownout
+        } else if stat & (SWRSTAT | POIRSTAT) != 0 {
+            Software
+        } else if stat & DBGRSTAT != 0 {
+            Debugger
+        } else if stat & EXRSTAT != 0 {
+            External
+        } else if stat & PORSTAT != 0 {
+            PowerOn
+        } else {
+            Unknown
+        }
+    }
+
+    /// Index in `Reboots::causes`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, PartialEq)]
+pub struct Reboots {
+    /// Reboots over the lifetime of the card.
+    pub total: u32,
+
+    /// Reboots since the start of the deployment.
+    pub deployment: u32,
+
+    /// Reboots for each cause (indexed by `ResetCause::index`).
+    pub causes: [u32; CAUSES],
+}
+
+impl Reboots {
+    /// Count a reboot.
+    pub fn count(&mut self, cause: ResetCause) {
+        self.total = self.total.saturating_add(1);
+        self.deployment = self.deployment.saturating_add(1);
+        self.causes[cause.index()] = self.causes[cause.index()].saturating_add(1);
+    }
+
+    /// Start a new deployment: the reboots since the start of the deployment are reset.
+    pub fn start_deployment(&mut self) {
+        self.deployment = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        use ResetCause::*;
+
+        assert_eq!(ResetCause::from_stat(PORSTAT), PowerOn);
+        assert_eq!(ResetCause::from_stat(EXRSTAT), External);
+        assert_eq!(ResetCause::from_stat(POIRSTAT), Software);
+        assert_eq!(ResetCause::from_stat(BOCSTAT | PORSTAT), Brownout);
+        assert_eq!(ResetCause::from_stat(WDRSTAT | POIRSTAT), Watchdog);
+        assert_eq!(ResetCause::from_stat(0), Unknown);
+    }
+
+    #[test]
+    fn count() {
+        let mut r = Reboots::default();
+        r.count(ResetCause::PowerOn);
+        r.count(ResetCause::Software);
+        r.start_deployment();
+        r.count(ResetCause::Software);
+
+        assert_eq!(r.total, 3);
+        assert_eq!(r.deployment, 1);
+        assert_eq!(r.causes[ResetCause::PowerOn.index()], 1);
+        assert_eq!(r.causes[ResetCause::Software.index()], 2);
+
+        let mut buf = [0u8; REBOOTS_SZ];
+        let mut m = r.clone();
+        m.total = u32::MAX;
+        m.deployment = u32::MAX;
+        m.causes = [u32::MAX; CAUSES];
+        let b = postcard::to_slice(&m, &mut buf).unwrap();
+        assert_eq!(postcard::from_bytes::<Reboots>(b).unwrap(), m);
+    }
+}
@@ -0,0 +1,210 @@
+//! Safe mode after repeated faults.
+//!
+//! A buoy that keeps panicking or resetting in a loop flattens its battery without sending
+//! anything useful. With `resets` in `safe_mode` in the config (`0` disables) the resets caused by
+//! a fault (panics, hard faults and the `reset` command are all software resets, see `reboots`,
+//! and the watchdog) are counted in [`SafeMode::recent`]: a fault reset less than `window` minutes
+//! after the previous boot adds to the count, the count is cleared when the buoy has run for
+//! `window` minutes, and any other reset (power-on, brown-out, ..) starts it again from zero. At
+//! `resets` fault resets in a row the buoy enters safe mode.
+//!
+//! In safe mode the IMU, the GPS, the wave statistics and the storage of packages are not set up.
+//! After the Notecard is set up the buoy only logs a heartbeat to notehub every `heartbeat`
+//! minutes, stating that it is in safe mode and why, and checks for commands. Safe mode is kept
+//! across resets (also power cycles) in `SAFE_MODE_FILE` on the SD-card, until it is left with the
+//! `exit-safe-mode` command (see `cmd`), which also clears the count and resets the buoy to start
+//! normally. Safe mode needs the `storage` feature, without it the count does not survive a reset.
+
+use crate::reboots::ResetCause;
+
+/// Safe mode record on the SD-card.
+pub const SAFE_MODE_FILE: &str = "SAFE.BIN";
+
+/// Max size of serialized record.
+pub const SAFE_MODE_SZ: usize = 16;
+
+/// Maximum `window` and `heartbeat` [minutes], one day.
+pub const MAX_MINUTES: u16 = 24 * 60;
+
+const MINUTE: i64 = 60 * 1000;
+
+/// Configuration of the safe mode. Fields that are not set in an override take the default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    /// Fault resets in a row that enter safe mode, `0` disables.
+    pub resets: u8,
+
+    /// A fault reset within this long after the previous boot is counted [minutes].
+    pub window: u16,
+
+    /// Interval of the heartbeat in safe mode [minutes].
+    pub heartbeat: u16,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            resets: 0,
+            window: 30,
+            heartbeat: 60,
+        }
+    }
+}
+
+impl Config {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_MINUTES).contains(&self.window) && (1..=MAX_MINUTES).contains(&self.heartbeat)
+    }
+
+    /// Interval of the heartbeat [ms].
+    pub fn heartbeat_ms(&self) -> i64 {
+        self.heartbeat as i64 * MINUTE
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, PartialEq)]
+pub struct SafeMode {
+    /// Fault resets in a row, each within `window` of the previous boot.
+    pub recent: u32,
+
+    /// In safe mode, until the `exit-safe-mode` command.
+    pub active: bool,
+}
+
+/// The reset was caused by a fault (or the `reset` command, which cannot be told apart).
+fn is_fault(cause: ResetCause) -> bool {
+    matches!(cause, ResetCause::Software | ResetCause::Watchdog)
+}
+
+impl SafeMode {
+    /// Count the reset at boot. Returns `true` if the buoy should run in safe mode, which is
+    /// never when disabled in `config`.
+    pub fn boot(&mut self, cause: ResetCause, config: &Config) -> bool {
+        if is_fault(cause) {
+            self.recent = self.recent.saturating_add(1);
+        } else {
+            self.recent = 0;
+        }
+
+        if config.resets == 0 {
+            return false;
+        }
+
+        if self.recent >= config.resets as u32 {
+            self.active = true;
+        }
+
+        self.active
+    }
+
+    /// The buoy has run for `uptime` [ms] since boot without a reset: the count is cleared after
+    /// `window`. Returns `true` if it was cleared, and should be persisted.
+    pub fn check_settled(&mut self, uptime: i64, config: &Config) -> bool {
+        if self.recent == 0 || uptime < config.window as i64 * MINUTE {
+            return false;
+        }
+
+        self.recent = 0;
+        true
+    }
+
+    /// Leave safe mode, and clear the count.
+    pub fn exit(&mut self) {
+        self.recent = 0;
+        self.active = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ResetCause::*;
+
+    #[test]
+    fn enter() {
+        let config = Config {
+            resets: 3,
+            ..Default::default()
+        };
+        let mut s = SafeMode::default();
+
+        assert!(!s.boot(PowerOn, &config));
+        assert!(!s.boot(Software, &config));
+        assert!(!s.boot(Watchdog, &config));
+        assert!(s.boot(Software, &config));
+        assert_eq!(s.recent, 3);
+
+        // Kept across any reset.
+        assert!(s.boot(PowerOn, &config));
+        assert_eq!(s.recent, 0);
+
+        s.exit();
+        assert!(!s.boot(Software, &config));
+        assert_eq!(s.recent, 1);
+    }
+
+    #[test]
+    fn settle() {
+        let config = Config {
+            resets: 2,
+            window: 10,
+            ..Default::default()
+        };
+        let mut s = SafeMode::default();
+
+        assert!(!s.boot(Software, &config));
+        assert!(!s.check_settled(10 * MINUTE - 1, &config));
+        assert!(s.check_settled(10 * MINUTE, &config));
+        assert!(!s.check_settled(20 * MINUTE, &config));
+
+        // The count starts again.
+        assert!(!s.boot(Software, &config));
+
+        // A power cycle in between starts it again as well.
+        assert!(!s.boot(PowerOn, &config));
+        assert!(!s.boot(Software, &config));
+        assert!(s.boot(Software, &config));
+    }
+
+    #[test]
+    fn disabled() {
+        let config = Config::default();
+        let mut s = SafeMode {
+            recent: 10,
+            active: true,
+        };
+
+        assert!(!s.boot(Software, &config));
+        assert_eq!(s.recent, 11);
+    }
+
+    #[test]
+    fn valid() {
+        assert!(Config::default().is_valid());
+
+        let c = Config {
+            window: 0,
+            ..Default::default()
+        };
+        assert!(!c.is_valid());
+
+        let c = Config {
+            heartbeat: MAX_MINUTES + 1,
+            ..Default::default()
+        };
+        assert!(!c.is_valid());
+    }
+
+    #[test]
+    fn serialize() {
+        let s = SafeMode {
+            recent: u32::MAX,
+            active: true,
+        };
+
+        let mut buf = [0u8; SAFE_MODE_SZ];
+        let b = postcard::to_slice(&s, &mut buf).unwrap();
+        assert_eq!(postcard::from_bytes::<SafeMode>(b).unwrap(), s);
+    }
+}
@@ -0,0 +1,226 @@
+//! Collections named by UTC day (`Config::day_files`).
+//!
+//! Collections are otherwise named by their number (see [`super::id_to_parts`]), which has no
+//! relation to the time of the packages. With `day_files` a collection is named by the UTC date
+//! of its first package and its number within the day: `YYMMDDNN.X`, e.g. `23111402.22` is the
+//! third collection of 14th of November 2023. `NN` counts `00` to `ZZ` (base 36), so that the
+//! collections of a day sort in order. A collection is closed at midnight (UTC), the rest of its
+//! IDs are skipped, so every collection holds packages of one day only.
+//!
+//! The storage IDs are unchanged, and are still used for replaying packages. The collection of an
+//! ID is found in the index ([`DAYS_INDEX_FILE`]), one line for every collection:
+//! `<collection> <file name>`, e.g. `00000441 23111402`. Collections are numbered in order, so the
+//! index is sorted and searched by seeking. A collection that is not in the index is read by its
+//! number, e.g. collections written before `day_files` was enabled.
+
+use chrono::{Datelike, NaiveDateTime};
+use core::fmt::Write;
+use heapless::String;
+
+use super::{COLLECTION_SIZE, STORAGE_VERSION_STR};
+
+/// Index of the collections named by day.
+pub const DAYS_INDEX_FILE: &str = "DAYS.IDX";
+
+/// Length of a line in the index.
+pub const RECORD_SZ: usize = 18;
+
+/// Maximum number of collections in a day.
+pub const MAX_PER_DAY: u16 = 36 * 36;
+
+const DIGITS: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// A collection named by day.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Entry {
+    pub collection: u32,
+
+    /// UTC date of the first package (`YYMMDD`).
+    pub date: [u8; 6],
+
+    /// Number of the collection within the day.
+    pub n: u16,
+}
+
+/// UTC date (`YYMMDD`) of `timestamp` [ms].
+pub fn date(timestamp: i64) -> [u8; 6] {
+    let d = NaiveDateTime::from_timestamp_opt(timestamp.div_euclid(1000), 0)
+        .unwrap_or_default()
+        .date();
+
+    let mut s = String::<8>::new();
+    write!(
+        s,
+        "{:02}{:02}{:02}",
+        d.year().rem_euclid(100),
+        d.month(),
+        d.day()
+    )
+    .ok();
+
+    let mut date = [b'0'; 6];
+    date.copy_from_slice(&s.as_bytes()[..6]);
+    date
+}
+
+/// The next free ID when the next package has `timestamp` and the current collection is
+/// `current`: the current collection is closed when the package is of another day.
+pub fn roll(next_id: u32, current: Option<&Entry>, timestamp: i64) -> u32 {
+    match current {
+        Some(e)
+            if next_id % COLLECTION_SIZE != 0
+                && e.collection == next_id / COLLECTION_SIZE
+                && e.date != date(timestamp) =>
+        {
+            (e.collection + 1) * COLLECTION_SIZE
+        }
+        _ => next_id,
+    }
+}
+
+impl Entry {
+    /// The entry for a new `collection` starting with a package at `timestamp`, after the last
+    /// collection in the index (`last`). `None` if the day is full.
+    pub fn next(last: Option<&Entry>, collection: u32, timestamp: i64) -> Option<Entry> {
+        let date = date(timestamp);
+        let n = match last {
+            Some(l) if l.date == date => l.n + 1,
+            _ => 0,
+        };
+
+        (n < MAX_PER_DAY).then_some(Entry {
+            collection,
+            date,
+            n,
+        })
+    }
+
+    /// File name without version, e.g. `23111402`.
+    pub fn stem(&self) -> [u8; 8] {
+        let mut s = [0u8; 8];
+        s[..6].copy_from_slice(&self.date);
+        s[6] = DIGITS[(self.n / 36) as usize];
+        s[7] = DIGITS[(self.n % 36) as usize];
+        s
+    }
+
+    pub fn fname(&self) -> String<32> {
+        let mut f: String<32> = String::new();
+        for c in self.stem() {
+            f.push(c as char).unwrap();
+        }
+        f.push_str(".").unwrap();
+        f.push_str(STORAGE_VERSION_STR).unwrap();
+        f
+    }
+
+    /// Line of the index.
+    pub fn to_record(&self) -> [u8; RECORD_SZ] {
+        let mut s = String::<RECORD_SZ>::new();
+        write!(s, "{:08} ", self.collection % 100_000_000).ok();
+
+        let mut r = [b'\n'; RECORD_SZ];
+        r[..9].copy_from_slice(s.as_bytes());
+        r[9..17].copy_from_slice(&self.stem());
+        r
+    }
+
+    pub fn from_record(r: &[u8]) -> Option<Entry> {
+        if r.len() < RECORD_SZ || r[8] != b' ' || r[17] != b'\n' {
+            return None;
+        }
+
+        let collection = core::str::from_utf8(&r[..8]).ok()?.parse().ok()?;
+        let (date, n) = parse_stem(&r[9..17])?;
+
+        Some(Entry {
+            collection,
+            date,
+            n,
+        })
+    }
+}
+
+fn parse_stem(s: &[u8]) -> Option<([u8; 6], u16)> {
+    if s.len() != 8 || !s[..6].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let digit = |c: u8| DIGITS.iter().position(|d| *d == c).map(|d| d as u16);
+    let n = digit(s[6])? * 36 + digit(s[7])?;
+
+    let mut date = [0u8; 6];
+    date.copy_from_slice(&s[..6]);
+    Some((date, n))
+}
+
+/// Whether `stem` is the name (without version) of a collection named by day.
+pub fn is_day_stem(stem: &str) -> bool {
+    parse_stem(stem.as_bytes()).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2023-11-14T22:13:20Z
+    const T: i64 = 1_700_000_000_000;
+    const DAY: i64 = 24 * 3600 * 1000;
+
+    #[test]
+    fn names() {
+        assert_eq!(&date(T), b"231114");
+        assert_eq!(&date(T + 2 * 3600 * 1000), b"231115");
+        assert_eq!(&date(0), b"700101");
+
+        let e = Entry {
+            collection: 441,
+            date: date(T),
+            n: 2,
+        };
+        assert_eq!(e.fname(), "23111402.22");
+        assert_eq!(&e.to_record(), b"00000441 23111402\n");
+        assert_eq!(Entry::from_record(&e.to_record()), Some(e));
+
+        let e = Entry { n: 36 + 10, ..e };
+        assert_eq!(&e.stem(), b"2311141A");
+        assert_eq!(Entry::from_record(&e.to_record()), Some(e));
+
+        assert!(is_day_stem("2311141A"));
+        assert!(!is_day_stem("12345"));
+        assert_eq!(Entry::from_record(b"00000441 2311140"), None);
+    }
+
+    #[test]
+    fn next() {
+        let e = Entry::next(None, 10, T).unwrap();
+        assert_eq!((e.collection, e.n), (10, 0));
+
+        let e = Entry::next(Some(&e), 11, T + 1000).unwrap();
+        assert_eq!((e.collection, e.n), (11, 1));
+
+        // New day.
+        let e = Entry::next(Some(&e), 12, T + DAY).unwrap();
+        assert_eq!((e.collection, e.n, &e.date), (12, 0, b"231115"));
+
+        let full = Entry {
+            n: MAX_PER_DAY - 1,
+            ..e
+        };
+        assert_eq!(Entry::next(Some(&full), 13, T + DAY), None);
+    }
+
+    #[test]
+    fn rollover() {
+        let e = Entry::next(None, 10, T).unwrap();
+
+        assert_eq!(roll(1005, Some(&e), T), 1005);
+        assert_eq!(roll(1005, None, T + DAY), 1005);
+
+        // Closed at midnight.
+        assert_eq!(roll(1005, Some(&e), T + DAY), 1100);
+
+        // A new collection is started anyway.
+        assert_eq!(roll(1100, Some(&e), T + DAY), 1100);
+    }
+}
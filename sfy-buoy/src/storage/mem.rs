@@ -0,0 +1,374 @@
+//! Storage of packages in memory, for testing the storage bookkeeping on the host.
+//!
+//! Packages are serialized the same way as on the SD-card. Reading from a collection that does
+//! not exist fails with `FileNotFound`, like the SD-card does, see
+//! [`MemStorage::remove_collection`]. So does reading past the end of a collection that was
+//! closed early, e.g. at midnight with `day_files` (see `days`).
+//!
+//! A card that keeps garbage is mocked by [`MemStorage::corrupt`]: a sample of the packages read
+//! back is changed, both when a package is verified after it is written and when it is read.
+
+use std::collections::BTreeMap;
+use std::vec::Vec;
+
+use super::days::{self, Entry};
+use super::{ring, Snapshot, StorageBackend, StorageErr, COLLECTION_SIZE};
+use crate::axl::{AxlPacket, SampleEncoding};
+use crate::burst::Capture;
+use crate::deployment::Deployment;
+use crate::health::Health;
+use crate::location_log::Record;
+use crate::reboots::Reboots;
+use crate::safe_mode::SafeMode;
+use crate::sync_history::{SyncHistory, SYNC_HISTORY_CSV_SZ};
+use crate::waves::AxlPacketT;
+
+pub struct MemStorage {
+    /// Serialized packages by ID.
+    packages: BTreeMap<u32, Vec<u8>>,
+    next_id: u32,
+
+    /// The oldest collection, see `ring`.
+    oldest: u32,
+
+    /// Size of the card (bytes).
+    pub card_size: u64,
+
+    /// The card is initialized, `next_id` and `free_space` return `None` otherwise.
+    pub ready: bool,
+
+    /// The next packages stored or read fail with `WriteError` and `ReadPackageError`, like a
+    /// card that has stopped responding.
+    pub fail: u32,
+
+    /// The next packages read back have their first sample changed, like a card that
+    /// acknowledges writes it does not keep.
+    pub corrupt: u32,
+
+    /// Times the card has been re-initialized (see `recovery`).
+    pub reinits: u32,
+
+    pub snapshot: Option<Snapshot>,
+
+    /// Sync history as written to the card (CSV).
+    pub sync_history: Option<heapless::String<SYNC_HISTORY_CSV_SZ>>,
+
+    /// Health file as written to the card.
+    pub health: Vec<u8>,
+
+    /// Location log as written to the card.
+    pub location_log: Vec<u8>,
+
+    pub reboots: Option<Reboots>,
+
+    pub deployment: Option<Deployment>,
+
+    pub safe_mode: Option<SafeMode>,
+
+    /// Burst file as written to the card.
+    pub bursts: Vec<u8>,
+
+    /// Setup log as written to the card.
+    pub setup_log: std::string::String,
+
+    /// Event log as written to the card.
+    pub event_log: std::string::String,
+
+    day_files: bool,
+
+    /// Encoding of the samples of the stored packages.
+    pub sample_encoding: SampleEncoding,
+
+    verify_writes: bool,
+
+    /// Collections named by day, see `days`.
+    pub days: Vec<Entry>,
+}
+
+impl MemStorage {
+    pub fn new(card_size: u64) -> MemStorage {
+        MemStorage {
+            packages: BTreeMap::new(),
+            next_id: 0,
+            oldest: 0,
+            card_size,
+            ready: true,
+            fail: 0,
+            corrupt: 0,
+            reinits: 0,
+            snapshot: None,
+            sync_history: None,
+            health: Vec::new(),
+            location_log: Vec::new(),
+            reboots: None,
+            deployment: None,
+            safe_mode: None,
+            bursts: Vec::new(),
+            setup_log: std::string::String::new(),
+            event_log: std::string::String::new(),
+            day_files: false,
+            sample_encoding: SampleEncoding::Raw,
+            verify_writes: false,
+            days: Vec::new(),
+        }
+    }
+
+    /// Number of stored packages.
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// Remove all packages of a collection, as if the collection file was deleted.
+    pub fn remove_collection(&mut self, collection: u32) {
+        self.packages
+            .retain(|id, _| id / COLLECTION_SIZE != collection);
+    }
+
+    /// Read a stored package back, corrupted by [`corrupt`](Self::corrupt).
+    fn read_back(&mut self, id: u32) -> Result<AxlPacket, StorageErr> {
+        let mut buf = self
+            .packages
+            .get(&id)
+            .ok_or(StorageErr::GenericSdMmmcErr(
+                embedded_sdmmc::Error::FileNotFound,
+            ))?
+            .clone();
+
+        #[cfg(not(feature = "encryption"))]
+        let pck = AxlPacket::from_cobs(&mut buf);
+
+        #[cfg(feature = "encryption")]
+        let pck = AxlPacket::decode_sealed(crate::axl::VERSION, &mut buf, Some(&crate::crypt::KEY));
+
+        let mut pck = pck.map_err(|_| StorageErr::ReadPackageError)?;
+
+        if self.corrupt > 0 && !pck.data.is_empty() {
+            self.corrupt -= 1;
+            pck.data[0] ^= 0x0100;
+        }
+
+        Ok(pck)
+    }
+}
+
+impl StorageBackend for MemStorage {
+    fn store(&mut self, pck: &mut AxlPacketT) -> Result<u32, StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        if self.fail > 0 {
+            self.fail -= 1;
+            return Err(StorageErr::WriteError);
+        }
+
+        let pck = &mut pck.0;
+
+        if self.day_files {
+            self.next_id = days::roll(self.next_id, self.days.last(), pck.timestamp);
+        }
+
+        let id = self.next_id;
+        pck.storage_id = Some(id);
+
+        #[cfg(not(feature = "encryption"))]
+        let buf: Result<heapless::Vec<u8, { crate::axl::AXL_POSTCARD_SZ }>, _> = pck
+            .to_cobs_encoded(self.sample_encoding)
+            .map_err(|_| StorageErr::SerializationError);
+
+        #[cfg(feature = "encryption")]
+        let buf: Result<heapless::Vec<u8, { crate::axl::AXL_POSTCARD_SZ }>, _> =
+            crate::crypt::nonce(pck.timestamp)
+                .and_then(|n| pck.to_sealed_cobs(&crate::crypt::KEY, n))
+                .map_err(|_| StorageErr::SerializationError);
+
+        // Like `Storage`, the ID is only taken by a serialized package.
+        let buf = buf.inspect_err(|_| pck.storage_id = None)?;
+
+        let c = id / COLLECTION_SIZE;
+        if self.day_files && self.days.last().map_or(true, |e| e.collection != c) {
+            let e = Entry::next(self.days.last(), c, pck.timestamp).ok_or(StorageErr::DiskFull)?;
+            self.days.push(e);
+        }
+
+        self.next_id += 1;
+        self.packages.insert(id, buf.to_vec());
+
+        if self.verify_writes && self.read_back(id)?.data != pck.data {
+            return Err(StorageErr::Verify);
+        }
+
+        Ok(id)
+    }
+
+    fn get(&mut self, id: u32) -> Result<AxlPacket, StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        if self.fail > 0 {
+            self.fail -= 1;
+            return Err(StorageErr::ReadPackageError);
+        }
+
+        let collection = id / COLLECTION_SIZE;
+
+        if !self
+            .packages
+            .keys()
+            .any(|id| id / COLLECTION_SIZE == collection)
+        {
+            return Err(StorageErr::GenericSdMmmcErr(
+                embedded_sdmmc::Error::FileNotFound,
+            ));
+        }
+
+        // Collections are only appended to, a missing package is past the end.
+        let pck = self.read_back(id)?;
+
+        if !pck.verify() {
+            return Err(StorageErr::Verify);
+        }
+
+        Ok(pck)
+    }
+
+    fn next_id(&self) -> Option<u32> {
+        self.ready.then_some(self.next_id)
+    }
+
+    fn free_space(&self) -> Option<u64> {
+        self.next_id()
+            .map(|next_id| ring::free_space(self.card_size, self.oldest, next_id))
+    }
+
+    fn remove_oldest(&mut self) -> Result<Option<u32>, StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        if !ring::removable(self.oldest, self.next_id) {
+            return Ok(None);
+        }
+
+        let c = self.oldest;
+        self.remove_collection(c);
+        self.oldest += 1;
+
+        Ok(Some(c))
+    }
+
+    fn write_snapshot(&mut self, s: &Snapshot) -> Result<(), StorageErr> {
+        self.snapshot = Some(s.clone());
+        Ok(())
+    }
+
+    fn write_sync_history(&mut self, h: &SyncHistory) -> Result<(), StorageErr> {
+        let mut s = heapless::String::new();
+        h.write_csv(&mut s)
+            .map_err(|_| StorageErr::SerializationError)?;
+        self.sync_history = Some(s);
+        Ok(())
+    }
+
+    fn append_health(&mut self, h: &Health) -> Result<(), StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        let b = h.to_cobs().map_err(|_| StorageErr::SerializationError)?;
+        self.health.extend_from_slice(&b);
+        Ok(())
+    }
+
+    fn append_location_event(&mut self, r: &Record) -> Result<(), StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        let b = r.to_cobs().map_err(|_| StorageErr::SerializationError)?;
+        self.location_log.extend_from_slice(&b);
+        Ok(())
+    }
+
+    fn write_reboots(&mut self, r: &Reboots) -> Result<(), StorageErr> {
+        self.reboots = Some(r.clone());
+        Ok(())
+    }
+
+    fn write_deployment(&mut self, d: &Deployment) -> Result<(), StorageErr> {
+        self.deployment = Some(d.clone());
+        Ok(())
+    }
+
+    fn write_safe_mode(&mut self, s: &SafeMode) -> Result<(), StorageErr> {
+        self.safe_mode = Some(s.clone());
+        Ok(())
+    }
+
+    fn append_burst(&mut self, b: &Capture) -> Result<(), StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        let b = b.to_cobs().map_err(|_| StorageErr::SerializationError)?;
+        self.bursts.extend_from_slice(&b);
+        Ok(())
+    }
+
+    fn append_setup_log(&mut self, msg: &str) -> Result<(), StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        self.setup_log.push_str(msg);
+        self.setup_log.push('\n');
+        Ok(())
+    }
+
+    fn append_event_log(&mut self, line: &str) -> Result<(), StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        self.event_log.push_str(line);
+        self.event_log.push('\n');
+        Ok(())
+    }
+
+    fn write_test(&mut self, _token: u32) -> Result<(), StorageErr> {
+        if !self.ready {
+            return Err(StorageErr::Uninitialized);
+        }
+
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.ready = false;
+    }
+
+    fn reinit(&mut self) {
+        self.reinits += 1;
+    }
+
+    fn set_day_files(&mut self, day_files: bool) {
+        // Like `Storage`, the next package starts a new collection.
+        if self.day_files != day_files {
+            self.day_files = day_files;
+            self.next_id = self.next_id.next_multiple_of(COLLECTION_SIZE);
+        }
+    }
+
+    fn set_sample_encoding(&mut self, encoding: SampleEncoding) {
+        self.sample_encoding = encoding;
+    }
+
+    fn set_verify_writes(&mut self, verify: bool) {
+        self.verify_writes = verify;
+    }
+}
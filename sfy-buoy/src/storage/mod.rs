@@ -6,10 +6,15 @@
 //! The maximum number of files in a FAT32 directory is 65536. If a data package has ID
 //! `1234567` it is put in the file: `12345.X` where `X` is the version of the storage format
 //! starting with 1. The packages are serialized using the `postcard` format and separated with
-//! `COBS`es. The collection file is the full ID stripped of the last 2 digits. Each collection
-//! file holds 100 packages.
+//! `COBS`es. From version 6 every package starts with a format version tag, see
+//! [`AxlPacket::decode`]. The collection file is the full ID stripped of the last 2 digits. Each
+//! collection file holds 100 packages. With the `encryption` feature the packages are sealed
+//! before they are framed, see `crypt`.
 //!
 //! At 52 Hz and 1024 length data-package, there is 4389 packages per day. That is about 44 collections per day. See tests for more details.
+//!
+//! With `day_files` the collections are named by the UTC day of their packages instead, and the
+//! collection of an ID is found in an index, see `days`.
 
 use core::fmt::Debug;
 use core::ops::DerefMut;
@@ -21,7 +26,19 @@ use embedded_sdmmc::{
 };
 use heapless::{String, Vec};
 
-use crate::axl::{self, AxlPacket, AXL_POSTCARD_SZ};
+use crate::axl::{self, AxlPacket, DecodeError, PackageBuf, SampleEncoding, AXL_POSTCARD_SZ};
+use crate::bist::BIST_TEST_FILE;
+use crate::burst::{Capture, BURST_FILE};
+#[cfg(feature = "decrypt")]
+use crate::crypt;
+use crate::deployment::{Deployment, DEPLOYMENT_FILE, DEPLOYMENT_SZ};
+use crate::health::{Health, HEALTH_FILE};
+use crate::location_log::{Record, LOCATION_LOG_FILE};
+use crate::panic_log::{keep, PANIC_LOG_FILE, PANIC_LOG_KEEP, PANIC_LOG_SZ};
+use crate::postmortem::{Dump, POSTMORTEM_FILE};
+use crate::reboots::{Reboots, REBOOTS_FILE, REBOOTS_SZ};
+use crate::safe_mode::{SafeMode, SAFE_MODE_FILE, SAFE_MODE_SZ};
+use crate::sync_history::{SyncHistory, SYNC_HISTORY_CSV_SZ, SYNC_HISTORY_FILE};
 use crate::waves::AxlPacketT;
 
 #[cfg(feature = "raw")]
@@ -33,18 +50,108 @@ pub const PACKAGE_SZ: usize = AXL_POSTCARD_SZ + RAW_AXL_BYTE_SZ;
 #[cfg(not(feature = "raw"))]
 pub const PACKAGE_SZ: usize = AXL_POSTCARD_SZ;
 
+/// Failures during the setup at boot, one line per failure.
+pub const SETUP_LOG_FILE: &str = "SETUP.LOG";
+
+/// Messages logged for the Notecard, one time stamped line per message (see `log`).
+pub const EVENT_LOG_FILE: &str = "EVENT.LOG";
+
+/// A package as stored in a collection: the COBS-framed package, followed by the raw samples with
+/// the `raw` feature. Generic over the buffer, see [`PackageBuf`].
+pub struct StoredPackage<B: PackageBuf> {
+    buf: B,
+
+    /// Size of the slot of the COBS-framed package.
+    slot: usize,
+}
+
+impl<B: PackageBuf> StoredPackage<B> {
+    /// Zeroed package, with room for the raw samples if `raw`.
+    pub fn new(raw: bool) -> StoredPackage<B> {
+        Self::with_slot(AXL_POSTCARD_SZ, raw)
+    }
+
+    /// Zeroed package in a slot of `slot` bytes, with room for the raw samples if `raw`. Packages
+    /// with gyroscope samples take a larger slot (`axl::GYRO_POSTCARD_SZ`), `sfypack` reads them
+    /// without the `gyro` feature.
+    pub fn with_slot(slot: usize, raw: bool) -> StoredPackage<B> {
+        let sz = if raw {
+            slot + PACKAGE_SZ - AXL_POSTCARD_SZ
+        } else {
+            slot
+        };
+
+        StoredPackage {
+            buf: B::zeroed(sz),
+            slot,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.as_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Buffer to read the stored package into.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.buf.as_mut()
+    }
+
+    /// Decode package of format `version`. The COBS framing is decoded in place.
+    #[cfg(not(feature = "encryption"))]
+    pub fn decode(&mut self, version: u32) -> Result<AxlPacket, DecodeError> {
+        AxlPacket::decode(version, &mut self.buf.as_mut()[..self.slot])
+    }
+
+    /// Decode package of format `version`, sealed with the key of the buoy. The COBS framing is
+    /// decoded in place.
+    #[cfg(feature = "encryption")]
+    pub fn decode(&mut self, version: u32) -> Result<AxlPacket, DecodeError> {
+        self.decode_sealed(version, Some(&crypt::KEY))
+    }
+
+    /// Decode package of format `version` that may be sealed, see
+    /// [`AxlPacket::decode_sealed`].
+    #[cfg(feature = "decrypt")]
+    pub fn decode_sealed(
+        &mut self,
+        version: u32,
+        key: Option<&crypt::Key>,
+    ) -> Result<AxlPacket, DecodeError> {
+        AxlPacket::decode_sealed(version, &mut self.buf.as_mut()[..self.slot], key)
+    }
+
+    /// Raw samples following the package, if there is room for them.
+    pub fn raw(&self) -> Option<&[u8]> {
+        let b = self.buf.as_ref();
+        (b.len() > self.slot).then(|| &b[self.slot..])
+    }
+}
+
 pub mod clock;
+pub mod days;
 mod handles;
+#[cfg(any(test, feature = "host-tests"))]
+pub mod mem;
+pub mod recovery;
+pub mod ring;
+pub mod snapshot;
 
 use clock::CountClock;
+use days::{Entry, DAYS_INDEX_FILE, RECORD_SZ};
 use handles::*;
+use snapshot::{Snapshot, SNAPSHOT_FILE, SNAPSHOT_SZ};
 
 /// Writing to a file seems to take longer time when it has more packages, this can cause timeouts
 /// in the interrupt that drains the IMU FIFO. See <https://github.com/gauteh/sfy/issues/77>.
 pub const COLLECTION_SIZE: u32 = 100;
 pub const STORAGE_VERSION: u32 = axl::VERSION;
+
 #[cfg(not(feature = "target-test"))]
-pub const STORAGE_VERSION_STR: &'static str = "5";
+pub const STORAGE_VERSION_STR: &'static str = "22";
 
 #[cfg(feature = "target-test")]
 pub const STORAGE_VERSION_STR: &'static str = "t";
@@ -60,6 +167,10 @@ pub enum StorageErr {
     SerializationError,
     DiskFull,
     Uninitialized,
+
+    /// The package read back from the card does not match what was written, or its samples do
+    /// not match their CRC.
+    Verify,
 }
 
 impl From<SdMmcError> for StorageErr {
@@ -78,8 +189,19 @@ const SD_RETRY_DELAY: i32 = 10 * 60;
 
 enum SdState {
     Uninitialized,
-    Retry { last_try: i32 },
-    Initialized { next_id: u32 },
+    Retry {
+        last_try: i32,
+    },
+    Initialized {
+        next_id: u32,
+
+        /// The last collection named by day, with `day_files`.
+        day: Option<Entry>,
+
+        /// The oldest collection on the card, `0` unless collections have been removed (see
+        /// `ring`).
+        oldest: u32,
+    },
 }
 
 pub enum SdSpiSpeed {
@@ -87,6 +209,72 @@ pub enum SdSpiSpeed {
     High,
 }
 
+/// Storage of packages, implemented by the SD-card ([`Storage`]) and in memory
+/// ([`mem::MemStorage`], with the `host-tests` feature) for testing [`crate::StorageManager`]
+/// without hardware.
+pub trait StorageBackend {
+    /// Store a new package, returns the storage ID of the package.
+    fn store(&mut self, pck: &mut AxlPacketT) -> Result<u32, StorageErr>;
+
+    fn get(&mut self, id: u32) -> Result<AxlPacket, StorageErr>;
+
+    /// Returns the next free ID, `None` if the storage is not ready.
+    fn next_id(&self) -> Option<u32>;
+
+    /// Estimated free space (bytes), `None` if the storage is not ready.
+    fn free_space(&self) -> Option<u64>;
+
+    /// Remove the oldest collection to make room, returns the removed collection (see `ring`).
+    fn remove_oldest(&mut self) -> Result<Option<u32>, StorageErr>;
+
+    fn write_snapshot(&mut self, s: &Snapshot) -> Result<(), StorageErr>;
+
+    /// Write the sync history (see [`crate::sync_history`]), replacing the previous one.
+    fn write_sync_history(&mut self, h: &SyncHistory) -> Result<(), StorageErr>;
+
+    /// Append a health record (see [`crate::health`]).
+    fn append_health(&mut self, h: &Health) -> Result<(), StorageErr>;
+
+    /// Append a record of the location log (see [`crate::location_log`]).
+    fn append_location_event(&mut self, r: &Record) -> Result<(), StorageErr>;
+
+    /// Write the reboot counters (see [`crate::reboots`]), replacing the previous ones.
+    fn write_reboots(&mut self, r: &Reboots) -> Result<(), StorageErr>;
+
+    /// Write the start of the deployment (see [`crate::deployment`]), replacing the previous one.
+    fn write_deployment(&mut self, d: &Deployment) -> Result<(), StorageErr>;
+
+    /// Write the safe mode record (see [`crate::safe_mode`]), replacing the previous one.
+    fn write_safe_mode(&mut self, s: &SafeMode) -> Result<(), StorageErr>;
+
+    /// Append a burst around an extreme event (see [`crate::burst`]).
+    fn append_burst(&mut self, b: &Capture) -> Result<(), StorageErr>;
+
+    /// Append a line to `SETUP_LOG_FILE`.
+    fn append_setup_log(&mut self, msg: &str) -> Result<(), StorageErr>;
+
+    /// Append a line to `EVENT_LOG_FILE`.
+    fn append_event_log(&mut self, line: &str) -> Result<(), StorageErr>;
+
+    /// Write `token` to a test file and read it back (see [`crate::bist`]).
+    fn write_test(&mut self, token: u32) -> Result<(), StorageErr>;
+
+    /// Release the storage before a planned sleep or reset, see [`Storage::shutdown`].
+    fn shutdown(&mut self);
+
+    /// Drop the state of the card after repeated errors, see [`Storage::reinit`].
+    fn reinit(&mut self);
+
+    /// Name new collections by UTC day (see `days`).
+    fn set_day_files(&mut self, day_files: bool);
+
+    /// Encoding of the samples of the stored packages (see `axl::SampleEncoding`).
+    fn set_sample_encoding(&mut self, encoding: SampleEncoding);
+
+    /// Read every package back after it is written, see [`Storage::set_verify_writes`].
+    fn set_verify_writes(&mut self, verify: bool);
+}
+
 pub struct Storage<Spi: Transfer<u8>, CS: OutputPin>
 where
     <Spi as Transfer<u8>>::Error: Debug,
@@ -96,6 +284,18 @@ where
     reclock_cb: fn(&mut Spi, SdSpiSpeed) -> (),
     clock: CountClock,
     state: SdState,
+
+    /// Size of card in bytes, set when initialized.
+    card_size: u64,
+
+    /// Name collections by UTC day, see `days`.
+    day_files: bool,
+
+    /// Encoding of the samples of the stored packages.
+    sample_encoding: SampleEncoding,
+
+    /// Read every package back after it is written.
+    verify_writes: bool,
 }
 
 impl<Spi: Transfer<u8>, CS: OutputPin> Storage<Spi, CS>
@@ -118,6 +318,10 @@ where
             reclock_cb,
             clock,
             state: SdState::Uninitialized,
+            card_size: 0,
+            day_files: false,
+            sample_encoding: SampleEncoding::Raw,
+            verify_writes: false,
         }
     }
 
@@ -128,24 +332,134 @@ where
     /// Returns the next free ID.
     pub fn next_id(&self) -> Option<u32> {
         match self.state {
-            SdState::Initialized { next_id } => Some(next_id),
+            SdState::Initialized { next_id, .. } => Some(next_id),
             _ => None,
         }
     }
 
+    /// Name new collections by UTC day. The card is initialized again when changed, so that the
+    /// next package starts a new collection.
+    pub fn set_day_files(&mut self, day_files: bool) {
+        if self.day_files != day_files {
+            defmt::info!("Collections named by day: {}", day_files);
+            self.day_files = day_files;
+            self.deinit();
+        }
+    }
+
+    /// Encoding of the samples of the stored packages. Takes effect from the next package, the
+    /// packages of a collection may be in either encoding.
+    pub fn set_sample_encoding(&mut self, encoding: SampleEncoding) {
+        self.sample_encoding = encoding;
+    }
+
+    /// Read every package back from the card after it is written, and compare it to what was
+    /// written. A card may acknowledge a write that does not read back: the package is then
+    /// stored with `StorageErr::Verify`, which counts towards re-initializing the card (see
+    /// `recovery`).
+    pub fn set_verify_writes(&mut self, verify: bool) {
+        self.verify_writes = verify;
+    }
+
+    /// Estimated free space on the card (bytes). Collections are the only files on the card, and
+    /// every ID from the oldest collection up to the next ID is assumed to be used, so this is a
+    /// lower bound (see `ring`).
+    pub fn free_space(&self) -> Option<u64> {
+        match self.state {
+            SdState::Initialized {
+                next_id, oldest, ..
+            } => Some(ring::free_space(self.card_size, oldest, next_id)),
+            _ => None,
+        }
+    }
+
+    /// Remove the oldest collection on the card to make room (see `ring`). Returns the removed
+    /// collection, `None` when only the collection the next package is written to is left.
+    pub fn remove_oldest(&mut self) -> Result<Option<u32>, StorageErr> {
+        let mut block = self.acquire()?;
+
+        let (oldest, next_id) = match *block.state {
+            SdState::Initialized {
+                next_id, oldest, ..
+            } => (oldest, next_id),
+            _ => return Err(StorageErr::Uninitialized),
+        };
+
+        if !ring::removable(oldest, next_id) {
+            return Ok(None);
+        }
+
+        let (f, _, _) = block.find_parts(oldest * COLLECTION_SIZE)?;
+
+        let r: Result<(), StorageErr> = try {
+            let mut c = Controller::new(&block.block, block.clock);
+            let mut v = c.get_volume(VolumeIdx(0))?;
+            let mut root = DirHandle::open_root(&mut c, &mut v)?;
+
+            // The new oldest collection is written first, so that the IDs of the removed
+            // collection are never used again.
+            let mut idx = root.open_file(ring::RING_FILE, Mode::ReadWriteCreateOrTruncate)?;
+            idx.write(&ring::to_record(oldest + 1))?;
+            drop(idx);
+
+            match root.delete_file(&f) {
+                Ok(()) | Err(GenericSdMmcError::FileNotFound) => {}
+                Err(e) => Err(e)?,
+            }
+        };
+
+        match r {
+            Ok(()) => {
+                defmt::info!("Removed collection: {} ({})", oldest, f.as_str());
+
+                if let SdState::Initialized { oldest: o, .. } = block.state {
+                    *o = oldest + 1;
+                }
+
+                Ok(Some(oldest))
+            }
+            Err(e) => {
+                *block.state = SdState::Uninitialized;
+                Err(e)
+            }
+        }
+    }
+
     pub fn deinit(&mut self) {
         self.state = SdState::Uninitialized;
     }
 
+    /// Release the card before a planned sleep or reset. Files are closed after every read and
+    /// write, so nothing is buffered on our side and the file system is consistent once this
+    /// returns. The next storage ID is not persisted here, it is found from the collections when
+    /// the card is initialized again (on the next access), and the last ID written is kept in
+    /// the snapshot. Write the snapshot before shutting down (see
+    /// [`crate::StorageManager::shutdown`]).
+    pub fn shutdown(&mut self) {
+        defmt::info!("Shutting down SD card..");
+        self.sd.deinit();
+        self.state = SdState::Uninitialized;
+    }
+
+    /// Drop the state of the card after repeated errors (see `recovery`). The card is mounted
+    /// again, and the next storage ID found from the collections, on the next access.
+    pub fn reinit(&mut self) {
+        defmt::warn!("Re-initializing SD card..");
+        self.sd.deinit();
+        self.state = SdState::Uninitialized;
+    }
+
     /// Deserialize and return AxlPacket.
     pub fn get(&mut self, id: u32) -> Result<AxlPacket, StorageErr> {
-        defmt::debug!("Reading file: {}", id);
-        let (collection, file, offset) = id_to_parts(id);
+        crate::clog!(Storage, debug, "Reading file: {}", id);
+        let mut block = self.acquire()?;
+        let (collection, file, offset) = block.find_parts(id)?;
 
-        let mut buf: Vec<u8, { AXL_POSTCARD_SZ }> = Vec::new();
-        buf.resize_default(AXL_POSTCARD_SZ).unwrap();
+        let mut pck: StoredPackage<Vec<u8, { AXL_POSTCARD_SZ }>> = StoredPackage::new(false);
 
-        defmt::debug!(
+        crate::clog!(
+            Storage,
+            debug,
             "Reading package id: {} from collection: {}, fileid: {}, offset: {}",
             id,
             collection,
@@ -153,19 +467,292 @@ where
             offset
         );
 
-        let sz = self
-            .acquire()
-            .and_then(|mut block| block.read(&collection, offset, &mut buf))?;
+        let sz = block.read(&collection, offset, pck.as_mut_slice())?;
 
-        defmt::trace!("Read {:?} bytes.", sz);
+        crate::clog!(Storage, trace, "Read {:?} bytes.", sz);
 
         // De-serialize
-        let pck: AxlPacket =
-            postcard::from_bytes_cobs(&mut buf).map_err(|_| StorageErr::ReadPackageError)?;
+        let pck = pck
+            .decode(STORAGE_VERSION)
+            .inspect_err(|e| defmt::error!("Failed to decode package: {:?}", e))
+            .map_err(|_| StorageErr::ReadPackageError)?;
+
+        // A package that decodes may still have had samples changed on the card.
+        if !pck.verify() {
+            defmt::error!("Package id: {} does not match its CRC.", id);
+            return Err(StorageErr::Verify);
+        }
 
         Ok(pck)
     }
 
+    /// Read the config file (`config::CONFIG_FILE`) into `buf`. Returns `Ok(None)` if there is no
+    /// config file.
+    pub fn read_config<'b>(&mut self, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, StorageErr> {
+        self.read_file(crate::config::CONFIG_FILE, buf)
+    }
+
+    /// Read the last snapshot of the state. Returns `Ok(None)` if there is no snapshot.
+    pub fn read_snapshot(&mut self) -> Result<Option<Snapshot>, StorageErr> {
+        let mut buf = [0u8; SNAPSHOT_SZ];
+
+        self.read_file(SNAPSHOT_FILE, &mut buf)?
+            .map(|b| Snapshot::decode(b).map_err(|_| StorageErr::ReadPackageError))
+            .transpose()
+    }
+
+    /// Write snapshot of the state, replacing the previous one.
+    pub fn write_snapshot(&mut self, s: &Snapshot) -> Result<(), StorageErr> {
+        let mut buf = [0u8; SNAPSHOT_SZ];
+        let b = postcard::to_slice(s, &mut buf).map_err(|_| StorageErr::SerializationError)?;
+
+        self.write_file(SNAPSHOT_FILE, b)
+    }
+
+    /// Read the reboot counters. Returns `Ok(None)` if there are none.
+    pub fn read_reboots(&mut self) -> Result<Option<Reboots>, StorageErr> {
+        let mut buf = [0u8; REBOOTS_SZ];
+
+        self.read_file(REBOOTS_FILE, &mut buf)?
+            .map(|b| postcard::from_bytes(b).map_err(|_| StorageErr::ReadPackageError))
+            .transpose()
+    }
+
+    /// Write the reboot counters, replacing the previous ones.
+    pub fn write_reboots(&mut self, r: &Reboots) -> Result<(), StorageErr> {
+        let mut buf = [0u8; REBOOTS_SZ];
+        let b = postcard::to_slice(r, &mut buf).map_err(|_| StorageErr::SerializationError)?;
+
+        self.write_file(REBOOTS_FILE, b)
+    }
+
+    /// Read the start of the deployment. Returns `Ok(None)` if there is none.
+    pub fn read_deployment(&mut self) -> Result<Option<Deployment>, StorageErr> {
+        let mut buf = [0u8; DEPLOYMENT_SZ];
+
+        self.read_file(DEPLOYMENT_FILE, &mut buf)?
+            .map(|b| postcard::from_bytes(b).map_err(|_| StorageErr::ReadPackageError))
+            .transpose()
+    }
+
+    /// Write the start of the deployment, replacing the previous one.
+    pub fn write_deployment(&mut self, d: &Deployment) -> Result<(), StorageErr> {
+        let mut buf = [0u8; DEPLOYMENT_SZ];
+        let b = postcard::to_slice(d, &mut buf).map_err(|_| StorageErr::SerializationError)?;
+
+        self.write_file(DEPLOYMENT_FILE, b)
+    }
+
+    /// Read the safe mode record. Returns `Ok(None)` if there is none.
+    pub fn read_safe_mode(&mut self) -> Result<Option<SafeMode>, StorageErr> {
+        let mut buf = [0u8; SAFE_MODE_SZ];
+
+        self.read_file(SAFE_MODE_FILE, &mut buf)?
+            .map(|b| postcard::from_bytes(b).map_err(|_| StorageErr::ReadPackageError))
+            .transpose()
+    }
+
+    /// Write the safe mode record, replacing the previous one.
+    pub fn write_safe_mode(&mut self, s: &SafeMode) -> Result<(), StorageErr> {
+        let mut buf = [0u8; SAFE_MODE_SZ];
+        let b = postcard::to_slice(s, &mut buf).map_err(|_| StorageErr::SerializationError)?;
+
+        self.write_file(SAFE_MODE_FILE, b)
+    }
+
+    /// Write the sync history as CSV, replacing the previous one.
+    pub fn write_sync_history(&mut self, h: &SyncHistory) -> Result<(), StorageErr> {
+        let mut s = String::<SYNC_HISTORY_CSV_SZ>::new();
+        h.write_csv(&mut s)
+            .map_err(|_| StorageErr::SerializationError)?;
+
+        self.write_file(SYNC_HISTORY_FILE, s.as_bytes())
+    }
+
+    /// Append a health record to `HEALTH_FILE`.
+    pub fn append_health(&mut self, h: &Health) -> Result<(), StorageErr> {
+        let b = h.to_cobs().map_err(|_| StorageErr::SerializationError)?;
+
+        self.append_file(HEALTH_FILE, &b)
+    }
+
+    /// Append a record to `LOCATION_LOG_FILE`.
+    pub fn append_location_event(&mut self, r: &Record) -> Result<(), StorageErr> {
+        let b = r.to_cobs().map_err(|_| StorageErr::SerializationError)?;
+
+        self.append_file(LOCATION_LOG_FILE, &b)
+    }
+
+    /// Append the samples frozen by a fault to `POSTMORTEM_FILE`.
+    pub fn append_postmortem(&mut self, d: &Dump) -> Result<(), StorageErr> {
+        let b = d.to_cobs().map_err(|_| StorageErr::SerializationError)?;
+
+        self.append_file(POSTMORTEM_FILE, &b)
+    }
+
+    /// Append a line to `PANIC_LOG_FILE`, rotating the file when it is full (see `panic_log`).
+    pub fn append_panic_log(&mut self, line: &str) -> Result<(), StorageErr> {
+        let mut l = String::<{ crate::log::LOG_LINE_SZ + 1 }>::new();
+        l.push_str(line)
+            .and_then(|_| l.push('\n'))
+            .map_err(|_| StorageErr::SerializationError)?;
+
+        let mut block = self.acquire()?;
+        let mut tail = [0u8; PANIC_LOG_KEEP];
+
+        let r: Result<(), StorageErr> = try {
+            let mut c = Controller::new(&block.block, block.clock);
+            let mut v = c.get_volume(VolumeIdx(0))?;
+            let mut root = DirHandle::open_root(&mut c, &mut v)?;
+
+            let mut f = root.open_file(PANIC_LOG_FILE, Mode::ReadWriteCreateOrAppend)?;
+            let len = f.length();
+
+            if len + l.len() as u32 > PANIC_LOG_SZ {
+                let start = len.saturating_sub(PANIC_LOG_KEEP as u32);
+                f.seek_from_start(start)
+                    .map_err(|_| StorageErr::ReadPackageError)?;
+                let n = free(|_| f.read(&mut tail))?;
+                drop(f);
+
+                // The first line is only cut when the tail starts inside the file.
+                let kept = if start == 0 {
+                    &tail[..n]
+                } else {
+                    keep(&tail[..n])
+                };
+
+                let mut f = root.open_file(PANIC_LOG_FILE, Mode::ReadWriteCreateOrTruncate)?;
+                f.write(kept)?;
+                f.write(l.as_bytes())?;
+            } else {
+                f.seek_from_end(0).map_err(|_| StorageErr::WriteError)?;
+                f.write(l.as_bytes())?;
+            }
+        };
+
+        if r.is_err() {
+            *block.state = SdState::Uninitialized;
+        }
+
+        r
+    }
+
+    /// Append a burst to `BURST_FILE`.
+    pub fn append_burst(&mut self, b: &Capture) -> Result<(), StorageErr> {
+        let b = b.to_cobs().map_err(|_| StorageErr::SerializationError)?;
+
+        self.append_file(BURST_FILE, &b)
+    }
+
+    /// Append a line to `SETUP_LOG_FILE`.
+    pub fn append_setup_log(&mut self, msg: &str) -> Result<(), StorageErr> {
+        let mut line = String::<256>::new();
+        line.push_str(msg)
+            .and_then(|_| line.push('\n'))
+            .map_err(|_| StorageErr::SerializationError)?;
+
+        self.append_file(SETUP_LOG_FILE, line.as_bytes())
+    }
+
+    /// Append a line to `EVENT_LOG_FILE`.
+    pub fn append_event_log(&mut self, line: &str) -> Result<(), StorageErr> {
+        let mut l = String::<{ crate::log::LOG_LINE_SZ + 1 }>::new();
+        l.push_str(line)
+            .and_then(|_| l.push('\n'))
+            .map_err(|_| StorageErr::SerializationError)?;
+
+        self.append_file(EVENT_LOG_FILE, l.as_bytes())
+    }
+
+    /// Write `token` and a test pattern to `BIST_TEST_FILE`, and read it back.
+    pub fn write_test(&mut self, token: u32) -> Result<(), StorageErr> {
+        let mut b = [0u8; 64];
+        b[..4].copy_from_slice(&token.to_le_bytes());
+        for (i, x) in b[4..].iter_mut().enumerate() {
+            *x = (i as u8).wrapping_mul(0x5a) ^ (token as u8);
+        }
+
+        self.write_file(BIST_TEST_FILE, &b)?;
+
+        let mut r = [0u8; 64];
+        match self.read_file(BIST_TEST_FILE, &mut r)? {
+            Some(r) if r == b => Ok(()),
+            _ => Err(StorageErr::WriteError),
+        }
+    }
+
+    /// Append to a file, creating it if it does not exist.
+    fn append_file(&mut self, name: &str, b: &[u8]) -> Result<(), StorageErr> {
+        let mut block = self.acquire()?;
+
+        let r: Result<(), StorageErr> = try {
+            let mut c = Controller::new(&block.block, block.clock);
+            let mut v = c.get_volume(VolumeIdx(0))?;
+            let mut root = DirHandle::open_root(&mut c, &mut v)?;
+            let mut f = root.open_file(name, Mode::ReadWriteCreateOrAppend)?;
+            f.seek_from_end(0).map_err(|_| StorageErr::WriteError)?;
+            f.write(b)?;
+        };
+
+        if r.is_err() {
+            *block.state = SdState::Uninitialized;
+        }
+
+        r
+    }
+
+    /// Write a small file, replacing the previous one.
+    fn write_file(&mut self, name: &str, b: &[u8]) -> Result<(), StorageErr> {
+        let mut block = self.acquire()?;
+
+        let r: Result<(), StorageErr> = try {
+            let mut c = Controller::new(&block.block, block.clock);
+            let mut v = c.get_volume(VolumeIdx(0))?;
+            let mut root = DirHandle::open_root(&mut c, &mut v)?;
+            let mut f = root.open_file(name, Mode::ReadWriteCreateOrTruncate)?;
+            f.write(b)?;
+        };
+
+        if r.is_err() {
+            *block.state = SdState::Uninitialized;
+        }
+
+        r
+    }
+
+    /// Read a small file into `buf`. Returns `Ok(None)` if the file does not exist.
+    fn read_file<'b>(
+        &mut self,
+        name: &str,
+        buf: &'b mut [u8],
+    ) -> Result<Option<&'b [u8]>, StorageErr> {
+        let mut block = self.acquire()?;
+
+        let sz: Result<usize, StorageErr> = try {
+            let mut c = Controller::new(&block.block, block.clock);
+            let mut v = c.get_volume(VolumeIdx(0))?;
+            let mut root = DirHandle::open_root(&mut c, &mut v)?;
+            let mut f = root.open_file(name, Mode::ReadOnly)?;
+
+            if f.length() as usize > buf.len() {
+                defmt::error!("File {} too large: {} bytes", name, f.length());
+                return Err(StorageErr::ReadPackageError);
+            }
+
+            free(|_| f.read(buf))?
+        };
+
+        match sz {
+            Ok(sz) => Ok(Some(&buf[..sz])),
+            Err(StorageErr::GenericSdMmmcErr(GenericSdMmcError::FileNotFound)) => Ok(None),
+            Err(e) => {
+                *block.state = SdState::Uninitialized;
+                Err(e)
+            }
+        }
+    }
+
     /// Store a new package.
     pub fn store(&mut self, pck: &mut AxlPacketT) -> Result<u32, StorageErr> {
         #[cfg(feature = "raw")]
@@ -174,22 +761,45 @@ where
         #[cfg(not(feature = "raw"))]
         let (pck,) = pck;
 
+        let encoding = self.sample_encoding;
+        let verify = self.verify_writes;
         let mut block = self.acquire()?;
+        block.roll_day(pck.timestamp);
 
-        // If writing fails we will always start a new collection, so ID's should not get out of
-        // sync within one collection file.
-        let id = block.advance_id()?;
-        let (collection, fid, offset) = id_to_parts(id);
-
-        // Package now has a storage ID.
+        // Package gets the next ID, which is only taken once the package has been serialized:
+        // packages are appended to the collection, so a skipped ID would shift the following
+        // packages out of their slots.
+        let id = block.peek_id()?;
         pck.storage_id = Some(id);
 
         // Serialize
-        let mut buf: Vec<u8, { AXL_POSTCARD_SZ }> = postcard::to_vec_cobs(pck)
-            .inspect_err(|e| defmt::error!("Serialization: {:?}", defmt::Debug2Format(e)))
-            .map_err(|_| StorageErr::SerializationError)?;
+        #[cfg(not(feature = "encryption"))]
+        let buf = pck
+            .to_cobs_encoded::<{ AXL_POSTCARD_SZ }>(encoding)
+            .inspect_err(|e| defmt::error!("Serialization: {}", e))
+            .ok();
+
+        #[cfg(feature = "encryption")]
+        let buf = crypt::nonce(pck.timestamp)
+            .and_then(|n| pck.to_sealed_cobs::<{ AXL_POSTCARD_SZ }>(&crypt::KEY, n))
+            .inspect_err(|e| defmt::error!("Serialization: {}", e))
+            .ok();
+
+        let mut buf = match buf {
+            Some(buf) => buf,
+            None => {
+                pck.storage_id = None;
+                return Err(StorageErr::SerializationError);
+            }
+        };
         buf.resize_default(buf.capacity()).unwrap();
 
+        // If writing fails we will always start a new collection, so ID's should not get out of
+        // sync within one collection file.
+        let next = block.advance_id()?;
+        debug_assert_eq!(next, id);
+        let (collection, fid, offset) = block.new_parts(id, pck.timestamp)?;
+
         // Serialize raw bytes
         #[cfg(feature = "raw")]
         let raw_bytes = {
@@ -206,7 +816,9 @@ where
         let raw_bytes: &[u8] = &[];
 
         // And write..
-        defmt::info!(
+        crate::clog!(
+            Storage,
+            info,
             "Writing package to card id: {}, size: {} + {}, timestamp: {}, collection: {}, fileid: {}, offset: {}",
             id,
             buf.len(),
@@ -226,10 +838,108 @@ where
             );
         }
 
+        if verify && !block.verify(&collection, offset, &buf)? {
+            defmt::error!(
+                "Package id: {} does not read back as written from collection: {}",
+                id,
+                collection
+            );
+            return Err(StorageErr::Verify);
+        }
+
         Ok(id)
     }
 }
 
+impl<Spi: Transfer<u8>, CS: OutputPin> StorageBackend for Storage<Spi, CS>
+where
+    <Spi as Transfer<u8>>::Error: Debug,
+{
+    fn store(&mut self, pck: &mut AxlPacketT) -> Result<u32, StorageErr> {
+        Storage::store(self, pck)
+    }
+
+    fn get(&mut self, id: u32) -> Result<AxlPacket, StorageErr> {
+        Storage::get(self, id)
+    }
+
+    fn next_id(&self) -> Option<u32> {
+        Storage::next_id(self)
+    }
+
+    fn free_space(&self) -> Option<u64> {
+        Storage::free_space(self)
+    }
+
+    fn remove_oldest(&mut self) -> Result<Option<u32>, StorageErr> {
+        Storage::remove_oldest(self)
+    }
+
+    fn write_snapshot(&mut self, s: &Snapshot) -> Result<(), StorageErr> {
+        Storage::write_snapshot(self, s)
+    }
+
+    fn write_sync_history(&mut self, h: &SyncHistory) -> Result<(), StorageErr> {
+        Storage::write_sync_history(self, h)
+    }
+
+    fn append_health(&mut self, h: &Health) -> Result<(), StorageErr> {
+        Storage::append_health(self, h)
+    }
+
+    fn append_location_event(&mut self, r: &Record) -> Result<(), StorageErr> {
+        Storage::append_location_event(self, r)
+    }
+
+    fn write_reboots(&mut self, r: &Reboots) -> Result<(), StorageErr> {
+        Storage::write_reboots(self, r)
+    }
+
+    fn write_deployment(&mut self, d: &Deployment) -> Result<(), StorageErr> {
+        Storage::write_deployment(self, d)
+    }
+
+    fn write_safe_mode(&mut self, s: &SafeMode) -> Result<(), StorageErr> {
+        Storage::write_safe_mode(self, s)
+    }
+
+    fn append_burst(&mut self, b: &Capture) -> Result<(), StorageErr> {
+        Storage::append_burst(self, b)
+    }
+
+    fn append_setup_log(&mut self, msg: &str) -> Result<(), StorageErr> {
+        Storage::append_setup_log(self, msg)
+    }
+
+    fn append_event_log(&mut self, line: &str) -> Result<(), StorageErr> {
+        Storage::append_event_log(self, line)
+    }
+
+    fn write_test(&mut self, token: u32) -> Result<(), StorageErr> {
+        Storage::write_test(self, token)
+    }
+
+    fn shutdown(&mut self) {
+        Storage::shutdown(self)
+    }
+
+    fn reinit(&mut self) {
+        Storage::reinit(self)
+    }
+
+    fn set_day_files(&mut self, day_files: bool) {
+        Storage::set_day_files(self, day_files)
+    }
+
+    fn set_sample_encoding(&mut self, encoding: SampleEncoding) {
+        Storage::set_sample_encoding(self, encoding)
+    }
+
+    fn set_verify_writes(&mut self, verify: bool) {
+        Storage::set_verify_writes(self, verify)
+    }
+}
+
 pub struct BlockSpiHandle<'a, Spi: Transfer<u8>, CS: OutputPin>
 where
     <Spi as Transfer<u8>>::Error: Debug,
@@ -237,6 +947,7 @@ where
     block: BlockSpi<'a, Spi, CS>,
     clock: &'a CountClock,
     state: &'a mut SdState,
+    day_files: bool,
 }
 
 impl<'spi, Spi: Transfer<u8> + 'spi, CS: OutputPin + 'spi> BlockSpiHandle<'spi, Spi, CS>
@@ -277,25 +988,41 @@ where
                 defmt::debug!("Increasing SPI speed.");
                 (storage.reclock_cb)(block.spi().deref_mut(), SdSpiSpeed::High);
 
-                let sz = block.card_size_bytes()? / 1024_u64.pow(2);
-                defmt::info!("SD card size: {} mb", sz);
+                storage.card_size = block.card_size_bytes()?;
+                defmt::info!("SD card size: {} mb", storage.card_size / 1024_u64.pow(2));
 
                 // XXX: This is a slow operation which is likely to cause trouble if it is done on
                 // every send to notecard loop. Hopefully we will fail above (quickly
                 // enough), otherwise this can only be attempted seldomly.
-                let next_id = Self::find_first_free_collection(&mut block, &storage.clock, None)?
-                    * COLLECTION_SIZE;
+                // Removed collections are not used again, see `ring`.
+                let oldest = Self::oldest(&mut block, &storage.clock)?;
+                let mut next =
+                    Self::find_first_free_collection(&mut block, &storage.clock, Some(oldest))?;
+
+                // Continue after the last collection named by day, also when `day_files` has been
+                // disabled since, so that the index stays valid.
+                let day = Self::last_day(&mut block, &storage.clock)?;
+                if let Some(d) = &day {
+                    next = next.max(d.collection + 1);
+                }
+
+                let next_id = next * COLLECTION_SIZE;
                 defmt::info!("Next free ID: {}", next_id);
 
-                storage.state = SdState::Initialized { next_id };
+                storage.state = SdState::Initialized {
+                    next_id,
+                    day,
+                    oldest,
+                };
 
                 Ok(BlockSpiHandle {
                     block,
                     clock: &storage.clock,
                     state: &mut storage.state,
+                    day_files: storage.day_files,
                 })
             }
-            SdState::Initialized { next_id: _ } => {
+            SdState::Initialized { .. } => {
                 let block = storage
                     .sd
                     .acquire()
@@ -305,6 +1032,7 @@ where
                     block,
                     clock: &storage.clock,
                     state: &mut storage.state,
+                    day_files: storage.day_files,
                 })
             }
         }
@@ -370,14 +1098,77 @@ where
         sz
     }
 
+    /// Read the package at `offset` in `collection` back and compare it to `buf`, in chunks so that
+    /// the package is not read into a second buffer.
+    pub fn verify(
+        &mut self,
+        collection: &str,
+        offset: usize,
+        buf: &[u8],
+    ) -> Result<bool, StorageErr> {
+        let same: Result<bool, StorageErr> = try {
+            let mut c = Controller::new(&self.block, self.clock);
+            let mut v = c.get_volume(VolumeIdx(0))?;
+            let mut root = DirHandle::open_root(&mut c, &mut v)?;
+            let mut f = root.open_file(collection, Mode::ReadOnly)?;
+
+            f.seek_from_start(offset as u32)
+                .map_err(|_| StorageErr::ReadPackageError)?;
+
+            let mut chunk = [0u8; 512];
+            let mut same = true;
+
+            for expected in buf.chunks(chunk.len()) {
+                let n = free(|_| f.read(&mut chunk[..expected.len()]))?;
+
+                if &chunk[..n] != expected {
+                    same = false;
+                    break;
+                }
+            }
+
+            same
+        };
+
+        if same.is_err() {
+            *self.state = SdState::Uninitialized;
+        }
+
+        same
+    }
+
+    /// The ID the next package will get from [`advance_id`](Self::advance_id).
+    fn peek_id(&self) -> Result<u32, StorageErr> {
+        match *self.state {
+            SdState::Initialized { next_id, .. } => Ok(next_id),
+            _ => Err(StorageErr::Uninitialized),
+        }
+    }
+
+    /// Close the current collection if the package at `timestamp` is of another day than the
+    /// collection, with `day_files`.
+    fn roll_day(&mut self, timestamp: i64) {
+        if let SdState::Initialized { next_id, day, .. } = &mut self.state {
+            if self.day_files {
+                let n = days::roll(*next_id, day.as_ref(), timestamp);
+
+                if n != *next_id {
+                    defmt::info!("New day, closing collection at: {}", *next_id);
+                    *next_id = n;
+                }
+            }
+        }
+    }
+
     /// Get the next free ID (and advance to new collection if necessary).
     fn advance_id(&mut self) -> Result<u32, StorageErr> {
-        if let SdState::Initialized { next_id: id } = &mut self.state {
+        if let SdState::Initialized { next_id: id, .. } = &mut self.state {
             let current = *id;
             let mut next_id = *id + 1;
 
-            // Check that the next collection is free, if rolling over.
-            if next_id % COLLECTION_SIZE == 0 {
+            // Check that the next collection is free, if rolling over. Collections named by day
+            // are numbered by the index.
+            if next_id % COLLECTION_SIZE == 0 && !self.day_files {
                 let c = next_id / COLLECTION_SIZE;
                 let nc = Self::find_first_free_collection(&mut self.block, self.clock, Some(c))?;
 
@@ -420,6 +1211,175 @@ where
         Err(StorageErr::DiskFull)
     }
 
+    /// The oldest collection on the card, as kept in `ring::RING_FILE` when collections have been
+    /// removed. `0` otherwise.
+    fn oldest<'a>(
+        block: &mut BlockSpi<'a, Spi, CS>,
+        clock: &CountClock,
+    ) -> Result<u32, StorageErr> {
+        let mut c = Controller::new(&block, clock);
+        let mut v = c.get_volume(VolumeIdx(0))?;
+        let mut root = DirHandle::open_root(&mut c, &mut v)?;
+
+        let mut f = match root.open_file(ring::RING_FILE, Mode::ReadOnly) {
+            Ok(f) => f,
+            Err(GenericSdMmcError::FileNotFound) => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut r = [0u8; ring::RECORD_SZ];
+        let n = f.read(&mut r)?;
+
+        ring::from_record(&r[..n]).ok_or(StorageErr::ParseIDFailure)
+    }
+
+    /// The last collection in the index of collections named by day, if any.
+    fn last_day<'a>(
+        block: &mut BlockSpi<'a, Spi, CS>,
+        clock: &CountClock,
+    ) -> Result<Option<Entry>, StorageErr> {
+        let mut c = Controller::new(&block, clock);
+        let mut v = c.get_volume(VolumeIdx(0))?;
+        let mut root = DirHandle::open_root(&mut c, &mut v)?;
+
+        let mut f = match root.open_file(DAYS_INDEX_FILE, Mode::ReadOnly) {
+            Ok(f) => f,
+            Err(GenericSdMmcError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let n = f.length() as usize / RECORD_SZ;
+        if n == 0 {
+            return Ok(None);
+        }
+
+        let mut r = [0u8; RECORD_SZ];
+        f.seek_from_start(((n - 1) * RECORD_SZ) as u32)
+            .map_err(|_| StorageErr::ReadPackageError)?;
+        f.read(&mut r)?;
+
+        Ok(Entry::from_record(&r))
+    }
+
+    /// Find a collection in the index of collections named by day (binary search, the index is
+    /// sorted).
+    fn find_day(&mut self, collection: u32) -> Result<Option<Entry>, StorageErr> {
+        let mut c = Controller::new(&self.block, self.clock);
+        let mut v = c.get_volume(VolumeIdx(0))?;
+        let mut root = DirHandle::open_root(&mut c, &mut v)?;
+
+        let mut f = match root.open_file(DAYS_INDEX_FILE, Mode::ReadOnly) {
+            Ok(f) => f,
+            Err(GenericSdMmcError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut r = [0u8; RECORD_SZ];
+        let (mut lo, mut hi) = (0, f.length() as usize / RECORD_SZ);
+
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            f.seek_from_start((mid * RECORD_SZ) as u32)
+                .map_err(|_| StorageErr::ReadPackageError)?;
+            f.read(&mut r)?;
+
+            match Entry::from_record(&r) {
+                Some(e) if e.collection == collection => return Ok(Some(e)),
+                Some(e) if e.collection < collection => lo = mid + 1,
+                Some(_) => hi = mid,
+                None => return Err(StorageErr::ParseIDFailure),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Start a new collection named by day, and add it to the index. The number within the day
+    /// is advanced past existing files, in case the day was seen before (e.g. after the clock was
+    /// set back).
+    fn start_day(&mut self, collection: u32, timestamp: i64) -> Result<Entry, StorageErr> {
+        let last = match *self.state {
+            SdState::Initialized { day, .. } => day,
+            _ => return Err(StorageErr::Uninitialized),
+        };
+
+        let mut c = Controller::new(&self.block, self.clock);
+        let mut v = c.get_volume(VolumeIdx(0))?;
+        let mut root = DirHandle::open_root(&mut c, &mut v)?;
+
+        let mut e =
+            Entry::next(last.as_ref(), collection, timestamp).ok_or(StorageErr::DiskFull)?;
+        loop {
+            match root.find_directory_entry(&e.fname()) {
+                Ok(_) => {
+                    e = Entry::next(Some(&e), collection, timestamp).ok_or(StorageErr::DiskFull)?
+                }
+                Err(GenericSdMmcError::FileNotFound) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        defmt::info!(
+            "Starting new collection: {} ({})",
+            collection,
+            e.fname().as_str()
+        );
+
+        let mut f = root.open_file(DAYS_INDEX_FILE, Mode::ReadWriteCreateOrAppend)?;
+        f.seek_from_end(0).map_err(|_| StorageErr::WriteError)?;
+        f.write(&e.to_record())?;
+
+        Ok(e)
+    }
+
+    /// Collection file, file number and offset for a new package with `id`, starting a new
+    /// collection named by day if necessary.
+    fn new_parts(
+        &mut self,
+        id: u32,
+        timestamp: i64,
+    ) -> Result<(String<32>, u32, usize), StorageErr> {
+        let (collection, fid, offset) = id_to_parts(id);
+
+        if !self.day_files {
+            return Ok((collection, fid, offset));
+        }
+
+        let c = id / COLLECTION_SIZE;
+        let e = match *self.state {
+            SdState::Initialized { day: Some(e), .. } if e.collection == c => e,
+            _ => {
+                let e = self
+                    .start_day(c, timestamp)
+                    .inspect_err(|_| *self.state = SdState::Uninitialized)?;
+
+                if let SdState::Initialized { day, .. } = &mut self.state {
+                    *day = Some(e);
+                }
+                e
+            }
+        };
+
+        Ok((e.fname(), fid, offset))
+    }
+
+    /// Collection file, file number and offset of a stored package with `id`. Collections that
+    /// are not in the index of collections named by day are read by their number, also when
+    /// `day_files` is disabled.
+    fn find_parts(&mut self, id: u32) -> Result<(String<32>, u32, usize), StorageErr> {
+        let (collection, fid, offset) = id_to_parts(id);
+        let c = id / COLLECTION_SIZE;
+
+        let e = match *self.state {
+            SdState::Initialized { day: Some(e), .. } if e.collection == c => Some(e),
+            _ => self
+                .find_day(c)
+                .inspect_err(|_| *self.state = SdState::Uninitialized)?,
+        };
+
+        Ok((e.map_or(collection, |e| e.fname()), fid, offset))
+    }
+
     pub fn remove_collection(&mut self, collection: u32) -> Result<(), StorageErr> {
         defmt::info!("Removing collection: {}", collection);
 
@@ -456,7 +1416,7 @@ pub fn id_to_parts(id: u32) -> (String<32>, u32, usize) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::axl::AXL_SZ;
+    use crate::axl::AxlPacketBuilder;
 
     #[test]
     fn version_str() {
@@ -467,12 +1427,12 @@ mod tests {
     #[test]
     fn test_id_to_parts() {
         let (c, file, o) = id_to_parts(0);
-        assert_eq!(c, "0.5");
+        assert_eq!(c, "0.22");
         assert_eq!(file, 0);
         assert_eq!(o, 0);
 
         let (c, file, o) = id_to_parts(1231255);
-        assert_eq!(c, "12312.5");
+        assert_eq!(c, "12312.22");
         assert_eq!(file, 55);
         assert_eq!(o, 55 * PACKAGE_SZ);
     }
@@ -509,53 +1469,31 @@ mod tests {
 
         let buf = c.as_mut_slice();
 
-        let p0: AxlPacket = postcard::from_bytes_cobs(&mut buf[..AXL_POSTCARD_SZ]).unwrap();
-        let p1: AxlPacket =
-            postcard::from_bytes_cobs(&mut buf[AXL_POSTCARD_SZ..(2 * AXL_POSTCARD_SZ)]).unwrap();
-        let p2: AxlPacket =
-            postcard::from_bytes_cobs(&mut buf[(AXL_POSTCARD_SZ * 2)..(AXL_POSTCARD_SZ * 3)])
-                .unwrap();
+        let p0 = AxlPacket::decode(2, &mut buf[..AXL_POSTCARD_SZ]).unwrap();
+        let p1 = AxlPacket::decode(2, &mut buf[AXL_POSTCARD_SZ..(2 * AXL_POSTCARD_SZ)]).unwrap();
+        let p2 =
+            AxlPacket::decode(2, &mut buf[(AXL_POSTCARD_SZ * 2)..(AXL_POSTCARD_SZ * 3)]).unwrap();
 
         assert_eq!(p0.storage_id, Some(0));
         assert_eq!(p1.storage_id, Some(1));
         assert_eq!(p2.storage_id, Some(2));
 
-        let p0_truth = AxlPacket {
-            timestamp: 1002330,
-            position_time: 123123,
-            temperature: 0.0,
-            lat: 34.52341,
-            lon: 54.012,
-            freq: 53.0,
-            offset: 15,
-            storage_id: Some(0),
-            storage_version: STORAGE_VERSION,
-            data: (6..3078).map(|v| v as u16).collect::<Vec<_, { AXL_SZ }>>(),
-        };
-        let p1_truth = AxlPacket {
-            timestamp: 1002400,
-            position_time: 123123,
-            temperature: 0.0,
-            lat: 34.52341,
-            lon: 54.012,
-            freq: 53.0,
-            offset: 15,
-            storage_id: Some(1),
-            storage_version: STORAGE_VERSION,
-            data: (6..3078).map(|v| v as u16).collect::<Vec<_, { AXL_SZ }>>(),
-        };
-        let p2_truth = AxlPacket {
-            timestamp: 1002500,
-            position_time: 123123,
-            temperature: 0.0,
-            lat: 34.52341,
-            lon: 54.012,
-            freq: 53.0,
-            offset: 15,
-            storage_id: Some(2),
-            storage_version: STORAGE_VERSION,
-            data: (9..3081).map(|v| v as u16).collect::<Vec<_, { AXL_SZ }>>(),
+        let truth = |id, timestamp, samples: core::ops::Range<u16>| {
+            AxlPacketBuilder::new(
+                timestamp,
+                53.0,
+                crate::waves::wire::ACCEL_MAX,
+                samples.collect(),
+            )
+            .offset(15)
+            .storage(Some(id), STORAGE_VERSION)
+            .position(123123, 54.012, 34.52341, 0.)
+            .build()
+            .unwrap()
         };
+        let p0_truth = truth(0, 1002330, 6..3078);
+        let p1_truth = truth(1, 1002400, 6..3078);
+        let p2_truth = truth(2, 1002500, 9..3081);
 
         assert_eq!(p0_truth, p0);
         assert_eq!(p1_truth, p1);
@@ -571,9 +1509,30 @@ mod tests {
 
         for p in 0..12 {
             let slice = &mut buf[(AXL_POSTCARD_SZ * p)..(AXL_POSTCARD_SZ * (p + 1))];
-            let pck: AxlPacket = postcard::from_bytes_cobs(slice).unwrap();
+            let pck = AxlPacket::decode(2, slice).unwrap();
             println!("Deserialized data package: {:?}", pck);
             assert_eq!(pck.storage_id, Some(200 + p as u32));
         }
     }
+
+    #[test]
+    fn stored_package_heapless_and_std() {
+        let c = std::fs::read("tests/data/44.5").unwrap();
+
+        let mut h: StoredPackage<Vec<u8, { AXL_POSTCARD_SZ }>> = StoredPackage::new(false);
+        h.as_mut_slice().copy_from_slice(&c[..AXL_POSTCARD_SZ]);
+        assert!(h.raw().is_none());
+
+        let mut s: StoredPackage<std::vec::Vec<u8>> = StoredPackage::new(false);
+        s.as_mut_slice().copy_from_slice(&c[..AXL_POSTCARD_SZ]);
+
+        assert_eq!(h.decode(5).unwrap(), s.decode(5).unwrap());
+
+        #[cfg(feature = "raw")]
+        {
+            let s: StoredPackage<std::vec::Vec<u8>> = StoredPackage::new(true);
+            assert_eq!(s.len(), PACKAGE_SZ);
+            assert_eq!(s.raw().unwrap().len(), RAW_AXL_BYTE_SZ);
+        }
+    }
 }
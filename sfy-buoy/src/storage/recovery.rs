@@ -0,0 +1,179 @@
+//! Recovery of the SD-card after repeated errors.
+//!
+//! A card that stops responding (e.g. a loose contact, or a brown-out of the card) keeps failing
+//! every access as long as the state of the initialized card is kept. [`Recovery`] counts the
+//! consecutive errors of the package writes and reads of `StorageManager`: after `ERRORS` in a row
+//! the card is re-initialized (the state is dropped and the card is mounted again on the next
+//! access, see `StorageBackend::reinit`), at most every `INTERVAL`. After `MAX_REINITS`
+//! re-initializations without a successful access in between the card is given up, and the
+//! packages are only forwarded to the notecard until the buoy is reset. A successful access
+//! clears the counts.
+//!
+//! Errors that do not come from the card are not counted: a package that cannot be serialized, a
+//! full card, or a collection that does not exist. Neither is a card that is not initialized, its
+//! initialization is retried by `Storage` itself (every ten minutes).
+//!
+//! A package that does not read back as it was written (`StorageErr::Verify`, see
+//! `Config::verify_writes`) is counted like any other error of the card.
+
+use super::StorageErr;
+
+/// Consecutive errors before the card is re-initialized.
+pub const ERRORS: u32 = 3;
+
+/// Re-initializations without a successful access before the card is given up.
+pub const MAX_REINITS: u32 = 3;
+
+/// Minimum time between re-initializations [ms].
+pub const INTERVAL: i64 = 5 * 60 * 1000;
+
+/// What to do after a failed access.
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Keep going, the card is not re-initialized (yet).
+    Continue,
+
+    /// Re-initialize the card.
+    Reinit,
+
+    /// The card does not recover, only forward the packages.
+    GiveUp,
+}
+
+#[derive(Debug, defmt::Format, Default, Clone, PartialEq)]
+pub struct Recovery {
+    /// Consecutive errors since the last successful access or re-initialization.
+    pub errors: u32,
+
+    /// Re-initializations since the last successful access.
+    pub reinits: u32,
+
+    /// Time of the last re-initialization [ms].
+    last: Option<i64>,
+
+    /// The card has been given up.
+    pub failed: bool,
+}
+
+/// The error comes from the card, and may be cleared by re-initializing it.
+fn is_card_error(e: &StorageErr) -> bool {
+    !matches!(
+        e,
+        StorageErr::SerializationError
+            | StorageErr::DiskFull
+            | StorageErr::Uninitialized
+            | StorageErr::GenericSdMmmcErr(embedded_sdmmc::Error::FileNotFound)
+    )
+}
+
+impl Recovery {
+    pub fn new() -> Recovery {
+        Recovery::default()
+    }
+
+    /// A successful access.
+    pub fn ok(&mut self) {
+        self.errors = 0;
+        self.reinits = 0;
+    }
+
+    /// A failed access at `now` [ms].
+    pub fn error(&mut self, e: &StorageErr, now: i64) -> Action {
+        if self.failed || !is_card_error(e) {
+            return Action::Continue;
+        }
+
+        self.errors = self.errors.saturating_add(1);
+
+        if self.errors < ERRORS || self.last.map_or(false, |t| now - t < INTERVAL) {
+            return Action::Continue;
+        }
+
+        if self.reinits >= MAX_REINITS {
+            self.failed = true;
+            return Action::GiveUp;
+        }
+
+        self.errors = 0;
+        self.reinits += 1;
+        self.last = Some(now);
+
+        Action::Reinit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reinit_and_give_up() {
+        let mut r = Recovery::new();
+        let e = StorageErr::WriteError;
+        let mut now = 0;
+
+        for reinit in 1..=MAX_REINITS {
+            for _ in 1..ERRORS {
+                assert_eq!(r.error(&e, now), Action::Continue);
+            }
+            assert_eq!(r.error(&e, now), Action::Reinit);
+            assert_eq!(r.reinits, reinit);
+
+            now += INTERVAL;
+        }
+
+        for _ in 1..ERRORS {
+            assert_eq!(r.error(&e, now), Action::Continue);
+        }
+        assert_eq!(r.error(&e, now), Action::GiveUp);
+        assert!(r.failed);
+        assert_eq!(r.error(&e, now + INTERVAL), Action::Continue);
+    }
+
+    #[test]
+    fn interval() {
+        let mut r = Recovery::new();
+        let e = StorageErr::WriteError;
+
+        for _ in 1..ERRORS {
+            r.error(&e, 0);
+        }
+        assert_eq!(r.error(&e, 0), Action::Reinit);
+
+        // Not again within the interval.
+        for _ in 0..2 * ERRORS {
+            assert_eq!(r.error(&e, INTERVAL - 1), Action::Continue);
+        }
+        assert_eq!(r.error(&e, INTERVAL), Action::Reinit);
+    }
+
+    #[test]
+    fn recover() {
+        let mut r = Recovery::new();
+        let e = StorageErr::WriteError;
+
+        for _ in 0..ERRORS {
+            r.error(&e, 0);
+        }
+        assert_eq!(r.reinits, 1);
+
+        r.ok();
+        assert_eq!((r.errors, r.reinits), (0, 0));
+        assert!(!r.failed);
+    }
+
+    #[test]
+    fn not_card_errors() {
+        let mut r = Recovery::new();
+
+        for _ in 0..2 * ERRORS {
+            assert_eq!(r.error(&StorageErr::DiskFull, 0), Action::Continue);
+            assert_eq!(
+                r.error(&StorageErr::SerializationError, 0),
+                Action::Continue
+            );
+            assert_eq!(r.error(&StorageErr::Uninitialized, 0), Action::Continue);
+        }
+        assert_eq!(r.errors, 0);
+    }
+}
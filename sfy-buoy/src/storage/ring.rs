@@ -0,0 +1,92 @@
+//! Oldest collections removed to make room on a full card (`Config::ring_buffer`).
+//!
+//! The free space on the card is estimated from the IDs that are in use: from the first ID of the
+//! oldest collection on the card to the next free ID (see `Storage::free_space`). Without the
+//! ring buffer the packages are no longer stored when the estimate drops below `min_free_space`.
+//! With the ring buffer the oldest collection is removed instead, until there is room again.
+//!
+//! The storage IDs keep counting up, so that requests for stored packages (`request_start` and
+//! `request_end` of the notecard) stay valid: a removed collection reads as `FileNotFound`, and
+//! the replay skips it like any other missing collection. The oldest collection left on the card
+//! is kept in [`RING_FILE`], and the first free collection is searched for from there at boot,
+//! so that the IDs of the removed collections are not used again. The file is written before the
+//! collection is removed: a reset in between leaves the collection on the card, but never reuses
+//! its IDs.
+//!
+//! The collection the next package is written to is never removed. Collections named by day are
+//! removed by their name in the index (see `days`), the index itself is kept.
+
+use core::fmt::Write;
+use heapless::String;
+
+use super::{COLLECTION_SIZE, PACKAGE_SZ};
+
+/// The oldest collection left on the card, when collections have been removed.
+pub const RING_FILE: &str = "RING.IDX";
+
+/// Length of [`RING_FILE`].
+pub const RECORD_SZ: usize = 9;
+
+/// Maximum number of collections removed at a time, the rest on the next package.
+pub const MAX_REMOVED: u32 = 4;
+
+/// Content of [`RING_FILE`].
+pub fn to_record(oldest: u32) -> [u8; RECORD_SZ] {
+    let mut s = String::<RECORD_SZ>::new();
+    write!(s, "{:08}", oldest % 100_000_000).ok();
+
+    let mut r = [b'\n'; RECORD_SZ];
+    r[..8].copy_from_slice(s.as_bytes());
+    r
+}
+
+pub fn from_record(r: &[u8]) -> Option<u32> {
+    if r.len() < RECORD_SZ || r[8] != b'\n' {
+        return None;
+    }
+
+    core::str::from_utf8(&r[..8]).ok()?.parse().ok()
+}
+
+/// Estimated free space on a card of `card_size` bytes, with the packages from the first ID of
+/// collection `oldest` up to `next_id` in use.
+pub fn free_space(card_size: u64, oldest: u32, next_id: u32) -> u64 {
+    let used = next_id.saturating_sub(oldest * COLLECTION_SIZE);
+    card_size.saturating_sub(used as u64 * PACKAGE_SZ as u64)
+}
+
+/// The oldest collection can be removed: it is not the collection the next package is written
+/// to.
+pub fn removable(oldest: u32, next_id: u32) -> bool {
+    oldest < next_id / COLLECTION_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        assert_eq!(&to_record(441), b"00000441\n");
+        assert_eq!(from_record(&to_record(441)), Some(441));
+        assert_eq!(from_record(b"00000441"), None);
+        assert_eq!(from_record(b"0000044x\n"), None);
+    }
+
+    #[test]
+    fn space() {
+        let sz = 1000 * PACKAGE_SZ as u64;
+        assert_eq!(free_space(sz, 0, 0), sz);
+        assert_eq!(free_space(sz, 0, 250), 750 * PACKAGE_SZ as u64);
+        assert_eq!(free_space(sz, 2, 250), 950 * PACKAGE_SZ as u64);
+        assert_eq!(free_space(sz, 0, 2000), 0);
+    }
+
+    #[test]
+    fn current_kept() {
+        assert!(removable(0, 100));
+        assert!(removable(1, 250));
+        assert!(!removable(2, 250));
+        assert!(!removable(2, 200));
+    }
+}
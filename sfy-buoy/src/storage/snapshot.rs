@@ -0,0 +1,118 @@
+//! Snapshot of the last known good state, stored on the SD-card so that it can be restored after
+//! a reboot.
+//!
+//! The snapshot holds the last position, so that packages have an approximate position (with the
+//! original `position_time`) until a new fix is retrieved, and the last storage ID. Storage IDs
+//! are already monotonic across reboots since every boot starts a new collection, the last ID is
+//! kept to detect if that is violated. The snapshot also holds the storage ID of the last package
+//! accepted by the notecard (see `dedup`). The configuration is not part of the snapshot: it is
+//! restored from the config file and the notecard on every boot.
+
+/// Snapshot file on SD-card.
+pub const SNAPSHOT_FILE: &str = "STATE.BIN";
+
+/// Max size of serialized snapshot.
+pub const SNAPSHOT_SZ: usize = 64;
+
+/// Write the snapshot at this interval [ms].
+pub const SNAPSHOT_INTERVAL: i64 = 10 * 60_000;
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Time the snapshot was taken [ms].
+    pub timestamp: i64,
+
+    /// Time of position in seconds.
+    pub position_time: u32,
+    pub lon: f64,
+    pub lat: f64,
+
+    /// Last used storage ID.
+    pub storage_id: Option<u32>,
+
+    /// Storage ID of the last package accepted by the notecard (see `dedup::ACKED`).
+    pub acked_id: Option<u32>,
+}
+
+/// Layout before the last accepted package was kept.
+#[derive(serde::Deserialize)]
+struct SnapshotV1 {
+    timestamp: i64,
+    position_time: u32,
+    lon: f64,
+    lat: f64,
+    storage_id: Option<u32>,
+}
+
+impl From<SnapshotV1> for Snapshot {
+    fn from(s: SnapshotV1) -> Snapshot {
+        Snapshot {
+            timestamp: s.timestamp,
+            position_time: s.position_time,
+            lon: s.lon,
+            lat: s.lat,
+            storage_id: s.storage_id,
+            acked_id: None,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Deserialize a snapshot, also of the layout from before the last accepted package was
+    /// kept.
+    pub fn decode(buf: &[u8]) -> Result<Snapshot, postcard::Error> {
+        postcard::from_bytes(buf)
+            .or_else(|_| postcard::from_bytes::<SnapshotV1>(buf).map(Snapshot::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let s = Snapshot {
+            timestamp: 1_700_000_000_000,
+            position_time: u32::MAX,
+            lon: 5.3,
+            lat: 60.4,
+            storage_id: Some(u32::MAX),
+            acked_id: Some(u32::MAX),
+        };
+
+        let mut buf = [0u8; SNAPSHOT_SZ];
+        let b = postcard::to_slice(&s, &mut buf).unwrap();
+        assert!(b.len() <= SNAPSHOT_SZ);
+
+        let d = Snapshot::decode(b).unwrap();
+        assert_eq!(d, s);
+    }
+
+    #[test]
+    fn version_1() {
+        #[derive(serde::Serialize)]
+        struct V1 {
+            timestamp: i64,
+            position_time: u32,
+            lon: f64,
+            lat: f64,
+            storage_id: Option<u32>,
+        }
+
+        let v1 = V1 {
+            timestamp: 1_700_000_000_000,
+            position_time: 12,
+            lon: 5.3,
+            lat: 60.4,
+            storage_id: Some(42),
+        };
+
+        let mut buf = [0u8; SNAPSHOT_SZ];
+        let b = postcard::to_slice(&v1, &mut buf).unwrap();
+
+        let d = Snapshot::decode(b).unwrap();
+        assert_eq!(d.storage_id, Some(42));
+        assert_eq!(d.acked_id, None);
+    }
+}
@@ -0,0 +1,187 @@
+//! Record of the syncs of the Notecard with notehub.
+//!
+//! The sync status of the Notecard (`hub.sync.status`) is polled in the main loop. A sync attempt
+//! starts when a sync is requested, and ends when it is no longer pending. The attempt succeeded
+//! if a sync completed after it was requested. The last attempts are kept (timestamped with the
+//! RTC), written to the SD-card and summarized in the health note. This tells a buoy that stopped
+//! collecting data apart from a buoy that could not connect.
+
+use core::fmt::Write;
+use heapless::Deque;
+
+/// Number of sync attempts kept.
+pub const SYNC_HISTORY_SZ: usize = 8;
+
+/// Sync history file on SD-card.
+pub const SYNC_HISTORY_FILE: &str = "SYNC.LOG";
+
+/// Maximum size of the history as CSV.
+pub const SYNC_HISTORY_CSV_SZ: usize = 512;
+
+/// Tolerance for comparing times derived from the second resolution of the sync status [ms].
+const TOLERANCE: i64 = 1000;
+
+#[derive(serde::Serialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct SyncAttempt {
+    /// Time sync was requested [ms].
+    pub started: i64,
+
+    /// Time sync was no longer pending [ms].
+    pub ended: i64,
+
+    /// A sync completed after it was requested.
+    pub ok: bool,
+}
+
+pub struct SyncHistory {
+    attempts: Deque<SyncAttempt, SYNC_HISTORY_SZ>,
+
+    /// Start of pending attempt [ms].
+    pending: Option<i64>,
+
+    /// Time of last completed sync [ms].
+    last_sync: Option<i64>,
+
+    changed: bool,
+}
+
+impl SyncHistory {
+    pub fn new() -> SyncHistory {
+        SyncHistory {
+            attempts: Deque::new(),
+            pending: None,
+            last_sync: None,
+            changed: false,
+        }
+    }
+
+    /// Update with the sync status at `now` (ms): seconds since a sync was `requested` (if
+    /// pending) and since the last sync `completed`. Returns the attempt that ended, if any.
+    pub fn update(
+        &mut self,
+        now: i64,
+        requested: Option<u32>,
+        completed: Option<u32>,
+    ) -> Option<SyncAttempt> {
+        if let Some(c) = completed {
+            self.last_sync = Some(now - c as i64 * 1000);
+        }
+
+        match (self.pending, requested) {
+            (None, Some(r)) => {
+                self.pending = Some(now - r as i64 * 1000);
+                None
+            }
+            (Some(started), None) => {
+                self.pending = None;
+
+                let a = SyncAttempt {
+                    started,
+                    ended: now,
+                    ok: self.last_sync.map_or(false, |l| l + TOLERANCE >= started),
+                };
+
+                if self.attempts.is_full() {
+                    self.attempts.pop_front();
+                }
+                self.attempts.push_back(a).ok();
+                self.changed = true;
+
+                Some(a)
+            }
+            _ => None,
+        }
+    }
+
+    /// Time of last completed sync [ms].
+    pub fn last_sync(&self) -> Option<i64> {
+        self.last_sync
+    }
+
+    /// Attempts, oldest first.
+    pub fn attempts(&self) -> impl Iterator<Item = &SyncAttempt> {
+        self.attempts.iter()
+    }
+
+    /// Number of failed attempts in the history.
+    pub fn failures(&self) -> usize {
+        self.attempts.iter().filter(|a| !a.ok).count()
+    }
+
+    /// Returns whether an attempt has been added since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::take(&mut self.changed)
+    }
+
+    /// Write the history as CSV: `started,ended,ok` (ms since epoch).
+    pub fn write_csv(&self, w: &mut impl Write) -> core::fmt::Result {
+        writeln!(w, "started,ended,ok")?;
+
+        for a in self.attempts() {
+            writeln!(w, "{},{},{}", a.started, a.ended, a.ok as u8)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T0: i64 = 1_700_000_000_000;
+
+    #[test]
+    fn successful_sync() {
+        let mut h = SyncHistory::new();
+
+        assert_eq!(h.update(T0, None, Some(3600)), None);
+        assert_eq!(h.last_sync(), Some(T0 - 3_600_000));
+
+        // Requested 2 s ago.
+        assert_eq!(h.update(T0 + 10_000, Some(2), Some(3610)), None);
+
+        let a = h.update(T0 + 60_000, None, Some(5)).unwrap();
+        assert_eq!(
+            a,
+            SyncAttempt {
+                started: T0 + 8_000,
+                ended: T0 + 60_000,
+                ok: true
+            }
+        );
+        assert_eq!(h.failures(), 0);
+        assert!(h.take_changed());
+        assert!(!h.take_changed());
+    }
+
+    #[test]
+    fn failed_sync() {
+        let mut h = SyncHistory::new();
+
+        h.update(T0, Some(0), Some(3600));
+
+        // Gave up without completing.
+        let a = h.update(T0 + 600_000, None, Some(4200)).unwrap();
+        assert!(!a.ok);
+        assert_eq!(h.failures(), 1);
+    }
+
+    #[test]
+    fn bounded() {
+        let mut h = SyncHistory::new();
+
+        for i in 0..20 {
+            let t = T0 + i * 100_000;
+            h.update(t, Some(0), None);
+            h.update(t + 1000, None, Some(0));
+        }
+
+        assert_eq!(h.attempts().count(), SYNC_HISTORY_SZ);
+        assert_eq!(h.attempts().next().unwrap().started, T0 + 12 * 100_000);
+
+        let mut s = heapless::String::<SYNC_HISTORY_CSV_SZ>::new();
+        h.write_csv(&mut s).unwrap();
+        assert_eq!(s.lines().count(), SYNC_HISTORY_SZ + 1);
+    }
+}
@@ -0,0 +1,136 @@
+//! Impact and tamper detection with the accelerometer of the Notecard.
+//!
+//! The Notecard has an accelerometer of its own, independent of the IMU. It counts motion events
+//! (accelerations above its sensitivity) and flags free-fall, which are read with `card.motion`
+//! (see `Notecarrier::motion`). The count is reset by every read, so it is the number of events
+//! since the last check in the main loop.
+//!
+//! A buoy drifting in the water makes a steady trickle of events, while an impact (a collision, or
+//! the buoy being hauled on deck) makes a burst, and a drop shows up as free-fall. An alarm is
+//! raised when the events since the last check reach `motion_threshold` (in the config, 0
+//! disables the check), or on free-fall, and sent as an alert log message that is synced right
+//! away (with the default `urgency.alarm`, see `urgency`). Alarms are held off for
+//! `ALARM_HOLDOFF` after the last one, so that a buoy on deck does not raise one on every check.
+//!
+//! This is a cheap secondary signal: it is coarse (only counts, no accelerations) compared to the
+//! IMU, but it keeps working if the IMU has failed, and needs no processing on the MCU. The
+//! threshold depends on the sea state and the check interval, and is found by looking at the counts
+//! logged for a normal deployment.
+
+/// Minimum time between alarms [ms].
+pub const ALARM_HOLDOFF: i64 = 60 * 60_000;
+
+/// Motion since the last check, as reported by the Notecard.
+#[derive(defmt::Format, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Motion {
+    /// Motion events since the last check.
+    pub count: u32,
+
+    /// Free-fall detected since the last check.
+    pub free_fall: bool,
+}
+
+/// Reason for an alarm.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum Alarm {
+    /// The number of motion events reached the threshold.
+    Impact(u32),
+
+    /// The Notecard detected free-fall.
+    FreeFall,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Tamper {
+    /// Time of the last alarm [ms].
+    last_alarm: Option<i64>,
+}
+
+impl Tamper {
+    pub fn new() -> Tamper {
+        Tamper::default()
+    }
+
+    /// Check the motion `m` at `now` [ms] against `threshold` (motion events, 0 disables), returns
+    /// the alarm to raise if any.
+    pub fn check(&mut self, threshold: u32, now: i64, m: Motion) -> Option<Alarm> {
+        if threshold == 0 {
+            return None;
+        }
+
+        let alarm = if m.free_fall {
+            Alarm::FreeFall
+        } else if m.count >= threshold {
+            Alarm::Impact(m.count)
+        } else {
+            return None;
+        };
+
+        if let Some(last) = self.last_alarm {
+            if (now - last) < ALARM_HOLDOFF {
+                return None;
+            }
+        }
+
+        self.last_alarm = Some(now);
+
+        Some(alarm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn motion(count: u32) -> Motion {
+        Motion {
+            count,
+            free_fall: false,
+        }
+    }
+
+    #[test]
+    fn disabled() {
+        let mut t = Tamper::new();
+        assert_eq!(t.check(0, 0, motion(1000)), None);
+        assert_eq!(
+            t.check(
+                0,
+                0,
+                Motion {
+                    count: 0,
+                    free_fall: true
+                }
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn threshold() {
+        let mut t = Tamper::new();
+        assert_eq!(t.check(50, 0, motion(49)), None);
+        assert_eq!(t.check(50, 1000, motion(50)), Some(Alarm::Impact(50)));
+    }
+
+    #[test]
+    fn free_fall() {
+        let mut t = Tamper::new();
+        let m = Motion {
+            count: 3,
+            free_fall: true,
+        };
+        assert_eq!(t.check(50, 0, m), Some(Alarm::FreeFall));
+    }
+
+    #[test]
+    fn holdoff() {
+        let mut t = Tamper::new();
+        assert_eq!(t.check(50, 0, motion(100)), Some(Alarm::Impact(100)));
+        assert_eq!(t.check(50, ALARM_HOLDOFF - 1, motion(100)), None);
+        assert_eq!(
+            t.check(50, ALARM_HOLDOFF, motion(120)),
+            Some(Alarm::Impact(120))
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Tracing of the raw requests and responses of the Notecard, for debugging the protocol.
+//!
+//! The Notecard is reached through a [`Traced`] bus (see `Notecarrier`), which picks the JSON
+//! requests and responses out of the I2C transactions with the Notecard. In the serial-over-I2C
+//! protocol of the Notecard every chunk written is prefixed with its length, and a read of `n`
+//! bytes is requested by writing `[0, n]` and answered with the number of bytes still available,
+//! the number of bytes returned and the bytes.
+//!
+//! When the run-time log level of the `note` category is `trace` (`{ "note": "trace" }` in the
+//! `levels` note in `log.db`, see `log`) every request and response is logged with `defmt`, and
+//! with the `storage` feature appended to the event log on the SD-card:
+//!
+//! ```text
+//! > {"req":"hub.sync"}
+//! < {}
+//! ```
+//!
+//! The tracing can in this way be enabled on a single buoy from notehub without reflashing, it is
+//! off by default. Lines are cut at `TRACE_SZ` characters (e.g. the payload of a package) and
+//! marked with `..`, and the string values of the fields in `REDACTED` (e.g. the key of a
+//! command) are replaced with `*`. The trace is never queued for the Notecard, which would trace
+//! itself. The event log only holds a few lines between writes to the SD-card, so lines are
+//! dropped when the Notecard is busy.
+
+use embedded_hal::blocking::i2c::{Read, Write};
+use heapless::String;
+
+use crate::log::{self, Category, Level};
+
+/// Maximum length of a traced line.
+pub const TRACE_SZ: usize = 192;
+
+/// Fields whose values are not traced.
+pub const REDACTED: &[&str] = &["key", "password", "pin", "token"];
+
+pub type TraceLine = String<TRACE_SZ>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+impl Direction {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Direction::Request => ">",
+            Direction::Response => "<",
+        }
+    }
+}
+
+/// A line being assembled from the chunks.
+#[derive(Default)]
+struct Line {
+    buf: TraceLine,
+    cut: bool,
+}
+
+impl Line {
+    /// Append `bytes`, passing every complete line to `f`.
+    fn push(&mut self, bytes: &[u8], mut f: impl FnMut(&str)) {
+        for b in bytes {
+            match b {
+                b'\n' => {
+                    if !self.buf.is_empty() {
+                        let mut line = redact(&self.buf);
+                        if self.cut {
+                            line.truncate(TRACE_SZ - 2);
+                            line.push_str("..").ok();
+                        }
+                        f(&line);
+                    }
+
+                    self.buf.clear();
+                    self.cut = false;
+                }
+                b'\r' => {}
+                b => {
+                    if self.buf.push(*b as char).is_err() {
+                        self.cut = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Requests and responses from the I2C transactions with the Notecard.
+#[derive(Default)]
+pub struct Tap {
+    request: Line,
+    response: Line,
+
+    /// A read of the response has been requested.
+    reading: bool,
+}
+
+impl Tap {
+    /// Bytes written to the Notecard.
+    pub fn write(&mut self, bytes: &[u8], mut f: impl FnMut(Direction, &str)) {
+        match bytes {
+            [0, _] => self.reading = true,
+            [n, data @ ..] if *n as usize == data.len() => {
+                self.reading = false;
+                self.request.push(data, |l| f(Direction::Request, l));
+            }
+            _ => self.reading = false,
+        }
+    }
+
+    /// Bytes read from the Notecard.
+    pub fn read(&mut self, buf: &[u8], mut f: impl FnMut(Direction, &str)) {
+        if !core::mem::take(&mut self.reading) {
+            return;
+        }
+
+        if let [_available, n, data @ ..] = buf {
+            let n = (*n as usize).min(data.len());
+            self.response
+                .push(&data[..n], |l| f(Direction::Response, l));
+        }
+    }
+}
+
+/// Start and end of the string value of `field` in `s`, the end is the end of `s` if the value is
+/// cut.
+fn find_value(s: &str, field: &str) -> Option<(usize, usize)> {
+    s.match_indices(field).find_map(|(i, _)| {
+        let start = i + field.len() + 3;
+
+        (i > 0 && s.as_bytes()[i - 1] == b'"' && s[i + field.len()..].starts_with("\":\"")).then(
+            || {
+                let end = s[start..].find('"').map_or(s.len(), |e| start + e);
+                (start, end)
+            },
+        )
+    })
+}
+
+/// `line` with the string values of the fields in `REDACTED` replaced with `*`.
+pub fn redact(line: &str) -> TraceLine {
+    let mut out = TraceLine::new();
+    let mut rest = line;
+
+    while let Some((start, end)) = REDACTED
+        .iter()
+        .filter_map(|f| find_value(rest, f))
+        .min_by_key(|(start, _)| *start)
+    {
+        out.push_str(&rest[..start]).ok();
+        out.push('*').ok();
+        rest = &rest[end..];
+    }
+
+    out.push_str(rest).ok();
+    out
+}
+
+fn emit(d: Direction, line: &str) {
+    let mut msg = log::LogLine::new();
+    msg.push_str(d.prefix()).ok();
+    msg.push(' ').ok();
+    msg.push_str(line).ok();
+
+    #[cfg(not(test))]
+    defmt::trace!("{}", msg.as_str());
+    log::event(&msg);
+}
+
+/// An I2C bus tracing the transactions with the Notecard at `address`, when the `note` category
+/// is at `trace`. Transactions with other devices on the bus are passed through.
+pub struct Traced<I2C> {
+    i2c: I2C,
+    address: u8,
+    tap: Tap,
+}
+
+impl<I2C> Traced<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Traced<I2C> {
+        Traced {
+            i2c,
+            address,
+            tap: Tap::default(),
+        }
+    }
+
+    fn tracing(&self, address: u8) -> bool {
+        address == self.address && log::enabled(Category::Note, Level::Trace)
+    }
+}
+
+impl<I2C: Write> Write for Traced<I2C> {
+    type Error = I2C::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.i2c.write(address, bytes)?;
+
+        if self.tracing(address) {
+            self.tap.write(bytes, emit);
+        }
+
+        Ok(())
+    }
+}
+
+impl<I2C: Read> Read for Traced<I2C> {
+    type Error = I2C::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.i2c.read(address, buffer)?;
+
+        if self.tracing(address) {
+            self.tap.read(buffer, emit);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `s` in chunks as the Notecard driver does.
+    fn write(
+        tap: &mut Tap,
+        s: &[u8],
+        chunk: usize,
+        lines: &mut Vec<(Direction, std::string::String)>,
+    ) {
+        for c in s.chunks(chunk) {
+            let mut b = vec![c.len() as u8];
+            b.extend_from_slice(c);
+            tap.write(&b, |d, l| lines.push((d, l.into())));
+        }
+    }
+
+    #[test]
+    fn requests_and_responses() {
+        let mut tap = Tap::default();
+        let mut lines = Vec::new();
+
+        write(&mut tap, b"{\"req\":\"hub.sync\"}\n", 8, &mut lines);
+
+        // Query of the available bytes, and the read.
+        tap.write(&[0, 0], |d, l| lines.push((d, l.into())));
+        tap.read(&[3, 0], |d, l| lines.push((d, l.into())));
+        tap.write(&[0, 3], |d, l| lines.push((d, l.into())));
+        tap.read(&[0, 3, b'{', b'}', b'\n'], |d, l| lines.push((d, l.into())));
+
+        // Reads without a request are not traced.
+        tap.read(&[0, 3, b'{', b'}', b'\n'], |d, l| lines.push((d, l.into())));
+
+        assert_eq!(
+            lines,
+            [
+                (Direction::Request, r#"{"req":"hub.sync"}"#.into()),
+                (Direction::Response, "{}".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn cut() {
+        let mut tap = Tap::default();
+        let mut lines = Vec::new();
+
+        let mut long = b"{\"payload\":\"".to_vec();
+        long.extend(std::iter::repeat(b'A').take(1000));
+        long.extend(b"\"}\n{}\n");
+        write(&mut tap, &long, 250, &mut lines);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].1.len(), TRACE_SZ);
+        assert!(lines[0].1.ends_with("AA.."));
+        assert_eq!(lines[1].1, "{}");
+    }
+
+    #[test]
+    fn redacted() {
+        assert_eq!(
+            redact(r#"{"req":"note.get","body":{"cmd":"reset","key":"s3cret","arg":1}}"#),
+            r#"{"req":"note.get","body":{"cmd":"reset","key":"*","arg":1}}"#
+        );
+        assert_eq!(
+            redact(r#"{"password":"a","keys":"b","token":"c"}"#),
+            r#"{"password":"*","keys":"b","token":"*"}"#
+        );
+
+        // A cut value.
+        assert_eq!(redact(r#"{"key":"abc"#), r#"{"key":"*"#);
+        assert_eq!(redact(r#"{"monkey":"x"}"#), r#"{"monkey":"x"}"#);
+    }
+}
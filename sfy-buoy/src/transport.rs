@@ -0,0 +1,171 @@
+//! Connection of the Notecard to notehub.
+//!
+//! Notecards may connect over cellular, WiFi or satellite (NTN, non-terrestrial network, e.g.
+//! through a Starnote). The transport is set with `card.transport` when the Notecard is
+//! provisioned (see `provision`), from `transport` in the config, which defaults to
+//! `BUOYTRANSPORT` at build time. A combination is tried in the fixed order of the Notecard: WiFi,
+//! then cellular, then satellite, e.g. `cell-ntn` connects over cellular and falls back to
+//! satellite when there is no cellular coverage. `-` (the default) leaves the Notecard at its own
+//! default.
+//!
+//! The bearer in use is recorded in the health reports (`Health::bearer`), as reported by the
+//! Notecard at the time of the report.
+//!
+//! The data budget is very different between the bearers:
+//!
+//! * WiFi: no budget, but only near shore or on a platform.
+//! * Cellular: the time series (`axl.qo`) is the bulk of the data, about 10 kB per package. Keep
+//!   the backfill batches (`replay_batch`) and the sync period in line with the data plan.
+//! * Satellite: a few kB per day at most. Only small notes fit: disable the time series
+//!   (`products.timeseries`) and send the statistics and health notes only, with a long sync
+//!   period. The time series are still stored on the SD-card.
+
+/// Transport method of the Notecard, named as in `card.transport`.
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+pub enum Transport {
+    /// The default of the Notecard.
+    #[default]
+    #[serde(rename = "-")]
+    Default,
+    #[serde(rename = "cell")]
+    Cell,
+    #[serde(rename = "wifi")]
+    Wifi,
+    #[serde(rename = "ntn")]
+    Ntn,
+    #[serde(rename = "wifi-cell")]
+    WifiCell,
+    #[serde(rename = "cell-ntn")]
+    CellNtn,
+    #[serde(rename = "wifi-ntn")]
+    WifiNtn,
+    #[serde(rename = "wifi-cell-ntn")]
+    WifiCellNtn,
+}
+
+/// All the transports, for parsing.
+const TRANSPORTS: [Transport; 8] = [
+    Transport::Default,
+    Transport::Cell,
+    Transport::Wifi,
+    Transport::Ntn,
+    Transport::WifiCell,
+    Transport::CellNtn,
+    Transport::WifiNtn,
+    Transport::WifiCellNtn,
+];
+
+/// Default transport, from `BUOYTRANSPORT`.
+pub const TRANSPORT: Transport = match option_env!("BUOYTRANSPORT") {
+    Some(s) => match Transport::parse(s) {
+        Some(t) => t,
+        None => panic!("BUOYTRANSPORT must be a transport of card.transport, e.g. cell-ntn"),
+    },
+    None => Transport::Default,
+};
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+impl Transport {
+    /// The `method` of `card.transport`.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Default => "-",
+            Transport::Cell => "cell",
+            Transport::Wifi => "wifi",
+            Transport::Ntn => "ntn",
+            Transport::WifiCell => "wifi-cell",
+            Transport::CellNtn => "cell-ntn",
+            Transport::WifiNtn => "wifi-ntn",
+            Transport::WifiCellNtn => "wifi-cell-ntn",
+        }
+    }
+
+    pub const fn parse(s: &str) -> Option<Transport> {
+        let mut i = 0;
+        while i < TRANSPORTS.len() {
+            if str_eq(TRANSPORTS[i].as_str(), s) {
+                return Some(TRANSPORTS[i]);
+            }
+            i += 1;
+        }
+
+        None
+    }
+
+    /// Whether the transport may connect over satellite.
+    pub fn satellite(&self) -> bool {
+        matches!(
+            self,
+            Transport::Ntn | Transport::CellNtn | Transport::WifiNtn | Transport::WifiCellNtn
+        )
+    }
+}
+
+/// Bearer of the connection to notehub.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Bearer {
+    Cell,
+    Wifi,
+    Ntn,
+}
+
+impl Bearer {
+    /// The bearer in use from the `method` reported by the Notecard, `None` if not known.
+    pub fn from_method(method: &str) -> Option<Bearer> {
+        match method {
+            "cell" => Some(Bearer::Cell),
+            "wifi" => Some(Bearer::Wifi),
+            "ntn" => Some(Bearer::Ntn),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for t in TRANSPORTS {
+            assert_eq!(Transport::parse(t.as_str()), Some(t));
+        }
+
+        assert_eq!(Transport::parse("cell-wifi"), None);
+        assert_eq!(Transport::parse(""), None);
+    }
+
+    #[test]
+    fn serde() {
+        let t: Transport = serde_json_core::from_str(r#""cell-ntn""#).unwrap().0;
+        assert_eq!(t, Transport::CellNtn);
+        assert!(t.satellite());
+        assert!(!Transport::WifiCell.satellite());
+
+        let s: heapless::String<16> = serde_json_core::to_string(&Transport::Default).unwrap();
+        assert_eq!(s, r#""-""#);
+
+        assert_eq!(Bearer::from_method("ntn"), Some(Bearer::Ntn));
+        assert_eq!(Bearer::from_method("cell-ntn"), None);
+    }
+}
@@ -0,0 +1,101 @@
+//! Sync urgency of the outbound notes.
+//!
+//! Every kind of outbound note has an urgency, which sets the `sync` flag of the `note.add` (or
+//! `hub.log`) request that queues it on the Notecard:
+//!
+//! | Urgency  | `sync`                                    | Sent                                   |
+//! |----------|-------------------------------------------|----------------------------------------|
+//! | `urgent` | always, also in the quiet hours           | right away, a sync of its own          |
+//! | `normal` | in continuous mode, outside quiet hours   | with the next sync                     |
+//! | `low`    | never                                     | with the next periodic sync            |
+//!
+//! In periodic mode (the default) `normal` and `low` are the same: the notes are sent with the
+//! periodic sync (`sync_period`), or the sync started when the Notecard is filling up. In
+//! continuous mode `low` keeps bulky notes (the time series, backfill batches) from starting a
+//! sync each, they go out with the outbound interval of the hub. An `urgent` note is never held
+//! back behind a backlog, but every one of them costs a connection.
+//!
+//! Health notes are also synced in the quiet hours when `quiet_hours.health` is set, regardless of
+//! their urgency.
+
+/// Urgency of a note.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Urgent,
+    Normal,
+    Low,
+}
+
+impl Urgency {
+    /// The `sync` flag of a note, in `continuous` mode and in the `quiet` hours.
+    pub fn sync(&self, continuous: bool, quiet: bool) -> bool {
+        match self {
+            Urgency::Urgent => true,
+            Urgency::Normal => continuous && !quiet,
+            Urgency::Low => false,
+        }
+    }
+}
+
+/// Urgency of every kind of outbound note. Fields that are not set in an override take the
+/// default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Urgencies {
+    /// Data packages (`axl.qo`).
+    pub data: Urgency,
+
+    /// Statistics of the packages (`stats.qo`).
+    pub stats: Urgency,
+
+    /// Health notes (`health.qo`).
+    pub health: Urgency,
+
+    /// Compressed batches of stored packages (`backfill.qo`).
+    pub backfill: Urgency,
+
+    /// Alarms (tamper, free fall), sent as alert log messages.
+    pub alarm: Urgency,
+}
+
+impl Default for Urgencies {
+    fn default() -> Urgencies {
+        Urgencies {
+            data: Urgency::Normal,
+            stats: Urgency::Normal,
+            health: Urgency::Normal,
+            backfill: Urgency::Low,
+            alarm: Urgency::Urgent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync() {
+        for (continuous, quiet) in [(false, false), (false, true), (true, false), (true, true)] {
+            assert!(Urgency::Urgent.sync(continuous, quiet));
+            assert!(!Urgency::Low.sync(continuous, quiet));
+        }
+
+        assert!(Urgency::Normal.sync(true, false));
+        assert!(!Urgency::Normal.sync(true, true));
+        assert!(!Urgency::Normal.sync(false, false));
+    }
+
+    #[test]
+    fn partial() {
+        let u: Urgencies = serde_json_core::from_str(r#"{ "data": "low", "health": "urgent" }"#)
+            .unwrap()
+            .0;
+
+        assert_eq!(u.data, Urgency::Low);
+        assert_eq!(u.health, Urgency::Urgent);
+        assert_eq!(u.alarm, Urgency::Urgent);
+        assert_eq!(u.stats, Urgency::Normal);
+    }
+}
@@ -0,0 +1,93 @@
+//! Watchdog timer of the MCU (`WDT`), resetting the board when the main loop stalls.
+//!
+//! The watchdog is armed at boot before safe mode is checked (see `safe_mode`), with the
+//! `watchdog` timeout from the config, and restarted once every turn of the main loop or of the
+//! safe mode loop. The setup at boot is covered too. A transaction with the Notecard or the IMU
+//! that never returns (e.g. a wedged I2C bus) stops the loop, or the interrupt that reads the
+//! IMU, and the watchdog resets the MCU when the timeout runs out. The reset is counted as
+//! [`ResetCause::Watchdog`](crate::reboots::ResetCause) in the reboots and in safe mode, and
+//! the storage continues in the first free collection on the SD-card (see `Storage::next_id`) as
+//! after any other reset.
+//!
+//! The watchdog runs from the low frequency RC oscillator at 1/16 Hz, which keeps running in deep
+//! sleep, and counts down at most 255 ticks: the timeout is rounded up to a multiple of 16 s and
+//! is at most 68 minutes. A turn of the loop that sends the data is slow: it waits for the GPS,
+//! the time and the sync (each up to `timeouts`), and sends the queued packages. The timeout
+//! must be well above that, or the watchdog resets a working buoy.
+
+/// Seconds per tick of the watchdog (1/16 Hz clock).
+pub const TICK: u32 = 16;
+
+/// Minimum timeout [s].
+pub const MIN_TIMEOUT: u32 = 5 * 60;
+
+/// Maximum timeout [s]: the reset value is 8 bits.
+pub const MAX_TIMEOUT: u32 = 255 * TICK;
+
+/// Default timeout [s].
+pub const DEFAULT_TIMEOUT: u32 = 20 * 60;
+
+/// Bits of `WDT.CFG`.
+const WDTEN: u32 = 1 << 0;
+const RESEN: u32 = 1 << 2;
+const RESVAL_POS: u32 = 8;
+const CLKSEL_POS: u32 = 24;
+
+/// `WDT.CFG.CLKSEL` for the 1/16 Hz clock.
+const CLKSEL_16TH_HZ: u32 = 4;
+
+/// Written to `WDT.RSTRT` to restart the count down.
+pub const RSTRT_KEY: u32 = 0xb2;
+
+/// Bit of `RSTGEN.CFG` that lets the watchdog reset the MCU.
+pub const WDREN: u32 = 1 << 1;
+
+/// The timeout is valid: `0` (disabled) or between `MIN_TIMEOUT` and `MAX_TIMEOUT`.
+pub fn is_valid(timeout: u32) -> bool {
+    timeout == 0 || (MIN_TIMEOUT..=MAX_TIMEOUT).contains(&timeout)
+}
+
+/// Ticks to count down for `timeout` [s], rounded up.
+pub fn ticks(timeout: u32) -> u8 {
+    (timeout.saturating_add(TICK - 1) / TICK).min(255) as u8
+}
+
+/// The timeout the watchdog runs with for `timeout` [s].
+pub fn timeout(timeout: u32) -> u32 {
+    ticks(timeout) as u32 * TICK
+}
+
+/// `WDT.CFG` resetting the MCU after `timeout` [s], without the interrupt.
+pub fn cfg(timeout: u32) -> u32 {
+    WDTEN | RESEN | ((ticks(timeout) as u32) << RESVAL_POS) | (CLKSEL_16TH_HZ << CLKSEL_POS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        assert!(is_valid(0));
+        assert!(is_valid(DEFAULT_TIMEOUT));
+        assert!(is_valid(MIN_TIMEOUT));
+        assert!(is_valid(MAX_TIMEOUT));
+        assert!(!is_valid(60));
+        assert!(!is_valid(MAX_TIMEOUT + 1));
+    }
+
+    #[test]
+    fn rounded_up() {
+        assert_eq!(ticks(DEFAULT_TIMEOUT), 75);
+        assert_eq!(ticks(301), 19);
+        assert_eq!(timeout(301), 304);
+        assert_eq!(ticks(MAX_TIMEOUT), 255);
+        assert_eq!(timeout(MAX_TIMEOUT), MAX_TIMEOUT);
+    }
+
+    #[test]
+    fn register() {
+        assert_eq!(cfg(DEFAULT_TIMEOUT), 0x0400_4b05);
+        assert_eq!((cfg(MAX_TIMEOUT) >> RESVAL_POS) & 0xff, 0xff);
+    }
+}
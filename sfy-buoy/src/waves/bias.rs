@@ -0,0 +1,286 @@
+//! Removal of the acceleration offset (bias).
+//!
+//! After rotation to the earth frame standard gravity (`SENSORS_GRAVITY_STANDARD`) is always
+//! subtracted from the vertical acceleration (see `buf`). Nothing else is subtracted unless an
+//! offset removal is selected with `bias_removal` in the config:
+//!
+//! * `off` (default): no offset is removed.
+//! * `mean`: the mean of each axis of the package is subtracted when the buffer is taken. The
+//!   offset is a whole number of steps of the wire encoding (see `wire::A16`), so adding the
+//!   recorded offset back restores the samples exactly (except samples at the limits of the
+//!   range). The quality flags are evaluated before the offset is removed.
+//! * `calibration`: the configured `accel_bias` (body frame, e.g. from `sfypack calibrate`) is
+//!   subtracted from every accelerometer sample before the orientation filter. With a temperature
+//!   table (`accel_thermal`) the bias and scale at the temperature of the buffer are applied
+//!   instead (see `thermal`), the scale is recorded in `AxlPacket::scale`.
+//! * `still`: the bias is estimated at boot from a window of `bias_window` seconds while the
+//!   buoy is still (e.g. on deck before the deployment, see [`StillEstimate`]), and then
+//!   subtracted like `calibration`. At rest the accelerometer only measures gravity, so the
+//!   offset of the mean from standard gravity is the bias. Only the bias along gravity can be
+//!   told apart from the tilt of the buoy, the estimate is the bias of the vertical axis of the
+//!   buoy at rest. A window with the buoy moving is discarded and the estimate starts over.
+//!   Until the estimate is complete nothing is subtracted, and the packages are recorded with
+//!   `off`.
+//!
+//! The mode and the offset that was removed are recorded in every package
+//! (`AxlPacket::bias_mode` and `AxlPacket::bias`, m/s^2). With `mean` the offset is in the earth
+//! frame of the samples and is added back per axis. With `calibration` the offset is in the body
+//! frame, it can only be added back to the raw samples. Calibration capture packages never have an
+//! offset removed.
+
+use super::wire::{ScaledF32, A16};
+use super::SENSORS_GRAVITY_STANDARD;
+use crate::axl::{axes_width, AXES_ALL, SAMPLE_SZ};
+use crate::config::MAX_ACCEL_BIAS;
+
+/// Maximum standard deviation of each axis over the window for the buoy to be still [m/s^2].
+pub const STILL_STD: f64 = 0.05;
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BiasRemoval {
+    Off,
+    Mean,
+    Calibration,
+    Still,
+}
+
+impl BiasRemoval {
+    /// Recorded in the packages.
+    pub const fn code(&self) -> u8 {
+        match self {
+            BiasRemoval::Off => 0,
+            BiasRemoval::Mean => 1,
+            BiasRemoval::Calibration => 2,
+            BiasRemoval::Still => 3,
+        }
+    }
+}
+
+/// Acceleration of one step of the wire encoding with full scale `accel_max` [m/s^2].
+pub fn step(accel_max: f32) -> f32 {
+    2. * accel_max / u16::MAX as f32
+}
+
+/// Subtract the mean of each axis from the samples in `data` (wire encoded with full scale
+/// `accel_max`, with the axes in `axes`, see `AxlPacket::axes`), in whole steps of the encoding.
+/// Returns the offset that was subtracted from each axis [m/s^2], `0` for axes left out.
+pub fn remove_mean(data: &mut [u16], axes: u8, accel_max: f32) -> [f32; SAMPLE_SZ] {
+    let w = axes_width(axes);
+
+    if w == 0 || data.len() < w {
+        return [0.; SAMPLE_SZ];
+    }
+
+    let n = data.len() / w;
+    let mid = A16::from_f32(0.).to_u16() as i64;
+
+    let mut sum = [0i64; SAMPLE_SZ];
+    for s in data.chunks_exact(w) {
+        for (sum, u) in sum.iter_mut().zip(s) {
+            *sum += *u as i64 - mid;
+        }
+    }
+
+    let steps = sum.map(|s| libm::round(s as f64 / n as f64) as i64);
+
+    for s in data.chunks_exact_mut(w) {
+        for (u, d) in s.iter_mut().zip(steps) {
+            *u = (*u as i64 - d).clamp(0, u16::MAX as i64) as u16;
+        }
+    }
+
+    // The columns of the samples back to the axes.
+    let mut bias = [0.; SAMPLE_SZ];
+    let mut c = 0;
+    for (i, b) in bias.iter_mut().enumerate() {
+        if axes & (1 << i) != 0 {
+            *b = steps[c] as f32 * step(accel_max);
+            c += 1;
+        }
+    }
+
+    bias
+}
+
+/// Estimate of the bias from a window of accelerometer samples while the buoy is still (see
+/// `BiasRemoval::Still`).
+#[derive(Debug, Clone)]
+pub struct StillEstimate {
+    /// Samples in a window.
+    window: u32,
+    n: u32,
+    sum: [f64; 3],
+    sum_sq: [f64; 3],
+}
+
+impl StillEstimate {
+    pub fn new(window: u32) -> StillEstimate {
+        StillEstimate {
+            window: window.max(1),
+            n: 0,
+            sum: [0.; 3],
+            sum_sq: [0.; 3],
+        }
+    }
+
+    /// Add a sample of the accelerometer in the body frame [m/s^2]. Returns the bias [m/s^2] when
+    /// a window is complete with the buoy still, otherwise the next window is started.
+    pub fn push(&mut self, a: [f64; 3]) -> Option<[f64; 3]> {
+        for i in 0..3 {
+            self.sum[i] += a[i];
+            self.sum_sq[i] += a[i] * a[i];
+        }
+        self.n += 1;
+
+        if self.n < self.window {
+            return None;
+        }
+
+        let n = self.n as f64;
+        let mean = self.sum.map(|s| s / n);
+        let still = (0..3).all(|i| self.sum_sq[i] / n - mean[i] * mean[i] <= STILL_STD * STILL_STD);
+
+        *self = StillEstimate::new(self.window);
+
+        if !still {
+            defmt::debug!("bias: buoy moving, estimate started over.");
+            return None;
+        }
+
+        let norm = libm::sqrt(mean.iter().map(|m| m * m).sum::<f64>());
+        if norm == 0. {
+            return None;
+        }
+
+        // The mean is gravity along its direction, and the bias along gravity.
+        let bias = mean.map(|m| m - m / norm * SENSORS_GRAVITY_STANDARD);
+
+        if bias.iter().any(|b| b.abs() >= MAX_ACCEL_BIAS as f64) {
+            defmt::warn!("bias: estimate out of range: {:?}, started over.", bias);
+            return None;
+        }
+
+        Some(bias)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waves::wire::ACCEL_MAX;
+
+    #[test]
+    fn mean_removed() {
+        let offset = [0.5, -0.25, 1.0];
+
+        let mut data = (0..300)
+            .map(|i| {
+                let wave = if i % 2 == 0 { 0.1 } else { -0.1 };
+                A16::from_f32(offset[i % SAMPLE_SZ] + wave).to_u16()
+            })
+            .collect::<heapless::Vec<u16, 300>>();
+        let orig = data.clone();
+
+        let bias = remove_mean(&mut data, AXES_ALL, ACCEL_MAX);
+        let step = step(ACCEL_MAX);
+
+        for i in 0..SAMPLE_SZ {
+            assert!((bias[i] - offset[i]).abs() < 2. * step, "{:?}", bias);
+
+            let mean = data
+                .iter()
+                .skip(i)
+                .step_by(SAMPLE_SZ)
+                .map(|u| A16::from_u16(*u).to_f32())
+                .sum::<f32>()
+                / 100.;
+            assert!(mean.abs() < 2. * step, "{}", mean);
+        }
+
+        // Reversible: the offset is a whole number of steps.
+        for (i, (u, o)) in data.iter().zip(&orig).enumerate() {
+            let d = libm::roundf(bias[i % SAMPLE_SZ] / step) as i64;
+            assert_eq!(*u as i64 + d, *o as i64);
+        }
+    }
+
+    #[test]
+    fn vertical_only() {
+        let mut data = (0..100)
+            .map(|i| A16::from_f32(if i % 2 == 0 { 1.1 } else { 0.9 }).to_u16())
+            .collect::<heapless::Vec<u16, 100>>();
+
+        let bias = remove_mean(&mut data, 0b100, ACCEL_MAX);
+        let step = step(ACCEL_MAX);
+
+        assert_eq!(bias[..2], [0., 0.]);
+        assert!((bias[2] - 1.0).abs() < 2. * step, "{:?}", bias);
+        assert!((A16::from_u16(data[0]).to_f32() - 0.1).abs() < 2. * step);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(remove_mean(&mut [], AXES_ALL, ACCEL_MAX), [0.; SAMPLE_SZ]);
+    }
+
+    #[test]
+    fn parse() {
+        let b: BiasRemoval = serde_json_core::from_str(r#""mean""#).unwrap().0;
+        assert_eq!(b, BiasRemoval::Mean);
+
+        let b: BiasRemoval = serde_json_core::from_str(r#""still""#).unwrap().0;
+        assert_eq!(b, BiasRemoval::Still);
+    }
+
+    #[test]
+    fn still_estimate() {
+        let g = SENSORS_GRAVITY_STANDARD;
+        let mut e = StillEstimate::new(100);
+
+        // Upright, with a small offset and noise on the vertical axis.
+        let bias = (0..100)
+            .filter_map(|i| {
+                let noise = if i % 2 == 0 { 0.01 } else { -0.01 };
+                e.push([0., 0., g + 0.15 + noise])
+            })
+            .collect::<heapless::Vec<_, 1>>();
+
+        assert_eq!(bias.len(), 1);
+        assert!(bias[0][..2].iter().all(|b| b.abs() < 1e-9), "{:?}", bias);
+        assert!((bias[0][2] - 0.15).abs() < 1e-6, "{:?}", bias);
+    }
+
+    #[test]
+    fn still_estimate_tilted() {
+        let g = SENSORS_GRAVITY_STANDARD;
+        let tilt = 10f64.to_radians();
+        let mut e = StillEstimate::new(10);
+
+        let a = [0., (g + 0.1) * tilt.sin(), (g + 0.1) * tilt.cos()];
+        let bias = (0..10).find_map(|_| e.push(a)).unwrap();
+
+        // Along gravity.
+        assert!((bias[1] - 0.1 * tilt.sin()).abs() < 1e-6, "{:?}", bias);
+        assert!((bias[2] - 0.1 * tilt.cos()).abs() < 1e-6, "{:?}", bias);
+    }
+
+    #[test]
+    fn still_estimate_moving() {
+        let g = SENSORS_GRAVITY_STANDARD;
+        let mut e = StillEstimate::new(100);
+
+        // Waves: started over.
+        for i in 0..100 {
+            let wave = if i % 2 == 0 { 1.0 } else { -1.0 };
+            assert!(e.push([0., 0., g + wave]).is_none());
+        }
+
+        // Still in the next window.
+        let bias = (0..100).find_map(|_| e.push([0., 0., g - 0.2])).unwrap();
+        assert!((bias[2] + 0.2).abs() < 1e-6, "{:?}", bias);
+
+        // Out of range.
+        assert!((0..100).all(|_| e.push([0., 0., g + 2.0]).is_none()));
+    }
+}
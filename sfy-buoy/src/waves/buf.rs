@@ -1,13 +1,57 @@
+//! Buffer of acceleration rotated to the earth frame, filtered and decimated.
+//!
+//! The orientation of the buoy is estimated with the AHRS (Kalman) filter from the gyroscope and
+//! the accelerometer (the magnetometer is not used), and every sample of acceleration is rotated
+//! from the body frame into the earth (NED) frame before the gravity is removed from the vertical
+//! component. The vertical channel is in this way measured along true vertical, rather than along
+//! the tilting z-axis of the buoy, where the tilt both leaks horizontal acceleration into the
+//! vertical and scales down the vertical acceleration (and gravity) by the cosine of the tilt.
+//!
+//! Assumptions:
+//!
+//! * The gyroscope tracks the fast rotation of the buoy in the waves, while the accelerometer
+//!   only corrects the slow drift of the estimated gravity direction. On average over a wave
+//!   period the acceleration of the buoy is gravity.
+//! * Without a magnetometer the heading is not known, so the horizontal components are in an
+//!   arbitrary (slowly drifting) horizontal frame. Only the vertical component is reliable.
+//! * The estimated orientation needs some time to converge after boot or reset of the filter.
+//!
+//! The buffer is double-buffered: when the buffer is full (or at the flush limit) it can be
+//! closed (`close`), and the following samples go to a second buffer until the full buffer is
+//! taken. The second buffer then takes its place. The FIFO of the IMU is in this way emptied
+//! before the full buffer is packaged, rather than filling up while the package is made and
+//! queued.
+//!
+//! With the `gyro` feature the angular rate is filtered and decimated in the body frame by
+//! pipelines of its own, in step with the acceleration, so that every sample of acceleration has
+//! a sample of angular rate (see `AxlPacket::gyro`).
+//!
+//! The samples with the acceleration at the full scale of the accelerometer are counted as they
+//! come in, before the rotation and the filters: the FIR filter smears a clipped sample over its
+//! neighbours, where it can no longer be told apart (see `AxlPacket::clipped_samples`).
+
 use ahrs_fusion::NxpFusion;
 use micromath::{vector::Vector3d, Quaternion};
 
-use crate::axl::{AXL_SZ, SAMPLE_SZ};
-#[cfg(feature = "fir")]
-use crate::fir;
+use crate::axl::{axes_width, AXES_ALL, AXL_SZ, MAX_FILLED, SAMPLE_SZ};
+use crate::config::AccelRange;
+#[cfg(feature = "despike")]
+use crate::despike;
+use crate::filter::Pipeline;
+#[cfg(feature = "despike")]
+use crate::filter::Stage;
 
-use super::wire::{ScaledF32, A16};
+use super::lever_arm::LeverArm;
+use super::raw;
+use super::wire::{scale_f32_to_u16, ACCEL_MAX};
 
-#[cfg(feature = "raw")]
+#[cfg(any(feature = "raw", feature = "gyro", test))]
+use super::wire::ScaledF32;
+
+#[cfg(any(feature = "raw", test))]
+use super::wire::A16;
+
+#[cfg(any(feature = "raw", feature = "gyro"))]
 use super::wire::G16;
 
 // From Adafruit Sensors library.
@@ -15,17 +59,8 @@ pub const SENSORS_RADS_TO_DPS: f64 = 57.29577793;
 pub const SENSORS_DPS_TO_RADS: f64 = 0.017453293;
 pub const SENSORS_GRAVITY_STANDARD: f64 = 9.80665;
 
-#[cfg(feature = "fir")]
-pub const RAW_AXL_SZ: usize = 2 * AXL_SZ * fir::DECIMATE as usize;
-
-#[cfg(feature = "fir")]
-pub const RAW_AXL_BYTE_SZ: usize = 2 * AXL_SZ * fir::DECIMATE as usize * 2;
-
-#[cfg(not(feature = "fir"))]
-pub const RAW_AXL_SZ: usize = 2 * AXL_SZ;
-
-#[cfg(not(feature = "fir"))]
-pub const RAW_AXL_BYTE_SZ: usize = 2 * AXL_SZ * 2;
+pub const RAW_AXL_SZ: usize = 2 * AXL_SZ * super::DECIMATION as usize;
+pub const RAW_AXL_BYTE_SZ: usize = RAW_AXL_SZ * 2;
 
 pub type VecAxl = heapless::Vec<u16, AXL_SZ>;
 pub type VecRawAxl = heapless::Vec<u16, RAW_AXL_SZ>;
@@ -42,54 +77,169 @@ pub enum Error {
 }
 
 pub struct ImuBuf {
-    #[cfg(feature = "fir")]
-    fir: [fir::Decimator; SAMPLE_SZ],
-
     filter: NxpFusion,
 
+    /// Filter stages (spike removal, FIR filter and decimation) of each axis of the rotated
+    /// acceleration (see `filter`).
+    pipeline: [Pipeline; SAMPLE_SZ],
+
+    /// Filter stages of each axis of the angular rate, in step with `pipeline`.
+    #[cfg(feature = "gyro")]
+    gyro_pipeline: [Pipeline; SAMPLE_SZ],
+
+    /// Number of filtered samples discarded because of the start-up transient of the pipeline
+    /// since the last time the buf was taken.
+    discarded: usize,
+
     /// Buffer with values ready to be sent. Only `sample()` is allowed to grow the buf, and
-    /// it must always grow with `SAMPLE_SZ` samples. The buf must also be a multiple of
-    /// `SAMPLE_SZ`.
+    /// it must always grow with one value for each stored axis (see `stored_axes`). The buf
+    /// must also be a multiple of the number of stored axes.
     pub axl: VecAxl,
 
     /// Buffer with raw values, is emptied whenever axl is emptied.
     #[cfg(feature = "raw")]
     pub raw_axl: VecRawAxl,
+
+    /// Second buffer, filled while `axl` is closed (`pending`). Takes the place of `axl` when
+    /// `axl` is taken.
+    next: VecAxl,
+
+    #[cfg(feature = "raw")]
+    raw_next: VecRawAxl,
+
+    /// Angular rate in the body frame, every axis for every sample in `axl`, and in `next`.
+    /// Taken with `take_gyro`.
+    #[cfg(feature = "gyro")]
+    gyro: VecAxl,
+
+    #[cfg(feature = "gyro")]
+    gyro_next: VecAxl,
+
+    /// `axl` is closed and waits to be taken.
+    pending: bool,
+
+    /// IMU samples consumed into `next`.
+    spill: usize,
+
+    /// IMU samples consumed into `axl`, and samples out of the decimation for `axl` and for
+    /// `next`, including the samples discarded in the start-up transient (see
+    /// `AxlPacket::raw_samples`).
+    consumed: usize,
+    output: usize,
+    output_next: usize,
+
+    /// Indices of the samples in `axl` and in `next` that were interpolated over a gap in the
+    /// FIFO (see `gap`).
+    filled: heapless::Vec<u16, MAX_FILLED>,
+    filled_next: heapless::Vec<u16, MAX_FILLED>,
+
+    /// IMU samples at the full scale of the accelerometer consumed into `axl` and into `next`.
+    clipped: usize,
+    clipped_next: usize,
+
+    /// Acceleration of the saturated count of the accelerometer [m/s^2], see
+    /// [`ImuBuf::set_accel_range`].
+    clip: f64,
+
+    /// Calibration capture: the acceleration is stored in the body frame at the IMU rate, without
+    /// filtering (see `calibration`). The filters are not updated, reset them when leaving the
+    /// capture.
+    pub calibration: bool,
+
+    /// Accelerometer bias in the body frame [m/s^2], subtracted from every sample before the
+    /// orientation filter (see `bias`). Not subtracted in the calibration capture.
+    pub bias: [f64; 3],
+
+    /// Accelerometer scale factor in the body frame, every sample is divided by it after the bias
+    /// is subtracted (see `thermal`). Not applied in the calibration capture.
+    pub scale: [f64; 3],
+
+    /// Axes to filter and store (see `Config::axes`). Disabled axes are neither despiked, nor
+    /// filtered, nor stored. All axes are stored in the calibration capture.
+    pub axes: u8,
+
+    /// Full scale of the encoding of the acceleration in `axl` [m/s^2] (see `wire`).
+    pub accel_max: f32,
+
+    /// Offset of the IMU from the center of buoyancy (see `lever_arm`), the acceleration of the
+    /// center is used. Not corrected in the calibration capture.
+    pub lever_arm: LeverArm,
+}
+
+/// Indices of the axes in `axes` (see `Config::axes`), in the order they are stored.
+fn enabled(axes: u8) -> impl Iterator<Item = usize> {
+    (0..SAMPLE_SZ).filter(move |i| axes & (1 << i) != 0)
 }
 
 impl ImuBuf {
     pub fn new(freq: f32) -> ImuBuf {
-        #[cfg(feature = "fir")]
-        let fir = [
-            fir::FIR::new().into_decimator(),
-            fir::FIR::new().into_decimator(),
-            fir::FIR::new().into_decimator(),
-        ];
-
         let filter = NxpFusion::new(freq);
 
         ImuBuf {
-            #[cfg(feature = "fir")]
-            fir,
-
             filter,
+            pipeline: [
+                Pipeline::default(),
+                Pipeline::default(),
+                Pipeline::default(),
+            ],
+            #[cfg(feature = "gyro")]
+            gyro_pipeline: [
+                Pipeline::default(),
+                Pipeline::default(),
+                Pipeline::default(),
+            ],
+            discarded: 0,
             axl: VecAxl::new(),
 
             #[cfg(feature = "raw")]
             raw_axl: VecRawAxl::new(),
+
+            next: VecAxl::new(),
+
+            #[cfg(feature = "raw")]
+            raw_next: VecRawAxl::new(),
+
+            #[cfg(feature = "gyro")]
+            gyro: VecAxl::new(),
+
+            #[cfg(feature = "gyro")]
+            gyro_next: VecAxl::new(),
+
+            pending: false,
+            spill: 0,
+            consumed: 0,
+            output: 0,
+            output_next: 0,
+            filled: heapless::Vec::new(),
+            filled_next: heapless::Vec::new(),
+            clipped: 0,
+            clipped_next: 0,
+            clip: raw::ms2(i16::MAX, AccelRange::G2),
+
+            calibration: false,
+            bias: [0.; 3],
+            scale: [1.; 3],
+            axes: AXES_ALL,
+            accel_max: ACCEL_MAX,
+            lever_arm: LeverArm::new([0.; 3], freq),
         }
     }
 
+    /// Take the buffer, the second buffer (empty unless the buffer was closed) takes its place.
     pub fn take_buf(&mut self) -> AxlBufT {
-        let b = self.axl.clone();
+        let b = core::mem::replace(&mut self.axl, core::mem::take(&mut self.next));
 
         #[cfg(feature = "raw")]
-        let r = self.raw_axl.clone();
+        let r = core::mem::replace(&mut self.raw_axl, core::mem::take(&mut self.raw_next));
 
-        self.axl.clear();
+        self.filled = core::mem::take(&mut self.filled_next);
+        self.clipped = core::mem::take(&mut self.clipped_next);
 
-        #[cfg(feature = "raw")]
-        self.raw_axl.clear();
+        self.pending = false;
+        self.consumed = self.spill;
+        self.output = core::mem::take(&mut self.output_next);
+        self.spill = 0;
+        self.discarded = 0;
 
         #[cfg(feature = "raw")]
         return (b, r);
@@ -98,54 +248,323 @@ impl ImuBuf {
         return (b,);
     }
 
+    /// Take the angular rate of the buffer, call right before `take_buf`.
+    #[cfg(feature = "gyro")]
+    pub fn take_gyro(&mut self) -> VecAxl {
+        core::mem::replace(&mut self.gyro, core::mem::take(&mut self.gyro_next))
+    }
+
     pub fn reset(&mut self) {
         self.axl.clear();
+        self.next.clear();
 
         #[cfg(feature = "raw")]
-        self.raw_axl.clear();
+        {
+            self.raw_axl.clear();
+            self.raw_next.clear();
+        }
+
+        #[cfg(feature = "gyro")]
+        {
+            self.gyro.clear();
+            self.gyro_next.clear();
+
+            for p in &mut self.gyro_pipeline {
+                p.reset();
+            }
+        }
+
+        self.filled.clear();
+        self.filled_next.clear();
+        self.clipped = 0;
+        self.clipped_next = 0;
+
+        self.pending = false;
+        self.spill = 0;
+        self.consumed = 0;
+        self.output = 0;
+        self.output_next = 0;
 
         self.filter.reset();
+        self.lever_arm.reset();
+
+        for p in &mut self.pipeline {
+            p.reset();
+        }
+    }
+
+    /// Replace the filter pipeline of every axis with the pipelines made by `pipeline`. The
+    /// decimation of the pipelines sets the rate of the packages (see `decimation`).
+    pub fn set_pipeline(&mut self, pipeline: impl Fn() -> Pipeline) {
+        self.pipeline = [pipeline(), pipeline(), pipeline()];
+
+        #[cfg(feature = "gyro")]
+        {
+            self.gyro_pipeline = [pipeline(), pipeline(), pipeline()];
+        }
+        debug_assert!(self
+            .pipeline
+            .iter()
+            .all(|p| p.decimation() == self.pipeline[0].decimation()));
+    }
+
+    /// Decimation of the filter pipeline from the IMU rate to the output rate. The samples of
+    /// the calibration capture are not decimated.
+    pub fn decimation(&self) -> u8 {
+        self.pipeline[0].decimation() as u8
+    }
 
-        #[cfg(feature = "fir")]
-        for f in &mut self.fir {
-            f.reset();
+    /// Total delay of the filter pipeline [s] (see `filter::Pipeline::delay`).
+    pub fn delay(&self) -> f32 {
+        self.pipeline[0].delay(super::FREQ.value())
+    }
+
+    /// Set window length of spike removal filter (resets the filter).
+    #[cfg(feature = "despike")]
+    pub fn set_despike_window(&mut self, window: usize) {
+        for p in &mut self.pipeline {
+            for s in p.stages_mut() {
+                if let Stage::Despike(h) = s {
+                    *h = despike::Hampel::new(window);
+                }
+            }
         }
     }
 
-    /// Free capacity in buf of full sample (`SAMPLE_SZ`).
+    /// Returns the fraction of samples replaced by the spike removal filter since last call.
+    #[cfg(feature = "despike")]
+    pub fn take_despiked(&mut self) -> f32 {
+        let (n, c) = self
+            .pipeline
+            .iter_mut()
+            .flat_map(|p| p.stages_mut())
+            .filter_map(|s| match s {
+                Stage::Despike(h) => Some(h.take_counts()),
+                _ => None,
+            })
+            .fold((0, 0), |(n, c), (hn, hc)| (n + hn, c + hc));
+
+        if n == 0 {
+            0.0
+        } else {
+            c as f32 / n as f32
+        }
+    }
+
+    /// Number of output samples discarded at the start of the buf because of the start-up
+    /// transient of the filter pipeline.
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    /// Indices of the interpolated samples in the buf (see [`ImuBuf::mark_filled`]).
+    pub fn filled(&self) -> &[u16] {
+        &self.filled
+    }
+
+    /// Mark the next sample as interpolated over a gap in the FIFO, call before `sample`. The
+    /// index is that of the next output sample, which is decimated from several IMU samples.
+    /// Returns `false` if the list is full and the sample could not be marked.
+    pub fn mark_filled(&mut self) -> bool {
+        let width = self.width();
+        let (out, filled) = if self.pending {
+            (&self.next, &mut self.filled_next)
+        } else {
+            (&self.axl, &mut self.filled)
+        };
+
+        let i = (out.len() / width) as u16;
+        if filled.last() == Some(&i) {
+            return true;
+        }
+
+        filled.push(i).is_ok()
+    }
+
+    /// Full scale of the accelerometer, the samples at the full scale are counted as clipped.
+    pub fn set_accel_range(&mut self, range: AccelRange) {
+        self.clip = raw::ms2(i16::MAX, range);
+    }
+
+    /// IMU samples at the full scale of the accelerometer consumed into the buf since it was
+    /// taken (see `AxlPacket::clipped_samples`).
+    pub fn clipped(&self) -> usize {
+        self.clipped
+    }
+
+    /// Axes stored in the buf: `axes`, or all axes in the calibration capture.
+    pub fn stored_axes(&self) -> u8 {
+        if self.calibration {
+            AXES_ALL
+        } else {
+            self.axes
+        }
+    }
+
+    /// Number of values per sample.
+    fn width(&self) -> usize {
+        axes_width(self.stored_axes()).max(1)
+    }
+
+    /// Free capacity in buf of full samples.
     #[allow(dead_code)]
     pub fn free(&self) -> usize {
-        (self.axl.capacity() - self.axl.len()) / SAMPLE_SZ
+        self.capacity() - self.len()
     }
 
+    /// The buffer is full, or the raw buffer has no room for the IMU samples of another output
+    /// sample (the raw samples of the start-up transient of the filters are kept).
     pub fn is_full(&self) -> bool {
-        self.axl.is_full()
+        #[cfg(feature = "raw")]
+        {
+            let d = if self.calibration {
+                1
+            } else {
+                self.decimation() as usize
+            };
+            if self.raw_axl.len() + 2 * SAMPLE_SZ * d > RAW_AXL_SZ {
+                return true;
+            }
+        }
+
+        self.len() >= self.capacity()
     }
 
     pub fn len(&self) -> usize {
-        self.axl.len() / SAMPLE_SZ
+        self.axl.len() / self.width()
     }
 
+    /// Capacity in samples, the same whatever axes are stored: a package with fewer axes is
+    /// smaller, rather than longer. The raw buffer is sized for `DECIMATION`, with a larger
+    /// decimation the raw samples limit the capacity.
     pub fn capacity(&self) -> usize {
-        self.axl.capacity() / SAMPLE_SZ
+        let capacity = self.axl.capacity() / SAMPLE_SZ;
+
+        #[cfg(feature = "raw")]
+        if !self.calibration {
+            return capacity.min(RAW_AXL_SZ / (2 * SAMPLE_SZ * self.decimation() as usize));
+        }
+
+        capacity
+    }
+
+    /// Close the buffer: the following samples go to the second buffer until the buffer is
+    /// taken.
+    pub fn close(&mut self) {
+        self.pending = true;
+    }
+
+    /// The buffer is closed and waits to be taken.
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Samples in the second buffer.
+    pub fn next_len(&self) -> usize {
+        self.next.len() / self.width()
+    }
+
+    /// Samples from the IMU consumed into the second buffer (at the IMU rate).
+    pub fn spill(&self) -> usize {
+        self.spill
+    }
+
+    /// Samples from the IMU consumed into the buf, and samples out of the decimation (including
+    /// the discarded samples) since the buf was taken.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.consumed, self.output)
     }
 
-    /// Sample a new value and filter through Kalman-filter and FIR-filters. Will grow
-    /// buffer with `SAMPLE_SZ` samples.
+    /// Sample a new value and filter through Kalman-filter and the filter pipeline. Will grow
+    /// buffer with one value for each stored axis.
     pub fn sample(&mut self, g: [f64; 3], a: [f64; 3]) -> Result<(), Error> {
-        if self.is_full() {
+        let axes = self.axes;
+        let width = self.width();
+        let capacity = self.capacity();
+
+        #[cfg(feature = "raw")]
+        {
+            let raw_out = if self.pending {
+                &self.raw_next
+            } else {
+                &self.raw_axl
+            };
+
+            if raw_out.len() + 2 * SAMPLE_SZ > RAW_AXL_SZ {
+                return Err(Error::BufFull);
+            }
+        }
+
+        let out = if self.pending {
+            &mut self.next
+        } else {
+            &mut self.axl
+        };
+
+        if out.len() / width >= capacity {
             return Err(Error::BufFull);
         }
 
+        if self.pending {
+            self.spill += 1;
+        } else {
+            self.consumed += 1;
+        }
+
+        // The saturated counts convert to the endpoints of the range, `i16::MIN` to just beyond
+        // it (see `raw`).
+        if a.iter().any(|a| a.abs() >= self.clip) {
+            if self.pending {
+                self.clipped_next += 1;
+            } else {
+                self.clipped += 1;
+            }
+        }
+
+        let output = if self.pending {
+            &mut self.output_next
+        } else {
+            &mut self.output
+        };
+
         // Store raw values
         #[cfg(feature = "raw")]
         {
-            self.raw_axl
-                .extend(g.iter().map(|g| G16::from_f32(*g as f32).to_u16()));
-            self.raw_axl
-                .extend(a.iter().map(|a| A16::from_f32(*a as f32).to_u16()));
+            let raw_out = if self.pending {
+                &mut self.raw_next
+            } else {
+                &mut self.raw_axl
+            };
+
+            raw_out.extend(g.iter().map(|g| G16::from_f32(*g as f32).to_u16()));
+            raw_out.extend(a.iter().map(|a| A16::from_f32(*a as f32).to_u16()));
+        }
+
+        #[cfg(feature = "gyro")]
+        let gyro_out = if self.pending {
+            &mut self.gyro_next
+        } else {
+            &mut self.gyro
+        };
+
+        if self.calibration {
+            let max = self.accel_max;
+            out.extend(a.iter().map(|a| scale_f32_to_u16(max, *a as f32)));
+
+            #[cfg(feature = "gyro")]
+            gyro_out.extend(g.iter().map(|g| G16::from_f32(*g as f32).to_u16()));
+
+            *output += 1;
+            return Ok(());
         }
 
+        let a = [
+            (a[0] - self.bias[0]) / self.scale[0],
+            (a[1] - self.bias[1]) / self.scale[1],
+            (a[2] - self.bias[2]) / self.scale[2],
+        ];
+        let a = self.lever_arm.correct(g, a);
+
         // Feed AHRS filter
         //
         // The filter takes gyro readings in degrees per second (dps) and accelerometer in (g) for
@@ -173,38 +592,45 @@ impl ImuBuf {
         };
         let axl = q.rotate(axl);
 
-        #[cfg(not(feature = "fir"))]
-        {
-            // x, y, z from axl is in m/s^2, the quaternion is only used to
-            // rotate the instantanuous acceleration.
-            self.axl.push(A16::from_f32(axl.x).to_u16()).unwrap();
-            self.axl.push(A16::from_f32(axl.y).to_u16()).unwrap();
-            self.axl
-                .push(A16::from_f32(axl.z - SENSORS_GRAVITY_STANDARD as f32).to_u16())
-                .unwrap();
+        // Removing the mean from the z-component should give better resolution.
+        let axl = [axl.x, axl.y, axl.z - SENSORS_GRAVITY_STANDARD as f32];
+
+        // Filter (and decimate) the rotated acceleration. The pipelines of disabled axes are not
+        // run, the pipelines of the enabled axes decimate in step.
+        let mut y = [None; SAMPLE_SZ];
+        for i in enabled(axes) {
+            y[i] = self.pipeline[i].process(axl[i]);
         }
 
-        // Filter and decimate the rotated acceleration.
-        //
-        // Removing the mean from the z-component should give better resolution.
-        #[cfg(feature = "fir")]
-        match (
-            self.fir[0].decimate(axl.x),
-            self.fir[1].decimate(axl.y),
-            self.fir[2].decimate(axl.z - SENSORS_GRAVITY_STANDARD as f32),
-        ) {
-            (Some(x), Some(y), Some(z)) => {
+        #[cfg(feature = "gyro")]
+        let gy: [Option<f32>; SAMPLE_SZ] =
+            core::array::from_fn(|i| self.gyro_pipeline[i].process(g[i] as f32));
+
+        let first = axes.trailing_zeros() as usize;
+        if y[first].is_some() {
+            *output += 1;
+        }
+
+        match y[first] {
+            Some(_) if !self.pipeline[first].is_warm() => {
+                // Start-up transient of filter, discard.
+                self.discarded += 1;
+            }
+            Some(_) => {
                 // x, y, z from axl is in m/s^2, the quaternion is only used to
                 // rotate the instantanuous acceleration.
-                self.axl.push(A16::from_f32(x).to_u16()).unwrap();
-                self.axl.push(A16::from_f32(y).to_u16()).unwrap();
-                self.axl.push(A16::from_f32(z).to_u16()).unwrap();
-            }
-            (None, None, None) => {} // No filter output.
-            _ => {
-                unreachable!()
+                let max = self.accel_max;
+                out.extend(y.iter().flatten().map(|v| scale_f32_to_u16(max, *v)));
+
+                // The pipelines of the angular rate decimate in step with the acceleration.
+                #[cfg(feature = "gyro")]
+                gyro_out.extend(
+                    gy.iter()
+                        .map(|v| G16::from_f32(v.unwrap_or_default()).to_u16()),
+                );
             }
-        };
+            None => {} // No filter output.
+        }
 
         Ok(())
     }
@@ -217,8 +643,14 @@ mod tests {
     fn filter_decimater() {
         use super::*;
         use crate::axl::SAMPLE_NO;
+        use crate::fir;
 
         let mut buf = ImuBuf::new(200.);
+        assert!(buf
+            .pipeline
+            .iter()
+            .all(|p| p.decimation() == crate::waves::DECIMATION as u32));
+        assert_eq!(buf.delay(), fir::DELAY);
 
         for _ in 0..SAMPLE_NO {
             buf.sample([0., 1., 2.], [0., 1., 2.]).unwrap();
@@ -226,11 +658,402 @@ mod tests {
 
         assert_eq!(
             buf.axl.len(),
-            SAMPLE_SZ * SAMPLE_NO / fir::DECIMATE as usize
+            SAMPLE_SZ * (SAMPLE_NO / crate::waves::DECIMATION as usize - fir::WARMUP)
         );
         assert_eq!(
             buf.free(),
-            (AXL_SZ / SAMPLE_SZ) - (SAMPLE_NO / fir::DECIMATE as usize)
+            (AXL_SZ / SAMPLE_SZ) - (SAMPLE_NO / crate::waves::DECIMATION as usize - fir::WARMUP)
         );
+        assert_eq!(buf.discarded(), fir::WARMUP);
+
+        buf.take_buf();
+        assert_eq!(buf.discarded(), 0);
+
+        for _ in 0..SAMPLE_NO {
+            buf.sample([0., 1., 2.], [0., 1., 2.]).unwrap();
+        }
+
+        assert_eq!(buf.len(), SAMPLE_NO / crate::waves::DECIMATION as usize);
+    }
+
+    #[cfg(all(feature = "fir", feature = "gyro"))]
+    #[test]
+    fn gyro_in_step() {
+        use super::*;
+        use crate::axl::SAMPLE_NO;
+
+        let mut buf = ImuBuf::new(200.);
+        buf.axes = 0b100;
+
+        for _ in 0..SAMPLE_NO {
+            buf.sample([0.1, 0., -0.1], [0., 0., 9.81]).unwrap();
+        }
+        buf.close();
+        for _ in 0..2 * crate::waves::DECIMATION as usize {
+            buf.sample([0.1, 0., -0.1], [0., 0., 9.81]).unwrap();
+        }
+
+        // Every axis of the angular rate for every sample of the vertical acceleration.
+        let gyro = buf.take_gyro();
+        let (axl, ..) = buf.take_buf();
+        assert_eq!(gyro.len(), axl.len() * SAMPLE_SZ);
+        assert_eq!(buf.take_gyro().len(), buf.len() * SAMPLE_SZ);
+
+        let x = G16::from_u16(gyro[gyro.len() - 3]).to_f32();
+        assert!((x - 0.1).abs() < 1e-3);
+    }
+
+    #[cfg(feature = "fir")]
+    #[test]
+    fn sample_counts() {
+        use super::*;
+        use crate::axl::SAMPLE_NO;
+        use crate::fir;
+
+        let d = crate::waves::DECIMATION as usize;
+        let mut buf = ImuBuf::new(200.);
+
+        // The discarded samples are counted out of the decimation.
+        for _ in 0..SAMPLE_NO + 3 {
+            buf.sample([0., 1., 2.], [0., 1., 2.]).unwrap();
+        }
+        let (raw, output) = buf.counts();
+        assert_eq!(raw, SAMPLE_NO + 3);
+        assert_eq!(output, (SAMPLE_NO + 3).div_ceil(d));
+        assert_eq!(output - buf.discarded(), buf.len());
+        assert_eq!(buf.discarded(), fir::WARMUP);
+
+        buf.take_buf();
+        assert_eq!(buf.counts(), (0, 0));
+
+        // Samples read into the second buffer are counted for it.
+        for _ in 0..SAMPLE_NO {
+            buf.sample([0., 1., 2.], [0., 1., 2.]).unwrap();
+        }
+        buf.close();
+        for _ in 0..2 * d {
+            buf.sample([0., 1., 2.], [0., 1., 2.]).unwrap();
+        }
+        assert_eq!(buf.counts().0, SAMPLE_NO);
+
+        buf.take_buf();
+        assert_eq!(buf.counts(), (2 * d, 2));
+    }
+
+    /// A high-pass stage after the default stages: the same delay, and the samples are discarded
+    /// until it is warm.
+    #[test]
+    fn custom_pipeline() {
+        use super::*;
+        use crate::filter::{DcBlock, FilterStage, Stage};
+        use crate::waves::OUTPUT_FREQ;
+
+        let mut buf = ImuBuf::new(208.);
+        let delay = buf.delay();
+
+        buf.set_pipeline(|| {
+            let mut p = Pipeline::default();
+            p.push(Stage::DcBlock(DcBlock::new(0.05, OUTPUT_FREQ)))
+                .ok()
+                .unwrap();
+            p
+        });
+        assert_eq!(buf.delay(), delay);
+
+        let mut dc = DcBlock::new(0.05, OUTPUT_FREQ);
+        let mut n = 0;
+        while !dc.is_warm() {
+            dc.process(0.);
+            n += 1;
+        }
+
+        let g = SENSORS_GRAVITY_STANDARD;
+        while buf.len() == 0 {
+            buf.sample([0.; 3], [0., 0., g]).unwrap();
+        }
+        assert!(buf.discarded() >= n);
+    }
+
+    #[test]
+    fn decimation() {
+        use super::*;
+        use crate::axl::SAMPLE_NO;
+        use crate::filter::{DecimationMode, FirFilter};
+        use crate::waves::DECIMATION;
+
+        let mut buf = ImuBuf::new(208.);
+        assert_eq!(buf.decimation(), DECIMATION);
+        assert_eq!(buf.capacity(), SAMPLE_NO);
+
+        buf.set_pipeline(|| Pipeline::with_filter(DecimationMode::Average, FirFilter::Hz20, 8));
+        assert_eq!(buf.decimation(), 8);
+
+        // The raw samples fill up first with more decimation than the raw buffer is sized for.
+        #[cfg(feature = "raw")]
+        assert_eq!(buf.capacity(), SAMPLE_NO * DECIMATION as usize / 8);
+
+        #[cfg(not(feature = "raw"))]
+        assert_eq!(buf.capacity(), SAMPLE_NO);
+
+        let mut n = 0;
+        while !buf.is_full() {
+            buf.sample([0.; 3], [0., 0., SENSORS_GRAVITY_STANDARD])
+                .unwrap();
+            n += 1;
+        }
+        assert_eq!(n, 8 * buf.capacity());
+
+        buf.calibration = true;
+        assert_eq!(buf.capacity(), SAMPLE_NO);
+    }
+
+    #[test]
+    fn calibration_capture() {
+        use super::*;
+
+        let mut buf = ImuBuf::new(208.);
+        buf.calibration = true;
+
+        let g = SENSORS_GRAVITY_STANDARD;
+        buf.sample([0.1, 0., 0.], [0.1, -0.2, g]).unwrap();
+
+        // Every sample is stored, in the body frame and with gravity.
+        assert_eq!(buf.len(), 1);
+        assert!((A16::from_u16(buf.axl[0]).to_f32() - 0.1).abs() < 1.0e-3);
+        assert!((A16::from_u16(buf.axl[1]).to_f32() + 0.2).abs() < 1.0e-3);
+        assert!((A16::from_u16(buf.axl[2]).to_f32() - g as f32).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn double_buffer() {
+        use super::*;
+
+        let mut buf = ImuBuf::new(208.);
+        buf.calibration = true;
+
+        let cap = buf.capacity();
+        for _ in 0..cap {
+            buf.sample([0.; 3], [0.1, 0., 0.]).unwrap();
+        }
+        assert!(buf.sample([0.; 3], [0.1, 0., 0.]).is_err());
+
+        // Samples go to the second buffer while the full buffer waits to be taken.
+        buf.close();
+        for _ in 0..10 {
+            buf.sample([0.; 3], [0.5, 0., 0.]).unwrap();
+        }
+        assert!(buf.is_pending());
+        assert_eq!((buf.len(), buf.next_len(), buf.spill()), (cap, 10, 10));
+
+        let (b, ..) = buf.take_buf();
+        assert_eq!(b.len(), cap * SAMPLE_SZ);
+        assert!((A16::from_u16(b[0]).to_f32() - 0.1).abs() < 1.0e-3);
+
+        assert!(!buf.is_pending());
+        assert_eq!((buf.len(), buf.next_len(), buf.spill()), (10, 0, 0));
+        assert!((A16::from_u16(buf.axl[0]).to_f32() - 0.5).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn clipped() {
+        use super::*;
+
+        let mut buf = ImuBuf::new(208.);
+        buf.set_accel_range(AccelRange::G4);
+
+        let max = raw::ms2(i16::MAX, AccelRange::G4);
+        let min = raw::ms2(i16::MIN, AccelRange::G4);
+
+        buf.sample([0.; 3], [0., 0., SENSORS_GRAVITY_STANDARD])
+            .unwrap();
+        buf.sample([0.; 3], [max, 0., SENSORS_GRAVITY_STANDARD])
+            .unwrap();
+        buf.sample([0.; 3], [0., min, SENSORS_GRAVITY_STANDARD])
+            .unwrap();
+        buf.sample([0.; 3], [0., 0., max * 0.99]).unwrap();
+        assert_eq!(buf.clipped(), 2);
+
+        // Counted for the second buffer while the buffer waits to be taken.
+        buf.close();
+        buf.sample([0.; 3], [0., 0., max]).unwrap();
+        assert_eq!(buf.clipped(), 2);
+
+        buf.take_buf();
+        assert_eq!(buf.clipped(), 1);
+
+        buf.reset();
+        assert_eq!(buf.clipped(), 0);
+    }
+
+    /// Only the vertical axis is filtered and stored, the same as the vertical of all axes.
+    #[test]
+    fn vertical_only() {
+        use super::*;
+
+        let freq = 208.;
+        let g = SENSORS_GRAVITY_STANDARD;
+
+        let mut all = ImuBuf::new(freq as f32);
+        let mut z = ImuBuf::new(freq as f32);
+        z.axes = 0b100;
+
+        let mut i = 0;
+        while !z.is_full() {
+            let t = i as f64 / freq;
+            let a = (2. * core::f64::consts::PI * 0.2 * t).sin();
+            let (g, a) = ([0.01, -0.02, 0.], [0.1 * a, 0.05, g + a]);
+
+            all.sample(g, a).unwrap();
+            z.sample(g, a).unwrap();
+            i += 1;
+        }
+
+        assert_eq!(z.capacity(), all.capacity());
+        assert_eq!(z.len(), all.len());
+        assert_eq!(z.axl.len(), z.len());
+        assert!(z.sample([0.; 3], [0., 0., g]).is_err());
+
+        let v = all.axl.iter().skip(2).step_by(SAMPLE_SZ);
+        assert!(v.eq(z.axl.iter()));
+
+        // The calibration capture stores all axes.
+        z.reset();
+        z.calibration = true;
+        z.sample([0.; 3], [0.1, -0.2, g]).unwrap();
+        assert_eq!((z.len(), z.axl.len()), (1, SAMPLE_SZ));
+    }
+
+    /// One buffer of IMU samples through orientation, rotation, filtering and scaling.
+    #[bench]
+    fn sample_buffer(b: &mut test::Bencher) {
+        use super::*;
+        use crate::axl::SAMPLE_NO;
+
+        let freq = 208.;
+        let g = SENSORS_GRAVITY_STANDARD;
+        let mut buf = ImuBuf::new(freq as f32);
+
+        let samples = (0..SAMPLE_NO)
+            .map(|i| {
+                let t = i as f64 / freq;
+                let a = (2. * core::f64::consts::PI * 0.2 * t).sin();
+                ([0.01, -0.02, 0.], [0.1 * a, 0.05, g + a])
+            })
+            .collect::<std::vec::Vec<_>>();
+
+        b.iter(|| {
+            for (g, a) in &samples {
+                if buf.sample(*g, *a).is_err() {
+                    test::black_box(buf.take_buf());
+                    buf.sample(*g, *a).unwrap();
+                }
+            }
+        });
+    }
+
+    /// A constant offset of the accelerometer is removed to near zero by subtracting the bias
+    /// estimated while the buoy is still (see `bias::StillEstimate`).
+    #[test]
+    fn still_bias_removed() {
+        use super::*;
+        use crate::waves::bias::StillEstimate;
+
+        let freq = 208.;
+        let g = SENSORS_GRAVITY_STANDARD;
+        let a = [0.05, -0.05, g + 0.3];
+
+        let mean_z = |buf: &mut ImuBuf| {
+            let mut z = 0.;
+            for _ in 0..5 {
+                while !buf.is_full() {
+                    buf.sample([0., 0., 0.], a).unwrap();
+                }
+
+                let data = buf.take_buf().0;
+                let v = data
+                    .iter()
+                    .skip(2)
+                    .step_by(SAMPLE_SZ)
+                    .map(|u| A16::from_u16(*u).to_f32() as f64)
+                    .collect::<std::vec::Vec<_>>();
+                z = v.iter().sum::<f64>() / v.len() as f64;
+            }
+            z
+        };
+
+        let mut buf = ImuBuf::new(freq as f32);
+        let offset = mean_z(&mut buf);
+        assert!((offset - 0.3).abs() < 0.05, "{}", offset);
+
+        let mut e = StillEstimate::new(freq as u32);
+        let bias = (0..freq as u32).find_map(|_| e.push(a)).unwrap();
+
+        let mut buf = ImuBuf::new(freq as f32);
+        buf.bias = bias;
+        let z = mean_z(&mut buf);
+        assert!(z.abs() < 0.01, "{}", z);
+    }
+
+    /// A buoy tilted 20 degrees (no rotation) in a 0.2 Hz wave: the vertical acceleration
+    /// rotated into the earth frame should be the wave acceleration, while the naive z-axis of the
+    /// buoy gets a large bias from the tilt.
+    #[test]
+    fn tilted_buoy() {
+        use super::*;
+
+        let freq = 208.;
+        let tilt = 20f64.to_radians();
+        let g = SENSORS_GRAVITY_STANDARD;
+        let amp = 1.0;
+
+        let mut buf = ImuBuf::new(freq as f32);
+        let mut naive = std::vec::Vec::new();
+        let mut z = std::vec::Vec::new();
+        let mut i = 0;
+
+        // The first buffers let the orientation estimate converge, only the last is checked.
+        for _ in 0..11 {
+            naive.clear();
+
+            while !buf.is_full() {
+                let t = i as f64 / freq;
+                let a = amp * (2. * core::f64::consts::PI * 0.2 * t).sin();
+                let a = [0., (g + a) * tilt.sin(), (g + a) * tilt.cos()];
+                naive.push(a[2] - g);
+
+                buf.sample([0., 0., 0.], a).unwrap();
+                i += 1;
+            }
+
+            z = buf
+                .take_buf()
+                .0
+                .iter()
+                .skip(2)
+                .step_by(SAMPLE_SZ)
+                .map(|u| A16::from_u16(*u).to_f32() as f64)
+                .collect();
+        }
+
+        let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+        let std = |v: &[f64]| {
+            let m = mean(v);
+            (v.iter().map(|v| (v - m).powi(2)).sum::<f64>() / v.len() as f64).sqrt()
+        };
+
+        println!(
+            "rotated: mean: {}, std: {}, naive z: mean: {}, std: {}",
+            mean(&z),
+            std(&z),
+            mean(&naive),
+            std(&naive)
+        );
+
+        // The naive z-axis is biased by (cos(tilt) - 1) * g, about -0.59 m/s^2.
+        assert!((mean(&naive) - (tilt.cos() - 1.) * g).abs() < 0.1);
+
+        assert!(mean(&z).abs() < 0.15);
+        assert!(mean(&z).abs() < mean(&naive).abs() / 4.);
+        assert!((std(&z) - amp / 2f64.sqrt()).abs() < 0.1);
     }
 }
@@ -0,0 +1,211 @@
+//! On-chip digital low-pass filters of the IMU.
+//!
+//! The ISM330DHCX filters the samples before they are put in the FIFO, ahead of the FIR
+//! anti-aliasing filter and decimation (`fir`):
+//!
+//! * Accelerometer: LPF1 at ODR/2 is always on. LPF2 (`LPF2_XL_EN` in `CTRL1_XL`) is selected
+//!   with `HPCF_XL[2:0]` in `CTRL8_XL` as a fraction of the ODR.
+//! * Gyroscope: LPF2 is always on and fixed by the ODR. LPF1 (`LPF1_SEL_G` in `CTRL4_C`) can be
+//!   added, with the bandwidth `FTYPE[2:0]` in `CTRL6_C` (see the table of gyroscope bandwidths in
+//!   the datasheet, it depends on the ODR).
+//!
+//! The registers are not covered by the driver, so they are written directly (read-modify-write
+//! keeping the other bits). The selected filters are recorded in the data notes.
+
+use core::sync::atomic::{AtomicI8, AtomicU16, Ordering};
+
+pub const CTRL4_C: u8 = 0x13;
+pub const CTRL6_C: u8 = 0x15;
+pub const CTRL8_XL: u8 = 0x17;
+
+const LPF1_SEL_G: u8 = 1 << 1;
+const FTYPE_MASK: u8 = 0b0000_0111;
+const HPCF_XL_SHIFT: u8 = 5;
+const HPCF_XL_MASK: u8 = 0b1110_0000;
+
+/// `HP_SLOPE_XL_EN` selects the high-pass path, it must be cleared for the low-pass filter.
+const HP_SLOPE_XL_EN: u8 = 1 << 2;
+
+/// Bandwidth of the accelerometer as a fraction of the ODR.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccelLpf {
+    /// LPF1 only.
+    Odr2,
+    Odr4,
+    Odr10,
+    Odr20,
+    Odr45,
+    Odr100,
+    Odr200,
+    Odr400,
+    Odr800,
+}
+
+impl AccelLpf {
+    /// Bandwidth is ODR divided by this.
+    pub const fn divisor(&self) -> u16 {
+        use AccelLpf::*;
+
+        match self {
+            Odr2 => 2,
+            Odr4 => 4,
+            Odr10 => 10,
+            Odr20 => 20,
+            Odr45 => 45,
+            Odr100 => 100,
+            Odr200 => 200,
+            Odr400 => 400,
+            Odr800 => 800,
+        }
+    }
+
+    /// Bandwidth at `odr` [Hz].
+    pub fn bandwidth(&self, odr: f32) -> f32 {
+        odr / self.divisor() as f32
+    }
+
+    /// LPF2 is enabled (`LPF2_XL_EN`).
+    pub const fn lpf2(&self) -> bool {
+        !matches!(self, AccelLpf::Odr2)
+    }
+
+    /// `HPCF_XL[2:0]`.
+    pub const fn hpcf(&self) -> u8 {
+        use AccelLpf::*;
+
+        match self {
+            Odr2 | Odr4 => 0b000,
+            Odr10 => 0b001,
+            Odr20 => 0b010,
+            Odr45 => 0b011,
+            Odr100 => 0b100,
+            Odr200 => 0b101,
+            Odr400 => 0b110,
+            Odr800 => 0b111,
+        }
+    }
+
+    /// `CTRL8_XL` with the bandwidth set, the other bits of `reg` are kept.
+    pub const fn ctrl8xl(&self, reg: u8) -> u8 {
+        (reg & !(HPCF_XL_MASK | HP_SLOPE_XL_EN)) | (self.hpcf() << HPCF_XL_SHIFT)
+    }
+}
+
+/// Gyroscope LPF1 bandwidth (`FTYPE`), or LPF1 off.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GyroLpf {
+    Off,
+    Ftype0,
+    Ftype1,
+    Ftype2,
+    Ftype3,
+    Ftype4,
+    Ftype5,
+    Ftype6,
+    Ftype7,
+}
+
+impl GyroLpf {
+    /// `FTYPE[2:0]`, `None` when LPF1 is off.
+    pub const fn ftype(&self) -> Option<u8> {
+        use GyroLpf::*;
+
+        match self {
+            Off => None,
+            Ftype0 => Some(0),
+            Ftype1 => Some(1),
+            Ftype2 => Some(2),
+            Ftype3 => Some(3),
+            Ftype4 => Some(4),
+            Ftype5 => Some(5),
+            Ftype6 => Some(6),
+            Ftype7 => Some(7),
+        }
+    }
+
+    /// Recorded in the data notes: `FTYPE`, or `-1` when LPF1 is off.
+    pub const fn code(&self) -> i8 {
+        match self.ftype() {
+            Some(f) => f as i8,
+            None => -1,
+        }
+    }
+
+    /// `CTRL4_C` with `LPF1_SEL_G` set, the other bits of `reg` are kept.
+    pub const fn ctrl4c(&self, reg: u8) -> u8 {
+        match self.ftype() {
+            Some(_) => reg | LPF1_SEL_G,
+            None => reg & !LPF1_SEL_G,
+        }
+    }
+
+    /// `CTRL6_C` with `FTYPE` set, the other bits of `reg` are kept.
+    pub const fn ctrl6c(&self, reg: u8) -> u8 {
+        match self.ftype() {
+            Some(f) => (reg & !FTYPE_MASK) | f,
+            None => reg,
+        }
+    }
+}
+
+static ACCEL_LPF: AtomicU16 = AtomicU16::new(AccelLpf::Odr4.divisor());
+static GYRO_LPF: AtomicI8 = AtomicI8::new(GyroLpf::Off.code());
+
+/// Record the filters configured in the IMU.
+pub fn set_current(accel: AccelLpf, gyro: GyroLpf) {
+    ACCEL_LPF.store(accel.divisor(), Ordering::Relaxed);
+    GYRO_LPF.store(gyro.code(), Ordering::Relaxed);
+}
+
+/// Divisor of the accelerometer bandwidth configured in the IMU.
+pub fn accel_lpf() -> u16 {
+    ACCEL_LPF.load(Ordering::Relaxed)
+}
+
+/// Gyroscope LPF1 configured in the IMU (see [`GyroLpf::code`]).
+pub fn gyro_lpf() -> i8 {
+    GYRO_LPF.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accel_encoding() {
+        assert_eq!(AccelLpf::Odr4.ctrl8xl(0), 0b0000_0000);
+        assert_eq!(AccelLpf::Odr10.ctrl8xl(0), 0b0010_0000);
+        assert_eq!(AccelLpf::Odr800.ctrl8xl(0), 0b1110_0000);
+
+        // Other bits are kept, and the high-pass path is cleared.
+        assert_eq!(AccelLpf::Odr20.ctrl8xl(0b1010_0111), 0b0100_0011);
+
+        assert!(!AccelLpf::Odr2.lpf2());
+        assert!(AccelLpf::Odr4.lpf2());
+
+        assert_eq!(AccelLpf::Odr4.bandwidth(208.), 52.);
+    }
+
+    #[test]
+    fn gyro_encoding() {
+        assert_eq!(GyroLpf::Off.ctrl4c(0b0000_0110), 0b0000_0100);
+        assert_eq!(GyroLpf::Off.ctrl6c(0b1000_0101), 0b1000_0101);
+
+        assert_eq!(GyroLpf::Ftype5.ctrl4c(0b0000_0100), 0b0000_0110);
+        assert_eq!(GyroLpf::Ftype5.ctrl6c(0b1000_0010), 0b1000_0101);
+
+        assert_eq!(GyroLpf::Off.code(), -1);
+        assert_eq!(GyroLpf::Ftype0.code(), 0);
+    }
+
+    #[test]
+    fn parse() {
+        let a: AccelLpf = serde_json_core::from_str(r#""odr45""#).unwrap().0;
+        assert_eq!(a, AccelLpf::Odr45);
+
+        let g: GyroLpf = serde_json_core::from_str(r#""ftype3""#).unwrap().0;
+        assert_eq!(g, GyroLpf::Ftype3);
+    }
+}
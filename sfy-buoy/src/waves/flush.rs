@@ -0,0 +1,108 @@
+//! When the buffer of samples is flushed to a package.
+//!
+//! By default a package is flushed when the buffer is full (`axl::SAMPLE_NO` samples). Some
+//! experiments need a fixed number of samples per package, or packages on a time boundary. A
+//! package flushed before the buffer is full is partial: it holds the actual number of samples
+//! (`AxlPacket::samples`), and is otherwise handled like a full package.
+
+use crate::config::Config;
+
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
+pub enum FlushPolicy {
+    /// Flush when the buffer is full.
+    OnFull,
+
+    /// Flush every `n` samples (clamped to the size of the buffer).
+    EveryNSamples(usize),
+
+    /// Flush every `t` ms since the first sample of the package, or when the buffer is full.
+    EveryMillis(i64),
+}
+
+impl FlushPolicy {
+    pub fn from_config(config: &Config) -> FlushPolicy {
+        match (config.flush_samples, config.flush_interval) {
+            (0, 0) => FlushPolicy::OnFull,
+            (n, 0) => FlushPolicy::EveryNSamples(n as usize),
+            (_, t) => FlushPolicy::EveryMillis(t as i64),
+        }
+    }
+
+    /// Maximum number of samples in a package, for a buffer of `capacity` samples.
+    pub fn limit(&self, capacity: usize) -> usize {
+        match *self {
+            FlushPolicy::EveryNSamples(n) => n.clamp(1, capacity),
+            _ => capacity,
+        }
+    }
+
+    /// Should a buffer with `len` of `capacity` samples, started at `start`, be flushed at `now`
+    /// (ms).
+    pub fn should_flush(&self, len: usize, capacity: usize, start: i64, now: i64) -> bool {
+        if len >= self.limit(capacity) {
+            return true;
+        }
+
+        match *self {
+            FlushPolicy::EveryMillis(t) => len > 0 && now - start >= t,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_full() {
+        let p = FlushPolicy::OnFull;
+
+        assert!(!p.should_flush(1023, 1024, 0, 1_000_000));
+        assert!(p.should_flush(1024, 1024, 0, 0));
+    }
+
+    #[test]
+    fn every_n_samples() {
+        let p = FlushPolicy::EveryNSamples(100);
+
+        assert_eq!(p.limit(1024), 100);
+        assert!(!p.should_flush(99, 1024, 0, 0));
+        assert!(p.should_flush(100, 1024, 0, 0));
+
+        assert_eq!(FlushPolicy::EveryNSamples(5000).limit(1024), 1024);
+    }
+
+    #[test]
+    fn every_millis() {
+        let p = FlushPolicy::EveryMillis(10_000);
+
+        assert!(!p.should_flush(10, 1024, 1000, 10_999));
+        assert!(p.should_flush(10, 1024, 1000, 11_000));
+
+        // Empty packages are not flushed.
+        assert!(!p.should_flush(0, 1024, 1000, 11_000));
+
+        // Full before the time is up.
+        assert!(p.should_flush(1024, 1024, 1000, 2000));
+    }
+
+    #[test]
+    fn config() {
+        let mut c = Config::default();
+        assert_eq!(FlushPolicy::from_config(&c), FlushPolicy::OnFull);
+
+        c.flush_samples = 256;
+        assert_eq!(
+            FlushPolicy::from_config(&c),
+            FlushPolicy::EveryNSamples(256)
+        );
+
+        c.flush_samples = 0;
+        c.flush_interval = 60_000;
+        assert_eq!(
+            FlushPolicy::from_config(&c),
+            FlushPolicy::EveryMillis(60_000)
+        );
+    }
+}
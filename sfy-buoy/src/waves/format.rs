@@ -0,0 +1,343 @@
+//! Format of the words in the FIFO of the IMU.
+//!
+//! Every word of the FIFO of the ISM330DHCX is 7 bytes: a tag (`TAG_SENSOR[4:0]` in bits 7:3,
+//! followed by the counter and the parity) and 6 bytes of data. Which sensors are batched into the
+//! FIFO is set in `FIFO_CTRL3`, `FIFO_CTRL4` and `CTRL10_C`. The format (`fifo_format` in the
+//! config) is the single description of the FIFO: the IMU is configured from it when the FIFO is
+//! enabled, and the words are decoded by it in `Waves::read_and_filter`.
+//!
+//! * `accel_gyro` (default): the gyroscope and the accelerometer at the IMU rate, in pairs (see
+//!   `gap`).
+//! * `accel_gyro_temperature`: the die temperature batched in between at 12.5 Hz.
+//! * `accel_gyro_timestamp`: the timestamp of the IMU batched every 8 samples.
+//!
+//! The temperature and timestamp words are decoded and passed over, the samples are timed and the
+//! temperature recorded as before. The compressed ("packed") words of the FIFO are not supported,
+//! every format is uncompressed.
+//!
+//! The two can not diverge silently: the registers are read back after the FIFO is enabled and
+//! checked against the format ([`FifoFormat::check`]), and a word with a tag that is not part of
+//! the format is an error ([`FifoFormat::decode`]). Both errors reset the IMU.
+
+use super::raw::{ms2, rads, GyroRange};
+use crate::config::AccelRange;
+
+/// `BDR_GY[3:0]` and `BDR_XL[3:0]`.
+pub const FIFO_CTRL3: u8 = 0x09;
+
+/// `DEC_TS_BATCH[1:0]`, `ODR_T_BATCH[1:0]` and `FIFO_MODE[2:0]`.
+pub const FIFO_CTRL4: u8 = 0x0a;
+
+/// `TIMESTAMP_EN`.
+pub const CTRL10_C: u8 = 0x19;
+
+/// First byte of the word at the head of the FIFO, the word is popped when the 7 bytes are read.
+pub const FIFO_DATA_OUT_TAG: u8 = 0x78;
+
+/// Size of a word of the FIFO [bytes].
+pub const WORD_SZ: usize = 7;
+
+pub type Word = [u8; WORD_SZ];
+
+/// `TAG_SENSOR`.
+pub mod tag {
+    pub const GYRO: u8 = 0x01;
+    pub const ACCEL: u8 = 0x02;
+    pub const TEMPERATURE: u8 = 0x03;
+    pub const TIMESTAMP: u8 = 0x04;
+}
+
+const TIMESTAMP_EN: u8 = 1 << 5;
+const DEC_TS_BATCH_SHIFT: u8 = 6;
+const ODR_T_BATCH_SHIFT: u8 = 4;
+const BATCH_MASK: u8 = 0b1111_0000;
+
+/// `ODR_T_BATCH` for 12.5 Hz.
+const ODR_T_BATCH_12HZ5: u8 = 0b10;
+
+/// `DEC_TS_BATCH` for every 8th batch.
+const DEC_TS_BATCH_8: u8 = 0b10;
+
+/// Rate of the temperature in the FIFO [Hz].
+pub const TEMPERATURE_FREQ: f32 = 12.5;
+
+/// Samples per timestamp in the FIFO.
+pub const TIMESTAMP_DECIMATION: u8 = 8;
+
+/// Sensors batched into the FIFO (`fifo_format` in the config).
+#[derive(
+    serde::Serialize, serde::Deserialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FifoFormat {
+    /// Gyroscope and accelerometer.
+    #[default]
+    AccelGyro,
+
+    /// Gyroscope and accelerometer, and the temperature at `TEMPERATURE_FREQ`.
+    AccelGyroTemperature,
+
+    /// Gyroscope and accelerometer, and a timestamp every `TIMESTAMP_DECIMATION` samples.
+    AccelGyroTimestamp,
+}
+
+/// A decoded word of the FIFO.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entry {
+    /// Angular rate [rad/s].
+    Gyro([f64; 3]),
+
+    /// Acceleration [m/s^2].
+    Accel([f64; 3]),
+
+    /// Die temperature [C].
+    Temperature(f32),
+
+    /// Timestamp of the IMU [25 us].
+    Timestamp(u32),
+}
+
+impl FifoFormat {
+    /// The format batches the sensor of `tag`.
+    pub const fn has(&self, tag: u8) -> bool {
+        use FifoFormat::*;
+
+        match tag {
+            tag::GYRO | tag::ACCEL => true,
+            tag::TEMPERATURE => matches!(self, AccelGyroTemperature),
+            tag::TIMESTAMP => matches!(self, AccelGyroTimestamp),
+            _ => false,
+        }
+    }
+
+    /// `DEC_TS_BATCH` and `ODR_T_BATCH` bits of `FIFO_CTRL4`.
+    const fn batch(&self) -> u8 {
+        use FifoFormat::*;
+
+        match self {
+            AccelGyro => 0,
+            AccelGyroTemperature => ODR_T_BATCH_12HZ5 << ODR_T_BATCH_SHIFT,
+            AccelGyroTimestamp => DEC_TS_BATCH_8 << DEC_TS_BATCH_SHIFT,
+        }
+    }
+
+    /// `FIFO_CTRL4` with the batching of the format set, the other bits of `reg` (the FIFO mode)
+    /// are kept.
+    pub const fn fifo_ctrl4(&self, reg: u8) -> u8 {
+        (reg & !BATCH_MASK) | self.batch()
+    }
+
+    /// `CTRL10_C` with `TIMESTAMP_EN` set for the timestamps, the other bits of `reg` are kept.
+    pub const fn ctrl10c(&self, reg: u8) -> u8 {
+        match self {
+            FifoFormat::AccelGyroTimestamp => reg | TIMESTAMP_EN,
+            _ => reg & !TIMESTAMP_EN,
+        }
+    }
+
+    /// The registers read back from the IMU configure the FIFO for this format: the gyroscope and
+    /// the accelerometer are batched at the same rate, and only the temperature or timestamp of
+    /// the format.
+    pub const fn check(&self, fifo_ctrl3: u8, fifo_ctrl4: u8, ctrl10c: u8) -> bool {
+        let (gy, xl) = (fifo_ctrl3 >> 4, fifo_ctrl3 & 0x0f);
+
+        gy != 0
+            && gy == xl
+            && fifo_ctrl4 & BATCH_MASK == self.batch()
+            && ((ctrl10c & TIMESTAMP_EN != 0) || !matches!(self, FifoFormat::AccelGyroTimestamp))
+    }
+
+    /// Words in the FIFO per sample (one gyroscope and one accelerometer word) at the IMU rate
+    /// `freq` [Hz].
+    pub fn words_per_sample(&self, freq: f32) -> f32 {
+        use FifoFormat::*;
+
+        match self {
+            AccelGyro => 2.,
+            AccelGyroTemperature => 2. + TEMPERATURE_FREQ / freq,
+            AccelGyroTimestamp => 2. + 1. / TIMESTAMP_DECIMATION as f32,
+        }
+    }
+
+    /// Samples in `words` words of the FIFO at the IMU rate `freq` [Hz].
+    pub fn samples(&self, words: u16, freq: f32) -> u16 {
+        match self {
+            FifoFormat::AccelGyro => words / 2,
+            _ => (words as f32 / self.words_per_sample(freq)) as u16,
+        }
+    }
+
+    /// Decode a word of the FIFO, fails with the tag of a word that is not part of the format.
+    pub fn decode(&self, w: &Word, accel: AccelRange, gyro: GyroRange) -> Result<Entry, u8> {
+        let tag = w[0] >> 3;
+        if !self.has(tag) {
+            return Err(tag);
+        }
+
+        let i = |k: usize| i16::from_le_bytes([w[1 + 2 * k], w[2 + 2 * k]]);
+
+        Ok(match tag {
+            tag::GYRO => Entry::Gyro(core::array::from_fn(|k| rads(i(k), gyro))),
+            tag::ACCEL => Entry::Accel(core::array::from_fn(|k| ms2(i(k), accel))),
+            tag::TEMPERATURE => Entry::Temperature(i(0) as f32 / 256. + 25.),
+            _ => Entry::Timestamp(u32::from_le_bytes([w[1], w[2], w[3], w[4]])),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waves::{raw_to_ms2, raw_to_rads};
+
+    const A: AccelRange = AccelRange::G4;
+    const G: GyroRange = GyroRange::Dps125;
+
+    /// Word with `tag` (counter 2 and parity bit set) and three counts.
+    fn word(tag: u8, v: [i16; 3]) -> Word {
+        let mut w = [(tag << 3) | 0b101, 0, 0, 0, 0, 0, 0];
+        for (k, v) in v.iter().enumerate() {
+            w[1 + 2 * k..3 + 2 * k].copy_from_slice(&v.to_le_bytes());
+        }
+        w
+    }
+
+    /// Decode a stream of bytes as read from the FIFO.
+    fn decode(f: FifoFormat, bytes: &[u8]) -> Vec<Result<Entry, u8>> {
+        bytes
+            .chunks(WORD_SZ)
+            .map(|w| f.decode(w.try_into().unwrap(), A, G))
+            .collect()
+    }
+
+    fn close(a: [f64; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (*a as f32 - b).abs() < 1.0e-6)
+    }
+
+    #[test]
+    fn accel_gyro() {
+        let bytes = [
+            word(tag::GYRO, [1, -1, 100]),
+            word(tag::ACCEL, [0, 8196, -8196]),
+            word(tag::GYRO, [i16::MIN, 0, i16::MAX]),
+            word(tag::ACCEL, [1, 2, 3]),
+        ]
+        .concat();
+
+        let e = decode(FifoFormat::AccelGyro, &bytes);
+        assert_eq!(e.len(), 4);
+
+        match (e[0], e[1], e[2]) {
+            (Ok(Entry::Gyro(g)), Ok(Entry::Accel(a)), Ok(Entry::Gyro(g2))) => {
+                assert!(close(g, [1, -1, 100].map(|c| raw_to_rads(c, G))));
+                assert!(close(a, [0, 8196, -8196].map(|c| raw_to_ms2(c, A))));
+                assert!((a[1] - 9.80665).abs() < 0.01);
+                assert!(close(
+                    g2,
+                    [i16::MIN, 0, i16::MAX].map(|c| raw_to_rads(c, G))
+                ));
+            }
+            e => panic!("{:?}", e),
+        }
+
+        // Words of the other formats are rejected.
+        assert_eq!(
+            FifoFormat::AccelGyro.decode(&word(tag::TEMPERATURE, [0; 3]), A, G),
+            Err(tag::TEMPERATURE)
+        );
+        assert_eq!(
+            FifoFormat::AccelGyro.decode(&word(tag::TIMESTAMP, [0; 3]), A, G),
+            Err(tag::TIMESTAMP)
+        );
+
+        // Compressed gyroscope (NC_T_1).
+        assert_eq!(
+            FifoFormat::AccelGyro.decode(&word(0x07, [0; 3]), A, G),
+            Err(0x07)
+        );
+    }
+
+    #[test]
+    fn accel_gyro_temperature() {
+        let bytes = [
+            word(tag::GYRO, [0; 3]),
+            word(tag::TEMPERATURE, [-1280, 0, 0]),
+            word(tag::ACCEL, [0; 3]),
+            word(tag::TEMPERATURE, [768, 0, 0]),
+        ]
+        .concat();
+
+        let e = decode(FifoFormat::AccelGyroTemperature, &bytes);
+        assert_eq!(e[1], Ok(Entry::Temperature(20.)));
+        assert_eq!(e[2], Ok(Entry::Accel([0.; 3])));
+        assert_eq!(e[3], Ok(Entry::Temperature(28.)));
+
+        assert!(decode(FifoFormat::AccelGyroTimestamp, &bytes)[1].is_err());
+    }
+
+    #[test]
+    fn accel_gyro_timestamp() {
+        let mut ts = word(tag::TIMESTAMP, [0; 3]);
+        ts[1..5].copy_from_slice(&0x0102_0304u32.to_le_bytes());
+
+        let bytes = [word(tag::ACCEL, [0; 3]), ts, word(tag::GYRO, [0; 3])].concat();
+
+        let e = decode(FifoFormat::AccelGyroTimestamp, &bytes);
+        assert_eq!(e[1], Ok(Entry::Timestamp(0x0102_0304)));
+        assert_eq!(e[2], Ok(Entry::Gyro([0.; 3])));
+
+        assert!(decode(FifoFormat::AccelGyroTemperature, &bytes)[1].is_err());
+    }
+
+    #[test]
+    fn registers() {
+        use FifoFormat::*;
+
+        // FIFO mode (0b001) is kept.
+        assert_eq!(AccelGyro.fifo_ctrl4(0b1011_0001), 0b0000_0001);
+        assert_eq!(AccelGyroTemperature.fifo_ctrl4(0b0000_0001), 0b0010_0001);
+        assert_eq!(AccelGyroTimestamp.fifo_ctrl4(0b0011_0001), 0b1000_0001);
+
+        assert_eq!(AccelGyroTimestamp.ctrl10c(0b0000_0100), 0b0010_0100);
+        assert_eq!(AccelGyro.ctrl10c(0b0010_0100), 0b0000_0100);
+
+        // 208 Hz for both sensors.
+        let ctrl3 = 0x55;
+
+        for f in [AccelGyro, AccelGyroTemperature, AccelGyroTimestamp] {
+            assert!(f.check(ctrl3, f.fifo_ctrl4(0b001), f.ctrl10c(0)));
+
+            // The sensor configured for another format.
+            for o in [AccelGyro, AccelGyroTemperature, AccelGyroTimestamp] {
+                if o != f {
+                    assert!(!f.check(ctrl3, o.fifo_ctrl4(0b001), o.ctrl10c(0)));
+                }
+            }
+
+            // Accelerometer not batched, or at another rate.
+            assert!(!f.check(0x50, f.fifo_ctrl4(0), f.ctrl10c(0)));
+            assert!(!f.check(0x54, f.fifo_ctrl4(0), f.ctrl10c(0)));
+        }
+
+        // Timestamps batched, but the timestamp counter not running.
+        assert!(!AccelGyroTimestamp.check(ctrl3, AccelGyroTimestamp.fifo_ctrl4(0), 0));
+    }
+
+    #[test]
+    fn samples() {
+        use FifoFormat::*;
+
+        assert_eq!(AccelGyro.samples(17, 208.), 8);
+        assert_eq!(AccelGyroTimestamp.samples(17, 208.), 8);
+        assert_eq!(AccelGyroTemperature.samples(2 * 208 + 13, 208.), 208);
+    }
+
+    #[test]
+    fn parse() {
+        let f: FifoFormat = serde_json_core::from_str(r#""accel_gyro_temperature""#)
+            .unwrap()
+            .0;
+        assert_eq!(f, FifoFormat::AccelGyroTemperature);
+        assert_eq!(FifoFormat::default(), FifoFormat::AccelGyro);
+    }
+}
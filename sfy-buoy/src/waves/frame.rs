@@ -0,0 +1,85 @@
+//! Frame of reference of the acceleration in a package.
+//!
+//! Which transformations were applied on the buoy depends on the configuration and the kind of
+//! package, and samples in different frames must not be mixed in an analysis. The frame is
+//! recorded in every package (`AxlPacket::frame`) as one of:
+//!
+//! * `sensor`: unfiltered acceleration in the body frame of the IMU, with gravity. These are the
+//!   calibration capture packages (see `calibration`).
+//! * `earth`: rotated to the earth frame by the orientation filter, with standard gravity
+//!   subtracted from the vertical (see `buf`).
+//! * `corrected`: as `earth`, and corrected for the lever arm of an IMU away from the center of
+//!   buoyancy (see `lever_arm`).
+//!
+//! The offset removal (`bias`) is recorded separately, and does not change the frame.
+//!
+//! Packages from before the frame was recorded (format version 11 and older) get the frame from
+//! the calibration step: `sensor` for calibration packages, `earth` otherwise. The lever-arm
+//! correction was not recorded in those packages.
+
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Frame {
+    Sensor,
+    Earth,
+    Corrected,
+}
+
+impl Frame {
+    /// Frame of a package captured in calibration step `calibration` (`0` for normal packages),
+    /// with or without the lever-arm correction.
+    pub const fn of(calibration: u8, lever_arm: bool) -> Frame {
+        match (calibration, lever_arm) {
+            (0, false) => Frame::Earth,
+            (0, true) => Frame::Corrected,
+            _ => Frame::Sensor,
+        }
+    }
+
+    /// Recorded in the packages, `0` is not used so that a missing field can be told apart.
+    pub const fn code(&self) -> u8 {
+        match self {
+            Frame::Sensor => 1,
+            Frame::Earth => 2,
+            Frame::Corrected => 3,
+        }
+    }
+
+    pub const fn from_code(code: u8) -> Option<Frame> {
+        match code {
+            1 => Some(Frame::Sensor),
+            2 => Some(Frame::Earth),
+            3 => Some(Frame::Corrected),
+            _ => None,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Frame::Sensor => "sensor",
+            Frame::Earth => "earth",
+            Frame::Corrected => "corrected",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        for f in [Frame::Sensor, Frame::Earth, Frame::Corrected] {
+            assert_eq!(Frame::from_code(f.code()), Some(f));
+        }
+        assert_eq!(Frame::from_code(0), None);
+    }
+
+    #[test]
+    fn of_package() {
+        assert_eq!(Frame::of(0, false), Frame::Earth);
+        assert_eq!(Frame::of(0, true), Frame::Corrected);
+        assert_eq!(Frame::of(3, false), Frame::Sensor);
+        assert_eq!(Frame::of(3, true), Frame::Sensor);
+    }
+}
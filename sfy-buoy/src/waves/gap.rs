@@ -0,0 +1,251 @@
+//! Filling of short gaps in the FIFO of the IMU.
+//!
+//! The gyroscope and the accelerometer are batched into the FIFO at the same rate, so the words
+//! of the FIFO come in pairs of one gyroscope and one accelerometer sample, always in the same
+//! order. When a word is missing, two words of the same sensor follow each other and the sensor
+//! of the missing word has a gap of one sample. The missing samples are interpolated linearly
+//! between the complete samples on either side of the gap, as long as the gap is no longer than
+//! `max_gap` samples (see `Config::max_gap`). The interpolated samples are marked in the package
+//! (`AxlPacket::filled`), so that they can be told apart from measured samples.
+//!
+//! Longer gaps, and gaps before the first complete sample, are not filled: they are reported as
+//! errors and the IMU is reset, leaving a discontinuity between the packages. With `max_gap` set
+//! to `0` every gap is an error.
+
+use heapless::{Deque, Vec};
+
+/// Maximum number of consecutive samples that can be filled.
+pub const MAX_GAP: usize = 8;
+
+/// Default `max_gap`.
+pub const DEFAULT_MAX_GAP: u8 = 2;
+
+/// A word of the FIFO.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Word {
+    Gyro([f64; 3]),
+    Accel([f64; 3]),
+}
+
+impl Word {
+    fn is_gyro(&self) -> bool {
+        matches!(self, Word::Gyro(_))
+    }
+}
+
+/// A sample of gyroscope and accelerometer, `filled` if one of them was interpolated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub g: [f64; 3],
+    pub a: [f64; 3],
+    pub filled: bool,
+}
+
+pub struct GapFill {
+    max_gap: usize,
+
+    /// First word of a pair, waiting for the second.
+    pending: Option<Word>,
+
+    /// The leading sensor of the pairs (`true` for the gyroscope), from the first complete pair.
+    lead: Option<bool>,
+
+    /// Last complete sample.
+    last: Option<([f64; 3], [f64; 3])>,
+
+    /// Words missing the other sensor since the last complete sample.
+    gap: Vec<Word, MAX_GAP>,
+
+    /// Samples ready to be filtered.
+    ready: Deque<Sample, { MAX_GAP + 1 }>,
+}
+
+fn lerp(x: [f64; 3], y: [f64; 3], t: f64) -> [f64; 3] {
+    core::array::from_fn(|i| x[i] + (y[i] - x[i]) * t)
+}
+
+impl GapFill {
+    /// Fill gaps of up to `max_gap` samples (at most [`MAX_GAP`]).
+    pub fn new(max_gap: u8) -> GapFill {
+        GapFill {
+            max_gap: (max_gap as usize).min(MAX_GAP),
+            pending: None,
+            lead: None,
+            last: None,
+            gap: Vec::new(),
+            ready: Deque::new(),
+        }
+    }
+
+    /// Push the next word of the FIFO. Fails with the length of the gap if the gap cannot be
+    /// filled, the state is then reset.
+    pub fn push(&mut self, w: Word) -> Result<(), usize> {
+        match (self.pending.take(), w) {
+            (Some(Word::Gyro(g)), Word::Accel(a)) => self.complete(true, g, a),
+            (Some(Word::Accel(a)), Word::Gyro(g)) => self.complete(false, g, a),
+            (Some(p), w) => {
+                // The second word of the pair is missing.
+                self.orphan(p)?;
+                self.pending = Some(w);
+                Ok(())
+            }
+            (None, w) if self.lead.map_or(true, |g| g == w.is_gyro()) => {
+                self.pending = Some(w);
+                Ok(())
+            }
+            // The first word of the pair is missing.
+            (None, w) => self.orphan(w),
+        }
+    }
+
+    /// Next sample ready to be filtered, in order.
+    pub fn pop(&mut self) -> Option<Sample> {
+        self.ready.pop_front()
+    }
+
+    /// Forget the words and samples not yet taken, e.g. when the FIFO is reset.
+    pub fn reset(&mut self) {
+        *self = GapFill::new(self.max_gap as u8);
+    }
+
+    fn orphan(&mut self, w: Word) -> Result<(), usize> {
+        if self.gap.len() >= self.max_gap {
+            let n = self.gap.len() + 1;
+            self.reset();
+            return Err(n);
+        }
+
+        self.gap.push(w).ok();
+        Ok(())
+    }
+
+    fn complete(&mut self, gyro_first: bool, g: [f64; 3], a: [f64; 3]) -> Result<(), usize> {
+        self.lead.get_or_insert(gyro_first);
+
+        if !self.gap.is_empty() {
+            let n = self.gap.len();
+            let (lg, la) = match self.last {
+                Some(last) => last,
+                None => {
+                    self.reset();
+                    return Err(n);
+                }
+            };
+
+            for (i, w) in self.gap.iter().enumerate() {
+                let t = (i + 1) as f64 / (n + 1) as f64;
+                let s = match *w {
+                    Word::Gyro(wg) => Sample {
+                        g: wg,
+                        a: lerp(la, a, t),
+                        filled: true,
+                    },
+                    Word::Accel(wa) => Sample {
+                        g: lerp(lg, g, t),
+                        a: wa,
+                        filled: true,
+                    },
+                };
+                self.ready.push_back(s).ok();
+            }
+
+            self.gap.clear();
+        }
+
+        self.ready
+            .push_back(Sample {
+                g,
+                a,
+                filled: false,
+            })
+            .ok();
+        self.last = Some((g, a));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn g(v: f64) -> Word {
+        Word::Gyro([v; 3])
+    }
+
+    fn a(v: f64) -> Word {
+        Word::Accel([v; 3])
+    }
+
+    fn drain(f: &mut GapFill) -> std::vec::Vec<Sample> {
+        core::iter::from_fn(|| f.pop()).collect()
+    }
+
+    #[test]
+    fn complete() {
+        let mut f = GapFill::new(0);
+        for w in [g(0.), a(1.), g(2.), a(3.)] {
+            f.push(w).unwrap();
+        }
+
+        let s = drain(&mut f);
+        assert_eq!(s.len(), 2);
+        assert_eq!(s[1].g, [2.; 3]);
+        assert_eq!(s[1].a, [3.; 3]);
+        assert!(s.iter().all(|s| !s.filled));
+    }
+
+    #[test]
+    fn one_sample_gap() {
+        // The accelerometer misses the second sample.
+        let mut f = GapFill::new(2);
+        for w in [g(0.), a(0.), g(1.), g(2.), a(4.)] {
+            f.push(w).unwrap();
+        }
+
+        let s = drain(&mut f);
+        assert_eq!(s.len(), 3);
+        assert_eq!(
+            s.iter().map(|s| s.filled).collect::<std::vec::Vec<_>>(),
+            [false, true, false]
+        );
+        assert_eq!(s[1].g, [1.; 3]);
+        assert_eq!(s[1].a, [2.; 3]);
+
+        // The gyroscope misses a sample.
+        for w in [a(5.), g(6.), a(6.)] {
+            f.push(w).unwrap();
+        }
+
+        let s = drain(&mut f);
+        assert_eq!(s.len(), 2);
+        assert!(s[0].filled);
+        assert_eq!(s[0].g, [4.; 3]);
+        assert_eq!(s[0].a, [5.; 3]);
+        assert!(!s[1].filled);
+    }
+
+    #[test]
+    fn long_gap() {
+        let mut f = GapFill::new(1);
+        for w in [g(0.), a(0.), g(1.)] {
+            f.push(w).unwrap();
+        }
+        assert_eq!(f.push(g(2.)), Ok(()));
+        assert_eq!(f.push(g(3.)), Err(2));
+
+        // Not filled.
+        let mut f = GapFill::new(0);
+        for w in [g(0.), a(0.), g(1.)] {
+            f.push(w).unwrap();
+        }
+        assert_eq!(f.push(g(2.)), Err(1));
+
+        // Nothing to interpolate from before the first sample.
+        let mut f = GapFill::new(2);
+        f.push(g(0.)).unwrap();
+        f.push(g(1.)).unwrap();
+        assert_eq!(f.push(a(1.)), Err(1));
+        assert_eq!(f.pop(), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Correction of the lever-arm effect of an IMU away from the center of buoyancy.
+//!
+//! The buoy rotates about (roughly) its center of buoyancy, so an IMU at the offset `r` from the
+//! center (body frame, m) measures, in addition to the acceleration of the center:
+//!
+//! ```text
+//! a_imu = a_center + α × r + ω × (ω × r)
+//! ```
+//!
+//! where `ω` is the angular rate from the gyroscope [rad/s] and `α = dω/dt` the angular
+//! acceleration [rad/s^2]. `α × r` is the tangential and `ω × (ω × r)` the centripetal
+//! acceleration. Both are subtracted from every accelerometer sample in the body frame, before the
+//! orientation filter and the rotation to the earth frame (see `buf`). `α` is the backward
+//! difference of consecutive gyroscope samples, and is zero for the first sample after a reset.
+//!
+//! The offset is `lever_arm` in the config (default zero, which disables the correction). The
+//! correction is not applied in the calibration capture.
+
+/// Offset of the IMU from the center of buoyancy.
+#[derive(Debug, Clone)]
+pub struct LeverArm {
+    /// Position of the IMU relative to the center of buoyancy, body frame [m].
+    r: [f64; 3],
+
+    /// Interval between samples [s].
+    dt: f64,
+
+    /// Last angular rate [rad/s].
+    last: Option<[f64; 3]>,
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+impl LeverArm {
+    /// IMU at `r` [m] from the center of buoyancy, sampled at `freq` [Hz].
+    pub fn new(r: [f64; 3], freq: f32) -> LeverArm {
+        LeverArm {
+            r,
+            dt: 1. / freq as f64,
+            last: None,
+        }
+    }
+
+    /// The IMU is at the center, nothing is corrected.
+    pub fn is_zero(&self) -> bool {
+        self.r == [0.; 3]
+    }
+
+    /// Forget the last angular rate, e.g. after a reset of the IMU.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Acceleration of the center of buoyancy from the acceleration `a` [m/s^2] and angular rate
+    /// `w` [rad/s] measured by the IMU.
+    pub fn correct(&mut self, w: [f64; 3], a: [f64; 3]) -> [f64; 3] {
+        if self.is_zero() {
+            return a;
+        }
+
+        let alpha = match self.last.replace(w) {
+            Some(l) => [0, 1, 2].map(|i| (w[i] - l[i]) / self.dt),
+            None => [0.; 3],
+        };
+
+        let tangential = cross(alpha, self.r);
+        let centripetal = cross(w, cross(w, self.r));
+
+        [0, 1, 2].map(|i| a[i] - tangential[i] - centripetal[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn zero_offset() {
+        let mut l = LeverArm::new([0.; 3], 208.);
+        assert!(l.is_zero());
+        assert_eq!(l.correct([0.1, 0.2, 0.3], [1., 2., 3.]), [1., 2., 3.]);
+    }
+
+    #[test]
+    fn spinning() {
+        // Steady rotation about z: only the centripetal acceleration, towards the axis.
+        let mut l = LeverArm::new([0.5, 0., 0.], 208.);
+        let w = [0., 0., 2.];
+
+        l.correct(w, [0.; 3]);
+        let a = l.correct(w, [-2. * 2. * 0.5, 0., 0.]);
+        assert!(a.iter().all(|a| a.abs() < 1.0e-12), "{:?}", a);
+    }
+
+    /// Significant wave height of a monochromatic vertical acceleration `az` at `f` [Hz]: four
+    /// times the standard deviation of the displacement.
+    fn hs(az: &[f64], f: f64) -> f64 {
+        let mean = az.iter().sum::<f64>() / az.len() as f64;
+        let var = az.iter().map(|a| (a - mean).powi(2)).sum::<f64>() / az.len() as f64;
+
+        4. * var.sqrt() / (2. * PI * f).powi(2)
+    }
+
+    /// A buoy heaving with 1 m amplitude and pitching 10 degrees in the same 10 s wave, with the
+    /// IMU 1 m from the center along x.
+    #[test]
+    fn rotating_buoy() {
+        let freq = 208.;
+        let f = 0.1;
+        let w0 = 2. * PI * f;
+        let (heave, pitch) = (1.0, 10f64.to_radians());
+        let r = [1.0, 0., 0.];
+
+        let n = (10. / f * freq) as usize;
+        let mut l = LeverArm::new(r, freq as f32);
+
+        let (mut center, mut measured, mut corrected) = (Vec::new(), Vec::new(), Vec::new());
+
+        for i in 0..n {
+            let t = i as f64 / freq;
+
+            // Acceleration of the center, and the angular rate and acceleration of the pitch.
+            let a = [0., 0., -heave * w0 * w0 * (w0 * t).sin()];
+            let w = [0., pitch * w0 * (w0 * t).cos(), 0.];
+            let alpha = [0., -pitch * w0 * w0 * (w0 * t).sin(), 0.];
+
+            let tangential = cross(alpha, r);
+            let centripetal = cross(w, cross(w, r));
+            let m = [0, 1, 2].map(|i| a[i] + tangential[i] + centripetal[i]);
+
+            center.push(a[2]);
+            measured.push(m[2]);
+            corrected.push(l.correct(w, m)[2]);
+        }
+
+        let expected = hs(&center, f);
+        assert!((expected - 4. * heave / 2f64.sqrt()).abs() < 1.0e-3);
+
+        // The tangential acceleration of the pitch counteracts the heave at the IMU.
+        let uncorrected = hs(&measured, f);
+        assert!(
+            ((uncorrected - expected) / expected).abs() > 0.1,
+            "{} vs {}",
+            uncorrected,
+            expected
+        );
+
+        let corrected = hs(&corrected, f);
+        assert!(
+            ((corrected - expected) / expected).abs() < 0.001,
+            "{} vs {}",
+            corrected,
+            expected
+        );
+    }
+}
@@ -11,16 +11,45 @@ use ism330dhcx::{ctrl1xl, ctrl2g, fifo, fifoctrl, Ism330Dhcx};
 #[cfg(feature = "fir")]
 use static_assertions as sa;
 
-use crate::{axl::AxlPacket, axl::VERSION};
+use crate::axl::{AxlPacket, AxlPacketBuilder, PacketError};
+use crate::clock;
+use crate::config::{AccelRange, Config};
+use crate::filter::{DecimationMode, FirFilter, Pipeline};
+use crate::quality;
 
 #[cfg(feature = "fir")]
 use crate::fir;
 
+pub mod bias;
 mod buf;
-mod wire;
-
+pub mod dlpf;
+pub mod flush;
+pub mod format;
+pub mod frame;
+pub mod gap;
+pub mod lever_arm;
+pub mod raw;
+#[cfg(feature = "redundant-imu")]
+pub mod redundant;
+pub mod registers;
+pub mod retry;
+pub mod self_test;
+#[cfg(any(test, feature = "host-tests"))]
+pub mod synthetic;
+pub mod thermal;
+pub mod wire;
+
+pub use bias::BiasRemoval;
 use buf::ImuBuf;
-pub use buf::{VecAxl, VecRawAxl, RAW_AXL_BYTE_SZ, RAW_AXL_SZ};
+pub use buf::{VecAxl, VecRawAxl, RAW_AXL_BYTE_SZ, RAW_AXL_SZ, SENSORS_GRAVITY_STANDARD};
+pub use dlpf::{AccelLpf, GyroLpf};
+pub use flush::FlushPolicy;
+pub use format::FifoFormat;
+pub use frame::Frame;
+pub use raw::{raw_to_ms2, raw_to_rads, GyroRange, GYRO_RANGE};
+pub use registers::Registers;
+pub use retry::{Exhausted, Retry};
+pub use self_test::SelfTest;
 
 #[cfg(feature = "raw")]
 pub type AxlPacketT = (AxlPacket, VecRawAxl);
@@ -33,15 +62,32 @@ pub const FREQ: Freq = Freq::Hz208;
 #[cfg(all(feature = "20Hz", not(feature = "fir")))]
 compile_error!("Feature 20Hz requires feature fir");
 
+/// Decimation from the IMU rate (`FREQ`) to the output rate: the output rate is the highest rate
+/// within the cut-off of the FIR filter (`fir::CUTOFF`), or the IMU rate without the `fir`
+/// feature. Change together with `FREQ`.
 #[cfg(feature = "fir")]
-pub const OUTPUT_FREQ: f32 = fir::OUT_FREQ;
+pub const DECIMATION: u8 = (FREQ.value() / fir::CUTOFF / 2.) as u8;
 
 #[cfg(not(feature = "fir"))]
-pub const OUTPUT_FREQ: f32 = FREQ.value();
+pub const DECIMATION: u8 = 1;
+
+/// Output rate after decimation by the default `DECIMATION`. The decimation can be set in the
+/// config (`decimation`), the output rate in use is [`Waves::output_freq`].
+pub const OUTPUT_FREQ: f32 = FREQ.value() / DECIMATION as f32;
+
+/// Maximum decimation that can be set in the config (13 Hz output rate).
+pub const MAX_DECIMATION: u8 = 16;
 
 #[cfg(feature = "fir")]
 sa::const_assert_eq!(FREQ.value(), fir::FREQ);
 
+// The FIR filter is designed for this decimation.
+#[cfg(feature = "fir")]
+sa::const_assert_eq!(DECIMATION, fir::DECIMATE);
+
+// The output rate is an integer rate.
+sa::const_assert_eq!(FREQ.value() as u32 % DECIMATION as u32, 0);
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Freq {
     Hz26,
@@ -117,6 +163,36 @@ impl Freq {
     }
 }
 
+impl AccelRange {
+    pub fn fs_xl(&self) -> ctrl1xl::Fs_Xl {
+        use ctrl1xl::Fs_Xl;
+        use AccelRange::*;
+
+        match self {
+            G2 => Fs_Xl::G2,
+            G4 => Fs_Xl::G4,
+            G8 => Fs_Xl::G8,
+            G16 => Fs_Xl::G16,
+        }
+    }
+}
+
+impl GyroRange {
+    pub fn fs(&self) -> ctrl2g::Fs {
+        use ctrl2g::Fs;
+        use GyroRange::*;
+
+        match self {
+            Dps125 => Fs::Dps125,
+            Dps250 => Fs::Dps250,
+            Dps500 => Fs::Dps500,
+            Dps1000 => Fs::Dps1000,
+            Dps2000 => Fs::Dps2000,
+            Dps4000 => Fs::Dps4000,
+        }
+    }
+}
+
 /// The installed IMU.
 pub type IMU = Ism330Dhcx;
 
@@ -124,11 +200,72 @@ pub struct Waves<I2C: WriteRead + Write> {
     pub i2c: I2C,
     pub imu: IMU,
     pub freq: Freq,
-    pub output_freq: f32,
+    pub accel_range: AccelRange,
+    pub accel_lpf: AccelLpf,
+    pub gyro_lpf: GyroLpf,
+
+    /// Removal of the acceleration offset (see `bias`).
+    pub bias_removal: BiasRemoval,
+
+    /// Accelerometer bias in the body frame [m/s^2], subtracted with `BiasRemoval::Calibration`.
+    pub accel_bias: [f32; 3],
+
+    /// Accelerometer bias and scale by temperature, used instead of `accel_bias` when not empty
+    /// (see `thermal`).
+    pub accel_thermal: thermal::Table,
+
+    /// Running estimate of the bias with `BiasRemoval::Still`, `None` once it is applied.
+    still: Option<bias::StillEstimate>,
+
+    /// Estimated bias, applied when the buffer is taken.
+    still_bias: Option<[f64; 3]>,
+
+    /// I2C address of IMU.
+    pub address: u8,
 
     /// Buffer with values ready to be sent.
     buf: ImuBuf,
 
+    /// Pairing of the words of the FIFO, filling short gaps (see `gap`).
+    gaps: gap::GapFill,
+
+    /// Sensors batched into the FIFO, and the decoding of its words (see `format`).
+    pub format: FifoFormat,
+
+    /// The registers read back when the FIFO was enabled match `format`.
+    format_ok: bool,
+
+    /// When the buffer is flushed to a package.
+    pub flush: FlushPolicy,
+
+    /// Read into the second buffer while a full buffer waits to be taken (see `buf`). Not used
+    /// in the calibration capture, so that the steps do not mix.
+    pub double_buffer: bool,
+
+    /// Step of running calibration capture, `0` in normal operation.
+    calibration: u8,
+
+    /// Quality flags collected for the current buffer (see `quality`).
+    quality: u8,
+
+    /// The RTC being set while the buffer is collected.
+    steps: clock::StepWatch,
+
+    /// Maximum age of the position of a package before it is flagged as stale [s], and whether
+    /// to log a warning when the position becomes stale.
+    pub gps_stale_age: u32,
+    pub gps_stale_warn: bool,
+
+    /// The position of the last package was stale.
+    stale: bool,
+
+    /// Record the samples from the IMU and out of the decimation in the packages (see
+    /// `Config::sample_counts`).
+    pub sample_counts: bool,
+
+    /// Warm-up after power-on [s], recorded in the packages (see `Config::warmup`).
+    pub warmup: u16,
+
     /// Timestamp at `fifo_offset` sample in buffer.
     pub timestamp: i64,
     pub position_time: u32,
@@ -137,8 +274,11 @@ pub struct Waves<I2C: WriteRead + Write> {
     pub temperature: f32,
 
     /// Offset in FIFO _in samples_ (that is one gyro and one accel sample) when timestamp
-    /// was set.
+    /// was set, including the samples already read into the second buffer.
     pub fifo_offset: u16,
+
+    /// Retries of the setup and of enabling the FIFO (see `retry`).
+    pub retry: Retry,
 }
 
 #[derive(Debug, defmt::Format)]
@@ -151,8 +291,21 @@ pub enum ImuError<E: Debug> {
         samples: u16,
         buffer: usize,
     },
-    FifoBadSequence(fifo::Value, fifo::Value),
+    /// A gap in the FIFO longer than `max_gap` samples, or before the first sample (see `gap`).
+    FifoGap(usize),
+
+    /// A word of the FIFO with a tag that is not part of the format (see `format`).
+    FifoUnexpected(u8),
+
+    /// The FIFO is not configured for the format (see `format`).
+    FifoFormat(FifoFormat),
+
+    /// Enabling the FIFO failed on every attempt (see `retry`).
+    FifoEnable(Exhausted<E>),
     TooFewSamples(i64),
+
+    /// The buffer could not be made into a valid package (see `axl::AxlPacketBuilder`).
+    Package(PacketError),
 }
 
 impl<E: Debug> From<E> for ImuError<E> {
@@ -162,39 +315,116 @@ impl<E: Debug> From<E> for ImuError<E> {
 }
 
 impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
-    pub fn new(mut i2c: I2C) -> Result<Waves<I2C>, E> {
-        defmt::debug!("setting up imu driver..");
-        let imu = Ism330Dhcx::new_with_address(&mut i2c, 0x6a)?;
+    /// Set up the IMU, retrying as configured by `imu_retry` (see `retry`).
+    pub fn new(
+        i2c: I2C,
+        config: &Config,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Waves<I2C>, Exhausted<E>> {
+        Waves::new_with_address(i2c, config, config.imu_address, delay)
+    }
+
+    /// Set up the IMU at `address`, rather than `imu_address` of the config (e.g. the second IMU
+    /// with the `redundant-imu` feature).
+    pub fn new_with_address(
+        mut i2c: I2C,
+        config: &Config,
+        address: u8,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<Waves<I2C>, Exhausted<E>> {
+        let retry = config.imu_retry;
+
+        let imu = retry.run("probe", delay, |_| {
+            defmt::debug!("probing imu at: {:#x}..", address);
+            if let Err(e) = i2c.write(address, &[]) {
+                defmt::error!("No IMU answering at address {:#x}", address);
+                return Err(e);
+            }
+
+            defmt::debug!("setting up imu driver..");
+            Ism330Dhcx::new_with_address(&mut i2c, address)
+        })?;
 
         defmt::debug!("imu frequency: {}", FREQ.value());
-        defmt::debug!("output frequency: {}", OUTPUT_FREQ);
 
         let mut w = Waves {
             i2c,
             imu,
             freq: FREQ,
-            output_freq: OUTPUT_FREQ,
+            accel_range: config.accel_range,
+            accel_lpf: config.accel_lpf,
+            gyro_lpf: config.gyro_lpf,
+            bias_removal: config.bias_removal,
+            accel_bias: config.accel_bias,
+            accel_thermal: config.accel_thermal.clone(),
+            still: (config.bias_removal == BiasRemoval::Still)
+                .then(|| bias::StillEstimate::new(config.bias_window * FREQ.value() as u32)),
+            still_bias: None,
+            address,
             buf: ImuBuf::new(FREQ.value()),
+            gaps: gap::GapFill::new(config.max_gap),
+            format: config.fifo_format,
+            format_ok: false,
+            flush: FlushPolicy::from_config(config),
+            double_buffer: config.double_buffer,
+            calibration: 0,
+            quality: quality::FILTER_TRANSIENT | quality::time_flags(),
+            steps: clock::StepWatch::new(config.rtc_settle),
+            gps_stale_age: config.gps_stale_age,
+            gps_stale_warn: config.gps_stale_warn,
+            stale: false,
+            sample_counts: config.sample_counts,
+            warmup: config.warmup,
             timestamp: 0,
             position_time: 0,
             temperature: 0.0,
             lon: 0.0,
             lat: 0.0,
             fifo_offset: 0,
+            retry,
         };
 
-        defmt::debug!("booting imu..");
-        w.boot_imu()?;
-        w.disable_fifo()?;
+        w.set_filter(
+            config.decimation_mode,
+            config.fir_filter,
+            config.decimation(),
+        );
+        defmt::debug!("output frequency: {}", w.output_freq());
+
+        #[cfg(feature = "despike")]
+        w.buf.set_despike_window(config.despike_window as usize);
+        defmt::debug!("filter delay: {} s", w.buf.delay());
+
+        if w.bias_removal == BiasRemoval::Calibration {
+            w.buf.bias = w.accel_bias.map(|b| b as f64);
+        }
+
+        w.buf.lever_arm =
+            lever_arm::LeverArm::new(config.lever_arm.map(|r| r as f64), FREQ.value());
+
+        w.buf.accel_max = config.accel_max();
+        w.buf.set_accel_range(w.accel_range);
+        w.buf.axes = config.axes;
+
+        retry.run("setup", delay, |_| {
+            defmt::debug!("booting imu..");
+            w.boot_imu()?;
+            w.disable_fifo()
+        })?;
 
         // TODO: Turn off magnetometer.
 
         Ok(w)
     }
 
+    /// Read the configuration and status registers of the IMU (see [`registers`]).
+    pub fn dump_registers(&mut self) -> Result<Registers, E> {
+        Registers::read(&mut self.i2c, self.address)
+    }
+
     pub fn ping(&mut self) -> bool {
         defmt::debug!("pinging imu..");
-        self.i2c.write(0x6a, &[]).is_ok()
+        self.i2c.write(self.address, &[]).is_ok()
     }
 
     /// Attempt to reset and re-boot IMU.
@@ -207,9 +437,10 @@ impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
         self.imu.ctrl3c.sw_reset(&mut self.i2c)?;
         delay.delay_ms(1000u16);
 
-        self.imu = Ism330Dhcx::new_with_address(&mut self.i2c, 0x6a)?;
+        self.imu = Ism330Dhcx::new_with_address(&mut self.i2c, self.address)?;
 
         self.buf.reset();
+        self.quality |= quality::FILTER_TRANSIENT;
 
         // first batch is going to be off in timing.
         self.timestamp = 0;
@@ -222,6 +453,60 @@ impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
         Ok(())
     }
 
+    /// Discard the samples in the buffer and in the FIFO, e.g. at the end of the warm-up after
+    /// power-on. The filters are reset and the FIR start-up transient is discarded as after a
+    /// reset, but the IMU is not re-configured. Call `take_buf` afterwards to set the time of the
+    /// first sample.
+    pub fn discard(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), E> {
+        defmt::debug!("discarding imu buffer and fifo..");
+
+        self.buf.reset();
+        self.quality = quality::FILTER_TRANSIENT | quality::time_flags();
+        self.timestamp = 0;
+        self.fifo_offset = 0;
+
+        self.enable_fifo(delay)
+    }
+
+    /// Power down the accelerometer and gyroscope, e.g. between bursts of sampling. The sensor
+    /// draws about 1.5 mA in high-performance mode at 208 Hz and a few µA when powered down
+    /// (datasheet values, not measured on the buoy). The configuration registers are kept, but
+    /// the FIFO is reset. Use [`Waves::power_up`] to restart sampling.
+    pub fn power_down(&mut self) -> Result<(), E> {
+        defmt::debug!("powering down imu..");
+        self.disable_fifo()?;
+
+        self.imu
+            .ctrl1xl
+            .set_accelerometer_data_rate(&mut self.i2c, ctrl1xl::Odr_Xl::PowerDown)?;
+        self.imu
+            .ctrl2g
+            .set_gyroscope_data_rate(&mut self.i2c, ctrl2g::Odr::PowerDown)?;
+
+        Ok(())
+    }
+
+    /// Power up the IMU after [`Waves::power_down`]. The sensor is re-configured from scratch so
+    /// that it is in a known-good state, and the filters and buffer are reset so that the first
+    /// buffer does not contain samples from before the sleep (the FIR start-up transient is
+    /// discarded as after a reset). Call `take_buf` afterwards to set the time of the first
+    /// sample.
+    pub fn power_up(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), E> {
+        defmt::debug!("powering up imu..");
+
+        self.buf.reset();
+        self.quality |= quality::FILTER_TRANSIENT;
+        self.timestamp = 0;
+        self.fifo_offset = 0;
+
+        self.boot_imu()?;
+
+        // Let the sensor settle (the gyroscope needs some time to start up).
+        delay.delay_ms(100u16);
+
+        self.enable_fifo(delay)
+    }
+
     /// Temperature in Celsius.
     pub fn get_temperature(&mut self) -> Result<f32, E> {
         self.imu.get_temperature(&mut self.i2c)
@@ -250,36 +535,75 @@ impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
 
         sensor
             .ctrl1xl
-            .set_chain_full_scale(i2c, ctrl1xl::Fs_Xl::G2)?;
-        sensor.ctrl1xl.set_lpf2_xl_en(i2c, true)?; // high-res mode on accelerometer.
+            .set_chain_full_scale(i2c, self.accel_range.fs_xl())?;
 
         // CTRL2_G
         sensor
             .ctrl2g
             .set_gyroscope_data_rate(i2c, self.freq.gyro_odr())?;
 
-        sensor
-            .ctrl2g
-            .set_chain_full_scale(i2c, ctrl2g::Fs::Dps125)?;
+        sensor.ctrl2g.set_chain_full_scale(i2c, GYRO_RANGE.fs())?;
 
         // CTRL7_G
         sensor.ctrl7g.set_g_hm_mode(i2c, true)?; // high-res mode on gyro
 
         // Both the gyro and accelerometer is low-pass filtered on-board:
         //
-        // Gyro: LPF2 at 66.8 Hz when ODR = 208 Hz (not configurable)
-        // Accel: default is ODR/2 => 104 Hz.
+        // Gyro: LPF2 at 66.8 Hz when ODR = 208 Hz (not configurable), optionally LPF1.
+        // Accel: LPF1 at ODR/2, and LPF2 at a fraction of ODR (default ODR/4 => 52 Hz).
+        self.set_lpf(self.accel_lpf, self.gyro_lpf)?;
+
+        Ok(())
+    }
+
+    fn read_register(&mut self, reg: u8) -> Result<u8, E> {
+        let mut b = [0u8];
+        self.i2c.write_read(self.address, &[reg], &mut b)?;
+        Ok(b[0])
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) -> Result<(), E> {
+        self.i2c.write(self.address, &[reg, value])
+    }
+
+    /// Configure the on-chip low-pass filters of the accelerometer and the gyroscope (see
+    /// [`dlpf`]). The filters are re-applied when the IMU is booted.
+    pub fn set_lpf(&mut self, accel: AccelLpf, gyro: GyroLpf) -> Result<(), E> {
+        defmt::debug!(
+            "imu low-pass filters: accel: {:?} ({} Hz), gyro: {:?}",
+            accel,
+            accel.bandwidth(self.freq.value()),
+            gyro
+        );
+
+        self.imu
+            .ctrl1xl
+            .set_lpf2_xl_en(&mut self.i2c, accel.lpf2())?;
+
+        let r = self.read_register(dlpf::CTRL8_XL)?;
+        self.write_register(dlpf::CTRL8_XL, accel.ctrl8xl(r))?;
+
+        let r = self.read_register(dlpf::CTRL6_C)?;
+        self.write_register(dlpf::CTRL6_C, gyro.ctrl6c(r))?;
+
+        let r = self.read_register(dlpf::CTRL4_C)?;
+        self.write_register(dlpf::CTRL4_C, gyro.ctrl4c(r))?;
+
+        self.accel_lpf = accel;
+        self.gyro_lpf = gyro;
+        dlpf::set_current(accel, gyro);
 
         Ok(())
     }
 
     pub fn enable_fifo(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), E> {
-        defmt::debug!("enabling FIFO mode");
+        defmt::debug!("enabling FIFO mode ({:?})", self.format);
 
         let i2c = &mut self.i2c;
 
         // Reset FIFO
         self.imu.fifoctrl.mode(i2c, fifoctrl::FifoMode::Bypass)?;
+        self.gaps.reset();
         self.imu
             .fifoctrl
             .set_accelerometer_batch_data_rate(i2c, self.freq.accel_bdr())?;
@@ -298,14 +622,39 @@ impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
         // Start FIFO. The FIFO will fill up and stop if it is not emptied fast enough.
         self.imu.fifoctrl.mode(i2c, fifoctrl::FifoMode::FifoMode)?;
 
+        // The batching of the temperature and timestamp is not covered by the driver, and is
+        // written after the FIFO mode so that the driver does not overwrite it.
+        let r = self.read_register(format::CTRL10_C)?;
+        self.write_register(format::CTRL10_C, self.format.ctrl10c(r))?;
+
+        let r = self.read_register(format::FIFO_CTRL4)?;
+        self.write_register(format::FIFO_CTRL4, self.format.fifo_ctrl4(r))?;
+
+        self.format_ok = self.format.check(
+            self.read_register(format::FIFO_CTRL3)?,
+            self.read_register(format::FIFO_CTRL4)?,
+            self.read_register(format::CTRL10_C)?,
+        );
+
+        if !self.format_ok {
+            defmt::error!("FIFO not configured for format: {:?}", self.format);
+        }
+
         Ok(())
     }
 
+    /// Enable the FIFO, retrying as configured (see `retry`).
+    pub fn start_fifo(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), Exhausted<E>> {
+        let retry = self.retry;
+        retry.run("FIFO enable", delay, |d| self.enable_fifo(d))
+    }
+
     /// Disable FIFO mode (this also resets the FIFO).
     pub fn disable_fifo(&mut self) -> Result<(), E> {
         self.imu
             .fifoctrl
             .mode(&mut self.i2c, fifoctrl::FifoMode::Bypass)?;
+        self.gaps.reset();
 
         // Read FIFO status register to clear.
         let _fifo_full = self.imu.fifostatus.full(&mut self.i2c)?;
@@ -325,39 +674,184 @@ impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
     }
 
     /// Take buf and reset timestamp.
+    ///
+    /// The package is stamped with the time of the sample at `offset` (the number of samples in
+    /// the FIFO when the previous buffer was taken, see `fifo_offset`), this is a time offset and
+    /// does not change the samples. The only transformation of the samples here is the optional
+    /// removal of the acceleration offset (see `bias`), which is recorded in the package.
     pub fn take_buf(
         &mut self,
         now: i64,
         position_time: u32,
         lon: f64,
         lat: f64,
-    ) -> Result<AxlPacketT, E> {
+    ) -> Result<AxlPacketT, ImuError<E>> {
         defmt::trace!("axl: taking buffer");
+
+        // Samples discarded because of filter transient (after reset) delays the first sample in
+        // the buffer.
+        let discarded = self.buf.discarded();
+
+        // The RTC was set while the buffer was collected: the samples are timed by the IMU from
+        // the first sample, so the buffer is moved by the step to line up with the new time.
+        if let Some(step) = self.steps.check(now) {
+            if self.buf.len() > 0 {
+                defmt::info!(
+                    "RTC set while collecting buffer, moving timestamp {} by {} ms.",
+                    self.timestamp,
+                    step
+                );
+                self.timestamp += step;
+                self.quality |= quality::RTC_STEP;
+            }
+        }
+
+        if self.steps.settling(self.timestamp) {
+            self.quality |= quality::RTC_STEP;
+        }
+
+        let timestamp = self.timestamp + (discarded as f32 * 1000. / self.output_freq()) as i64;
+
+        let filled = heapless::Vec::from_slice(self.buf.filled()).unwrap_or_default();
+
+        let (raw_samples, output_samples) = match self.sample_counts {
+            true => self.buf.counts(),
+            false => (0, 0),
+        };
+
+        let clipped = self.buf.clipped().min(u16::MAX as usize) as u16;
+        if clipped > 0 {
+            defmt::warn!(
+                "{} IMU samples at the full scale of the accelerometer.",
+                clipped
+            );
+        }
+
+        // Samples already read into the second buffer come before the samples in the FIFO.
+        let spill = self.buf.spill().min(u16::MAX as usize) as u16;
+
+        #[cfg(feature = "despike")]
+        {
+            let despiked = self.buf.take_despiked();
+            if despiked > crate::despike::MAX_CORRECTED_FRACTION {
+                use core::fmt::Write as _;
+
+                let mut msg = heapless::String::<256>::new();
+                write!(
+                    &mut msg,
+                    "Excessive spikes in IMU data at {}: {:.2}% of samples replaced.",
+                    self.timestamp,
+                    despiked * 100.
+                )
+                .ok();
+                crate::log::log_at(crate::log::Category::Imu, crate::log::Level::Warn, &msg);
+            }
+        }
+
+        #[cfg(feature = "gyro")]
+        let gyro = self.buf.take_gyro();
+
         #[cfg(feature = "raw")]
         let (data, raw) = self.buf.take_buf();
 
         #[cfg(not(feature = "raw"))]
         let (data,) = self.buf.take_buf();
 
-        let pck = AxlPacket {
-            timestamp: self.timestamp,
-            offset: self.fifo_offset,
-            data,
-            storage_id: None,
-            storage_version: VERSION,
-            position_time: self.position_time,
-            temperature: self.temperature,
-            lon: self.lon,
-            lat: self.lat,
-            freq: self.output_freq,
+        // The rate of the filter that produced the samples, calibration packages are not
+        // decimated.
+        let freq = match self.calibration {
+            0 => self.output_freq(),
+            _ => self.freq.value(),
         };
+
+        let (battery_v, temperature_c) = crate::card_readings();
+
+        let pck = AxlPacketBuilder::new(timestamp, freq, self.buf.accel_max, data)
+            .offset(self.fifo_offset)
+            .position(
+                self.position_time,
+                self.lon,
+                self.lat,
+                crate::position_dop(),
+            )
+            .temperature(self.temperature)
+            .calibration(self.calibration)
+            .frame(Frame::of(self.calibration, !self.buf.lever_arm.is_zero()).code())
+            .axes(self.buf.stored_axes())
+            .warmup(self.warmup)
+            .filled(filled)
+            .sample_counts(raw_samples as u32, output_samples as u32)
+            .card(battery_v, temperature_c)
+            .clipped_samples(clipped);
+
+        #[cfg(feature = "gyro")]
+        let pck = pck.gyro(gyro);
+
+        let mut pck = pck.build().map_err(ImuError::Package)?;
+
+        // Flags collected for an empty buffer (e.g. the buffer taken after a reset) are kept for
+        // the next package.
+        if !pck.data.is_empty() {
+            pck.quality = self.quality | quality::package_flags(&pck, self.gps_stale_age);
+            if discarded > 0 {
+                pck.quality |= quality::FILTER_TRANSIENT;
+            }
+            self.quality = 0;
+
+            let stale = pck.quality & quality::GPS_STALE != 0;
+            if stale && !self.stale && self.gps_stale_warn && pck.position_time != 0 {
+                use core::fmt::Write as _;
+
+                let mut msg = heapless::String::<64>::new();
+                write!(&mut msg, "Position is stale: {} s old.", pck.fix_age()).ok();
+                crate::log::log_at(
+                    crate::log::Category::Location,
+                    crate::log::Level::Warn,
+                    &msg,
+                );
+            }
+            self.stale = stale;
+
+            // Offset removal after the quality flags, so that clipping is detected on the
+            // samples as measured.
+            if self.calibration == 0 {
+                pck.bias_mode = self.bias_removal.code();
+                pck.bias = match self.bias_removal {
+                    BiasRemoval::Off => [0.; 3],
+                    BiasRemoval::Mean => bias::remove_mean(&mut pck.data, pck.axes, pck.accel_max),
+                    BiasRemoval::Calibration => {
+                        pck.scale = self.buf.scale.map(|s| s as f32);
+                        self.buf.bias.map(|b| b as f32)
+                    }
+                    BiasRemoval::Still if self.still.is_none() => self.buf.bias.map(|b| b as f32),
+                    BiasRemoval::Still => {
+                        pck.bias_mode = BiasRemoval::Off.code();
+                        [0.; 3]
+                    }
+                };
+            }
+
+            // The estimated bias is applied from the next buffer on.
+            if let Some(b) = self.still_bias.take() {
+                self.buf.bias = b;
+                self.still = None;
+            }
+
+            // Over the samples as stored and sent, after the offset removal.
+            pck.crc = crate::axl::crc32(&pck.data);
+        }
         defmt::trace!("axl: buffer taken: {:?}", pck);
 
         self.lon = lon;
         self.lat = lat;
         self.timestamp = now;
+        self.quality = (self.quality & !quality::TIME_UNSYNCED) | quality::time_flags();
         self.position_time = position_time;
-        self.fifo_offset = self.imu.fifostatus.diff_fifo(&mut self.i2c)? / 2;
+        let words = self.imu.fifostatus.diff_fifo(&mut self.i2c)?;
+        self.fifo_offset = self
+            .format
+            .samples(words, self.freq.value())
+            .saturating_add(spill);
         self.temperature = self.get_temperature()?;
 
         defmt::debug!(
@@ -374,23 +868,107 @@ impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
         return Ok((pck,));
     }
 
+    /// Take the partially filled buffer at the end of a burst or before shutdown, so that the
+    /// last samples are not dropped. The package holds the actual number of samples (see
+    /// [`flush`]). Returns `None` if the buffer is empty.
+    pub fn flush_partial(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+    ) -> Result<Option<AxlPacketT>, ImuError<E>> {
+        if self.buf.len() == 0 {
+            return Ok(None);
+        }
+
+        defmt::debug!("axl: flushing partial buffer ({} samples)", self.buf.len());
+        self.take_buf(now, position_time, lon, lat).map(Some)
+    }
+
     pub fn is_full(&self) -> bool {
         self.buf.is_full()
     }
 
+    /// Replace the filter pipeline: decimation by `decimation` by `mode`, with the coefficients
+    /// `filter` for the FIR filter (see `filter::Pipeline::with_filter`). Panics if the decimation
+    /// is not within the cut-off of the filter, see `Config::validate`. The buffer should be
+    /// taken before, the samples in the buffer are not at the new rate.
+    pub fn set_filter(&mut self, mode: DecimationMode, filter: FirFilter, decimation: u8) {
+        self.buf
+            .set_pipeline(|| Pipeline::with_filter(mode, filter, decimation));
+    }
+
+    /// Decimation from the IMU rate (`FREQ`) to the output rate.
+    pub fn decimation(&self) -> u8 {
+        self.buf.decimation()
+    }
+
+    /// Output rate after decimation [Hz], the sample rate of the packages (other than the
+    /// calibration capture).
+    pub fn output_freq(&self) -> f32 {
+        FREQ.value() / self.decimation() as f32
+    }
+
+    /// Step of running calibration capture, `0` in normal operation.
+    pub fn calibration(&self) -> u8 {
+        self.calibration
+    }
+
+    /// Set step of calibration capture (`0` for normal operation). The buffer must be taken
+    /// before changing the step, so that packages do not span two steps. The buffer and filters
+    /// are reset when entering or leaving the capture.
+    pub fn set_calibration(&mut self, step: u8) {
+        if (step == 0) != (self.calibration == 0) {
+            self.buf.reset();
+        }
+
+        self.buf.calibration = step != 0;
+        self.calibration = step;
+    }
+
+    /// The buffer should be flushed to a package at `now` according to the flush policy.
+    pub fn should_flush(&self, now: i64) -> bool {
+        self.is_full()
+            || self.buf.is_pending()
+            || self
+                .flush
+                .should_flush(self.buf.len(), self.buf.capacity(), self.timestamp, now)
+    }
+
     pub fn len(&self) -> usize {
         self.buf.len()
     }
 
-    /// Read and filter samples from IMU. Returns number of sample pairs consumed (at IMU
-    /// frequency).
+    /// Apply the calibration from the temperature table at the temperature of the current buffer
+    /// (see `thermal`), if any.
+    fn apply_thermal(&mut self) {
+        if self.bias_removal != BiasRemoval::Calibration {
+            return;
+        }
+
+        if let Some(p) = thermal::at(&self.accel_thermal, self.temperature) {
+            self.buf.bias = p.bias.map(|b| b as f64);
+            self.buf.scale = p.scale.map(|s| s as f64);
+        }
+    }
+
+    /// Read and filter samples from IMU. Returns number of samples filtered (at IMU frequency),
+    /// including the samples interpolated over short gaps in the FIFO (see `gap`).
     pub fn read_and_filter(&mut self) -> Result<u32, ImuError<E>> {
-        use fifo::Value;
+        use format::Entry;
+
+        if !self.format_ok {
+            return Err(ImuError::FifoFormat(self.format));
+        }
+
+        self.apply_thermal();
 
         let n = self.imu.fifostatus.diff_fifo(&mut self.i2c)?;
 
         let i2c = &mut self.i2c;
         let imu = &mut self.imu;
+        let address = self.address;
 
         let fifo_full = imu.fifostatus.full(i2c)?;
         let fifo_overrun = imu.fifostatus.overrun(i2c)?;
@@ -400,6 +978,7 @@ impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
         // XXX: If any of these flags are true we need to reset the FIFO (and return an error from
         // this function), otherwise it will have stopped accumulating samples.
         if fifo_full || fifo_overrun || fifo_overrun_latched {
+            self.quality |= quality::FIFO_OVERRUN;
             defmt::error!("IMU fifo overrun: fifo sz: {}, (fifo_full: {}, overrun: {}, overrun_latched: {}) (buffer: {}/{})", n, fifo_full, fifo_overrun, fifo_overrun_latched, self.buf.len(), self.buf.capacity());
 
             return Err(ImuError::FifoOverrun {
@@ -411,33 +990,85 @@ impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
             });
         }
 
-        let n = n / 2;
-
+        let mut words = n;
         let mut samples = 0;
+        let limit = self.flush.limit(self.buf.capacity());
+
+        loop {
+            if !self.buf.is_pending() && (self.buf.is_full() || self.buf.len() >= limit) {
+                if self.double_buffer && self.calibration == 0 {
+                    defmt::debug!("axl buf is full, reading into second buffer..");
+                    self.buf.close();
+                } else {
+                    defmt::debug!("axl buf is full, waiting to be cleared..");
+                    break;
+                }
+            }
 
-        for _ in 0..n {
-            if self.buf.is_full() {
-                defmt::debug!("axl buf is full, waiting to be cleared..");
+            if self.buf.is_pending() && self.buf.next_len() >= limit {
+                defmt::debug!("axl buf and second buffer are full, waiting to be cleared..");
                 break;
             }
 
-            let m1 = imu.fifo_pop(i2c)?;
-            let m2 = imu.fifo_pop(i2c)?;
+            if let Some(s) = self.gaps.pop() {
+                if s.filled && !self.buf.mark_filled() {
+                    defmt::warn!("too many interpolated samples in buffer, not marked.");
+                }
+
+                crate::postmortem::push(s.a);
+                crate::burst::push(s.a);
+
+                if self.calibration == 0 && self.still_bias.is_none() {
+                    if let Some(e) = &mut self.still {
+                        self.still_bias = e.push(s.a);
+
+                        if let Some(b) = self.still_bias {
+                            use core::fmt::Write as _;
+
+                            let mut msg = heapless::String::<128>::new();
+                            write!(
+                                &mut msg,
+                                "Estimated accelerometer bias while still: {:?}",
+                                b
+                            )
+                            .ok();
+                            crate::log::log_at(
+                                crate::log::Category::Imu,
+                                crate::log::Level::Info,
+                                &msg,
+                            );
+                        }
+                    }
+                }
+
+                self.buf.sample(s.g, s.a).unwrap();
+                samples += 1;
+                continue;
+            }
 
-            let ga = match (m1, m2) {
-                (Value::Gyro(g), Value::Accel(a)) => Some((g, a)),
-                (Value::Accel(a), Value::Gyro(g)) => Some((g, a)),
-                _ => None,
+            if words == 0 {
+                break;
+            }
+            words -= 1;
+
+            let mut word = [0u8; format::WORD_SZ];
+            i2c.write_read(address, &[format::FIFO_DATA_OUT_TAG], &mut word)?;
+
+            let w = match self.format.decode(&word, self.accel_range, GYRO_RANGE) {
+                Ok(Entry::Gyro(g)) => gap::Word::Gyro(g),
+                Ok(Entry::Accel(a)) => gap::Word::Accel(a),
+                Ok(Entry::Temperature(_) | Entry::Timestamp(_)) => continue,
+                Err(tag) => {
+                    defmt::error!("Unexpected word in FIFO: {:?}, tag: {:#x}", word, tag);
+                    self.gaps.reset();
+                    return Err(ImuError::FifoUnexpected(tag));
+                }
             };
 
-            if let Some((g, a)) = ga {
-                self.buf.sample(g, a).unwrap();
-            } else {
-                defmt::error!("Bad sequence of samples in FIFO: {:?}, {:?}", m1, m2);
-                return Err(ImuError::FifoBadSequence(m1, m2));
+            if let Err(gap) = self.gaps.push(w) {
+                defmt::error!("Gap of {} samples in FIFO, not filled.", gap);
+                return Err(ImuError::FifoGap(gap));
             }
-
-            samples += 1;
         }
 
         let nn = imu.fifostatus.diff_fifo(i2c)?;
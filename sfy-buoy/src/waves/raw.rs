@@ -0,0 +1,211 @@
+//! Conversion of the raw 16 bit counts of the IMU to physical units, from the sensitivity of each
+//! full scale in the datasheet of the ISM330DHCX.
+//!
+//! The counts are two's complement, read little-endian from the output registers. The conversion
+//! is done in `f64`, which holds every count exactly, so `i16::MIN` is never negated or truncated.
+//! The sensitivity is rounded in the datasheet: the counts of the accelerometer span slightly less
+//! than the nominal full scale (`i16::MAX` is 1.9988 g at ±2 g), and the counts of the gyroscope
+//! span more (`i16::MAX` is 143.4 dps at ±125 dps). The endpoints, `i16::MIN` and `i16::MAX`,
+//! are readings of a saturated sensor (see [`saturated`]).
+//!
+//! The words of the FIFO are decoded with the same conversions (see `format`).
+
+use super::buf::{SENSORS_DPS_TO_RADS, SENSORS_GRAVITY_STANDARD};
+use crate::config::AccelRange;
+
+/// Full scale of the gyroscope.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub enum GyroRange {
+    Dps125,
+    Dps250,
+    Dps500,
+    Dps1000,
+    Dps2000,
+    Dps4000,
+}
+
+/// Full scale of the gyroscope of the buoy.
+///
+/// > Do not change without updating `wire::GYRO_MAX`.
+pub const GYRO_RANGE: GyroRange = GyroRange::Dps125;
+
+impl GyroRange {
+    /// Nominal full scale [dps].
+    pub fn dps(&self) -> f32 {
+        use GyroRange::*;
+
+        match self {
+            Dps125 => 125.,
+            Dps250 => 250.,
+            Dps500 => 500.,
+            Dps1000 => 1000.,
+            Dps2000 => 2000.,
+            Dps4000 => 4000.,
+        }
+    }
+
+    /// Sensitivity [mdps/LSB].
+    pub fn sensitivity(&self) -> f64 {
+        use GyroRange::*;
+
+        match self {
+            Dps125 => 4.375,
+            Dps250 => 8.75,
+            Dps500 => 17.5,
+            Dps1000 => 35.,
+            Dps2000 => 70.,
+            Dps4000 => 140.,
+        }
+    }
+}
+
+impl AccelRange {
+    /// Sensitivity [mg/LSB].
+    pub fn sensitivity(&self) -> f64 {
+        use AccelRange::*;
+
+        match self {
+            G2 => 0.061,
+            G4 => 0.122,
+            G8 => 0.244,
+            G16 => 0.488,
+        }
+    }
+}
+
+/// Acceleration [m/s^2] of a count of the accelerometer at `range`.
+pub fn raw_to_ms2(count: i16, range: AccelRange) -> f32 {
+    ms2(count, range) as f32
+}
+
+/// Angular rate [rad/s] of a count of the gyroscope at `range`.
+pub fn raw_to_rads(count: i16, range: GyroRange) -> f32 {
+    rads(count, range) as f32
+}
+
+pub(crate) fn ms2(count: i16, range: AccelRange) -> f64 {
+    let mg = f64::from(count) * range.sensitivity();
+
+    mg * 1.0e-3 * SENSORS_GRAVITY_STANDARD
+}
+
+pub(crate) fn rads(count: i16, range: GyroRange) -> f64 {
+    let mdps = f64::from(count) * range.sensitivity();
+
+    mdps * 1.0e-3 * SENSORS_DPS_TO_RADS
+}
+
+/// The count is at an endpoint of the output, the sensor is saturated and the true value may be
+/// beyond it.
+pub fn saturated(count: i16) -> bool {
+    count == i16::MIN || count == i16::MAX
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waves::wire::GYRO_MAX;
+
+    const ACCEL_RANGES: [AccelRange; 4] = [
+        AccelRange::G2,
+        AccelRange::G4,
+        AccelRange::G8,
+        AccelRange::G16,
+    ];
+
+    const GYRO_RANGES: [GyroRange; 6] = [
+        GyroRange::Dps125,
+        GyroRange::Dps250,
+        GyroRange::Dps500,
+        GyroRange::Dps1000,
+        GyroRange::Dps2000,
+        GyroRange::Dps4000,
+    ];
+
+    fn close(a: f32, b: f64) -> bool {
+        (a as f64 - b).abs() <= b.abs() * 1.0e-6
+    }
+
+    #[test]
+    fn accel_endpoints() {
+        let g = SENSORS_GRAVITY_STANDARD;
+
+        for (range, fs) in ACCEL_RANGES.into_iter().zip([2., 4., 8., 16.]) {
+            assert_eq!(raw_to_ms2(0, range), 0.);
+            assert_eq!(raw_to_ms2(1, range), -raw_to_ms2(-1, range));
+            assert_eq!(raw_to_ms2(i16::MAX, range), -raw_to_ms2(-i16::MAX, range));
+
+            let max = raw_to_ms2(i16::MAX, range);
+            let min = raw_to_ms2(i16::MIN, range);
+
+            // The rounded sensitivity spans within 0.1 % of the full scale, on both sides.
+            assert!(close(max, 32767. * range.sensitivity() * 1.0e-3 * g));
+            assert!(close(min, -32768. * range.sensitivity() * 1.0e-3 * g));
+            assert!(max > 0. && min < 0.);
+            assert!(min.abs() <= range.full_scale() && max < range.full_scale());
+            assert!(min.abs() > 0.999 * fs * g as f32);
+        }
+
+        assert!(close(
+            raw_to_ms2(16393, AccelRange::G2),
+            16393. * 0.061e-3 * g
+        ));
+        assert!(close(
+            raw_to_ms2(-2049, AccelRange::G16),
+            -2049. * 0.488e-3 * g
+        ));
+    }
+
+    #[test]
+    fn gyro_endpoints() {
+        for range in GYRO_RANGES {
+            assert_eq!(raw_to_rads(0, range), 0.);
+            assert_eq!(raw_to_rads(1, range), -raw_to_rads(-1, range));
+            assert_eq!(raw_to_rads(i16::MAX, range), -raw_to_rads(-i16::MAX, range));
+
+            let max = raw_to_rads(i16::MAX, range) as f64 / SENSORS_DPS_TO_RADS;
+            let min = raw_to_rads(i16::MIN, range) as f64 / SENSORS_DPS_TO_RADS;
+
+            // The counts span about 15 % beyond the nominal full scale.
+            let dps = range.dps() as f64;
+            assert!(max > dps && max < 1.15 * dps);
+            assert!(min < -dps && min > -1.15 * dps);
+        }
+
+        // The wire encoding covers the whole output of the gyroscope of the buoy.
+        assert!(raw_to_rads(i16::MIN, GYRO_RANGE).abs() < GYRO_MAX);
+    }
+
+    #[test]
+    fn monotonic() {
+        for range in ACCEL_RANGES {
+            let mut prev = f32::NEG_INFINITY;
+
+            for count in i16::MIN..=i16::MAX {
+                let v = raw_to_ms2(count, range);
+                assert!(v > prev, "{range:?}: {count}");
+                assert_eq!(v.signum(), if count < 0 { -1. } else { 1. });
+                prev = v;
+            }
+        }
+
+        for range in GYRO_RANGES {
+            let mut prev = f32::NEG_INFINITY;
+
+            for count in i16::MIN..=i16::MAX {
+                let v = raw_to_rads(count, range);
+                assert!(v > prev, "{range:?}: {count}");
+                prev = v;
+            }
+        }
+    }
+
+    #[test]
+    fn saturation() {
+        assert!(saturated(i16::MIN));
+        assert!(saturated(i16::MAX));
+        assert!(!saturated(0));
+        assert!(!saturated(i16::MIN + 1));
+        assert!(!saturated(i16::MAX - 1));
+    }
+}
@@ -0,0 +1,337 @@
+//! Two IMUs on the same I2C bus for redundancy (feature `redundant-imu`).
+//!
+//! The primary IMU is at `imu_address` and the secondary at `imu_address_secondary`. Both are read
+//! and filtered, and packages are taken from both at the same time. The package of the active IMU
+//! (the primary, unless it has failed) is used. The packages of the two IMUs are compared for
+//! plausibility (see [`agree`]): when they disagree the `IMU_DISAGREE` quality flag is set on the
+//! package, and the package passing the most quality checks is used (the active IMU on a tie).
+//! The samples of the two IMUs are not aligned, so they are not averaged.
+//!
+//! An I2C error from an IMU marks it as failed, an alarm is logged to the notecard and the buoy
+//! continues on the other IMU. A failed IMU is tried again when the IMUs are reset, and is used
+//! again if the reset succeeds. Other errors (e.g. a FIFO overrun) are returned as for a single
+//! IMU, so that both IMUs are reset. When both IMUs have failed both are still read, and the errors
+//! of the active IMU are returned.
+
+use core::fmt::Debug;
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Write, WriteRead},
+};
+
+use super::{AxlPacketT, Exhausted, ImuError, Registers, Waves};
+use crate::axl::AxlPacket;
+use crate::log::{self, Category, Level};
+use crate::quality;
+
+/// Largest difference of the standard deviation of an axis between the IMUs that is always
+/// plausible [m/s^2].
+pub const DISAGREE_ABS: f32 = 0.05;
+
+/// Largest relative difference of the standard deviation of an axis between the IMUs.
+pub const DISAGREE_REL: f32 = 0.2;
+
+/// Are the packages `a` and `b`, taken at the same time from the two IMUs, plausibly measuring
+/// the same motion: the standard deviations of every axis are within `DISAGREE_ABS` or
+/// `DISAGREE_REL` of each other.
+pub fn agree(a: &AxlPacket, b: &AxlPacket) -> bool {
+    let (a, b) = (a.stats(), b.stats());
+
+    [(a.x_std, b.x_std), (a.y_std, b.y_std), (a.z_std, b.z_std)]
+        .iter()
+        .all(|(a, b)| libm::fabsf(a - b) <= DISAGREE_ABS.max(DISAGREE_REL * a.max(*b)))
+}
+
+pub struct RedundantImu<I2C: WriteRead + Write> {
+    /// Primary and secondary IMU, `None` if it did not answer at boot.
+    imus: [Option<Waves<I2C>>; 2],
+    failed: [bool; 2],
+
+    /// The IMU the packages are taken from.
+    active: usize,
+
+    /// Temperature of the IMU of the last package.
+    pub temperature: f32,
+}
+
+impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> RedundantImu<I2C> {
+    /// Set up with the IMUs that answered at boot, `None` if neither did.
+    pub fn new(
+        primary: Option<Waves<I2C>>,
+        secondary: Option<Waves<I2C>>,
+    ) -> Option<RedundantImu<I2C>> {
+        let imus = [primary, secondary];
+        let active = imus.iter().position(Option::is_some)?;
+
+        if imus.iter().any(Option::is_none) {
+            log::log_at(
+                Category::Imu,
+                Level::Error,
+                "Only one IMU found, running without redundancy.",
+            );
+        }
+
+        Some(RedundantImu {
+            temperature: imus[active].as_ref().unwrap().temperature,
+            failed: [imus[0].is_none(), imus[1].is_none()],
+            imus,
+            active,
+        })
+    }
+
+    /// The IMU the packages are taken from (`0` for the primary).
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    fn active_imu(&self) -> &Waves<I2C> {
+        self.imus[self.active].as_ref().unwrap()
+    }
+
+    /// Is the IMU `i` present and not failed, or are all IMUs failed.
+    fn candidate(&self, i: usize) -> bool {
+        self.imus[i].is_some() && (!self.failed[i] || self.failed.iter().all(|f| *f))
+    }
+
+    fn fail(&mut self, i: usize) {
+        if self.failed[i] {
+            return;
+        }
+
+        self.failed[i] = true;
+        defmt::error!("IMU {} failed.", i);
+
+        let other = 1 - i;
+        let msg = if self.imus[other].is_some() && !self.failed[other] {
+            self.active = other;
+
+            match i {
+                0 => "Primary IMU failed, continuing on the secondary IMU.",
+                _ => "Secondary IMU failed, continuing on the primary IMU.",
+            }
+        } else {
+            "All IMUs failed."
+        };
+
+        log::log_at(Category::Imu, Level::Error, msg);
+    }
+
+    /// Run `f` on the candidate IMUs.
+    fn map<R>(&mut self, mut f: impl FnMut(&mut Waves<I2C>) -> R) -> [Option<R>; 2] {
+        let candidate = [self.candidate(0), self.candidate(1)];
+        let mut r = [None, None];
+
+        for (i, w) in self.imus.iter_mut().enumerate() {
+            if candidate[i] {
+                r[i] = w.as_mut().map(&mut f);
+            }
+        }
+
+        r
+    }
+
+    /// Fail the IMUs with an error for which `failure` is true.
+    fn fail_errors<R, Er>(
+        &mut self,
+        r: &[Option<Result<R, Er>>; 2],
+        failure: impl Fn(&Er) -> bool,
+    ) {
+        for (i, r) in r.iter().enumerate() {
+            if let Some(Err(e)) = r {
+                if failure(e) {
+                    self.fail(i);
+                }
+            }
+        }
+    }
+
+    /// Split the results into the one of the active IMU and the one of the standby IMU, if it
+    /// has not failed.
+    fn split<R>(&self, r: [Option<R>; 2]) -> (R, Option<R>) {
+        let [a, b] = r;
+        let (active, standby) = if self.active == 0 { (a, b) } else { (b, a) };
+        let standby = standby.filter(|_| !self.failed[1 - self.active]);
+
+        (active.unwrap(), standby)
+    }
+
+    /// Run `f` on the IMUs, returning the result of the active IMU.
+    fn each<Er>(&mut self, f: impl FnMut(&mut Waves<I2C>) -> Result<(), Er>) -> Result<(), Er> {
+        let r = self.map(f);
+        self.fail_errors(&r, |_| true);
+
+        self.split(r).0
+    }
+
+    /// Use the package of the active IMU `a`, unless it disagrees with the package of the standby
+    /// IMU `b` and `b` passes more quality checks.
+    fn select(&self, mut a: AxlPacketT, mut b: AxlPacketT) -> AxlPacketT {
+        if agree(&a.0, &b.0) {
+            return a;
+        }
+
+        defmt::warn!(
+            "IMUs disagree: quality {} (active) and {} (standby).",
+            a.0.quality,
+            b.0.quality
+        );
+
+        a.0.quality |= quality::IMU_DISAGREE;
+        b.0.quality |= quality::IMU_DISAGREE;
+
+        if quality::score(b.0.quality) > quality::score(a.0.quality) {
+            b
+        } else {
+            a
+        }
+    }
+
+    pub fn read_and_filter(&mut self) -> Result<u32, ImuError<E>> {
+        let r = self.map(|w| w.read_and_filter());
+        self.fail_errors(&r, |e| matches!(e, ImuError::I2C(_)));
+
+        match self.split(r) {
+            // Reset both IMUs on an error of the standby IMU as well.
+            (Ok(_), Some(Err(e))) => Err(e),
+            (r, _) => r,
+        }
+    }
+
+    pub fn take_buf(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+    ) -> Result<AxlPacketT, ImuError<E>> {
+        let r = self.map(|w| w.take_buf(now, position_time, lon, lat));
+        self.fail_errors(&r, |_| true);
+
+        let pck = match self.split(r) {
+            (Ok(a), Some(Ok(b))) => self.select(a, b),
+            (a, _) => a?,
+        };
+
+        self.temperature = pck.0.temperature;
+
+        Ok(pck)
+    }
+
+    pub fn flush_partial(
+        &mut self,
+        now: i64,
+        position_time: u32,
+        lon: f64,
+        lat: f64,
+    ) -> Result<Option<AxlPacketT>, ImuError<E>> {
+        let r = self.map(|w| w.flush_partial(now, position_time, lon, lat));
+        self.fail_errors(&r, |_| true);
+
+        match self.split(r) {
+            (Ok(Some(a)), Some(Ok(Some(b)))) => Ok(Some(self.select(a, b))),
+            (a, _) => a,
+        }
+    }
+
+    /// Reset all IMUs, also the failed ones. A failed IMU that resets is used again, and the
+    /// primary IMU is preferred.
+    pub fn reset(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), E> {
+        let r = [0, 1].map(|i| self.imus[i].as_mut().map(|w| w.reset(delay)));
+
+        for (i, r) in r.iter().enumerate() {
+            match r {
+                Some(Ok(())) if self.failed[i] => {
+                    self.failed[i] = false;
+                    log::log_at(Category::Imu, Level::Warn, "Failed IMU recovered by reset.");
+                }
+                Some(Err(_)) => self.fail(i),
+                _ => {}
+            }
+        }
+
+        if !self.failed[0] {
+            self.active = 0;
+        } else if !self.failed[1] {
+            self.active = 1;
+        }
+
+        let [a, b] = r;
+        (if self.active == 0 { a } else { b }).unwrap()
+    }
+
+    pub fn enable_fifo(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), E> {
+        self.each(|w| w.enable_fifo(delay))
+    }
+
+    pub fn start_fifo(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), Exhausted<E>> {
+        self.each(|w| w.start_fifo(delay))
+    }
+
+    pub fn power_down(&mut self) -> Result<(), E> {
+        self.each(|w| w.power_down())
+    }
+
+    pub fn power_up(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), E> {
+        self.each(|w| w.power_up(delay))
+    }
+
+    pub fn discard(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), E> {
+        self.each(|w| w.discard(delay))
+    }
+
+    /// Read the registers of the active IMU.
+    pub fn dump_registers(&mut self) -> Result<Registers, E> {
+        self.imus[self.active].as_mut().unwrap().dump_registers()
+    }
+
+    pub fn calibration(&self) -> u8 {
+        self.active_imu().calibration()
+    }
+
+    pub fn set_calibration(&mut self, step: u8) {
+        for w in self.imus.iter_mut().flatten() {
+            w.set_calibration(step);
+        }
+    }
+
+    /// Flush when any of the IMUs should, so that the buffer of the standby IMU does not fill up.
+    pub fn should_flush(&self, now: i64) -> bool {
+        (0..2).any(|i| {
+            self.candidate(i) && self.imus[i].as_ref().map_or(false, |w| w.should_flush(now))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.active_imu().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axl::{AxlPacketBuilder, AXL_SZ, SAMPLE_SZ};
+    use crate::waves::wire::{ScaledF32, A16, ACCEL_MAX};
+
+    fn package(amplitude: f32) -> AxlPacket {
+        let data = (0..AXL_SZ)
+            .map(|i| {
+                let s = if (i / SAMPLE_SZ) % 2 == 0 { 1. } else { -1. };
+                A16::from_f32(s * amplitude).to_u16()
+            })
+            .collect();
+
+        AxlPacketBuilder::new(1, 52.0, ACCEL_MAX, data)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn plausible() {
+        assert!(agree(&package(1.0), &package(1.0)));
+        assert!(agree(&package(1.0), &package(1.1)));
+        assert!(agree(&package(0.01), &package(0.05)));
+
+        assert!(!agree(&package(1.0), &package(1.5)));
+        assert!(!agree(&package(1.0), &package(0.0)));
+    }
+}
@@ -0,0 +1,111 @@
+//! Dump of the configuration and status registers of the IMU, logged on repeated IMU failures to
+//! tell a corrupted configuration (e.g. from a glitch on the bus) apart from a failed IMU.
+//!
+//! The registers are read one at a time, so that the dump does not rely on the address
+//! auto-increment (`IF_INC` of `CTRL3_C`) which may itself be corrupted.
+
+use core::fmt;
+use embedded_hal::blocking::i2c::WriteRead;
+
+/// `FIFO_CTRL1` to `FIFO_CTRL4`.
+const FIFO_CTRL: u8 = 0x07;
+const WHO_AM_I: u8 = 0x0f;
+/// `CTRL1_XL` to `CTRL10_C`.
+const CTRL: u8 = 0x10;
+const STATUS_REG: u8 = 0x1e;
+/// `FIFO_STATUS1` and `FIFO_STATUS2`.
+const FIFO_STATUS: u8 = 0x3a;
+
+#[derive(defmt::Format, Debug, Default, Clone, PartialEq)]
+pub struct Registers {
+    pub who_am_i: u8,
+    pub fifo_ctrl: [u8; 4],
+    pub ctrl: [u8; 10],
+    pub status: u8,
+    pub fifo_status: [u8; 2],
+}
+
+fn read_reg<E, I2C: WriteRead<Error = E>>(i2c: &mut I2C, address: u8, reg: u8) -> Result<u8, E> {
+    let mut v = [0u8];
+    i2c.write_read(address, &[reg], &mut v)?;
+
+    Ok(v[0])
+}
+
+fn read_into<E, I2C: WriteRead<Error = E>>(
+    i2c: &mut I2C,
+    address: u8,
+    start: u8,
+    buf: &mut [u8],
+) -> Result<(), E> {
+    for (reg, b) in (start..).zip(buf.iter_mut()) {
+        *b = read_reg(i2c, address, reg)?;
+    }
+
+    Ok(())
+}
+
+impl Registers {
+    /// Read the registers of the IMU at `address`.
+    pub fn read<E, I2C: WriteRead<Error = E>>(i2c: &mut I2C, address: u8) -> Result<Registers, E> {
+        let mut r = Registers {
+            who_am_i: read_reg(i2c, address, WHO_AM_I)?,
+            status: read_reg(i2c, address, STATUS_REG)?,
+            ..Default::default()
+        };
+
+        read_into(i2c, address, FIFO_CTRL, &mut r.fifo_ctrl)?;
+        read_into(i2c, address, CTRL, &mut r.ctrl)?;
+        read_into(i2c, address, FIFO_STATUS, &mut r.fifo_status)?;
+
+        Ok(r)
+    }
+}
+
+/// Compact hex dump, short enough for a log message.
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn hex(f: &mut fmt::Formatter<'_>, b: &[u8]) -> fmt::Result {
+            b.iter().try_for_each(|b| write!(f, "{:02x}", b))
+        }
+
+        write!(f, "who_am_i: {:02x}, fifo_ctrl: ", self.who_am_i)?;
+        hex(f, &self.fifo_ctrl)?;
+        write!(f, ", ctrl: ")?;
+        hex(f, &self.ctrl)?;
+        write!(f, ", status: {:02x}, fifo_status: ", self.status)?;
+        hex(f, &self.fifo_status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Register file where every register holds its own address.
+    struct Echo;
+
+    impl WriteRead for Echo {
+        type Error = ();
+
+        fn write_read(&mut self, _address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            buffer[0] = bytes[0];
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dump() {
+        let r = Registers::read(&mut Echo, 0x6a).unwrap();
+
+        assert_eq!(r.who_am_i, WHO_AM_I);
+        assert_eq!(r.fifo_ctrl, [0x07, 0x08, 0x09, 0x0a]);
+        assert_eq!(r.ctrl[9], 0x19);
+        assert_eq!(r.fifo_status, [0x3a, 0x3b]);
+
+        assert_eq!(
+            format!("{}", r),
+            "who_am_i: 0f, fifo_ctrl: 0708090a, ctrl: 10111213141516171819, status: 1e, fifo_status: 3a3b"
+        );
+    }
+}
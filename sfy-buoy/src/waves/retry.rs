@@ -0,0 +1,159 @@
+//! Retrying the setup of the IMU.
+//!
+//! On a cold boot the IMU may not answer, or not take its configuration, before it has fully
+//! powered up. The setup of the IMU (probing, booting and configuring it, see `Waves::new`) and
+//! enabling the FIFO (`Waves::start_fifo`, at boot and after a reset of the IMU) are therefore
+//! tried up to `1 + retries` times, `delay` ms apart (`imu_retry` in the config). Every failed
+//! attempt is logged. When all attempts fail the error of the last attempt is returned with the
+//! number of attempts ([`Exhausted`]).
+
+use core::fmt::{Debug, Write};
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::log::{self, Category, Level};
+
+/// Maximum number of retries.
+pub const MAX_RETRIES: u8 = 10;
+
+/// Maximum delay between attempts [ms].
+pub const MAX_DELAY: u16 = 10_000;
+
+/// Retries of the IMU setup. Fields that are not set in an override take the default value.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Retry {
+    /// Attempts after the first, `0` to fail on the first error.
+    pub retries: u8,
+
+    /// Delay before every retry [ms].
+    pub delay: u16,
+}
+
+impl Default for Retry {
+    fn default() -> Retry {
+        Retry {
+            retries: 3,
+            delay: 500,
+        }
+    }
+}
+
+/// All attempts failed.
+#[derive(Debug, defmt::Format, PartialEq)]
+pub struct Exhausted<E> {
+    pub attempts: u8,
+
+    /// Error of the last attempt.
+    pub error: E,
+}
+
+impl Retry {
+    pub fn is_valid(&self) -> bool {
+        self.retries <= MAX_RETRIES && self.delay <= MAX_DELAY
+    }
+
+    /// Run `f` until it succeeds, at most `1 + retries` times. `what` names the step in the log.
+    pub fn run<T, E: Debug, D: DelayMs<u16>>(
+        &self,
+        what: &str,
+        delay: &mut D,
+        mut f: impl FnMut(&mut D) -> Result<T, E>,
+    ) -> Result<T, Exhausted<E>> {
+        let attempts = self.retries.saturating_add(1);
+        let mut attempt = 1;
+
+        loop {
+            let error = match f(delay) {
+                Ok(v) => return Ok(v),
+                Err(e) => e,
+            };
+
+            let last = attempt >= attempts;
+
+            let mut msg = heapless::String::<128>::new();
+            write!(
+                &mut msg,
+                "IMU {} failed (attempt {} of {}): {:?}",
+                what, attempt, attempts, error
+            )
+            .ok();
+            defmt::warn!("{}", msg.as_str());
+            log::log_at(
+                Category::Imu,
+                if last { Level::Error } else { Level::Warn },
+                &msg,
+            );
+
+            if last {
+                return Err(Exhausted { attempts, error });
+            }
+
+            delay.delay_ms(self.delay);
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Total time delayed [ms].
+    struct Delay(u32);
+
+    impl DelayMs<u16> for Delay {
+        fn delay_ms(&mut self, ms: u16) {
+            self.0 += ms as u32;
+        }
+    }
+
+    #[test]
+    fn retries() {
+        let r = Retry::default();
+        assert!(r.is_valid());
+
+        // Fails twice on a cold boot.
+        let mut d = Delay(0);
+        let mut n = 0;
+        let v = r.run("setup", &mut d, |_| {
+            n += 1;
+            if n < 3 {
+                Err(n)
+            } else {
+                Ok(n)
+            }
+        });
+        assert_eq!(v, Ok(3));
+        assert_eq!(d.0, 1000);
+
+        let mut d = Delay(0);
+        let v: Result<(), _> = r.run("FIFO enable", &mut d, |_| Err("nack"));
+        assert_eq!(
+            v,
+            Err(Exhausted {
+                attempts: 4,
+                error: "nack"
+            })
+        );
+        assert_eq!(d.0, 1500);
+
+        let r = Retry {
+            retries: 0,
+            delay: 500,
+        };
+        let mut d = Delay(0);
+        assert_eq!(
+            r.run("setup", &mut d, |_| Err::<(), _>(1))
+                .unwrap_err()
+                .attempts,
+            1
+        );
+        assert_eq!(d.0, 0);
+
+        assert!(!Retry {
+            retries: MAX_RETRIES + 1,
+            ..r
+        }
+        .is_valid());
+    }
+}
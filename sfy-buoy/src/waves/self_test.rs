@@ -0,0 +1,225 @@
+//! Self-test of the accelerometer and the gyroscope of the IMU, part of the self-test at boot (see
+//! `bist`).
+//!
+//! The self-test of the ISM330DHCX applies an electrostatic force to the proof masses
+//! (`ST_XL` and `ST_G` in `CTRL5_C`), which shifts the output of every axis by a known amount
+//! when the sensor is working. Following the procedure of the datasheet the output is averaged
+//! over `SAMPLES` samples with and without the self-test (the accelerometer at 52 Hz and ±4 g,
+//! the gyroscope at 208 Hz and ±2000 dps), and the difference of every axis must be within
+//! `ACCEL_LIMITS` and `GYRO_LIMITS`. The buoy must be still during the test, which takes about
+//! half a second. The IMU is booted with the configuration of the buoy afterwards.
+
+use core::fmt::Debug;
+use core::ops::RangeInclusive;
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Write, WriteRead},
+};
+
+use super::Waves;
+
+/// WHO_AM_I of the ISM330DHCX.
+pub const WHO_AM_I: u8 = 0x6b;
+
+const WHO_AM_I_REG: u8 = 0x0f;
+const CTRL1_XL: u8 = 0x10;
+const CTRL2_G: u8 = 0x11;
+const CTRL5_C: u8 = 0x14;
+const STATUS_REG: u8 = 0x1e;
+const OUTX_L_G: u8 = 0x22;
+const OUTX_L_A: u8 = 0x28;
+
+/// 52 Hz, ±4 g.
+const ST_CTRL1_XL: u8 = 0x38;
+
+/// 208 Hz, ±2000 dps.
+const ST_CTRL2_G: u8 = 0x5c;
+
+/// Positive self-test of the accelerometer and the gyroscope in `CTRL5_C`.
+const ST_XL: u8 = 0x01;
+const ST_G: u8 = 0x04;
+
+/// Data ready in `STATUS_REG`.
+const XLDA: u8 = 0x01;
+const GDA: u8 = 0x02;
+
+/// Sensitivity at ±4 g [mg/LSB] and at ±2000 dps [dps/LSB].
+const ACCEL_SENSITIVITY: f32 = 0.122;
+const GYRO_SENSITIVITY: f32 = 0.070;
+
+/// Samples averaged with and without the self-test.
+pub const SAMPLES: usize = 5;
+
+/// Settling time after changing the configuration [ms].
+const SETTLE: u16 = 100;
+
+/// Limits of the change of the output in the self-test [mg] and [dps].
+pub const ACCEL_LIMITS: RangeInclusive<f32> = 40.0..=1700.0;
+pub const GYRO_LIMITS: RangeInclusive<f32> = 150.0..=700.0;
+
+/// Change of the output of every axis in the self-test.
+#[derive(serde::Serialize, defmt::Format, Debug, Default, Clone, Copy, PartialEq)]
+pub struct SelfTest {
+    /// [mg].
+    pub accel: [f32; 3],
+
+    /// [dps].
+    pub gyro: [f32; 3],
+}
+
+impl SelfTest {
+    /// From the mean output [LSB] without (`nost`) and with (`st`) the self-test.
+    pub fn new(nost_xl: [f32; 3], st_xl: [f32; 3], nost_g: [f32; 3], st_g: [f32; 3]) -> SelfTest {
+        let delta =
+            |a: [f32; 3], b: [f32; 3], s: f32| [0, 1, 2].map(|i| libm::fabsf(b[i] - a[i]) * s);
+
+        SelfTest {
+            accel: delta(nost_xl, st_xl, ACCEL_SENSITIVITY),
+            gyro: delta(nost_g, st_g, GYRO_SENSITIVITY),
+        }
+    }
+
+    pub fn accel_ok(&self) -> bool {
+        self.accel.iter().all(|a| ACCEL_LIMITS.contains(a))
+    }
+
+    pub fn gyro_ok(&self) -> bool {
+        self.gyro.iter().all(|g| GYRO_LIMITS.contains(g))
+    }
+
+    pub fn passed(&self) -> bool {
+        self.accel_ok() && self.gyro_ok()
+    }
+}
+
+/// Mean of samples [LSB].
+fn mean(samples: &[[i16; 3]]) -> [f32; 3] {
+    [0, 1, 2]
+        .map(|i| samples.iter().map(|s| s[i] as f32).sum::<f32>() / samples.len().max(1) as f32)
+}
+
+impl<E: Debug, I2C: WriteRead<Error = E> + Write<Error = E>> Waves<I2C> {
+    /// WHO_AM_I of the IMU, `WHO_AM_I` for the ISM330DHCX.
+    pub fn who_am_i(&mut self) -> Result<u8, E> {
+        self.read_register(WHO_AM_I_REG)
+    }
+
+    /// Run the self-test of the accelerometer and the gyroscope. Must be done before the FIFO is
+    /// enabled, the IMU is booted with the configuration of the buoy afterwards (also when the
+    /// test fails on an I2C error).
+    pub fn self_test(&mut self, delay: &mut impl DelayMs<u16>) -> Result<SelfTest, E> {
+        defmt::debug!("running imu self-test..");
+
+        let r = self.run_self_test(delay);
+
+        let restore = self
+            .write_register(CTRL5_C, 0)
+            .and_then(|_| self.boot_imu())
+            .and_then(|_| self.disable_fifo());
+
+        let st = r?;
+        restore?;
+
+        defmt::info!("imu self-test: {:?}", st);
+
+        Ok(st)
+    }
+
+    fn run_self_test(&mut self, delay: &mut impl DelayMs<u16>) -> Result<SelfTest, E> {
+        self.disable_fifo()?;
+        self.write_register(CTRL5_C, 0)?;
+
+        // Accelerometer, with the gyroscope off.
+        self.write_register(CTRL2_G, 0)?;
+        self.write_register(CTRL1_XL, ST_CTRL1_XL)?;
+        let nost_xl = self.settle_and_average(OUTX_L_A, XLDA, delay)?;
+
+        self.write_register(CTRL5_C, ST_XL)?;
+        let st_xl = self.settle_and_average(OUTX_L_A, XLDA, delay)?;
+        self.write_register(CTRL5_C, 0)?;
+
+        // Gyroscope, with the accelerometer off.
+        self.write_register(CTRL1_XL, 0)?;
+        self.write_register(CTRL2_G, ST_CTRL2_G)?;
+        let nost_g = self.settle_and_average(OUTX_L_G, GDA, delay)?;
+
+        self.write_register(CTRL5_C, ST_G)?;
+        let st_g = self.settle_and_average(OUTX_L_G, GDA, delay)?;
+        self.write_register(CTRL5_C, 0)?;
+
+        Ok(SelfTest::new(nost_xl, st_xl, nost_g, st_g))
+    }
+
+    /// Wait for the output to settle, discard the first sample and average the next `SAMPLES`
+    /// samples of the output at `out`.
+    fn settle_and_average(
+        &mut self,
+        out: u8,
+        ready: u8,
+        delay: &mut impl DelayMs<u16>,
+    ) -> Result<[f32; 3], E> {
+        delay.delay_ms(SETTLE);
+
+        let mut samples = [[0i16; 3]; SAMPLES + 1];
+        for s in samples.iter_mut() {
+            // A sample every 20 ms at 52 Hz, give up waiting after a second.
+            for _ in 0..200 {
+                if self.read_register(STATUS_REG)? & ready != 0 {
+                    break;
+                }
+                delay.delay_ms(5);
+            }
+
+            let mut b = [0u8; 6];
+            self.i2c.write_read(self.address, &[out], &mut b)?;
+            *s = [0, 1, 2].map(|i| i16::from_le_bytes([b[2 * i], b[2 * i + 1]]));
+        }
+
+        Ok(mean(&samples[1..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        // A working sensor: about 500 mg and 300 dps (datasheet, typical).
+        let st = SelfTest::new(
+            [0., 0., 8196.],
+            [4100., 4100., 12296.],
+            [10., -10., 0.],
+            [4300., 4300., -4300.],
+        );
+        assert!((st.accel[2] - 500.2).abs() < 0.1, "{:?}", st);
+        assert!((st.gyro[1] - 301.7).abs() < 0.1, "{:?}", st);
+        assert!(st.passed());
+
+        // The z-axis of the accelerometer is stuck.
+        let st = SelfTest::new(
+            [0., 0., 8196.],
+            [4100., 4100., 8196.],
+            [10., -10., 0.],
+            [4300., 4300., -4300.],
+        );
+        assert!(!st.accel_ok());
+        assert!(st.gyro_ok());
+        assert!(!st.passed());
+
+        // Gyroscope out of range.
+        let st = SelfTest::new(
+            [0., 0., 0.],
+            [4100., 4100., 4100.],
+            [0., 0., 0.],
+            [20000., 4300., 4300.],
+        );
+        assert!(st.accel_ok());
+        assert!(!st.gyro_ok());
+    }
+
+    #[test]
+    fn mean_of_samples() {
+        assert_eq!(mean(&[[1, -2, 3], [3, -4, 5]]), [2., -3., 4.]);
+    }
+}
@@ -0,0 +1,446 @@
+//! Synthetic IMU, for running the wave pipeline on the host without hardware.
+//!
+//! [`SyntheticImu`] is an I2C device that answers like the ISM330DHCX: a register file that keeps
+//! what is written to it, and a FIFO of gyroscope and accelerometer words. `Waves<SyntheticImu>`
+//! is set up and read exactly as on the buoy (`Waves::new`, `enable_fifo`, `read_and_filter` and
+//! `take_buf`), so the packages go through the same orientation filter, FIR decimation, scaling
+//! and quality flags as device output.
+//!
+//! The acceleration is gravity along `z` with a superposition of [`Wave`]s on top, in the body
+//! frame of a buoy that does not rotate: the angular rate is zero. Samples are generated at the
+//! IMU rate (`FREQ`) by [`SyntheticImu::advance`], and only batched into the FIFO while it is
+//! enabled. The counts are rounded to the sensitivity of the accelerometer range, a FIFO that is
+//! not read in time overruns, as on the buoy.
+//!
+//! [`record`] polls the IMU like `Imu::check_retrieve`, and returns the packages as they would be
+//! queued for storage.
+
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Write, WriteRead},
+};
+
+use super::buf::SENSORS_GRAVITY_STANDARD;
+use super::format::{self, tag, Word};
+use super::raw::ms2;
+use super::self_test::WHO_AM_I;
+use super::{AxlPacketT, ImuError, Waves, FREQ};
+use crate::config::AccelRange;
+
+/// Words in the FIFO of the ISM330DHCX (3 kB).
+pub const FIFO_WORDS: usize = 512;
+
+const WHO_AM_I_REG: u8 = 0x0f;
+const CTRL3_C: u8 = 0x12;
+const OUT_TEMP_L: u8 = 0x20;
+const FIFO_STATUS1: u8 = 0x3a;
+const FIFO_STATUS2: u8 = 0x3b;
+
+/// `BOOT` and `SW_RESET` of `CTRL3_C` are cleared by the IMU when done.
+const CTRL3_C_SELF_CLEARING: u8 = 0b1000_0001;
+
+/// `FIFO_MODE[2:0]` of `FIFO_CTRL4`, `0` is bypass.
+const FIFO_MODE_MASK: u8 = 0b111;
+
+/// `FIFO_OVR_IA`, `FIFO_FULL_IA` and `FIFO_OVR_LATCHED` of `FIFO_STATUS2`.
+const FIFO_OVERRUN: u8 = 0b0110_1000;
+
+/// A sine wave of the acceleration along one axis of the body frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wave {
+    /// Axis (0: x, 1: y, 2: z).
+    pub axis: usize,
+
+    /// Amplitude of the acceleration [m/s^2].
+    pub amplitude: f64,
+
+    /// Frequency [Hz].
+    pub freq: f64,
+
+    /// Phase [rad].
+    pub phase: f64,
+}
+
+impl Wave {
+    /// Acceleration of a buoy following a wave of heave (or surge, sway) `amplitude` [m] at
+    /// `freq` [Hz].
+    pub fn displacement(axis: usize, amplitude: f64, freq: f64) -> Wave {
+        let w = 2. * core::f64::consts::PI * freq;
+
+        Wave {
+            axis,
+            amplitude: w * w * amplitude,
+            freq,
+            phase: core::f64::consts::PI,
+        }
+    }
+
+    /// Acceleration at `t` [s].
+    pub fn at(&self, t: f64) -> f64 {
+        self.amplitude * (2. * core::f64::consts::PI * self.freq * t + self.phase).sin()
+    }
+}
+
+pub struct SyntheticImu {
+    /// I2C address the IMU answers at.
+    pub address: u8,
+
+    /// Range of the accelerometer the counts are generated for, set it to the range of the config.
+    pub accel_range: AccelRange,
+
+    pub waves: Vec<Wave>,
+
+    /// Die temperature [C].
+    pub temperature: f32,
+
+    regs: [u8; 128],
+    fifo: VecDeque<Word>,
+
+    /// Overrun flags of `FIFO_STATUS2`, cleared when the FIFO is reset.
+    overrun: u8,
+
+    /// Samples generated since the start.
+    pub samples: u64,
+}
+
+impl SyntheticImu {
+    pub fn new(address: u8, accel_range: AccelRange) -> SyntheticImu {
+        let mut regs = [0u8; 128];
+        regs[WHO_AM_I_REG as usize] = WHO_AM_I;
+
+        SyntheticImu {
+            address,
+            accel_range,
+            waves: Vec::new(),
+            temperature: 25.,
+            regs,
+            fifo: VecDeque::new(),
+            overrun: 0,
+            samples: 0,
+        }
+    }
+
+    /// Add a wave to the acceleration.
+    pub fn wave(mut self, wave: Wave) -> SyntheticImu {
+        self.waves.push(wave);
+        self
+    }
+
+    /// Time since the start [ms] after the generated samples.
+    pub fn now(&self) -> i64 {
+        (self.samples as f64 * 1000. / FREQ.value() as f64).round() as i64
+    }
+
+    /// Acceleration in the body frame at `t` [s].
+    pub fn accel(&self, t: f64) -> [f64; 3] {
+        let mut a = [0., 0., SENSORS_GRAVITY_STANDARD];
+        for w in &self.waves {
+            a[w.axis] += w.at(t);
+        }
+        a
+    }
+
+    /// Generate `n` samples at the IMU rate, batched into the FIFO if it is enabled.
+    pub fn advance(&mut self, n: usize) {
+        let lsb = ms2(1, self.accel_range);
+
+        for _ in 0..n {
+            let t = self.samples as f64 / FREQ.value() as f64;
+            self.samples += 1;
+
+            if self.regs[format::FIFO_CTRL4 as usize] & FIFO_MODE_MASK == 0 {
+                continue;
+            }
+
+            if self.fifo.len() + 2 > FIFO_WORDS {
+                self.overrun = FIFO_OVERRUN;
+                continue;
+            }
+
+            let a = self
+                .accel(t)
+                .map(|a| (a / lsb).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16);
+
+            self.fifo.push_back(word(tag::GYRO, [0; 3]));
+            self.fifo.push_back(word(tag::ACCEL, a));
+        }
+    }
+
+    /// Words in the FIFO.
+    pub fn fifo_len(&self) -> usize {
+        self.fifo.len()
+    }
+
+    fn read(&self, reg: u8) -> u8 {
+        let temperature = (((self.temperature - 25.) * 256.) as i16).to_le_bytes();
+
+        match reg {
+            FIFO_STATUS1 => self.fifo.len() as u8,
+            FIFO_STATUS2 => ((self.fifo.len() >> 8) as u8 & 0b11) | self.overrun,
+            OUT_TEMP_L => temperature[0],
+            r if r == OUT_TEMP_L + 1 => temperature[1],
+            r => self.regs.get(r as usize).copied().unwrap_or_default(),
+        }
+    }
+
+    fn set(&mut self, reg: u8, value: u8) {
+        let Some(r) = self.regs.get_mut(reg as usize) else {
+            return;
+        };
+
+        *r = match reg {
+            CTRL3_C => value & !CTRL3_C_SELF_CLEARING,
+            _ => value,
+        };
+
+        if reg == format::FIFO_CTRL4 && value & FIFO_MODE_MASK == 0 {
+            self.fifo.clear();
+            self.overrun = 0;
+        }
+    }
+}
+
+/// Word of the FIFO with `tag` and three counts.
+fn word(tag: u8, v: [i16; 3]) -> Word {
+    let mut w = [tag << 3, 0, 0, 0, 0, 0, 0];
+    for (k, v) in v.iter().enumerate() {
+        w[1 + 2 * k..3 + 2 * k].copy_from_slice(&v.to_le_bytes());
+    }
+    w
+}
+
+impl Write for SyntheticImu {
+    type Error = ();
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+        if address != self.address {
+            return Err(());
+        }
+
+        if let Some((reg, values)) = bytes.split_first() {
+            for (k, v) in values.iter().enumerate() {
+                self.set(reg.wrapping_add(k as u8), *v);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl WriteRead for SyntheticImu {
+    type Error = ();
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+        if address != self.address {
+            return Err(());
+        }
+
+        let reg = *bytes.first().ok_or(())?;
+
+        if reg == format::FIFO_DATA_OUT_TAG {
+            let w = self.fifo.pop_front().unwrap_or_default();
+            let n = buffer.len().min(w.len());
+            buffer[..n].copy_from_slice(&w[..n]);
+        } else {
+            for (k, b) in buffer.iter_mut().enumerate() {
+                *b = self.read(reg.wrapping_add(k as u8));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Delay that returns at once, time is advanced with [`SyntheticImu::advance`].
+pub struct NoDelay;
+
+impl DelayMs<u16> for NoDelay {
+    fn delay_ms(&mut self, _ms: u16) {}
+}
+
+/// Run the IMU until `n` packages are taken, starting at `start` [ms]. The FIFO is enabled and the
+/// time of the first sample set as at the end of the warm-up, the FIFO is then read every output
+/// sample (`Waves::decimation` samples), and the buffer taken when it should be flushed.
+pub fn record(
+    waves: &mut Waves<SyntheticImu>,
+    start: i64,
+    n: usize,
+) -> Result<Vec<AxlPacketT>, ImuError<()>> {
+    let mut packages = Vec::with_capacity(n);
+
+    waves.discard(&mut NoDelay)?;
+    waves.take_buf(start + waves.i2c.now(), 0, 0., 0.)?;
+
+    while packages.len() < n {
+        waves.i2c.advance(waves.decimation() as usize);
+        waves.read_and_filter()?;
+
+        let now = start + waves.i2c.now();
+        if waves.should_flush(now) {
+            packages.push(waves.take_buf(now, 0, 0., 0.)?);
+            waves.read_and_filter()?;
+        }
+    }
+
+    Ok(packages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axl::AxlPacket;
+    use crate::config::Config;
+    use crate::waves::OUTPUT_FREQ;
+
+    fn waves(imu: SyntheticImu) -> Waves<SyntheticImu> {
+        let config = Config::default();
+        Waves::new(imu, &config, &mut NoDelay).unwrap()
+    }
+
+    fn imu() -> SyntheticImu {
+        let config = Config::default();
+        SyntheticImu::new(config.imu_address, config.accel_range)
+    }
+
+    /// Amplitude of the component of `x` at `freq` [Hz], sampled at `fs` [Hz].
+    fn amplitude(x: &[f64], freq: f64, fs: f64) -> f64 {
+        let w = 2. * core::f64::consts::PI * freq / fs;
+        let (re, im) = x.iter().enumerate().fold((0., 0.), |(re, im), (i, v)| {
+            (re + v * (w * i as f64).cos(), im + v * (w * i as f64).sin())
+        });
+
+        2. * (re * re + im * im).sqrt() / x.len() as f64
+    }
+
+    #[test]
+    fn not_answering() {
+        let mut i = imu();
+        let address = i.address;
+        i.address = address + 1;
+
+        assert!(Waves::new(i, &Config::default(), &mut NoDelay).is_err());
+    }
+
+    #[test]
+    fn fifo() {
+        let mut w = waves(imu());
+
+        // The samples are only batched while the FIFO is enabled.
+        w.i2c.advance(10);
+        assert_eq!(w.i2c.fifo_len(), 0);
+
+        w.enable_fifo(&mut NoDelay).unwrap();
+        w.i2c.advance(10);
+        assert_eq!(w.i2c.fifo_len(), 20);
+
+        assert_eq!(w.read_and_filter().unwrap(), 10);
+        assert_eq!(w.i2c.fifo_len(), 0);
+
+        // Overrun when the FIFO is not read in time.
+        w.i2c.advance(FIFO_WORDS);
+        assert!(matches!(
+            w.read_and_filter(),
+            Err(ImuError::FifoOverrun {
+                fifo_full: true,
+                ..
+            })
+        ));
+
+        w.disable_fifo().unwrap();
+        w.enable_fifo(&mut NoDelay).unwrap();
+        w.i2c.advance(1);
+        assert_eq!(w.read_and_filter().unwrap(), 1);
+    }
+
+    #[test]
+    fn packages() {
+        // About 0.2 Hz, a whole number of periods over three packages.
+        let freq = 12. * OUTPUT_FREQ as f64 / (3 * crate::axl::SAMPLE_NO) as f64;
+        let wave = Wave::displacement(2, 1.0, freq);
+        let mut w = waves(imu().wave(wave));
+
+        let start = 1_700_000_000_000;
+        let pcks = record(&mut w, start, 4).unwrap();
+        assert_eq!(pcks.len(), 4);
+
+        let p: std::vec::Vec<&AxlPacket> = pcks.iter().map(|p| &p.0).collect();
+
+        for (a, b) in p.iter().zip(&p[1..]) {
+            assert_eq!(a.freq, OUTPUT_FREQ);
+            assert_eq!(b.samples(), crate::axl::SAMPLE_NO);
+
+            // Consecutive packages follow each other without a gap.
+            let end = a.timestamp as f64 + a.samples() as f64 * 1000. / a.freq as f64;
+            assert!(
+                (b.timestamp as f64 - end).abs() < 1000. / a.freq as f64,
+                "{end}"
+            );
+        }
+
+        // The packages are structured as on the buoy, and decode.
+        let mut b = p[1].to_cobs::<{ crate::axl::AXL_POSTCARD_SZ }>().unwrap();
+        assert_eq!(AxlPacket::from_cobs(&mut b).unwrap(), *p[1]);
+
+        // The vertical acceleration of the wave is recovered, after the transient of the filters.
+        let z: std::vec::Vec<f64> = p[1..]
+            .iter()
+            .flat_map(|p| p.data.chunks(3).map(|s| p.accel(s[2]) as f64))
+            .collect();
+        let a = amplitude(&z, wave.freq, OUTPUT_FREQ as f64);
+        assert!((a - wave.amplitude).abs() < 0.03 * wave.amplitude, "{a}");
+
+        let x: std::vec::Vec<f64> = p[1..]
+            .iter()
+            .flat_map(|p| p.data.chunks(3).map(|s| p.accel(s[0]) as f64))
+            .collect();
+        assert!(amplitude(&x, wave.freq, OUTPUT_FREQ as f64) < 0.01 * wave.amplitude);
+
+        // Deterministic.
+        let mut w = waves(imu().wave(wave));
+        let again = record(&mut w, start, 2).unwrap();
+        assert_eq!(again[1].0.data, p[1].data);
+    }
+
+    #[cfg(feature = "fir")]
+    #[test]
+    fn decimation() {
+        let mut config = Config::default();
+        config
+            .apply_json(br#"{ "fir_filter": "hz20", "decimation": 8 }"#)
+            .unwrap();
+
+        // About 0.2 Hz, a whole number of periods over two packages (of 512 samples with the raw
+        // buffer, of 1024 samples without).
+        let freq = 8. * 26. / 1024.;
+        let wave = Wave::displacement(2, 1.0, freq);
+        let mut w = Waves::new(imu().wave(wave), &config, &mut NoDelay).unwrap();
+
+        assert_eq!(w.decimation(), 8);
+        assert_eq!(w.output_freq(), 26.);
+
+        let start = 1_700_000_000_000;
+        let pcks = record(&mut w, start, 3).unwrap();
+        let p: std::vec::Vec<&AxlPacket> = pcks.iter().map(|p| &p.0).collect();
+
+        // The packages are stamped with the rate of the filter that produced them.
+        for (a, b) in p.iter().zip(&p[1..]) {
+            assert_eq!(a.freq, w.output_freq());
+
+            let end = a.timestamp as f64 + a.samples() as f64 * 1000. / a.freq as f64;
+            assert!(
+                (b.timestamp as f64 - end).abs() < 1000. / a.freq as f64,
+                "{end}"
+            );
+        }
+
+        let z: std::vec::Vec<f64> = p[1..]
+            .iter()
+            .flat_map(|p| p.data.chunks(3).map(|s| p.accel(s[2]) as f64))
+            .collect();
+        let a = amplitude(&z, wave.freq, w.output_freq() as f64);
+        assert!((a - wave.amplitude).abs() < 0.03 * wave.amplitude, "{a}");
+    }
+}
@@ -0,0 +1,141 @@
+//! Temperature compensation of the accelerometer calibration.
+//!
+//! The bias and scale of the accelerometer drift with the temperature of the IMU. The table
+//! `accel_thermal` in the config holds the calibration (as estimated by `sfypack calibrate`) at a
+//! few temperatures, e.g. from captures in a thermal chamber fitted with `sfypack thermal`. With
+//! `bias_removal` set to `calibration` and a table in the config, the bias and scale at the die
+//! temperature of the buffer (`AxlPacket::temperature`) are interpolated linearly between the
+//! points of the table, and applied to every accelerometer sample before the orientation filter
+//! instead of `accel_bias`:
+//!
+//! ```text
+//! a = (a - bias) / scale
+//! ```
+//!
+//! Outside the table the calibration of the nearest point is used. The bias and scale that were
+//! applied are recorded in every package (`AxlPacket::bias` and `AxlPacket::scale`).
+
+/// Maximum number of points in the table.
+pub const MAX_POINTS: usize = 8;
+
+/// Calibration at one temperature.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// Die temperature of the IMU [C].
+    pub temperature: f32,
+
+    /// Bias (x, y, z) in the body frame [m/s^2].
+    pub bias: [f32; 3],
+
+    /// Scale factor (x, y, z).
+    pub scale: [f32; 3],
+}
+
+/// Points sorted by temperature.
+pub type Table = heapless::Vec<Point, MAX_POINTS>;
+
+/// The temperatures are strictly increasing, and the bias and scale are within `max_bias` and
+/// positive.
+pub fn is_valid(table: &[Point], max_bias: f32) -> bool {
+    table
+        .windows(2)
+        .all(|w| w[0].temperature < w[1].temperature)
+        && table.iter().all(|p| {
+            p.temperature.is_finite()
+                && p.bias.iter().all(|b| b.is_finite() && b.abs() < max_bias)
+                && p.scale.iter().all(|s| s.is_finite() && *s > 0.)
+        })
+}
+
+/// Calibration at `temperature`, interpolated between the points of the table. `None` if the
+/// table is empty.
+pub fn at(table: &[Point], temperature: f32) -> Option<Point> {
+    let first = table.first()?;
+    let last = table.last()?;
+
+    if temperature.is_nan() || temperature <= first.temperature {
+        return Some(Point {
+            temperature,
+            ..*first
+        });
+    }
+
+    let i = match table.iter().position(|p| p.temperature >= temperature) {
+        Some(i) => i,
+        None => {
+            return Some(Point {
+                temperature,
+                ..*last
+            })
+        }
+    };
+
+    let (a, b) = (&table[i - 1], &table[i]);
+    let f = (temperature - a.temperature) / (b.temperature - a.temperature);
+    let lerp = |x: &[f32; 3], y: &[f32; 3]| core::array::from_fn(|j| x[j] + f * (y[j] - x[j]));
+
+    Some(Point {
+        temperature,
+        bias: lerp(&a.bias, &b.bias),
+        scale: lerp(&a.scale, &b.scale),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        Table::from_slice(&[
+            Point {
+                temperature: 0.,
+                bias: [0.1, -0.2, 0.0],
+                scale: [1.0, 1.0, 0.98],
+            },
+            Point {
+                temperature: 20.,
+                bias: [0.2, -0.2, 0.1],
+                scale: [1.02, 1.0, 1.0],
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn interpolate() {
+        let t = table();
+        assert!(is_valid(&t, 1.));
+        assert_eq!(at(&[], 10.), None);
+
+        let p = at(&t, 10.).unwrap();
+        assert_eq!(p.temperature, 10.);
+        for (a, b) in p.bias.iter().zip([0.15, -0.2, 0.05]) {
+            assert!((a - b).abs() < 1.0e-6);
+        }
+        for (a, b) in p.scale.iter().zip([1.01, 1.0, 0.99]) {
+            assert!((a - b).abs() < 1.0e-6);
+        }
+
+        assert_eq!(at(&t, 20.).unwrap().bias, t[1].bias);
+
+        // Clamped outside the table.
+        assert_eq!(at(&t, -5.).unwrap().bias, t[0].bias);
+        assert_eq!(at(&t, 35.).unwrap().scale, t[1].scale);
+        assert_eq!(at(&t, f32::NAN).unwrap().scale, t[0].scale);
+    }
+
+    #[test]
+    fn invalid() {
+        let mut t = table();
+        t[1].temperature = 0.;
+        assert!(!is_valid(&t, 1.));
+
+        let mut t = table();
+        t[0].scale[2] = 0.;
+        assert!(!is_valid(&t, 1.));
+
+        let mut t = table();
+        t[0].bias[0] = 1.5;
+        assert!(!is_valid(&t, 1.));
+    }
+}
@@ -1,11 +1,14 @@
 use super::buf::{SENSORS_DPS_TO_RADS, SENSORS_GRAVITY_STANDARD};
 
-/// Scaling of acceleration values before they are sent or stored.
+/// Scaling of acceleration values before they are sent or stored, unless the scale follows the
+/// range of the accelerometer (`config::AccelScale::Range`). The scale of the samples of a package
+/// is recorded in `AxlPacket::accel_max`.
 ///
 /// > Do not change without updating the storage version.
 pub const ACCEL_MAX: f32 = SENSORS_GRAVITY_STANDARD as f32 * 2.; // in g
 
-/// Scaling of gyro values before they are sent or stored.
+/// Scaling of gyro values before they are sent or stored: twice the full scale of
+/// `raw::GYRO_RANGE`, which covers the whole output of the gyroscope.
 ///
 /// > Do not change without updating the storage version.
 pub const GYRO_MAX: f32 = ((125. * SENSORS_DPS_TO_RADS) * 2.) as f32; // in rad/s
@@ -65,7 +68,7 @@ impl ScaledF32 for G16 {
 }
 
 /// Move an f32 on the range -max to max to 0 to u16::MAX
-fn scale_f32_to_u16(max: f32, v: f32) -> u16 {
+pub fn scale_f32_to_u16(max: f32, v: f32) -> u16 {
     debug_assert!(max > 0.);
     let max = max as f64;
     let v = v as f64;
@@ -81,8 +84,7 @@ fn scale_f32_to_u16(max: f32, v: f32) -> u16 {
 }
 
 /// Move an u16 on given -max to max range to its real value in f32.
-#[allow(unused)]
-fn scale_u16_to_f32(max: f32, u: u16) -> f32 {
+pub fn scale_u16_to_f32(max: f32, u: u16) -> f32 {
     debug_assert!(max > 0.);
     let max = max as f64;
     let v = u as f64;
@@ -147,6 +149,38 @@ mod tests {
         assert!(max < 0.01);
     }
 
+    #[test]
+    fn round_trip_accel_ranges() {
+        use crate::config::AccelRange::{self, *};
+
+        // Precision of the scale following each range of the accelerometer: the error is at most
+        // half a step of the encoding.
+        for range in [G2, G4, G8, G16] {
+            let max = AccelRange::full_scale(&range);
+            let step = 2. * max / u16::MAX as f32;
+
+            let mut maxd: f32 = 0.0;
+            let mut avg: f32 = 0.0;
+
+            const N: i32 = 100000i32;
+
+            for i in 0..N {
+                let v = (i as f32) * 2. * max / N as f32 - max;
+                let fu = scale_u16_to_f32(max, scale_f32_to_u16(max, v));
+
+                let d = (v - fu).abs();
+                maxd = maxd.max(d);
+                avg = avg + d;
+            }
+
+            avg = avg / N as f32;
+            println!("accel {:?} u16 avg diff: {}", range, avg);
+            println!("accel {:?} u16 max diff: {} (step: {})", range, maxd, step);
+
+            assert!(maxd <= step / 2. * 1.01);
+        }
+    }
+
     #[test]
     fn round_trip_accel_half16() {
         let mut max: f32 = 0.0;
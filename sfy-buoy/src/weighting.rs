@@ -0,0 +1,81 @@
+//! Weighting of flagged samples in the statistics of a package (`stats.qo`).
+//!
+//! A sample is flagged when one of its values is clipped (at the limits of the scaled range) or
+//! when it was interpolated over a gap in the IMU FIFO (see `waves::gap`), see
+//! `AxlPacket::flagged`. Clipped samples bias the standard deviation low, and interpolated
+//! samples smooth it. The flagged samples count with `weight` in the standard deviations, from `0`
+//! (left out) to `1` (like the other samples, the default), and are left out of the maximum unless
+//! the weight is above `0`. When fewer than `min_good` of the samples are good the statistics of
+//! the package are rejected: they are sent as `0`, with `rejected` set.
+//!
+//! The fraction of good samples is sent with the statistics (`good`) regardless of the weighting.
+//! The time series is not changed, the flags are in the package.
+
+/// Weighting of flagged samples. Fields that are not set in an override take the default value,
+/// the default counts every sample.
+#[derive(serde::Serialize, serde::Deserialize, defmt::Format, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Weighting {
+    /// Weight of a flagged sample, from `0` (left out) to `1`.
+    pub weight: f32,
+
+    /// Fraction of good samples below which the statistics are rejected, `0` never rejects.
+    pub min_good: f32,
+}
+
+impl Default for Weighting {
+    fn default() -> Weighting {
+        Weighting {
+            weight: 1.,
+            min_good: 0.,
+        }
+    }
+}
+
+impl Weighting {
+    pub fn is_valid(&self) -> bool {
+        (0. ..=1.).contains(&self.weight) && (0. ..=1.).contains(&self.min_good)
+    }
+
+    /// Weight of a sample.
+    pub fn weight(&self, flagged: bool) -> f32 {
+        if flagged {
+            self.weight
+        } else {
+            1.
+        }
+    }
+
+    /// The statistics are rejected with this fraction of good samples.
+    pub fn rejects(&self, good: f32) -> bool {
+        good < self.min_good
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights() {
+        let w = Weighting::default();
+        assert!(w.is_valid());
+        assert_eq!((w.weight(false), w.weight(true)), (1., 1.));
+        assert!(!w.rejects(0.));
+
+        let w = Weighting {
+            weight: 0.25,
+            min_good: 0.9,
+        };
+        assert_eq!((w.weight(false), w.weight(true)), (1., 0.25));
+        assert!(w.rejects(0.8));
+        assert!(!w.rejects(0.9));
+
+        assert!(!Weighting { weight: 1.5, ..w }.is_valid());
+        assert!(!Weighting {
+            min_good: f32::NAN,
+            ..w
+        }
+        .is_valid());
+    }
+}
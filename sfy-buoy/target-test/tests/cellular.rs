@@ -31,7 +31,7 @@ mod tests {
         let i2c = I2c::new(dp.IOM4, pins.d10, pins.d9, Freq::F100kHz);
 
         defmt::info!("Setting up notecarrier");
-        let note = Notecarrier::new(i2c, &mut delay).unwrap();
+        let note = Notecarrier::new(i2c, Default::default(), &mut delay).unwrap();
 
         State { note, delay }
     }
@@ -37,7 +37,7 @@ mod tests {
         let i2c = I2c::new(dp.IOM2, pins.d17, pins.d18, Freq::F100kHz);
 
         defmt::info!("Setting up notecarrier");
-        let note = Notecarrier::new(i2c, &mut delay).unwrap();
+        let note = Notecarrier::new(i2c, Default::default(), &mut delay).unwrap();
 
         State { note, delay }
     }
@@ -53,18 +53,16 @@ mod tests {
 
     #[test]
     fn send_axl_batch(s: &mut State) {
-        let pck = sfy::axl::AxlPacket {
-            timestamp: 1000,
-            storage_id: None,
-            storage_version: 0,
-            temperature: 0.0,
-            position_time: 0,
-            offset: 1,
-            freq: 100.,
-            lon: 10.23,
-            lat: 14.233,
-            data: (0..3072).collect::<heapless::Vec<_, { 3 * 1024 }>>(),
-        };
+        let pck = sfy::axl::AxlPacketBuilder::new(
+            1000,
+            100.,
+            sfy::waves::wire::ACCEL_MAX,
+            (0..3072).collect::<heapless::Vec<_, { 3 * 1024 }>>(),
+        )
+        .offset(1)
+        .position(0, 10.23, 14.233, 0.)
+        .build()
+        .unwrap();
 
         assert!(pck.data.len() == sfy::axl::AXL_SZ);
 
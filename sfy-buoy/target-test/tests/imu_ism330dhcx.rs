@@ -33,13 +33,13 @@ mod tests {
         let mut dp = hal::pac::Peripherals::take().unwrap();
         let pins = hal::gpio::Pins::new(dp.GPIO);
 
-        let delay = hal::delay::Delay::new(core.SYST, &mut dp.CLKGEN);
+        let mut delay = hal::delay::Delay::new(core.SYST, &mut dp.CLKGEN);
         // let i2c = I2c::new(dp.IOM2, pins.d17, pins.d18, Freq::F100kHz);
         let i2c = I2c::new(dp.IOM3, pins.d6, pins.d7, Freq::F1mHz);
         // let i2c = I2c::new(dp.IOM4, pins.d10, pins.d9, Freq::F1mHz);
 
         defmt::info!("Setting up wave sensor");
-        let waves = Waves::new(i2c).unwrap();
+        let waves = Waves::new(i2c, &Default::default(), &mut delay).unwrap();
 
         State { waves, delay }
     }
@@ -435,6 +435,11 @@ class Axl(Event, AxlTimeseries):
         data['position_time'] = data['body'].get('position_time')
         data['temperature'] = data['body'].get('temperature', 0.)
         data['freq'] = data['body'].get('freq', 208.)
+        accel_max = data['body'].get('accel_max')
+
+        # Axes in the payload (bit 0: x, bit 1: y, bit 2: z), recorded from package format
+        # version 13.
+        axes = data['body'].get('axes', 0b111)
         del data['body']
 
         # decode x, y, z
@@ -444,6 +449,10 @@ class Axl(Event, AxlTimeseries):
         SENSORS_GRAVITY_STANDARD = 9.80665
         ACCEL_MAX = SENSORS_GRAVITY_STANDARD * 2.
 
+        # Full scale of the samples, recorded from package format version 11.
+        if not accel_max:
+            accel_max = ACCEL_MAX
+
         SENSORS_DPS_TO_RADS = 0.017453293
         GYRO_MAX = ((125. * SENSORS_DPS_TO_RADS) * 2.)
 
@@ -480,13 +489,18 @@ class Axl(Event, AxlTimeseries):
                 payload.byteswap(inplace=True)
 
 
-            payload = scale_u16_to_f32(ACCEL_MAX, payload)
+            payload = scale_u16_to_f32(accel_max, payload)
 
             assert len(payload) == n
 
-            x = payload[0::3]
-            y = payload[1::3]
-            z = payload[2::3] + SENSORS_GRAVITY_STANDARD
+            # Axes left out of the package are NaN.
+            width = bin(axes & 0b111).count('1')
+            columns = iter(range(width))
+            x, y, z = [
+                payload[next(columns)::width] if axes & (1 << i) else
+                np.full(n // width, np.nan, dtype=np.float32) for i in range(3)
+            ]
+            z = z + SENSORS_GRAVITY_STANDARD
 
         raw = data.pop('raw', None)
 